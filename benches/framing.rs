@@ -0,0 +1,107 @@
+//! Benchmarks of the serial frame codec, which every byte to and from the
+//! device goes through.
+//!
+//! Run with `cargo bench --bench framing`.
+
+use bytes::BytesMut;
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use std::hint::black_box;
+
+// The crate has no library target, so the codec is built in directly
+#[allow(dead_code)]
+#[path = "../src/framing.rs"]
+mod framing;
+
+use framing::{cobs_decode_in_place, cobs_encode_into, split_frame, Continuation};
+
+/// Frame sizes: a short OK, a message, a neighbor table, a log page.
+const SIZES: [usize; 4] = [16, 184, 1024, 4096];
+
+/// Bytes with a zero every few hundred, like a raw packet.
+fn payload(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 31 % 251) as u8).collect()
+}
+
+fn encoded(data: &[u8]) -> BytesMut {
+    let mut out = BytesMut::new();
+    cobs_encode_into(data, &mut out);
+    out
+}
+
+fn bench_cobs(c: &mut Criterion) {
+    let mut group = c.benchmark_group("cobs");
+    for size in SIZES {
+        let data = payload(size);
+        group.throughput(Throughput::Bytes(size as u64));
+
+        let mut out = BytesMut::with_capacity(size * 2);
+        group.bench_with_input(BenchmarkId::new("encode", size), &data, |b, data| {
+            b.iter(|| {
+                out.clear();
+                cobs_encode_into(black_box(data), &mut out);
+            });
+        });
+
+        let frame = encoded(&data);
+        group.bench_with_input(BenchmarkId::new("decode", size), &frame, |b, frame| {
+            b.iter_batched_ref(
+                || frame[..frame.len() - 1].to_vec(),
+                |buf| cobs_decode_in_place(black_box(buf)),
+                BatchSize::SmallInput,
+            );
+        });
+    }
+    group.finish();
+}
+
+/// A busy monitor stream: many short frames arriving in one read.
+fn bench_stream(c: &mut Criterion) {
+    let mut stream = BytesMut::new();
+    for i in 0..256 {
+        let event = format!(
+            "{{\"type\":\"message\",\"from\":\"node{i}\",\"rssi\":-87,\"text\":\"position report {i}\"}}"
+        );
+        cobs_encode_into(event.as_bytes(), &mut stream);
+    }
+    let mut group = c.benchmark_group("stream");
+    group.throughput(Throughput::Bytes(stream.len() as u64));
+    group.bench_function("split_256_frames", |b| {
+        b.iter_batched_ref(
+            || stream.clone(),
+            |buf| {
+                while let Some(frame) = split_frame(buf) {
+                    black_box(frame);
+                }
+            },
+            BatchSize::SmallInput,
+        );
+    });
+    group.finish();
+}
+
+/// A 64 KiB response reassembled from 1 KiB continuation frames.
+fn bench_continuation(c: &mut Criterion) {
+    let chunk = payload(1024);
+    let frames: Vec<Vec<u8>> = (0..64)
+        .map(|seq| {
+            let prefix = if seq == 63 { "END" } else { "MORE" };
+            [format!("{prefix} {seq} ").as_bytes(), &chunk].concat()
+        })
+        .collect();
+    let mut group = c.benchmark_group("continuation");
+    group.throughput(Throughput::Bytes(64 * 1024));
+    group.bench_function("reassemble_64k", |b| {
+        b.iter(|| {
+            let mut partial = Continuation::default();
+            for frame in &frames {
+                let (seq, _, data) = framing::continuation_frame(frame).unwrap();
+                partial.push(seq, data).unwrap();
+            }
+            black_box(partial.data.len())
+        });
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_cobs, bench_stream, bench_continuation);
+criterion_main!(benches);
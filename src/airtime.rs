@@ -0,0 +1,217 @@
+//! Airtime budget for regional duty-cycle limits.
+//!
+//! Each transmission occupies the channel for a time-on-air that follows from
+//! the radio settings. In the EU868 band a device may only transmit for a
+//! fraction of every hour (0.1-10% depending on the sub-band). [`Budget`]
+//! tracks the airtime used over the last hour and holds a send back until it
+//! fits, or refuses it if that would take longer than `[airtime]
+//! max_wait_secs`. One budget is shared by every connection in the process,
+//! since `batch` and `run` connect once per command.
+
+use anyhow::{bail, Result};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::protocol::DeviceConfig;
+use crate::settings::AirtimeConfig;
+
+/// Duty cycles are measured over a rolling hour.
+const WINDOW: Duration = Duration::from_secs(3600);
+
+/// Default longest wait for airtime before a send is refused.
+const DEFAULT_MAX_WAIT: Duration = Duration::from_secs(60);
+
+static SHARED: Mutex<Option<Budget>> = Mutex::new(None);
+
+/// Whether airtime is being tracked yet.
+pub fn started() -> bool {
+    SHARED.lock().unwrap_or_else(|e| e.into_inner()).is_some()
+}
+
+/// Track airtime against `budget`, unless already tracking.
+pub fn start(budget: Budget) {
+    SHARED
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get_or_insert(budget);
+}
+
+/// Reserve airtime for a `payload_len` byte packet sent now. Returns how long
+/// to wait before sending it, and the duty-cycle limit in percent.
+pub fn reserve(payload_len: usize) -> Result<(Duration, f64)> {
+    let mut shared = SHARED.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(budget) = shared.as_mut() else {
+        return Ok((Duration::ZERO, 0.0));
+    };
+    let wait = budget.reserve(payload_len, Instant::now())?;
+    Ok((wait, budget.limit.unwrap_or_default()))
+}
+
+/// Duty-cycle limit (percent) of the ETSI EN 300 220 sub-band containing
+/// `freq_mhz`, or `None` where none applies.
+pub fn regional_limit(freq_mhz: f32) -> Option<f64> {
+    let limit = match freq_mhz {
+        f if (433.05..=434.79).contains(&f) => 10.0,
+        f if (863.0..=868.6).contains(&f) => 1.0,
+        f if (868.7..=869.2).contains(&f) => 0.1,
+        f if (869.4..=869.65).contains(&f) => 10.0,
+        f if (869.7..=870.0).contains(&f) => 1.0,
+        _ => return None,
+    };
+    Some(limit)
+}
+
+/// Time on air of a `payload_len` byte `LoRa` packet (explicit header, CRC
+/// on), per the Semtech SX127x datasheet.
+pub fn time_on_air(config: &DeviceConfig, payload_len: usize) -> Duration {
+    let sf = f64::from(config.spreading_factor);
+    let symbol = 2f64.powf(sf) / (f64::from(config.bandwidth_khz.max(1)) * 1000.0);
+    let low_data_rate = if symbol > 0.016 { 1.0 } else { 0.0 };
+    // Coding rate 4/5..4/8, given as 5..8 or 1..4
+    let cr = f64::from(match config.coding_rate {
+        rate @ 5..=8 => rate - 4,
+        rate => rate,
+    });
+
+    let bits = 8.0 * payload_len as f64 - 4.0 * sf + 28.0 + 16.0;
+    let payload_symbols =
+        8.0 + ((bits / (4.0 * (sf - 2.0 * low_data_rate))).ceil() * (cr + 4.0)).max(0.0);
+    let preamble_symbols = f64::from(config.preamble_len) + 4.25;
+    Duration::from_secs_f64((preamble_symbols + payload_symbols) * symbol)
+}
+
+/// Airtime used by recent transmissions.
+#[derive(Debug)]
+pub struct Budget {
+    config: Option<DeviceConfig>,
+    /// Percentage of the window the device may transmit, if limited
+    limit: Option<f64>,
+    max_wait: Duration,
+    /// (start, airtime) of transmissions in the last window
+    sent: VecDeque<(Instant, Duration)>,
+}
+
+impl Budget {
+    /// Budget for a device with radio settings `config`.
+    pub fn new(config: DeviceConfig, settings: &AirtimeConfig) -> Self {
+        let limit = match settings.duty_cycle_percent {
+            Some(percent) if percent > 0.0 => Some(percent),
+            Some(_) => None,
+            None => regional_limit(config.freq_mhz),
+        };
+        Self {
+            config: Some(config),
+            limit,
+            max_wait: settings
+                .max_wait_secs
+                .map_or(DEFAULT_MAX_WAIT, Duration::from_secs),
+            sent: VecDeque::new(),
+        }
+    }
+
+    /// No limit (disabled, or the radio settings are unknown).
+    pub fn unlimited() -> Self {
+        Self {
+            config: None,
+            limit: None,
+            max_wait: DEFAULT_MAX_WAIT,
+            sent: VecDeque::new(),
+        }
+    }
+
+    /// Reserve airtime for a `payload_len` byte packet sent at `now`.
+    /// Returns how long to wait before sending it.
+    pub fn reserve(&mut self, payload_len: usize, now: Instant) -> Result<Duration> {
+        let (Some(config), Some(limit)) = (&self.config, self.limit) else {
+            return Ok(Duration::ZERO);
+        };
+        let airtime = time_on_air(config, payload_len);
+        let allowed = WINDOW.mul_f64(limit / 100.0);
+        if airtime > allowed {
+            bail!(
+                "A {payload_len}-byte packet takes {:.1}s on air, more than the {limit}% duty cycle allows per hour",
+                airtime.as_secs_f64()
+            );
+        }
+
+        while let Some(&(start, _)) = self.sent.front() {
+            if start + WINDOW > now {
+                break;
+            }
+            self.sent.pop_front();
+        }
+
+        // Wait until enough past transmissions leave the window
+        let mut used: Duration = self.sent.iter().map(|(_, t)| *t).sum();
+        let mut send_at = now;
+        for &(start, spent) in &self.sent {
+            if used + airtime <= allowed {
+                break;
+            }
+            used -= spent;
+            send_at = start + WINDOW;
+        }
+        let wait = send_at - now;
+        if wait > self.max_wait {
+            bail!(
+                "Sending now would exceed the {limit}% duty cycle; airtime frees up in {}s \
+                 (see [airtime] max_wait_secs)",
+                wait.as_secs()
+            );
+        }
+        self.sent.push_back((send_at, airtime));
+        Ok(wait)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(spreading_factor: u8, bandwidth_khz: u32) -> DeviceConfig {
+        DeviceConfig {
+            name: None,
+            freq_mhz: 868.3,
+            tx_power_dbm: 14,
+            bandwidth_khz,
+            spreading_factor,
+            coding_rate: 5,
+            preamble_len: 8,
+        }
+    }
+
+    #[test]
+    fn test_time_on_air() {
+        let toa = time_on_air(&config(7, 125), 10);
+        assert_eq!(toa.as_micros(), 41_216);
+        assert_eq!(regional_limit(869.525), Some(10.0));
+        assert_eq!(regional_limit(915.0), None);
+    }
+
+    #[test]
+    fn test_budget() {
+        // SF12/125 kHz: ~1.3s per 40 bytes against 36s per hour at 1%
+        let settings = AirtimeConfig {
+            duty_cycle_percent: None,
+            max_wait_secs: Some(3600),
+        };
+        let mut budget = Budget::new(config(12, 125), &settings);
+        let start = Instant::now();
+        let mut waits = Vec::new();
+        for _ in 0..30 {
+            waits.push(budget.reserve(40, start).unwrap());
+        }
+        assert_eq!(waits[0], Duration::ZERO);
+        // The first send past the budget waits for the first one to expire
+        let first_delayed = waits.iter().position(|w| !w.is_zero()).unwrap();
+        assert_eq!(waits[first_delayed], WINDOW);
+
+        let settings = AirtimeConfig {
+            duty_cycle_percent: Some(1.0),
+            max_wait_secs: Some(10),
+        };
+        let mut budget = Budget::new(config(12, 125), &settings);
+        assert!((0..30).any(|_| budget.reserve(40, start).is_err()));
+    }
+}
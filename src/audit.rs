@@ -0,0 +1,487 @@
+//! Audit log of mutating operations.
+//!
+//! Every command that changes a device (config, mode, time, channels, auth,
+//! reboot, flash, provisioning, ...) appends one JSON line to `audit.log` next
+//! to the config file: when, who, what, which device, and whether it worked.
+//! That includes lines of `run`/`batch` and stdin, `rpc` and `serve` calls and
+//! commands `share` relays. The file is only ever opened for appending, and a
+//! command that can't be recorded (the vault is locked) isn't run.
+
+use crate::cli::{
+    AuthAction, ChannelsAction, Cli, Commands, ConfigAction, ContactsAction, FlashAction,
+    FleetAction, GpsAction, LogAction, MessagesAction, NvsAction, PositionAction, ProvisionAction,
+    RawAction, TimeAction, WaypointsAction,
+};
+use crate::settings::{Scope, Settings};
+use crate::vault;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// Set while an audited command runs, so connections identify the device.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+static DEVICE: Mutex<Option<DeviceIdentity>> = Mutex::new(None);
+
+/// Device an operation was performed on.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DeviceIdentity {
+    pub port: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<String>,
+}
+
+/// One audit log line.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Record {
+    pub timestamp: String,
+    pub user: String,
+    pub operation: String,
+    pub device: DeviceIdentity,
+    /// "ok", "failed", or "relayed" for a remote client's command whose
+    /// answer this process doesn't read
+    pub outcome: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Audited operation in progress.
+pub struct Operation {
+    operation: String,
+    port: Option<String>,
+}
+
+impl Operation {
+    /// Start auditing `cli`'s command if it changes a device; fails if the
+    /// audit log can't be written.
+    pub fn start(cli: &Cli) -> Result<Option<Self>> {
+        Self::of(&cli.command, cli.port.as_deref())
+    }
+
+    /// Start auditing `command` on `port`, e.g. a `run` line on the device
+    /// behind the batch proxy.
+    pub fn of(command: &Commands, port: Option<&str>) -> Result<Option<Self>> {
+        let Some(operation) = describe(command) else {
+            return Ok(None);
+        };
+        Self::named(operation, port).map(Some)
+    }
+
+    /// Start auditing an operation that isn't a CLI command of its own
+    /// (stdin lines, `rpc` methods, `serve` calls).
+    pub fn named(operation: String, port: Option<&str>) -> Result<Self> {
+        ensure_writable()?;
+        ACTIVE.store(true, Ordering::Relaxed);
+        Ok(Operation {
+            operation,
+            port: port.map(String::from),
+        })
+    }
+
+    /// Append the outcome to the audit log.
+    pub fn finish<T, E: std::fmt::Display>(self, result: &std::result::Result<T, E>) -> Result<()> {
+        let mut device = DEVICE
+            .lock()
+            .map(|d| d.clone())
+            .unwrap_or_default()
+            .unwrap_or_default();
+        // The port the operation was given, not a proxy the device was
+        // reached through
+        if self.port.is_some() {
+            device.port = self.port;
+        }
+        let record = Record {
+            timestamp: chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, false),
+            user: current_user(),
+            operation: self.operation,
+            device,
+            outcome: if result.is_ok() { "ok" } else { "failed" }.into(),
+            error: result.as_ref().err().map(|e| format!("{e:#}")),
+        };
+        append(&record).context("Failed to write audit log")
+    }
+
+    /// Append the outcome and pass `result` on, failed if it couldn't be
+    /// recorded.
+    pub fn close<T>(self, result: Result<T>) -> Result<T> {
+        match (self.finish(&result), result) {
+            (Err(e), Err(failed)) => {
+                eprintln!("⚠ {e:#}");
+                Err(failed)
+            }
+            (logged, result) => logged.and(result),
+        }
+    }
+}
+
+/// Record a command relayed to the device on `port` for a remote client.
+pub fn relayed(operation: String, port: &str) -> Result<()> {
+    ensure_writable()?;
+    let record = Record {
+        timestamp: chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, false),
+        user: current_user(),
+        operation,
+        device: DeviceIdentity {
+            port: Some(port.to_string()),
+            ..DeviceIdentity::default()
+        },
+        outcome: "relayed".into(),
+        error: None,
+    };
+    append(&record).context("Failed to write audit log")
+}
+
+/// Fail before a change if it couldn't be recorded.
+fn ensure_writable() -> Result<()> {
+    vault::ensure_unlocked(&path()?).context("Changes to a device are recorded in the audit log")
+}
+
+/// Login name of the local user.
+fn current_user() -> String {
+    if let Some(user) = ["USER", "LOGNAME", "USERNAME"]
+        .iter()
+        .find_map(|var| std::env::var(var).ok().filter(|v| !v.is_empty()))
+    {
+        return user;
+    }
+    #[cfg(unix)]
+    {
+        // SAFETY: geteuid has no preconditions and cannot fail
+        format!("uid {}", unsafe { libc::geteuid() })
+    }
+    #[cfg(not(unix))]
+    {
+        "unknown".into()
+    }
+}
+
+/// Whether the device being connected to should be identified for the log.
+pub fn wants_device() -> bool {
+    ACTIVE.load(Ordering::Relaxed) && DEVICE.lock().is_ok_and(|d| d.is_none())
+}
+
+/// Record the device an audited operation talks to.
+pub fn note_device(port: &str, name: Option<&str>, public_key: Option<&[u8; 32]>) {
+    if let Ok(mut device) = DEVICE.lock() {
+        *device = Some(DeviceIdentity {
+            port: Some(port.to_string()),
+            name: name.map(String::from),
+            public_key: public_key.map(hex::encode),
+        });
+    }
+}
+
+pub fn path() -> Result<PathBuf> {
+    Ok(Settings::path()?.with_file_name("audit.log"))
+}
+
+fn append(record: &Record) -> Result<()> {
+    let path = path()?;
+    vault::ensure_unlocked(&path)?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(&path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    writeln!(file, "{}", serde_json::to_string(record)?)?;
+    Ok(())
+}
+
+/// All records, oldest first.
+pub fn read() -> Result<Vec<Record>> {
+    let path = path()?;
+    vault::ensure_unlocked(&path)?;
+    let file = match std::fs::File::open(&path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    let mut records = Vec::new();
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        records.push(
+            serde_json::from_str(&line)
+                .with_context(|| format!("{}:{}: invalid audit record", path.display(), i + 1))?,
+        );
+    }
+    Ok(records)
+}
+
+/// Description of a raw protocol command if it changes the device (the
+/// commands a shared device keeps for the admin scope), without secrets.
+pub fn describe_command(cmd: &str) -> Option<String> {
+    if Scope::for_command(cmd) < Scope::Admin {
+        return None;
+    }
+    let words: Vec<&str> = cmd.split_whitespace().collect();
+    let verb: Vec<String> = words
+        .iter()
+        .take(2)
+        .map(|w| w.to_ascii_uppercase())
+        .collect();
+    let keep = match verb.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["SETPIN" | "SETPASS", ..] => 1,
+        ["CHANNEL", "JOIN"] => 3,
+        _ => words.len(),
+    };
+    Some(words[..keep.min(words.len())].join(" "))
+}
+
+/// Description of a mutating command, or `None` for read-only ones. Secrets
+/// (passwords, PINs, PSKs) are never included.
+fn describe(command: &Commands) -> Option<String> {
+    let op = match command {
+        Commands::Config { action } => match action.as_ref()? {
+            ConfigAction::Show => return None,
+            ConfigAction::Preset { preset } => format!("config preset {preset}"),
+            ConfigAction::Name { name } => format!("config name {name}"),
+            ConfigAction::Frequency { freq_mhz } => format!("config frequency {freq_mhz}"),
+            ConfigAction::Power { power_dbm } => format!("config power {power_dbm}"),
+            ConfigAction::Bandwidth { bandwidth_khz } => {
+                format!("config bandwidth {bandwidth_khz}")
+            }
+            ConfigAction::SpreadingFactor { sf } => format!("config spreading-factor {sf}"),
+            ConfigAction::CodingRate { cr } => format!("config coding-rate {cr}"),
+            ConfigAction::Preamble { len } => format!("config preamble {len}"),
+        },
+        Commands::Provision {
+            action: ProvisionAction::Apply {
+                bundle, insecure, ..
+            },
+        } => {
+            let insecure = if *insecure { " --insecure" } else { "" };
+            format!("provision apply {bundle}{insecure}")
+        }
+        Commands::Reboot => "reboot".into(),
+        Commands::Replay { file, .. } => format!("replay {file}"),
+        Commands::Log {
+            action:
+                LogAction::Level {
+                    level: Some(level),
+                    module,
+                },
+        } => {
+            let name = |v: Option<clap::builder::PossibleValue>| {
+                v.map_or("?".into(), |v| v.get_name().to_string())
+            };
+            let mut op = format!("log level {}", name(level.to_possible_value()));
+            if let Some(module) = module {
+                op.push_str(&format!(" --module {}", name(module.to_possible_value())));
+            }
+            op
+        }
+        Commands::Log {
+            action: LogAction::Clear,
+        } => "log clear".to_string(),
+        Commands::Crashlog { clear: true, .. } => "crashlog --clear".to_string(),
+        Commands::Recover { fix: true, .. } => "recover --fix".to_string(),
+        Commands::Fuzz { iterations, .. } => format!("fuzz --iterations {iterations}"),
+        Commands::Raw { hex, action } => match action {
+            None => format!("raw {}", hex.as_deref()?),
+            Some(RawAction::Build {
+                payload_type,
+                send: true,
+                ..
+            }) => format!(
+                "raw build --type {} --send",
+                payload_type
+                    .to_possible_value()
+                    .map_or("?".into(), |v| v.get_name().to_string())
+            ),
+            Some(RawAction::Build { send: false, .. }) => return None,
+        },
+        Commands::Mode { mode } => format!(
+            "mode {}",
+            mode.to_possible_value()
+                .map_or("?".into(), |v| v.get_name().to_string())
+        ),
+        Commands::Position { action } => match action.as_ref()? {
+            PositionAction::Show | PositionAction::Request { .. } => return None,
+            PositionAction::Set { lat, lon, alt } => match alt {
+                Some(alt) => format!("position set {lat} {lon} --alt {alt}"),
+                None => format!("position set {lat} {lon}"),
+            },
+            PositionAction::Send { flood: true } => "position send --flood".into(),
+            PositionAction::Send { flood: false } => "position send".into(),
+        },
+        Commands::Gps {
+            action: GpsAction::Feed { source, .. },
+        } => format!("gps feed --source {source}"),
+        Commands::Waypoints {
+            action: WaypointsAction::Send { name },
+        } => format!("waypoints send {name}"),
+        Commands::Time { action } => match action.as_ref()? {
+            TimeAction::Show => return None,
+            TimeAction::Sync { mesh: false, .. } => "time sync".into(),
+            TimeAction::Sync { mesh: true, .. } => "time sync --mesh".into(),
+            TimeAction::Set { time } => format!("time set {time}"),
+        },
+        Commands::Messages {
+            action: Some(MessagesAction::Clear),
+            ..
+        } => "messages clear".into(),
+        Commands::Channels { action, .. } => match action.as_ref()? {
+            ChannelsAction::Add { name, url, qr, .. } => match (name, url, qr) {
+                (Some(name), _, _) => format!("channels add {name}"),
+                (None, Some(_), _) => "channels add --url".into(),
+                (None, None, _) => "channels add --qr".into(),
+            },
+            ChannelsAction::Remove { name } => format!("channels remove {name}"),
+            ChannelsAction::Default { name } => format!("channels default {}", name.as_ref()?),
+            ChannelsAction::Import {
+                file,
+                dry_run: false,
+                ..
+            } => format!("channels import {file}"),
+            ChannelsAction::List
+            | ChannelsAction::Share { .. }
+            | ChannelsAction::Audit { .. }
+            | ChannelsAction::History { .. }
+            | ChannelsAction::Scan { .. }
+            | ChannelsAction::Config { .. }
+            | ChannelsAction::Stats { .. }
+            | ChannelsAction::Import { .. }
+            | ChannelsAction::Export { .. } => return None,
+        },
+        Commands::Contacts {
+            action:
+                ContactsAction::Sync {
+                    from,
+                    to,
+                    dry_run: false,
+                },
+        } => format!("contacts sync --from {from} --to {to}"),
+        Commands::RotateIdentity { schedule, announce } => {
+            let mut op = "rotate-identity".to_string();
+            if let Some(schedule) = schedule {
+                op.push_str(&format!(" --schedule {schedule}"));
+            }
+            if *announce {
+                op.push_str(" --announce");
+            }
+            op
+        }
+        Commands::Auth { action } => match action {
+            AuthAction::Enable => "auth enable".into(),
+            AuthAction::Disable => "auth disable".into(),
+            _ => return None,
+        },
+        Commands::Setpass { .. } => "setpass".into(),
+        Commands::Setpin { .. } => "setpin".into(),
+        Commands::Fleet {
+            action: FleetAction::Apply { dry_run: true, .. },
+        } => return None,
+        Commands::Fleet {
+            action: FleetAction::Apply { manifest, .. },
+        } => format!("fleet apply {manifest}"),
+        Commands::Nvs { action } => match action {
+            NvsAction::Backup { .. } => return None,
+            NvsAction::Restore { file, .. } => format!("nvs restore {file}"),
+            NvsAction::Erase => "nvs erase".to_string(),
+        },
+        Commands::Flash {
+            action: Some(FlashAction::Fs { source, .. }),
+            ..
+        } => format!("flash fs {source}"),
+        Commands::Flash {
+            board,
+            local,
+            version,
+            allow_downgrade,
+            ota,
+            ..
+        } => {
+            let mut op = "flash".to_string();
+            if let Some(board) = board.and_then(|b| b.to_possible_value()) {
+                op.push_str(&format!(" --board {}", board.get_name()));
+            }
+            if let Some(local) = local {
+                op.push_str(&format!(" --local {local}"));
+            }
+            if let Some(version) = version {
+                op.push_str(&format!(" --version {version}"));
+            }
+            if *allow_downgrade {
+                op.push_str(" --allow-downgrade");
+            }
+            if let Some(ota) = ota {
+                op.push_str(&format!(" --ota {ota}"));
+            }
+            op
+        }
+        Commands::Run { file, .. } => format!("run {file}"),
+        // Only the subcommand names: arguments may hold PINs or PSKs
+        Commands::Batch { commands, .. } => {
+            let names: Vec<&str> = commands
+                .iter()
+                .filter_map(|c| c.split_whitespace().next())
+                .collect();
+            format!("batch {}", names.join(", "))
+        }
+        _ => return None,
+    };
+    Some(op)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn describe_args(args: &[&str]) -> Option<String> {
+        let cli = Cli::try_parse_from([&["meshgrid"], args].concat()).unwrap();
+        describe(&cli.command)
+    }
+
+    #[test]
+    fn test_describe() {
+        assert_eq!(
+            describe_args(&["config", "name", "hill"]).as_deref(),
+            Some("config name hill")
+        );
+        assert_eq!(describe_args(&["config"]), None);
+        assert_eq!(describe_args(&["info"]), None);
+        assert_eq!(
+            describe_args(&["mode", "repeater"]).as_deref(),
+            Some("mode repeater")
+        );
+        assert_eq!(
+            describe_args(&["setpin", "123456"]).as_deref(),
+            Some("setpin")
+        );
+        assert_eq!(
+            describe_args(&["channels", "add", "ops", "c2VjcmV0"]).as_deref(),
+            Some("channels add ops")
+        );
+        assert_eq!(
+            describe_args(&["log", "level", "debug", "--module", "radio"]).as_deref(),
+            Some("log level debug --module radio")
+        );
+        assert_eq!(describe_args(&["log", "level"]), None);
+
+        // Raw protocol commands, as sent from stdin, rpc, serve and share
+        assert_eq!(
+            describe_command("SET NAME relay-1").as_deref(),
+            Some("SET NAME relay-1")
+        );
+        assert_eq!(describe_command("setpin 123456").as_deref(), Some("setpin"));
+        assert_eq!(
+            describe_command("CHANNEL JOIN ops c2VjcmV0").as_deref(),
+            Some("CHANNEL JOIN ops")
+        );
+        assert_eq!(describe_command("INFO"), None);
+        assert_eq!(describe_command("SEND hello"), None);
+        assert_eq!(describe_command("AUTH 123456"), None);
+    }
+}
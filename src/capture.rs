@@ -0,0 +1,196 @@
+//! Packet capture files.
+//!
+//! `recv --capture` writes one JSON object per received packet:
+//!
+//! ```text
+//! {"time":"2026-01-12T15:30:00.123Z","hex":"1100..."}
+//! ```
+//!
+//! `replay` reads these, and classic libpcap files (microsecond or
+//! nanosecond timestamps, with LoRaTap headers stripped).
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+/// pcap file magic as stored: microsecond and nanosecond timestamps, in
+/// little- and big-endian byte order.
+const PCAP_MAGIC: [[u8; 4]; 4] = [
+    [0xd4, 0xc3, 0xb2, 0xa1],
+    [0xa1, 0xb2, 0xc3, 0xd4],
+    [0x4d, 0x3c, 0xb2, 0xa1],
+    [0xa1, 0xb2, 0x3c, 0x4d],
+];
+
+/// pcap link type of LoRaTap captures, whose records start with a header.
+const LINKTYPE_LORATAP: u32 = 270;
+
+/// A captured packet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Captured {
+    /// Time since the first packet of the capture
+    pub offset: Duration,
+    pub data: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Record {
+    /// RFC 3339 receive time
+    time: String,
+    hex: String,
+}
+
+/// Appends received packets to an NDJSON capture.
+pub struct Writer {
+    file: std::fs::File,
+}
+
+impl Writer {
+    /// Create (or append to) the capture at `path`.
+    pub fn create(path: &str) -> Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)
+            .with_context(|| format!("Failed to open {path}"))?;
+        Ok(Self { file })
+    }
+
+    /// Record `data`, received now.
+    pub fn write(&mut self, data: &[u8]) -> Result<()> {
+        let record = Record {
+            time: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            hex: hex::encode(data),
+        };
+        writeln!(self.file, "{}", serde_json::to_string(&record)?)?;
+        Ok(())
+    }
+}
+
+/// Read an NDJSON or pcap capture.
+pub fn read(path: &Path) -> Result<Vec<Captured>> {
+    crate::vault::ensure_unlocked(path)?;
+    let data = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let is_pcap = data
+        .first_chunk::<4>()
+        .is_some_and(|magic| PCAP_MAGIC.contains(magic));
+    let packets = if is_pcap {
+        parse_pcap(&data)
+    } else {
+        parse_ndjson(&String::from_utf8_lossy(&data))
+    }
+    .with_context(|| format!("Invalid capture {}", path.display()))?;
+    if packets.is_empty() {
+        bail!("{} contains no packets", path.display());
+    }
+    Ok(packets)
+}
+
+fn parse_ndjson(text: &str) -> Result<Vec<Captured>> {
+    let mut records = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: Record =
+            serde_json::from_str(line).with_context(|| format!("line {}", number + 1))?;
+        let time = DateTime::parse_from_rfc3339(&record.time)
+            .with_context(|| format!("line {}", number + 1))?;
+        let data =
+            hex::decode(record.hex.trim()).with_context(|| format!("line {}", number + 1))?;
+        records.push((time, data));
+    }
+    let Some(start) = records.iter().map(|(time, _)| *time).min() else {
+        return Ok(Vec::new());
+    };
+    Ok(records
+        .into_iter()
+        .map(|(time, data)| Captured {
+            offset: (time - start).to_std().unwrap_or_default(),
+            data,
+        })
+        .collect())
+}
+
+fn parse_pcap(data: &[u8]) -> Result<Vec<Captured>> {
+    let (header, mut records) = data.split_at_checked(24).context("Truncated header")?;
+    let big_endian = header[0] == 0xa1;
+    let nanos = header[..4] == PCAP_MAGIC[2] || header[..4] == PCAP_MAGIC[3];
+    let u32_at = |bytes: &[u8], at: usize| {
+        let field = [bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]];
+        if big_endian {
+            u32::from_be_bytes(field)
+        } else {
+            u32::from_le_bytes(field)
+        }
+    };
+    let link_type = u32_at(header, 20);
+
+    let mut packets = Vec::new();
+    let mut start = None;
+    while !records.is_empty() {
+        let (record, rest) = records
+            .split_at_checked(16)
+            .context("Truncated record header")?;
+        let len = u32_at(record, 8) as usize;
+        let (mut packet, rest) = rest.split_at_checked(len).context("Truncated record")?;
+        records = rest;
+
+        let fraction = u64::from(u32_at(record, 4));
+        let time = Duration::from_secs(u32_at(record, 0).into())
+            + if nanos {
+                Duration::from_nanos(fraction)
+            } else {
+                Duration::from_micros(fraction)
+            };
+        if link_type == LINKTYPE_LORATAP {
+            // LoRaTap header length is a big-endian u16 at offset 2
+            let header_len = packet
+                .get(2..4)
+                .map_or(0, |len| usize::from(u16::from_be_bytes([len[0], len[1]])));
+            packet = packet.get(header_len..).unwrap_or_default();
+        }
+        let start = *start.get_or_insert(time);
+        packets.push(Captured {
+            offset: time.saturating_sub(start),
+            data: packet.to_vec(),
+        });
+    }
+    Ok(packets)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_captures() {
+        let text = r#"{"time":"2026-01-12T15:30:00.000Z","hex":"1100"}
+{"time":"2026-01-12T15:30:01.500Z","hex":"0d0001020304"}
+"#;
+        let packets = parse_ndjson(text).unwrap();
+        assert_eq!(packets[1].offset, Duration::from_millis(1500));
+        assert_eq!(packets[1].data, [0x0d, 0, 1, 2, 3, 4]);
+
+        // Little-endian microsecond pcap, link type USER0, two records
+        let mut pcap = vec![0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0];
+        pcap.extend([0; 8]);
+        pcap.extend(65535u32.to_le_bytes());
+        pcap.extend(147u32.to_le_bytes());
+        for (secs, micros, data) in [(100u32, 0u32, &[0x11, 0][..]), (102, 250_000, &[0x25])] {
+            pcap.extend(secs.to_le_bytes());
+            pcap.extend(micros.to_le_bytes());
+            pcap.extend((data.len() as u32).to_le_bytes());
+            pcap.extend((data.len() as u32).to_le_bytes());
+            pcap.extend(data);
+        }
+        let packets = parse_pcap(&pcap).unwrap();
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[1].offset, Duration::from_millis(2250));
+        assert_eq!(packets[1].data, [0x25]);
+        assert!(parse_pcap(&pcap[..pcap.len() - 1]).is_err());
+    }
+}
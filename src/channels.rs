@@ -0,0 +1,132 @@
+//! Per-channel notification preferences.
+//!
+//! `channels.toml` next to the config file records which channels are muted
+//! or have priority, as set with `channels config`. `monitor --notify`, the
+//! `message` hook and the TUI unread counters follow them.
+//!
+//! ```toml
+//! [channels]
+//! Public = "mute"
+//! ops = "priority"
+//! ```
+
+use crate::settings::Settings;
+use crate::vault;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Name of the channel broadcasts go to.
+pub const PUBLIC: &str = "Public";
+
+/// How messages on a channel are announced.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    /// No notifications, hooks or unread counts
+    Mute,
+    /// Announced like any message
+    #[default]
+    Notify,
+    /// Announced urgently and listed first
+    Priority,
+}
+
+impl std::fmt::Display for Level {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Level::Mute => "mute",
+            Level::Notify => "notify",
+            Level::Priority => "priority",
+        })
+    }
+}
+
+/// Channel of a monitor message: `None` for direct messages, the public
+/// channel for broadcasts without one.
+pub fn of_message<'a>(to: Option<&str>, channel: Option<&'a str>) -> Option<&'a str> {
+    match to {
+        Some(_) => None,
+        None => Some(channel.unwrap_or(PUBLIC)),
+    }
+}
+
+/// Saved preferences, keyed by channel name.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Prefs {
+    #[serde(default)]
+    channels: BTreeMap<String, Level>,
+}
+
+impl Prefs {
+    /// Preference for `channel` (case-insensitive); direct messages (`None`)
+    /// are always announced.
+    pub fn get(&self, channel: Option<&str>) -> Level {
+        let Some(channel) = channel else {
+            return Level::Notify;
+        };
+        self.channels
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(channel))
+            .map_or(Level::Notify, |(_, level)| *level)
+    }
+
+    fn set(&mut self, channel: &str, level: Level) {
+        self.channels
+            .retain(|name, _| !name.eq_ignore_ascii_case(channel));
+        if level != Level::Notify {
+            self.channels.insert(channel.to_string(), level);
+        }
+    }
+}
+
+fn store_path() -> Result<PathBuf> {
+    Ok(Settings::path()?.with_file_name("channels.toml"))
+}
+
+/// Load the saved preferences.
+pub fn load() -> Result<Prefs> {
+    let path = store_path()?;
+    vault::ensure_unlocked(&path)?;
+    match std::fs::read_to_string(&path) {
+        Ok(content) => toml::from_str(&content)
+            .with_context(|| format!("Invalid channel preferences {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Prefs::default()),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+/// Set the preference for `channel`; `Level::Notify` (the default) forgets it.
+pub fn set(channel: &str, level: Level) -> Result<()> {
+    let mut prefs = load()?;
+    prefs.set(channel, level);
+    let path = store_path()?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&path, toml::to_string(&prefs)?)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prefs() {
+        let mut prefs: Prefs = toml::from_str("[channels]\nPublic = \"mute\"\n").unwrap();
+        prefs.set("OPS", Level::Priority);
+        prefs.set("ops", Level::Priority);
+        prefs.set("#hike", Level::Notify);
+
+        assert_eq!(prefs.get(of_message(None, None)), Level::Mute);
+        assert_eq!(prefs.get(of_message(None, Some("Ops"))), Level::Priority);
+        assert_eq!(prefs.get(of_message(None, Some("#hike"))), Level::Notify);
+        // Direct messages are never muted
+        assert_eq!(prefs.get(of_message(Some("0x11"), None)), Level::Notify);
+
+        let text = toml::to_string(&prefs).unwrap();
+        assert_eq!(text, "[channels]\nPublic = \"mute\"\nops = \"priority\"\n");
+    }
+}
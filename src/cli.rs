@@ -2,6 +2,10 @@
 
 use clap::{Parser, Subcommand, ValueEnum};
 
+pub use crate::error::ErrorFormat;
+pub use crate::output::{ColorMode, OutputFormat};
+pub use crate::packet::{PayloadType, RouteType};
+
 #[derive(Parser)]
 #[command(name = "meshgrid")]
 #[command(author, version, about = "Meshgrid mesh networking CLI", long_about = None)]
@@ -10,6 +14,15 @@ pub struct Cli {
     #[arg(short, long, global = true)]
     pub port: Option<String>,
 
+    /// Run a read-only command (info, stats, telemetry, neighbors) on several
+    /// devices at once, e.g. `--ports /dev/ttyUSB0,/dev/ttyUSB1`
+    #[arg(long, global = true, value_delimiter = ',', conflicts_with = "port")]
+    pub ports: Vec<String>,
+
+    /// Like --ports, with every auto-detected device
+    #[arg(long, global = true, conflicts_with_all = ["port", "ports"])]
+    pub all_devices: bool,
+
     /// Baud rate
     #[arg(short, long, default_value = "115200", global = true)]
     pub baud: u32,
@@ -18,10 +31,42 @@ pub struct Cli {
     #[arg(short, long, global = true)]
     pub verbose: bool,
 
-    /// PIN for authentication (if device has security enabled)
-    #[arg(long, global = true)]
+    /// Plain output for scripts: key=value lines, no banners, emoji or progress bars
+    #[arg(short, long, visible_alias = "plain", global = true)]
+    pub quiet: bool,
+
+    /// Don't ask before destructive operations (reboot, flash, erase, clear, rotate-identity)
+    #[arg(short = 'y', long, global = true)]
+    pub yes: bool,
+
+    /// Config file profile whose settings override the global ones ([profiles.NAME])
+    #[arg(long, global = true, env = "MESHGRID_PROFILE", value_name = "NAME")]
+    pub profile: Option<String>,
+
+    /// PIN for authentication (if device has security enabled; see `auth save`)
+    #[arg(long, global = true, env = "MESHGRID_PIN", hide_env_values = true)]
     pub pin: Option<String>,
 
+    /// Log every byte sent to and received from the device to this file
+    #[arg(long, global = true, value_name = "FILE")]
+    pub trace_serial: Option<String>,
+
+    /// Record the command line, all device I/O and the result to this file (see `replay-session`)
+    #[arg(long, global = true, value_name = "FILE")]
+    pub record: Option<String>,
+
+    /// Error output format (json prints a structured object on stderr)
+    #[arg(long, value_enum, default_value = "text", global = true)]
+    pub error_format: ErrorFormat,
+
+    /// When to color output (auto honors NO_COLOR; MESHGRID_NO_EMOJI=1 prints ASCII instead of emoji)
+    #[arg(long, value_enum, default_value = "auto", global = true)]
+    pub color: ColorMode,
+
+    /// Show times in UTC instead of local time (logs, messages, monitors)
+    #[arg(long, global = true)]
+    pub utc: bool,
+
     #[command(subcommand)]
     pub command: Commands,
 }
@@ -29,24 +74,187 @@ pub struct Cli {
 #[derive(Subcommand)]
 pub enum Commands {
     /// List available serial ports
-    Ports,
+    Ports {
+        /// Output format
+        #[arg(long, value_enum, default_value = "table")]
+        output: OutputFormat,
+    },
+
+    /// Check serial port access (Linux) and install a udev rule for connected boards
+    SetupPermissions {
+        /// Install the rule and add you to the serial group, using sudo
+        #[arg(long)]
+        install: bool,
+    },
+
+    /// Replay a session recorded with --record, without the device
+    ReplaySession {
+        /// Session file
+        file: String,
+    },
 
     /// Connect to a device and show info
-    Info,
+    Info {
+        /// Output format
+        #[arg(long, value_enum, default_value = "table")]
+        output: OutputFormat,
+    },
 
     /// Send a text message
     Send {
-        /// Destination node (name or hash)
+        /// Destination node (name or hash); repeat to send to several
         #[arg(short = 't', long = "to")]
-        to: Option<String>,
+        to: Vec<String>,
 
-        /// Channel name (e.g., "Public", "test-v1")
+        /// Channel name (e.g., "Public", "test-v1"); repeat to send to several.
+        /// Without --to or --channel, the default channel (`channels default`)
         #[arg(short = 'c', long = "channel")]
-        channel: Option<String>,
+        channel: Vec<String>,
 
         /// Message text
         #[arg(last = true)]
         message: String,
+
+        /// Wait up to N seconds for an ACK to a direct message
+        /// (runs the `ack_timeout` hook if none arrives)
+        #[arg(long, value_name = "SECS", requires = "to")]
+        wait_ack: Option<u64>,
+
+        /// Query the neighbor table instead of resolving --to from the cache
+        #[arg(long, requires = "to")]
+        refresh: bool,
+
+        /// Seconds between sends to several destinations, so the mesh can
+        /// relay each before the next
+        #[arg(long, value_name = "SECS", default_value = "3")]
+        spacing: u64,
+
+        /// Queue the message for --to nodes out of range; `monitor` sends it
+        /// when they are heard again
+        #[arg(long, requires = "to")]
+        queue: bool,
+
+        /// Send a message over the payload limit as is, instead of in
+        /// numbered parts that `monitor` and `messages` join again
+        #[arg(long)]
+        no_fragment: bool,
+
+        /// Compress the text to save airtime; `monitor` and `messages`
+        /// expand it again, other clients show it garbled
+        #[arg(long)]
+        compress: bool,
+
+        /// Replace accents, emoji and other non-ASCII characters with plain
+        /// ASCII, for clients that can't display them (e.g. e-ink screens)
+        #[arg(long)]
+        transliterate: bool,
+    },
+
+    /// Run unattended (e.g. under systemd): keep the device connected, log
+    /// telemetry and events to CSV, serve Prometheus metrics and apply alert
+    /// rules
+    Collector {
+        /// Collector configuration
+        #[arg(short, long, default_value = "collector.toml")]
+        config: String,
+    },
+
+    /// Monitor mesh traffic and run configured event hooks
+    Monitor {
+        /// Battery poll interval in seconds for the `battery_low` hook (0 = disabled)
+        #[arg(long, default_value = "60")]
+        battery_interval: u64,
+
+        /// Show a desktop notification for each message, except on muted
+        /// channels (see `channels config`)
+        #[arg(long)]
+        notify: bool,
+    },
+
+    /// Serve the device API as JSON-RPC 2.0 on stdin/stdout (for GUI frontends)
+    Rpc,
+
+    /// Bridge mesh messages to other networks
+    Bridge {
+        #[command(subcommand)]
+        action: BridgeAction,
+    },
+
+    /// Export mesh data to other tools
+    Export {
+        #[command(subcommand)]
+        action: ExportAction,
+    },
+
+    /// Serve the device API over the network
+    Serve {
+        #[command(subcommand)]
+        action: ServeAction,
+    },
+
+    /// Share the device's serial stream over TCP (ser2net raw style), for
+    /// `--port tcp://host:port` on other machines
+    Share {
+        /// Address to listen on (`:4403` listens on all interfaces)
+        #[arg(long, default_value = "127.0.0.1:4403")]
+        listen: String,
+
+        /// Only allow commands that read from the device
+        #[arg(long)]
+        read_only: bool,
+
+        /// Token clients pass as `--pin` for full access; scoped tokens go
+        /// in `[[serve.tokens]]` in the config file
+        #[arg(long, env = "MESHGRID_SHARE_TOKEN", hide_env_values = true)]
+        token: Option<String>,
+    },
+
+    /// Alert when a node goes silent, e.g. a solar repeater that died: run a
+    /// command, post to a webhook or show a notification
+    Watchdog {
+        /// Node to watch (name or hash); repeat for several. Without it, the
+        /// attached device is pinged instead
+        #[arg(short, long)]
+        node: Vec<String>,
+
+        /// Longest expected gap between adverts or other packets from a node,
+        /// e.g. 90s, 10m or 2h
+        #[arg(long, value_name = "DURATION", value_parser = crate::timefmt::parse_duration)]
+        expect_interval: std::time::Duration,
+
+        /// Shell command run when a node goes silent and when it's heard again
+        /// (see MESHGRID_EVENT, MESHGRID_NODE_NAME)
+        #[arg(long)]
+        exec: Option<String>,
+
+        /// Post alerts to this webhook URL (Discord, Slack or plain JSON)
+        #[arg(long)]
+        webhook: Option<String>,
+
+        /// Show a desktop notification for alerts
+        #[arg(long)]
+        notify: bool,
+
+        /// Query the neighbor table instead of resolving --node from the cache
+        #[arg(long)]
+        refresh: bool,
+    },
+
+    /// Feed host GPS fixes to the device, or diagnose its own receiver
+    Gps {
+        #[command(subcommand)]
+        action: GpsAction,
+    },
+
+    /// Serve a live map of node positions in the browser
+    Map {
+        /// Address to listen on (`:8080` listens on all interfaces)
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        listen: String,
+
+        /// Map tile URL template, e.g. a local tile server for offline use
+        #[arg(long, default_value = "https://tile.openstreetmap.org/{z}/{x}/{y}.png")]
+        tiles: String,
     },
 
     /// Interactive terminal UI
@@ -58,22 +266,118 @@ pub enum Commands {
         action: Option<ConfigAction>,
     },
 
+    /// Sign, verify and apply provisioning bundles
+    Provision {
+        #[command(subcommand)]
+        action: ProvisionAction,
+    },
+
+    /// Show or export the log of device changes made with this CLI
+    Audit {
+        #[command(subcommand)]
+        action: AuditAction,
+    },
+
+    /// Export the local journal of monitor events, telemetry and device changes
+    Events {
+        #[command(subcommand)]
+        action: EventsAction,
+    },
+
+    /// Encrypt local data (contacts, audit log, saved PINs) with a passphrase
+    Vault {
+        #[command(subcommand)]
+        action: VaultAction,
+    },
+
     /// Show neighbor table
-    Neighbors,
+    Neighbors {
+        /// Output format
+        #[arg(long, value_enum, default_value = "table")]
+        format: NeighborsFormat,
+
+        /// Sort by signal (strongest first), last seen (most recent first)
+        /// or name, instead of the device's order
+        #[arg(long, value_enum)]
+        sort: Option<NeighborSort>,
+
+        /// Columns to show, comma-separated (e.g. hash,name,rssi,fw). In
+        /// json and csv, lastseen is in seconds and distance in meters
+        #[arg(long, value_enum, value_delimiter = ',')]
+        columns: Vec<NeighborColumn>,
+
+        /// Print the neighbors as JSON or YAML (all columns unless --columns)
+        #[arg(long, value_enum, default_value = "table", conflicts_with = "format")]
+        output: OutputFormat,
+    },
+
+    /// Ask a node over the mesh for its name, mode, firmware, battery and uptime
+    Nodeinfo {
+        /// Node to ask (name, hash or public key prefix)
+        node: String,
+
+        /// Seconds to wait for the reply
+        #[arg(short, long, default_value = "30")]
+        timeout: u64,
+
+        /// Query the neighbor table instead of resolving the node from the cache
+        #[arg(long)]
+        refresh: bool,
+    },
+
+    /// Browse the local node database: every node seen, with notes
+    Nodes {
+        #[command(subcommand)]
+        action: NodesAction,
+    },
+
+    /// Verify and trust contact public keys
+    Contacts {
+        #[command(subcommand)]
+        action: ContactsAction,
+    },
 
     /// Trace route to a node
     Trace {
         /// Target node (name or hash)
-        target: String,
+        #[arg(required_unless_present = "all")]
+        target: Option<String>,
+
+        /// Trace every node in the neighbor table and node database, one line each
+        #[arg(long, conflicts_with_all = ["target", "refresh"])]
+        all: bool,
+
+        /// Query the neighbor table instead of resolving the target from the cache
+        #[arg(long)]
+        refresh: bool,
+
+        /// Send this many traces and summarize the paths taken and RTTs
+        #[arg(short = 'n', long, default_value = "1", value_parser = clap::value_parser!(u32).range(1..))]
+        count: u32,
+
+        /// Output format
+        #[arg(long, value_enum, default_value = "table")]
+        output: OutputFormat,
+    },
+
+    /// Inspect the device's route cache
+    Routes {
+        #[command(subcommand)]
+        action: RoutesAction,
     },
 
     /// Reboot device
     Reboot,
 
-    /// Send raw packet (hex)
+    /// Send raw packet (hex), or build one from named fields
+    #[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
     Raw {
         /// Packet data in hex format
-        hex: String,
+        #[arg(required = true)]
+        hex: Option<String>,
+
+        #[command(subcommand)]
+        action: Option<RawAction>,
     },
 
     /// Receive raw packets
@@ -81,6 +385,152 @@ pub enum Commands {
         /// Timeout in seconds
         #[arg(short, long, default_value = "60")]
         timeout: u64,
+
+        /// Append received packets to an NDJSON capture for `replay`
+        #[arg(long)]
+        capture: Option<String>,
+
+        /// Bytes per hex dump line
+        #[arg(short, long, default_value = "16", value_parser = clap::value_parser!(u16).range(1..=64))]
+        width: u16,
+    },
+
+    /// Merge the device log, debug frames and monitor events into one timeline
+    Timeline {
+        /// Seconds to collect live debug frames and events
+        #[arg(short, long, default_value = "60")]
+        duration: u64,
+
+        /// Print entries as JSON lines
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Export the device's stored log
+    Log {
+        #[command(subcommand)]
+        action: LogAction,
+    },
+
+    /// Fetch the firmware's stored crash dump and decode its backtrace
+    Crashlog {
+        /// Firmware ELF to resolve backtrace addresses with addr2line
+        #[arg(long)]
+        elf: Option<String>,
+
+        /// Write the report to a file instead of stdout
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// Clear the stored crash dump afterwards
+        #[arg(long)]
+        clear: bool,
+    },
+
+    /// Diagnose a device stuck in a reboot loop and guide its recovery
+    Recover {
+        /// How long to capture the boot output (seconds)
+        #[arg(short, long, default_value = "15")]
+        timeout: u64,
+
+        /// Directory for the diagnostic bundle (default recovery-<time>)
+        #[arg(short, long)]
+        output: Option<String>,
+
+        /// Offer to erase the settings and reflash, asking before each step
+        /// (--yes runs them without asking)
+        #[arg(long)]
+        fix: bool,
+    },
+
+    /// Send mutated command frames and packets to find firmware crashes
+    Fuzz {
+        /// Number of inputs to send
+        #[arg(short = 'n', long, default_value = "1000")]
+        iterations: usize,
+
+        /// Random seed (printed when not given; reuse it to reproduce a run)
+        #[arg(long)]
+        seed: Option<u64>,
+
+        /// Log of failing inputs (NDJSON, default fuzz-<seed>.ndjson)
+        #[arg(long)]
+        log: Option<String>,
+    },
+
+    /// Run a virtual mesh of simulated nodes, reachable as `-p sim://node3`
+    Sim {
+        /// Number of nodes
+        #[arg(short, long, default_value = "10", value_parser = clap::value_parser!(u16).range(2..=100))]
+        nodes: u16,
+
+        /// How the nodes are laid out
+        #[arg(short, long, value_enum, default_value = "line")]
+        topology: SimTopology,
+
+        /// Distance between neighboring nodes in meters (random: average)
+        #[arg(long, default_value = "1000")]
+        spacing: u32,
+
+        /// Spreading factor of every node (higher reaches further)
+        #[arg(long, default_value = "11", value_parser = clap::value_parser!(u8).range(7..=12))]
+        sf: u8,
+
+        /// Seconds between the nodes' adverts (0 = only at startup)
+        #[arg(long, default_value = "600")]
+        advert_interval: u64,
+
+        /// Random seed for placement and packet loss (printed when not given)
+        #[arg(long)]
+        seed: Option<u64>,
+    },
+
+    /// Plan repeater sites offline: link budgets between candidate sites
+    /// and which of them need repeaters to connect the network
+    #[command(allow_negative_numbers = true)]
+    Plan {
+        /// CSV of sites with a header row: name, lat, lon, and optionally
+        /// height (antenna above ground, m), elevation (ground, m) and role
+        /// (repeater, candidate or client)
+        #[arg(long, value_name = "CSV")]
+        nodes: String,
+
+        /// Radio preset to plan for (default: the settings of the device on
+        /// --port, or EU without one)
+        #[arg(long, value_enum)]
+        preset: Option<RadioPreset>,
+
+        /// Directory of SRTM .hgt elevation tiles (e.g. N52E005.hgt)
+        #[arg(long, value_name = "DIR")]
+        elevation: Option<String>,
+
+        /// Antenna gain at each site (dBi)
+        #[arg(long, default_value = "3")]
+        gain: f64,
+
+        /// Fade margin a link needs to count (dB)
+        #[arg(long, default_value = "10")]
+        margin: f64,
+
+        /// Print the plan as JSON or YAML
+        #[arg(long, value_enum, default_value = "table")]
+        output: OutputFormat,
+    },
+
+    /// Radio measurements
+    Rf {
+        #[command(subcommand)]
+        action: RfAction,
+    },
+
+    /// Re-send captured packets with their original timing
+    Replay {
+        /// Capture file (NDJSON from `recv --capture`, or pcap)
+        file: String,
+
+        /// Playback speed (e.g. 2x; 0 sends back to back)
+        #[arg(long, default_value = "1x")]
+        speed: String,
     },
 
     /// Show telemetry data
@@ -91,7 +541,39 @@ pub enum Commands {
     },
 
     /// Show statistics
-    Stats,
+    Stats {
+        /// Output format
+        #[arg(long, value_enum, default_value = "table")]
+        output: OutputFormat,
+
+        /// Print each sample as one line of JSON, for graphing
+        #[arg(long, conflicts_with = "output")]
+        json: bool,
+
+        /// Keep sampling every --interval seconds
+        #[arg(short, long)]
+        watch: bool,
+
+        /// Seconds between samples with --watch, --delta or --log
+        #[arg(short, long, default_value = "5", value_parser = clap::value_parser!(u64).range(1..))]
+        interval: u64,
+
+        /// Show the change in packet counters since the previous sample
+        #[arg(long)]
+        delta: bool,
+
+        /// Append a row of counters to this CSV file every --interval seconds
+        #[arg(long, value_name = "FILE")]
+        log: Option<String>,
+
+        /// Rotate the --log file when it reaches this many megabytes
+        #[arg(long, value_name = "MB", default_value = "10", requires = "log", value_parser = clap::value_parser!(u64).range(1..))]
+        log_max_mb: u64,
+
+        /// Rotated --log files to keep (stats.1.csv, stats.2.csv, ...)
+        #[arg(long, value_name = "N", default_value = "5", requires = "log")]
+        log_keep: usize,
+    },
 
     /// Set device mode
     Mode {
@@ -106,20 +588,74 @@ pub enum Commands {
         action: Option<TimeAction>,
     },
 
+    /// Set, advertise or show the node's fixed position
+    Position {
+        #[command(subcommand)]
+        action: Option<PositionAction>,
+    },
+
+    /// Distance and bearing between two nodes with known positions
+    Distance {
+        /// Node (name, hash or public key prefix)
+        node_a: String,
+
+        /// Other node (default: this device)
+        node_b: Option<String>,
+
+        /// Query the neighbor table instead of resolving nodes from the cache
+        #[arg(long)]
+        refresh: bool,
+    },
+
+    /// Follow a remote tracker's position live
+    Track {
+        #[command(subcommand)]
+        action: TrackAction,
+    },
+
+    /// Save waypoints and broadcast them to the mesh
+    Waypoints {
+        #[command(subcommand)]
+        action: WaypointsAction,
+    },
+
     /// Manage message inbox
     Messages {
+        /// Output format of the inbox
+        #[arg(long, value_enum, default_value = "table")]
+        output: OutputFormat,
+
         #[command(subcommand)]
         action: Option<MessagesAction>,
     },
 
+    /// Messages queued with `send --queue` for nodes out of range
+    Outbox {
+        #[command(subcommand)]
+        action: Option<OutboxAction>,
+    },
+
     /// Manage custom channels
     Channels {
+        /// Output format of the channel list
+        #[arg(long, value_enum, default_value = "table")]
+        output: OutputFormat,
+
         #[command(subcommand)]
         action: Option<ChannelsAction>,
     },
 
     /// Rotate device identity (generate new keys)
-    RotateIdentity,
+    RotateIdentity {
+        /// Rotate at this time instead of now: `+30m`, `+2h`, `+1d`, `HH:MM`,
+        /// `YYYY-MM-DD HH:MM` or RFC 3339
+        #[arg(long)]
+        schedule: Option<String>,
+
+        /// Send the new public key to trusted contacts before switching
+        #[arg(long)]
+        announce: bool,
+    },
 
     /// Manage serial authentication
     Auth {
@@ -150,8 +686,24 @@ pub enum Commands {
         flood: bool,
     },
 
+    /// Flash and provision the devices listed in a fleet manifest
+    Fleet {
+        #[command(subcommand)]
+        action: FleetAction,
+    },
+
+    /// Back up, restore or erase the settings partition, keeping the firmware
+    Nvs {
+        #[command(subcommand)]
+        action: NvsAction,
+    },
+
     /// Flash firmware to device
+    #[command(args_conflicts_with_subcommands = true)]
     Flash {
+        #[command(subcommand)]
+        action: Option<FlashAction>,
+
         /// Board type
         #[arg(short = 'B', long, value_enum)]
         board: Option<BoardType>,
@@ -179,6 +731,54 @@ pub enum Commands {
         /// Use cached firmware only, don't download
         #[arg(long)]
         offline: bool,
+
+        /// Only write flash sectors that differ from the device (read-back
+        /// comparison) instead of erasing and rewriting everything
+        #[arg(long)]
+        incremental: bool,
+
+        /// Don't read the flash back to check its SHA-256 against the
+        /// downloaded firmware after writing
+        #[arg(long)]
+        no_verify: bool,
+
+        /// Flash a version older than the one the device runs
+        #[arg(long)]
+        allow_downgrade: bool,
+
+        /// Don't probe the chip and flash size to check they match the board
+        #[arg(long)]
+        no_hardware_check: bool,
+
+        /// Update an ESP32 board on WiFi over the network (ArduinoOTA)
+        /// instead of USB: its IP address or hostname, optionally with :port
+        #[arg(long, value_name = "HOST", conflicts_with_all = ["local", "detect", "incremental", "monitor"])]
+        ota: Option<String>,
+
+        /// ArduinoOTA password
+        #[arg(
+            long,
+            env = "MESHGRID_OTA_PASSWORD",
+            hide_env_values = true,
+            requires = "ota"
+        )]
+        ota_password: Option<String>,
+    },
+
+    /// Check for newer CLI and firmware releases
+    Update {
+        /// Only print what is available, without offering to install it
+        #[arg(long)]
+        check: bool,
+
+        /// Download and install the latest signed CLI release
+        #[arg(long = "self", conflicts_with = "check")]
+        self_update: bool,
+
+        /// Trusted release signing key (base64), in addition to the
+        /// built-in key and [update] trusted_keys
+        #[arg(long)]
+        trusted_key: Vec<String>,
     },
 
     /// Capture debug output to file
@@ -190,11 +790,91 @@ pub enum Commands {
         /// Timeout in seconds (0 = infinite)
         #[arg(short, long, default_value = "0")]
         timeout: u64,
+
+        /// Only show messages at this level or more severe
+        #[arg(short, long, value_enum, default_value = "trace")]
+        level: DebugLevel,
+
+        /// Only show messages from these modules (comma-separated, e.g. radio,mesh)
+        #[arg(short, long, value_delimiter = ',')]
+        module: Vec<String>,
+
+        /// Pass matching debug frames through as JSON lines
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Run commands from a file over one connection
+    Run {
+        /// Command file (one CLI subcommand or `!RAW COMMAND` per line)
+        file: String,
+
+        /// Variable for `${NAME}` substitution (repeatable)
+        #[arg(long = "var", value_name = "NAME=VALUE")]
+        vars: Vec<String>,
+
+        /// Stop at the first failing line
+        #[arg(long)]
+        stop_on_error: bool,
+    },
+
+    /// Run several commands over one connection, e.g. `batch info neighbors telemetry`
+    Batch {
+        /// Commands as typed after `meshgrid-cli` (quote ones with arguments),
+        /// or `!RAW COMMAND`
+        #[arg(required = true)]
+        commands: Vec<String>,
+
+        /// Stop at the first failing command
+        #[arg(long)]
+        stop_on_error: bool,
     },
 
-    /// Read from stdin and send each line as a command
+    /// Measure command latency, frame throughput and error rate on this link
+    Bench {
+        /// Round trips per test
+        #[arg(long, default_value = "50")]
+        count: usize,
+
+        /// Payload bytes per frame in the throughput test
+        #[arg(long, default_value = "200")]
+        size: usize,
+    },
+
+    /// Read protocol commands from stdin, one per line, and print responses
     #[command(name = "-")]
-    Stdin,
+    Stdin {
+        /// Exit with an error as soon as a command does not return OK/JSON
+        #[arg(long)]
+        expect_ok: bool,
+    },
+
+    /// External plugin (`meshgrid-<name>` on PATH)
+    #[command(external_subcommand)]
+    External(Vec<String>),
+}
+
+impl Commands {
+    /// `--output` of the commands that have one.
+    pub fn output_format(&self) -> OutputFormat {
+        match self {
+            Self::Ports { output }
+            | Self::Info { output }
+            | Self::Stats { output, .. }
+            | Self::Neighbors { output, .. }
+            | Self::Messages { output, .. }
+            | Self::Channels { output, .. }
+            | Self::Plan { output, .. }
+            | Self::Trace { output, .. }
+            | Self::Routes {
+                action: RoutesAction::Show { output, .. },
+            }
+            | Self::Rf {
+                action: RfAction::Abtest { output, .. },
+            } => *output,
+            _ => OutputFormat::Table,
+        }
+    }
 }
 
 #[derive(Subcommand)]
@@ -228,38 +908,764 @@ pub enum ConfigAction {
 }
 
 #[derive(Subcommand)]
-pub enum TimeAction {
-    /// Show current time
-    Show,
+pub enum RawAction {
+    /// Build a MeshCore packet from named fields, print it and optionally send it
+    Build {
+        /// Payload type
+        #[arg(long = "type", value_enum)]
+        payload_type: PayloadType,
+
+        /// Route type
+        #[arg(long, value_enum, default_value = "flood")]
+        route: RouteType,
+
+        /// Payload: the node name for adverts, message text for grp-txt
+        #[arg(long)]
+        payload: Option<String>,
 
-    /// Sync time with computer
-    Sync,
+        /// Treat --payload as the payload bytes in hex (needed for encrypted types)
+        #[arg(long)]
+        hex: bool,
 
-    /// Set time (YYYY-MM-DD HH:MM:SS)
-    Set { time: String },
-}
+        /// Path of node hashes (comma-separated hex, e.g. a1,b2)
+        #[arg(long, value_delimiter = ',')]
+        path: Vec<String>,
 
-#[derive(Subcommand)]
-pub enum MessagesAction {
-    /// Show message inbox
-    Show,
+        /// Ed25519 seed (hex) to sign adverts with (default: a throwaway key)
+        #[arg(long)]
+        key: Option<String>,
 
-    /// Clear message inbox
-    Clear,
+        /// Channel PSK (base64) for grp-txt (default: the public channel)
+        #[arg(long)]
+        channel_psk: Option<String>,
+
+        /// Send the packet instead of only printing it
+        #[arg(long)]
+        send: bool,
+    },
 }
 
 #[derive(Subcommand)]
-pub enum ChannelsAction {
-    /// List custom channels
-    List,
+pub enum ProvisionAction {
+    /// Generate an ed25519 signing key
+    Keygen {
+        /// Where to write the private key (PEM)
+        key: String,
+    },
+
+    /// Sign a provisioning manifest in place
+    Sign {
+        /// Manifest (TOML) to sign
+        bundle: String,
+
+        /// Private signing key (PEM)
+        #[arg(long)]
+        key: String,
+    },
+
+    /// Check a bundle's signature against the trusted keys
+    Verify {
+        bundle: String,
+
+        /// Trusted public key (base64), in addition to [provision] trusted_keys
+        #[arg(long)]
+        trusted_key: Vec<String>,
+    },
+
+    /// Apply a signed bundle to the device
+    Apply {
+        bundle: String,
+
+        /// Trusted public key (base64), in addition to [provision] trusted_keys
+        #[arg(long)]
+        trusted_key: Vec<String>,
+
+        /// Apply unsigned or unverified bundles
+        #[arg(long)]
+        insecure: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum AuditAction {
+    /// Show the most recent entries
+    Show {
+        /// Number of entries to show
+        #[arg(short = 'n', long, default_value = "20")]
+        limit: usize,
+    },
+
+    /// Export the whole log
+    Export {
+        #[arg(long, value_enum, default_value = "jsonl")]
+        format: AuditFormat,
+
+        /// Output file (default: stdout)
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum EventsAction {
+    /// Export the recorded messages, monitor events, telemetry samples and
+    /// audit records merged in time order
+    Export {
+        /// Only records from this time on (e.g. 24h, 7d, 2026-01-12)
+        #[arg(long)]
+        since: Option<String>,
+
+        #[arg(long, value_enum, default_value = "ndjson")]
+        format: EventsFormat,
+
+        /// Output file (default: stdout)
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum FlashAction {
+    /// Write a LittleFS/SPIFFS image (web UI, map tiles) to the filesystem
+    /// partition, building it first when given a directory
+    Fs {
+        /// Image file, or directory to build an image from
+        source: String,
+
+        /// Filesystem to build a directory into
+        #[arg(long, value_enum, default_value = "littlefs")]
+        fs: FsType,
+
+        /// Partition label, when the board has several filesystem partitions
+        #[arg(long)]
+        label: Option<String>,
+
+        /// Don't read the partition back to check the image's SHA-256
+        #[arg(long)]
+        no_verify: bool,
+    },
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum FsType {
+    Littlefs,
+    Spiffs,
+}
+
+#[derive(Subcommand)]
+pub enum FleetAction {
+    /// Bring each connected device to its firmware version and profile
+    Apply {
+        /// Fleet manifest (YAML, or TOML for a .toml file)
+        manifest: String,
+
+        /// Show what would be done without changing any device
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Trusted public key (base64) for the profiles, in addition to
+        /// [provision] trusted_keys
+        #[arg(long)]
+        trusted_key: Vec<String>,
+
+        /// Apply unsigned or unverified profiles
+        #[arg(long)]
+        insecure: bool,
+
+        /// Flash versions older than the ones the devices run
+        #[arg(long)]
+        allow_downgrade: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum NvsAction {
+    /// Save the settings partition to a file
+    Backup {
+        /// Output file
+        #[arg(short, long, default_value = "nvs.bin")]
+        output: String,
+    },
+
+    /// Write a backup to the settings partition (e.g. to clone a device)
+    Restore {
+        /// Backup made with `nvs backup`
+        file: String,
+    },
+
+    /// Erase the settings partition: factory defaults, firmware kept
+    Erase,
+}
+
+#[derive(Subcommand)]
+pub enum VaultAction {
+    /// Encrypt local data files, or the given files
+    Lock {
+        /// Files to encrypt instead of the local data files
+        files: Vec<String>,
+    },
+
+    /// Decrypt local data files, or the given files
+    Unlock {
+        /// Files to decrypt instead of the local data files
+        files: Vec<String>,
+    },
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SimTopology {
+    /// A chain, each node in range of the next
+    Line,
+    /// A square grid
+    Grid,
+    /// Scattered at random
+    Random,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum RadioPreset {
+    /// 869.525 MHz, 250 kHz, SF11, 20 dBm
+    Eu,
+    /// 910.525 MHz, 250 kHz, SF11, 22 dBm
+    Us,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum AuditFormat {
+    /// One JSON object per line
+    Jsonl,
+    /// CSV with a header row
+    Csv,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum EventsFormat {
+    /// One JSON object per line
+    Ndjson,
+    /// JSON array of objects
+    Json,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum NeighborsFormat {
+    /// Aligned columns
+    Table,
+    /// JSON array of objects
+    Json,
+    /// CSV with a header row
+    Csv,
+}
+
+#[derive(Subcommand)]
+pub enum RfAction {
+    /// Sample the noise floor on the configured frequency at intervals, to
+    /// match reliability problems with interference by time of day
+    Noise {
+        /// How long to sample, in hours (0 = until Ctrl+C)
+        #[arg(long, default_value = "24")]
+        hours: f64,
+
+        /// Seconds between samples
+        #[arg(short, long, default_value = "60", value_parser = clap::value_parser!(u64).range(1..))]
+        interval: u64,
+
+        /// CSV file to append the samples to (timestamp, freq_mhz,
+        /// noise_floor_dbm)
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+
+    /// Compare two antennas: probe a node in bursts, swapping antennas
+    /// between bursts when prompted, and report which one does better
+    Abtest {
+        /// Node to probe (name, hash or public key prefix)
+        #[arg(long)]
+        to: String,
+
+        /// Bursts per antenna, run A-B, B-A, A-B... so each round needs one swap
+        #[arg(long, default_value = "3", value_parser = clap::value_parser!(u32).range(1..))]
+        rounds: u32,
+
+        /// Probes per burst
+        #[arg(long, default_value = "10", value_parser = clap::value_parser!(u32).range(1..))]
+        probes: u32,
+
+        /// Seconds to wait for each probe's reply before counting it lost
+        #[arg(short, long, default_value = "10")]
+        timeout: u64,
+
+        /// Names of the two antennas, comma-separated
+        #[arg(long, value_delimiter = ',', default_values = ["A", "B"])]
+        labels: Vec<String>,
+
+        /// Print the bursts and statistics as JSON or YAML
+        #[arg(long, value_enum, default_value = "table")]
+        output: OutputFormat,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum RoutesAction {
+    /// List the learned paths to other nodes, with hop counts and ages
+    Show {
+        /// Only the route to this node (name or hash)
+        #[arg(long)]
+        node: Option<String>,
+
+        /// Output format: aligned columns, or a Graphviz DOT graph
+        #[arg(long, value_enum, default_value = "table")]
+        format: RoutesFormat,
+
+        /// Print the routes as JSON or YAML
+        #[arg(long, value_enum, default_value = "table", conflicts_with = "format")]
+        output: OutputFormat,
+    },
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum RoutesFormat {
+    /// Aligned columns
+    Table,
+    /// Graphviz digraph of the paths (render with `dot -Tsvg`)
+    Dot,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum NeighborSort {
+    Rssi,
+    Lastseen,
+    Name,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum NeighborColumn {
+    Hash,
+    Version,
+    Name,
+    Rssi,
+    Snr,
+    Fw,
+    Distance,
+    Lastseen,
+    Lat,
+    Lon,
+}
+
+/// Firmware debug message severity, least severe first
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum DebugLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+#[derive(Subcommand)]
+pub enum TimeAction {
+    /// Show current time
+    Show,
+
+    /// Sync time with computer
+    Sync {
+        /// Then push the time to every repeater and room server in the
+        /// neighbor table over the mesh, reporting their offsets
+        #[arg(long)]
+        mesh: bool,
+
+        /// Seconds to wait for each node's reply
+        #[arg(long, default_value = "20", requires = "mesh")]
+        timeout: u64,
+
+        /// Remote admin password of the nodes, if they require one
+        #[arg(
+            long,
+            env = "MESHGRID_ADMIN_PASSWORD",
+            hide_env_values = true,
+            requires = "mesh"
+        )]
+        admin_password: Option<String>,
+    },
+
+    /// Set time (YYYY-MM-DD HH:MM:SS)
+    Set { time: String },
+}
+
+#[derive(Subcommand)]
+pub enum PositionAction {
+    /// Show the position the device reports
+    Show,
+
+    /// Set a fixed position, included in adverts (for nodes without GPS)
+    #[command(allow_negative_numbers = true)]
+    Set {
+        /// Latitude in decimal degrees
+        lat: f64,
+
+        /// Longitude in decimal degrees
+        lon: f64,
+
+        /// Altitude in meters
+        #[arg(long)]
+        alt: Option<f64>,
+    },
+
+    /// Send an advertisement with the position now
+    Send {
+        /// Flood the advertisement instead of sending it to neighbors only
+        #[arg(long)]
+        flood: bool,
+    },
+
+    /// Ask a node over the mesh for its position (e.g. a tracker)
+    Request {
+        /// Node to ask (name, hash or public key prefix)
+        node: String,
+
+        /// Seconds to wait for the reply
+        #[arg(short, long, default_value = "30")]
+        timeout: u64,
+
+        /// Query the neighbor table instead of resolving the node from the cache
+        #[arg(long)]
+        refresh: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum LogAction {
+    /// Download the whole log to a file, with timestamps and levels
+    Export {
+        /// File to write
+        #[arg(short, long)]
+        output: String,
+
+        /// Only lines since then: `30m`, `24h`, `7d`, `YYYY-MM-DD` or RFC 3339
+        #[arg(long)]
+        since: Option<String>,
+
+        /// Write JSON lines, with the raw device timestamps
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Print the stored log of this device or of a remote node
+    Show {
+        /// Remote node to fetch the log from over the mesh (name, hash or
+        /// public key prefix)
+        #[arg(long)]
+        node: Option<String>,
+
+        /// Only the last N lines
+        #[arg(short = 'n', long)]
+        lines: Option<usize>,
+
+        /// Seconds to wait for each page from the node
+        #[arg(short, long, default_value = "30")]
+        timeout: u64,
+
+        /// Times to ask for a page before giving up
+        #[arg(long, default_value = "3")]
+        retries: u32,
+
+        /// Query the neighbor table instead of resolving the node from the cache
+        #[arg(long)]
+        refresh: bool,
+
+        /// Print JSON lines, with the raw device timestamps
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Print live log output until stopped
+    Follow {
+        /// Print JSON lines, with the raw device timestamps
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Show or change the firmware's log verbosity at runtime
+    Level {
+        /// New level (omit to show the current levels)
+        #[arg(value_enum)]
+        level: Option<LogLevel>,
+
+        /// Only change this subsystem
+        #[arg(long, value_enum, requires = "level")]
+        module: Option<LogModule>,
+    },
+
+    /// Delete the log stored on the device
+    Clear,
+
+    /// Forward live debug output to syslog or journald until stopped
+    Forward {
+        /// Syslog server: udp://host[:514] or tcp://host[:601]
+        #[arg(
+            long,
+            required_unless_present = "journald",
+            conflicts_with = "journald"
+        )]
+        syslog: Option<String>,
+
+        /// Write to the systemd journal (Linux)
+        #[arg(long)]
+        journald: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum TrackAction {
+    /// Poll a tracker for its position and show speed, heading and distance
+    Follow {
+        /// Tracker to follow (name, hash or public key prefix)
+        node: String,
+
+        /// Seconds between position requests
+        #[arg(short, long, default_value = "60")]
+        interval: u64,
+
+        /// Reference point for distances as LAT,LON (default: this device)
+        #[arg(long, allow_hyphen_values = true)]
+        from: Option<String>,
+
+        /// Also append the track to this GPX file
+        #[arg(long)]
+        gpx: Option<String>,
+
+        /// Query the neighbor table instead of resolving the node from the cache
+        #[arg(long)]
+        refresh: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum NodesAction {
+    /// List recorded nodes, most recently seen first
+    List {
+        /// Print the nodes as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Show everything recorded about a node
+    Show {
+        /// Node name, hash or public key prefix
+        node: String,
+    },
+
+    /// Attach a note to a node (replacing any earlier one)
+    Annotate {
+        /// Node name, hash or public key prefix
+        node: String,
+
+        #[arg(required_unless_present = "clear")]
+        note: Option<String>,
+
+        /// Remove the note instead
+        #[arg(long, conflicts_with = "note")]
+        clear: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum WaypointsAction {
+    /// Save a waypoint (replacing one of the same name)
+    #[command(allow_negative_numbers = true)]
+    Add {
+        /// Waypoint name
+        name: String,
+
+        /// Latitude in decimal degrees
+        lat: f64,
+
+        /// Longitude in decimal degrees
+        lon: f64,
+
+        /// Symbol for devices with maps, e.g. an emoji
+        #[arg(long)]
+        icon: Option<String>,
+
+        /// Hide the waypoint after this time: `+2h`, `+1d`, `HH:MM`,
+        /// `YYYY-MM-DD HH:MM` or RFC 3339
+        #[arg(long)]
+        expires: Option<String>,
+    },
+
+    /// List saved waypoints
+    List,
+
+    /// Delete a saved waypoint
+    Remove {
+        /// Waypoint name
+        name: String,
+    },
+
+    /// Broadcast a saved waypoint to the mesh
+    Send {
+        /// Waypoint name
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum MessagesAction {
+    /// Show message inbox
+    Show,
+
+    /// Clear message inbox
+    Clear,
+}
+
+#[derive(Subcommand)]
+pub enum OutboxAction {
+    /// List queued messages
+    List,
+
+    /// Drop a queued message
+    Remove {
+        /// Message number, as listed
+        id: u32,
+    },
+
+    /// Drop every queued message
+    Clear,
+}
+
+#[derive(Subcommand)]
+pub enum ChannelsAction {
+    /// List custom channels
+    List,
 
     /// Add a custom channel
     /// For hashtag channels (e.g., #test), PSK is auto-generated as SHA256(name)
     /// For private channels, PSK must be provided (16 or 32 bytes, base64-encoded)
-    Add { name: String, psk: Option<String> },
+    Add {
+        #[arg(required_unless_present_any = ["url", "qr"])]
+        name: Option<String>,
+        psk: Option<String>,
+
+        /// Import a share link (meshgrid://channel?...) from `channels share`
+        #[arg(long, conflicts_with_all = ["name", "psk", "qr"])]
+        url: Option<String>,
+
+        /// Import a share link from a QR code image (needs zbarimg)
+        #[arg(long, conflicts_with_all = ["name", "psk"])]
+        qr: Option<String>,
+    },
+
+    /// Print a share link and QR code for a channel
+    ///
+    /// Anyone with the link can read and post to the channel.
+    Share {
+        name: String,
+
+        /// PSK of a private channel, if the device does not report it
+        #[arg(long)]
+        psk: Option<String>,
+    },
+
+    /// Show the stored messages of one channel
+    History {
+        /// Channel name, or a room server node with --room
+        name: String,
+
+        /// Most recent messages to show
+        #[arg(short = 'n', long, default_value = "50")]
+        limit: usize,
+
+        /// Fetch the posts kept by room server NAME instead
+        #[arg(long)]
+        room: bool,
+
+        /// Query the neighbor table instead of resolving the room server from the cache
+        #[arg(long, requires = "room")]
+        refresh: bool,
+    },
+
+    /// Listen for channel traffic and report which channels are active nearby
+    ///
+    /// Shows the channel hashes heard, how many senders each has and whether
+    /// this device holds a matching PSK.
+    Scan {
+        /// How long to listen
+        #[arg(long, default_value = "300")]
+        seconds: u64,
+    },
+
+    /// Mute a channel or give it priority for notifications, hooks and the
+    /// TUI unread counters; shows the setting without a flag
+    #[command(group = clap::ArgGroup::new("level").args(["mute", "notify", "priority"]))]
+    Config {
+        name: String,
+
+        /// No notifications, `message` hook or unread count
+        #[arg(long)]
+        mute: bool,
+
+        /// Notify as usual (the default)
+        #[arg(long)]
+        notify: bool,
+
+        /// Notify urgently and list first
+        #[arg(long)]
+        priority: bool,
+    },
+
+    /// Message counts, senders, busiest hours and signal per channel, from
+    /// the history recorded by `monitor` and the UI
+    Stats {
+        /// Only messages since then: `24h`, `7d`, `YYYY-MM-DD` or RFC 3339
+        #[arg(long)]
+        since: Option<String>,
+
+        /// Print the statistics as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Join the channels listed in a file, in order, and set the default
+    ///
+    /// Channels already in place are left alone, so importing the same file
+    /// again changes nothing.
+    Import {
+        /// Channel file as written by `channels export`: YAML, or TOML
+        /// `[[channels]]` for a `.toml` file (provisioning manifests work too)
+        file: String,
+
+        /// Also remove custom channels that are not in the file
+        #[arg(long)]
+        prune: bool,
+
+        /// Show the changes without making them
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Write the custom channels, their PSKs, order and default to a file
+    /// for `channels import`
+    Export {
+        /// Output file (defaults to stdout); YAML unless it ends in `.toml`
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+
+    /// Show or set the channel `send` and the TUI use when no destination is
+    /// given (Public unless set; `Public` resets it)
+    Default {
+        /// Channel to make the default
+        name: Option<String>,
+    },
 
     /// Remove a custom channel
     Remove { name: String },
+
+    /// Check channel PSKs for weak, well-known or reused keys
+    Audit {
+        /// Provisioning manifests (.toml) or CHANNELS JSON exports to review
+        /// instead of the connected device
+        files: Vec<String>,
+    },
 }
 
 #[derive(Subcommand)]
@@ -278,6 +1684,251 @@ pub enum AuthAction {
 
     /// Disable serial authentication
     Disable,
+
+    /// Save the PIN/password for this device in the OS keyring
+    ///
+    /// Later commands authenticate with it automatically, keyed by the
+    /// device's public key.
+    Save {
+        /// PIN or password (prompted for when omitted)
+        pin: Option<String>,
+
+        /// Without an OS keyring, store the PIN unencrypted in credentials.toml
+        #[arg(long)]
+        plaintext: bool,
+    },
+
+    /// Remove the saved PIN/password for this device
+    Forget,
+}
+
+#[derive(Subcommand)]
+pub enum ContactsAction {
+    /// Show a contact's key fingerprint as words and a QR code
+    Verify {
+        /// Contact name, node hash or public key prefix (omit for this device)
+        node: Option<String>,
+    },
+
+    /// Mark a contact's current key as verified
+    Trust {
+        /// Contact name, node hash or public key prefix
+        node: String,
+    },
+
+    /// Copy the contacts one device knows to another (e.g. a new handheld)
+    Sync {
+        /// Device to read contacts from
+        #[arg(long)]
+        from: String,
+
+        /// Device to add them to
+        #[arg(long)]
+        to: String,
+
+        /// Show what would be added without changing the target
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum BridgeAction {
+    /// Forward direct messages to email and relay inbound emails as DMs
+    ///
+    /// Address <-> node mappings are read from [bridge.email.contacts] in the
+    /// config file; only mapped addresses can message the mesh.
+    Email {
+        /// SMTP server ([smtp://|smtps://]host[:port])
+        #[arg(long)]
+        smtp: String,
+
+        /// IMAP server ([imap://|imaps://]host[:port])
+        #[arg(long)]
+        imap: String,
+
+        /// Mail account user name
+        #[arg(long)]
+        user: String,
+
+        /// Mail account password
+        #[arg(long, env = "MESHGRID_MAIL_PASSWORD", hide_env_values = true)]
+        password: String,
+
+        /// Sender address for forwarded messages (defaults to --user)
+        #[arg(long)]
+        from: Option<String>,
+
+        /// Only forward DMs matching this regex; named groups `to` and `text`
+        /// override the recipient address and the forwarded text
+        #[arg(long)]
+        pattern: Option<String>,
+
+        /// Inbox poll interval in seconds
+        #[arg(long, default_value = "60")]
+        poll_interval: u64,
+
+        /// Longest text relayed into the mesh, in bytes (longer emails are
+        /// truncated)
+        #[arg(long, default_value = "140")]
+        max_len: usize,
+    },
+
+    /// Mirror mesh traffic to a Discord/Slack webhook and accept messages to send
+    Webhook {
+        /// Incoming webhook URL that mesh traffic is posted to
+        #[arg(long)]
+        incoming: String,
+
+        /// Payload format (auto detects Discord and Slack from the URL)
+        #[arg(long, value_enum, default_value = "auto")]
+        format: WebhookFormat,
+
+        /// Also mirror direct messages addressed to this node
+        #[arg(long)]
+        dms: bool,
+
+        /// Address to accept outgoing messages on (POST /send), e.g. 127.0.0.1:8787
+        #[arg(long)]
+        listen: Option<String>,
+
+        /// Bearer token required by the listener
+        #[arg(long, env = "MESHGRID_WEBHOOK_TOKEN", hide_env_values = true)]
+        token: Option<String>,
+
+        /// Channel for outgoing messages (default: public broadcast)
+        #[arg(long)]
+        channel: Option<String>,
+    },
+    /// Publish channel messages to Nostr relays and relay mentions/DMs back
+    ///
+    /// Notes are signed with a key derived from a local seed and the node's
+    /// public key (or --secret-key); its npub is printed on start.
+    Nostr {
+        /// Relay URL (wss://...); repeat for several relays
+        #[arg(long, required = true)]
+        relay: Vec<String>,
+
+        /// Sign with this key (nsec or hex) instead of the derived one
+        #[arg(long, env = "MESHGRID_NOSTR_SECRET", hide_env_values = true)]
+        secret_key: Option<String>,
+
+        /// Hashtag added to published notes
+        #[arg(long, default_value = "meshgrid")]
+        hashtag: String,
+
+        /// Only relay mentions/DMs from these public keys (npub or hex); repeatable
+        #[arg(long)]
+        allow: Vec<String>,
+
+        /// Channel for messages from Nostr (default: public broadcast)
+        #[arg(long)]
+        channel: Option<String>,
+
+        /// Longest text relayed into the mesh, in bytes
+        #[arg(long, default_value = "140")]
+        max_len: usize,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum GpsAction {
+    /// Stream fixes into the device's position, for nodes without GPS
+    Feed {
+        /// GPS serial port (e.g. /dev/ttyUSB1) or gpsd://host[:port]
+        #[arg(long)]
+        source: String,
+
+        /// Baud rate of a serial GPS receiver
+        #[arg(long, default_value = "9600")]
+        gps_baud: u32,
+
+        /// Minimum seconds between position updates
+        #[arg(short, long, default_value = "30")]
+        interval: u64,
+    },
+
+    /// Show the device's GNSS fix, satellites and time to first fix
+    Status {
+        /// Refresh every second
+        #[arg(short, long)]
+        watch: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ExportAction {
+    /// Write environment telemetry as a WeeWX loop packet or JSON feed
+    Weather {
+        /// File to (atomically) rewrite with each reading
+        #[arg(long)]
+        loop_file: String,
+
+        /// Sensor node to poll over the mesh (default: the attached device)
+        #[arg(long)]
+        node: Option<String>,
+
+        /// Output format
+        #[arg(long, value_enum, default_value = "weewx")]
+        format: WeatherFormat,
+
+        /// Seconds between readings
+        #[arg(long, default_value = "60")]
+        interval: u64,
+
+        /// Write one reading and exit
+        #[arg(long)]
+        once: bool,
+    },
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum WeatherFormat {
+    /// WeeWX loop packet (dateTime, usUnits=METRIC, outTemp, outHumidity, pressure)
+    Weewx,
+    /// Simple JSON feed (timestamp, node, temperature_c, humidity_pct, pressure_hpa)
+    Json,
+}
+
+#[derive(Subcommand)]
+pub enum ServeAction {
+    /// gRPC service for remote control (see proto/meshgrid.proto)
+    Grpc {
+        /// Address to listen on (`:50051` listens on all interfaces)
+        #[arg(long, default_value = "127.0.0.1:50051")]
+        listen: String,
+
+        /// Bearer token with full access (`authorization` metadata); scoped
+        /// tokens go in `[[serve.tokens]]` in the config file
+        #[arg(long, env = "MESHGRID_GRPC_TOKEN", hide_env_values = true)]
+        token: Option<String>,
+    },
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum WebhookFormat {
+    /// Detect from the webhook URL (Discord, Slack, otherwise JSON)
+    Auto,
+    Discord,
+    Slack,
+    /// Plain JSON object with from/to/text/rssi fields
+    Json,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+/// Firmware subsystems with their own log level.
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum LogModule {
+    Radio,
+    Mesh,
+    Storage,
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
@@ -0,0 +1,98 @@
+//! Audit log commands
+
+use crate::audit::{self, Record};
+use crate::cli::{AuditAction, AuditFormat};
+use crate::output;
+use anyhow::{Context, Result};
+use std::io::Write;
+
+/// Show or export the audit log
+pub fn cmd_audit(action: AuditAction) -> Result<()> {
+    let records = audit::read()?;
+
+    match action {
+        AuditAction::Show { limit } => {
+            if records.is_empty() {
+                println!("Audit log is empty ({}).", audit::path()?.display());
+                return Ok(());
+            }
+            let start = records.len().saturating_sub(limit);
+            println!(
+                "Audit log ({} of {} entries):\n",
+                records.len() - start,
+                records.len()
+            );
+            for record in &records[start..] {
+                let time = chrono::DateTime::parse_from_rfc3339(&record.timestamp)
+                    .map(|t| crate::timefmt::datetime(&t))
+                    .unwrap_or_else(|_| record.timestamp.clone());
+                let mark = if record.outcome == "ok" { "✓" } else { "✗" };
+                println!(
+                    "  {time}  {mark} {:10} {:24} {}",
+                    record.user,
+                    device_label(record),
+                    record.operation
+                );
+                if let Some(error) = &record.error {
+                    println!("      {error}");
+                }
+            }
+        }
+        AuditAction::Export { format, output } => {
+            let mut out = String::new();
+            match format {
+                AuditFormat::Jsonl => {
+                    for record in &records {
+                        out.push_str(&serde_json::to_string(record)?);
+                        out.push('\n');
+                    }
+                }
+                AuditFormat::Csv => {
+                    out.push_str(
+                        "timestamp,user,operation,port,device_name,public_key,outcome,error\n",
+                    );
+                    for record in &records {
+                        let device = &record.device;
+                        let fields = [
+                            record.timestamp.as_str(),
+                            &record.user,
+                            &record.operation,
+                            device.port.as_deref().unwrap_or_default(),
+                            device.name.as_deref().unwrap_or_default(),
+                            device.public_key.as_deref().unwrap_or_default(),
+                            &record.outcome,
+                            record.error.as_deref().unwrap_or_default(),
+                        ];
+                        let row: Vec<String> =
+                            fields.iter().map(|f| output::csv_field(f)).collect();
+                        out.push_str(&row.join(","));
+                        out.push('\n');
+                    }
+                }
+            }
+            match output {
+                Some(path) => {
+                    std::fs::write(&path, out)
+                        .with_context(|| format!("Failed to write {path}"))?;
+                    eprintln!("✓ Exported {} entries to {path}", records.len());
+                }
+                None => match std::io::stdout().write_all(out.as_bytes()) {
+                    Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => {}
+                    other => other?,
+                },
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Short device description: name and key prefix, or the port.
+fn device_label(record: &Record) -> String {
+    let device = &record.device;
+    match (&device.name, &device.public_key) {
+        (Some(name), Some(key)) => format!("{name} ({})", &key[..8.min(key.len())]),
+        (None, Some(key)) => key[..16.min(key.len())].to_string(),
+        _ => device.port.clone().unwrap_or_else(|| "?".into()),
+    }
+}
@@ -0,0 +1,366 @@
+//! Batch execution of command files
+//!
+//! A command file lists one command per line:
+//!
+//! ```text
+//! # Commission a repeater
+//! config name "rpt-${SITE}"
+//! config preset EU
+//! mode repeater
+//! # Raw protocol command, sent verbatim
+//! !SET POWER 20
+//! ```
+//!
+//! Lines are CLI subcommands as typed after `meshgrid-cli`, or raw protocol
+//! commands when prefixed with `!`. `${NAME}` is replaced with `--var NAME=value`
+//! or, failing that, the environment variable `NAME`, within the word it
+//! appears in. All lines share one device connection through a local proxy.
+//!
+//! `batch` runs commands given as arguments the same way, e.g.
+//! `meshgrid-cli batch info neighbors telemetry`.
+
+use super::connect_with_auth;
+use crate::audit;
+use crate::cli::{Cli, Commands};
+use crate::error::CliError;
+use crate::protocol::Response;
+use crate::proxy::DeviceProxy;
+use anyhow::{anyhow, bail, Context, Result};
+use clap::Parser;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+
+/// Executes a parsed CLI invocation (the main dispatcher).
+pub type CommandRunner = dyn Fn(Cli) -> Pin<Box<dyn Future<Output = Result<()>>>>;
+
+/// One executable line of a command file.
+enum Step {
+    /// CLI subcommand arguments
+    Cli(Vec<String>),
+    /// Raw protocol command
+    Raw(String),
+}
+
+/// Replace `${NAME}` references from `vars`, then the environment.
+fn substitute(line: &str, vars: &HashMap<String, String>) -> Result<String> {
+    let mut out = String::with_capacity(line.len());
+    let mut rest = line;
+
+    while let Some(start) = rest.find("${") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find('}')
+            .ok_or_else(|| anyhow!("Unterminated variable reference"))?;
+        let name = &after[..end];
+        let value = vars
+            .get(name)
+            .cloned()
+            .or_else(|| std::env::var(name).ok())
+            .ok_or_else(|| anyhow!("Undefined variable '{name}'"))?;
+        out.push_str(&value);
+        rest = &after[end + 1..];
+    }
+    out.push_str(rest);
+
+    Ok(out)
+}
+
+/// Parse `NAME=value` pairs from `--var`.
+pub fn parse_vars(vars: &[String]) -> Result<HashMap<String, String>> {
+    vars.iter()
+        .map(|v| {
+            v.split_once('=')
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .ok_or_else(|| {
+                    CliError::InvalidArgs(format!("Invalid --var '{v}' (expected NAME=value)"))
+                        .into()
+                })
+        })
+        .collect()
+}
+
+/// Turn a command file line into a step (None for blanks and comments).
+fn parse_line(line: &str, vars: &HashMap<String, String>) -> Result<Option<Step>> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+
+    if let Some(raw) = line.strip_prefix('!') {
+        return Ok(Some(Step::Raw(substitute(raw.trim(), vars)?)));
+    }
+
+    // shell_words drops unquoted `# comments` and keeps quoted `"#channel"`;
+    // variables are filled in afterwards so a value is always one word
+    let words = shell_words::split(line).context("Invalid quoting")?;
+    let words = words
+        .iter()
+        .map(|word| substitute(word, vars))
+        .collect::<Result<_>>()?;
+    Ok(Some(Step::Cli(words)))
+}
+
+/// Run a single step against the proxied device on `port`.
+async fn run_step(
+    step: Step,
+    port: &str,
+    address: &str,
+    baud: u32,
+    pin: Option<&str>,
+    runner: &CommandRunner,
+) -> Result<()> {
+    match step {
+        Step::Raw(cmd) => {
+            let audited = audit::describe_command(&cmd)
+                .map(|op| audit::Operation::named(format!("!{op}"), Some(port)))
+                .transpose()?;
+            let result = async {
+                let mut proto = connect_with_auth(address, baud, pin).await?.into_protocol();
+                match proto.command(&cmd).await? {
+                    Response::Ok(msg) => println!("{}", msg.unwrap_or_else(|| "OK".into())),
+                    Response::Json(json) => println!("{json}"),
+                    Response::Error(e) => bail!(CliError::Device(e)),
+                }
+                Ok(())
+            }
+            .await;
+            match audited {
+                Some(operation) => operation.close(result),
+                None => result,
+            }
+        }
+        Step::Cli(words) => {
+            let mut args = vec![
+                "meshgrid-cli".to_string(),
+                "--port".to_string(),
+                address.to_string(),
+                "--baud".to_string(),
+                baud.to_string(),
+            ];
+            if let Some(pin) = pin {
+                args.push("--pin".into());
+                args.push(pin.into());
+            }
+            args.extend(words);
+
+            let cli = Cli::try_parse_from(args)
+                .map_err(|e| CliError::InvalidArgs(e.render().to_string().trim().to_string()))?;
+            if matches!(cli.command, Commands::Run { .. } | Commands::Batch { .. }) {
+                bail!(CliError::InvalidArgs(
+                    "Nested 'run' or 'batch' is not supported".into()
+                ));
+            }
+            match audit::Operation::of(&cli.command, Some(port))? {
+                Some(operation) => operation.close(runner(cli).await),
+                None => runner(cli).await,
+            }
+        }
+    }
+}
+
+/// Execute a command file over one device connection
+pub async fn cmd_run(
+    port: &str,
+    baud: u32,
+    pin: Option<&str>,
+    file: &str,
+    vars: &[String],
+    stop_on_error: bool,
+    runner: &CommandRunner,
+) -> Result<()> {
+    let vars = parse_vars(vars)?;
+    let content =
+        std::fs::read_to_string(file).with_context(|| format!("Failed to read {file}"))?;
+
+    let lines: Vec<(String, &str)> = content
+        .lines()
+        .enumerate()
+        .map(|(i, l)| (format!("line {}", i + 1), l.trim()))
+        .filter(|(_, l)| !l.is_empty() && !l.starts_with('#'))
+        .collect();
+
+    run_lines(port, baud, pin, &lines, &vars, stop_on_error, runner).await
+}
+
+/// Execute commands given as arguments over one device connection
+pub async fn cmd_batch(
+    port: &str,
+    baud: u32,
+    pin: Option<&str>,
+    commands: &[String],
+    stop_on_error: bool,
+    runner: &CommandRunner,
+) -> Result<()> {
+    let lines: Vec<(String, &str)> = commands
+        .iter()
+        .enumerate()
+        .map(|(i, c)| (format!("command {}", i + 1), c.trim()))
+        .collect();
+
+    run_lines(
+        port,
+        baud,
+        pin,
+        &lines,
+        &HashMap::new(),
+        stop_on_error,
+        runner,
+    )
+    .await
+}
+
+/// Run (label, line) pairs through a proxy for `port`, reporting each result.
+async fn run_lines(
+    port: &str,
+    baud: u32,
+    pin: Option<&str>,
+    lines: &[(String, &str)],
+    vars: &HashMap<String, String>,
+    stop_on_error: bool,
+    runner: &CommandRunner,
+) -> Result<()> {
+    let proxy = DeviceProxy::start(port, baud).await?;
+    let total = lines.len();
+    let mut succeeded = 0;
+    let mut failed = 0;
+
+    for (i, (label, line)) in lines.iter().enumerate() {
+        println!("[{}/{total}] {line}", i + 1);
+
+        let result = match parse_line(line, vars) {
+            Ok(Some(step)) => run_step(step, port, proxy.address(), baud, pin, runner).await,
+            Ok(None) => continue,
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(()) => {
+                succeeded += 1;
+                println!("  ✓ ok\n");
+            }
+            Err(e) => {
+                failed += 1;
+                println!("  ✗ {label}: {e:#}\n");
+                if stop_on_error {
+                    break;
+                }
+            }
+        }
+    }
+
+    println!("{succeeded} succeeded, {failed} failed");
+    if failed > 0 {
+        bail!("{failed} of {total} commands failed");
+    }
+    Ok(())
+}
+
+/// Send protocol commands read from stdin over one connection
+///
+/// Each response is printed on one line: `OK [message]`, `ERR <message>`, or
+/// compact JSON. With `expect_ok`, the first `ERR` ends the session with a
+/// device error exit code.
+pub async fn cmd_stdin(port: &str, baud: u32, pin: Option<&str>, expect_ok: bool) -> Result<()> {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    super::restore_blocking_stdin();
+
+    let dev = connect_with_auth(port, baud, pin).await?;
+    let mut proto = dev.into_protocol();
+
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut stdout = tokio::io::stdout();
+
+    while let Some(line) = lines.next_line().await? {
+        let cmd = line.trim();
+        if cmd.is_empty() || cmd.starts_with('#') {
+            continue;
+        }
+
+        let audited = audit::describe_command(cmd)
+            .map(|op| audit::Operation::named(format!("stdin {op}"), Some(port)))
+            .transpose()?;
+        let response = proto.command(cmd).await;
+        if let Some(operation) = audited {
+            let outcome = match &response {
+                Ok(Response::Error(e)) => Err(e.clone()),
+                Ok(_) => Ok(()),
+                Err(e) => Err(format!("{e:#}")),
+            };
+            operation.finish(&outcome)?;
+        }
+        let output = match response? {
+            Response::Ok(Some(msg)) => format!("OK {msg}"),
+            Response::Ok(None) => "OK".to_string(),
+            Response::Json(json) => json.to_string(),
+            Response::Error(e) => {
+                if expect_ok {
+                    bail!(CliError::Device(format!("'{cmd}' failed: {e}")));
+                }
+                format!("ERR {e}")
+            }
+        };
+        stdout.write_all(output.as_bytes()).await?;
+        stdout.write_all(b"\n").await?;
+        stdout.flush().await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_substitute_vars_and_env() {
+        let vars = HashMap::from([("SITE".to_string(), "hill".to_string())]);
+        assert_eq!(
+            substitute("config name rpt-${SITE}", &vars).unwrap(),
+            "config name rpt-hill"
+        );
+        assert!(substitute("config name ${MESHGRID_TEST_UNDEFINED}", &vars).is_err());
+        assert!(substitute("config name ${SITE", &vars).is_err());
+    }
+
+    #[test]
+    fn test_parse_line_comments() {
+        let vars = HashMap::new();
+        let words = |line| match parse_line(line, &vars).unwrap() {
+            Some(Step::Cli(words)) => words,
+            _ => panic!("not a CLI step: {line}"),
+        };
+        assert_eq!(
+            words("channels add \"#hike\""),
+            ["channels", "add", "#hike"]
+        );
+        assert_eq!(words("info # device info"), ["info"]);
+        assert!(parse_line("# comment", &vars).unwrap().is_none());
+        // Raw commands go out as written
+        assert!(matches!(
+            parse_line("!CHANNEL JOIN #public", &vars).unwrap(),
+            Some(Step::Raw(cmd)) if cmd == "CHANNEL JOIN #public"
+        ));
+    }
+
+    #[test]
+    fn test_parse_line_vars() {
+        let vars = parse_vars(&["NAME=North Ridge".into(), "Q=it's".into()]).unwrap();
+        let step = |line| parse_line(line, &vars).unwrap().unwrap();
+        assert!(matches!(
+            step("config name ${NAME}"),
+            Step::Cli(words) if words == ["config", "name", "North Ridge"]
+        ));
+        assert!(matches!(
+            step("send --to ${NAME} \"${Q} up\""),
+            Step::Cli(words) if words == ["send", "--to", "North Ridge", "it's up"]
+        ));
+        assert!(matches!(
+            step("!SET NAME ${NAME}"),
+            Step::Raw(cmd) if cmd == "SET NAME North Ridge"
+        ));
+        assert!(parse_line("info ${MESHGRID_TEST_UNDEFINED}", &vars).is_err());
+    }
+}
@@ -0,0 +1,169 @@
+//! Link benchmark
+
+use super::connect_with_auth;
+use crate::output;
+use crate::protocol::{Protocol, Response};
+use anyhow::{bail, Result};
+use std::time::{Duration, Instant};
+
+/// Standard baud rates the firmware can be configured for.
+const BAUD_RATES: [u32; 5] = [115_200, 230_400, 460_800, 921_600, 2_000_000];
+
+/// Measure command latency, frame throughput and error rate
+pub async fn cmd_bench(
+    port: &str,
+    baud: u32,
+    pin: Option<&str>,
+    count: usize,
+    size: usize,
+) -> Result<()> {
+    if count == 0 {
+        bail!("--count must be at least 1");
+    }
+    let mut proto = connect_with_auth(port, baud, pin).await?.into_protocol();
+    if !output::is_plain() {
+        println!("Benchmarking {port} at {baud} baud ({count} round trips per test)...\n");
+    }
+
+    // Latency: empty PINGs
+    let mut latencies = Vec::with_capacity(count);
+    let mut errors = 0;
+    for _ in 0..count {
+        let start = Instant::now();
+        match ping(&mut proto, "PING").await {
+            Ok(_) => latencies.push(start.elapsed()),
+            Err(e) => {
+                tracing::debug!("PING failed: {e:#}");
+                errors += 1;
+            }
+        }
+    }
+    latencies.sort();
+
+    // Throughput: PINGs carrying `size` bytes of padding
+    let cmd = format!("PING {}", "x".repeat(size));
+    let mut bytes = 0;
+    let mut frames = 0;
+    let start = Instant::now();
+    for _ in 0..count {
+        match ping(&mut proto, &cmd).await {
+            Ok(reply_len) => {
+                // Frame delimiter and COBS overhead byte each way
+                bytes += cmd.len() + reply_len + 4;
+                frames += 2;
+            }
+            Err(e) => {
+                tracing::debug!("PING failed: {e:#}");
+                errors += 1;
+            }
+        }
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    let bytes_per_sec = bytes as f64 / elapsed;
+    let frames_per_sec = f64::from(frames) / elapsed;
+    let total = 2 * count;
+    let error_pct = 100.0 * errors as f64 / total as f64;
+
+    if output::is_plain() {
+        println!(
+            "latency_min_ms={:.2} latency_avg_ms={:.2} latency_p95_ms={:.2} latency_max_ms={:.2} \
+             frames_per_sec={frames_per_sec:.0} bytes_per_sec={bytes_per_sec:.0} \
+             errors={errors} total={total}",
+            ms(latencies.first()),
+            ms(average(&latencies).as_ref()),
+            ms(percentile(&latencies, 95)),
+            ms(latencies.last()),
+        );
+    } else {
+        println!(
+            "Latency:    min {:.2} ms  avg {:.2} ms  p95 {:.2} ms  max {:.2} ms",
+            ms(latencies.first()),
+            ms(average(&latencies).as_ref()),
+            ms(percentile(&latencies, 95)),
+            ms(latencies.last()),
+        );
+        println!(
+            "Throughput: {frames_per_sec:.0} frames/s, {:.1} KB/s ({size}-byte payloads)",
+            bytes_per_sec / 1024.0
+        );
+        println!("Errors:     {errors} of {total} ({error_pct:.1}%)");
+    }
+
+    if latencies.is_empty() {
+        bail!("Device did not answer PING");
+    }
+    if !output::is_plain() {
+        println!();
+        suggest_baud(port, baud, bytes_per_sec, errors);
+    }
+    Ok(())
+}
+
+/// Length of the reply to a PING command.
+async fn ping(proto: &mut Protocol, cmd: &str) -> Result<usize> {
+    match proto.command(cmd).await? {
+        Response::Ok(reply) => Ok(reply.map_or(2, |r| r.len())),
+        Response::Error(e) => bail!("{e}"),
+        Response::Json(json) => Ok(json.to_string().len()),
+    }
+}
+
+fn suggest_baud(port: &str, baud: u32, bytes_per_sec: f64, errors: usize) {
+    if port.starts_with("tcp://") || port.starts_with("unix:") {
+        println!("Network link: baud rate does not apply.");
+        return;
+    }
+    match next_baud(baud, bytes_per_sec, errors) {
+        Some(faster) => println!(
+            "⚠ The link is near its limit at {baud} baud. If the firmware is set to match, \
+             try `--baud {faster}`."
+        ),
+        None if errors > 0 => {
+            println!("⚠ Errors at {baud} baud: check the cable or try a lower baud rate.");
+        }
+        None => println!("✓ {baud} baud has headroom for this workload."),
+    }
+}
+
+/// Next standard baud rate when a clean link uses more than half its
+/// capacity (10 bits per byte on the wire).
+fn next_baud(baud: u32, bytes_per_sec: f64, errors: usize) -> Option<u32> {
+    let utilization = bytes_per_sec * 10.0 / f64::from(baud);
+    if errors > 0 || utilization < 0.5 {
+        return None;
+    }
+    BAUD_RATES.into_iter().find(|&rate| rate > baud)
+}
+
+fn average(samples: &[Duration]) -> Option<Duration> {
+    let count = u32::try_from(samples.len()).ok().filter(|&n| n > 0)?;
+    Some(samples.iter().sum::<Duration>() / count)
+}
+
+/// `pct`th percentile of sorted samples.
+fn percentile(sorted: &[Duration], pct: usize) -> Option<&Duration> {
+    let index = (sorted.len() * pct).div_ceil(100).saturating_sub(1);
+    sorted.get(index)
+}
+
+fn ms(duration: Option<&Duration>) -> f64 {
+    duration.map_or(0.0, |d| d.as_secs_f64() * 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_baud() {
+        // 11 KB/s is 96% of 115200 baud
+        assert_eq!(next_baud(115_200, 11_000.0, 0), Some(230_400));
+        assert_eq!(next_baud(115_200, 11_000.0, 3), None);
+        assert_eq!(next_baud(115_200, 2_000.0, 0), None);
+        assert_eq!(next_baud(2_000_000, 190_000.0, 0), None);
+
+        let samples: Vec<Duration> = (1..=20).map(Duration::from_millis).collect();
+        assert_eq!(percentile(&samples, 95), Some(&Duration::from_millis(19)));
+        assert_eq!(average(&samples), Some(Duration::from_micros(10_500)));
+    }
+}
@@ -0,0 +1,1003 @@
+//! Bridges between the mesh and other networks
+
+use super::connect_with_auth;
+use super::serve::token_matches;
+use crate::cli::{BridgeAction, WebhookFormat};
+use crate::error::CliError;
+use crate::http;
+use crate::mail::{self, Credentials, IncomingMail, OutgoingMail, Server};
+use crate::nostr::{self, Event, Keys};
+use crate::protocol::{MonitorEvent, Protocol, Response};
+use crate::settings::Settings;
+use crate::text;
+use crate::vault;
+use crate::websocket;
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, oneshot};
+
+/// Run a bridge
+pub async fn cmd_bridge(
+    port: &str,
+    baud: u32,
+    pin: Option<&str>,
+    action: BridgeAction,
+) -> Result<()> {
+    match action {
+        BridgeAction::Email {
+            smtp,
+            imap,
+            user,
+            password,
+            from,
+            pattern,
+            poll_interval,
+            max_len,
+        } => {
+            let settings = Settings::load()?;
+            let pattern = pattern
+                .map(|p| Regex::new(&p))
+                .transpose()
+                .map_err(|e| CliError::InvalidArgs(format!("Invalid --pattern: {e}")))?;
+            let bridge = EmailBridge {
+                smtp: Server::smtp(&smtp)?,
+                imap: Server::imap(&imap)?,
+                from: from.unwrap_or_else(|| user.clone()),
+                credentials: Credentials { user, password },
+                pattern,
+                routes: EmailRoutes::new(settings.bridge.email.contacts),
+                poll_interval: Duration::from_secs(poll_interval.max(1)),
+                max_len,
+            };
+            bridge.run(port, baud, pin).await
+        }
+        BridgeAction::Webhook {
+            incoming,
+            format,
+            dms,
+            listen,
+            token,
+            channel,
+        } => {
+            let format = match format {
+                WebhookFormat::Auto => detect_webhook_format(&incoming),
+                f => f,
+            };
+            let bridge = WebhookBridge {
+                incoming,
+                format,
+                dms,
+                listen,
+                token,
+                channel,
+            };
+            bridge.run(port, baud, pin).await
+        }
+        BridgeAction::Nostr {
+            relay,
+            secret_key,
+            hashtag,
+            allow,
+            channel,
+            max_len,
+        } => {
+            let bridge = NostrBridge {
+                relays: relay,
+                secret_key,
+                hashtag,
+                allow,
+                channel,
+                max_len,
+            };
+            bridge.run(port, baud, pin).await
+        }
+    }
+}
+
+/// Address <-> node routing for the email bridge.
+struct EmailRoutes {
+    /// Configured contacts: lowercased address -> node
+    contacts: BTreeMap<String, String>,
+    /// Addresses a node emailed through a `to` pattern group, so replies can
+    /// find their way back
+    replies: HashMap<String, String>,
+}
+
+impl EmailRoutes {
+    fn new(contacts: BTreeMap<String, String>) -> Self {
+        Self {
+            contacts: contacts
+                .into_iter()
+                .map(|(addr, node)| (addr.to_ascii_lowercase(), node))
+                .collect(),
+            replies: HashMap::new(),
+        }
+    }
+
+    /// Address configured for a node.
+    fn address_of(&self, node: &str) -> Option<&str> {
+        self.contacts
+            .iter()
+            .find(|(_, n)| n.eq_ignore_ascii_case(node))
+            .map(|(addr, _)| addr.as_str())
+    }
+
+    /// Node an inbound email from `address` should be delivered to.
+    fn node_for(&self, address: &str) -> Option<&str> {
+        self.contacts
+            .get(address)
+            .or_else(|| self.replies.get(address))
+            .map(String::as_str)
+    }
+
+    /// Work out where a DM should be emailed: `(address, text)`, or None if it
+    /// should not be forwarded.
+    fn forward(
+        &mut self,
+        from: &str,
+        text: &str,
+        pattern: Option<&Regex>,
+    ) -> Option<(String, String)> {
+        let Some(pattern) = pattern else {
+            let addr = self.address_of(from)?;
+            return Some((addr.to_string(), text.to_string()));
+        };
+
+        let caps = pattern.captures(text)?;
+        let body = caps.name("text").map_or(text, |m| m.as_str()).to_string();
+        match caps.name("to") {
+            Some(to) => {
+                let addr = to.as_str().to_ascii_lowercase();
+                if !self.contacts.contains_key(&addr) {
+                    self.replies.insert(addr.clone(), from.to_string());
+                }
+                Some((addr, body))
+            }
+            None => Some((self.address_of(from)?.to_string(), body)),
+        }
+    }
+}
+
+struct EmailBridge {
+    smtp: Server,
+    imap: Server,
+    from: String,
+    credentials: Credentials,
+    pattern: Option<Regex>,
+    routes: EmailRoutes,
+    poll_interval: Duration,
+    max_len: usize,
+}
+
+impl EmailBridge {
+    async fn run(mut self, port: &str, baud: u32, pin: Option<&str>) -> Result<()> {
+        if self.routes.contacts.is_empty() && self.pattern.is_none() {
+            bail!(CliError::InvalidArgs(format!(
+                "No email contacts configured. Add [bridge.email.contacts] to {} or use --pattern with a `to` group",
+                Settings::path()?.display()
+            )));
+        }
+
+        // Check the mailbox once up front so bad credentials fail fast
+        mail::check_login(&self.imap, &self.credentials)
+            .await
+            .context("Failed to connect to IMAP server")?;
+
+        let dev = connect_with_auth(port, baud, pin).await?;
+        let mut proto = dev.into_protocol();
+        proto.enter_monitor_mode().await?;
+
+        let (inbox_tx, mut inbox) = mpsc::channel::<IncomingMail>(32);
+        let poller = tokio::spawn(poll_inbox(
+            self.imap.clone(),
+            self.credentials.clone(),
+            self.poll_interval,
+            inbox_tx,
+        ));
+
+        println!(
+            "Bridging direct messages <-> email via {} / {} (Ctrl+C to stop)...\n",
+            self.smtp.host, self.imap.host
+        );
+
+        let result = loop {
+            if let Err(e) = self.step(&mut proto, &mut inbox).await {
+                break Err(e);
+            }
+        };
+        poller.abort();
+        result
+    }
+
+    async fn step(
+        &mut self,
+        proto: &mut Protocol,
+        inbox: &mut mpsc::Receiver<IncomingMail>,
+    ) -> Result<()> {
+        if let Some(MonitorEvent::Message {
+            from,
+            to: Some(_),
+            text,
+            ..
+        }) = proto.read_event().await?
+        {
+            self.forward_dm(&from, &text);
+        }
+
+        while let Ok(mail) = inbox.try_recv() {
+            self.deliver_email(proto, &mail).await?;
+        }
+        Ok(())
+    }
+
+    /// Email a direct message if it should be forwarded.
+    fn forward_dm(&mut self, from: &str, text: &str) {
+        let timestamp = chrono::Local::now().format("%H:%M:%S");
+        let Some((to, body)) = self.routes.forward(from, text, self.pattern.as_ref()) else {
+            tracing::debug!("Not forwarding DM from {from}");
+            return;
+        };
+
+        let mail = OutgoingMail {
+            from: self.from.clone(),
+            to: to.clone(),
+            subject: format!("Mesh message from {from}"),
+            body,
+        };
+        let server = self.smtp.clone();
+        let credentials = self.credentials.clone();
+        tokio::spawn(async move {
+            match mail::send_mail(&server, Some(&credentials), &mail).await {
+                Ok(()) => println!("[{timestamp}] ✓ mesh -> {to}: {}", mail.body),
+                Err(e) => eprintln!("[{timestamp}] ✗ Failed to email {to}: {e:#}"),
+            }
+        });
+    }
+
+    /// Relay an inbound email to its mapped node.
+    async fn deliver_email(&self, proto: &mut Protocol, mail: &IncomingMail) -> Result<()> {
+        let timestamp = chrono::Local::now().format("%H:%M:%S");
+        let Some(node) = self.routes.node_for(&mail.from) else {
+            println!(
+                "[{timestamp}] Ignoring email from unknown sender {}",
+                mail.from
+            );
+            return Ok(());
+        };
+
+        let text = text::truncate(&text::clean(&mail::condense(mail)), self.max_len);
+        if text.is_empty() {
+            return Ok(());
+        }
+
+        let result = proto.command(&format!("SEND {node} {text}")).await?;
+        // Sending a command leaves monitor mode on some firmware
+        proto.enter_monitor_mode().await?;
+        match result {
+            Response::Ok(_) => println!("[{timestamp}] ✓ {} -> {node}: {text}", mail.from),
+            Response::Error(e) => eprintln!("[{timestamp}] ✗ Failed to send to {node}: {e}"),
+            Response::Json(_) => eprintln!("[{timestamp}] ✗ Unexpected response to SEND"),
+        }
+        Ok(())
+    }
+}
+
+/// Poll the inbox for unseen mail until the receiver goes away.
+async fn poll_inbox(
+    server: Server,
+    credentials: Credentials,
+    interval: Duration,
+    tx: mpsc::Sender<IncomingMail>,
+) {
+    loop {
+        match mail::fetch_unseen(&server, &credentials).await {
+            Ok(mails) => {
+                for mail in mails {
+                    if tx.send(mail).await.is_err() {
+                        return;
+                    }
+                }
+            }
+            Err(e) => tracing::warn!("Inbox poll failed: {e:#}"),
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Pick a webhook payload format from the URL.
+pub(super) fn detect_webhook_format(url: &str) -> WebhookFormat {
+    let host = url::Url::parse(url)
+        .ok()
+        .and_then(|u| u.host_str().map(str::to_ascii_lowercase))
+        .unwrap_or_default();
+    if host.ends_with("discord.com") || host.ends_with("discordapp.com") {
+        WebhookFormat::Discord
+    } else if host.ends_with("slack.com") {
+        WebhookFormat::Slack
+    } else {
+        WebhookFormat::Json
+    }
+}
+
+/// Build the webhook payload for a mesh message.
+fn webhook_payload(
+    format: WebhookFormat,
+    from: &str,
+    to: Option<&str>,
+    rssi: i16,
+    text: &str,
+) -> serde_json::Value {
+    let dm = if to.is_some() { "(DM) " } else { "" };
+    match format {
+        WebhookFormat::Discord => serde_json::json!({
+            "username": from.chars().take(80).collect::<String>(),
+            "content": format!("{dm}{text}"),
+            // Never let mesh traffic ping @everyone or users
+            "allowed_mentions": { "parse": [] },
+        }),
+        WebhookFormat::Slack => {
+            let escape = |s: &str| {
+                s.replace('&', "&amp;")
+                    .replace('<', "&lt;")
+                    .replace('>', "&gt;")
+            };
+            serde_json::json!({ "text": format!("*{}*: {dm}{}", escape(from), escape(text)) })
+        }
+        WebhookFormat::Json | WebhookFormat::Auto => serde_json::json!({
+            "from": from,
+            "to": to,
+            "rssi": rssi,
+            "text": text,
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+        }),
+    }
+}
+
+/// Message submitted to the webhook bridge's listener.
+#[derive(Debug, serde::Deserialize)]
+struct OutgoingMessage {
+    text: String,
+    /// Display name prepended to the text
+    from: Option<String>,
+    /// Destination node for a direct message
+    to: Option<String>,
+    channel: Option<String>,
+}
+
+type OutgoingRequest = (OutgoingMessage, oneshot::Sender<Result<(), String>>);
+
+struct WebhookBridge {
+    incoming: String,
+    format: WebhookFormat,
+    dms: bool,
+    listen: Option<String>,
+    token: Option<String>,
+    channel: Option<String>,
+}
+
+impl WebhookBridge {
+    async fn run(self, port: &str, baud: u32, pin: Option<&str>) -> Result<()> {
+        let dev = connect_with_auth(port, baud, pin).await?;
+        let mut proto = dev.into_protocol();
+        proto.enter_monitor_mode().await?;
+
+        // Posts go through one task so they stay in order
+        let (post_tx, post_rx) = mpsc::channel::<serde_json::Value>(256);
+        let poster = tokio::spawn(post_webhooks(self.incoming.clone(), post_rx));
+
+        let (out_tx, mut outgoing) = mpsc::channel::<OutgoingRequest>(32);
+        let listener = match &self.listen {
+            Some(addr) => Some(self.start_listener(addr, out_tx).await?),
+            None => None,
+        };
+
+        println!(
+            "Mirroring {} to {} webhook (Ctrl+C to stop)...\n",
+            if self.dms {
+                "channel and DM traffic"
+            } else {
+                "channel traffic"
+            },
+            format!("{:?}", self.format).to_lowercase()
+        );
+
+        let result = async {
+            loop {
+                if let Some(MonitorEvent::Message {
+                    from,
+                    to,
+                    rssi,
+                    text,
+                    ..
+                }) = proto.read_event().await?
+                {
+                    if to.is_none() || self.dms {
+                        let payload =
+                            webhook_payload(self.format, &from, to.as_deref(), rssi, &text);
+                        if post_tx.try_send(payload).is_err() {
+                            tracing::warn!("Webhook queue full, dropping message from {from}");
+                        }
+                    }
+                }
+
+                while let Ok((msg, reply)) = outgoing.try_recv() {
+                    let result = self.transmit(&mut proto, &msg).await?;
+                    let _ = reply.send(result);
+                }
+            }
+        }
+        .await;
+
+        poster.abort();
+        if let Some(listener) = listener {
+            listener.abort();
+        }
+        result
+    }
+
+    /// Start the HTTP listener for outgoing messages.
+    async fn start_listener(
+        &self,
+        addr: &str,
+        tx: mpsc::Sender<OutgoingRequest>,
+    ) -> Result<tokio::task::JoinHandle<Result<()>>> {
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("Failed to listen on {addr}"))?;
+        let local = listener.local_addr()?;
+        if self.token.is_none() && !local.ip().is_loopback() {
+            tracing::warn!("Listener on {local} accepts messages without a token (set --token)");
+        }
+        println!("Accepting outgoing messages on http://{local}/send");
+
+        let token = self.token.clone();
+        Ok(tokio::spawn(http::serve(listener, move |req| {
+            let tx = tx.clone();
+            let token = token.clone();
+            async move { handle_send_request(req, token.as_deref(), &tx).await }
+        })))
+    }
+
+    /// Send a message from the listener into the mesh.
+    async fn transmit(
+        &self,
+        proto: &mut Protocol,
+        msg: &OutgoingMessage,
+    ) -> Result<Result<(), String>> {
+        let text = match &msg.from {
+            Some(from) => format!("[{from}] {}", msg.text),
+            None => msg.text.clone(),
+        };
+        let cmd = if let Some(dest) = &msg.to {
+            format!("SEND {dest} {text}")
+        } else if let Some(ch) = msg.channel.as_ref().or(self.channel.as_ref()) {
+            format!("CHANNEL SEND {ch} {text}")
+        } else {
+            format!("SEND {text}")
+        };
+
+        let response = proto.command(&cmd).await?;
+        // Sending a command leaves monitor mode on some firmware
+        proto.enter_monitor_mode().await?;
+
+        let timestamp = chrono::Local::now().format("%H:%M:%S");
+        Ok(match response {
+            Response::Ok(_) => {
+                println!("[{timestamp}] ✓ webhook -> mesh: {text}");
+                Ok(())
+            }
+            Response::Error(e) => {
+                eprintln!("[{timestamp}] ✗ Failed to send: {e}");
+                Err(e)
+            }
+            Response::Json(_) => Err("Unexpected response to SEND".to_string()),
+        })
+    }
+}
+
+/// Handle `POST /send` with a JSON (`{"text": ...}`) or plain-text body.
+/// The token is only accepted as a bearer header, never in the URL, where
+/// it would end up in proxy and shell history.
+async fn handle_send_request(
+    req: http::Request,
+    token: Option<&str>,
+    tx: &mpsc::Sender<OutgoingRequest>,
+) -> http::Response {
+    let error = |status, msg: &str| {
+        http::Response::json(status, &serde_json::json!({ "ok": false, "error": msg }))
+    };
+
+    if req.path != "/send" {
+        return error(404, "not found");
+    }
+    if req.method != "POST" {
+        return error(405, "use POST").with_header("Allow", "POST");
+    }
+    if let Some(token) = token {
+        let bearer = req
+            .header("authorization")
+            .and_then(|v| v.strip_prefix("Bearer "));
+        if !bearer.is_some_and(|given| token_matches(token, given)) {
+            return error(401, "invalid token");
+        }
+    }
+
+    let is_json = req
+        .header("content-type")
+        .is_some_and(|ct| ct.starts_with("application/json"));
+    let msg = if is_json {
+        match serde_json::from_slice::<OutgoingMessage>(&req.body) {
+            Ok(msg) => msg,
+            Err(e) => return error(400, &format!("invalid JSON: {e}")),
+        }
+    } else {
+        OutgoingMessage {
+            text: String::from_utf8_lossy(&req.body).trim().to_string(),
+            from: None,
+            to: None,
+            channel: None,
+        }
+    };
+    if msg.text.trim().is_empty() {
+        return error(400, "empty message");
+    }
+
+    let (reply_tx, reply_rx) = oneshot::channel();
+    if tx.send((msg, reply_tx)).await.is_err() {
+        return error(503, "bridge is shutting down");
+    }
+    match reply_rx.await {
+        Ok(Ok(())) => http::Response::json(200, &serde_json::json!({ "ok": true })),
+        Ok(Err(e)) => error(502, &e),
+        Err(_) => error(503, "bridge is shutting down"),
+    }
+}
+
+/// Post queued payloads to the webhook, honouring rate limits.
+async fn post_webhooks(url: String, mut rx: mpsc::Receiver<serde_json::Value>) {
+    let client = reqwest::Client::new();
+    while let Some(payload) = rx.recv().await {
+        for _attempt in 0..3 {
+            match client.post(&url).json(&payload).send().await {
+                Ok(resp) if resp.status() == reqwest::StatusCode::TOO_MANY_REQUESTS => {
+                    let wait = resp
+                        .headers()
+                        .get("retry-after")
+                        .and_then(|v| v.to_str().ok())
+                        .and_then(|v| v.parse::<f64>().ok())
+                        .unwrap_or(1.0);
+                    tokio::time::sleep(Duration::from_secs_f64(wait.clamp(0.1, 60.0))).await;
+                }
+                Ok(resp) if !resp.status().is_success() => {
+                    tracing::warn!("Webhook returned {}", resp.status());
+                    break;
+                }
+                Ok(_) => break,
+                Err(e) => {
+                    tracing::warn!("Webhook post failed: {e}");
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Split `@node text` into a direct-message target and text.
+fn nostr_route(text: &str) -> (Option<&str>, &str) {
+    if let Some(rest) = text.strip_prefix('@') {
+        if let Some((node, body)) = rest.split_once(char::is_whitespace) {
+            if !node.is_empty() && !body.trim().is_empty() {
+                return (Some(node), body.trim());
+            }
+        }
+    }
+    (None, text)
+}
+
+/// Drop NIP-27 `nostr:npub1...`/`nostr:nprofile1...` references.
+fn strip_nostr_mentions(text: &str) -> String {
+    text.split_whitespace()
+        .filter(|word| {
+            let word = word.trim_start_matches('@');
+            !(word.starts_with("nostr:npub1") || word.starts_with("nostr:nprofile1"))
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Path of the seed the bridge's Nostr key is derived from; created on first use.
+fn nostr_seed() -> Result<Vec<u8>> {
+    let path = Settings::path()?.with_file_name("nostr-seed");
+    vault::ensure_unlocked(&path)?;
+    if let Ok(seed) = std::fs::read_to_string(&path) {
+        return hex::decode(seed.trim())
+            .with_context(|| format!("Invalid Nostr seed in {}", path.display()));
+    }
+
+    let mut seed = [0u8; 32];
+    getrandom::getrandom(&mut seed)?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options
+        .open(&path)
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+    std::io::Write::write_all(&mut file, hex::encode(seed).as_bytes())?;
+    Ok(seed.to_vec())
+}
+
+struct NostrBridge {
+    relays: Vec<String>,
+    secret_key: Option<String>,
+    hashtag: String,
+    allow: Vec<String>,
+    channel: Option<String>,
+    max_len: usize,
+}
+
+impl NostrBridge {
+    async fn run(self, port: &str, baud: u32, pin: Option<&str>) -> Result<()> {
+        let allow = self
+            .allow
+            .iter()
+            .map(|key| nostr::parse_public_key(key))
+            .collect::<Result<HashSet<_>>>()
+            .map_err(|e| CliError::InvalidArgs(format!("Invalid --allow key: {e}")))?;
+        let explicit_keys = self
+            .secret_key
+            .as_deref()
+            .map(Keys::parse)
+            .transpose()
+            .map_err(|e| CliError::InvalidArgs(format!("Invalid --secret-key: {e:#}")))?;
+
+        let dev = connect_with_auth(port, baud, pin).await?;
+        let mut proto = dev.into_protocol();
+        let keys = match explicit_keys {
+            Some(keys) => keys,
+            None => {
+                let info = proto.get_info().await?;
+                Keys::derive(&nostr_seed()?, &info.public_key)?
+            }
+        };
+        proto.enter_monitor_mode().await?;
+
+        // Relays send mentions of and DMs to the bridge from now on
+        let pubkey = keys.public_hex();
+        let since = chrono::Utc::now().timestamp();
+        let subscription = serde_json::json!([
+            "REQ",
+            "meshgrid",
+            { "kinds": [nostr::KIND_TEXT_NOTE, nostr::KIND_ENCRYPTED_DM], "#p": [pubkey], "since": since }
+        ])
+        .to_string();
+
+        let (publish_tx, _) = broadcast::channel::<String>(256);
+        let (inbound_tx, mut inbound) = mpsc::channel::<Event>(64);
+        let tasks: Vec<_> = self
+            .relays
+            .iter()
+            .map(|url| {
+                tokio::spawn(run_relay(
+                    url.clone(),
+                    subscription.clone(),
+                    publish_tx.subscribe(),
+                    inbound_tx.clone(),
+                ))
+            })
+            .collect();
+
+        println!("Nostr identity: {}", keys.npub());
+        println!(
+            "Bridging channel messages <-> {} relay(s) (Ctrl+C to stop)...\n",
+            self.relays.len()
+        );
+
+        let mut seen = HashSet::new();
+        let result = async {
+            loop {
+                if let Some(MonitorEvent::Message {
+                    from,
+                    to: None,
+                    text,
+                    ..
+                }) = proto.read_event().await?
+                {
+                    self.publish(&keys, &publish_tx, &from, &text);
+                }
+
+                while let Ok(event) = inbound.try_recv() {
+                    // The same event usually arrives from several relays
+                    if seen.len() > 4096 {
+                        seen.clear();
+                    }
+                    if seen.insert(event.id.clone()) {
+                        self.deliver(&mut proto, &keys, &allow, &event).await?;
+                    }
+                }
+            }
+        }
+        .await;
+
+        for task in tasks {
+            task.abort();
+        }
+        result
+    }
+
+    /// Publish a channel message as a text note.
+    fn publish(&self, keys: &Keys, tx: &broadcast::Sender<String>, from: &str, text: &str) {
+        let timestamp = chrono::Local::now().format("%H:%M:%S");
+        let tags = vec![vec!["t".to_string(), self.hashtag.clone()]];
+        match Event::sign(
+            keys,
+            nostr::KIND_TEXT_NOTE,
+            tags,
+            &format!("{from}: {text}"),
+        ) {
+            Ok(event) => {
+                let _ = tx.send(serde_json::json!(["EVENT", event]).to_string());
+                println!("[{timestamp}] ✓ mesh -> nostr: {from}: {text}");
+            }
+            Err(e) => eprintln!("[{timestamp}] ✗ Failed to sign event: {e:#}"),
+        }
+    }
+
+    /// Relay a mention or DM into the mesh.
+    async fn deliver(
+        &self,
+        proto: &mut Protocol,
+        keys: &Keys,
+        allow: &HashSet<String>,
+        event: &Event,
+    ) -> Result<()> {
+        let timestamp = chrono::Local::now().format("%H:%M:%S");
+        let pubkey = keys.public_hex();
+        if event.pubkey == pubkey || !event.mentions(&pubkey) {
+            return Ok(());
+        }
+        if !event.verify() {
+            tracing::debug!("Dropping event {} with a bad signature", event.id);
+            return Ok(());
+        }
+        let Some(author) = event.author() else {
+            return Ok(());
+        };
+        let npub = nostr::bech32_encode("npub", &author);
+        if !allow.is_empty() && !allow.contains(&event.pubkey) {
+            println!("[{timestamp}] Ignoring nostr event from {npub}");
+            return Ok(());
+        }
+
+        let (text, direct) = match event.kind {
+            nostr::KIND_ENCRYPTED_DM => match keys.decrypt_dm(&author, &event.content) {
+                Ok(text) => (text, true),
+                Err(e) => {
+                    eprintln!("[{timestamp}] ✗ Failed to decrypt DM from {npub}: {e:#}");
+                    return Ok(());
+                }
+            },
+            nostr::KIND_TEXT_NOTE => (strip_nostr_mentions(&event.content), false),
+            _ => return Ok(()),
+        };
+
+        // DMs may address a node directly with `@node text`
+        let (node, body) = if direct {
+            nostr_route(&text)
+        } else {
+            (None, text.as_str())
+        };
+        let sender: String = npub.chars().take(12).collect();
+        let text = text::truncate(&text::clean(&format!("{sender}: {body}")), self.max_len);
+        let cmd = if let Some(node) = node {
+            format!("SEND {node} {text}")
+        } else if let Some(ch) = &self.channel {
+            format!("CHANNEL SEND {ch} {text}")
+        } else {
+            format!("SEND {text}")
+        };
+
+        let result = proto.command(&cmd).await?;
+        // Sending a command leaves monitor mode on some firmware
+        proto.enter_monitor_mode().await?;
+        match result {
+            Response::Ok(_) => println!("[{timestamp}] ✓ nostr -> mesh: {text}"),
+            Response::Error(e) => eprintln!("[{timestamp}] ✗ Failed to send: {e}"),
+            Response::Json(_) => eprintln!("[{timestamp}] ✗ Unexpected response to SEND"),
+        }
+        Ok(())
+    }
+}
+
+/// Keep one relay connected: publish queued events and forward matching ones.
+async fn run_relay(
+    url: String,
+    subscription: String,
+    mut publish: broadcast::Receiver<String>,
+    inbound: mpsc::Sender<Event>,
+) {
+    let mut backoff = Duration::from_secs(2);
+    loop {
+        match websocket::connect(&url).await {
+            Ok((reader, mut writer)) => {
+                tracing::info!("Connected to {url}");
+                backoff = Duration::from_secs(2);
+                match relay_session(reader, &mut writer, &subscription, &mut publish, &inbound)
+                    .await
+                {
+                    Ok(true) => return,
+                    Ok(false) => tracing::warn!("Relay {url} closed the connection"),
+                    Err(e) => tracing::warn!("Relay {url} failed: {e:#}"),
+                }
+            }
+            Err(e) => tracing::warn!("Failed to connect to {url}: {e:#}"),
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(Duration::from_secs(120));
+    }
+}
+
+/// Run one relay connection; returns Ok(true) when the bridge is shutting down.
+async fn relay_session(
+    mut reader: websocket::Reader,
+    writer: &mut websocket::Writer,
+    subscription: &str,
+    publish: &mut broadcast::Receiver<String>,
+    inbound: &mpsc::Sender<Event>,
+) -> Result<bool> {
+    writer.send_text(subscription).await?;
+
+    // Read on a separate task so a half-read frame is never dropped by select!
+    let (msg_tx, mut messages) = mpsc::channel(64);
+    let read_task = tokio::spawn(async move {
+        while let Ok(msg) = reader.recv().await {
+            if msg_tx.send(msg).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let result = loop {
+        tokio::select! {
+            msg = messages.recv() => match msg {
+                Some(websocket::Message::Text(text)) => handle_relay_message(&text, inbound).await,
+                Some(websocket::Message::Ping(payload)) => writer.pong(&payload).await?,
+                Some(websocket::Message::Binary(_) | websocket::Message::Pong) => {}
+                Some(websocket::Message::Close) | None => break Ok(false),
+            },
+            event = publish.recv() => match event {
+                Ok(event) => writer.send_text(&event).await?,
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    tracing::warn!("Relay too slow, dropped {n} events");
+                }
+                Err(broadcast::error::RecvError::Closed) => {
+                    let _ = writer.close().await;
+                    break Ok(true);
+                }
+            },
+        }
+    };
+    read_task.abort();
+    result
+}
+
+/// Handle a relay-to-client message (NIP-01).
+async fn handle_relay_message(text: &str, inbound: &mpsc::Sender<Event>) {
+    let Ok(serde_json::Value::Array(msg)) = serde_json::from_str(text) else {
+        return;
+    };
+    match msg.first().and_then(|v| v.as_str()) {
+        Some("EVENT") => {
+            if let Some(Ok(event)) = msg.get(2).cloned().map(serde_json::from_value::<Event>) {
+                let _ = inbound.send(event).await;
+            }
+        }
+        Some("OK") if msg.get(2) == Some(&serde_json::Value::Bool(false)) => {
+            let reason = msg.get(3).and_then(|v| v.as_str()).unwrap_or_default();
+            tracing::warn!("Relay rejected event: {reason}");
+        }
+        Some("NOTICE" | "CLOSED") => {
+            let note = msg.last().and_then(|v| v.as_str()).unwrap_or_default();
+            tracing::warn!("Relay notice: {note}");
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_email_routes() {
+        let mut routes = EmailRoutes::new(BTreeMap::from([(
+            "Alice@Example.com".to_string(),
+            "alice-node".to_string(),
+        )]));
+
+        // Without a pattern only mapped nodes are forwarded
+        assert_eq!(
+            routes.forward("ALICE-NODE", "hi", None),
+            Some(("alice@example.com".into(), "hi".into()))
+        );
+        assert_eq!(routes.forward("bob", "hi", None), None);
+
+        // A `to` group routes anywhere and remembers the sender for replies
+        let pattern = Regex::new(r"^mail (?P<to>\S+@\S+) (?P<text>.+)$").unwrap();
+        assert_eq!(
+            routes.forward("bob", "mail carol@example.net see you", Some(&pattern)),
+            Some(("carol@example.net".into(), "see you".into()))
+        );
+        assert_eq!(routes.forward("bob", "no match", Some(&pattern)), None);
+        assert_eq!(routes.node_for("carol@example.net"), Some("bob"));
+        assert_eq!(routes.node_for("alice@example.com"), Some("alice-node"));
+    }
+
+    #[test]
+    fn test_webhook_payloads() {
+        assert_eq!(
+            detect_webhook_format("https://discord.com/api/webhooks/1/abc"),
+            WebhookFormat::Discord
+        );
+        assert_eq!(
+            detect_webhook_format("https://hooks.slack.com/services/T/B/x"),
+            WebhookFormat::Slack
+        );
+
+        let discord = webhook_payload(WebhookFormat::Discord, "alice", None, -70, "@everyone hi");
+        assert_eq!(discord["content"], "@everyone hi");
+        assert_eq!(discord["allowed_mentions"]["parse"], serde_json::json!([]));
+
+        let slack = webhook_payload(WebhookFormat::Slack, "bob", Some("me"), -70, "<b> & c");
+        assert_eq!(slack["text"], "*bob*: (DM) &lt;b&gt; &amp; c");
+    }
+
+    #[tokio::test]
+    async fn test_send_request_token() {
+        let (tx, mut rx) = mpsc::channel(4);
+        let request = |auth: Option<&str>| http::Request {
+            method: "POST".into(),
+            path: "/send".into(),
+            headers: auth
+                .map(|a| ("authorization".to_string(), a.to_string()))
+                .into_iter()
+                .collect(),
+            body: b"hello".to_vec(),
+        };
+
+        let denied = [
+            request(None),
+            request(Some("secret")),
+            request(Some("Bearer wrong")),
+            request(Some("Bearer secre")),
+        ];
+        for req in denied {
+            let response = handle_send_request(req, Some("secret"), &tx).await;
+            assert_eq!(response.status, 401);
+        }
+        assert!(rx.try_recv().is_err());
+
+        let req = request(Some("Bearer secret"));
+        let send = tokio::spawn(async move { handle_send_request(req, Some("secret"), &tx).await });
+        let (msg, reply) = rx.recv().await.unwrap();
+        assert_eq!(msg.text, "hello");
+        let _ = reply.send(Ok(()));
+        assert_eq!(send.await.unwrap().status, 200);
+    }
+
+    #[test]
+    fn test_nostr_inbound_text() {
+        assert_eq!(nostr_route("@alice see you"), (Some("alice"), "see you"));
+        assert_eq!(nostr_route("@alice"), (None, "@alice"));
+        assert_eq!(nostr_route("hello"), (None, "hello"));
+        assert_eq!(
+            strip_nostr_mentions("nostr:npub1abc hi @nostr:nprofile1xyz there"),
+            "hi there"
+        );
+    }
+}
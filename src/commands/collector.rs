@@ -0,0 +1,804 @@
+//! Unattended collector: `collector --config collector.toml`
+//!
+//! One long-running process for a gateway under systemd, in place of
+//! `monitor`, `telemetry --watch` and a metrics script side by side. It keeps
+//! the device connection up (reconnecting after resets and unplugs), appends
+//! telemetry samples and mesh events to CSV files, serves the latest values
+//! as Prometheus metrics and runs alert commands when a value crosses a
+//! threshold.
+
+use super::connect_with_auth;
+use super::util::require_port;
+use crate::channels::{self, Level, Prefs};
+use crate::error::CliError;
+use crate::history::Recorder;
+use crate::hooks::{self, HookEvent, HookRunner};
+use crate::nodes;
+use crate::output::csv_field;
+use crate::protocol::{MonitorEvent, Protocol, Telemetry};
+use crate::settings::Settings;
+use crate::{http, timefmt};
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Longest wait between reconnection attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// A connection that lasted this long resets the backoff.
+const STABLE: Duration = Duration::from_secs(60);
+
+const TELEMETRY_COLUMNS: [&str; 14] = [
+    "timestamp",
+    "battery_percent",
+    "battery_volts",
+    "charging",
+    "usb_power",
+    "uptime_secs",
+    "free_heap",
+    "cpu_temp_c",
+    "temperature_c",
+    "humidity_pct",
+    "pressure_hpa",
+    "lat",
+    "lon",
+    "satellites",
+];
+
+const EVENT_COLUMNS: [&str; 7] = [
+    "timestamp",
+    "event",
+    "from",
+    "to",
+    "channel",
+    "rssi",
+    "text",
+];
+
+/// Metrics as (name, Prometheus type, help). Alert rules name them without
+/// the `meshgrid_` prefix.
+const METRICS: [(&str, &str, &str); 15] = [
+    ("up", "gauge", "Whether the device is connected"),
+    ("battery_percent", "gauge", "Battery charge"),
+    ("battery_volts", "gauge", "Battery voltage"),
+    ("usb_power", "gauge", "Whether the device runs on USB power"),
+    ("uptime_seconds", "gauge", "Device uptime"),
+    ("free_heap_bytes", "gauge", "Free heap memory"),
+    ("cpu_temperature_celsius", "gauge", "CPU temperature"),
+    (
+        "temperature_celsius",
+        "gauge",
+        "Environment sensor temperature",
+    ),
+    (
+        "humidity_percent",
+        "gauge",
+        "Environment sensor relative humidity",
+    ),
+    ("pressure_hpa", "gauge", "Environment sensor air pressure"),
+    (
+        "last_sample_timestamp_seconds",
+        "gauge",
+        "Unix time of the last telemetry sample",
+    ),
+    ("messages_received_total", "counter", "Messages received"),
+    (
+        "adverts_received_total",
+        "counter",
+        "Advertisements received",
+    ),
+    (
+        "device_resets_total",
+        "counter",
+        "Device resets seen (uptime going back)",
+    ),
+    ("reconnects_total", "counter", "Reconnections to the device"),
+];
+
+/// collector.toml
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    /// Device port, unless given with -p
+    port: Option<String>,
+    /// Seconds between telemetry samples
+    #[serde(default = "default_interval")]
+    interval: u64,
+    /// Directory of the CSV files, relative to the config file (default:
+    /// the config file's directory)
+    data_dir: Option<PathBuf>,
+    /// Address to serve Prometheus metrics on (`127.0.0.1:9464`)
+    metrics: Option<String>,
+    #[serde(default, rename = "alert")]
+    alerts: Vec<AlertRule>,
+}
+
+fn default_interval() -> u64 {
+    60
+}
+
+/// `[[alert]]`: fires when `metric` is below or above a threshold for
+/// `for_secs`, and resolves when it no longer is.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AlertRule {
+    name: String,
+    metric: String,
+    below: Option<f64>,
+    above: Option<f64>,
+    #[serde(default)]
+    for_secs: u64,
+    /// Shell command run when the alert fires and when it resolves
+    command: Option<String>,
+}
+
+fn load_config(path: &str) -> Result<Config> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("Failed to read {path}"))?;
+    let config: Config =
+        toml::from_str(&text).with_context(|| format!("Invalid collector config {path}"))?;
+    if config.interval == 0 {
+        bail!(CliError::InvalidArgs(
+            "interval must be at least 1 second".into()
+        ));
+    }
+    for rule in &config.alerts {
+        let invalid = |msg: String| CliError::InvalidArgs(format!("Alert '{}': {msg}", rule.name));
+        if !METRICS.iter().any(|(name, ..)| *name == rule.metric) {
+            bail!(invalid(format!("unknown metric '{}'", rule.metric)));
+        }
+        if rule.below.is_none() == rule.above.is_none() {
+            bail!(invalid("set one of below or above".into()));
+        }
+    }
+    Ok(config)
+}
+
+/// Latest state of the device, as served on `/metrics`.
+#[derive(Debug, Default)]
+struct Metrics {
+    device: Option<String>,
+    up: bool,
+    telemetry: Telemetry,
+    last_sample: Option<i64>,
+    messages: u64,
+    adverts: u64,
+    resets: u64,
+    reconnects: u64,
+}
+
+impl Metrics {
+    /// Value of metric `name` (without prefix), if known.
+    fn value(&self, name: &str) -> Option<f64> {
+        let device = self.telemetry.device.as_ref();
+        let env = self.telemetry.environment.as_ref();
+        let flag = |b: bool| if b { 1.0 } else { 0.0 };
+        #[allow(clippy::cast_precision_loss)]
+        Some(match name {
+            "up" => flag(self.up),
+            "battery_percent" => f64::from(device?.battery_percent),
+            "battery_volts" => f64::from(device?.voltage_mv) / 1000.0,
+            "usb_power" => flag(device?.usb_power),
+            "uptime_seconds" => f64::from(device?.uptime_secs),
+            "free_heap_bytes" => f64::from(device?.free_heap),
+            "cpu_temperature_celsius" => tenths(device?.cpu_temp_celsius()),
+            "temperature_celsius" => tenths(env?.temperature_celsius()),
+            "humidity_percent" => tenths(env?.humidity_percent()),
+            "pressure_hpa" => tenths(env?.pressure_hpa()),
+            "last_sample_timestamp_seconds" => self.last_sample? as f64,
+            "messages_received_total" => self.messages as f64,
+            "adverts_received_total" => self.adverts as f64,
+            "device_resets_total" => self.resets as f64,
+            "reconnects_total" => self.reconnects as f64,
+            _ => return None,
+        })
+    }
+
+    /// Prometheus text exposition of the known metrics.
+    fn render(&self) -> String {
+        let labels = self.device.as_ref().map_or_else(String::new, |device| {
+            let escaped = device.replace('\\', "\\\\").replace('"', "\\\"");
+            format!("{{device=\"{escaped}\"}}")
+        });
+        let mut out = String::new();
+        for (name, kind, help) in METRICS {
+            if let Some(value) = self.value(name) {
+                out.push_str(&format!(
+                    "# HELP meshgrid_{name} {help}\n# TYPE meshgrid_{name} {kind}\n\
+                     meshgrid_{name}{labels} {value}\n"
+                ));
+            }
+        }
+        out
+    }
+}
+
+/// A sensor reading without f32 noise (`21.3`, not `21.299999237060547`).
+fn tenths(value: f32) -> f64 {
+    (f64::from(value) * 10.0).round() / 10.0
+}
+
+/// An alert rule and whether it is firing.
+struct Alert {
+    rule: AlertRule,
+    /// When the condition started to hold
+    since: Option<Instant>,
+    firing: bool,
+}
+
+impl Alert {
+    /// Update with the metric's current value: `Some(true)` when the alert
+    /// starts firing, `Some(false)` when it resolves.
+    fn update(&mut self, value: Option<f64>, now: Instant) -> Option<bool> {
+        let holds = value.is_some_and(|v| {
+            self.rule.below.is_some_and(|t| v < t) || self.rule.above.is_some_and(|t| v > t)
+        });
+        if !holds {
+            self.since = None;
+            if self.firing {
+                self.firing = false;
+                return Some(false);
+            }
+            return None;
+        }
+        let since = *self.since.get_or_insert(now);
+        if !self.firing && now.duration_since(since) >= Duration::from_secs(self.rule.for_secs) {
+            self.firing = true;
+            return Some(true);
+        }
+        None
+    }
+}
+
+/// A CSV file rows are appended to, with a header when it's new.
+pub(super) struct CsvLog {
+    file: File,
+    path: PathBuf,
+    header: String,
+    rotation: Option<Rotation>,
+}
+
+/// When a [`CsvLog`] is moved aside for a new file, and how many old ones
+/// are kept.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Rotation {
+    pub max_bytes: u64,
+    pub keep: usize,
+}
+
+impl CsvLog {
+    pub(super) fn open(path: PathBuf, columns: &[&str]) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let header = columns.join(",");
+        let file = open_with_header(&path, &header)?;
+        Ok(Self {
+            file,
+            path,
+            header,
+            rotation: None,
+        })
+    }
+
+    /// Like [`CsvLog::open`], but the file is rotated (`stats.csv` to
+    /// `stats.1.csv`, `stats.2.csv`...) once it reaches `rotation.max_bytes`,
+    /// and right away if it has other columns.
+    pub(super) fn rotating(path: PathBuf, columns: &[&str], rotation: Rotation) -> Result<Self> {
+        let header = columns.join(",");
+        let first_line = std::fs::read(&path).ok().and_then(|data| {
+            let line = data.split(|&b| b == b'\n').next()?;
+            Some(String::from_utf8_lossy(line).trim_end().to_string())
+        });
+        if first_line.is_some_and(|line| !line.is_empty() && line != header) {
+            rotate(&path, rotation.keep)?;
+        }
+        let mut log = Self::open(path, columns)?;
+        log.rotation = Some(rotation);
+        Ok(log)
+    }
+
+    pub(super) fn append(&mut self, fields: &[String]) {
+        if let Err(e) = self.rotate_if_full() {
+            tracing::warn!("Failed to rotate {}: {e:#}", self.path.display());
+        }
+        let line: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+        if let Err(e) = writeln!(self.file, "{}", line.join(",")) {
+            tracing::warn!("Failed to write {}: {e}", self.path.display());
+        }
+    }
+
+    fn rotate_if_full(&mut self) -> Result<()> {
+        let Some(rotation) = self.rotation else {
+            return Ok(());
+        };
+        if self.file.metadata()?.len() < rotation.max_bytes {
+            return Ok(());
+        }
+        rotate(&self.path, rotation.keep)?;
+        self.file = open_with_header(&self.path, &self.header)?;
+        Ok(())
+    }
+}
+
+fn open_with_header(path: &Path, header: &str) -> Result<File> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    if file.metadata()?.len() == 0 {
+        writeln!(file, "{header}")?;
+    }
+    Ok(file)
+}
+
+/// Move `path` to its first rotated name, shifting older ones along and
+/// dropping those beyond `keep`.
+fn rotate(path: &Path, keep: usize) -> Result<()> {
+    let rotated = |n: usize| {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let name = match path.extension() {
+            Some(ext) => format!("{stem}.{n}.{}", ext.to_string_lossy()),
+            None => format!("{stem}.{n}"),
+        };
+        path.with_file_name(name)
+    };
+    if keep == 0 {
+        return Ok(std::fs::remove_file(path)?);
+    }
+    let _ = std::fs::remove_file(rotated(keep));
+    for n in (1..keep).rev() {
+        let _ = std::fs::rename(rotated(n), rotated(n + 1));
+    }
+    std::fs::rename(path, rotated(1))
+        .with_context(|| format!("Failed to rotate {}", path.display()))
+}
+
+struct Collector {
+    interval: Duration,
+    metrics: Arc<Mutex<Metrics>>,
+    alerts: Vec<Alert>,
+    last_alert_check: Option<Instant>,
+    telemetry_csv: CsvLog,
+    events_csv: CsvLog,
+    history: Recorder,
+    hooks: HookRunner,
+    prefs: Prefs,
+    /// Whether the current outage has been reported
+    outage_reported: bool,
+    seen_nodes: HashSet<u8>,
+    /// Device uptime at the last sample, to notice resets
+    last_uptime: Option<u32>,
+}
+
+impl Collector {
+    fn metrics(&self) -> std::sync::MutexGuard<'_, Metrics> {
+        self.metrics.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Connect, then sample and listen until the connection fails.
+    async fn session(&mut self, port: &str, baud: u32, pin: Option<&str>) -> Result<()> {
+        let mut proto = connect_with_auth(port, baud, pin).await?.into_protocol();
+        let info = proto.get_info().await?;
+        let device_key = hex::encode(info.public_key);
+        let name = info
+            .name
+            .clone()
+            .unwrap_or_else(|| device_key[..8].to_string());
+        {
+            let mut metrics = self.metrics();
+            metrics.device = Some(name.clone());
+            metrics.up = true;
+        }
+        self.outage_reported = false;
+        println!("[{}] ✓ Connected to {name} on {port}", timefmt::clock());
+        self.log_event("connected", "", "", "", "", &name);
+
+        self.sample(&mut proto).await?;
+        proto.enter_monitor_mode().await?;
+        let mut last_sample = Instant::now();
+        loop {
+            if let Some(event) = proto.read_event().await? {
+                self.on_event(event, &device_key);
+            }
+            if last_sample.elapsed() >= self.interval {
+                last_sample = Instant::now();
+                self.sample(&mut proto).await?;
+                // Querying telemetry leaves monitor mode on some firmware
+                proto.enter_monitor_mode().await?;
+            }
+            self.check_alerts();
+        }
+    }
+
+    async fn sample(&mut self, proto: &mut Protocol) -> Result<()> {
+        let telemetry = proto.get_telemetry().await?;
+        self.history.record_telemetry(&telemetry);
+        if let Some(device) = &telemetry.device {
+            // Uptime going back: the device restarted since the last sample
+            if self
+                .last_uptime
+                .is_some_and(|last| device.uptime_secs < last)
+            {
+                self.metrics().resets += 1;
+                println!(
+                    "[{}] ⚠ Device reset (uptime {})",
+                    timefmt::clock(),
+                    timefmt::duration(device.uptime_secs.into())
+                );
+                self.log_event("reset", "", "", "", "", "");
+            }
+            self.last_uptime = Some(device.uptime_secs);
+        }
+        self.telemetry_csv.append(&telemetry_row(&telemetry));
+        let mut metrics = self.metrics();
+        metrics.telemetry = telemetry;
+        metrics.last_sample = Some(Utc::now().timestamp());
+        Ok(())
+    }
+
+    fn on_event(&mut self, event: MonitorEvent, device_key: &str) {
+        self.history.record(&event);
+        match event {
+            MonitorEvent::Message {
+                from,
+                to,
+                channel,
+                rssi,
+                text,
+            } => {
+                self.metrics().messages += 1;
+                let channel =
+                    channels::of_message(to.as_deref(), channel.as_deref()).map(String::from);
+                self.log_event(
+                    "message",
+                    &from,
+                    to.as_deref().unwrap_or_default(),
+                    channel.as_deref().unwrap_or_default(),
+                    &rssi.to_string(),
+                    &text,
+                );
+                let level = self.prefs.get(channel.as_deref());
+                if level != Level::Mute {
+                    self.hooks.fire(&HookEvent::MessageReceived {
+                        from,
+                        to,
+                        channel,
+                        priority: level == Level::Priority,
+                        text,
+                        rssi,
+                    });
+                }
+            }
+            MonitorEvent::Advertisement {
+                node_hash,
+                rssi,
+                name,
+            } => {
+                self.metrics().adverts += 1;
+                let from = name.clone().unwrap_or_else(|| format!("0x{node_hash:02x}"));
+                self.log_event("advert", &from, "", "", &rssi.to_string(), "");
+                if let Err(e) = nodes::heard(device_key, node_hash, name.as_deref(), rssi) {
+                    tracing::debug!("Failed to update node database: {e:#}");
+                }
+                if self.seen_nodes.insert(node_hash) {
+                    self.hooks.fire(&HookEvent::NodeAppeared {
+                        node_hash,
+                        name,
+                        rssi,
+                    });
+                }
+            }
+            MonitorEvent::Ack { from } => self.log_event("ack", &from, "", "", "", ""),
+            MonitorEvent::Waypoint {
+                from, rssi, name, ..
+            } => self.log_event("waypoint", &from, "", "", &rssi.to_string(), &name),
+            MonitorEvent::Error { message } => self.log_event("error", "", "", "", "", &message),
+        }
+    }
+
+    fn log_event(
+        &mut self,
+        event: &str,
+        from: &str,
+        to: &str,
+        channel: &str,
+        rssi: &str,
+        text: &str,
+    ) {
+        let row = [
+            now(),
+            event.into(),
+            from.into(),
+            to.into(),
+            channel.into(),
+            rssi.into(),
+            text.into(),
+        ];
+        self.events_csv.append(&row);
+    }
+
+    /// Note a failed connection; each outage is reported once.
+    fn disconnected(&mut self, error: &anyhow::Error) {
+        let was_up = std::mem::replace(&mut self.metrics().up, false);
+        if was_up {
+            println!("[{}] ✗ Disconnected: {error:#}", timefmt::clock());
+            self.log_event("disconnected", "", "", "", "", &format!("{error:#}"));
+        } else if !self.outage_reported {
+            println!(
+                "[{}] ✗ Failed to connect: {error:#}; retrying",
+                timefmt::clock()
+            );
+        } else {
+            tracing::debug!("Reconnect failed: {error:#}");
+        }
+        self.outage_reported = true;
+    }
+
+    /// Evaluate the alert rules, at most once a second.
+    fn check_alerts(&mut self) {
+        let now = Instant::now();
+        if self
+            .last_alert_check
+            .is_some_and(|last| now.duration_since(last) < Duration::from_secs(1))
+        {
+            return;
+        }
+        self.last_alert_check = Some(now);
+        let metrics = self.metrics.lock().unwrap_or_else(|e| e.into_inner());
+        let device = metrics.device.clone().unwrap_or_default();
+        let mut changes = Vec::new();
+        for alert in &mut self.alerts {
+            let value = metrics.value(&alert.rule.metric);
+            if let Some(firing) = alert.update(value, now) {
+                changes.push((alert.rule.clone(), firing, value.unwrap_or_default()));
+            }
+        }
+        drop(metrics);
+
+        for (rule, firing, value) in changes {
+            let state = if firing { "firing" } else { "resolved" };
+            if firing {
+                println!(
+                    "[{}] ⚠ Alert {}: {} = {value}",
+                    timefmt::clock(),
+                    rule.name,
+                    rule.metric
+                );
+            } else {
+                println!(
+                    "[{}] ✓ Resolved {}: {} = {value}",
+                    timefmt::clock(),
+                    rule.name,
+                    rule.metric
+                );
+            }
+            self.log_event("alert", "", "", "", "", &format!("{} {state}", rule.name));
+            if let Some(command) = &rule.command {
+                hooks::spawn(
+                    &format!("alert {}", rule.name),
+                    command,
+                    vec![
+                        ("MESHGRID_EVENT", "alert".into()),
+                        ("MESHGRID_TIMESTAMP", chrono::Local::now().to_rfc3339()),
+                        ("MESHGRID_ALERT", rule.name.clone()),
+                        ("MESHGRID_ALERT_STATE", state.into()),
+                        ("MESHGRID_METRIC", rule.metric.clone()),
+                        ("MESHGRID_VALUE", value.to_string()),
+                        ("MESHGRID_DEVICE", device.clone()),
+                    ],
+                );
+            }
+        }
+    }
+}
+
+fn now() -> String {
+    Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+}
+
+fn telemetry_row(telemetry: &Telemetry) -> Vec<String> {
+    let mut row = vec![now()];
+    match &telemetry.device {
+        Some(d) => row.extend([
+            d.battery_percent.to_string(),
+            format!("{:.2}", d.voltage()),
+            d.charging.to_string(),
+            d.usb_power.to_string(),
+            d.uptime_secs.to_string(),
+            d.free_heap.to_string(),
+            format!("{:.1}", d.cpu_temp_celsius()),
+        ]),
+        None => row.extend(std::iter::repeat_n(String::new(), 7)),
+    }
+    match &telemetry.environment {
+        Some(e) => row.extend([
+            format!("{:.1}", e.temperature_celsius()),
+            format!("{:.1}", e.humidity_percent()),
+            format!("{:.1}", e.pressure_hpa()),
+        ]),
+        None => row.extend(std::iter::repeat_n(String::new(), 3)),
+    }
+    match &telemetry.location {
+        Some(l) if l.has_fix() => row.extend([
+            format!("{:.6}", l.latitude()),
+            format!("{:.6}", l.longitude()),
+            l.satellites.to_string(),
+        ]),
+        _ => row.extend(std::iter::repeat_n(String::new(), 3)),
+    }
+    row
+}
+
+/// Run the collector described by `config_path` until stopped.
+pub async fn cmd_collector(
+    port: Option<&String>,
+    baud: u32,
+    pin: Option<&str>,
+    config_path: &str,
+) -> Result<()> {
+    let config = load_config(config_path)?;
+    let port = require_port(port.or(config.port.as_ref()))?;
+    let base = Path::new(config_path)
+        .parent()
+        .unwrap_or_else(|| Path::new("."));
+    let data_dir = base.join(config.data_dir.as_deref().unwrap_or_else(|| Path::new("")));
+
+    let settings = Settings::load()?;
+    let metrics = Arc::new(Mutex::new(Metrics::default()));
+    let mut collector = Collector {
+        interval: Duration::from_secs(config.interval),
+        metrics: metrics.clone(),
+        alerts: config
+            .alerts
+            .into_iter()
+            .map(|rule| Alert {
+                rule,
+                since: None,
+                firing: false,
+            })
+            .collect(),
+        last_alert_check: None,
+        telemetry_csv: CsvLog::open(data_dir.join("telemetry.csv"), &TELEMETRY_COLUMNS)?,
+        events_csv: CsvLog::open(data_dir.join("events.csv"), &EVENT_COLUMNS)?,
+        history: Recorder::new(&settings),
+        hooks: HookRunner::new(settings.hooks),
+        prefs: channels::load()?,
+        outage_reported: false,
+        seen_nodes: HashSet::new(),
+        last_uptime: None,
+    };
+    println!(
+        "Collecting from {port} every {}s into {} ({} alert rule(s))",
+        config.interval,
+        data_dir.display(),
+        collector.alerts.len()
+    );
+
+    if let Some(addr) = &config.metrics {
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("Failed to listen on {addr}"))?;
+        println!(
+            "Serving metrics on http://{}/metrics",
+            listener.local_addr()?
+        );
+        tokio::spawn(http::serve(listener, move |req| {
+            let metrics = metrics.clone();
+            async move {
+                if req.path != "/metrics" {
+                    return http::Response::text(404, "Not found\n");
+                }
+                let body = metrics.lock().unwrap_or_else(|e| e.into_inner()).render();
+                http::Response::new(200, "text/plain; version=0.0.4", body)
+            }
+        }));
+    }
+
+    let mut backoff = Duration::from_secs(1);
+    loop {
+        let started = Instant::now();
+        // A session only ends when the connection fails
+        let Err(e) = collector.session(&port, baud, pin).await else {
+            continue;
+        };
+        collector.disconnected(&e);
+        if started.elapsed() >= STABLE {
+            backoff = Duration::from_secs(1);
+        }
+        // Keep evaluating alerts (`up` below 1) while the device is away
+        let until = Instant::now() + backoff;
+        while Instant::now() < until {
+            collector.check_alerts();
+            tokio::time::sleep(Duration::from_millis(250)).await;
+        }
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+        collector.metrics().reconnects += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::DeviceTelemetry;
+
+    #[test]
+    fn test_collector_metrics_and_alerts() {
+        let mut metrics = Metrics {
+            device: Some("gate\"way".into()),
+            up: true,
+            ..Metrics::default()
+        };
+        metrics.telemetry.device = Some(DeviceTelemetry {
+            battery_percent: 15,
+            ..DeviceTelemetry::default()
+        });
+        let text = metrics.render();
+        assert!(text.contains("# TYPE meshgrid_up gauge\nmeshgrid_up{device=\"gate\\\"way\"} 1\n"));
+        assert!(text.contains("meshgrid_battery_percent{device=\"gate\\\"way\"} 15\n"));
+        assert!(text.contains("# TYPE meshgrid_reconnects_total counter\n"));
+        assert!(!text.contains("humidity"));
+
+        let mut alert = Alert {
+            rule: AlertRule {
+                name: "battery".into(),
+                metric: "battery_percent".into(),
+                below: Some(20.0),
+                above: None,
+                for_secs: 60,
+                command: None,
+            },
+            since: None,
+            firing: false,
+        };
+        let start = Instant::now();
+        let value = metrics.value("battery_percent");
+        assert_eq!(alert.update(value, start), None);
+        assert_eq!(
+            alert.update(value, start + Duration::from_secs(60)),
+            Some(true)
+        );
+        assert_eq!(alert.update(value, start + Duration::from_secs(61)), None);
+        assert_eq!(
+            alert.update(Some(80.0), start + Duration::from_secs(62)),
+            Some(false)
+        );
+        assert_eq!(alert.update(None, start + Duration::from_secs(63)), None);
+    }
+
+    #[test]
+    fn test_csv_rotation() {
+        let dir = std::env::temp_dir().join(format!("meshgrid-csv-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("stats.csv");
+        let rotation = Rotation {
+            max_bytes: 15,
+            keep: 2,
+        };
+        let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap_or_default();
+
+        let mut log = CsvLog::rotating(path.clone(), &["a", "b"], rotation).unwrap();
+        for i in 0..4 {
+            log.append(&[format!("{i}"), "xxxxxxxx".into()]);
+        }
+        // Every file starts with the header; the oldest rows are gone
+        assert_eq!(read("stats.csv"), "a,b\n3,xxxxxxxx\n");
+        assert_eq!(read("stats.1.csv"), "a,b\n2,xxxxxxxx\n");
+        assert_eq!(read("stats.2.csv"), "a,b\n1,xxxxxxxx\n");
+
+        // New columns start a new file
+        drop(log);
+        let mut log = CsvLog::rotating(path, &["a", "b", "c"], rotation).unwrap();
+        log.append(&["4".into(), "y".into(), "z".into()]);
+        assert_eq!(read("stats.csv"), "a,b,c\n4,y,z\n");
+        assert_eq!(read("stats.1.csv"), "a,b\n3,xxxxxxxx\n");
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
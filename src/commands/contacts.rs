@@ -0,0 +1,361 @@
+//! Contact key verification and syncing between devices
+
+use super::connect_with_auth;
+use crate::cli::ContactsAction;
+use crate::contacts::{self, KeyStatus};
+use crate::error::CliError;
+use crate::nodes::{self, CachedNode};
+use crate::output;
+use crate::protocol::{NeighborInfo, Protocol};
+use crate::qr::QrCode;
+use anyhow::{bail, Context, Result};
+
+/// Verify and trust contact keys
+pub async fn cmd_contacts(
+    port: &str,
+    baud: u32,
+    pin: Option<&str>,
+    action: ContactsAction,
+) -> Result<()> {
+    let mut proto = connect_with_auth(port, baud, pin).await?.into_protocol();
+
+    match action {
+        ContactsAction::Verify { node: None } => {
+            let info = proto.get_info().await?;
+            let name = info.name.as_deref().unwrap_or("this device");
+            println!("This device: {name} (0x{:02x})", info.node_hash);
+            println!("  Public Key: {}", hex::encode(info.public_key));
+            print_fingerprint(name, &info.public_key)?;
+            println!("Ask your contact to compare these words with their own output of");
+            println!("`meshgrid-cli contacts verify {name}`.");
+        }
+        ContactsAction::Verify { node: Some(node) } => {
+            let neighbors = proto.get_neighbors().await?;
+            let (name, public_key) = contact_key(&neighbors, &node)?;
+            let key_hex = hex::encode(public_key);
+
+            println!("Contact: {name}");
+            println!("  Public Key: {key_hex}");
+            let status = match contacts::status(&name, &key_hex)? {
+                KeyStatus::Trusted => "trusted".to_string(),
+                KeyStatus::Unverified | KeyStatus::New => "not verified".to_string(),
+                KeyStatus::Changed { previous } => {
+                    format!(
+                        "CHANGED (previously {})",
+                        &previous[..16.min(previous.len())]
+                    )
+                }
+            };
+            println!("  Status:     {status}");
+            print_fingerprint(&name, &public_key)?;
+            println!("Compare these words with `meshgrid-cli contacts verify` on {name}'s device.");
+            println!("If they match, run `meshgrid-cli contacts trust {node}`.");
+        }
+        ContactsAction::Trust { node } => {
+            let neighbors = proto.get_neighbors().await?;
+            let (name, public_key) = contact_key(&neighbors, &node)?;
+            let replaced = contacts::trust(&name, &hex::encode(public_key))?;
+            println!(
+                "✓ Trusted {name} ({})",
+                contacts::fingerprint_words(&public_key).join(" ")
+            );
+            for key in replaced {
+                println!("  Replaced previous key {}", &key[..16.min(key.len())]);
+            }
+        }
+        ContactsAction::Sync { from, to, dry_run } => {
+            cmd_contacts_sync(&from, &to, baud, pin, dry_run).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// What syncing one contact does on the target device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SyncStep {
+    Add,
+    /// The target has the same key already
+    Known,
+    /// The contact's advert with its key hasn't been heard yet
+    NoKey,
+    /// The key differs from the one trusted for this contact
+    KeyChanged,
+}
+
+fn sync_step(contact: &NeighborInfo, target: &[NeighborInfo], status: &KeyStatus) -> SyncStep {
+    let Some(key) = contact.public_key else {
+        return SyncStep::NoKey;
+    };
+    if target.iter().any(|n| n.public_key == Some(key)) {
+        SyncStep::Known
+    } else if matches!(status, KeyStatus::Changed { .. }) {
+        SyncStep::KeyChanged
+    } else {
+        SyncStep::Add
+    }
+}
+
+/// Copy the contacts of the device on `from` to the device on `to`
+pub async fn cmd_contacts_sync(
+    from: &str,
+    to: &str,
+    baud: u32,
+    pin: Option<&str>,
+    dry_run: bool,
+) -> Result<()> {
+    let mut source = connect_with_auth(from, baud, pin)
+        .await
+        .with_context(|| format!("Failed to connect to {from}"))?
+        .into_protocol();
+    let mut target = connect_with_auth(to, baud, pin)
+        .await
+        .with_context(|| format!("Failed to connect to {to}"))?
+        .into_protocol();
+    if source.get_info().await?.public_key == target.get_info().await?.public_key {
+        bail!(CliError::InvalidArgs(format!(
+            "{from} and {to} are the same device"
+        )));
+    }
+
+    let contacts = source.get_neighbors().await?;
+    remember_neighbors(&mut source, from, &contacts).await;
+    let known = target.get_neighbors().await?;
+
+    let (mut added, mut already, mut skipped) = (0, 0, 0);
+    for contact in &contacts {
+        let name = contact_name(contact);
+        let key_hex = contact.public_key.map(hex::encode);
+        let status = match &key_hex {
+            Some(key) => contacts::status(&name, key)?,
+            None => KeyStatus::New,
+        };
+        match sync_step(contact, &known, &status) {
+            SyncStep::Known => already += 1,
+            SyncStep::NoKey => {
+                skipped += 1;
+                if !output::is_plain() {
+                    println!("⚠ {name}: no public key yet, skipped");
+                }
+            }
+            SyncStep::KeyChanged => {
+                skipped += 1;
+                if !output::is_plain() {
+                    println!(
+                        "⚠ {name}: key differs from the trusted one, skipped (see `contacts verify {name}`)"
+                    );
+                }
+            }
+            SyncStep::Add => {
+                let key = contact.public_key.expect("checked by sync_step");
+                let trusted = if status == KeyStatus::Trusted {
+                    " (trusted)"
+                } else {
+                    ""
+                };
+                if dry_run {
+                    println!("+ {name}{trusted}");
+                } else {
+                    match target.add_contact(&key, &name).await {
+                        Ok(()) => {
+                            if !output::is_plain() {
+                                println!("✓ Added {name}{trusted}");
+                            }
+                        }
+                        Err(e) => {
+                            skipped += 1;
+                            eprintln!("✗ {name}: {e:#}");
+                            continue;
+                        }
+                    }
+                }
+                added += 1;
+            }
+        }
+    }
+
+    if output::is_plain() {
+        output::kv("added", added);
+        output::kv("known", already);
+        output::kv("skipped", skipped);
+    } else {
+        let verb = if dry_run { "would be added" } else { "added" };
+        println!("{added} contacts {verb}, {already} already known, {skipped} skipped");
+    }
+    Ok(())
+}
+
+/// Print fingerprint words and a QR code carrying the key.
+fn print_fingerprint(name: &str, public_key: &[u8; 32]) -> Result<()> {
+    let words = contacts::fingerprint_words(public_key);
+    println!("\nFingerprint:");
+    println!("  {}", words[..4].join(" "));
+    println!("  {}\n", words[4..].join(" "));
+
+    let link = contact_link(name, public_key);
+    print!("{}", QrCode::encode(link.as_bytes())?.render());
+    println!("{link}\n");
+    Ok(())
+}
+
+/// Share link for a contact key.
+fn contact_link(name: &str, public_key: &[u8; 32]) -> String {
+    let mut link = url::Url::parse("meshgrid://contact/").expect("valid base URL");
+    link.set_path(&format!("/{}", hex::encode(public_key)));
+    link.query_pairs_mut().append_pair("name", name);
+    link.to_string()
+}
+
+/// Display name for a neighbor.
+pub(super) fn contact_name(neighbor: &NeighborInfo) -> String {
+    neighbor
+        .name
+        .clone()
+        .unwrap_or_else(|| format!("0x{:02x}", neighbor.node_hash))
+}
+
+/// Find a neighbor by name, node hash (`0x1f` or `1f`) or public key prefix.
+pub(super) fn find_contact<'a>(
+    neighbors: &'a [NeighborInfo],
+    node: &str,
+) -> Option<&'a NeighborInfo> {
+    let lower = node.to_ascii_lowercase();
+    let hash = u8::from_str_radix(lower.trim_start_matches("0x"), 16).ok();
+    neighbors
+        .iter()
+        .find(|n| {
+            n.name
+                .as_deref()
+                .is_some_and(|name| name.eq_ignore_ascii_case(node))
+        })
+        .or_else(|| neighbors.iter().find(|n| Some(n.node_hash) == hash))
+        .or_else(|| {
+            (lower.len() >= 8).then_some(())?;
+            neighbors.iter().find(|n| {
+                n.public_key
+                    .is_some_and(|key| hex::encode(key).starts_with(&lower))
+            })
+        })
+}
+
+/// Resolve `node` from the cached neighbor table of the device on `port`.
+/// On a cache miss, or with `refresh`, the device is asked for its neighbor
+/// table, which updates the cache.
+pub(super) async fn resolve_node(
+    proto: &mut Protocol,
+    port: &str,
+    node: &str,
+    refresh: bool,
+) -> Option<CachedNode> {
+    if !refresh {
+        match cached_node(port, node) {
+            Ok(Some(cached)) => return Some(cached),
+            Ok(None) => {}
+            Err(e) => tracing::debug!("Skipping neighbor cache: {e:#}"),
+        }
+    }
+
+    let neighbors = match proto.get_neighbors().await {
+        Ok(neighbors) => neighbors,
+        Err(e) => {
+            tracing::debug!("Could not resolve {node}: {e:#}");
+            return None;
+        }
+    };
+    let device_key = remember_neighbors(proto, port, &neighbors).await;
+
+    match find_contact(&neighbors, node) {
+        Some(neighbor) => Some(neighbor.into()),
+        // Out of range now, but the device may have reported it before
+        None => nodes::lookup(&device_key?, node).ok().flatten(),
+    }
+}
+
+/// Add a neighbor table read from the device on `port` to the node
+/// database; returns the device's public key.
+pub(super) async fn remember_neighbors(
+    proto: &mut Protocol,
+    port: &str,
+    neighbors: &[NeighborInfo],
+) -> Option<String> {
+    let device_key = proto
+        .get_info()
+        .await
+        .ok()
+        .map(|info| hex::encode(info.public_key))?;
+    if let Err(e) = nodes::store(port, &device_key, neighbors) {
+        tracing::debug!("Failed to update neighbor cache: {e:#}");
+    }
+    Some(device_key)
+}
+
+fn cached_node(port: &str, node: &str) -> Result<Option<CachedNode>> {
+    match nodes::device_on_port(port)? {
+        Some(device_key) => nodes::lookup(&device_key, node),
+        None => Ok(None),
+    }
+}
+
+fn contact_key(neighbors: &[NeighborInfo], node: &str) -> Result<(String, [u8; 32])> {
+    let Some(neighbor) = find_contact(neighbors, node) else {
+        bail!("No contact '{node}' in the neighbor table");
+    };
+    let Some(public_key) = neighbor.public_key else {
+        bail!(
+            "{} has not shared its public key yet (wait for its next advert)",
+            contact_name(neighbor)
+        );
+    };
+    Ok((contact_name(neighbor), public_key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sync_step() {
+        let contact = |hash, key: Option<u8>| NeighborInfo {
+            node_hash: hash,
+            protocol_version: None,
+            name: None,
+            public_key: key.map(|k| [k; 32]),
+            rssi: -80,
+            snr: 5,
+            last_seen_secs: 10,
+            firmware: None,
+            lat: None,
+            lon: None,
+            alt: None,
+        };
+        let target = [contact(0x11, Some(1))];
+        let changed = KeyStatus::Changed {
+            previous: "ab".repeat(32),
+        };
+
+        assert_eq!(
+            sync_step(&contact(0x11, Some(1)), &target, &KeyStatus::Trusted),
+            SyncStep::Known
+        );
+        assert_eq!(
+            sync_step(&contact(0x22, Some(2)), &target, &KeyStatus::New),
+            SyncStep::Add
+        );
+        assert_eq!(
+            sync_step(&contact(0x22, Some(2)), &target, &changed),
+            SyncStep::KeyChanged
+        );
+        assert_eq!(
+            sync_step(&contact(0x33, None), &target, &KeyStatus::New),
+            SyncStep::NoKey
+        );
+    }
+
+    #[test]
+    fn test_contact_link() {
+        assert_eq!(
+            contact_link("bob smith", &[0xab; 32]),
+            format!("meshgrid://contact/{}?name=bob+smith", "ab".repeat(32))
+        );
+    }
+}
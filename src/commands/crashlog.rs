@@ -0,0 +1,209 @@
+//! Crash log retrieval
+
+use super::connect_with_auth;
+use crate::error::CliError;
+use crate::output;
+use crate::protocol::Response;
+use anyhow::{bail, Context, Result};
+use std::fmt::Write as _;
+use std::process::Command;
+
+/// ELF machine types of ESP32 chips.
+const EM_XTENSA: u16 = 94;
+const EM_RISCV: u16 = 243;
+
+/// Fetch the stored crash dump, symbolize its backtrace and print a report
+pub async fn cmd_crashlog(
+    port: &str,
+    baud: u32,
+    pin: Option<&str>,
+    elf: Option<&str>,
+    output_file: Option<&str>,
+    clear: bool,
+) -> Result<()> {
+    let mut proto = connect_with_auth(port, baud, pin).await?.into_protocol();
+    let info = proto.get_info().await?;
+
+    let dump = match proto.command("CRASHLOG").await? {
+        Response::Json(json) => json,
+        Response::Ok(Some(text)) => serde_json::json!({ "log": text }),
+        Response::Ok(None) => {
+            if !output::is_plain() {
+                println!("✓ No crash log stored on the device");
+            }
+            return Ok(());
+        }
+        Response::Error(e) => bail!(CliError::Device(format!("failed to read crash log: {e}"))),
+    };
+    let field = |name: &str| dump.get(name).and_then(|v| v.as_str());
+
+    // Frames from the backtrace field, or from the panic output itself
+    let trace_text = field("backtrace")
+        .or_else(|| field("log"))
+        .unwrap_or_default();
+    let addresses = backtrace_addresses(trace_text);
+    let symbols = match elf {
+        Some(elf) if !addresses.is_empty() => Some(symbolize(elf, &addresses)?),
+        _ => None,
+    };
+
+    let mut report = String::new();
+    writeln!(report, "# Crash report")?;
+    writeln!(report)?;
+    writeln!(
+        report,
+        "Device:   {} (0x{:02x})",
+        info.name.as_deref().unwrap_or("<unnamed>"),
+        info.node_hash
+    )?;
+    writeln!(
+        report,
+        "Firmware: {}",
+        field("firmware")
+            .or(info.firmware_version.as_deref())
+            .unwrap_or("unknown")
+    )?;
+    for (label, name) in [("Reason:", "reason"), ("Task:", "task")] {
+        if let Some(value) = field(name) {
+            writeln!(report, "{label:<9} {value}")?;
+        }
+    }
+    if let Some(uptime) = dump.get("uptime_secs").and_then(serde_json::Value::as_u64) {
+        writeln!(
+            report,
+            "Uptime:   {} before the crash",
+            crate::timefmt::duration(uptime)
+        )?;
+    }
+
+    writeln!(report, "\n## Backtrace\n")?;
+    match (&symbols, addresses.is_empty()) {
+        (_, true) => writeln!(report, "(none found in the dump)")?,
+        (Some(lines), false) => {
+            for line in lines {
+                writeln!(report, "{line}")?;
+            }
+        }
+        (None, false) => {
+            for address in &addresses {
+                writeln!(report, "0x{address:08x}")?;
+            }
+            writeln!(
+                report,
+                "\n(pass --elf firmware.elf to resolve functions and lines)"
+            )?;
+        }
+    }
+    if let Some(log) = field("log") {
+        writeln!(report, "\n## Panic output\n\n```\n{}\n```", log.trim_end())?;
+    }
+
+    match output_file {
+        Some(path) => {
+            std::fs::write(path, &report).with_context(|| format!("Failed to write {path}"))?;
+            if output::is_plain() {
+                output::kv("report", path);
+            } else {
+                println!("✓ Crash report written to {path}");
+            }
+        }
+        None => print!("{report}"),
+    }
+
+    if clear {
+        match proto.command("CRASHLOG CLEAR").await? {
+            Response::Ok(_) => {
+                if !output::is_plain() {
+                    println!("✓ Crash log cleared");
+                }
+            }
+            Response::Error(e) => {
+                bail!(CliError::Device(format!("failed to clear crash log: {e}")))
+            }
+            Response::Json(_) => bail!("Unexpected response to CRASHLOG CLEAR"),
+        }
+    }
+    Ok(())
+}
+
+/// Program counters of an ESP-IDF backtrace (`Backtrace: 0xPC:0xSP ...`),
+/// or bare addresses one per word.
+pub(super) fn backtrace_addresses(text: &str) -> Vec<u32> {
+    let parse = |word: &str| {
+        let pc = word.split(':').next()?.strip_prefix("0x")?;
+        u32::from_str_radix(pc, 16).ok().filter(|&pc| pc != 0)
+    };
+    let words: Vec<&str> = match text.find("Backtrace:") {
+        Some(start) => text[start + "Backtrace:".len()..]
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .split_whitespace()
+            .collect(),
+        None => text.split_whitespace().collect(),
+    };
+    words.into_iter().filter_map(parse).collect()
+}
+
+/// `addr2line` output for `addresses`, with the toolchain's addr2line for
+/// the ELF's architecture when installed.
+fn symbolize(elf: &str, addresses: &[u32]) -> Result<Vec<String>> {
+    let header = std::fs::read(elf).with_context(|| format!("Failed to read {elf}"))?;
+    if !header.starts_with(b"\x7fELF") || header.len() < 20 {
+        bail!(CliError::InvalidArgs(format!("{elf} is not an ELF file")));
+    }
+    let machine = u16::from_le_bytes([header[18], header[19]]);
+    let tools: &[&str] = match machine {
+        EM_XTENSA => &[
+            "xtensa-esp32s3-elf-addr2line",
+            "xtensa-esp32-elf-addr2line",
+            "xtensa-esp-elf-addr2line",
+        ],
+        EM_RISCV => &["riscv32-esp-elf-addr2line"],
+        _ => &[],
+    };
+
+    let args: Vec<String> = ["-pfiaC", "-e", elf]
+        .into_iter()
+        .map(String::from)
+        .chain(addresses.iter().map(|a| format!("0x{a:08x}")))
+        .collect();
+    for tool in tools.iter().chain(&["addr2line"]) {
+        match Command::new(tool).args(&args).output() {
+            Ok(out) if out.status.success() => {
+                return Ok(String::from_utf8_lossy(&out.stdout)
+                    .lines()
+                    .map(String::from)
+                    .collect());
+            }
+            Ok(out) => bail!(
+                "{tool} failed: {}",
+                String::from_utf8_lossy(&out.stderr).trim()
+            ),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e).with_context(|| format!("Failed to run {tool}")),
+        }
+    }
+    bail!(
+        "No addr2line found for this ELF. Install the ESP-IDF toolchain \
+         (e.g. xtensa-esp32-elf-addr2line) or binutils"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backtrace_addresses() {
+        let panic = "Guru Meditation Error: Core  1 panic'ed (LoadProhibited)\n\
+                     Backtrace: 0x400d1a2b:0x3ffb1f50 0x400d2c3d:0x3ffb1f70 0x00000000:0x00000000 |<-CORRUPTED\n\
+                     ELF file SHA256: 0000";
+        assert_eq!(backtrace_addresses(panic), vec![0x400d_1a2b, 0x400d_2c3d]);
+        assert_eq!(
+            backtrace_addresses("0x42001234 0x42005678"),
+            vec![0x4200_1234, 0x4200_5678]
+        );
+        assert!(backtrace_addresses("no trace").is_empty());
+    }
+}
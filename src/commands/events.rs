@@ -0,0 +1,42 @@
+//! Event journal export
+
+use crate::cli::{EventsAction, EventsFormat};
+use crate::history;
+use anyhow::{Context, Result};
+use std::io::Write;
+
+/// Export the merged local history
+pub fn cmd_events(action: EventsAction) -> Result<()> {
+    let EventsAction::Export {
+        since,
+        format,
+        output,
+    } = action;
+    let since = since.as_deref().map(history::parse_since).transpose()?;
+    let records = history::journal(since)?;
+
+    let mut out = String::new();
+    match format {
+        EventsFormat::Ndjson => {
+            for record in &records {
+                out.push_str(&serde_json::to_string(record)?);
+                out.push('\n');
+            }
+        }
+        EventsFormat::Json => {
+            out = serde_json::to_string_pretty(&records)?;
+            out.push('\n');
+        }
+    }
+    match output {
+        Some(path) => {
+            std::fs::write(&path, out).with_context(|| format!("Failed to write {path}"))?;
+            eprintln!("✓ Exported {} records to {path}", records.len());
+        }
+        None => match std::io::stdout().write_all(out.as_bytes()) {
+            Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => {}
+            other => other?,
+        },
+    }
+    Ok(())
+}
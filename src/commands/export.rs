@@ -0,0 +1,132 @@
+//! Export mesh data to other tools
+
+use super::connect_with_auth;
+use crate::cli::{ExportAction, WeatherFormat};
+use crate::protocol::{EnvironmentTelemetry, Protocol};
+use anyhow::{bail, Context, Result};
+use serde_json::json;
+use std::path::Path;
+use std::time::Duration;
+
+/// Export data from the device
+pub async fn cmd_export(
+    port: &str,
+    baud: u32,
+    pin: Option<&str>,
+    action: ExportAction,
+) -> Result<()> {
+    match action {
+        ExportAction::Weather {
+            loop_file,
+            node,
+            format,
+            interval,
+            once,
+        } => {
+            let dev = connect_with_auth(port, baud, pin).await?;
+            let mut proto = dev.into_protocol();
+            let source = node.as_deref().unwrap_or("local device");
+
+            if !once {
+                println!(
+                    "Writing {source} weather to {loop_file} every {interval}s (Ctrl+C to stop)..."
+                );
+            }
+            loop {
+                let timestamp = chrono::Local::now().format("%H:%M:%S");
+                match export_weather(&mut proto, node.as_deref(), format, Path::new(&loop_file))
+                    .await
+                {
+                    Ok(()) if once => return Ok(()),
+                    Ok(()) => tracing::debug!("Wrote {loop_file}"),
+                    Err(e) if once => return Err(e),
+                    Err(e) => eprintln!("[{timestamp}] ✗ {e:#}"),
+                }
+                tokio::time::sleep(Duration::from_secs(interval.max(1))).await;
+            }
+        }
+    }
+}
+
+/// Read environment telemetry once and write it to `path`.
+async fn export_weather(
+    proto: &mut Protocol,
+    node: Option<&str>,
+    format: WeatherFormat,
+    path: &Path,
+) -> Result<()> {
+    let telemetry = match node {
+        Some(node) => proto.get_node_telemetry(node).await,
+        None => proto.get_telemetry().await,
+    }
+    .context("Failed to read telemetry")?;
+    let Some(env) = telemetry.environment else {
+        bail!(
+            "{} reported no environment telemetry",
+            node.unwrap_or("Device")
+        );
+    };
+
+    let packet = weather_packet(format, &env, node, chrono::Utc::now());
+    write_atomic(path, format!("{packet}\n").as_bytes())
+}
+
+/// Build a weather record. WeeWX loop packets use the METRIC unit system
+/// (°C, %, mbar).
+fn weather_packet(
+    format: WeatherFormat,
+    env: &EnvironmentTelemetry,
+    node: Option<&str>,
+    now: chrono::DateTime<chrono::Utc>,
+) -> serde_json::Value {
+    let round = |v: f32| (f64::from(v) * 10.0).round() / 10.0;
+    match format {
+        WeatherFormat::Weewx => json!({
+            "dateTime": now.timestamp(),
+            "usUnits": 16,
+            "outTemp": round(env.temperature_celsius()),
+            "outHumidity": round(env.humidity_percent()),
+            "pressure": round(env.pressure_hpa()),
+        }),
+        WeatherFormat::Json => json!({
+            "timestamp": now.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            "node": node,
+            "temperature_c": round(env.temperature_celsius()),
+            "humidity_pct": round(env.humidity_percent()),
+            "pressure_hpa": round(env.pressure_hpa()),
+        }),
+    }
+}
+
+/// Replace `path` in one step so readers never see a partial file.
+fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    std::fs::write(&tmp, contents)
+        .with_context(|| format!("Failed to write {}", Path::new(&tmp).display()))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("Failed to replace {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_weather_packet() {
+        let env = EnvironmentTelemetry::new()
+            .with_temperature(21.34)
+            .with_humidity(55.0)
+            .with_pressure_hpa(1013.2);
+        let now = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+
+        let weewx = weather_packet(WeatherFormat::Weewx, &env, None, now);
+        assert_eq!(
+            weewx,
+            json!({"dateTime": 1_700_000_000, "usUnits": 16, "outTemp": 21.3, "outHumidity": 55.0, "pressure": 1013.2})
+        );
+
+        let plain = weather_packet(WeatherFormat::Json, &env, Some("garden"), now);
+        assert_eq!(plain["timestamp"], "2023-11-14T22:13:20Z");
+        assert_eq!(plain["node"], "garden");
+    }
+}
@@ -0,0 +1,213 @@
+//! Filesystem image upload
+//!
+//! Boards with a web UI or offline map tiles keep them in a LittleFS or
+//! SPIFFS data partition. `flash fs` takes a prebuilt image, or builds one
+//! from a directory with `mklittlefs`/`mkspiffs` (the tools PlatformIO's
+//! `buildfs` uses), and writes it to the filesystem partition listed in the
+//! partition table read from the device, so the offset always matches the
+//! board's firmware build.
+
+use super::system::{espflash, verify_flash};
+use crate::cli::FsType;
+use crate::error::CliError;
+use crate::firmware::{
+    parse_partition_table, Partition, FLASH_SECTOR, PARTITION_TABLE_OFFSET, PARTITION_TABLE_SIZE,
+};
+use crate::outln;
+use crate::output;
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Page size of the images (the ESP32 Arduino and ESP-IDF default).
+const FS_PAGE: usize = 256;
+
+/// Build or take a filesystem image and write it to the device's
+/// filesystem partition
+pub fn cmd_flash_fs(
+    port: Option<&str>,
+    source: &str,
+    fs: FsType,
+    label: Option<&str>,
+    verify: bool,
+) -> Result<()> {
+    if port.is_some_and(|p| p.starts_with("tcp://") || p.starts_with("unix:")) {
+        bail!(CliError::InvalidArgs(
+            "flash fs needs the device's serial port, not a network connection".into()
+        ));
+    }
+    let plain = output::is_plain();
+    let source_path = Path::new(source);
+    if !source_path.exists() {
+        bail!(CliError::InvalidArgs(format!("{source} does not exist")));
+    }
+
+    let partitions = read_partition_table(port)?;
+    let partition = select_partition(&partitions, label)?;
+    if plain {
+        output::kv("partition", &partition.label);
+        output::kv("offset", format!("0x{:x}", partition.offset));
+        output::kv("size", partition.size);
+    } else {
+        outln!(
+            "Filesystem partition '{}' at 0x{:x} ({} KB)",
+            partition.label,
+            partition.offset,
+            partition.size / 1024
+        );
+    }
+
+    let built = if source_path.is_dir() {
+        Some(build_image(source_path, fs, partition.size as usize)?)
+    } else {
+        None
+    };
+    let image_path = built.as_deref().unwrap_or(source_path);
+    let image_len = std::fs::metadata(image_path)?.len();
+    if image_len > u64::from(partition.size) {
+        bail!(CliError::InvalidArgs(format!(
+            "{} is {} KB but the '{}' partition holds {} KB",
+            image_path.display(),
+            image_len / 1024,
+            partition.label,
+            partition.size / 1024
+        )));
+    }
+
+    if !plain {
+        outln!("Writing {} KB...", image_len / 1024);
+    }
+    let result = espflash(
+        "write-bin",
+        port,
+        &[
+            format!("0x{:x}", partition.offset),
+            image_path.display().to_string(),
+        ],
+    )
+    .and_then(|()| {
+        if verify {
+            verify_flash(image_path, partition.offset, port)
+        } else {
+            Ok(())
+        }
+    });
+    if let Some(built) = &built {
+        let _ = std::fs::remove_file(built);
+    }
+    result?;
+
+    if plain {
+        output::kv("result", "ok");
+    } else {
+        outln!("✓ Filesystem written to '{}'", partition.label);
+    }
+    Ok(())
+}
+
+fn read_partition_table(port: Option<&str>) -> Result<Vec<Partition>> {
+    let path = std::env::temp_dir().join(format!("meshgrid-partitions-{}.bin", std::process::id()));
+    let result = espflash(
+        "read-flash",
+        port,
+        &[
+            format!("0x{PARTITION_TABLE_OFFSET:x}"),
+            PARTITION_TABLE_SIZE.to_string(),
+            path.display().to_string(),
+        ],
+    )
+    .and_then(|()| Ok(std::fs::read(&path)?));
+    let _ = std::fs::remove_file(&path);
+    let partitions = parse_partition_table(&result.context("Failed to read the partition table")?);
+    if partitions.is_empty() {
+        bail!(CliError::Device(
+            "No ESP-IDF partition table found at 0x8000 (flash fs supports ESP32 boards only)"
+                .into()
+        ));
+    }
+    Ok(partitions)
+}
+
+/// The filesystem partition named `label`, or the only one.
+fn select_partition<'a>(partitions: &'a [Partition], label: Option<&str>) -> Result<&'a Partition> {
+    if let Some(label) = label {
+        return partitions
+            .iter()
+            .find(|p| p.label == label && p.is_filesystem())
+            .ok_or_else(|| {
+                CliError::InvalidArgs(format!(
+                    "No filesystem partition labelled '{label}' on the device"
+                ))
+                .into()
+            });
+    }
+    let filesystems: Vec<&Partition> = partitions.iter().filter(|p| p.is_filesystem()).collect();
+    match filesystems.as_slice() {
+        [partition] => Ok(partition),
+        [] => bail!(CliError::Device(
+            "The device's partition table has no filesystem partition".into()
+        )),
+        several => {
+            let labels: Vec<&str> = several.iter().map(|p| p.label.as_str()).collect();
+            bail!(CliError::InvalidArgs(format!(
+                "Several filesystem partitions ({}); choose one with --label",
+                labels.join(", ")
+            )))
+        }
+    }
+}
+
+/// Build an image of `dir` filling a partition of `size` bytes.
+fn build_image(dir: &Path, fs: FsType, size: usize) -> Result<PathBuf> {
+    let tool = match fs {
+        FsType::Littlefs => "mklittlefs",
+        FsType::Spiffs => "mkspiffs",
+    };
+    let out = std::env::temp_dir().join(format!("meshgrid-fs-{}.bin", std::process::id()));
+    if !output::is_plain() {
+        outln!("Building an image of {} with {tool}...", dir.display());
+    }
+    let result = Command::new(tool)
+        .arg("-c")
+        .arg(dir)
+        .args(["-b", &FLASH_SECTOR.to_string()])
+        .args(["-p", &FS_PAGE.to_string()])
+        .args(["-s", &size.to_string()])
+        .arg(&out)
+        .output();
+    match result {
+        Ok(o) if o.status.success() => Ok(out),
+        Ok(o) => bail!(
+            "{tool} failed: {}",
+            String::from_utf8_lossy(&o.stderr).trim()
+        ),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => bail!(
+            "{tool} not found. It comes with PlatformIO \
+             (~/.platformio/packages/tool-{tool}); add it to PATH or pass a prebuilt image"
+        ),
+        Err(e) => Err(e).with_context(|| format!("Failed to run {tool}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_partition() {
+        let partition = |label: &str, subtype: u8| Partition {
+            label: label.into(),
+            kind: 0x01,
+            subtype,
+            offset: 0x670000,
+            size: 0x180000,
+        };
+        let partitions = [partition("nvs", 0x02), partition("spiffs", 0x82)];
+        assert_eq!(select_partition(&partitions, None).unwrap().label, "spiffs");
+        assert!(select_partition(&partitions, Some("nvs")).is_err());
+        assert!(select_partition(&partitions[..1], None).is_err());
+        let two = [partition("spiffs", 0x82), partition("tiles", 0x83)];
+        assert!(select_partition(&two, None).is_err());
+        assert_eq!(select_partition(&two, Some("tiles")).unwrap().subtype, 0x83);
+    }
+}
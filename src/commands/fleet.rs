@@ -0,0 +1,616 @@
+//! Commands across several devices at once
+//!
+//! With `--ports a,b,c` or `--all-devices`, `info`, `stats`, `telemetry` and
+//! `neighbors` query every device concurrently and print one table with a
+//! device column.
+//!
+//! `fleet apply` brings the connected devices listed in a fleet manifest to
+//! their target firmware and provisioning profile:
+//!
+//! ```yaml
+//! devices:
+//!   - serial: "58:CF:79:0A:12:34"   # USB serial number,
+//!     # public_key: a1b2c3d4        # or node public key (hex prefix),
+//!     # port: /dev/ttyUSB0          # or port
+//!     board: heltec-v3
+//!     firmware: "0.0.5"
+//!     profile: profiles/repeater.toml
+//! ```
+//!
+//! Manifests ending in `.toml` are read as TOML `[[devices]]` entries.
+
+use super::connect_with_auth;
+use super::provision::apply_bundle;
+use super::system::cmd_flash;
+use crate::cli::{BoardType, Cli, Commands, FleetAction};
+use crate::device::DeviceInfo;
+use crate::error::CliError;
+use crate::output;
+use crate::protocol::Response;
+use crate::provision;
+use crate::timefmt;
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// How long a device may take to come back after flashing.
+const REBOOT_WAIT: Duration = Duration::from_secs(30);
+
+type Row = Vec<String>;
+
+/// Query to run on each device.
+#[derive(Clone, Copy)]
+enum Query {
+    Info,
+    Stats,
+    Telemetry,
+    Neighbors,
+}
+
+impl Query {
+    fn columns(self) -> &'static [&'static str] {
+        match self {
+            Query::Info => &["Name", "Mode", "Hash", "Firmware", "Freq MHz", "TX dBm"],
+            Query::Stats => &[
+                "RX",
+                "TX",
+                "Fwd",
+                "Dropped",
+                "Neighbors",
+                "Battery",
+                "Uptime",
+            ],
+            Query::Telemetry => &["Battery", "Voltage", "Uptime", "Temp", "Humidity", "GPS"],
+            Query::Neighbors => &["Hash", "Name", "RSSI", "SNR", "Last Seen"],
+        }
+    }
+}
+
+/// Run a read-only command on several devices concurrently
+pub async fn cmd_fleet(cli: &Cli) -> Result<()> {
+    let query = match &cli.command {
+        Commands::Info { .. } => Query::Info,
+        Commands::Stats {
+            json: false,
+            watch: false,
+            delta: false,
+            log: None,
+            ..
+        } => Query::Stats,
+        Commands::Telemetry { watch: false } => Query::Telemetry,
+        Commands::Neighbors { .. } => Query::Neighbors,
+        _ => bail!(CliError::InvalidArgs(
+            "--ports/--all-devices only work with info, neighbors, stats and telemetry (without --watch, --delta, --json or --log)".into()
+        )),
+    };
+    let ports = if cli.all_devices {
+        crate::serial::detect_devices()?
+    } else {
+        cli.ports.clone()
+    };
+    if ports.is_empty() {
+        bail!(CliError::PortNotFound("No devices detected".into()));
+    }
+
+    let tasks: Vec<_> = ports
+        .iter()
+        .map(|port| {
+            let (port, baud, pin) = (port.clone(), cli.baud, cli.pin.clone());
+            tokio::spawn(async move { run_query(&port, baud, pin.as_deref(), query).await })
+        })
+        .collect();
+
+    let mut rows = Vec::new();
+    let mut failed = 0;
+    for (port, task) in ports.iter().zip(tasks) {
+        match task.await? {
+            Ok(device_rows) => {
+                rows.extend(
+                    device_rows
+                        .into_iter()
+                        .map(|row| [vec![port.clone()], row].concat()),
+                );
+            }
+            Err(e) => {
+                failed += 1;
+                rows.push(vec![port.clone(), format!("{e:#}")]);
+            }
+        }
+    }
+
+    let mut columns = vec!["Device"];
+    columns.extend(query.columns());
+    print_table(&columns, &rows);
+
+    if failed > 0 {
+        bail!("{failed} of {} devices failed", ports.len());
+    }
+    Ok(())
+}
+
+async fn run_query(port: &str, baud: u32, pin: Option<&str>, query: Query) -> Result<Vec<Row>> {
+    let mut dev = connect_with_auth(port, baud, pin).await?;
+    let rows = match query {
+        Query::Info => {
+            let info = dev.get_info().await?;
+            let config = dev.get_config().await?;
+            vec![vec![
+                info.name.unwrap_or_else(|| "<unnamed>".into()),
+                info.mode.unwrap_or_else(|| "unknown".into()),
+                format!("0x{:02x}", info.node_hash),
+                info.firmware_version.unwrap_or_else(|| "unknown".into()),
+                format!("{:.3}", config.freq_mhz),
+                config.tx_power_dbm.to_string(),
+            ]]
+        }
+        Query::Neighbors => dev
+            .get_neighbors()
+            .await?
+            .into_iter()
+            .map(|n| {
+                vec![
+                    format!("0x{:02x}", n.node_hash),
+                    n.name.unwrap_or_else(|| "?".into()),
+                    n.rssi.to_string(),
+                    n.snr.to_string(),
+                    timefmt::ago(n.last_seen_secs.into()),
+                ]
+            })
+            .collect(),
+        Query::Stats => {
+            let json = match dev.into_protocol().command("STATS").await? {
+                Response::Json(json) => json,
+                Response::Error(e) => bail!(CliError::Device(e)),
+                Response::Ok(_) => bail!("Unexpected OK response to STATS"),
+            };
+            let field = |pointer: &str| {
+                json.pointer(pointer)
+                    .map_or_else(|| "-".into(), ToString::to_string)
+            };
+            vec![vec![
+                field("/packets/rx"),
+                field("/packets/tx"),
+                field("/packets/fwd"),
+                field("/packets/dropped"),
+                field("/neighbors/total"),
+                json.pointer("/power/battery_pct")
+                    .map_or_else(|| "-".into(), |v| format!("{v}%")),
+                json.pointer("/firmware/uptime_secs")
+                    .and_then(serde_json::Value::as_u64)
+                    .map_or_else(|| "-".into(), timefmt::duration),
+            ]]
+        }
+        Query::Telemetry => {
+            let telemetry = dev.into_protocol().get_telemetry().await?;
+            let mut row = match &telemetry.device {
+                Some(d) => vec![
+                    format!("{}%", d.battery_percent),
+                    format!("{:.2} V", d.voltage()),
+                    timefmt::duration(d.uptime_secs.into()),
+                ],
+                None => vec!["-".into(); 3],
+            };
+            match &telemetry.environment {
+                Some(e) => row.extend([
+                    format!("{:.1} °C", e.temperature_celsius()),
+                    format!("{:.0}%", e.humidity_percent()),
+                ]),
+                None => row.extend(["-".into(), "-".into()]),
+            }
+            row.push(match &telemetry.location {
+                Some(l) if l.has_fix() => format!("{:.5},{:.5}", l.latitude(), l.longitude()),
+                Some(_) => "no fix".into(),
+                None => "-".into(),
+            });
+            vec![row]
+        }
+    };
+    Ok(rows)
+}
+
+/// Fleet manifest.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    #[serde(default)]
+    devices: Vec<Member>,
+}
+
+/// A device in the fleet and its targets.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Member {
+    serial: Option<String>,
+    public_key: Option<String>,
+    port: Option<String>,
+    board: Option<String>,
+    firmware: Option<String>,
+    /// Provisioning bundle, relative to the manifest
+    profile: Option<String>,
+}
+
+impl Member {
+    fn identity(&self) -> String {
+        self.serial
+            .clone()
+            .or_else(|| self.public_key.clone())
+            .or_else(|| self.port.clone())
+            .unwrap_or_default()
+    }
+
+    /// Whether this is the device on `port`, with USB `serial` and `info`.
+    fn matches(&self, port: &str, serial: Option<&str>, info: Option<&DeviceInfo>) -> bool {
+        if let Some(wanted) = &self.serial {
+            return serial.is_some_and(|s| s.eq_ignore_ascii_case(wanted));
+        }
+        if let Some(wanted) = &self.public_key {
+            return info.is_some_and(|info| {
+                hex::encode(info.public_key).starts_with(&wanted.to_ascii_lowercase())
+            });
+        }
+        self.port.as_deref() == Some(port)
+    }
+}
+
+fn load_manifest(path: &str) -> Result<Manifest> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("Failed to read {path}"))?;
+    let manifest: Manifest = if provision::is_yaml(path) {
+        serde_yaml::from_str(&text).with_context(|| format!("Invalid fleet manifest {path}"))?
+    } else {
+        toml::from_str(&text).with_context(|| format!("Invalid fleet manifest {path}"))?
+    };
+    for member in &manifest.devices {
+        let invalid = |msg: &str| CliError::InvalidArgs(format!("{}: {msg}", member.identity()));
+        if member.serial.is_none() && member.public_key.is_none() && member.port.is_none() {
+            bail!(CliError::InvalidArgs(
+                "Every device needs a serial, public_key or port".into()
+            ));
+        }
+        if member
+            .public_key
+            .as_ref()
+            .is_some_and(|k| k.len() < 8 || !k.chars().all(|c| c.is_ascii_hexdigit()))
+        {
+            bail!(invalid("public_key must be at least 8 hex digits"));
+        }
+        if let Some(board) = &member.board {
+            if BoardType::from_str(board, true).is_err() {
+                bail!(invalid(&format!("unknown board '{board}'")));
+            }
+        }
+        match &member.firmware {
+            Some(_) if member.board.is_none() => bail!(invalid("firmware needs a board")),
+            Some(version) if version == "latest" => {
+                bail!(invalid("pin firmware to a version, not 'latest'"))
+            }
+            _ => {}
+        }
+    }
+    Ok(manifest)
+}
+
+/// Version string without a leading `v`, for comparison.
+fn normalize_version(version: &str) -> &str {
+    version.trim().trim_start_matches('v')
+}
+
+/// Flash and provision the connected devices listed in a fleet manifest
+pub async fn cmd_fleet_apply(baud: u32, pin: Option<&str>, action: FleetAction) -> Result<()> {
+    let FleetAction::Apply {
+        manifest: manifest_path,
+        dry_run,
+        trusted_key,
+        insecure,
+        allow_downgrade,
+    } = action;
+    let manifest = load_manifest(&manifest_path)?;
+    let base = Path::new(&manifest_path)
+        .parent()
+        .unwrap_or_else(|| Path::new("."));
+
+    // Connected USB devices, plus devices the manifest names by port
+    let mut candidates: Vec<(String, Option<String>)> = crate::serial::detect_usb_devices()?;
+    for port in manifest.devices.iter().filter_map(|m| m.port.clone()) {
+        if !candidates.iter().any(|(p, _)| *p == port) {
+            candidates.push((port, None));
+        }
+    }
+
+    let mut rows = Vec::new();
+    let mut matched = vec![false; manifest.devices.len()];
+    let mut failed = 0;
+    for (port, serial) in &candidates {
+        let info = match connect_with_auth(port, baud, pin).await {
+            Ok(mut dev) => dev.get_info().await.ok(),
+            Err(_) => None,
+        };
+        let Some(index) =
+            manifest.devices.iter().enumerate().position(|(i, m)| {
+                !matched[i] && m.matches(port, serial.as_deref(), info.as_ref())
+            })
+        else {
+            continue;
+        };
+        matched[index] = true;
+        let member = &manifest.devices[index];
+        let profile = member
+            .profile
+            .as_ref()
+            .map(|p| base.join(p).display().to_string());
+
+        let current = info.as_ref().and_then(|i| i.firmware_version.clone());
+        let firmware = match (&member.firmware, &current) {
+            (Some(target), Some(current))
+                if normalize_version(target) == normalize_version(current) =>
+            {
+                current.clone()
+            }
+            (Some(target), current) => {
+                format!("{} → {target}", current.as_deref().unwrap_or("none"))
+            }
+            (None, current) => current.clone().unwrap_or_else(|| "?".into()),
+        };
+        let needs_flash = member.firmware.as_ref().is_some_and(|target| {
+            current
+                .as_deref()
+                .is_none_or(|c| normalize_version(c) != normalize_version(target))
+        });
+
+        let mut row = vec![
+            port.clone(),
+            member.identity(),
+            member.board.clone().unwrap_or_else(|| "-".into()),
+            firmware,
+            member.profile.clone().unwrap_or_else(|| "-".into()),
+        ];
+        if dry_run {
+            let mut plan = Vec::new();
+            if needs_flash {
+                plan.push("flash");
+            }
+            if profile.is_some() {
+                plan.push("provision");
+            }
+            row.push(if plan.is_empty() {
+                "up to date".into()
+            } else {
+                format!("would {}", plan.join(", "))
+            });
+            rows.push(row);
+            continue;
+        }
+
+        if !output::is_plain() {
+            println!("== {port} ({}) ==", member.identity());
+        }
+        let result = apply_member(
+            port,
+            baud,
+            pin,
+            member,
+            needs_flash,
+            profile.as_deref(),
+            &trusted_key,
+            insecure,
+            allow_downgrade,
+        )
+        .await;
+        match result {
+            Ok(done) if done.is_empty() => row.push("✓ up to date".into()),
+            Ok(done) => row.push(format!("✓ {}", done.join(", "))),
+            Err(e) => {
+                failed += 1;
+                row.push(format!("✗ {e:#}"));
+            }
+        }
+        rows.push(row);
+    }
+
+    let missing: Vec<&Member> = manifest
+        .devices
+        .iter()
+        .zip(&matched)
+        .filter(|(_, matched)| !**matched)
+        .map(|(member, _)| member)
+        .collect();
+    for member in &missing {
+        rows.push(vec![
+            "-".into(),
+            member.identity(),
+            member.board.clone().unwrap_or_else(|| "-".into()),
+            member.firmware.clone().unwrap_or_else(|| "-".into()),
+            member.profile.clone().unwrap_or_else(|| "-".into()),
+            "✗ not connected".into(),
+        ]);
+    }
+
+    if !output::is_plain() {
+        println!();
+    }
+    print_table(
+        &[
+            "Device", "Identity", "Board", "Firmware", "Profile", "Status",
+        ],
+        &rows,
+    );
+
+    if dry_run {
+        return Ok(());
+    }
+    let mut problems = Vec::new();
+    if failed > 0 {
+        problems.push(format!("{failed} failed"));
+    }
+    if !missing.is_empty() {
+        problems.push(format!("{} not connected", missing.len()));
+    }
+    if !problems.is_empty() {
+        bail!(
+            "{} of {} devices: {}",
+            failed + missing.len(),
+            manifest.devices.len(),
+            problems.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Flash and provision one device; the steps taken.
+#[allow(clippy::too_many_arguments)]
+async fn apply_member(
+    port: &str,
+    baud: u32,
+    pin: Option<&str>,
+    member: &Member,
+    needs_flash: bool,
+    profile: Option<&str>,
+    trusted_key: &[String],
+    insecure: bool,
+    allow_downgrade: bool,
+) -> Result<Vec<&'static str>> {
+    let mut done = Vec::new();
+    if needs_flash {
+        let board = member
+            .board
+            .as_deref()
+            .and_then(|b| BoardType::from_str(b, true).ok());
+        cmd_flash(
+            board,
+            Some(port),
+            false,
+            None,
+            false,
+            member.firmware.as_deref(),
+            false,
+            false,
+            false,
+            true,
+            allow_downgrade,
+            true,
+        )
+        .await?;
+        done.push("flashed");
+        wait_for_device(port, baud, pin).await?;
+    }
+    if let Some(profile) = profile {
+        apply_bundle(port, baud, pin, profile, trusted_key.to_vec(), insecure).await?;
+        done.push("provisioned");
+    }
+    Ok(done)
+}
+
+/// Wait for a freshly flashed device to answer again.
+async fn wait_for_device(port: &str, baud: u32, pin: Option<&str>) -> Result<()> {
+    let deadline = Instant::now() + REBOOT_WAIT;
+    loop {
+        match connect_with_auth(port, baud, pin).await {
+            Ok(_) => return Ok(()),
+            Err(e) if Instant::now() >= deadline => {
+                return Err(e).context("Device did not come back after flashing");
+            }
+            Err(_) => tokio::time::sleep(Duration::from_secs(1)).await,
+        }
+    }
+}
+
+/// Print rows under `columns`, or as `key=value` lines in plain mode. Rows
+/// of just (device, error) are failures.
+fn print_table(columns: &[&str], rows: &[Row]) {
+    if output::is_plain() {
+        for row in rows {
+            if row.len() != columns.len() {
+                println!("device={} error={}", row[0], row[1..].join(" "));
+                continue;
+            }
+            let fields: Vec<String> = columns
+                .iter()
+                .zip(row)
+                .map(|(column, value)| {
+                    format!("{}={value}", column.to_lowercase().replace(' ', "_"))
+                })
+                .collect();
+            println!("{}", fields.join(" "));
+        }
+        return;
+    }
+
+    let mut widths: Vec<usize> = columns.iter().map(|c| c.chars().count()).collect();
+    for row in rows {
+        // Error rows span the remaining columns
+        if row.len() == columns.len() {
+            for (width, value) in widths.iter_mut().zip(row) {
+                *width = (*width).max(value.chars().count());
+            }
+        } else if let Some(device) = row.first() {
+            widths[0] = widths[0].max(device.chars().count());
+        }
+    }
+    let line = |values: &[String]| {
+        let cells: Vec<String> = values
+            .iter()
+            .zip(&widths)
+            .map(|(value, width)| format!("{value:width$}"))
+            .collect();
+        println!("  {}", cells.join("  ").trim_end());
+    };
+    line(&columns.iter().map(ToString::to_string).collect::<Vec<_>>());
+    line(&widths.iter().map(|w| "-".repeat(*w)).collect::<Vec<_>>());
+    for row in rows {
+        if row.len() == columns.len() {
+            line(row);
+        } else {
+            line(&[row[0].clone(), format!("✗ {}", row[1..].join(" "))]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest_matching() {
+        let manifest: Manifest = toml::from_str(
+            r#"
+            [[devices]]
+            serial = "58:CF:79:0A:12:34"
+            board = "heltec-v3"
+            firmware = "v0.0.5"
+
+            [[devices]]
+            public_key = "A1B2C3D4"
+            "#,
+        )
+        .unwrap();
+        let [by_serial, by_key] = manifest.devices.as_slice() else {
+            panic!("expected two devices");
+        };
+        assert!(by_serial.matches("/dev/ttyUSB0", Some("58:cf:79:0a:12:34"), None));
+        assert!(!by_serial.matches("/dev/ttyUSB0", None, None));
+
+        let mut public_key = [0u8; 32];
+        public_key[..4].copy_from_slice(&[0xa1, 0xb2, 0xc3, 0xd4]);
+        let info = DeviceInfo {
+            name: None,
+            public_key,
+            node_hash: 0,
+            firmware_version: Some("0.0.5".into()),
+            mode: None,
+        };
+        assert!(by_key.matches("/dev/ttyUSB1", None, Some(&info)));
+        assert!(!by_key.matches("/dev/ttyUSB1", None, None));
+        assert_eq!(normalize_version("v0.0.5"), "0.0.5");
+
+        assert!(toml::from_str::<Manifest>("[[devices]]\nfirmwre = \"1\"").is_err());
+
+        let yaml: Manifest = serde_yaml::from_str(
+            "devices:\n  - serial: \"58:CF:79:0A:12:34\"\n    firmware: v0.0.5\n  - public_key: A1B2C3D4\n",
+        )
+        .unwrap();
+        assert_eq!(yaml.devices[0].firmware.as_deref(), Some("v0.0.5"));
+        assert!(yaml.devices[1].matches("/dev/ttyUSB1", None, Some(&info)));
+        assert!(serde_yaml::from_str::<Manifest>("devices:\n  - firmwre: 1\n").is_err());
+    }
+}
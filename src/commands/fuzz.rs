@@ -0,0 +1,407 @@
+//! Protocol fuzzing
+//!
+//! Sends seeded mutations of well-formed command frames and raw packets and
+//! watches the serial line for reboot banners, a dropped connection or a
+//! device that stops answering PING. Every input that triggers one is logged
+//! with its iteration number; the same `--seed` reproduces the whole run.
+
+use super::connect_with_auth;
+use crate::output;
+use crate::packet::{Packet, PayloadType, RouteType};
+use crate::protocol::BANNERS;
+use crate::rng::{self, Rng};
+use crate::serial::{cobs_decode_in_place, SerialPort};
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use indicatif::ProgressBar;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+/// Read-only commands whose frames are mutated. Nothing that changes
+/// settings, so a mutation is unlikely to turn into one.
+const SEED_COMMANDS: [&str; 12] = [
+    "PING",
+    "INFO",
+    "CONFIG",
+    "NEIGHBORS",
+    "STATS",
+    "TELEMETRY",
+    "TIME",
+    "CHANNELS",
+    "AUTH STATUS",
+    "TRACE 0x11",
+    "SEND hello",
+    "CHANNEL SEND 0 hello",
+];
+
+/// How long to wait for an answer to each input.
+const RESPONSE_WAIT: Duration = Duration::from_millis(500);
+
+/// How long to wait for PING when an input got no answer.
+const PING_WAIT: Duration = Duration::from_secs(2);
+
+/// How long a device may take to come back after a failure.
+const RECOVERY_WAIT: Duration = Duration::from_secs(15);
+
+/// One fuzz input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Input {
+    /// A command frame
+    Command(Vec<u8>),
+    /// A raw packet sent with PKT
+    Packet(Vec<u8>),
+}
+
+impl Input {
+    fn kind(&self) -> &'static str {
+        match self {
+            Input::Command(_) => "command",
+            Input::Packet(_) => "packet",
+        }
+    }
+
+    fn bytes(&self) -> &[u8] {
+        match self {
+            Input::Command(b) | Input::Packet(b) => b,
+        }
+    }
+}
+
+/// A well-formed input, mutated.
+fn generate(rng: &mut Rng) -> Input {
+    if rng.below(4) == 0 {
+        let routes = RouteType::value_variants();
+        let types = PayloadType::value_variants();
+        let path_len = rng.below(4);
+        let payload_len = rng.below(64);
+        let packet = Packet {
+            route: routes[rng.below(routes.len())],
+            payload_type: types[rng.below(types.len())],
+            version: 0,
+            transport_codes: Some([0, 0]),
+            path: rng.bytes(path_len),
+            payload: rng.bytes(payload_len),
+        };
+        let bytes = packet.encode().unwrap_or_default();
+        Input::Packet(mutate(rng, bytes))
+    } else {
+        let cmd = SEED_COMMANDS[rng.below(SEED_COMMANDS.len())];
+        Input::Command(mutate(rng, cmd.as_bytes().to_vec()))
+    }
+}
+
+/// Apply one to three random mutations.
+fn mutate(rng: &mut Rng, mut data: Vec<u8>) -> Vec<u8> {
+    const INTERESTING: [u8; 8] = [0x00, 0xff, 0x7f, 0x80, b' ', b'\n', b'%', b'-'];
+    const NUMBERS: [&str; 5] = ["-1", "0", "255", "4294967296", "99999999999999999999"];
+
+    for _ in 0..=rng.below(3) {
+        let at = rng.below(data.len() + 1);
+        match rng.below(8) {
+            0 if !data.is_empty() => {
+                let i = at.min(data.len() - 1);
+                data[i] ^= 1 << rng.below(8);
+            }
+            1 if !data.is_empty() => {
+                let i = at.min(data.len() - 1);
+                data[i] = INTERESTING[rng.below(INTERESTING.len())];
+            }
+            2 => {
+                let len = 1 + rng.below(16);
+                let bytes = rng.bytes(len);
+                data.splice(at..at, bytes);
+            }
+            3 => {
+                let end = (at + 1 + rng.below(8)).min(data.len());
+                data.drain(at.min(end)..end);
+            }
+            4 => data.truncate(at),
+            5 => {
+                // Overlong field
+                let byte = data.get(at).copied().unwrap_or(b'A');
+                let len = 64 + rng.below(512);
+                data.splice(at..at, std::iter::repeat_n(byte, len));
+            }
+            6 => {
+                let number = NUMBERS[rng.below(NUMBERS.len())];
+                data.extend(b" ");
+                data.extend(number.as_bytes());
+            }
+            _ => {
+                let end = (at + rng.below(16)).min(data.len());
+                let copy = data[at.min(end)..end].to_vec();
+                data.splice(end..end, copy);
+            }
+        }
+    }
+    data
+}
+
+/// An input that reset the device or left it unresponsive.
+struct Failure {
+    outcome: &'static str,
+    detail: String,
+    /// What the device printed
+    output: Vec<String>,
+}
+
+/// What the device did after an input.
+#[derive(Default)]
+struct Observed {
+    responded: bool,
+    banner: Option<String>,
+    /// Frames and text seen, for the log
+    output: Vec<String>,
+}
+
+/// Read until a response frame arrives or `wait` passes, checking the raw
+/// stream (boot ROM output isn't framed) and each frame for banners.
+async fn observe(serial: &mut SerialPort, wait: Duration) -> Result<Observed> {
+    let mut seen = Observed::default();
+    let mut pending = Vec::new();
+    let mut buf = [0u8; 1024];
+    let deadline = Instant::now() + wait;
+
+    while !seen.responded {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let Some(n) = serial.read_timeout(&mut buf, remaining).await? else {
+            break;
+        };
+        if n == 0 {
+            bail!("EOF on serial port");
+        }
+        pending.extend_from_slice(&buf[..n]);
+
+        while let Some(pos) = pending.iter().position(|&b| b == 0) {
+            let raw: Vec<u8> = pending.drain(..=pos).collect();
+            let mut frame = raw[..pos].to_vec();
+            let text = match cobs_decode_in_place(&mut frame) {
+                Some(len) => String::from_utf8_lossy(&frame[..len]).into_owned(),
+                // Unframed output (boot ROM) ending at the next frame
+                None => String::from_utf8_lossy(&raw[..pos]).into_owned(),
+            };
+            if seen.banner.is_none() {
+                seen.banner = find_banner(&text);
+            }
+            let debug = serde_json::from_str::<serde_json::Value>(&text)
+                .is_ok_and(|json| json.get("type").and_then(|v| v.as_str()) == Some("debug"));
+            seen.responded |= !debug
+                && ["OK", "ERR", "PONG", "{", "["]
+                    .iter()
+                    .any(|p| text.starts_with(p));
+            seen.output.push(text);
+        }
+        if seen.banner.is_none() {
+            seen.banner = find_banner(&String::from_utf8_lossy(&pending));
+        }
+    }
+    if !pending.is_empty() {
+        seen.output
+            .push(String::from_utf8_lossy(&pending).into_owned());
+    }
+    Ok(seen)
+}
+
+/// The line of `text` holding a crash or boot banner.
+fn find_banner(text: &str) -> Option<String> {
+    text.lines()
+        .find(|line| BANNERS.iter().any(|b| line.contains(b)))
+        .map(|line| line.trim().to_string())
+}
+
+async fn send(serial: &mut SerialPort, input: &Input) -> Result<()> {
+    match input {
+        Input::Command(frame) => serial.write_cobs_frame(frame).await,
+        Input::Packet(packet) => {
+            serial
+                .write(format!("PKT {}\n", packet.len()).as_bytes())
+                .await?;
+            serial.write(packet).await
+        }
+    }
+}
+
+/// Fuzz the device's command parser and packet handling
+pub async fn cmd_fuzz(
+    port: &str,
+    baud: u32,
+    pin: Option<&str>,
+    iterations: usize,
+    seed: Option<u64>,
+    log: Option<&str>,
+) -> Result<()> {
+    let seed = seed.unwrap_or_else(rng::time_seed);
+    let log_path = log.map_or_else(|| format!("fuzz-{seed}.ndjson"), String::from);
+    let plain = output::is_plain();
+    if !plain {
+        println!("Fuzzing {port} with {iterations} inputs (seed {seed})");
+        println!("⚠ Packets are transmitted: use a test device away from a live mesh\n");
+    }
+
+    let mut serial = connect(port, baud, pin).await?;
+    let mut rng = Rng::new(seed);
+    let mut failures = 0;
+
+    let pb = if plain {
+        ProgressBar::hidden()
+    } else {
+        ProgressBar::new(iterations as u64)
+    };
+    pb.set_style(output::progress_style(
+        "  [{bar:40.cyan/blue}] {pos}/{len} {msg}",
+    ));
+
+    // A crash can surface on the input after the one that caused it
+    let mut previous: Option<Input> = None;
+    for iteration in 0..iterations {
+        pb.set_position(iteration as u64);
+        let input = generate(&mut rng);
+
+        let Failure {
+            outcome,
+            detail,
+            output,
+        } = match check(&mut serial, &input).await {
+            Ok(None) => {
+                previous = Some(input);
+                continue;
+            }
+            Ok(Some(failure)) => failure,
+            Err(e) => Failure {
+                outcome: "disconnected",
+                detail: format!("{e:#}"),
+                output: Vec::new(),
+            },
+        };
+        failures += 1;
+        pb.set_message(format!("{failures} failures"));
+        if plain {
+            println!(
+                "iteration={iteration} kind={} outcome={outcome} input={}",
+                input.kind(),
+                hex::encode(input.bytes())
+            );
+        } else {
+            pb.suspend(|| {
+                println!(
+                    "✗ #{iteration} {} {outcome}: {detail}\n    input: {}",
+                    input.kind(),
+                    hex::encode(input.bytes())
+                );
+            });
+        }
+        let record = serde_json::json!({
+            "seed": seed,
+            "iteration": iteration,
+            "kind": input.kind(),
+            "input": hex::encode(input.bytes()),
+            "text": String::from_utf8_lossy(input.bytes()),
+            "outcome": outcome,
+            "detail": detail,
+            "output": output,
+            "previous_input": previous.as_ref().map(|p| hex::encode(p.bytes())),
+        });
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&log_path)?;
+        writeln!(file, "{record}")?;
+
+        serial = recover(port, baud, pin).await.map_err(|e| {
+            anyhow::anyhow!("Device did not recover after iteration {iteration} ({e:#}); inputs logged to {log_path}")
+        })?;
+        previous = None;
+    }
+    pb.finish_and_clear();
+
+    if plain {
+        output::kv("iterations", iterations);
+        output::kv("failures", failures);
+    } else if failures == 0 {
+        println!("✓ {iterations} inputs, no crashes or hangs (seed {seed})");
+    }
+    if failures > 0 {
+        bail!(
+            "{failures} of {iterations} inputs crashed or hung the device (logged to {log_path})"
+        );
+    }
+    Ok(())
+}
+
+/// Send `input` and check the device survived it.
+async fn check(serial: &mut SerialPort, input: &Input) -> Result<Option<Failure>> {
+    send(serial, input).await?;
+    let mut seen = observe(serial, RESPONSE_WAIT).await?;
+    if !seen.responded && seen.banner.is_none() {
+        // Some inputs get no answer; make sure the device still does
+        serial.write_cobs_frame(b"PING").await?;
+        let ping = observe(serial, PING_WAIT).await?;
+        seen.responded = ping.responded;
+        seen.banner = ping.banner;
+        seen.output.extend(ping.output);
+    }
+    Ok(match seen.banner {
+        Some(banner) => Some(Failure {
+            outcome: "reset",
+            detail: banner,
+            output: seen.output,
+        }),
+        None if !seen.responded => Some(Failure {
+            outcome: "unresponsive",
+            detail: format!("no answer to PING within {}s", PING_WAIT.as_secs()),
+            output: seen.output,
+        }),
+        None => None,
+    })
+}
+
+async fn connect(port: &str, baud: u32, pin: Option<&str>) -> Result<SerialPort> {
+    let mut serial = connect_with_auth(port, baud, pin)
+        .await?
+        .into_protocol()
+        .into_port();
+    serial.clear().await?;
+    Ok(serial)
+}
+
+/// Reconnect once the device answers PING again after a failure.
+async fn recover(port: &str, baud: u32, pin: Option<&str>) -> Result<SerialPort> {
+    let start = Instant::now();
+    loop {
+        let attempt = async {
+            let mut serial = connect(port, baud, pin).await?;
+            serial.write_cobs_frame(b"PING").await?;
+            if !observe(&mut serial, PING_WAIT).await?.responded {
+                bail!("no answer to PING");
+            }
+            Ok(serial)
+        };
+        match attempt.await {
+            Ok(serial) => return Ok(serial),
+            Err(e) if start.elapsed() > RECOVERY_WAIT => return Err(e),
+            Err(_) => tokio::time::sleep(Duration::from_millis(500)).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate_is_reproducible() {
+        let run = |seed| {
+            let mut rng = Rng::new(seed);
+            (0..200).map(|_| generate(&mut rng)).collect::<Vec<_>>()
+        };
+        assert_eq!(run(7), run(7));
+        assert_ne!(run(7), run(8));
+        assert!(run(7).iter().any(|i| matches!(i, Input::Packet(_))));
+
+        assert_eq!(
+            find_banner("x\nets Jun  8 2016 00:22:57\r\nrst:0x1"),
+            Some("ets Jun  8 2016 00:22:57".into())
+        );
+        assert_eq!(find_banner("OK"), None);
+    }
+}
@@ -0,0 +1,308 @@
+//! GPS: host passthrough and receiver diagnostics
+//!
+//! `gps feed` reads NMEA from a receiver on the host (a serial port or
+//! gpsd) and stores each fix on the device, so a node without GPS
+//! advertises where it actually is. `gps status` shows what the device's
+//! own receiver sees.
+
+use super::connect_with_auth;
+use super::position::set_position;
+use crate::cli::GpsAction;
+use crate::error::CliError;
+use crate::geo;
+use crate::nmea::{self, Fix};
+use crate::output;
+use crate::protocol::GpsStatus;
+use crate::timefmt;
+use anyhow::{bail, Context, Result};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio_serial::SerialPortBuilderExt;
+
+const GPSD_PORT: u16 = 2947;
+
+/// Movement below this is treated as GPS jitter.
+const MIN_MOVE_M: f64 = 10.0;
+
+/// A stationary position is still re-sent this often.
+const RESEND: Duration = Duration::from_secs(600);
+
+/// Where fixes come from.
+#[derive(Debug, PartialEq)]
+enum Source {
+    /// Serial port of a receiver
+    Serial(String),
+    /// gpsd `host:port`
+    Gpsd(String),
+}
+
+impl Source {
+    /// `gpsd://host[:port]` or a serial port path.
+    fn parse(source: &str) -> Self {
+        match source.strip_prefix("gpsd://") {
+            Some(rest) => {
+                let host = match rest.trim_end_matches('/') {
+                    "" => "localhost",
+                    host => host,
+                };
+                if host.contains(':') {
+                    Self::Gpsd(host.to_string())
+                } else {
+                    Self::Gpsd(format!("{host}:{GPSD_PORT}"))
+                }
+            }
+            None => Self::Serial(source.to_string()),
+        }
+    }
+
+    async fn open(&self, gps_baud: u32) -> Result<Box<dyn AsyncBufRead + Unpin + Send>> {
+        match self {
+            Self::Serial(path) => {
+                let port = tokio_serial::new(path, gps_baud)
+                    .open_native_async()
+                    .map_err(|e| {
+                        CliError::PortNotFound(format!("Failed to open GPS receiver {path}: {e}"))
+                    })?;
+                Ok(Box::new(BufReader::new(port)))
+            }
+            Self::Gpsd(addr) => {
+                let mut stream = tokio::net::TcpStream::connect(addr).await.map_err(|e| {
+                    CliError::ConnectionFailed(format!("Failed to connect to gpsd at {addr}: {e}"))
+                })?;
+                // Ask for the receiver's raw NMEA rather than gpsd's JSON reports
+                stream
+                    .write_all(b"?WATCH={\"enable\":true,\"nmea\":true}\n")
+                    .await
+                    .context("Failed to start gpsd watch")?;
+                Ok(Box::new(BufReader::new(stream)))
+            }
+        }
+    }
+}
+
+/// Feed host GPS fixes to the device, or show its receiver status
+pub async fn cmd_gps(port: &str, baud: u32, pin: Option<&str>, action: GpsAction) -> Result<()> {
+    match action {
+        GpsAction::Status { watch } => {
+            let mut proto = connect_with_auth(port, baud, pin).await?.into_protocol();
+            loop {
+                let status = proto.get_gps_status().await?;
+                if output::is_plain() {
+                    for (key, value) in output::flatten_json(&serde_json::to_value(&status)?) {
+                        output::kv(&key, value);
+                    }
+                } else {
+                    if watch {
+                        print!("\x1B[2J\x1B[1;1H"); // ANSI clear screen
+                    }
+                    print_status(&status);
+                }
+                if !watch {
+                    return Ok(());
+                }
+                if output::is_plain() {
+                    println!();
+                }
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+        GpsAction::Feed {
+            source,
+            gps_baud,
+            interval,
+        } => {
+            let mut gps = Source::parse(&source).open(gps_baud).await?;
+            let mut proto = connect_with_auth(port, baud, pin).await?.into_protocol();
+            let interval = Duration::from_secs(interval.max(1));
+
+            if !output::is_plain() {
+                println!(
+                    "Feeding GPS fixes from {source} to the device every {}s (Ctrl+C to stop)...",
+                    interval.as_secs()
+                );
+                println!("Waiting for a fix...");
+            }
+            let mut latest: Option<Fix> = None;
+            let mut sent: Option<(Fix, Instant)> = None;
+            let mut line = Vec::new();
+            loop {
+                line.clear();
+                if gps.read_until(b'\n', &mut line).await? == 0 {
+                    bail!(CliError::ConnectionFailed(format!(
+                        "GPS source {source} closed"
+                    )));
+                }
+                let Some(fix) = nmea::parse(&String::from_utf8_lossy(&line)) else {
+                    continue;
+                };
+                // RMC carries no altitude; keep the last one from GGA
+                let fix = Fix {
+                    alt: fix.alt.or(latest.and_then(|l| l.alt)),
+                    satellites: fix.satellites.or(latest.and_then(|l| l.satellites)),
+                    ..fix
+                };
+                latest = Some(fix);
+
+                let due = sent.as_ref().is_none_or(|(last, at)| {
+                    at.elapsed() >= RESEND
+                        || (at.elapsed() >= interval
+                            && geo::distance_m((last.lat, last.lon), (fix.lat, fix.lon))
+                                >= MIN_MOVE_M)
+                });
+                if !due {
+                    continue;
+                }
+                let timestamp = timefmt::clock();
+                match set_position(&mut proto, fix.lat, fix.lon, fix.alt).await {
+                    Ok(()) => {
+                        sent = Some((fix, Instant::now()));
+                        if output::is_plain() {
+                            continue;
+                        }
+                        print!("[{timestamp}] ✓ {:.6}, {:.6}", fix.lat, fix.lon);
+                        match (fix.alt, fix.satellites) {
+                            (Some(alt), Some(sats)) => println!(" ({alt:.0} m, {sats} satellites)"),
+                            (Some(alt), None) => println!(" ({alt:.0} m)"),
+                            (None, Some(sats)) => println!(" ({sats} satellites)"),
+                            (None, None) => println!(),
+                        }
+                    }
+                    Err(e) => eprintln!("[{timestamp}] ✗ {e:#}"),
+                }
+            }
+        }
+    }
+}
+
+fn print_status(status: &GpsStatus) {
+    println!("GPS Status");
+    println!("==========\n");
+
+    let fix = match status.fix_type {
+        0 | 1 => "none",
+        2 => "2D",
+        _ => "3D",
+    };
+    match status.hdop {
+        Some(hdop) if status.fix_type >= 2 => {
+            println!(
+                "Fix:         {fix} (HDOP {hdop:.1}, {})",
+                hdop_quality(hdop)
+            );
+        }
+        _ => println!("Fix:         {fix}"),
+    }
+    println!(
+        "Satellites:  {} used / {} in view",
+        status.satellites_used, status.satellites_in_view
+    );
+    for constellation in &status.constellations {
+        println!(
+            "  {:10} {}/{}",
+            constellation.name, constellation.used, constellation.in_view
+        );
+    }
+    match (status.ttff_secs, status.on_secs) {
+        (Some(ttff), _) => println!(
+            "First fix:   {} after power-on",
+            timefmt::duration(ttff.into())
+        ),
+        (None, Some(on)) => println!(
+            "First fix:   still searching ({} so far)",
+            timefmt::duration(on.into())
+        ),
+        (None, None) => println!("First fix:   still searching"),
+    }
+
+    if let Some(hint) = diagnose(status) {
+        println!("\n⚠ {hint}");
+    }
+}
+
+/// Rule-of-thumb rating of horizontal dilution of precision.
+fn hdop_quality(hdop: f32) -> &'static str {
+    match hdop {
+        h if h <= 1.0 => "ideal",
+        h if h <= 2.0 => "excellent",
+        h if h <= 5.0 => "good",
+        h if h <= 10.0 => "moderate",
+        h if h <= 20.0 => "fair",
+        _ => "poor",
+    }
+}
+
+/// Likely reason a receiver has no fix yet.
+fn diagnose(status: &GpsStatus) -> Option<&'static str> {
+    // A cold start commonly takes up to a minute outdoors
+    const COLD_START_SECS: u32 = 60;
+
+    if status.fix_type >= 2 {
+        return None;
+    }
+    let searching = status.on_secs.unwrap_or(u32::MAX);
+    if status.satellites_in_view == 0 {
+        (searching >= COLD_START_SECS).then_some(
+            "No satellites in view: check the antenna connection and move outdoors \
+             or next to a window",
+        )
+    } else if status.satellites_used < 4 {
+        (searching >= COLD_START_SECS).then_some(
+            "Satellites are visible but too weak for a fix (4 are needed), \
+             typical indoors; give the antenna a clear view of the sky",
+        )
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_source() {
+        assert_eq!(
+            Source::parse("gpsd://"),
+            Source::Gpsd("localhost:2947".into())
+        );
+        assert_eq!(
+            Source::parse("gpsd://pi.local:3000"),
+            Source::Gpsd("pi.local:3000".into())
+        );
+        assert_eq!(
+            Source::parse("/dev/ttyGPS"),
+            Source::Serial("/dev/ttyGPS".into())
+        );
+    }
+
+    #[test]
+    fn test_diagnose() {
+        let status: GpsStatus = serde_json::from_str(
+            r#"{"fix_type":0,"satellites_in_view":6,"satellites_used":2,"on_secs":300,
+                "constellations":[{"name":"GPS","in_view":4,"used":2}]}"#,
+        )
+        .unwrap();
+        assert!(diagnose(&status).unwrap().contains("too weak"));
+        assert_eq!(
+            diagnose(&GpsStatus {
+                on_secs: Some(20),
+                ..status.clone()
+            }),
+            None
+        );
+        assert!(diagnose(&GpsStatus {
+            satellites_in_view: 0,
+            ..status.clone()
+        })
+        .unwrap()
+        .contains("No satellites"));
+        assert_eq!(
+            diagnose(&GpsStatus {
+                fix_type: 3,
+                ..status
+            }),
+            None
+        );
+        assert_eq!(hdop_quality(1.4), "excellent");
+    }
+}
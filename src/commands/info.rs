@@ -1,9 +1,47 @@
 //! Device information commands
 
+use super::collector::{CsvLog, Rotation};
 use super::connect_with_auth;
+use super::contacts::{remember_neighbors, resolve_node};
+use super::position::own_position;
+use crate::cli::{NeighborColumn, NeighborSort, NeighborsFormat};
+use crate::device::NeighborInfo;
+use crate::error::CliError;
+use crate::geo;
+use crate::history::Recorder;
+use crate::outln;
+use crate::output;
 use crate::protocol::{Protocol, Response};
 use crate::serial::SerialPort;
+use crate::settings::Settings;
+use crate::timefmt;
 use anyhow::{bail, Result};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// `info --output json|yaml`.
+#[derive(Serialize)]
+struct InfoView {
+    name: Option<String>,
+    mode: Option<String>,
+    public_key: String,
+    node_hash: String,
+    firmware: Option<String>,
+    radio: RadioView,
+}
+
+#[derive(Serialize)]
+struct RadioView {
+    freq_mhz: f32,
+    tx_power_dbm: i8,
+    bandwidth_khz: u32,
+    spreading_factor: u8,
+    coding_rate: String,
+    preamble_len: u16,
+}
 
 /// Show device information and configuration
 pub async fn cmd_info(port: &str, baud: u32, pin: Option<&str>) -> Result<()> {
@@ -11,6 +49,24 @@ pub async fn cmd_info(port: &str, baud: u32, pin: Option<&str>) -> Result<()> {
     let info = dev.get_info().await?;
     let config = dev.get_config().await?;
 
+    if output::is_structured() {
+        return output::print(&InfoView {
+            name: info.name,
+            mode: info.mode,
+            public_key: hex::encode(info.public_key),
+            node_hash: format!("0x{:02x}", info.node_hash),
+            firmware: info.firmware_version,
+            radio: RadioView {
+                freq_mhz: config.freq_mhz,
+                tx_power_dbm: config.tx_power_dbm,
+                bandwidth_khz: config.bandwidth_khz,
+                spreading_factor: config.spreading_factor,
+                coding_rate: format!("4/{}", config.coding_rate),
+                preamble_len: config.preamble_len,
+            },
+        });
+    }
+
     println!("Device Information:");
     println!(
         "  Name:       {}",
@@ -38,333 +94,721 @@ pub async fn cmd_info(port: &str, baud: u32, pin: Option<&str>) -> Result<()> {
     Ok(())
 }
 
-/// Show device statistics
-#[allow(clippy::too_many_lines)]
-pub async fn cmd_stats(port: &str, baud: u32, pin: Option<&str>) -> Result<()> {
+/// Packet counters in `STATS`, with their labels.
+const PACKET_COUNTERS: [(&str, &str); 5] = [
+    ("rx", "RX"),
+    ("tx", "TX"),
+    ("fwd", "FWD"),
+    ("dropped", "DROP"),
+    ("duplicates", "DUP"),
+];
+
+/// Change in the packet counters between two `STATS` samples.
+#[derive(Debug, Serialize)]
+struct PacketDelta {
+    interval_secs: f64,
+    #[serde(flatten)]
+    counters: BTreeMap<&'static str, u64>,
+}
+
+impl PacketDelta {
+    fn between(
+        previous: &serde_json::Value,
+        current: &serde_json::Value,
+        interval: Duration,
+    ) -> Self {
+        let count = |json: &serde_json::Value, key| {
+            json.pointer(&format!("/packets/{key}"))
+                .and_then(serde_json::Value::as_u64)
+                .unwrap_or(0)
+        };
+        let counters = PACKET_COUNTERS
+            .iter()
+            .map(|&(key, _)| {
+                let (before, now) = (count(previous, key), count(current, key));
+                // A counter that went down was reset by a reboot
+                let change = if now >= before { now - before } else { now };
+                (key, change)
+            })
+            .collect();
+        Self {
+            interval_secs: interval.as_secs_f64(),
+            counters,
+        }
+    }
+
+    /// `(+12, 2.4/min)` for counter `key`.
+    fn describe(&self, key: &str) -> String {
+        let change = self.counters.get(key).copied().unwrap_or(0);
+        let per_min = change as f64 * 60.0 / self.interval_secs.max(f64::EPSILON);
+        format!("(+{change}, {per_min:.1}/min)")
+    }
+}
+
+/// Columns of `stats --log`.
+const STATS_COLUMNS: [&str; 10] = [
+    "timestamp",
+    "rx",
+    "tx",
+    "fwd",
+    "dropped",
+    "duplicates",
+    "heap_free_kb",
+    "battery_percent",
+    "battery_mv",
+    "uptime_secs",
+];
+
+/// A `stats --log` row for a `STATS` sample; fields the device doesn't
+/// report are left empty.
+fn stats_row(json: &serde_json::Value) -> Vec<String> {
+    let field = |pointer: &str| {
+        json.pointer(pointer)
+            .map(|v| v.to_string().trim_matches('"').to_string())
+            .unwrap_or_default()
+    };
+    vec![
+        chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        field("/packets/rx"),
+        field("/packets/tx"),
+        field("/packets/fwd"),
+        field("/packets/dropped"),
+        field("/packets/duplicates"),
+        field("/memory/heap_free_kb"),
+        field("/power/battery_pct"),
+        field("/power/battery_mv"),
+        field("/firmware/uptime_secs"),
+    ]
+}
+
+/// Show device statistics, once or every `interval` seconds with `watch`.
+/// `delta` adds the change in packet counters since the previous sample
+/// (taking two samples `interval` apart without `watch`). With `log`, a row
+/// of counters goes to that CSV file every `interval` until stopped.
+#[allow(clippy::too_many_arguments)]
+pub async fn cmd_stats(
+    port: &str,
+    baud: u32,
+    pin: Option<&str>,
+    json_lines: bool,
+    watch: bool,
+    interval: u64,
+    delta: bool,
+    log: Option<&str>,
+    log_max_mb: u64,
+    log_keep: usize,
+) -> Result<()> {
+    let mut csv = match log {
+        Some(path) => Some(CsvLog::rotating(
+            PathBuf::from(path),
+            &STATS_COLUMNS,
+            Rotation {
+                max_bytes: log_max_mb * 1024 * 1024,
+                keep: log_keep,
+            },
+        )?),
+        None => None,
+    };
     let dev = connect_with_auth(port, baud, pin).await?;
     let mut proto = dev.into_protocol();
+    let interval = Duration::from_secs(interval);
+    // Logging alone only reports where the rows go
+    let show = watch || log.is_none();
+    if let (Some(path), false) = (log, show) {
+        println!(
+            "Logging stats to {path} every {} (Ctrl+C to stop)",
+            timefmt::duration(interval.as_secs())
+        );
+    }
 
-    // Request stats from device
-    match proto.command("STATS").await? {
-        Response::Json(json) => {
-            // Format stats nicely
-            println!("╔══════════════════════════════════════════╗");
-            println!("║        MESHGRID PERFORMANCE STATS        ║");
-            println!("╚══════════════════════════════════════════╝");
-
-            // Hardware
-            if let Some(hw) = json.get("hardware") {
-                println!("\n📟 Hardware:");
-                if let Some(board) = hw.get("board").and_then(|v| v.as_str()) {
-                    println!("  Board:  {board}");
-                }
-                if let Some(chip) = hw.get("chip").and_then(|v| v.as_str()) {
-                    let mhz = hw
-                        .get("cpu_mhz")
-                        .and_then(serde_json::Value::as_u64)
-                        .unwrap_or(0);
-                    let cores = hw
-                        .get("cores")
-                        .and_then(serde_json::Value::as_u64)
-                        .unwrap_or(0);
-                    println!("  CPU:    {chip} @ {mhz} MHz ({cores} cores)");
-                }
-            }
+    let mut previous = None;
+    if delta && !watch {
+        previous = Some((Instant::now(), request_stats(&mut proto).await?));
+        tokio::time::sleep(interval).await;
+    }
+    loop {
+        let json = request_stats(&mut proto).await?;
+        let now = Instant::now();
+        let change = match (&previous, delta) {
+            (Some((at, before)), true) => Some(PacketDelta::between(before, &json, now - *at)),
+            _ => None,
+        };
+        if let Some(csv) = &mut csv {
+            csv.append(&stats_row(&json));
+        }
 
-            // Memory
-            if let Some(mem) = json.get("memory") {
-                println!("\n💾 Memory:");
-                let ram_used = mem
-                    .get("ram_used_kb")
-                    .and_then(serde_json::Value::as_u64)
-                    .unwrap_or(0);
-                let ram_total = mem
-                    .get("ram_total_kb")
-                    .and_then(serde_json::Value::as_u64)
-                    .unwrap_or(0);
-                let ram_pct = if ram_total > 0 {
-                    (ram_used * 100) / ram_total
-                } else {
-                    0
-                };
-                println!("  RAM:    {ram_used} / {ram_total} KB ({ram_pct}%)");
-
-                if let Some(heap) = mem.get("heap_free_kb").and_then(serde_json::Value::as_u64) {
-                    println!("  Heap:   {heap} KB free");
-                }
-
-                let flash_used = mem
-                    .get("flash_used_kb")
-                    .and_then(serde_json::Value::as_u64)
-                    .unwrap_or(0);
-                let flash_total = mem
-                    .get("flash_total_kb")
-                    .and_then(serde_json::Value::as_u64)
-                    .unwrap_or(0);
-                let flash_pct = if flash_total > 0 {
-                    (flash_used * 100) / flash_total
-                } else {
-                    0
-                };
-                println!("  Flash:  {flash_used} / {flash_total} KB ({flash_pct}%)");
-            }
+        if show {
+            print_sample(&json, change.as_ref(), json_lines, watch, interval)?;
+        }
 
-            // Packets
-            if let Some(packets) = json.get("packets") {
-                println!("\n📡 Packets:");
-                println!(
-                    "  RX:     {}",
-                    packets
-                        .get("rx")
-                        .and_then(serde_json::Value::as_u64)
-                        .unwrap_or(0)
-                );
-                println!(
-                    "  TX:     {}",
-                    packets
-                        .get("tx")
-                        .and_then(serde_json::Value::as_u64)
-                        .unwrap_or(0)
-                );
-                println!(
-                    "  FWD:    {}",
-                    packets
-                        .get("fwd")
-                        .and_then(serde_json::Value::as_u64)
-                        .unwrap_or(0)
-                );
-                println!(
-                    "  DROP:   {}",
-                    packets
-                        .get("dropped")
-                        .and_then(serde_json::Value::as_u64)
-                        .unwrap_or(0)
-                );
-                println!(
-                    "  DUP:    {}",
-                    packets
-                        .get("duplicates")
-                        .and_then(serde_json::Value::as_u64)
-                        .unwrap_or(0)
-                );
-            }
+        if !watch && csv.is_none() {
+            return Ok(());
+        }
+        previous = Some((now, json));
+        tokio::time::sleep(interval).await;
+    }
+}
 
-            // Neighbors
-            if let Some(neighbors) = json.get("neighbors") {
-                let total = neighbors
-                    .get("total")
-                    .and_then(serde_json::Value::as_u64)
-                    .unwrap_or(0);
-                let clients = neighbors
-                    .get("clients")
-                    .and_then(serde_json::Value::as_u64)
-                    .unwrap_or(0);
-                let repeaters = neighbors
-                    .get("repeaters")
-                    .and_then(serde_json::Value::as_u64)
-                    .unwrap_or(0);
-                let rooms = neighbors
-                    .get("rooms")
-                    .and_then(serde_json::Value::as_u64)
-                    .unwrap_or(0);
-                println!("\n🔗 Neighbors: {total}");
-                if total > 0 {
-                    println!("  Clients:   {clients}");
-                    println!("  Repeaters: {repeaters}");
-                    println!("  Rooms:     {rooms}");
-                }
+/// Print one `stats` sample in the selected format.
+fn print_sample(
+    json: &serde_json::Value,
+    change: Option<&PacketDelta>,
+    json_lines: bool,
+    watch: bool,
+    interval: Duration,
+) -> Result<()> {
+    if json_lines || output::is_structured() || output::is_plain() {
+        let mut sample = json.clone();
+        if let Some(object) = sample.as_object_mut() {
+            if watch {
+                object.insert("timestamp".into(), chrono::Utc::now().to_rfc3339().into());
             }
-
-            // Radio
-            if let Some(radio) = json.get("radio") {
-                println!("\n📻 Radio:");
-                if let Some(freq) = radio.get("freq_mhz").and_then(serde_json::Value::as_f64) {
-                    println!("  Freq:   {freq:.2} MHz");
-                }
-                if let Some(bw) = radio
-                    .get("bandwidth_khz")
-                    .and_then(serde_json::Value::as_f64)
-                {
-                    println!("  BW:     {bw:.1} kHz");
-                }
-                if let Some(sf) = radio
-                    .get("spreading_factor")
-                    .and_then(serde_json::Value::as_u64)
-                {
-                    println!("  SF:     {sf}");
-                }
-                if let Some(power) = radio
-                    .get("tx_power_dbm")
-                    .and_then(serde_json::Value::as_i64)
-                {
-                    println!("  Power:  {power} dBm");
-                }
+            if let Some(change) = change {
+                object.insert("delta".into(), serde_json::to_value(change)?);
             }
-
-            // Power
-            if let Some(power) = json.get("power") {
-                println!("\n🔋 Power:");
-                let pct = power
-                    .get("battery_pct")
-                    .and_then(serde_json::Value::as_u64)
-                    .unwrap_or(0);
-                let mv = power
-                    .get("battery_mv")
-                    .and_then(serde_json::Value::as_u64)
-                    .unwrap_or(0);
-                let voltage = f64::from(u32::try_from(mv).unwrap_or(0)) / 1000.0;
-                println!("  Battery:  {pct}% ({voltage:.2}V)");
-
-                let usb = power
-                    .get("usb_power")
-                    .and_then(serde_json::Value::as_bool)
-                    .unwrap_or(false);
-                let charging = power
-                    .get("charging")
-                    .and_then(serde_json::Value::as_bool)
-                    .unwrap_or(false);
-                let sleep = power
-                    .get("sleep_enabled")
-                    .and_then(serde_json::Value::as_bool)
-                    .unwrap_or(false);
-
-                println!("  USB:      {}", if usb { "Yes" } else { "No" });
-                println!("  Charging: {}", if charging { "Yes" } else { "No" });
-                println!("  Sleep:    {}", if sleep { "Enabled" } else { "Disabled" });
+        }
+        if json_lines {
+            println!("{sample}");
+        } else if output::is_plain() {
+            output::print_json_kv(&sample);
+            if watch {
+                println!();
             }
+        } else {
+            output::print(&sample)?;
+        }
+    } else {
+        if watch {
+            print!("\x1B[2J\x1B[1;1H"); // ANSI clear screen
+        }
+        print_stats(json, change);
+        if watch {
+            outln!(
+                "Updated {}, every {} (Ctrl+C to stop)",
+                timefmt::clock(),
+                timefmt::duration(interval.as_secs())
+            );
+        }
+    }
+    Ok(())
+}
 
-            // Features
-            if let Some(features) = json.get("features") {
-                println!("\n⚡ Optimizations:");
-                if features
-                    .get("hw_aes")
-                    .and_then(serde_json::Value::as_bool)
-                    .unwrap_or(false)
-                {
-                    println!("  ✓ Hardware AES-128");
-                } else {
-                    println!("  ✗ Hardware AES-128 (software)");
-                }
-                if features
-                    .get("hw_sha256")
-                    .and_then(serde_json::Value::as_bool)
-                    .unwrap_or(false)
-                {
-                    println!("  ✓ Hardware SHA-256");
-                } else {
-                    println!("  ✗ Hardware SHA-256 (software)");
-                }
-                if features
-                    .get("priority_scheduling")
-                    .and_then(serde_json::Value::as_bool)
-                    .unwrap_or(false)
-                {
-                    println!("  ✓ Priority Scheduling");
-                }
-                if features
-                    .get("airtime_budget")
-                    .and_then(serde_json::Value::as_bool)
-                    .unwrap_or(false)
-                {
-                    println!("  ✓ Airtime Budget (33%)");
-                }
-                if let Some(queue_size) = features
-                    .get("tx_queue_size")
-                    .and_then(serde_json::Value::as_u64)
-                {
-                    println!("  ✓ TX Queue ({queue_size} slots)");
-                }
-                if features
-                    .get("secret_caching")
-                    .and_then(serde_json::Value::as_bool)
-                    .unwrap_or(false)
-                {
-                    println!("  ✓ Shared Secret Caching");
-                }
-            }
+/// The device's `STATS` JSON.
+async fn request_stats(proto: &mut Protocol) -> Result<serde_json::Value> {
+    match proto.command("STATS").await? {
+        Response::Json(json) => Ok(json),
+        Response::Error(e) => bail!(CliError::Device(e)),
+        Response::Ok(_) => bail!("Unexpected OK response to STATS (expected JSON)"),
+    }
+}
 
-            // Firmware
-            if let Some(fw) = json.get("firmware") {
-                println!("\n🔧 Firmware:");
-                if let Some(ver) = fw.get("version").and_then(|v| v.as_str()) {
-                    println!("  Version: {ver}");
-                }
-                if let Some(mode) = fw.get("mode").and_then(|v| v.as_str()) {
-                    println!("  Mode:    {mode}");
-                }
-                if let Some(uptime) = fw.get("uptime_secs").and_then(serde_json::Value::as_u64) {
-                    let hours = uptime / 3600;
-                    let mins = (uptime % 3600) / 60;
-                    let secs = uptime % 60;
-                    if hours > 0 {
-                        println!("  Uptime:  {hours}h {mins}m {secs}s");
-                    } else if mins > 0 {
-                        println!("  Uptime:  {mins}m {secs}s");
-                    } else {
-                        println!("  Uptime:  {secs}s");
-                    }
-                }
-            }
+/// `STATS` as decorated sections, with packet counter changes if given.
+#[allow(clippy::too_many_lines)]
+fn print_stats(json: &serde_json::Value, delta: Option<&PacketDelta>) {
+    // Format stats nicely
+    outln!("╔══════════════════════════════════════════╗");
+    outln!("║        MESHGRID PERFORMANCE STATS        ║");
+    outln!("╚══════════════════════════════════════════╝");
+
+    // Hardware
+    if let Some(hw) = json.get("hardware") {
+        outln!("\n📟 Hardware:");
+        if let Some(board) = hw.get("board").and_then(|v| v.as_str()) {
+            outln!("  Board:  {board}");
+        }
+        if let Some(chip) = hw.get("chip").and_then(|v| v.as_str()) {
+            let mhz = hw
+                .get("cpu_mhz")
+                .and_then(serde_json::Value::as_u64)
+                .unwrap_or(0);
+            let cores = hw
+                .get("cores")
+                .and_then(serde_json::Value::as_u64)
+                .unwrap_or(0);
+            outln!("  CPU:    {chip} @ {mhz} MHz ({cores} cores)");
+        }
+    }
 
-            // Temperature
-            if let Some(temp) = json.get("temperature") {
-                if let Some(cpu_temp) = temp.get("cpu_c").and_then(serde_json::Value::as_f64) {
-                    println!("\n🌡️  CPU Temp: {cpu_temp:.1}°C");
-                }
-            }
+    // Memory
+    if let Some(mem) = json.get("memory") {
+        outln!("\n💾 Memory:");
+        let ram_used = mem
+            .get("ram_used_kb")
+            .and_then(serde_json::Value::as_u64)
+            .unwrap_or(0);
+        let ram_total = mem
+            .get("ram_total_kb")
+            .and_then(serde_json::Value::as_u64)
+            .unwrap_or(0);
+        let ram_pct = (ram_used * 100).checked_div(ram_total).unwrap_or(0);
+        outln!("  RAM:    {ram_used} / {ram_total} KB ({ram_pct}%)");
+
+        if let Some(heap) = mem.get("heap_free_kb").and_then(serde_json::Value::as_u64) {
+            outln!("  Heap:   {heap} KB free");
+        }
 
-            println!();
+        let flash_used = mem
+            .get("flash_used_kb")
+            .and_then(serde_json::Value::as_u64)
+            .unwrap_or(0);
+        let flash_total = mem
+            .get("flash_total_kb")
+            .and_then(serde_json::Value::as_u64)
+            .unwrap_or(0);
+        let flash_pct = (flash_used * 100).checked_div(flash_total).unwrap_or(0);
+        outln!("  Flash:  {flash_used} / {flash_total} KB ({flash_pct}%)");
+    }
+
+    // Packets
+    if let Some(packets) = json.get("packets") {
+        outln!("\n📡 Packets:");
+        for (key, label) in PACKET_COUNTERS {
+            let count = packets
+                .get(key)
+                .and_then(serde_json::Value::as_u64)
+                .unwrap_or(0);
+            let change = match delta {
+                Some(delta) => format!("  {}", delta.describe(key)),
+                None => String::new(),
+            };
+            outln!("  {:<7} {count}{change}", format!("{label}:"));
         }
-        Response::Error(e) => bail!("Device error: {e}"),
-        Response::Ok(data) => {
-            eprintln!("DEBUG: Got OK response: {data:?}");
-            bail!("Unexpected OK response to STATS (expected JSON)")
+    }
+
+    // Neighbors
+    if let Some(neighbors) = json.get("neighbors") {
+        let total = neighbors
+            .get("total")
+            .and_then(serde_json::Value::as_u64)
+            .unwrap_or(0);
+        let clients = neighbors
+            .get("clients")
+            .and_then(serde_json::Value::as_u64)
+            .unwrap_or(0);
+        let repeaters = neighbors
+            .get("repeaters")
+            .and_then(serde_json::Value::as_u64)
+            .unwrap_or(0);
+        let rooms = neighbors
+            .get("rooms")
+            .and_then(serde_json::Value::as_u64)
+            .unwrap_or(0);
+        outln!("\n🔗 Neighbors: {total}");
+        if total > 0 {
+            outln!("  Clients:   {clients}");
+            outln!("  Repeaters: {repeaters}");
+            outln!("  Rooms:     {rooms}");
         }
     }
 
-    Ok(())
+    // Radio
+    if let Some(radio) = json.get("radio") {
+        outln!("\n📻 Radio:");
+        if let Some(freq) = radio.get("freq_mhz").and_then(serde_json::Value::as_f64) {
+            outln!("  Freq:   {freq:.2} MHz");
+        }
+        if let Some(bw) = radio
+            .get("bandwidth_khz")
+            .and_then(serde_json::Value::as_f64)
+        {
+            outln!("  BW:     {bw:.1} kHz");
+        }
+        if let Some(sf) = radio
+            .get("spreading_factor")
+            .and_then(serde_json::Value::as_u64)
+        {
+            outln!("  SF:     {sf}");
+        }
+        if let Some(power) = radio
+            .get("tx_power_dbm")
+            .and_then(serde_json::Value::as_i64)
+        {
+            outln!("  Power:  {power} dBm");
+        }
+    }
+
+    // Power
+    if let Some(power) = json.get("power") {
+        outln!("\n🔋 Power:");
+        let pct = power
+            .get("battery_pct")
+            .and_then(serde_json::Value::as_u64)
+            .unwrap_or(0);
+        let mv = power
+            .get("battery_mv")
+            .and_then(serde_json::Value::as_u64)
+            .unwrap_or(0);
+        let voltage = f64::from(u32::try_from(mv).unwrap_or(0)) / 1000.0;
+        outln!("  Battery:  {pct}% ({voltage:.2}V)");
+
+        let usb = power
+            .get("usb_power")
+            .and_then(serde_json::Value::as_bool)
+            .unwrap_or(false);
+        let charging = power
+            .get("charging")
+            .and_then(serde_json::Value::as_bool)
+            .unwrap_or(false);
+        let sleep = power
+            .get("sleep_enabled")
+            .and_then(serde_json::Value::as_bool)
+            .unwrap_or(false);
+
+        outln!("  USB:      {}", if usb { "Yes" } else { "No" });
+        outln!("  Charging: {}", if charging { "Yes" } else { "No" });
+        outln!("  Sleep:    {}", if sleep { "Enabled" } else { "Disabled" });
+    }
+
+    // Features
+    if let Some(features) = json.get("features") {
+        outln!("\n⚡ Optimizations:");
+        if features
+            .get("hw_aes")
+            .and_then(serde_json::Value::as_bool)
+            .unwrap_or(false)
+        {
+            outln!("  ✓ Hardware AES-128");
+        } else {
+            outln!("  ✗ Hardware AES-128 (software)");
+        }
+        if features
+            .get("hw_sha256")
+            .and_then(serde_json::Value::as_bool)
+            .unwrap_or(false)
+        {
+            outln!("  ✓ Hardware SHA-256");
+        } else {
+            outln!("  ✗ Hardware SHA-256 (software)");
+        }
+        if features
+            .get("priority_scheduling")
+            .and_then(serde_json::Value::as_bool)
+            .unwrap_or(false)
+        {
+            outln!("  ✓ Priority Scheduling");
+        }
+        if features
+            .get("airtime_budget")
+            .and_then(serde_json::Value::as_bool)
+            .unwrap_or(false)
+        {
+            outln!("  ✓ Airtime Budget (33%)");
+        }
+        if let Some(queue_size) = features
+            .get("tx_queue_size")
+            .and_then(serde_json::Value::as_u64)
+        {
+            outln!("  ✓ TX Queue ({queue_size} slots)");
+        }
+        if features
+            .get("secret_caching")
+            .and_then(serde_json::Value::as_bool)
+            .unwrap_or(false)
+        {
+            outln!("  ✓ Shared Secret Caching");
+        }
+    }
+
+    // Firmware
+    if let Some(fw) = json.get("firmware") {
+        outln!("\n🔧 Firmware:");
+        if let Some(ver) = fw.get("version").and_then(|v| v.as_str()) {
+            outln!("  Version: {ver}");
+        }
+        if let Some(mode) = fw.get("mode").and_then(|v| v.as_str()) {
+            outln!("  Mode:    {mode}");
+        }
+        if let Some(uptime) = fw.get("uptime_secs").and_then(serde_json::Value::as_u64) {
+            outln!("  Uptime:  {}", timefmt::duration(uptime));
+        }
+    }
+
+    // Temperature
+    if let Some(temp) = json.get("temperature") {
+        if let Some(cpu_temp) = temp.get("cpu_c").and_then(serde_json::Value::as_f64) {
+            outln!("\n🌡️  CPU Temp: {cpu_temp:.1}°C");
+        }
+    }
+
+    outln!();
 }
 
 /// Show neighbor table
-pub async fn cmd_neighbors(port: &str, baud: u32, pin: Option<&str>) -> Result<()> {
-    let mut dev = connect_with_auth(port, baud, pin).await?;
-    let neighbors = dev.get_neighbors().await?;
-
-    if neighbors.is_empty() {
+pub async fn cmd_neighbors(
+    port: &str,
+    baud: u32,
+    pin: Option<&str>,
+    format: NeighborsFormat,
+    sort: Option<NeighborSort>,
+    columns: &[NeighborColumn],
+) -> Result<()> {
+    let mut proto = connect_with_auth(port, baud, pin).await?.into_protocol();
+    let reported = proto.get_neighbors().await?;
+    remember_neighbors(&mut proto, port, &reported).await;
+    let mut neighbors: Vec<NeighborInfo> = reported.into_iter().map(NeighborInfo::from).collect();
+    // `--output json|yaml` prints the same rows as `--format json`
+    let format = if output::is_structured() {
+        NeighborsFormat::Json
+    } else {
+        format
+    };
+
+    if neighbors.is_empty() && format == NeighborsFormat::Table {
         println!("No neighbors discovered yet.");
         return Ok(());
     }
 
-    println!("Neighbor Table ({} nodes):\n", neighbors.len());
-    println!(
-        "  {:8} {:4} {:16} {:6} {:6} {:12} {:8}",
-        "Hash", "Ver", "Name", "RSSI", "SNR", "Firmware", "Last Seen"
-    );
-    println!(
-        "  {:-<8} {:-<4} {:-<16} {:-<6} {:-<6} {:-<12} {:-<8}",
-        "", "", "", "", "", "", ""
-    );
+    // Distances only when the device and some neighbors have a position
+    let wants_distance = columns.is_empty() || columns.contains(&NeighborColumn::Distance);
+    let own = if wants_distance && neighbors.iter().any(|n| n.lat.is_some()) {
+        own_position(&mut proto)
+            .await
+            .unwrap_or_else(|e| {
+                tracing::debug!("Failed to read own position: {e:#}");
+                None
+            })
+            .map(|(lat, lon, _)| (lat, lon))
+    } else {
+        None
+    };
+
+    match sort {
+        Some(NeighborSort::Rssi) => neighbors.sort_by_key(|n| std::cmp::Reverse(n.rssi)),
+        Some(NeighborSort::Lastseen) => neighbors.sort_by_key(|n| n.last_seen_secs),
+        Some(NeighborSort::Name) => {
+            neighbors
+                .sort_by_key(|n| (n.name.is_none(), n.name.as_ref().map(|n| n.to_lowercase())));
+        }
+        None => {}
+    }
 
-    for n in neighbors {
-        let name = n.name.unwrap_or_else(|| "?".into());
-        let firmware = n.firmware.unwrap_or_else(|| "unknown".into());
-        println!(
-            "  0x{:02x}     v{:<3} {:16} {:6} {:6} {:12} {}s ago",
-            n.node_hash, n.protocol_version, name, n.rssi, n.snr, firmware, n.last_seen_secs
-        );
+    let columns = if !columns.is_empty() {
+        columns.to_vec()
+    } else if format == NeighborsFormat::Table {
+        use NeighborColumn::*;
+        let mut columns = vec![Hash, Version, Name, Rssi, Snr, Fw, Distance, Lastseen];
+        columns.retain(|c| *c != Distance || own.is_some());
+        columns
+    } else {
+        NeighborColumn::value_variants().to_vec()
+    };
+    let distance = |n: &NeighborInfo| {
+        let pos = geo::valid(n.lat?, n.lon?)?;
+        Some(geo::distance_m(own?, pos))
+    };
+
+    match format {
+        NeighborsFormat::Json => {
+            let rows: Vec<NeighborRow> = neighbors
+                .iter()
+                .map(|n| {
+                    NeighborRow(
+                        columns
+                            .iter()
+                            .map(|c| (column_key(*c), neighbor_value(n, *c, distance(n))))
+                            .collect(),
+                    )
+                })
+                .collect();
+            output::print(&rows)?;
+        }
+        NeighborsFormat::Csv => {
+            let header: Vec<String> = columns.iter().map(|c| column_key(*c)).collect();
+            println!("{}", header.join(","));
+            for n in &neighbors {
+                let row: Vec<String> = columns
+                    .iter()
+                    .map(|c| match neighbor_value(n, *c, distance(n)) {
+                        serde_json::Value::Null => String::new(),
+                        serde_json::Value::String(s) => output::csv_field(&s),
+                        value => value.to_string(),
+                    })
+                    .collect();
+                println!("{}", row.join(","));
+            }
+        }
+        NeighborsFormat::Table => {
+            let header: Vec<&str> = columns.iter().map(|c| column_title(*c)).collect();
+            let rows: Vec<Vec<String>> = neighbors
+                .iter()
+                .map(|n| {
+                    columns
+                        .iter()
+                        .map(|c| neighbor_text(n, *c, distance(n)))
+                        .collect()
+                })
+                .collect();
+            // Wide enough for the longest value, so names aren't cut off
+            let widths: Vec<usize> = header
+                .iter()
+                .enumerate()
+                .map(|(i, title)| {
+                    rows.iter()
+                        .map(|row| row[i].chars().count())
+                        .chain([title.len()])
+                        .max()
+                        .unwrap_or_default()
+                })
+                .collect();
+            let line = |cells: Vec<String>| {
+                let padded: Vec<String> = cells
+                    .iter()
+                    .zip(&widths)
+                    .map(|(cell, width)| format!("{cell:width$}"))
+                    .collect();
+                format!("  {}", padded.join(" ").trim_end())
+            };
+
+            println!("Neighbor Table ({} nodes):\n", neighbors.len());
+            println!("{}", line(header.iter().map(ToString::to_string).collect()));
+            println!("{}", line(widths.iter().map(|w| "-".repeat(*w)).collect()));
+            for row in rows {
+                println!("{}", line(row));
+            }
+        }
     }
 
     Ok(())
 }
 
+/// A JSON neighbor object, with its keys in column order.
+struct NeighborRow(Vec<(String, serde_json::Value)>);
+
+impl serde::Serialize for NeighborRow {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (key, value) in &self.0 {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
+
+/// Name of a column in `--columns`, JSON keys and the CSV header.
+fn column_key(column: NeighborColumn) -> String {
+    column
+        .to_possible_value()
+        .map(|v| v.get_name().to_string())
+        .unwrap_or_default()
+}
+
+fn column_title(column: NeighborColumn) -> &'static str {
+    match column {
+        NeighborColumn::Hash => "Hash",
+        NeighborColumn::Version => "Ver",
+        NeighborColumn::Name => "Name",
+        NeighborColumn::Rssi => "RSSI",
+        NeighborColumn::Snr => "SNR",
+        NeighborColumn::Fw => "Firmware",
+        NeighborColumn::Distance => "Dist",
+        NeighborColumn::Lastseen => "Last Seen",
+        NeighborColumn::Lat => "Lat",
+        NeighborColumn::Lon => "Lon",
+    }
+}
+
+/// A neighbor's value in `column` for JSON and CSV.
+fn neighbor_value(
+    n: &NeighborInfo,
+    column: NeighborColumn,
+    distance_m: Option<f64>,
+) -> serde_json::Value {
+    use serde_json::json;
+    match column {
+        NeighborColumn::Hash => json!(format!("0x{:02x}", n.node_hash)),
+        NeighborColumn::Version => json!(n.protocol_version),
+        NeighborColumn::Name => json!(n.name),
+        NeighborColumn::Rssi => json!(n.rssi),
+        NeighborColumn::Snr => json!(n.snr),
+        NeighborColumn::Fw => json!(n.firmware),
+        NeighborColumn::Distance => json!(distance_m.map(|d| d.round() as i64)),
+        NeighborColumn::Lastseen => json!(n.last_seen_secs),
+        NeighborColumn::Lat => json!(n.lat),
+        NeighborColumn::Lon => json!(n.lon),
+    }
+}
+
+/// A neighbor's value in `column` for the table.
+fn neighbor_text(n: &NeighborInfo, column: NeighborColumn, distance_m: Option<f64>) -> String {
+    match column {
+        NeighborColumn::Hash => format!("0x{:02x}", n.node_hash),
+        NeighborColumn::Version => format!("v{}", n.protocol_version),
+        NeighborColumn::Name => n.name.clone().unwrap_or_else(|| "?".into()),
+        NeighborColumn::Rssi => n.rssi.to_string(),
+        NeighborColumn::Snr => n.snr.to_string(),
+        NeighborColumn::Fw => n.firmware.clone().unwrap_or_else(|| "unknown".into()),
+        NeighborColumn::Distance => distance_m.map_or_else(|| "-".into(), geo::format_distance),
+        NeighborColumn::Lastseen => timefmt::ago(n.last_seen_secs.into()),
+        NeighborColumn::Lat => n.lat.map_or_else(|| "-".into(), |lat| format!("{lat:.5}")),
+        NeighborColumn::Lon => n.lon.map_or_else(|| "-".into(), |lon| format!("{lon:.5}")),
+    }
+}
+
+/// Ask a node over the mesh for its name, mode, firmware and status
+pub async fn cmd_nodeinfo(
+    port: &str,
+    baud: u32,
+    pin: Option<&str>,
+    node: &str,
+    timeout: u64,
+    refresh: bool,
+) -> Result<()> {
+    let mut proto = connect_with_auth(port, baud, pin).await?.into_protocol();
+    let (target, label) = match resolve_node(&mut proto, port, node, refresh).await {
+        Some(found) => (format!("0x{:02x}", found.node_hash), found.label()),
+        None => (node.to_string(), node.to_string()),
+    };
+    if !output::is_plain() {
+        println!("Requesting node info from {label}...");
+    }
+    let info = proto
+        .request_node_info(&target, Duration::from_secs(timeout))
+        .await?;
+
+    if output::is_plain() {
+        output::print_json_kv(&serde_json::to_value(&info)?);
+        return Ok(());
+    }
+    let unknown = || "unknown".to_string();
+    println!("\n{label}:");
+    println!("  Name:     {}", info.name.unwrap_or_else(unknown));
+    println!("  Mode:     {}", info.mode.unwrap_or_else(unknown));
+    println!("  Firmware: {}", info.firmware.unwrap_or_else(unknown));
+    let battery = match (info.battery_percent, info.battery_mv) {
+        (Some(percent), Some(mv)) => format!("{percent}% ({:.2} V)", f64::from(mv) / 1000.0),
+        (Some(percent), None) => format!("{percent}%"),
+        (None, Some(mv)) => format!("{:.2} V", f64::from(mv) / 1000.0),
+        (None, None) => unknown(),
+    };
+    println!("  Battery:  {battery}");
+    println!(
+        "  Uptime:   {}",
+        info.uptime_secs.map_or_else(unknown, timefmt::duration)
+    );
+    Ok(())
+}
+
 /// Show telemetry data
 pub async fn cmd_telemetry(port: &str, baud: u32, watch: bool) -> Result<()> {
     let serial_port = SerialPort::open(port, baud).await?;
     let mut proto = Protocol::new(serial_port);
+    let mut history = Recorder::new(&Settings::load().unwrap_or_default());
 
     loop {
         // Request telemetry from device
         let telem = proto.get_telemetry().await?;
+        history.record_telemetry(&telem);
+
+        if output::is_plain() {
+            print_telemetry_kv(&telem);
+            if !watch {
+                break;
+            }
+            println!();
+            tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+            continue;
+        }
 
         // Clear screen in watch mode
         if watch {
@@ -382,7 +826,7 @@ pub async fn cmd_telemetry(port: &str, baud: u32, watch: bool) -> Result<()> {
             );
             println!("Charging:    {}", if dev.charging { "Yes" } else { "No" });
             println!("USB Power:   {}", if dev.usb_power { "Yes" } else { "No" });
-            println!("Uptime:      {}s", dev.uptime_secs);
+            println!("Uptime:      {}", timefmt::duration(dev.uptime_secs.into()));
             println!("Free Heap:   {} bytes", dev.free_heap);
             println!("CPU Temp:    {:.1}°C", dev.cpu_temp_celsius());
             println!();
@@ -421,3 +865,56 @@ pub async fn cmd_telemetry(port: &str, baud: u32, watch: bool) -> Result<()> {
 
     Ok(())
 }
+
+/// Print telemetry as `key=value` lines
+fn print_telemetry_kv(telem: &crate::protocol::Telemetry) {
+    if let Some(dev) = &telem.device {
+        output::kv("battery_percent", dev.battery_percent);
+        output::kv("battery_voltage", format!("{:.2}", dev.voltage()));
+        output::kv("charging", dev.charging);
+        output::kv("usb_power", dev.usb_power);
+        output::kv("uptime_secs", dev.uptime_secs);
+        output::kv("free_heap", dev.free_heap);
+        output::kv("cpu_temp_c", format!("{:.1}", dev.cpu_temp_celsius()));
+    }
+
+    if let Some(env) = &telem.environment {
+        output::kv("temperature_c", format!("{:.1}", env.temperature_celsius()));
+        output::kv("humidity_percent", format!("{:.1}", env.humidity_percent()));
+        output::kv("pressure_hpa", format!("{:.1}", env.pressure_hpa()));
+        output::kv("air_quality", env.air_quality);
+    }
+
+    if let Some(loc) = &telem.location {
+        output::kv("gps_fix", loc.has_fix());
+        if loc.has_fix() {
+            output::kv("latitude", format!("{:.6}", loc.latitude()));
+            output::kv("longitude", format!("{:.6}", loc.longitude()));
+            output::kv("altitude_m", format!("{:.1}", loc.altitude_meters()));
+            output::kv("speed_m_s", format!("{:.1}", loc.speed_m_s()));
+            output::kv("heading_deg", format!("{:.0}", loc.heading_degrees()));
+            output::kv("satellites", loc.satellites);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packet_delta() {
+        let before = serde_json::json!({"packets": {"rx": 100, "tx": 40, "fwd": 7}});
+        let after = serde_json::json!({"packets": {"rx": 130, "tx": 42, "fwd": 3}});
+        let delta = PacketDelta::between(&before, &after, Duration::from_secs(30));
+        assert_eq!(delta.counters["rx"], 30);
+        assert_eq!(delta.describe("rx"), "(+30, 60.0/min)");
+        // Reset by a reboot: counted from zero
+        assert_eq!(delta.counters["fwd"], 3);
+        assert_eq!(delta.counters["dropped"], 0);
+
+        let json = serde_json::to_value(&delta).unwrap();
+        assert_eq!(json["interval_secs"], 30.0);
+        assert_eq!(json["tx"], 2);
+    }
+}
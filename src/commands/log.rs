@@ -0,0 +1,372 @@
+//! Device log: show, export, follow, forward, clear and log levels
+//!
+//! Log lines are stamped with device uptime, or with the device's clock
+//! (RTC) on firmware that keeps one. Both are rewritten into host-local
+//! time: uptime from the boot time implied by the uptime the device reports,
+//! RTC stamps corrected by the device clock's offset from the host clock.
+//! JSON output keeps the raw values next to the converted time.
+
+use super::connect_with_auth;
+use super::contacts::resolve_node;
+use super::timeline::boot_time;
+use crate::cli::LogAction;
+use crate::confirm::{confirm, Operation, Target};
+use crate::history;
+use crate::logfwd::{Record, Sink};
+use crate::output;
+use crate::protocol::{LogLine, Protocol, Response};
+use crate::timefmt;
+use anyhow::{Context, Result};
+use chrono::{
+    DateTime, Duration as ChronoDuration, Local, NaiveDateTime, SecondsFormat, TimeZone, Utc,
+};
+use clap::ValueEnum;
+use std::fmt::Write as _;
+use std::time::Duration;
+
+/// Maps device timestamps to host time.
+#[derive(Debug, Default, Clone, Copy)]
+struct Clock {
+    /// When the device booted, in host time
+    boot: Option<DateTime<Utc>>,
+    /// Host clock minus device clock
+    skew: Option<ChronoDuration>,
+}
+
+impl Clock {
+    /// Correlate the device's uptime and clock with the host clock.
+    async fn query(proto: &mut Protocol) -> Result<Self> {
+        let boot = boot_time(proto).await?;
+        let asked = Utc::now();
+        let skew = device_time(proto)
+            .await
+            .map(|device| asked + (Utc::now() - asked) / 2 - device);
+        Ok(Self { boot, skew })
+    }
+
+    /// Host-local time of a line, if its stamp can be placed.
+    fn time(&self, line: &LogLine) -> Option<DateTime<Local>> {
+        let time = match (line.rtc_ms, line.uptime_ms) {
+            (Some(rtc), _) => DateTime::from_timestamp_millis(rtc)? + self.skew.unwrap_or_default(),
+            (None, Some(uptime)) => {
+                self.boot? + ChronoDuration::milliseconds(i64::try_from(uptime).ok()?)
+            }
+            (None, None) => return None,
+        };
+        Some(time.with_timezone(&Local))
+    }
+}
+
+/// The device's clock (`TIME`), if it is set.
+async fn device_time(proto: &mut Protocol) -> Option<DateTime<Utc>> {
+    match proto.command("TIME").await {
+        Ok(Response::Ok(Some(text))) => parse_device_time(&text),
+        Ok(_) => None,
+        Err(e) => {
+            tracing::debug!("No device time: {e:#}");
+            None
+        }
+    }
+}
+
+/// Device time as unix seconds, RFC 3339, or `YYYY-MM-DD HH:MM:SS` in host
+/// local time (what `time sync` sets).
+fn parse_device_time(text: &str) -> Option<DateTime<Utc>> {
+    let text = text.trim();
+    if let Ok(secs) = text.parse::<i64>() {
+        return DateTime::from_timestamp(secs, 0);
+    }
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Some(time.with_timezone(&Utc));
+    }
+    let start = text.len().checked_sub(19)?;
+    let naive = NaiveDateTime::parse_from_str(text.get(start..)?, "%Y-%m-%d %H:%M:%S").ok()?;
+    Some(
+        Local
+            .from_local_datetime(&naive)
+            .earliest()?
+            .with_timezone(&Utc),
+    )
+}
+
+/// Export, show, follow or forward the device's log, or set its level
+pub async fn cmd_log(port: &str, baud: u32, pin: Option<&str>, action: LogAction) -> Result<()> {
+    match action {
+        LogAction::Export {
+            output: path,
+            since,
+            json,
+        } => {
+            let since = since.as_deref().map(history::parse_since).transpose()?;
+            let mut proto = connect_with_auth(port, baud, pin).await?.into_protocol();
+            let clock = Clock::query(&mut proto).await?;
+            let log = proto.get_log().await?;
+
+            let mut text = String::new();
+            let mut written = 0;
+            for line in &log.lines {
+                let time = clock.time(line);
+                if since.is_some_and(|since| time.is_some_and(|t| t < since)) {
+                    continue;
+                }
+                writeln!(text, "{}", render_line(line, time, json))?;
+                written += 1;
+            }
+            std::fs::write(&path, text).with_context(|| format!("Failed to write {path}"))?;
+
+            if output::is_plain() {
+                output::kv("output", &path);
+                output::kv("lines", written);
+                output::kv("dropped", log.dropped);
+                return Ok(());
+            }
+            println!("✓ Wrote {written} log lines to {path}");
+            if log.dropped > 0 {
+                println!(
+                    "⚠ {} lines were overwritten on the device while downloading",
+                    log.dropped
+                );
+            }
+            if clock.boot.is_none() {
+                println!("⚠ Device didn't report its uptime; lines are stamped with uptime only");
+            }
+        }
+        LogAction::Show {
+            node,
+            lines,
+            timeout,
+            retries,
+            refresh,
+            json,
+        } => {
+            let mut proto = connect_with_auth(port, baud, pin).await?.into_protocol();
+            let (log, clock) = match node {
+                Some(node) => {
+                    let (target, label) = match resolve_node(&mut proto, port, &node, refresh).await
+                    {
+                        Some(found) => (format!("0x{:02x}", found.node_hash), found.label()),
+                        None => (node.clone(), node.clone()),
+                    };
+                    if !output::is_plain() {
+                        eprintln!("Requesting the log of {label}...");
+                    }
+                    let log = proto
+                        .get_remote_log(&target, Duration::from_secs(timeout), retries)
+                        .await?;
+                    // Mesh latency aside, the node's uptime when it sent the last page
+                    let boot = log.uptime_ms.and_then(|ms| {
+                        Some(Utc::now() - ChronoDuration::milliseconds(i64::try_from(ms).ok()?))
+                    });
+                    (log, Clock { boot, skew: None })
+                }
+                None => {
+                    let clock = Clock::query(&mut proto).await?;
+                    (proto.get_log().await?, clock)
+                }
+            };
+
+            let skip = lines.map_or(0, |n| log.lines.len().saturating_sub(n));
+            for line in &log.lines[skip..] {
+                println!("{}", render_line(line, clock.time(line), json));
+            }
+            if log.dropped > 0 && !output::is_plain() {
+                eprintln!(
+                    "⚠ {} lines were overwritten on the device while downloading",
+                    log.dropped
+                );
+            }
+        }
+        LogAction::Follow { json } => {
+            let mut proto = connect_with_auth(port, baud, pin).await?.into_protocol();
+            let clock = Clock::query(&mut proto).await?;
+            proto.enter_monitor_mode().await?;
+            if !output::is_plain() && !json {
+                eprintln!("Following the device log (Ctrl+C to stop)...");
+            }
+            loop {
+                let Some(frame) = proto.read_frame(Duration::from_secs(1)).await? else {
+                    continue;
+                };
+                if let Some(debug) = debug_frame(&frame) {
+                    let line = LogLine::from_json(&debug);
+                    println!("{}", render_line(&line, clock.time(&line), json));
+                }
+            }
+        }
+        LogAction::Clear => {
+            let mut dev = connect_with_auth(port, baud, pin).await?;
+            let info = dev.get_info().await?;
+            let target = Target::new(Some(port), info.name.as_deref(), Some(&info.public_key));
+            if !confirm(Operation::LogClear, &target)? {
+                return Ok(());
+            }
+            dev.into_protocol().clear_log().await?;
+            if output::is_plain() {
+                output::kv("cleared", "log");
+            } else {
+                println!("✓ Device log cleared");
+            }
+        }
+        LogAction::Level {
+            level: None,
+            module: _,
+        } => {
+            let mut proto = connect_with_auth(port, baud, pin).await?.into_protocol();
+            let levels = proto.get_log_levels().await?;
+            if output::is_plain() {
+                output::print_json_kv(&serde_json::to_value(&levels)?);
+                return Ok(());
+            }
+            println!("Log level: {}", levels.level);
+            for (module, level) in &levels.modules {
+                println!("  {module:<8} {level}");
+            }
+        }
+        LogAction::Level {
+            level: Some(level),
+            module,
+        } => {
+            let level = value_name(level);
+            let module = module.map(value_name);
+            let mut proto = connect_with_auth(port, baud, pin).await?.into_protocol();
+            proto.set_log_level(&level, module.as_deref()).await?;
+            if !output::is_plain() {
+                match module {
+                    Some(module) => println!("✓ Log level of {module} set to {level}"),
+                    None => println!("✓ Log level set to {level}"),
+                }
+            }
+        }
+        // clap requires --journald when --syslog is missing
+        LogAction::Forward { syslog, .. } => {
+            let (mut sink, target) = match syslog {
+                Some(spec) => (Sink::syslog(&spec).await?, spec),
+                None => (Sink::journald()?, "journald".to_string()),
+            };
+            let mut proto = connect_with_auth(port, baud, pin).await?.into_protocol();
+            let info = proto.get_info().await?;
+            let node = info
+                .name
+                .unwrap_or_else(|| format!("0x{:02x}", info.node_hash));
+            let clock = Clock::query(&mut proto).await?;
+            proto.enter_monitor_mode().await?;
+            if !output::is_plain() {
+                println!("Forwarding the log of {node} to {target} (Ctrl+C to stop)...");
+            }
+
+            loop {
+                let Some(frame) = proto.read_frame(Duration::from_secs(1)).await? else {
+                    continue;
+                };
+                let Some(debug) = debug_frame(&frame) else {
+                    continue;
+                };
+                let field = |name: &str| debug.get(name).and_then(|v| v.as_str()).map(String::from);
+                let line = LogLine::from_json(&debug);
+                let record = Record {
+                    time: clock
+                        .time(&line)
+                        .map_or_else(Utc::now, |t| t.with_timezone(&Utc)),
+                    node: node.clone(),
+                    node_hash: info.node_hash,
+                    level: line.level,
+                    subsystem: field("subsystem").or_else(|| field("tag")),
+                    msg: line.msg,
+                };
+                if let Err(e) = sink.send(&record).await {
+                    eprintln!("✗ Failed to forward a log line: {e:#}");
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A monitor frame carrying a live log line (`{"type":"debug",...}`).
+fn debug_frame(frame: &str) -> Option<serde_json::Value> {
+    let json: serde_json::Value = serde_json::from_str(frame).ok()?;
+    (json.get("type").and_then(|v| v.as_str()) == Some("debug")).then_some(json)
+}
+
+/// Command-line name of a value, as the firmware expects it.
+fn value_name(value: impl ValueEnum) -> String {
+    value
+        .to_possible_value()
+        .map_or_else(String::new, |v| v.get_name().to_string())
+}
+
+fn render_line(line: &LogLine, time: Option<DateTime<Local>>, json: bool) -> String {
+    if json {
+        serde_json::json!({
+            "time": time.map(|t| t.to_rfc3339_opts(SecondsFormat::Millis, false)),
+            "seq": line.seq,
+            "uptime_ms": line.uptime_ms,
+            "rtc_ms": line.rtc_ms,
+            "level": line.level,
+            "msg": line.msg,
+        })
+        .to_string()
+    } else {
+        format_line(line, time)
+    }
+}
+
+/// `<time> <LEVEL> <message>`, with device uptime (`+12.345s`) when the host
+/// time is unknown.
+fn format_line(line: &LogLine, time: Option<DateTime<Local>>) -> String {
+    let stamp = match (time, line.uptime_ms) {
+        (Some(time), _) => timefmt::precise(&time),
+        (None, Some(ms)) => format!("+{}.{:03}s", ms / 1000, ms % 1000),
+        (None, None) => "-".to_string(),
+    };
+    let level = line.level.as_deref().unwrap_or("-");
+    format!("{stamp} {level:<5} {}", line.msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clock() {
+        let at = |s| DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc);
+        let clock = Clock {
+            boot: Some(at("2026-01-12T15:00:00Z")),
+            // The device clock is 90s behind
+            skew: Some(ChronoDuration::seconds(90)),
+        };
+        let line = LogLine {
+            seq: Some(7),
+            uptime_ms: Some(61_005),
+            rtc_ms: None,
+            level: Some("WARN".into()),
+            msg: "low battery".into(),
+        };
+        let time = clock.time(&line).unwrap();
+        assert_eq!(time, at("2026-01-12T15:01:01.005Z"));
+        assert_eq!(
+            format_line(&line, Some(time)),
+            format!(
+                "{} WARN  low battery",
+                time.to_rfc3339_opts(SecondsFormat::Millis, false)
+            )
+        );
+        assert_eq!(format_line(&line, None), "+61.005s WARN  low battery");
+
+        // RTC stamps win over uptime and are corrected for the skew
+        let stamped = LogLine {
+            rtc_ms: Some(at("2026-01-12T16:00:00Z").timestamp_millis()),
+            ..line
+        };
+        assert_eq!(clock.time(&stamped).unwrap(), at("2026-01-12T16:01:30Z"));
+        let json: serde_json::Value =
+            serde_json::from_str(&render_line(&stamped, None, true)).unwrap();
+        assert_eq!(json["uptime_ms"], 61_005);
+
+        assert_eq!(
+            parse_device_time("1768230000"),
+            Some(at("2026-01-12T15:00:00Z"))
+        );
+        assert!(parse_device_time("Device time not set").is_none());
+    }
+}
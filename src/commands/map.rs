@@ -0,0 +1,334 @@
+//! Live node map
+//!
+//! `map` serves a Leaflet page plotting every node with a known position:
+//! the device itself (from its telemetry), neighbors (from their adverts) and
+//! nodes in the neighbor cache. The page loads `/nodes.json` and then follows
+//! `/ws`, which pushes each new or moved node as a JSON object.
+
+use super::connect_with_auth;
+use super::serve::listen_addr;
+use crate::geo::valid;
+use crate::http::{self, Response};
+use crate::nodes::{self, CachedNode};
+use crate::protocol::{MonitorEvent, NeighborInfo, Protocol};
+use crate::websocket::{self, Message};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, mpsc};
+
+const PAGE: &str = include_str!("map.html");
+
+/// How often the neighbor table and own position are re-read.
+const REFRESH: Duration = Duration::from_secs(60);
+
+/// Shortest gap between refreshes triggered by adverts.
+const ADVERT_REFRESH: Duration = Duration::from_secs(5);
+
+/// A node on the map.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct Marker {
+    id: String,
+    name: String,
+    lat: f64,
+    lon: f64,
+    /// `self`, `advert` or `contacts`
+    source: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rssi: Option<i16>,
+    /// RFC 3339 time the position was last reported
+    updated: String,
+}
+
+impl Marker {
+    fn from_neighbor(neighbor: &NeighborInfo) -> Option<Self> {
+        let (lat, lon) = valid(neighbor.lat?, neighbor.lon?)?;
+        let id = neighbor
+            .public_key
+            .map_or_else(|| format!("{:02x}", neighbor.node_hash), hex::encode);
+        Some(Self {
+            id,
+            name: neighbor
+                .name
+                .clone()
+                .unwrap_or_else(|| format!("0x{:02x}", neighbor.node_hash)),
+            lat,
+            lon,
+            source: "advert",
+            rssi: Some(neighbor.rssi),
+            updated: now(),
+        })
+    }
+
+    fn from_cached(node: &CachedNode) -> Option<Self> {
+        let (lat, lon) = valid(node.lat?, node.lon?)?;
+        Some(Self {
+            id: node
+                .public_key
+                .clone()
+                .unwrap_or_else(|| format!("{:02x}", node.node_hash)),
+            name: node.label(),
+            lat,
+            lon,
+            source: "contacts",
+            rssi: None,
+            updated: node.last_seen.clone(),
+        })
+    }
+}
+
+fn now() -> String {
+    chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+}
+
+/// Markers by id, and the updates pushed to WebSocket clients.
+struct MapState {
+    markers: Mutex<BTreeMap<String, Marker>>,
+    updates: broadcast::Sender<String>,
+    page: String,
+}
+
+impl MapState {
+    /// Add or move a marker, notifying clients if anything changed.
+    fn update(&self, marker: Marker) {
+        let mut markers = self.markers.lock().unwrap();
+        let changed = markers.get(&marker.id).is_none_or(|old| {
+            (old.lat, old.lon, &old.name, old.source)
+                != (marker.lat, marker.lon, &marker.name, marker.source)
+        });
+        if changed {
+            if let Ok(json) = serde_json::to_string(&marker) {
+                // No clients is fine
+                let _ = self.updates.send(json);
+            }
+        }
+        markers.insert(marker.id.clone(), marker);
+    }
+
+    fn snapshot(&self) -> Vec<Marker> {
+        self.markers.lock().unwrap().values().cloned().collect()
+    }
+}
+
+/// Serve a live map of node positions
+pub async fn cmd_map(
+    port: &str,
+    baud: u32,
+    pin: Option<&str>,
+    listen: &str,
+    tiles: &str,
+) -> Result<()> {
+    let addr = listen_addr(listen);
+    let listener = TcpListener::bind(&addr)
+        .await
+        .with_context(|| format!("Failed to listen on {addr}"))?;
+    let local = listener.local_addr()?;
+
+    let mut proto = connect_with_auth(port, baud, pin).await?.into_protocol();
+    let info = proto.get_info().await?;
+    let device_key = hex::encode(info.public_key);
+
+    let state = Arc::new(MapState {
+        markers: Mutex::new(BTreeMap::new()),
+        updates: broadcast::channel(256).0,
+        page: PAGE.replace("\"{{TILES}}\"", &serde_json::to_string(tiles)?),
+    });
+    match nodes::nodes(&device_key) {
+        Ok(cached) => cached
+            .iter()
+            .filter_map(Marker::from_cached)
+            .for_each(|marker| state.update(marker)),
+        Err(e) => tracing::debug!("Skipping neighbor cache: {e:#}"),
+    }
+
+    let host = if local.ip().is_unspecified() {
+        format!("localhost:{}", local.port())
+    } else {
+        local.to_string()
+    };
+    println!("Serving the node map on http://{host}/ (Ctrl+C to stop)...");
+    let server = tokio::spawn(serve(listener, Arc::clone(&state)));
+
+    let result = follow_device(&mut proto, port, &device_key, info.name.as_deref(), &state).await;
+    server.abort();
+    result
+}
+
+/// Keep the markers current: re-read positions periodically and soon
+/// after adverts, which may carry new ones.
+async fn follow_device(
+    proto: &mut Protocol,
+    port: &str,
+    device_key: &str,
+    name: Option<&str>,
+    state: &MapState,
+) -> Result<()> {
+    let mut last_refresh: Option<Instant> = None;
+    let mut refresh_due = true;
+    loop {
+        let since = last_refresh.map_or(REFRESH, |t| t.elapsed());
+        if since >= REFRESH || (refresh_due && since >= ADVERT_REFRESH) {
+            refresh(proto, port, device_key, name, state).await;
+            last_refresh = Some(Instant::now());
+            refresh_due = false;
+            proto.enter_monitor_mode().await?;
+        }
+
+        if let Some(MonitorEvent::Advertisement { .. }) = proto.read_event().await? {
+            refresh_due = true;
+        }
+    }
+}
+
+async fn refresh(
+    proto: &mut Protocol,
+    port: &str,
+    device_key: &str,
+    name: Option<&str>,
+    state: &MapState,
+) {
+    match proto.get_telemetry().await {
+        Ok(telemetry) => {
+            let own = telemetry
+                .location
+                .filter(|l| l.has_fix())
+                .and_then(|l| valid(l.latitude(), l.longitude()));
+            if let Some((lat, lon)) = own {
+                state.update(Marker {
+                    id: device_key.to_string(),
+                    name: name.unwrap_or("this device").to_string(),
+                    lat,
+                    lon,
+                    source: "self",
+                    rssi: None,
+                    updated: now(),
+                });
+            }
+        }
+        Err(e) => tracing::debug!("Failed to read own position: {e:#}"),
+    }
+
+    match proto.get_neighbors().await {
+        Ok(neighbors) => {
+            if let Err(e) = nodes::store(port, device_key, &neighbors) {
+                tracing::debug!("Failed to update neighbor cache: {e:#}");
+            }
+            for marker in neighbors.iter().filter_map(Marker::from_neighbor) {
+                state.update(marker);
+            }
+        }
+        Err(e) => tracing::debug!("Failed to read neighbors: {e:#}"),
+    }
+}
+
+async fn serve(listener: TcpListener, state: Arc<MapState>) -> Result<()> {
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let state = Arc::clone(&state);
+        tokio::spawn(async move {
+            if let Err(e) = handle(stream, &state).await {
+                tracing::debug!("Map client {peer}: {e:#}");
+            }
+        });
+    }
+}
+
+async fn handle(mut stream: TcpStream, state: &MapState) -> Result<()> {
+    let request = http::read_request(&mut stream).await?;
+    let response = match request.path.as_str() {
+        "/ws" => {
+            let key = request
+                .header("sec-websocket-key")
+                .context("Not a WebSocket upgrade")?
+                .to_string();
+            return push_updates(stream, &key, state).await;
+        }
+        "/" => Response::new(200, "text/html; charset=utf-8", state.page.clone()),
+        "/nodes.json" => Response::json(200, &serde_json::to_value(state.snapshot())?),
+        _ => Response::text(404, "Not found\n"),
+    };
+    http::write_response(&mut stream, &response).await
+}
+
+/// Send every marker update to a WebSocket client until it disconnects.
+async fn push_updates(stream: TcpStream, key: &str, state: &MapState) -> Result<()> {
+    let mut updates = state.updates.subscribe();
+    let (mut reader, mut writer) = websocket::accept(Box::new(stream), key).await?;
+
+    // Reads aren't cancel-safe, so they get their own task
+    let (pings, mut pinged) = mpsc::channel(4);
+    let read_task = tokio::spawn(async move {
+        while let Ok(message) = reader.recv().await {
+            let open = match message {
+                Message::Ping(payload) => pings.send(payload).await.is_ok(),
+                Message::Close => false,
+                _ => true,
+            };
+            if !open {
+                break;
+            }
+        }
+    });
+
+    let result = async {
+        loop {
+            tokio::select! {
+                update = updates.recv() => match update {
+                    Ok(json) => writer.send_text(&json).await?,
+                    Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                ping = pinged.recv() => match ping {
+                    Some(payload) => writer.pong(&payload).await?,
+                    None => break,
+                },
+            }
+        }
+        writer.close().await
+    }
+    .await;
+    read_task.abort();
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_marker_updates() {
+        let state = MapState {
+            markers: Mutex::new(BTreeMap::new()),
+            updates: broadcast::channel(8).0,
+            page: String::new(),
+        };
+        let mut updates = state.updates.subscribe();
+        let marker = Marker {
+            id: "11".into(),
+            name: "alice".into(),
+            lat: 47.3769,
+            lon: 8.5417,
+            source: "advert",
+            rssi: Some(-70),
+            updated: now(),
+        };
+
+        state.update(marker.clone());
+        // Same position again (new RSSI) isn't pushed
+        state.update(Marker {
+            rssi: Some(-90),
+            ..marker.clone()
+        });
+        state.update(Marker {
+            lat: 47.4,
+            ..marker
+        });
+        assert!(updates.try_recv().unwrap().contains("47.3769"));
+        assert!(updates.try_recv().unwrap().contains("47.4"));
+        assert!(updates.try_recv().is_err());
+        assert_eq!(state.snapshot()[0].rssi, Some(-70));
+    }
+}
@@ -1,40 +1,158 @@
 //! Messaging commands
 
 use super::connect_with_auth;
-use crate::cli::{ChannelsAction, MessagesAction};
-use crate::protocol::Response;
-use anyhow::{bail, Result};
-use base64::{engine::general_purpose, Engine as _};
-use sha2::{Digest, Sha256};
+use super::contacts::{contact_name, find_contact, remember_neighbors, resolve_node};
+use super::waypoints::describe_received;
+use crate::channels::{self, Level};
+use crate::cli::{ChannelsAction, MessagesAction, OutboxAction};
+use crate::compress;
+use crate::confirm::{confirm, Operation, Target};
+use crate::contacts::{self, KeyStatus};
+use crate::error::CliError;
+use crate::fragment::{self, Reassembler};
+use crate::history::{self, Recorder};
+use crate::hooks::{HookEvent, HookRunner};
+use crate::nodes::{self, CachedNode};
+use crate::outbox;
+use crate::output;
+use crate::packet::{self, Packet, PayloadType};
+use crate::protocol::{MonitorEvent, Protocol, Response};
+use crate::provision::{self, ChannelSettings};
+use crate::psk;
+use crate::qr::QrCode;
+use crate::settings::Settings;
+use crate::text;
+use crate::timefmt;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashSet};
+use std::time::{Duration, Instant};
 
 /// Send a message (broadcast, direct, or channel)
+#[allow(clippy::too_many_arguments)]
 pub async fn cmd_send(
     port: &str,
     baud: u32,
     pin: Option<&str>,
-    to: Option<&str>,
-    channel: Option<&str>,
+    to: &[String],
+    channel: &[String],
     message: &str,
+    wait_ack: Option<u64>,
+    refresh: bool,
+    spacing: u64,
+    queue: bool,
+    fragment: bool,
+    compress: bool,
+    transliterate: bool,
 ) -> Result<()> {
+    let message = if transliterate {
+        Cow::Owned(text::transliterate(&text::clean(message)))
+    } else {
+        text::clean(message)
+    };
+    let message: &str = &message;
+    let payload = if compress {
+        compress::pack(message)
+    } else {
+        None
+    };
+    match &payload {
+        Some(packed) => println!("Compressed {} bytes to {}", message.len(), packed.len()),
+        None if compress => {
+            println!("⚠ Compression doesn't shorten this message; sending it as is")
+        }
+        None => {}
+    }
+    let payload = payload.as_deref().unwrap_or(message);
+    let chars = message.chars().count();
+    if payload.len() > fragment::MAX_TEXT_BYTES && chars <= fragment::MAX_TEXT_BYTES {
+        let hint = if transliterate {
+            String::new()
+        } else {
+            format!(
+                " (--transliterate makes it {})",
+                text::transliterate(message).len()
+            )
+        };
+        println!(
+            "⚠ Accents and emoji take 2-4 bytes each: these {chars} characters are {} bytes{hint}",
+            message.len()
+        );
+    }
+    let parts = if fragment {
+        fragment::split(payload)?.len()
+    } else {
+        if payload.len() > fragment::MAX_TEXT_BYTES {
+            println!(
+                "⚠ {} bytes is over the {}-byte payload limit; the device may cut it short",
+                payload.len(),
+                fragment::MAX_TEXT_BYTES
+            );
+        }
+        1
+    };
+    let send = Sender {
+        fragment,
+        compress,
+        parts,
+    };
     let dev = connect_with_auth(port, baud, pin).await?;
     let mut proto = dev.into_protocol();
 
-    if let Some(ch) = channel {
+    let mut channels: Vec<&str> = Vec::new();
+    for ch in channel {
+        if !channels.contains(&ch.as_str()) {
+            channels.push(ch);
+        }
+    }
+    let mut dests: Vec<&str> = Vec::new();
+    for dest in to {
+        if !dests.contains(&dest.as_str()) {
+            dests.push(dest);
+        }
+    }
+    if queue {
+        dests = queue_unreachable(&mut proto, port, &dests, message).await?;
+        if channels.is_empty() && dests.is_empty() {
+            return Ok(());
+        }
+    }
+    if channels.len() + dests.len() > 1 {
+        return send_to_many(
+            &mut proto, port, &channels, &dests, message, wait_ack, refresh, spacing, send,
+        )
+        .await;
+    }
+
+    // Without a destination, the default channel set with `channels default`
+    let default_channel;
+    if channels.is_empty() && dests.is_empty() {
+        default_channel = proto.default_channel().await?;
+        channels.extend(default_channel.as_deref());
+    }
+
+    if let Some(ch) = channels.first() {
         // Send to channel
-        println!("Sending to channel {ch}: {message}");
-        let cmd = format!("CHANNEL SEND {ch} {message}");
-        match proto.command(&cmd).await? {
+        println!("Sending to channel {ch}{}: {message}", send.in_parts());
+        match send
+            .send(&mut proto, &format!("CHANNEL SEND {ch}"), message)
+            .await?
+        {
             Response::Ok(_) => {
                 println!("Sent!");
             }
-            Response::Error(e) => bail!("Device error: {e}"),
+            Response::Error(e) => bail!(CliError::Device(e)),
             Response::Json(_) => bail!("Unexpected response to CHANNEL SEND"),
         }
-    } else if let Some(dest) = to {
-        // Send direct message
-        println!("Sending to {dest}: {message}");
-        let cmd = format!("SEND {dest} {message}");
-        match proto.command(&cmd).await? {
+    } else if let Some(&dest) = dests.first() {
+        // Send direct message, by node hash if the name resolves
+        let (target, label) = resolve_dest(&mut proto, port, dest, refresh).await;
+        println!("Sending to {label}{}: {message}", send.in_parts());
+        match send
+            .send(&mut proto, &format!("SEND {target}"), message)
+            .await?
+        {
             Response::Ok(msg) => {
                 if let Some(m) = msg {
                     println!("Sent! ({m})");
@@ -42,18 +160,21 @@ pub async fn cmd_send(
                     println!("Sent!");
                 }
             }
-            Response::Error(e) => bail!("Device error: {e}"),
+            Response::Error(e) => bail!(CliError::Device(e)),
             Response::Json(_) => bail!("Unexpected response to SEND"),
         }
+
+        if let Some(timeout_secs) = wait_ack {
+            wait_for_acks(&mut proto, vec![(dest, target)], message, timeout_secs).await?;
+        }
     } else {
         // Broadcast to public channel
-        println!("Broadcasting: {message}");
-        let cmd = format!("SEND {message}");
-        match proto.command(&cmd).await? {
+        println!("Broadcasting{}: {message}", send.in_parts());
+        match send.send(&mut proto, "SEND", message).await? {
             Response::Ok(_) => {
                 println!("Sent!");
             }
-            Response::Error(e) => bail!("Device error: {e}"),
+            Response::Error(e) => bail!(CliError::Device(e)),
             Response::Json(_) => bail!("Unexpected response to SEND"),
         }
     }
@@ -61,83 +182,570 @@ pub async fn cmd_send(
     Ok(())
 }
 
-/// Manage inbox messages
-pub async fn cmd_messages(
+/// How `send` puts a message on air.
+#[derive(Clone, Copy)]
+struct Sender {
+    /// In numbered parts when it's over the payload limit
+    fragment: bool,
+    /// Packed with [`compress::pack`] when that makes it shorter
+    compress: bool,
+    parts: usize,
+}
+
+impl Sender {
+    async fn send(&self, proto: &mut Protocol, cmd: &str, message: &str) -> Result<Response> {
+        let packed = if self.compress {
+            compress::pack(message)
+        } else {
+            None
+        };
+        let message = packed.as_deref().unwrap_or(message);
+        if self.fragment {
+            proto.send_text(cmd, message).await
+        } else {
+            proto.command(&format!("{cmd} {message}")).await
+        }
+    }
+
+    fn in_parts(&self) -> String {
+        if self.parts > 1 {
+            format!(" in {} parts", self.parts)
+        } else {
+            String::new()
+        }
+    }
+}
+
+/// Send `message` to several channels and nodes in turn, `spacing` seconds
+/// apart, reporting each. A target the device refuses doesn't stop the rest.
+#[allow(clippy::too_many_arguments)]
+async fn send_to_many(
+    proto: &mut Protocol,
+    port: &str,
+    channels: &[&str],
+    dests: &[&str],
+    message: &str,
+    wait_ack: Option<u64>,
+    refresh: bool,
+    spacing: u64,
+    send: Sender,
+) -> Result<()> {
+    let total = channels.len() + dests.len();
+    println!(
+        "Sending to {total} destinations{}: {message}",
+        send.in_parts()
+    );
+
+    let mut sends = Vec::new();
+    for &ch in channels {
+        sends.push((format!("channel {ch}"), format!("CHANNEL SEND {ch}"), None));
+    }
+    for &dest in dests {
+        let (target, label) = resolve_dest(proto, port, dest, refresh).await;
+        sends.push((label, format!("SEND {target}"), Some((dest, target))));
+    }
+
+    let mut failed = 0;
+    let mut pending_acks = Vec::new();
+    for (i, (label, cmd, dest)) in sends.into_iter().enumerate() {
+        if i > 0 && spacing > 0 {
+            tokio::time::sleep(Duration::from_secs(spacing)).await;
+        }
+        match send.send(proto, &cmd, message).await? {
+            Response::Ok(Some(m)) if dest.is_some() => println!("  ✓ {label} ({m})"),
+            Response::Ok(_) => println!("  ✓ {label}"),
+            Response::Error(e) => {
+                println!("  ✗ {label}: {e}");
+                failed += 1;
+                continue;
+            }
+            Response::Json(_) => bail!("Unexpected response to {cmd}"),
+        }
+        pending_acks.extend(dest);
+    }
+
+    if let Some(timeout_secs) = wait_ack {
+        if !pending_acks.is_empty() {
+            wait_for_acks(proto, pending_acks, message, timeout_secs).await?;
+        }
+    }
+    if failed > 0 {
+        bail!(CliError::Device(format!(
+            "{failed} of {total} sends failed"
+        )));
+    }
+    Ok(())
+}
+
+/// Queue `message` in the outbox for each of `dests` missing from the
+/// device's neighbor table; returns the ones in range.
+async fn queue_unreachable<'a>(
+    proto: &mut Protocol,
+    port: &str,
+    dests: &[&'a str],
+    message: &str,
+) -> Result<Vec<&'a str>> {
+    let neighbors = proto.get_neighbors().await?;
+    let device_key = remember_neighbors(proto, port, &neighbors).await;
+    let mut reachable = Vec::new();
+    for &dest in dests {
+        if find_contact(&neighbors, dest).is_some() {
+            reachable.push(dest);
+            continue;
+        }
+        // Known from an earlier neighbor table: wait for its hash
+        let node_hash = device_key
+            .as_deref()
+            .and_then(|key| nodes::lookup(key, dest).ok().flatten())
+            .map(|node| node.node_hash);
+        let queued = outbox::add(dest, node_hash, message)?;
+        println!(
+            "⚠ {dest} is out of range; queued as #{} until it is heard again",
+            queued.id
+        );
+    }
+    if reachable.len() < dests.len() {
+        println!("  `meshgrid-cli monitor` sends queued messages (see `meshgrid-cli outbox`)");
+    }
+    Ok(reachable)
+}
+
+/// Address of direct message destination `dest` (its node hash if the name
+/// resolves) and how to show it.
+async fn resolve_dest(
+    proto: &mut Protocol,
+    port: &str,
+    dest: &str,
+    refresh: bool,
+) -> (String, String) {
+    match resolve_node(proto, port, dest, refresh).await {
+        Some(node) => {
+            warn_unverified_key(&node, dest);
+            let hash = format!("0x{:02x}", node.node_hash);
+            let label = format!("{} ({hash})", node.label());
+            (hash, label)
+        }
+        None => (dest.to_string(), dest.to_string()),
+    }
+}
+
+/// Warn when the destination's key is unverified or has changed since it was
+/// first seen.
+fn warn_unverified_key(node: &CachedNode, dest: &str) {
+    let Some(public_key) = &node.public_key else {
+        return;
+    };
+    let name = node.label();
+    match contacts::remember(&name, public_key) {
+        Ok(KeyStatus::Trusted) => {}
+        Ok(KeyStatus::Unverified | KeyStatus::New) => eprintln!(
+            "⚠ {name}'s key is not verified (check it with `meshgrid-cli contacts verify {dest}`)"
+        ),
+        Ok(KeyStatus::Changed { previous }) => eprintln!(
+            "⚠ {name}'s key has CHANGED since it was first seen (was {}..., now {}...).\n  \
+             Verify it with `meshgrid-cli contacts verify {dest}` before trusting it.",
+            &previous[..16.min(previous.len())],
+            &public_key[..16.min(public_key.len())]
+        ),
+        Err(e) => tracing::warn!("Failed to check key for {name}: {e:#}"),
+    }
+}
+
+/// Trust a new key announced by `sender` if the announcement came from its
+/// trusted key.
+async fn note_key_rotation(proto: &mut Protocol, sender: &str, new_key: &str, effective: i64) {
+    let neighbors = proto.get_neighbors().await.unwrap_or_default();
+    let Some(neighbor) = find_contact(&neighbors, sender) else {
+        eprintln!("      ⚠ Key rotation announced by unknown node {sender}; ignored");
+        return;
+    };
+    let name = contact_name(neighbor);
+    let when = chrono::DateTime::from_timestamp(effective, 0).map_or_else(
+        || effective.to_string(),
+        |t| timefmt::format(&t, "%Y-%m-%d %H:%M"),
+    );
+    let sender_key = neighbor.public_key.map(hex::encode).unwrap_or_default();
+    match contacts::accept_rotation(&name, &sender_key, new_key) {
+        Ok(true) => {
+            let note = format!(
+                "      ✓ {name} switches to key {}... at {when}; new key trusted",
+                &new_key[..16]
+            );
+            // Keep stdout parseable with --output json|yaml
+            if output::is_structured() {
+                eprintln!("{note}");
+            } else {
+                println!("{note}");
+            }
+        }
+        Ok(false) => eprintln!(
+            "      ⚠ {name} announced a key rotation but is not a trusted contact; ignored"
+        ),
+        Err(e) => tracing::warn!("Failed to record key rotation for {name}: {e:#}"),
+    }
+}
+
+/// Wait for ACKs from each `dest` (sent to as `target`), running the
+/// `ack_timeout` hook for those that don't arrive
+async fn wait_for_acks(
+    proto: &mut Protocol,
+    mut pending: Vec<(&str, String)>,
+    message: &str,
+    timeout_secs: u64,
+) -> Result<()> {
+    proto.enter_monitor_mode().await?;
+
+    let timeout = Duration::from_secs(timeout_secs);
+    let start = Instant::now();
+    while start.elapsed() < timeout && !pending.is_empty() {
+        if let Some(MonitorEvent::Ack { from }) = proto.read_event().await? {
+            let acked = pending.iter().position(|(dest, target)| {
+                from.eq_ignore_ascii_case(dest) || from.eq_ignore_ascii_case(target)
+            });
+            if let Some(i) = acked {
+                println!("ACK received from {from}");
+                pending.remove(i);
+            }
+        }
+    }
+    if pending.is_empty() {
+        return Ok(());
+    }
+
+    let hooks = HookRunner::new(Settings::load()?.hooks);
+    for (dest, _) in &pending {
+        hooks.fire(&HookEvent::AckTimeout {
+            to: dest.to_string(),
+            text: message.to_string(),
+            timeout_secs,
+        });
+    }
+    // Give the hook a moment to start before the runtime shuts down
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let missing: Vec<&str> = pending.iter().map(|(dest, _)| *dest).collect();
+    bail!(CliError::Timeout(format!(
+        "No ACK from {} within {timeout_secs}s",
+        missing.join(", ")
+    )))
+}
+
+/// Monitor mesh traffic and run event hooks
+pub async fn cmd_monitor(
     port: &str,
     baud: u32,
     pin: Option<&str>,
-    action: Option<MessagesAction>,
+    battery_interval: u64,
+    notify: bool,
 ) -> Result<()> {
-    use chrono::{Local, TimeZone};
+    let settings = Settings::load()?;
+    let mut history = Recorder::new(&settings);
+    let hooks = HookRunner::new(settings.hooks);
+    let prefs = channels::load()?;
     let dev = connect_with_auth(port, baud, pin).await?;
     let mut proto = dev.into_protocol();
+    // Adverts go into this device's node database
+    let device_key = proto
+        .get_info()
+        .await
+        .ok()
+        .map(|info| hex::encode(info.public_key));
 
-    let action = action.unwrap_or(MessagesAction::Show);
+    proto.enter_monitor_mode().await?;
+    println!("Monitoring mesh traffic (Ctrl+C to stop)...\n");
 
-    match action {
-        MessagesAction::Show => {
-            match proto.command("MESSAGES").await? {
-                Response::Json(json) => {
-                    let total = json
-                        .get("total")
-                        .and_then(serde_json::Value::as_u64)
-                        .unwrap_or(0);
-
-                    if total == 0 {
-                        println!("No messages in inbox");
-                    } else if let Some(messages) = json.get("messages").and_then(|m| m.as_array()) {
-                        println!("Inbox ({total} messages):\n");
-
-                        for msg in messages {
-                            let _from_hash =
-                                msg.get("from_hash").and_then(|h| h.as_str()).unwrap_or("?");
-                            let from_name =
-                                msg.get("from_name").and_then(|n| n.as_str()).unwrap_or("?");
-                            let channel =
-                                msg.get("channel").and_then(|c| c.as_str()).unwrap_or("?");
-                            let protocol =
-                                msg.get("protocol").and_then(|p| p.as_str()).unwrap_or("v0");
-                            let decrypted = msg
-                                .get("decrypted")
-                                .and_then(serde_json::Value::as_bool)
-                                .unwrap_or(false);
-                            let text = msg.get("text").and_then(|t| t.as_str()).unwrap_or("");
-                            let timestamp = msg
-                                .get("timestamp")
-                                .and_then(serde_json::Value::as_u64)
-                                .unwrap_or(0);
-
-                            let channel_str = match channel {
-                                "direct" => "DM".to_string(),
-                                "public" => "Public".to_string(),
-                                ch => format!("CH:{ch}"),
-                            };
-
-                            let lock = if decrypted { " " } else { "🔒" };
-
-                            // Format timestamp as datetime
-                            let timestamp_i64 = i64::try_from(timestamp).unwrap_or(0);
-                            let datetime =
-                                Local.timestamp_opt(timestamp_i64, 0).single().map_or_else(
-                                    || format!("invalid-ts:{timestamp}"),
-                                    |dt| dt.format("%Y-%m-%d %H:%M:%S").to_string(),
-                                );
-
-                            println!(
-                                "  [{datetime}] {lock} from {from_name} ({channel_str}/{protocol}): {text}"
-                            );
+    let mut seen_nodes: HashSet<u8> = HashSet::new();
+    let mut joiner = MessageJoiner::default();
+    let mut battery_alerted = false;
+    let battery_interval = Duration::from_secs(battery_interval);
+    let mut last_battery_check = Instant::now();
+
+    loop {
+        let mut events = Vec::new();
+        if let Some(event) = proto.read_event().await? {
+            history.record(&event);
+            events.extend(joiner.join(event));
+        }
+        events.extend(joiner.expired());
+        for event in events {
+            let timestamp = timefmt::clock();
+            match event {
+                MonitorEvent::Message {
+                    from,
+                    to,
+                    channel,
+                    rssi,
+                    text,
+                } => {
+                    let dest = to.as_deref().or(channel.as_deref()).unwrap_or("all");
+                    let channel =
+                        channels::of_message(to.as_deref(), channel.as_deref()).map(String::from);
+                    let level = prefs.get(channel.as_deref());
+                    let mark = match level {
+                        Level::Priority => "★ ",
+                        Level::Notify | Level::Mute => "",
+                    };
+                    println!("[{timestamp}] {mark}{from} -> {dest} ({rssi}dB): {text}");
+                    if level == Level::Mute {
+                        continue;
+                    }
+                    if notify {
+                        let title = match &channel {
+                            Some(channel) => format!("{from} in {channel}"),
+                            None => from.clone(),
+                        };
+                        notify_desktop(&title, &text, level == Level::Priority);
+                    }
+                    hooks.fire(&HookEvent::MessageReceived {
+                        from,
+                        to,
+                        channel,
+                        priority: level == Level::Priority,
+                        text,
+                        rssi,
+                    });
+                }
+                MonitorEvent::Advertisement {
+                    node_hash,
+                    rssi,
+                    name,
+                } => {
+                    let display = name.clone().unwrap_or_else(|| format!("0x{node_hash:02x}"));
+                    println!("[{timestamp}] ADV {display} ({rssi}dB)");
+                    send_queued(&mut proto, &hooks, node_hash, name.as_deref()).await?;
+                    if let Some(device_key) = &device_key {
+                        if let Err(e) = nodes::heard(device_key, node_hash, name.as_deref(), rssi) {
+                            tracing::debug!("Failed to update node database: {e:#}");
                         }
                     }
+                    if seen_nodes.insert(node_hash) {
+                        hooks.fire(&HookEvent::NodeAppeared {
+                            node_hash,
+                            name,
+                            rssi,
+                        });
+                    }
+                }
+                MonitorEvent::Ack { from } => println!("[{timestamp}] ACK from {from}"),
+                MonitorEvent::Waypoint {
+                    from,
+                    rssi,
+                    name,
+                    lat,
+                    lon,
+                    icon,
+                    expires,
+                } => {
+                    let waypoint = describe_received(&name, lat, lon, icon.as_deref(), expires);
+                    println!("[{timestamp}] WPT {waypoint} from {from} ({rssi}dB)");
                 }
-                Response::Error(e) => bail!("Device error: {e}"),
-                Response::Ok(_) => bail!("Unexpected OK response to MESSAGES"),
+                MonitorEvent::Error { message } => eprintln!("[{timestamp}] ERR {message}"),
             }
         }
+
+        if !battery_interval.is_zero() && last_battery_check.elapsed() >= battery_interval {
+            last_battery_check = Instant::now();
+            let telem = proto.get_telemetry().await?;
+            // Querying telemetry leaves monitor mode on some firmware
+            proto.enter_monitor_mode().await?;
+
+            if let Some(dev) = telem.device {
+                if dev.usb_power || dev.battery_percent >= hooks.battery_low_percent() {
+                    battery_alerted = false;
+                } else if !battery_alerted {
+                    battery_alerted = true;
+                    println!("[battery] Low battery: {}%", dev.battery_percent);
+                    hooks.fire(&HookEvent::BatteryLow {
+                        percent: dev.battery_percent,
+                        voltage: dev.voltage(),
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// How long `monitor` waits for the missing parts of a split message.
+const FRAGMENT_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Sender and destination of a message, and what's kept of its first part.
+type MessageKey = (String, Option<String>);
+type MessageMeta = (String, Option<String>, Option<String>, i16);
+
+/// Joins the parts of split messages in `monitor`.
+#[derive(Default)]
+struct MessageJoiner(Reassembler<MessageKey, MessageMeta>);
+
+impl MessageJoiner {
+    /// `event`, unless it's a part of a message still missing others.
+    fn join(&mut self, event: MonitorEvent) -> Option<MonitorEvent> {
+        let MonitorEvent::Message {
+            from,
+            to,
+            channel,
+            rssi,
+            text,
+        } = event
+        else {
+            return Some(event);
+        };
+        let key = (from.clone(), to.clone().or_else(|| channel.clone()));
+        self.0
+            .push(key, (from, to, channel, rssi), &text)
+            .map(Self::message)
+    }
+
+    /// Messages whose missing parts didn't turn up in time, with the gaps
+    /// marked.
+    fn expired(&mut self) -> Vec<MonitorEvent> {
+        self.0
+            .expire(FRAGMENT_TIMEOUT)
+            .into_iter()
+            .map(Self::message)
+            .collect()
+    }
+
+    fn message(((from, to, channel, rssi), text): (MessageMeta, String)) -> MonitorEvent {
+        MonitorEvent::Message {
+            from,
+            to,
+            channel,
+            rssi,
+            text: compress::unpack(&text).into_owned(),
+        }
+    }
+}
+
+/// Send the outbox messages waiting for the node behind an advert, running
+/// the `queued_delivered` hook for each. Those the device refuses stay queued.
+async fn send_queued(
+    proto: &mut Protocol,
+    hooks: &HookRunner,
+    node_hash: u8,
+    name: Option<&str>,
+) -> Result<()> {
+    let waiting = match outbox::waiting_for(node_hash, name) {
+        Ok(waiting) => waiting,
+        Err(e) => {
+            tracing::warn!("Failed to read the outbox: {e:#}");
+            return Ok(());
+        }
+    };
+    if waiting.is_empty() {
+        return Ok(());
+    }
+    for queued in waiting {
+        let cmd = format!("SEND 0x{node_hash:02x}");
+        let timestamp = timefmt::clock();
+        match proto.send_text(&cmd, &queued.text).await? {
+            Response::Ok(_) => {
+                let queued_secs = queued.waited_secs();
+                println!(
+                    "[{timestamp}] ✓ Sent queued message #{} to {} (queued {})",
+                    queued.id,
+                    queued.to,
+                    timefmt::ago(queued_secs)
+                );
+                outbox::remove(queued.id)?;
+                hooks.fire(&HookEvent::QueuedDelivered {
+                    to: queued.to,
+                    text: queued.text,
+                    queued_secs,
+                });
+            }
+            Response::Error(e) => println!(
+                "[{timestamp}] ✗ Queued message #{} to {}: {e}; kept in the outbox",
+                queued.id, queued.to
+            ),
+            Response::Json(_) => bail!("Unexpected response to SEND"),
+        }
+    }
+    proto.enter_monitor_mode().await
+}
+
+/// Show a desktop notification for a message (`notify-send` on Linux,
+/// Notification Center on macOS).
+pub(super) fn notify_desktop(title: &str, body: &str, urgent: bool) {
+    let mut command = if cfg!(target_os = "macos") {
+        let mut c = std::process::Command::new("osascript");
+        let quote = |s: &str| s.replace('\\', "\\\\").replace('"', "\\\"");
+        c.args([
+            "-e",
+            &format!(
+                "display notification \"{}\" with title \"{}\"",
+                quote(body),
+                quote(title)
+            ),
+        ]);
+        c
+    } else {
+        let mut c = std::process::Command::new("notify-send");
+        c.args([
+            "-a",
+            "meshgrid",
+            "-u",
+            if urgent { "critical" } else { "normal" },
+        ]);
+        c.args(["--", title, body]);
+        c
+    };
+    if let Err(e) = command.spawn() {
+        tracing::warn!("Failed to show notification: {e}");
+    }
+}
+
+/// Manage inbox messages
+pub async fn cmd_messages(
+    port: &str,
+    baud: u32,
+    pin: Option<&str>,
+    action: Option<MessagesAction>,
+) -> Result<()> {
+    let mut dev = connect_with_auth(port, baud, pin).await?;
+    let action = action.unwrap_or(MessagesAction::Show);
+    if matches!(action, MessagesAction::Clear) {
+        let info = dev.get_info().await?;
+        let target = Target::new(Some(port), info.name.as_deref(), Some(&info.public_key));
+        if !confirm(Operation::MessagesClear, &target)? {
+            return Ok(());
+        }
+    }
+    let mut proto = dev.into_protocol();
+
+    match action {
+        MessagesAction::Show => match proto.command("MESSAGES").await? {
+            Response::Json(json) => {
+                let mut inbox: Inbox = serde_json::from_value(json)
+                    .context("Unexpected MESSAGES response from the device")?;
+                let received = inbox.messages.len();
+                inbox.messages = join_parts(std::mem::take(&mut inbox.messages));
+                inbox.total -= (received - inbox.messages.len()) as u64;
+                if output::is_structured() {
+                    output::print(&inbox)?;
+                } else if inbox.total == 0 {
+                    println!("No messages in inbox");
+                } else {
+                    println!("Inbox ({} messages):\n", inbox.total);
+                    for msg in &inbox.messages {
+                        print_message(&serde_json::to_value(msg)?);
+                    }
+                }
+
+                for msg in inbox.messages.iter().filter(|m| m.channel == "direct") {
+                    if let Some((new_key, effective)) =
+                        contacts::parse_rotation_announcement(&msg.text)
+                    {
+                        note_key_rotation(&mut proto, &msg.from_hash, &new_key, effective).await;
+                    }
+                }
+            }
+            Response::Error(e) => bail!(CliError::Device(e)),
+            Response::Ok(_) => bail!("Unexpected OK response to MESSAGES"),
+        },
         MessagesAction::Clear => match proto.command("MESSAGES CLEAR").await? {
             Response::Ok(msg) => {
                 println!("{}", msg.unwrap_or_else(|| "Messages cleared".to_string()));
             }
-            Response::Error(e) => bail!("Device error: {e}"),
+            Response::Error(e) => bail!(CliError::Device(e)),
             Response::Json(_) => bail!("Unexpected response to MESSAGES CLEAR"),
         },
     }
@@ -145,6 +753,151 @@ pub async fn cmd_messages(
     Ok(())
 }
 
+/// `CHANNELS` response, without the keys.
+#[derive(Serialize, Deserialize)]
+struct ChannelList {
+    #[serde(default)]
+    total: u64,
+    #[serde(default)]
+    channels: Vec<ChannelEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ChannelEntry {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    hash: String,
+    #[serde(default)]
+    builtin: bool,
+}
+
+/// `MESSAGES` response.
+#[derive(Serialize, Deserialize)]
+struct Inbox {
+    #[serde(default)]
+    total: u64,
+    #[serde(default)]
+    messages: Vec<InboxMessage>,
+}
+
+/// An inbox entry, as the device reports it.
+#[derive(Serialize, Deserialize)]
+struct InboxMessage {
+    #[serde(default)]
+    timestamp: u64,
+    #[serde(default)]
+    from_name: Option<String>,
+    #[serde(default)]
+    from_hash: String,
+    #[serde(default)]
+    channel: String,
+    #[serde(default)]
+    protocol: Option<String>,
+    #[serde(default)]
+    decrypted: bool,
+    #[serde(default)]
+    text: String,
+}
+
+/// Inbox entries with the parts of split messages joined, each where its
+/// first part was.
+fn join_parts(messages: Vec<InboxMessage>) -> Vec<InboxMessage> {
+    let mut joiner = Reassembler::default();
+    let mut joined = Vec::new();
+    for (i, mut msg) in messages.into_iter().enumerate() {
+        let key = (msg.from_hash.clone(), msg.channel.clone());
+        let text = std::mem::take(&mut msg.text);
+        joined.extend(joiner.push(key, (i, msg), &text));
+    }
+    joined.extend(joiner.expire(Duration::ZERO));
+    joined.sort_by_key(|((i, _), _)| *i);
+    joined
+        .into_iter()
+        .map(|((_, mut msg), text)| {
+            msg.text = compress::unpack(&text).into_owned();
+            msg
+        })
+        .collect()
+}
+
+/// One inbox line: time, sender, channel and text of a `MESSAGES` entry.
+fn print_message(msg: &serde_json::Value) {
+    let from_name = msg.get("from_name").and_then(|n| n.as_str()).unwrap_or("?");
+    let channel = msg.get("channel").and_then(|c| c.as_str()).unwrap_or("?");
+    let protocol = msg.get("protocol").and_then(|p| p.as_str()).unwrap_or("v0");
+    let decrypted = msg
+        .get("decrypted")
+        .and_then(serde_json::Value::as_bool)
+        .unwrap_or(false);
+    let text = msg.get("text").and_then(|t| t.as_str()).unwrap_or("");
+    let timestamp = msg
+        .get("timestamp")
+        .and_then(serde_json::Value::as_u64)
+        .unwrap_or(0);
+
+    let channel_str = match channel {
+        "direct" => "DM".to_string(),
+        "public" => "Public".to_string(),
+        ch => format!("CH:{ch}"),
+    };
+
+    let lock = if decrypted { " " } else { "🔒" };
+
+    let when = timefmt::relative_unix(i64::try_from(timestamp).unwrap_or(0));
+
+    println!("  [{when}] {lock} from {from_name} ({channel_str}/{protocol}): {text}");
+}
+
+/// List or drop messages queued with `send --queue`
+pub fn cmd_outbox(action: OutboxAction) -> Result<()> {
+    match action {
+        OutboxAction::List => {
+            let queued = outbox::list()?;
+            if output::is_plain() {
+                for (key, value) in output::flatten_json(&serde_json::to_value(&queued)?) {
+                    output::kv(&key, value);
+                }
+                return Ok(());
+            }
+            if queued.is_empty() {
+                println!("Outbox is empty");
+                return Ok(());
+            }
+            println!("Outbox ({}):\n", queued.len());
+            println!("  {:>4} {:20} {:10} Message", "#", "To", "Queued");
+            println!("  {:->4} {:-<20} {:-<10} {:-<30}", "", "", "", "");
+            for message in &queued {
+                let to = match message.node_hash {
+                    Some(hash) => format!("{} (0x{hash:02x})", message.to),
+                    None => message.to.clone(),
+                };
+                println!(
+                    "  {:>4} {to:20} {:10} {}",
+                    message.id,
+                    timefmt::ago(message.waited_secs()),
+                    message.text
+                );
+            }
+        }
+        OutboxAction::Remove { id } => match outbox::remove(id)? {
+            Some(message) => {
+                if !output::is_plain() {
+                    println!("✓ Removed queued message #{id} to {}", message.to);
+                }
+            }
+            None => bail!(CliError::InvalidArgs(format!("No queued message #{id}"))),
+        },
+        OutboxAction::Clear => {
+            let count = outbox::clear()?;
+            if !output::is_plain() {
+                println!("✓ Removed {count} queued message(s)");
+            }
+        }
+    }
+    Ok(())
+}
+
 /// Manage channels
 pub async fn cmd_channels(
     port: &str,
@@ -160,46 +913,53 @@ pub async fn cmd_channels(
     match action {
         ChannelsAction::List => match proto.command("CHANNELS").await? {
             Response::Json(json) => {
-                let channels = json.get("channels").and_then(|c| c.as_array());
-                let total = json
-                    .get("total")
-                    .and_then(serde_json::Value::as_u64)
-                    .unwrap_or(0);
-
-                println!("Channels ({total}):\n");
-
-                if let Some(channels) = channels {
-                    for channel in channels {
-                        let name = channel.get("name").and_then(|n| n.as_str()).unwrap_or("?");
-                        let hash = channel.get("hash").and_then(|h| h.as_str()).unwrap_or("?");
-                        let builtin = channel
-                            .get("builtin")
-                            .and_then(serde_json::Value::as_bool)
-                            .unwrap_or(false);
-
-                        let tag = if builtin { "[builtin]" } else { "[custom]" };
-                        println!("  {hash} - {name} {tag}");
-                    }
+                let list: ChannelList = serde_json::from_value(json)
+                    .context("Unexpected CHANNELS response from the device")?;
+                if output::is_structured() {
+                    return output::print(&list);
+                }
+
+                println!("Channels ({}):\n", list.total);
+                for channel in &list.channels {
+                    let tag = if channel.builtin {
+                        "[builtin]"
+                    } else {
+                        "[custom]"
+                    };
+                    println!("  {} - {} {tag}", channel.hash, channel.name);
                 }
             }
-            Response::Error(e) => bail!("Device error: {e}"),
+            Response::Error(e) => bail!(CliError::Device(e)),
             Response::Ok(_) => bail!("Unexpected OK response to CHANNELS"),
         },
-        ChannelsAction::Add { name, psk } => {
+        ChannelsAction::Add { name, psk, url, qr } => {
+            let (name, psk) = match (url, qr) {
+                (Some(link), _) => {
+                    let (name, psk) = parse_channel_link(&link)?;
+                    (name, Some(psk))
+                }
+                (None, Some(image)) => {
+                    let (name, psk) = parse_channel_link(&read_qr_image(&image)?)?;
+                    println!("Read share link for '{name}' from {image}");
+                    (name, Some(psk))
+                }
+                (None, None) => (name.expect("required without --url/--qr"), psk),
+            };
+
             // Auto-generate PSK for hashtag channels (public channels)
             let psk_to_use = if name.starts_with('#') {
-                // Calculate SHA256(channel_name) for public hashtag channels
-                let mut hasher = Sha256::new();
-                hasher.update(name.as_bytes());
-                let hash = hasher.finalize();
-                let encoded = general_purpose::STANDARD.encode(hash);
                 println!("Auto-generated PSK for public hashtag channel '{}'", name);
-                encoded
+                psk::hashtag_psk(&name)
             } else {
                 // For non-hashtag channels, PSK is required
                 match psk {
-                    Some(p) => p,
-                    None => bail!("PSK is required for non-hashtag channels. Use hashtag (#) prefix for public channels."),
+                    Some(p) => {
+                        psk::warn_if_weak(&name, &psk::decode(&p)?);
+                        p
+                    }
+                    None => bail!(CliError::InvalidArgs(
+                        "PSK is required for non-hashtag channels. Use hashtag (#) prefix for public channels.".into()
+                    )),
                 }
             };
 
@@ -208,17 +968,211 @@ pub async fn cmd_channels(
                 Response::Ok(msg) => {
                     println!("{}", msg.unwrap_or_else(|| "Channel added".to_string()));
                 }
-                Response::Error(e) => bail!("Device error: {e}"),
+                Response::Error(e) => bail!(CliError::Device(e)),
                 Response::Json(_) => bail!("Unexpected response to CHANNEL JOIN"),
             }
         }
+        ChannelsAction::Share { name, psk } => {
+            let psk = match psk {
+                Some(psk) => psk,
+                None if name.starts_with('#') => psk::hashtag_psk(&name),
+                None => reported_psk(&mut proto, &name).await?.ok_or_else(|| {
+                    CliError::InvalidArgs(format!(
+                        "The device does not report the PSK for '{name}', pass it with --psk"
+                    ))
+                })?,
+            };
+            let link = channel_link(&name, &psk)?;
+            if !name.starts_with('#') {
+                psk::warn_if_weak(&name, &psk::decode(&psk)?);
+            }
+
+            print!("{}", QrCode::encode(link.as_bytes())?.render());
+            println!("{link}\n");
+            println!("Anyone with this link can read and post to {name}. Import it with:");
+            println!("  meshgrid-cli channels add --url '{link}'");
+        }
+        ChannelsAction::Audit { .. } => {
+            let channels = match proto.command("CHANNELS").await? {
+                Response::Json(json) => json,
+                Response::Error(e) => bail!(CliError::Device(e)),
+                Response::Ok(_) => bail!("Unexpected OK response to CHANNELS"),
+            };
+            let channels = exported_channels(&channels, None)
+                .into_iter()
+                .filter(|c| !c.builtin)
+                .collect::<Vec<_>>();
+            report_channel_audit(&channels)?;
+        }
+        ChannelsAction::History {
+            name,
+            limit,
+            room,
+            refresh,
+        } => {
+            let cmd = if room {
+                let target = match resolve_node(&mut proto, port, &name, refresh).await {
+                    Some(found) => format!("0x{:02x}", found.node_hash),
+                    None => name.clone(),
+                };
+                if !output::is_plain() {
+                    println!("Fetching history from room server {name}...");
+                }
+                format!("ROOM HISTORY {target} {limit}")
+            } else {
+                format!("MESSAGES CHANNEL {name} {limit}")
+            };
+            let json = match proto.command(&cmd).await? {
+                Response::Json(json) => json,
+                Response::Error(e) => bail!(CliError::Device(e)),
+                Response::Ok(_) => bail!("Unexpected OK response to {cmd}"),
+            };
+            if output::is_plain() {
+                for (key, value) in output::flatten_json(&json) {
+                    output::kv(&key, value);
+                }
+                return Ok(());
+            }
+            let messages = json
+                .get("messages")
+                .and_then(|m| m.as_array())
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            if messages.is_empty() {
+                println!("No stored messages for {name}");
+                return Ok(());
+            }
+            let total = json
+                .get("total")
+                .and_then(serde_json::Value::as_u64)
+                .unwrap_or(messages.len() as u64);
+            if total > messages.len() as u64 {
+                println!("{name} (last {} of {total} messages):\n", messages.len());
+            } else {
+                println!("{name} ({total} messages):\n");
+            }
+            for msg in messages {
+                print_message(msg);
+            }
+        }
+        ChannelsAction::Scan { seconds } => {
+            let known = match proto.command("CHANNELS").await? {
+                Response::Json(json) => known_channels(&json),
+                Response::Error(e) => bail!(CliError::Device(e)),
+                Response::Ok(_) => bail!("Unexpected OK response to CHANNELS"),
+            };
+            if !output::is_plain() {
+                println!("Listening for channel traffic for {seconds}s...");
+            }
+            let deadline = Instant::now() + Duration::from_secs(seconds);
+            let mut heard: BTreeMap<u8, ChannelActivity> = BTreeMap::new();
+            while Instant::now() < deadline {
+                let Some(bytes) = proto.recv_packet(Duration::from_millis(200)).await? else {
+                    continue;
+                };
+                let Ok(packet) = Packet::parse(&bytes) else {
+                    continue;
+                };
+                if !matches!(
+                    packet.payload_type,
+                    PayloadType::GrpTxt | PayloadType::GrpData
+                ) {
+                    continue;
+                }
+                let Some(&hash) = packet.payload.first() else {
+                    continue;
+                };
+                heard
+                    .entry(hash)
+                    .or_default()
+                    .record(&packet, &known, Instant::now());
+            }
+            report_channel_scan(&heard, &known)?;
+        }
+        ChannelsAction::Config {
+            name,
+            mute,
+            notify,
+            priority,
+        } => cmd_channels_config(&name, mute, notify, priority)?,
+        ChannelsAction::Stats { since, json } => cmd_channels_stats(since.as_deref(), json)?,
+        ChannelsAction::Import {
+            file,
+            prune,
+            dry_run,
+        } => {
+            let text =
+                std::fs::read_to_string(&file).with_context(|| format!("Failed to read {file}"))?;
+            let manifest = provision::parse_manifest(&file, &text)
+                .with_context(|| format!("Invalid channel file {file}"))?;
+            if manifest.channels.is_empty() {
+                bail!(CliError::InvalidArgs(format!("No channels in {file}")));
+            }
+            apply_channels(&mut proto, manifest.channels, &file, prune, dry_run).await?;
+        }
+        ChannelsAction::Export { output } => {
+            let json = match proto.command("CHANNELS").await? {
+                Response::Json(json) => json,
+                Response::Error(e) => bail!(CliError::Device(e)),
+                Response::Ok(_) => bail!("Unexpected OK response to CHANNELS"),
+            };
+            let mut channels = device_channels(&json);
+            for channel in &mut channels {
+                if channel.name.starts_with('#') {
+                    // Derived from the name on import
+                    channel.psk = None;
+                } else if channel.psk.is_none() {
+                    eprintln!(
+                        "⚠ The device does not report the PSK for '{}'; add it to the file before importing",
+                        channel.name
+                    );
+                }
+            }
+            let file = ChannelsFile { channels };
+            let text = match &output {
+                Some(path) if path.ends_with(".toml") => toml::to_string(&file)?,
+                _ => serde_yaml::to_string(&file)?,
+            };
+            match output {
+                Some(path) => {
+                    std::fs::write(&path, text)
+                        .with_context(|| format!("Failed to write {path}"))?;
+                    println!("✓ Exported channels to {path}");
+                    println!(
+                        "  It holds the channel PSKs: anyone with it can read and post to them."
+                    );
+                }
+                None => print!("{text}"),
+            }
+        }
+        ChannelsAction::Default { name: None } => {
+            let name = proto
+                .default_channel()
+                .await?
+                .unwrap_or_else(|| channels::PUBLIC.to_string());
+            if output::is_plain() {
+                output::kv("default", name);
+            } else {
+                println!("Default channel: {name}");
+            }
+        }
+        ChannelsAction::Default { name: Some(name) } => {
+            let cmd = format!("CHANNEL DEFAULT {name}");
+            match proto.command(&cmd).await? {
+                Response::Ok(_) => {
+                    println!("✓ `send` without --to or --channel now goes to {name}");
+                }
+                Response::Error(e) => bail!(CliError::Device(e)),
+                Response::Json(_) => bail!("Unexpected response to CHANNEL DEFAULT"),
+            }
+        }
         ChannelsAction::Remove { name } => {
             let cmd = format!("CHANNEL LEAVE {name}");
             match proto.command(&cmd).await? {
                 Response::Ok(msg) => {
                     println!("{}", msg.unwrap_or_else(|| "Channel removed".to_string()));
                 }
-                Response::Error(e) => bail!("Device error: {e}"),
+                Response::Error(e) => bail!(CliError::Device(e)),
                 Response::Json(_) => bail!("Unexpected response to CHANNEL LEAVE"),
             }
         }
@@ -227,14 +1181,686 @@ pub async fn cmd_channels(
     Ok(())
 }
 
+/// `channels export` file (YAML, or TOML for a `.toml` output file).
+#[derive(serde::Serialize)]
+struct ChannelsFile {
+    channels: Vec<ChannelSettings>,
+}
+
+/// A change `channels import` makes to the device's channel list.
+#[derive(Debug, PartialEq)]
+enum ChannelChange {
+    Leave(String),
+    Join { name: String, psk: String },
+    SetDefault(String),
+}
+
+/// Custom channels in a `CHANNELS` response, in device order.
+fn device_channels(json: &serde_json::Value) -> Vec<ChannelSettings> {
+    let list = json.get("channels").and_then(|c| c.as_array());
+    list.into_iter()
+        .flatten()
+        .filter(|c| {
+            !c.get("builtin")
+                .and_then(serde_json::Value::as_bool)
+                .unwrap_or(false)
+        })
+        .filter_map(|c| Some((c.get("name")?.as_str()?, c)))
+        .zip(1..)
+        .map(|((name, c), order)| ChannelSettings {
+            name: name.to_string(),
+            psk: c.get("psk").and_then(|p| p.as_str()).map(String::from),
+            order: Some(order),
+            default: c
+                .get("default")
+                .and_then(serde_json::Value::as_bool)
+                .unwrap_or(false),
+        })
+        .collect()
+}
+
+/// The channels of an import in the order they belong on the device, with
+/// hashtag PSKs filled in.
+fn wanted_channels(
+    mut channels: Vec<ChannelSettings>,
+    source: &str,
+) -> Result<Vec<ChannelSettings>> {
+    // Stable, so channels without an order keep their place in the file
+    channels.sort_by_key(|c| c.order.unwrap_or(u32::MAX));
+    let mut names = HashSet::new();
+    for channel in &mut channels {
+        if !names.insert(channel.name.clone()) {
+            bail!(CliError::InvalidArgs(format!(
+                "Channel '{}' is listed twice in {source}",
+                channel.name
+            )));
+        }
+        channel.psk = Some(match channel.psk.take() {
+            Some(psk) => {
+                psk::warn_if_weak(&channel.name, &psk::decode(&psk)?);
+                psk
+            }
+            None if channel.name.starts_with('#') => psk::hashtag_psk(&channel.name),
+            None => bail!(CliError::InvalidArgs(format!(
+                "Channel '{}' in {source} needs a psk",
+                channel.name
+            ))),
+        });
+    }
+    if channels.iter().filter(|c| c.default).count() > 1 {
+        bail!(CliError::InvalidArgs(format!(
+            "More than one default channel in {source}"
+        )));
+    }
+    Ok(channels)
+}
+
+/// Changes that turn the `current` custom channels into `wanted`. Wanted
+/// channels already on the device in the right order with the same PSK are
+/// kept; from the first one that isn't, the rest are (re)joined in order.
+fn plan_channel_import(
+    current: &[ChannelSettings],
+    current_default: Option<&str>,
+    wanted: &[ChannelSettings],
+    prune: bool,
+) -> Vec<ChannelChange> {
+    let is_wanted = |name: &str| wanted.iter().any(|w| w.name == name);
+    let same_key = |a: &Option<String>, b: &Option<String>| match (a, b) {
+        (Some(a), Some(b)) => psk::decode(a).ok() == psk::decode(b).ok(),
+        // The device doesn't report the key; trust it
+        _ => true,
+    };
+    let present: Vec<&ChannelSettings> = current.iter().filter(|c| is_wanted(&c.name)).collect();
+    let kept = present
+        .iter()
+        .zip(wanted)
+        .take_while(|(c, w)| c.name == w.name && same_key(&c.psk, &w.psk))
+        .count();
+
+    let mut changes = Vec::new();
+    if prune {
+        changes.extend(
+            current
+                .iter()
+                .filter(|c| !is_wanted(&c.name))
+                .map(|c| ChannelChange::Leave(c.name.clone())),
+        );
+    }
+    changes.extend(
+        present[kept..]
+            .iter()
+            .map(|c| ChannelChange::Leave(c.name.clone())),
+    );
+    changes.extend(wanted[kept..].iter().map(|w| ChannelChange::Join {
+        name: w.name.clone(),
+        psk: w.psk.clone().unwrap_or_default(),
+    }));
+    if let Some(default) = wanted.iter().find(|w| w.default) {
+        let rejoined = wanted[kept..].iter().any(|w| w.name == default.name);
+        if rejoined || current_default != Some(default.name.as_str()) {
+            changes.push(ChannelChange::SetDefault(default.name.clone()));
+        }
+    }
+    changes
+}
+
+/// Bring the device's channels in line with `channels` from `source`.
+pub(super) async fn apply_channels(
+    proto: &mut Protocol,
+    channels: Vec<ChannelSettings>,
+    source: &str,
+    prune: bool,
+    dry_run: bool,
+) -> Result<()> {
+    let wanted = wanted_channels(channels, source)?;
+    let json = match proto.command("CHANNELS").await? {
+        Response::Json(json) => json,
+        Response::Error(e) => bail!(CliError::Device(e)),
+        Response::Ok(_) => bail!("Unexpected OK response to CHANNELS"),
+    };
+    let current_default = json
+        .get("channels")
+        .and_then(|c| c.as_array())
+        .into_iter()
+        .flatten()
+        .find(|c| c.get("default").and_then(serde_json::Value::as_bool) == Some(true))
+        .and_then(|c| c.get("name")?.as_str());
+    let changes = plan_channel_import(&device_channels(&json), current_default, &wanted, prune);
+    if changes.is_empty() {
+        println!(
+            "✓ Channels already match {source} ({} channels)",
+            wanted.len()
+        );
+        return Ok(());
+    }
+
+    for change in &changes {
+        let (cmd, line) = match change {
+            ChannelChange::Leave(name) => (format!("CHANNEL LEAVE {name}"), format!("- {name}")),
+            ChannelChange::Join { name, psk } => {
+                (format!("CHANNEL JOIN {name} {psk}"), format!("+ {name}"))
+            }
+            ChannelChange::SetDefault(name) => (
+                format!("CHANNEL DEFAULT {name}"),
+                format!("* {name} (default)"),
+            ),
+        };
+        if dry_run {
+            println!("  {line}");
+            continue;
+        }
+        match proto.command(&cmd).await? {
+            Response::Ok(_) => println!("  {line}"),
+            Response::Error(e) => bail!(CliError::Device(format!("{line}: {e}"))),
+            Response::Json(_) => bail!("Unexpected JSON response to CHANNEL command"),
+        }
+    }
+    if dry_run {
+        println!("Dry run: {} changes not made", changes.len());
+    } else {
+        println!("✓ Applied {} channel changes from {source}", changes.len());
+    }
+    Ok(())
+}
+
+/// A channel on the device, with its PSK when it is known.
+struct KnownChannel {
+    name: String,
+    hash: u8,
+    key: Option<Vec<u8>>,
+}
+
+/// Channels from a `CHANNELS` response. Hashtag and public channel PSKs are
+/// derived when the device doesn't report them.
+fn known_channels(json: &serde_json::Value) -> Vec<KnownChannel> {
+    let channels = json.get("channels").and_then(|c| c.as_array());
+    channels
+        .into_iter()
+        .flatten()
+        .filter_map(|channel| {
+            let name = channel.get("name").and_then(|n| n.as_str())?.to_string();
+            let psk = match channel.get("psk").and_then(|p| p.as_str()) {
+                Some(psk) => Some(psk.to_string()),
+                None if name.starts_with('#') => Some(psk::hashtag_psk(&name)),
+                None if name.eq_ignore_ascii_case("public") => {
+                    Some(packet::PUBLIC_CHANNEL_PSK.to_string())
+                }
+                None => None,
+            };
+            let key = psk.and_then(|p| psk::decode(&p).ok());
+            let hash = match &key {
+                Some(key) => packet::channel_hash(key),
+                None => {
+                    let hash = channel.get("hash").and_then(|h| h.as_str())?;
+                    u8::from_str_radix(hash.trim_start_matches("0x"), 16).ok()?
+                }
+            };
+            Some(KnownChannel { name, hash, key })
+        })
+        .collect()
+}
+
+/// Traffic heard on one channel hash during `channels scan`.
+#[derive(Default)]
+struct ChannelActivity {
+    packets: u32,
+    /// Sender names of messages we could decrypt
+    senders: HashSet<String>,
+    /// First repeater of each packet (None when heard directly), which tells
+    /// senders apart roughly when the messages can't be read
+    routes: HashSet<Option<u8>>,
+    /// Channel whose PSK decrypted a message
+    decrypted_by: Option<String>,
+    last_heard: Option<Instant>,
+}
+
+impl ChannelActivity {
+    fn record(&mut self, packet: &Packet, known: &[KnownChannel], at: Instant) {
+        self.packets += 1;
+        self.last_heard = Some(at);
+        self.routes.insert(packet.path.first().copied());
+        if packet.payload_type != PayloadType::GrpTxt {
+            return;
+        }
+        let hash = packet.payload[0];
+        for channel in known.iter().filter(|c| c.hash == hash) {
+            let Some(key) = &channel.key else { continue };
+            if let Some((_, text)) = packet::decrypt_group_text(key, &packet.payload) {
+                // Channel messages read "<sender>: <text>"
+                if let Some((sender, _)) = text.split_once(": ") {
+                    self.senders.insert(sender.to_string());
+                }
+                self.decrypted_by = Some(channel.name.clone());
+                return;
+            }
+        }
+    }
+
+    /// Distinct senders, or `~N` estimated from routes for unreadable channels.
+    fn senders(&self) -> String {
+        if self.decrypted_by.is_some() {
+            self.senders.len().to_string()
+        } else {
+            format!("~{}", self.routes.len())
+        }
+    }
+}
+
+fn report_channel_scan(
+    heard: &BTreeMap<u8, ChannelActivity>,
+    known: &[KnownChannel],
+) -> Result<()> {
+    let mut active: Vec<(&u8, &ChannelActivity)> = heard.iter().collect();
+    active.sort_by_key(|(_, activity)| std::cmp::Reverse(activity.packets));
+    let matches = |hash: u8, activity: &ChannelActivity| -> Vec<String> {
+        match &activity.decrypted_by {
+            Some(name) => vec![name.clone()],
+            None => known
+                .iter()
+                .filter(|c| c.hash == hash)
+                .map(|c| c.name.clone())
+                .collect(),
+        }
+    };
+
+    if output::is_plain() {
+        let channels: Vec<serde_json::Value> = active
+            .iter()
+            .map(|(&hash, activity)| {
+                serde_json::json!({
+                    "hash": format!("{hash:02x}"),
+                    "packets": activity.packets,
+                    "senders": activity.senders(),
+                    "channel": matches(hash, activity).join(","),
+                    "decrypted": activity.decrypted_by.is_some(),
+                })
+            })
+            .collect();
+        let json = serde_json::json!({ "channels": channels });
+        for (key, value) in output::flatten_json(&json) {
+            output::kv(&key, value);
+        }
+        return Ok(());
+    }
+
+    if active.is_empty() {
+        println!("\nNo channel traffic heard");
+        return Ok(());
+    }
+    println!("\nActive channels ({}):\n", active.len());
+    println!(
+        "  {:4} {:>7} {:>7} {:>10}  Channel",
+        "Hash", "Packets", "Senders", "Last heard"
+    );
+    println!(
+        "  {:-<4} {:->7} {:->7} {:->10}  {:-<20}",
+        "", "", "", "", ""
+    );
+    for (&hash, activity) in &active {
+        let ago = activity.last_heard.map_or(0, |t| t.elapsed().as_secs());
+        let names = matches(hash, activity);
+        let channel = match (&activity.decrypted_by, names.is_empty()) {
+            (Some(name), _) => format!("✓ {name}"),
+            (None, true) => "no matching PSK".to_string(),
+            (None, false) => format!("⚠ {} (hash matches, not decrypted)", names.join(", ")),
+        };
+        println!(
+            "  {hash:02x}   {:>7} {:>7} {:>10}  {channel}",
+            activity.packets,
+            activity.senders(),
+            timefmt::ago(ago)
+        );
+    }
+    if active.iter().any(|(_, a)| a.decrypted_by.is_none()) {
+        println!("\n~N: senders estimated from the routes packets arrived by, as the");
+        println!("    messages can't be read without the channel's PSK");
+    }
+    Ok(())
+}
+
+/// Show or set the notification preference of a channel
+pub fn cmd_channels_config(name: &str, mute: bool, notify: bool, priority: bool) -> Result<()> {
+    let level = match (mute, notify, priority) {
+        (true, _, _) => Level::Mute,
+        (_, true, _) => Level::Notify,
+        (_, _, true) => Level::Priority,
+        _ => {
+            let level = channels::load()?.get(Some(name));
+            if output::is_plain() {
+                output::kv("notify", level);
+            } else {
+                println!("{name}: {level}");
+            }
+            return Ok(());
+        }
+    };
+    channels::set(name, level)?;
+    if !output::is_plain() {
+        match level {
+            Level::Mute => println!("✓ Muted {name}"),
+            Level::Notify => println!("✓ {name} notifies as usual"),
+            Level::Priority => println!("✓ {name} has priority"),
+        }
+    }
+    Ok(())
+}
+
+/// Per-channel statistics of the recorded message history
+pub fn cmd_channels_stats(since: Option<&str>, json: bool) -> Result<()> {
+    let since = since.map(history::parse_since).transpose()?;
+    let entries = history::read()?;
+    let stats = history::stats(&entries, since);
+
+    let mut channels: Vec<(&String, &history::ChannelStats)> = stats.iter().collect();
+    channels.sort_by_key(|(_, s)| std::cmp::Reverse(s.messages));
+    let report = || -> serde_json::Value {
+        let channels: Vec<serde_json::Value> = channels
+            .iter()
+            .map(|(name, s)| {
+                let busiest: Vec<serde_json::Value> = s
+                    .busiest_hours(3)
+                    .into_iter()
+                    .map(|(hour, messages)| serde_json::json!({ "hour": hour, "messages": messages }))
+                    .collect();
+                serde_json::json!({
+                    "channel": name,
+                    "messages": s.messages,
+                    "senders": s.senders,
+                    "avg_rssi": (s.avg_rssi * 10.0).round() / 10.0,
+                    "busiest_hours": busiest,
+                    "hourly": s.hourly,
+                })
+            })
+            .collect();
+        serde_json::json!({
+            "since": since.map(|t| t.to_rfc3339_opts(chrono::SecondsFormat::Secs, false)),
+            "channels": channels,
+        })
+    };
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report())?);
+        return Ok(());
+    }
+    if output::is_plain() {
+        for (key, value) in output::flatten_json(&report()) {
+            output::kv(&key, value);
+        }
+        return Ok(());
+    }
+
+    if entries.is_empty() {
+        println!("No message history yet (`monitor` and `ui` record received messages)");
+        return Ok(());
+    }
+    let total: u32 = channels.iter().map(|(_, s)| s.messages).sum();
+    match since {
+        Some(since) => println!(
+            "Channel activity since {} ({total} messages):\n",
+            since.format("%Y-%m-%d %H:%M")
+        ),
+        None => println!("Channel activity ({total} messages):\n"),
+    }
+    if channels.is_empty() {
+        println!("  No messages in this period");
+        return Ok(());
+    }
+    println!(
+        "  {:16} {:>8} {:>7} {:>8}  Busiest hours",
+        "Channel", "Messages", "Senders", "Avg RSSI"
+    );
+    println!(
+        "  {:-<16} {:->8} {:->7} {:->8}  {:-<24}",
+        "", "", "", "", ""
+    );
+    for (name, s) in &channels {
+        let busiest: Vec<String> = s
+            .busiest_hours(3)
+            .into_iter()
+            .map(|(hour, messages)| format!("{hour:02}h ({messages})"))
+            .collect();
+        println!(
+            "  {name:16} {:>8} {:>7} {:>8}  {}",
+            s.messages,
+            s.senders,
+            format!("{:.0} dB", s.avg_rssi),
+            busiest.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Review channel PSKs in fleet export files
+pub fn cmd_channels_audit(files: &[String]) -> Result<()> {
+    let mut channels = Vec::new();
+    for file in files {
+        let text =
+            std::fs::read_to_string(file).with_context(|| format!("Failed to read {file}"))?;
+        if file.ends_with(".toml") || provision::is_yaml(file) {
+            let manifest = provision::parse_manifest(file, &text)
+                .with_context(|| format!("Invalid provisioning manifest {file}"))?;
+            channels.extend(manifest.channels.into_iter().map(|c| AuditedChannel {
+                source: Some(file.clone()),
+                name: c.name,
+                psk: c.psk,
+                builtin: false,
+            }));
+        } else {
+            let json: serde_json::Value = serde_json::from_str(&text)
+                .with_context(|| format!("{file} is not a provisioning manifest or JSON export"))?;
+            channels.extend(exported_channels(&json, Some(file)));
+        }
+    }
+    report_channel_audit(&channels)
+}
+
+/// Channel under review by `channels audit`.
+struct AuditedChannel {
+    /// File it came from, if not the connected device
+    source: Option<String>,
+    name: String,
+    psk: Option<String>,
+    builtin: bool,
+}
+
+/// Channels in `CHANNELS` output, either `{"channels": [...]}` or a bare array.
+fn exported_channels(json: &serde_json::Value, source: Option<&String>) -> Vec<AuditedChannel> {
+    let list = json.get("channels").unwrap_or(json);
+    list.as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|c| {
+            Some(AuditedChannel {
+                source: source.cloned(),
+                name: c.get("name")?.as_str()?.to_string(),
+                psk: c.get("psk").and_then(|p| p.as_str()).map(String::from),
+                builtin: c
+                    .get("builtin")
+                    .and_then(serde_json::Value::as_bool)
+                    .unwrap_or(false),
+            })
+        })
+        .collect()
+}
+
+/// Print findings for each channel; fails if any key is weak or reused.
+fn report_channel_audit(channels: &[AuditedChannel]) -> Result<()> {
+    let label = |c: &AuditedChannel| match &c.source {
+        Some(source) => format!("{} ({source})", c.name),
+        None => c.name.clone(),
+    };
+    if channels.is_empty() {
+        println!("No custom channels to audit.");
+        return Ok(());
+    }
+    println!("Channel audit ({} channels):\n", channels.len());
+
+    let mut flagged = 0;
+    for (i, channel) in channels.iter().enumerate() {
+        let mut problems = Vec::new();
+        let mut note = None;
+        if channel.name.starts_with('#') {
+            note = Some("public hashtag channel");
+        } else {
+            match channel.psk.as_deref().map(psk::decode) {
+                None => note = Some("PSK not reported, not checked"),
+                Some(Err(e)) => problems.push(e.to_string()),
+                Some(Ok(key)) => {
+                    problems.extend(
+                        psk::weaknesses(&channel.name, &key)
+                            .into_iter()
+                            .map(|w| format!("PSK {w}")),
+                    );
+                    for other in &channels[..i] {
+                        let Some(other_key) =
+                            other.psk.as_deref().and_then(|p| psk::decode(p).ok())
+                        else {
+                            continue;
+                        };
+                        if other.name != channel.name && other_key == key {
+                            problems.push(format!("same PSK as {}", label(other)));
+                        } else if other.name == channel.name && other_key != key {
+                            problems.push(format!("PSK differs from {}", label(other)));
+                        }
+                    }
+                }
+            }
+        }
+
+        let mark = if !problems.is_empty() {
+            "✗"
+        } else if note == Some("PSK not reported, not checked") {
+            "?"
+        } else {
+            "✓"
+        };
+        println!(
+            "  {mark} {}{}",
+            label(channel),
+            note.map(|n| format!(" - {n}")).unwrap_or_default()
+        );
+        for problem in &problems {
+            println!("      {problem}");
+        }
+        if !problems.is_empty() {
+            flagged += 1;
+        }
+    }
+
+    if flagged > 0 {
+        bail!("{flagged} channel(s) with weak, reused or inconsistent PSKs");
+    }
+    println!("\n✓ No weak or reused PSKs found");
+    Ok(())
+}
+
+/// PSK the device reports for a channel in `CHANNELS`, if any.
+async fn reported_psk(proto: &mut Protocol, name: &str) -> Result<Option<String>> {
+    let json = match proto.command("CHANNELS").await? {
+        Response::Json(json) => json,
+        Response::Error(e) => bail!(CliError::Device(e)),
+        Response::Ok(_) => bail!("Unexpected OK response to CHANNELS"),
+    };
+    let channel = json
+        .get("channels")
+        .and_then(|c| c.as_array())
+        .and_then(|channels| {
+            channels
+                .iter()
+                .find(|c| c.get("name").and_then(|n| n.as_str()) == Some(name))
+        });
+    let Some(channel) = channel else {
+        bail!(CliError::InvalidArgs(format!(
+            "No channel '{name}' on the device"
+        )));
+    };
+    Ok(channel
+        .get("psk")
+        .and_then(|p| p.as_str())
+        .map(String::from))
+}
+
+/// Share link for a channel: `meshgrid://channel?name=...&psk=...`.
+fn channel_link(name: &str, psk: &str) -> Result<String> {
+    psk::decode(psk)?;
+    let mut link = url::Url::parse("meshgrid://channel").expect("valid base URL");
+    link.query_pairs_mut()
+        .append_pair("name", name)
+        .append_pair("psk", psk);
+    Ok(link.to_string())
+}
+
+/// Channel name and PSK from a share link.
+fn parse_channel_link(link: &str) -> Result<(String, String)> {
+    let invalid = || CliError::InvalidArgs(format!("Not a channel share link: '{link}'"));
+    let url = url::Url::parse(link.trim()).map_err(|_| invalid())?;
+    if url.scheme() != "meshgrid" || url.host_str() != Some("channel") {
+        bail!(invalid());
+    }
+    let param = |key: &str| {
+        url.query_pairs()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.into_owned())
+    };
+    let (Some(name), Some(psk)) = (param("name"), param("psk")) else {
+        bail!(invalid());
+    };
+    psk::decode(&psk)?;
+    Ok((name, psk))
+}
+
+/// Decode the share link in a QR code image with `zbarimg`.
+fn read_qr_image(path: &str) -> Result<String> {
+    let output = match std::process::Command::new("zbarimg")
+        .args(["--raw", "-q", path])
+        .output()
+    {
+        Ok(output) => output,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            bail!("Reading QR images needs zbarimg (zbar-tools); pass the link with --url instead")
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let text = String::from_utf8_lossy(&output.stdout);
+    match text.lines().find(|l| l.starts_with("meshgrid://channel")) {
+        Some(link) => Ok(link.to_string()),
+        None => bail!("No channel share link found in {path}"),
+    }
+}
+
 /// Rotate device identity (generate new keypair)
-pub async fn cmd_rotate_identity(port: &str, baud: u32, pin: Option<&str>) -> Result<()> {
-    let dev = connect_with_auth(port, baud, pin).await?;
+pub async fn cmd_rotate_identity(
+    port: &str,
+    baud: u32,
+    pin: Option<&str>,
+    schedule: Option<&str>,
+    announce: bool,
+) -> Result<()> {
+    let mut at = schedule.map(parse_schedule).transpose()?;
+    let mut dev = connect_with_auth(port, baud, pin).await?;
+    let info = dev.get_info().await?;
     let mut proto = dev.into_protocol();
 
     println!("WARNING: This will generate a new keypair and clear all encrypted data.");
     println!("         Old messages and neighbor secrets will be deleted.");
     println!("         Other nodes will need to re-discover your new identity.\n");
+    let target = Target::new(Some(port), info.name.as_deref(), Some(&info.public_key));
+    if !confirm(Operation::RotateIdentity, &target)? {
+        return Ok(());
+    }
+
+    if announce {
+        let effective = at.unwrap_or_else(|| chrono::Local::now() + ANNOUNCE_LEAD);
+        announce_rotation(&mut proto, effective.timestamp()).await?;
+        at = Some(effective);
+    }
+
+    if let Some(at) = at {
+        println!("Rotating at {}", at.format("%Y-%m-%d %H:%M:%S"));
+        drop(proto);
+        let wait = (at - chrono::Local::now()).to_std().unwrap_or_default();
+        tokio::time::sleep(wait).await;
+        proto = connect_with_auth(port, baud, pin).await?.into_protocol();
+    }
 
     match proto.command("IDENTITY ROTATE").await? {
         Response::Ok(msg) => {
@@ -244,7 +1870,213 @@ pub async fn cmd_rotate_identity(port: &str, baud: u32, pin: Option<&str>) -> Re
             );
             Ok(())
         }
-        Response::Error(e) => bail!("Device error: {e}"),
+        Response::Error(e) => bail!(CliError::Device(e)),
         Response::Json(_) => bail!("Unexpected response to IDENTITY ROTATE"),
     }
 }
+
+/// Time between announcing a new key and switching to it, when no
+/// `--schedule` is given.
+const ANNOUNCE_LEAD: chrono::TimeDelta = chrono::TimeDelta::seconds(60);
+
+/// Have the device generate its next key, and send it to every trusted
+/// contact while the current key is still in use.
+async fn announce_rotation(proto: &mut Protocol, effective: i64) -> Result<()> {
+    let new_key = match proto.command("IDENTITY PREPARE").await? {
+        Response::Ok(Some(key)) => key.trim().to_ascii_lowercase(),
+        Response::Error(e) => bail!(CliError::Device(format!(
+            "firmware cannot pre-generate a key for --announce: {e}"
+        ))),
+        _ => bail!("Unexpected response to IDENTITY PREPARE"),
+    };
+    let announcement = contacts::rotation_announcement(&new_key, effective);
+    if contacts::parse_rotation_announcement(&announcement).is_none() {
+        bail!("Device returned an invalid public key: {new_key}");
+    }
+    println!("New public key: {new_key}");
+
+    let names: std::collections::BTreeSet<String> = contacts::trusted()?
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    if names.is_empty() {
+        println!("No trusted contacts to announce to (see `contacts trust`)");
+    }
+    for name in &names {
+        match proto
+            .command(&format!("SEND {name} {announcement}"))
+            .await?
+        {
+            Response::Ok(_) => println!("  ✓ Announced to {name}"),
+            Response::Error(e) => println!("  ✗ {name}: {e}"),
+            Response::Json(_) => bail!("Unexpected response to SEND"),
+        }
+    }
+    Ok(())
+}
+
+/// Parse a `--schedule` or `--expires` time; it must be in the future.
+pub(super) fn parse_schedule(s: &str) -> Result<chrono::DateTime<chrono::Local>> {
+    use chrono::{DateTime, Local, NaiveDateTime, NaiveTime, TimeDelta, TimeZone};
+
+    let invalid = || {
+        CliError::InvalidArgs(format!(
+            "Invalid time '{s}' (use +30m, +2h, +1d, HH:MM, YYYY-MM-DD HH:MM or RFC 3339)"
+        ))
+    };
+    let now = Local::now();
+    let at = if let Some(relative) = s.strip_prefix('+').filter(|r| r.is_ascii()) {
+        let (count, unit) = relative.split_at(relative.len().saturating_sub(1));
+        let count: i64 = count.parse().map_err(|_| invalid())?;
+        let unit = match unit {
+            "m" => TimeDelta::minutes(1),
+            "h" => TimeDelta::hours(1),
+            "d" => TimeDelta::days(1),
+            _ => bail!(invalid()),
+        };
+        now + unit * i32::try_from(count).map_err(|_| invalid())?
+    } else if let Ok(t) = DateTime::parse_from_rfc3339(s) {
+        t.with_timezone(&Local)
+    } else if let Ok(t) = NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M") {
+        Local.from_local_datetime(&t).single().ok_or_else(invalid)?
+    } else if let Ok(t) = NaiveTime::parse_from_str(s, "%H:%M") {
+        let today = Local
+            .from_local_datetime(&now.date_naive().and_time(t))
+            .single()
+            .ok_or_else(invalid)?;
+        if today > now {
+            today
+        } else {
+            today + TimeDelta::days(1)
+        }
+    } else {
+        bail!(invalid());
+    };
+    if at <= now {
+        bail!(CliError::InvalidArgs(format!("Time '{s}' is in the past")));
+    }
+    Ok(at)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_schedule() {
+        let in_two_hours = parse_schedule("+2h").unwrap() - chrono::Local::now();
+        assert!(in_two_hours > chrono::TimeDelta::minutes(119));
+        assert!(parse_schedule("12:30").is_ok());
+        assert!(parse_schedule("+2x").is_err());
+        assert!(parse_schedule("2000-01-01 00:00").is_err());
+    }
+
+    #[test]
+    fn test_inbox_join_parts() {
+        let message = |from_hash: &str, timestamp: u64, text: &str| InboxMessage {
+            timestamp,
+            from_name: None,
+            from_hash: from_hash.into(),
+            channel: "direct".into(),
+            protocol: None,
+            decrypted: true,
+            text: text.into(),
+        };
+        let long = "lorem ipsum ".repeat(20);
+        let parts = fragment::split(&long).unwrap();
+        assert_eq!(parts.len(), 2);
+        let inbox = join_parts(vec![
+            message("a1", 1, &parts[0]),
+            message("b2", 2, "hi"),
+            message("a1", 3, &parts[1]),
+            message("a1", 4, "[0f 2/3] lost its first part"),
+        ]);
+        let texts: Vec<(u64, &str)> = inbox
+            .iter()
+            .map(|m| (m.timestamp, m.text.as_str()))
+            .collect();
+        assert_eq!(
+            texts,
+            [
+                (1, long.as_str()),
+                (2, "hi"),
+                (4, "[…] lost its first part […]")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_channel_link() {
+        let psk = psk::hashtag_psk("#test");
+        let link = channel_link("#test", &psk).unwrap();
+        assert!(link.starts_with("meshgrid://channel?name=%23test&psk="));
+        assert_eq!(
+            parse_channel_link(&link).unwrap(),
+            ("#test".to_string(), psk)
+        );
+        assert!(parse_channel_link("meshgrid://contact/ab?name=x").is_err());
+        assert!(channel_link("ops", "c2hvcnQ=").is_err());
+    }
+
+    #[test]
+    fn test_plan_channel_import() {
+        let ops = "AAECAwQFBgcICQoLDA0ODw==";
+        let team = "EBESExQVFhcYGRobHB0eHw==";
+        let file = format!(
+            "[[channels]]\nname = \"ops\"\npsk = \"{ops}\"\norder = 2\n\n\
+             [[channels]]\nname = \"team\"\npsk = \"{team}\"\n\n\
+             [[channels]]\nname = \"#hike\"\norder = 1\ndefault = true\n"
+        );
+        let manifest = provision::parse_manifest("group.toml", &file).unwrap();
+        let yaml = format!(
+            "channels:\n\
+             - name: ops\n  psk: {ops}\n  order: 2\n\
+             - name: team\n  psk: {team}\n\
+             - name: '#hike'\n  order: 1\n  default: true\n"
+        );
+        let from_yaml = provision::parse_manifest("group.yaml", &yaml).unwrap();
+        assert_eq!(from_yaml.channels, manifest.channels);
+        let exported = serde_yaml::to_string(&ChannelsFile {
+            channels: manifest.channels.clone(),
+        })
+        .unwrap();
+        assert_eq!(
+            provision::parse_manifest("group.yml", &exported)
+                .unwrap()
+                .channels,
+            manifest.channels
+        );
+        let wanted = wanted_channels(manifest.channels, "test").unwrap();
+        let names: Vec<&str> = wanted.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["#hike", "ops", "team"]);
+
+        let device = serde_json::json!({"channels": [
+            {"name": "Public", "builtin": true, "default": true},
+            {"name": "#hike"},
+            {"name": "ops", "psk": ops},
+            {"name": "old", "psk": team},
+        ]});
+        let current = device_channels(&device);
+        assert_eq!(
+            plan_channel_import(&current, Some("Public"), &wanted, true),
+            [
+                ChannelChange::Leave("old".into()),
+                ChannelChange::Join {
+                    name: "team".into(),
+                    psk: team.into()
+                },
+                ChannelChange::SetDefault("#hike".into()),
+            ]
+        );
+
+        // Importing again changes nothing; a changed key rejoins from there
+        let mut applied = wanted.clone();
+        applied[0].psk = None;
+        assert!(plan_channel_import(&applied, Some("#hike"), &wanted, false).is_empty());
+        applied[1].psk = Some(team.into());
+        assert_eq!(
+            plan_channel_import(&applied, Some("#hike"), &wanted, false).len(),
+            4
+        );
+    }
+}
@@ -1,31 +1,121 @@
 //! Command implementations
 
+pub mod audit;
+pub mod batch;
+pub mod bench;
+pub mod bridge;
+pub mod collector;
 pub mod config;
+pub mod contacts;
+pub mod crashlog;
+pub mod events;
+pub mod export;
+pub mod flashfs;
+pub mod fleet;
+pub mod fuzz;
+pub mod gps;
 pub mod info;
+pub mod log;
+pub mod map;
 pub mod messaging;
 pub mod network;
+pub mod nodes;
+pub mod nvs;
+pub mod permissions;
+pub mod plan;
+pub mod plugin;
+pub mod position;
+pub mod probe;
+pub mod provision;
+pub mod recover;
+pub mod replay;
+pub mod rf;
+pub mod routes;
+pub mod rpc;
+pub mod serve;
+pub mod share;
+pub mod sim;
 pub mod system;
+pub mod timeline;
+pub mod track;
+pub mod update;
 pub mod util;
+pub mod vault;
+pub mod watchdog;
+pub mod waypoints;
 
 // Re-export command functions
+pub use audit::*;
+pub use batch::*;
+pub use bench::*;
+pub use bridge::*;
+pub use collector::*;
 pub use config::*;
+pub use contacts::*;
+pub use crashlog::*;
+pub use events::*;
+pub use export::*;
+pub use flashfs::*;
+pub use fleet::*;
+pub use fuzz::*;
+pub use gps::*;
 pub use info::*;
+pub use log::*;
+pub use map::*;
 pub use messaging::*;
 pub use network::*;
+pub use nodes::*;
+pub use nvs::*;
+pub use permissions::*;
+pub use plan::*;
+pub use plugin::*;
+pub use position::*;
+pub use provision::*;
+pub use recover::*;
+pub use replay::*;
+pub use rf::*;
+pub use routes::*;
+pub use rpc::*;
+pub use serve::*;
+pub use share::*;
+pub use sim::*;
 pub use system::*;
+pub use timeline::*;
+pub use track::*;
+pub use update::*;
 pub use util::*;
+pub use vault::*;
+pub use watchdog::*;
+pub use waypoints::*;
 
+use crate::credentials;
 use crate::device::Device;
-use anyhow::Result;
+use anyhow::{Context, Result};
 
-/// Connect to device and authenticate if PIN provided
+/// Connect to device and authenticate if PIN provided (or saved with `auth save`)
 pub async fn connect_with_auth(port: &str, baud: u32, pin: Option<&str>) -> Result<Device> {
     let mut dev = Device::connect(port, baud).await?;
 
     // Authenticate if PIN provided
     if let Some(pin_str) = pin {
         dev.authenticate(pin_str).await?;
+    } else if let Some(saved) = saved_pin(&mut dev).await {
+        dev.authenticate(&saved)
+            .await
+            .context("Saved PIN was rejected (update it with `auth save`)")?;
     }
 
     Ok(dev)
 }
+
+/// PIN saved for the connected device, if any.
+async fn saved_pin(dev: &mut Device) -> Option<String> {
+    if !credentials::any_saved() {
+        return None;
+    }
+    let info = dev.get_info().await.ok()?;
+    credentials::load(&hex::encode(info.public_key)).unwrap_or_else(|e| {
+        tracing::warn!("{e:#}");
+        None
+    })
+}
@@ -1,23 +1,355 @@
 //! Network and radio commands
 
 use super::connect_with_auth;
+use super::contacts::{remember_neighbors, resolve_node};
+use super::require_port;
+use crate::capture;
+use crate::cli::RawAction;
 use crate::device::Device;
-use anyhow::Result;
+use crate::error::CliError;
+use crate::hexdump;
+use crate::nodes::{self, CachedNode};
+use crate::output;
+use crate::packet::{self, Fields, Packet};
+use crate::protocol::{Protocol, TraceResult};
+use anyhow::{bail, Result};
+use serde::Serialize;
+use std::time::Duration;
 
-pub async fn cmd_trace(port: &str, baud: u32, pin: Option<&str>, target: &str) -> Result<()> {
-    let mut dev = connect_with_auth(port, baud, pin).await?;
+/// Pause between traces with `--count`, so one has cleared the mesh before
+/// the next.
+const TRACE_GAP: Duration = Duration::from_secs(2);
+
+/// Trace the route to `target`, `count` times to show which paths the mesh
+/// takes and how round-trip times spread; without a target, to every node
+/// the device knows.
+pub async fn cmd_trace(
+    port: &str,
+    baud: u32,
+    pin: Option<&str>,
+    target: Option<&str>,
+    refresh: bool,
+    count: u32,
+) -> Result<()> {
+    let mut proto = connect_with_auth(port, baud, pin).await?.into_protocol();
+    let structured = output::is_structured();
+
+    // clap requires --all when the target is missing
+    let Some(target) = target else {
+        return trace_all(&mut proto, port, count).await;
+    };
+
+    let (address, name, label) = match resolve_node(&mut proto, port, target, refresh).await {
+        Some(node) => {
+            let hash = format!("0x{:02x}", node.node_hash);
+            let label = format!("{} ({hash})", node.label());
+            (hash, node.label(), label)
+        }
+        None => (target.to_string(), target.to_string(), target.to_string()),
+    };
+
+    if count == 1 && !structured {
+        println!("Tracing route to {label}...\n");
+        let trace = proto.trace(&address).await?;
+        println!("Route: {}", trace.path.join(" -> "));
+        println!("Hops: {}", trace.hop_count);
+        println!("RTT: {} ms", trace.rtt_ms);
+        return Ok(());
+    }
+
+    if !structured {
+        println!("Tracing route to {label} {count} times...\n");
+    }
+    let mut traces = Vec::new();
+    for i in 1..=count {
+        if i > 1 {
+            tokio::time::sleep(TRACE_GAP).await;
+        }
+        let trace = probe(&mut proto, &address).await?;
+        if !structured {
+            match &trace {
+                Some(t) => println!(
+                    "  {i:>3}/{count}  {:>5} ms  {}",
+                    t.rtt_ms,
+                    t.path.join(" -> ")
+                ),
+                None => println!("  {i:>3}/{count}  timeout"),
+            }
+        }
+        traces.push(trace);
+    }
+
+    let summary = TraceSummary::new(&name, &traces);
+    if structured {
+        return output::print(&summary);
+    }
+    if summary.received == 0 {
+        bail!(CliError::Timeout(format!(
+            "No trace response from {label} in {count} tries"
+        )));
+    }
+    println!();
+    summary.print();
+    Ok(())
+}
 
-    println!("Tracing route to {target}...\n");
+/// One trace to `address`, `None` if it timed out.
+async fn probe(proto: &mut Protocol, address: &str) -> Result<Option<TraceResult>> {
+    match proto.trace(address).await {
+        Ok(trace) => Ok(Some(trace)),
+        Err(e) if matches!(e.downcast_ref::<CliError>(), Some(CliError::Timeout(_))) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
 
-    let trace = dev.trace(target).await?;
+/// Trace every node in the device's neighbor table and the node database,
+/// `count` times each, one table line per node. Sends go through the
+/// duty-cycle budget like any other, so a long sweep slows down rather than
+/// flooding the channel.
+async fn trace_all(proto: &mut Protocol, port: &str, count: u32) -> Result<()> {
+    let structured = output::is_structured();
+    let neighbors = proto.get_neighbors().await?;
+    let mut targets: Vec<CachedNode> = neighbors.iter().map(CachedNode::from).collect();
+    // Nodes heard before but out of range now are worth a try as well
+    if let Some(device_key) = remember_neighbors(proto, port, &neighbors).await {
+        for node in nodes::nodes(&device_key).unwrap_or_default() {
+            if !targets.iter().any(|t| t.node_hash == node.node_hash) {
+                targets.push(node);
+            }
+        }
+    }
+    if targets.is_empty() {
+        bail!(CliError::Device(
+            "No known nodes to trace: the neighbor table is empty".into()
+        ));
+    }
 
-    println!("Route: {}", trace.path.join(" -> "));
-    println!("Hops: {}", trace.hop_count);
-    println!("RTT: {} ms", trace.rtt_ms);
+    if !structured {
+        println!(
+            "Tracing {} nodes{}...\n",
+            targets.len(),
+            if count > 1 {
+                format!(", {count} times each")
+            } else {
+                String::new()
+            }
+        );
+        println!(
+            "  {:<20}  {:>4}  {:>8}  {:>8}  STATUS",
+            "NODE", "HOPS", "RTT (ms)", "ANSWERED"
+        );
+    }
+    let mut sweep = Vec::new();
+    let mut first = true;
+    for node in &targets {
+        let address = format!("0x{:02x}", node.node_hash);
+        let mut traces = Vec::new();
+        let mut error = None;
+        for _ in 0..count {
+            if !first {
+                tokio::time::sleep(TRACE_GAP).await;
+            }
+            first = false;
+            match probe(proto, &address).await {
+                Ok(trace) => traces.push(trace),
+                // The device refusing one node shouldn't end the sweep
+                Err(e) if matches!(e.downcast_ref::<CliError>(), Some(CliError::Device(_))) => {
+                    error = Some(e.to_string());
+                    break;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        let entry = SweepEntry {
+            node_hash: address,
+            summary: TraceSummary::new(&node.label(), &traces),
+            error,
+        };
+        if !structured {
+            entry.print();
+        }
+        sweep.push(entry);
+    }
 
+    if structured {
+        return output::print(&sweep);
+    }
+    let reachable = sweep.iter().filter(|e| e.summary.received > 0).count();
+    let mark = if reachable == sweep.len() {
+        "✓"
+    } else {
+        "⚠"
+    };
+    println!("\n{mark} {reachable} of {} nodes reachable", sweep.len());
     Ok(())
 }
 
+/// Result of `trace --all` for one node.
+#[derive(Debug, Serialize)]
+struct SweepEntry {
+    node_hash: String,
+    #[serde(flatten)]
+    summary: TraceSummary,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl SweepEntry {
+    /// Short verdict for the table.
+    fn status(&self) -> String {
+        let summary = &self.summary;
+        if let Some(error) = &self.error {
+            return format!("✗ {error}");
+        }
+        if summary.received == 0 {
+            return "✗ no response".into();
+        }
+        let lost = summary.sent - summary.received;
+        match (summary.paths.len(), lost) {
+            (1, 0) => "✓".into(),
+            (1, _) => format!("⚠ {lost} lost"),
+            (n, 0) => format!("⚠ {n} paths"),
+            (n, _) => format!("⚠ {n} paths, {lost} lost"),
+        }
+    }
+
+    fn print(&self) {
+        let summary = &self.summary;
+        // Hops of the most frequent path
+        let hops = summary
+            .paths
+            .first()
+            .map_or("-".to_string(), |p| p.hops.to_string());
+        let rtt = summary
+            .rtt_ms
+            .as_ref()
+            .map_or("-".to_string(), |r| r.median.to_string());
+        println!(
+            "  {:<20}  {hops:>4}  {rtt:>8}  {:>8}  {}",
+            summary.target,
+            format!("{}/{}", summary.received, summary.sent),
+            self.status()
+        );
+    }
+}
+
+/// Round-trip times of a set of traces, in milliseconds.
+#[derive(Debug, PartialEq, Serialize)]
+struct RttStats {
+    min: u32,
+    median: u32,
+    mean: u32,
+    p90: u32,
+    max: u32,
+}
+
+impl RttStats {
+    fn of(rtts: &[u32]) -> Option<Self> {
+        let mut sorted = rtts.to_vec();
+        sorted.sort_unstable();
+        let n = sorted.len();
+        let (&min, &max) = (sorted.first()?, sorted.last()?);
+        let median = if n.is_multiple_of(2) {
+            (sorted[n / 2 - 1] + sorted[n / 2]) / 2
+        } else {
+            sorted[n / 2]
+        };
+        let sum: u64 = sorted.iter().map(|&r| u64::from(r)).sum();
+        Some(Self {
+            min,
+            median,
+            mean: u32::try_from(sum / n as u64).unwrap_or(u32::MAX),
+            // Nearest rank
+            p90: sorted[(n * 9).div_ceil(10) - 1],
+            max,
+        })
+    }
+}
+
+/// One path the traces took.
+#[derive(Debug, Serialize)]
+struct PathStats {
+    path: Vec<String>,
+    hops: u8,
+    count: u32,
+    rtt_ms: RttStats,
+}
+
+/// Paths and round-trip times over several traces to one node.
+#[derive(Debug, Serialize)]
+struct TraceSummary {
+    target: String,
+    sent: u32,
+    received: u32,
+    /// Most frequent first
+    paths: Vec<PathStats>,
+    rtt_ms: Option<RttStats>,
+}
+
+impl TraceSummary {
+    /// Summarize `traces`, `None` for those that timed out.
+    fn new(target: &str, traces: &[Option<TraceResult>]) -> Self {
+        let answered: Vec<&TraceResult> = traces.iter().flatten().collect();
+        let mut by_path: Vec<(&TraceResult, Vec<u32>)> = Vec::new();
+        for trace in &answered {
+            match by_path.iter_mut().find(|(t, _)| t.path == trace.path) {
+                Some((_, rtts)) => rtts.push(trace.rtt_ms),
+                None => by_path.push((trace, vec![trace.rtt_ms])),
+            }
+        }
+        // Stable, so equally frequent paths stay in order of first use
+        by_path.sort_by_key(|(_, rtts)| std::cmp::Reverse(rtts.len()));
+        let paths = by_path
+            .into_iter()
+            .filter_map(|(trace, rtts)| {
+                Some(PathStats {
+                    path: trace.path.clone(),
+                    hops: trace.hop_count,
+                    count: u32::try_from(rtts.len()).ok()?,
+                    rtt_ms: RttStats::of(&rtts)?,
+                })
+            })
+            .collect();
+        let rtts: Vec<u32> = answered.iter().map(|t| t.rtt_ms).collect();
+        Self {
+            target: target.to_string(),
+            sent: u32::try_from(traces.len()).unwrap_or(u32::MAX),
+            received: u32::try_from(answered.len()).unwrap_or(u32::MAX),
+            paths,
+            rtt_ms: RttStats::of(&rtts),
+        }
+    }
+
+    fn print(&self) {
+        println!("Paths ({}/{} answered):", self.received, self.sent);
+        for path in &self.paths {
+            let share = f64::from(path.count) * 100.0 / f64::from(self.received);
+            println!(
+                "  {:>3}x {share:>4.0}%  {}  ({} hops, median {} ms)",
+                path.count,
+                path.path.join(" -> "),
+                path.hops,
+                path.rtt_ms.median
+            );
+        }
+        if let Some(rtt) = &self.rtt_ms {
+            println!(
+                "RTT: min {}, median {}, mean {}, p90 {}, max {} ms",
+                rtt.min, rtt.median, rtt.mean, rtt.p90, rtt.max
+            );
+        }
+        let lost = self.sent - self.received;
+        if lost > 0 {
+            println!("⚠ {lost} of {} traces got no response", self.sent);
+        }
+        match self.paths.len() {
+            0 => {}
+            1 => println!("✓ Stable route"),
+            n => println!("⚠ Route changed between {n} paths"),
+        }
+    }
+}
+
 pub async fn cmd_advert(
     port: &str,
     baud: u32,
@@ -54,7 +386,8 @@ pub async fn cmd_advert(
 pub async fn cmd_raw(port: &str, baud: u32, hex_data: &str) -> Result<()> {
     let mut dev = Device::connect(port, baud).await?;
 
-    let packet = hex::decode(hex_data.trim()).map_err(|e| anyhow::anyhow!("Invalid hex: {e}"))?;
+    let packet = hex::decode(hex_data.trim())
+        .map_err(|e| CliError::InvalidArgs(format!("Invalid hex: {e}")))?;
 
     println!("Sending {} bytes: {}", packet.len(), hex_data);
     dev.send_packet(&packet).await?;
@@ -63,8 +396,69 @@ pub async fn cmd_raw(port: &str, baud: u32, hex_data: &str) -> Result<()> {
     Ok(())
 }
 
-pub async fn cmd_recv(port: &str, baud: u32, timeout_secs: u64) -> Result<()> {
+/// Build a packet from named fields, print it and optionally send it
+pub async fn cmd_raw_build(port: Option<&String>, baud: u32, action: RawAction) -> Result<()> {
+    let RawAction::Build {
+        payload_type,
+        route,
+        payload,
+        hex,
+        path,
+        key,
+        channel_psk,
+        send,
+    } = action;
+
+    let path = path
+        .iter()
+        .map(|hash| u8::from_str_radix(hash.trim().trim_start_matches("0x"), 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|_| CliError::InvalidArgs("--path takes 1-byte hex node hashes".into()))?;
+    let raw = match (&payload, hex) {
+        (Some(data), true) => Some(
+            hex::decode(data.trim())
+                .map_err(|e| CliError::InvalidArgs(format!("Invalid hex payload: {e}")))?,
+        ),
+        _ => None,
+    };
+    let fields = Fields {
+        text: payload.as_deref().filter(|_| !hex),
+        raw,
+        key: key.as_deref(),
+        channel_psk: channel_psk.as_deref(),
+    };
+    let built = packet::build(route, payload_type, path, &fields)?;
+    let bytes = built.encode()?;
+
+    if output::is_plain() {
+        println!("{}", hex::encode(&bytes));
+    } else {
+        println!("{} ({} bytes)", built.summary(), bytes.len());
+        println!("{}", hex::encode(&bytes));
+    }
+    if send {
+        let port = require_port(port)?;
+        let mut dev = Device::connect(&port, baud).await?;
+        dev.send_packet(&bytes).await?;
+        if !output::is_plain() {
+            println!("✓ Sent");
+        }
+    }
+    Ok(())
+}
+
+pub async fn cmd_recv(
+    port: &str,
+    baud: u32,
+    timeout_secs: u64,
+    capture: Option<&str>,
+    width: usize,
+) -> Result<()> {
+    use std::io::IsTerminal;
+
     let dev = Device::connect(port, baud).await?;
+    let color = std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+    let mut capture = capture.map(capture::Writer::create).transpose()?;
 
     println!("Waiting for packets ({timeout_secs}s timeout, Ctrl+C to stop)...\n");
 
@@ -79,7 +473,10 @@ pub async fn cmd_recv(port: &str, baud: u32, timeout_secs: u64) -> Result<()> {
             .recv_packet(std::time::Duration::from_millis(100))
             .await?
         {
-            print_packet(&packet);
+            print_packet(&packet, width, color);
+            if let Some(capture) = &mut capture {
+                capture.write(&packet)?;
+            }
         }
     }
 
@@ -87,19 +484,75 @@ pub async fn cmd_recv(port: &str, baud: u32, timeout_secs: u64) -> Result<()> {
     Ok(())
 }
 
-fn print_packet(packet: &[u8]) {
-    let timestamp = chrono::Local::now().format("%H:%M:%S");
+fn print_packet(packet: &[u8], width: usize, color: bool) {
+    let timestamp = crate::timefmt::clock();
     println!("[{}] Received {} bytes:", timestamp, packet.len());
-    println!("  Hex: {}", hex::encode(packet));
-
-    // Try to decode as text if it looks like ASCII
-    if packet
-        .iter()
-        .all(|&b| b.is_ascii_graphic() || b.is_ascii_whitespace())
-    {
-        if let Ok(text) = std::str::from_utf8(packet) {
-            println!("  Text: \"{text}\"");
+    let fields = match Packet::parse(packet) {
+        Ok(parsed) => {
+            println!("  Packet: {}", parsed.summary());
+            parsed.fields()
         }
+        Err(_) => Vec::new(),
+    };
+    if !fields.is_empty() {
+        println!("  Fields: {}", hexdump::legend(&fields, color));
+    }
+    for line in hexdump::hexdump(packet, width, &fields, color).lines() {
+        println!("  {line}");
     }
     println!();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trace_summary() {
+        let trace = |path: &[&str], rtt_ms| {
+            Some(TraceResult {
+                path: path.iter().map(|&p| p.to_string()).collect(),
+                hop_count: u8::try_from(path.len()).unwrap(),
+                rtt_ms,
+            })
+        };
+        let via_ridge = ["me", "ridge", "hut"];
+        let via_valley = ["me", "valley", "hut"];
+        let traces = [
+            trace(&via_valley, 900),
+            trace(&via_ridge, 400),
+            None,
+            trace(&via_ridge, 500),
+            trace(&via_ridge, 420),
+        ];
+        let summary = TraceSummary::new("hut", &traces);
+        assert_eq!((summary.sent, summary.received), (5, 4));
+        // The route flapped, mostly via the ridge
+        assert_eq!(summary.paths.len(), 2);
+        assert_eq!(summary.paths[0].path, via_ridge);
+        assert_eq!(summary.paths[0].count, 3);
+        assert_eq!(summary.paths[0].rtt_ms.median, 420);
+        assert_eq!(
+            summary.rtt_ms,
+            Some(RttStats {
+                min: 400,
+                median: 460,
+                mean: 555,
+                p90: 900,
+                max: 900
+            })
+        );
+        assert_eq!(TraceSummary::new("hut", &[None]).rtt_ms, None);
+
+        // Verdicts in the `trace --all` table
+        let entry = |traces: &[Option<TraceResult>], error: Option<&str>| SweepEntry {
+            node_hash: "0x3a".into(),
+            summary: TraceSummary::new("hut", traces),
+            error: error.map(String::from),
+        };
+        assert_eq!(entry(&traces, None).status(), "⚠ 2 paths, 1 lost");
+        assert_eq!(entry(&traces[1..2], None).status(), "✓");
+        assert_eq!(entry(&[None, None], None).status(), "✗ no response");
+        assert_eq!(entry(&[], Some("Unknown node")).status(), "✗ Unknown node");
+    }
+}
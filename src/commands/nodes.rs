@@ -0,0 +1,151 @@
+//! Node database: every node seen, with operator notes
+
+use crate::cli::NodesAction;
+use crate::error::CliError;
+use crate::nodes::{self, CachedNode};
+use crate::output;
+use crate::timefmt;
+use anyhow::{bail, Result};
+use chrono::DateTime;
+
+/// Browse and annotate the node database
+pub fn cmd_nodes(port: Option<&String>, action: NodesAction) -> Result<()> {
+    let device_key = mesh(port)?;
+    match action {
+        NodesAction::List { json } => {
+            let mut list = nodes::nodes(&device_key)?;
+            list.sort_by(|a, b| b.last_seen.cmp(&a.last_seen));
+            if json {
+                println!("{}", serde_json::to_string_pretty(&list)?);
+                return Ok(());
+            }
+            if output::is_plain() {
+                output::print_json_kv(&serde_json::to_value(&list)?);
+                return Ok(());
+            }
+            println!(
+                "Nodes seen by device {} ({}):\n",
+                &device_key[..8.min(device_key.len())],
+                list.len()
+            );
+            println!(
+                "  {:16} {:5} {:16} {:16} {:>5} {:10} Note",
+                "Name", "Hash", "First seen", "Last seen", "Best", "Firmware"
+            );
+            println!(
+                "  {:-<16} {:-<5} {:-<16} {:-<16} {:->5} {:-<10} {:-<4}",
+                "", "", "", "", "", "", ""
+            );
+            for node in &list {
+                let line = format!(
+                    "  {:16} 0x{:02x}  {:16} {:16} {:>5} {:10} {}",
+                    node.name.as_deref().unwrap_or("?"),
+                    node.node_hash,
+                    node.first_seen
+                        .as_deref()
+                        .map(format_seen)
+                        .unwrap_or_default(),
+                    format_seen(&node.last_seen),
+                    node.best_rssi.map(|r| r.to_string()).unwrap_or_default(),
+                    node.firmware.last().map(String::as_str).unwrap_or("-"),
+                    node.note.as_deref().unwrap_or("")
+                );
+                println!("{}", line.trim_end());
+            }
+        }
+        NodesAction::Show { node } => {
+            let Some(found) = nodes::lookup(&device_key, &node)? else {
+                bail!(CliError::InvalidArgs(format!(
+                    "No node '{node}' recorded (see `nodes list`)"
+                )));
+            };
+            if output::is_plain() {
+                output::print_json_kv(&serde_json::to_value(&found)?);
+            } else {
+                print_node(&found);
+            }
+        }
+        NodesAction::Annotate { node, note, .. } => {
+            let cleared = note.is_none();
+            let Some(updated) = nodes::annotate(&device_key, &node, note)? else {
+                bail!(CliError::InvalidArgs(format!(
+                    "No node '{node}' recorded (see `nodes list`)"
+                )));
+            };
+            if !output::is_plain() {
+                if cleared {
+                    println!("✓ Removed the note on {}", updated.label());
+                } else {
+                    println!("✓ Noted on {}", updated.label());
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Public key of the device whose nodes to use: the one last seen on
+/// `port`, or the only one recorded.
+fn mesh(port: Option<&String>) -> Result<String> {
+    if let Some(port) = port {
+        return match nodes::device_on_port(port)? {
+            Some(device_key) => Ok(device_key),
+            None => bail!(CliError::InvalidArgs(format!(
+                "No nodes recorded for the device on {port} yet (run `neighbors` first)"
+            ))),
+        };
+    }
+    let mut keys = nodes::devices()?.into_keys();
+    match (keys.next(), keys.next()) {
+        (Some(device_key), None) => Ok(device_key),
+        (None, _) => bail!(CliError::InvalidArgs(
+            "No nodes recorded yet (run `neighbors` or `monitor` first)".into()
+        )),
+        (Some(_), Some(_)) => bail!(CliError::InvalidArgs(
+            "Nodes from several devices are recorded; pick one with --port".into()
+        )),
+    }
+}
+
+/// A recorded timestamp as `3m ago` or a date and time; older records only
+/// have a date.
+fn format_seen(seen: &str) -> String {
+    DateTime::parse_from_rfc3339(seen).map_or_else(|_| seen.to_string(), |t| timefmt::relative(&t))
+}
+
+fn print_node(node: &CachedNode) {
+    println!("{} (0x{:02x})", node.label(), node.node_hash);
+    if let Some(key) = &node.public_key {
+        println!("  Public key: {key}");
+    }
+    if let Some(first_seen) = &node.first_seen {
+        println!("  First seen: {}", format_seen(first_seen));
+    }
+    println!("  Last seen:  {}", format_seen(&node.last_seen));
+    if let Some(rssi) = node.best_rssi {
+        println!("  Best RSSI:  {rssi} dBm");
+    }
+    if !node.firmware.is_empty() {
+        println!("  Firmware:   {}", node.firmware.join(", "));
+    }
+    if let Some((lat, lon)) = node.lat.zip(node.lon) {
+        match node.alt {
+            Some(alt) => println!("  Position:   {lat:.5}, {lon:.5} ({alt:.0} m)"),
+            None => println!("  Position:   {lat:.5}, {lon:.5}"),
+        }
+    }
+    if let Some(note) = &node.note {
+        println!("  Note:       {note}");
+    }
+    if node.positions.len() > 1 {
+        println!("\n  Positions ({}):", node.positions.len());
+        for position in &node.positions {
+            println!(
+                "    {}  {:.5}, {:.5}",
+                format_seen(&position.seen),
+                position.lat,
+                position.lon
+            );
+        }
+    }
+}
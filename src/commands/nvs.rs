@@ -0,0 +1,95 @@
+//! Settings partition backup, restore and erase
+//!
+//! The firmware keeps its settings (name, radio, channels, contacts, keys)
+//! in the NVS partition, separate from the application image. These
+//! commands read, write or erase just that partition with espflash, so
+//! settings can be wiped without reflashing or copied to another device.
+
+use super::system::{espflash, flash_target};
+use crate::cli::NvsAction;
+use crate::confirm::{confirm, Operation};
+use crate::error::CliError;
+use crate::firmware::{looks_like_nvs, sha256_hex, NVS_OFFSET, NVS_SIZE};
+use crate::output;
+use anyhow::{bail, Context, Result};
+
+/// Back up, restore or erase the settings partition
+pub async fn cmd_nvs(port: Option<&str>, action: NvsAction) -> Result<()> {
+    if port.is_some_and(|p| p.starts_with("tcp://") || p.starts_with("unix:")) {
+        bail!(CliError::InvalidArgs(
+            "nvs needs the device's serial port, not a network connection".into()
+        ));
+    }
+    let plain = output::is_plain();
+
+    match action {
+        NvsAction::Backup { output: path } => {
+            espflash(
+                "read-flash",
+                port,
+                &[
+                    format!("0x{NVS_OFFSET:x}"),
+                    NVS_SIZE.to_string(),
+                    path.clone(),
+                ],
+            )?;
+            let data = std::fs::read(&path).with_context(|| format!("Failed to read {path}"))?;
+            if plain {
+                output::kv("backup", &path);
+                output::kv("sha256", sha256_hex(&data));
+            } else {
+                println!(
+                    "✓ Settings partition saved to {path} ({} KB)",
+                    data.len() / 1024
+                );
+                if !looks_like_nvs(&data) {
+                    println!(
+                        "⚠ The data doesn't look like an NVS partition; check the partition table"
+                    );
+                }
+            }
+        }
+        NvsAction::Restore { file } => {
+            let data = std::fs::read(&file).with_context(|| format!("Failed to read {file}"))?;
+            if !looks_like_nvs(&data) {
+                bail!(CliError::InvalidArgs(format!(
+                    "{file} is not an NVS partition backup ({NVS_SIZE} bytes made with `nvs backup`)"
+                )));
+            }
+            if !confirm(Operation::RestoreSettings, &flash_target(port).await.0)? {
+                return Ok(());
+            }
+            espflash(
+                "write-bin",
+                port,
+                &[format!("0x{NVS_OFFSET:x}"), file.clone()],
+            )?;
+            if plain {
+                output::kv("restored", &file);
+            } else {
+                println!("✓ Settings restored from {file}");
+            }
+        }
+        NvsAction::Erase => {
+            if !confirm(Operation::FactoryReset, &flash_target(port).await.0)? {
+                return Ok(());
+            }
+            erase_settings(port)?;
+            if plain {
+                output::kv("erased", "settings");
+            } else {
+                println!("✓ Settings erased; the firmware starts with defaults");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Erase the settings partition, leaving the firmware in place.
+pub(super) fn erase_settings(port: Option<&str>) -> Result<()> {
+    espflash(
+        "erase-region",
+        port,
+        &[format!("0x{NVS_OFFSET:x}"), format!("0x{NVS_SIZE:x}")],
+    )
+}
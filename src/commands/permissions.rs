@@ -0,0 +1,319 @@
+//! setup-permissions: serial port access on Linux
+//!
+//! Finds the USB IDs of the connected boards, generates a udev rule that
+//! gives their ports to the serial group (and, through `uaccess`, to the
+//! user logged in at the machine), and checks that the user is in that
+//! group. `--install` writes the rule and adds the user with sudo.
+
+use anyhow::Result;
+
+/// Check serial port access and set up a udev rule for supported boards.
+pub fn cmd_setup_permissions(install: bool) -> Result<()> {
+    #[cfg(target_os = "linux")]
+    return linux::setup(install);
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = install;
+        println!("Serial ports need no setup on this platform.");
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use crate::diagnose::port_group;
+    use crate::serial::UsbChip;
+    use anyhow::{bail, Context, Result};
+    use std::ffi::{CStr, CString};
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    const RULES_PATH: &str = "/etc/udev/rules.d/99-meshgrid.rules";
+
+    /// A connected board.
+    struct Board {
+        port: String,
+        chip: UsbChip,
+        vid: u16,
+        pid: u16,
+    }
+
+    /// Whether the user can use the serial group.
+    #[derive(Debug, PartialEq, Eq)]
+    enum Membership {
+        Active,
+        /// Added, but not in this login session yet
+        NeedsRelogin,
+        Missing,
+    }
+
+    pub(super) fn setup(install: bool) -> Result<()> {
+        let boards = connected_boards();
+        let mut ids: Vec<(UsbChip, u16, Option<u16>)> = Vec::new();
+        if boards.is_empty() {
+            println!("⚠ No supported board connected; the rule covers every supported USB chip");
+            ids.extend(UsbChip::ALL.map(|chip| (chip, chip.ids().0, chip.ids().1)));
+        }
+        for board in &boards {
+            println!(
+                "✓ {} ({:04x}:{:04x}) on {}",
+                board.chip.description(),
+                board.vid,
+                board.pid,
+                board.port
+            );
+            if !ids
+                .iter()
+                .any(|&(_, v, p)| (v, p) == (board.vid, Some(board.pid)))
+            {
+                ids.push((board.chip, board.vid, Some(board.pid)));
+            }
+        }
+
+        let group = serial_group(&boards);
+        let rule = udev_rule(&ids, &group);
+        let membership = membership(&group);
+        match membership {
+            Membership::Active => println!("✓ You are in the '{group}' group"),
+            Membership::NeedsRelogin => {
+                println!("⚠ You were added to '{group}', but this session started before that");
+            }
+            Membership::Missing => println!("✗ You are not in the '{group}' group"),
+        }
+
+        if !install {
+            println!("\nudev rule for {RULES_PATH}:\n");
+            print!("{rule}");
+            println!("\nRun `meshgrid-cli setup-permissions --install` to install it with sudo");
+            if membership == Membership::Missing {
+                println!("and add you to '{group}' (or run `sudo usermod -aG {group} $USER`).");
+            }
+            return Ok(());
+        }
+
+        install_rule(&rule)?;
+        println!("✓ Installed {RULES_PATH} and reloaded udev");
+        if membership == Membership::Missing {
+            let user = user_name().context("Failed to look up your user name")?;
+            privileged(&["usermod", "-aG", &group, &user])?;
+            println!("✓ Added {user} to '{group}'");
+        }
+        if membership != Membership::Active {
+            println!("⚠ Log out and back in for the group change to take effect");
+        }
+
+        // udev applies the rule asynchronously after the trigger
+        std::thread::sleep(std::time::Duration::from_millis(500));
+        for board in &boards {
+            if accessible(&board.port) {
+                println!("✓ {} is accessible", board.port);
+            } else {
+                println!("✗ {} is not accessible yet; replug the board", board.port);
+            }
+        }
+        Ok(())
+    }
+
+    fn connected_boards() -> Vec<Board> {
+        serialport::available_ports()
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|port| match port.port_type {
+                serialport::SerialPortType::UsbPort(info) => Some(Board {
+                    chip: UsbChip::from_ids(info.vid, info.pid)?,
+                    port: port.port_name,
+                    vid: info.vid,
+                    pid: info.pid,
+                }),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The group owning a connected board's port, else the distribution's
+    /// serial group (`dialout`, or `uucp` on Arch).
+    fn serial_group(boards: &[Board]) -> String {
+        boards
+            .iter()
+            .find_map(|board| port_group(&board.port))
+            .or_else(|| {
+                ["dialout", "uucp"]
+                    .into_iter()
+                    .find(|group| group_id(group).is_some())
+                    .map(String::from)
+            })
+            .unwrap_or_else(|| "dialout".into())
+    }
+
+    /// udev rules giving the matching ports to `group` and the seat's user.
+    pub(super) fn udev_rule(ids: &[(UsbChip, u16, Option<u16>)], group: &str) -> String {
+        let mut rule = String::from("# meshgrid-cli: serial access to meshgrid boards\n");
+        for (chip, vid, pid) in ids {
+            let product = pid.map_or_else(String::new, |pid| {
+                format!(", ATTRS{{idProduct}}==\"{pid:04x}\"")
+            });
+            rule.push_str(&format!(
+                "# {}\nSUBSYSTEM==\"tty\", ATTRS{{idVendor}}==\"{vid:04x}\"{product}, \
+             MODE=\"0660\", GROUP=\"{group}\", TAG+=\"uaccess\"\n",
+                chip.description()
+            ));
+        }
+        rule
+    }
+
+    fn install_rule(rule: &str) -> Result<()> {
+        let (program, args) = elevate(&["tee", RULES_PATH]);
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .with_context(|| format!("Failed to run {program}"))?;
+        child
+            .stdin
+            .take()
+            .context("No stdin for tee")?
+            .write_all(rule.as_bytes())?;
+        if !child.wait()?.success() {
+            bail!("Failed to write {RULES_PATH}");
+        }
+        privileged(&["udevadm", "control", "--reload-rules"])?;
+        privileged(&[
+            "udevadm",
+            "trigger",
+            "--action=add",
+            "--subsystem-match=tty",
+        ])
+    }
+
+    /// Run a command as root, through sudo unless we already are.
+    fn privileged(command: &[&str]) -> Result<()> {
+        let (program, args) = elevate(command);
+        let status = Command::new(program)
+            .args(args)
+            .status()
+            .with_context(|| format!("Failed to run {program}"))?;
+        if !status.success() {
+            bail!("`{}` failed ({status})", command.join(" "));
+        }
+        Ok(())
+    }
+
+    fn elevate<'a>(command: &'a [&'a str]) -> (&'a str, &'a [&'a str]) {
+        // SAFETY: geteuid has no preconditions
+        if unsafe { libc::geteuid() } == 0 {
+            (command[0], &command[1..])
+        } else {
+            ("sudo", command)
+        }
+    }
+
+    fn membership(group: &str) -> Membership {
+        let Some(gid) = group_id(group) else {
+            return Membership::Missing;
+        };
+        // SAFETY: getgroups with a zero count only returns the count; the second
+        // call fills a buffer of that size
+        let active = unsafe {
+            let count = libc::getgroups(0, std::ptr::null_mut());
+            let mut groups = vec![0; usize::try_from(count).unwrap_or(0)];
+            let count = libc::getgroups(count, groups.as_mut_ptr());
+            groups.truncate(usize::try_from(count).unwrap_or(0));
+            libc::getegid() == gid || groups.contains(&gid)
+        };
+        if active {
+            Membership::Active
+        } else if user_name().is_some_and(|user| group_members(group).contains(&user)) {
+            Membership::NeedsRelogin
+        } else {
+            Membership::Missing
+        }
+    }
+
+    fn group_id(group: &str) -> Option<libc::gid_t> {
+        let name = CString::new(group).ok()?;
+        // SAFETY: getgrnam returns null or a pointer to a static entry, read
+        // before any other call can overwrite it
+        unsafe {
+            let entry = libc::getgrnam(name.as_ptr());
+            (!entry.is_null()).then(|| (*entry).gr_gid)
+        }
+    }
+
+    /// Users listed as members of `group` in the group database.
+    fn group_members(group: &str) -> Vec<String> {
+        let Ok(name) = CString::new(group) else {
+            return Vec::new();
+        };
+        let mut members = Vec::new();
+        // SAFETY: as in group_id; gr_mem is a null-terminated array of strings
+        unsafe {
+            let entry = libc::getgrnam(name.as_ptr());
+            if entry.is_null() {
+                return members;
+            }
+            let mut member = (*entry).gr_mem;
+            while !(*member).is_null() {
+                members.push(CStr::from_ptr(*member).to_string_lossy().into_owned());
+                member = member.add(1);
+            }
+        }
+        members
+    }
+
+    /// The invoking user (the one behind sudo, if any).
+    fn user_name() -> Option<String> {
+        if let Ok(user) = std::env::var("SUDO_USER") {
+            return Some(user);
+        }
+        // SAFETY: getpwuid returns null or a pointer to a static entry, read
+        // before any other call can overwrite it
+        unsafe {
+            let entry = libc::getpwuid(libc::getuid());
+            (!entry.is_null()).then(|| {
+                CStr::from_ptr((*entry).pw_name)
+                    .to_string_lossy()
+                    .into_owned()
+            })
+        }
+    }
+
+    fn accessible(port: &str) -> bool {
+        let Ok(path) = CString::new(port) else {
+            return false;
+        };
+        // SAFETY: access only reads the path
+        unsafe { libc::access(path.as_ptr(), libc::R_OK | libc::W_OK) == 0 }
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::linux::udev_rule;
+    use crate::serial::UsbChip;
+
+    #[test]
+    fn test_udev_rule() {
+        let rule = udev_rule(
+            &[
+                (UsbChip::Ch340, 0x1a86, Some(0x7523)),
+                (UsbChip::Esp32Usb, 0x303a, None),
+            ],
+            "uucp",
+        );
+        assert_eq!(
+            rule.lines().nth(2),
+            Some(
+                "SUBSYSTEM==\"tty\", ATTRS{idVendor}==\"1a86\", ATTRS{idProduct}==\"7523\", \
+                 MODE=\"0660\", GROUP=\"uucp\", TAG+=\"uaccess\""
+            )
+        );
+        assert_eq!(
+            rule.lines().nth(4),
+            Some(
+                "SUBSYSTEM==\"tty\", ATTRS{idVendor}==\"303a\", MODE=\"0660\", \
+                 GROUP=\"uucp\", TAG+=\"uaccess\""
+            )
+        );
+    }
+}
@@ -0,0 +1,242 @@
+//! `plan`: where a network needs repeaters, from candidate sites.
+
+use super::connect_with_auth;
+use crate::cli::RadioPreset;
+use crate::error::CliError;
+use crate::geo;
+use crate::output;
+use crate::plan::{self, Link, Radio, Role, Site, Terrain};
+use crate::serial::normalize_port;
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::path::Path;
+
+#[derive(Serialize)]
+struct LinkRow<'a> {
+    from: &'a str,
+    to: &'a str,
+    #[serde(flatten)]
+    link: &'a Link,
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    radio: &'a Radio,
+    margin_db: f64,
+    sites: &'a [Site],
+    /// Links with any margin at all
+    links: Vec<LinkRow<'a>>,
+    repeaters: Vec<&'a str>,
+    unreached: Vec<&'a str>,
+    groups: Vec<Vec<&'a str>>,
+}
+
+/// Compute link budgets between the sites in `nodes` and suggest repeaters
+#[allow(clippy::too_many_arguments)]
+pub async fn cmd_plan(
+    port: Option<&String>,
+    baud: u32,
+    pin: Option<&str>,
+    nodes: &str,
+    preset: Option<RadioPreset>,
+    elevation: Option<&str>,
+    gain: f64,
+    margin: f64,
+) -> Result<()> {
+    let csv = std::fs::read_to_string(nodes).with_context(|| format!("Failed to read {nodes}"))?;
+    let mut sites =
+        plan::parse_sites(&csv).with_context(|| format!("Invalid sites file {nodes}"))?;
+    if sites.len() < 2 {
+        bail!(CliError::InvalidArgs(format!(
+            "{nodes} needs at least two sites"
+        )));
+    }
+
+    let preset_name = |preset: RadioPreset| {
+        preset
+            .to_possible_value()
+            .map_or("?".into(), |v| v.get_name().to_uppercase())
+    };
+    let (config, source) = match (preset, port) {
+        (Some(preset), _) => (
+            plan::preset(preset),
+            format!("{} preset", preset_name(preset)),
+        ),
+        (None, Some(port)) => {
+            let port = normalize_port(port);
+            let mut proto = connect_with_auth(&port, baud, pin).await?.into_protocol();
+            (proto.get_config().await?, format!("settings of {port}"))
+        }
+        (None, None) => (
+            plan::preset(RadioPreset::Eu),
+            format!("{} preset", preset_name(RadioPreset::Eu)),
+        ),
+    };
+    let radio = Radio {
+        config,
+        gain_dbi: gain,
+    };
+
+    let mut terrain = elevation
+        .map(|dir| Terrain::open(Path::new(dir)))
+        .transpose()?;
+    if let Some(terrain) = terrain.as_mut() {
+        plan::fill_elevations(&mut sites, terrain);
+    }
+    let mut links = Vec::new();
+    for a in 0..sites.len() {
+        for b in a + 1..sites.len() {
+            links.push(plan::link(&sites, a, b, &radio, terrain.as_mut()));
+        }
+    }
+    let plan = plan::plan(&sites, &links, margin);
+    let name = |site: usize| sites[site].name.as_str();
+
+    if output::is_structured() {
+        return output::print(&Report {
+            radio: &radio,
+            margin_db: margin,
+            sites: &sites,
+            links: links
+                .iter()
+                .filter(|l| l.margin_db >= 0.0)
+                .map(|link| LinkRow {
+                    from: name(link.a),
+                    to: name(link.b),
+                    link,
+                })
+                .collect(),
+            repeaters: plan.repeaters.iter().map(|&s| name(s)).collect(),
+            unreached: plan.unreached.iter().map(|&s| name(s)).collect(),
+            groups: plan
+                .groups
+                .iter()
+                .map(|g| g.iter().map(|&s| name(s)).collect())
+                .collect(),
+        });
+    }
+
+    let c = &radio.config;
+    println!(
+        "Planning {} sites at {} MHz, {} kHz, SF{}, {} dBm with {gain} dBi antennas ({source})",
+        sites.len(),
+        c.freq_mhz,
+        c.bandwidth_khz,
+        c.spreading_factor,
+        c.tx_power_dbm
+    );
+    match (&terrain, elevation) {
+        (Some(terrain), Some(dir)) => {
+            println!("Terrain: {} SRTM tile(s) from {dir}", terrain.loaded());
+        }
+        _ => println!("Terrain: level ground between sites (use --elevation for SRTM tiles)"),
+    }
+    println!("Links need {margin} dB of margin.\n");
+
+    let usable: Vec<&Link> = links.iter().filter(|l| l.margin_db >= 0.0).collect();
+    let width = sites.iter().map(|s| s.name.len()).max().unwrap_or(0).max(4);
+    if usable.is_empty() {
+        println!("✗ No two sites are in range of each other");
+    } else {
+        println!(
+            "  {:<width$}  {:<width$}  DISTANCE  LOSS    MARGIN  FRESNEL",
+            "FROM", "TO"
+        );
+        for link in &usable {
+            let mark = if link.margin_db >= margin {
+                '✓'
+            } else {
+                '⚠'
+            };
+            let fresnel = match link.clearance {
+                None => "-".to_string(),
+                Some(c) if c < 0.0 => "blocked".to_string(),
+                Some(c) => format!("{:.0}%", (c * 100.0).min(100.0)),
+            };
+            println!(
+                "{mark} {:<width$}  {:<width$}  {:<8}  {:<6}  {:<6}  {fresnel}",
+                name(link.a),
+                name(link.b),
+                geo::format_distance(link.distance_m),
+                format!("{:.0} dB", link.loss_db),
+                format!("{:+.0} dB", link.margin_db),
+            );
+        }
+        let out_of_range = links.len() - usable.len();
+        if out_of_range > 0 {
+            println!("  ({out_of_range} pair(s) out of range not shown)");
+        }
+    }
+
+    println!();
+    if plan.repeaters.is_empty() {
+        if plan.groups.len() == 1 {
+            println!("No repeaters needed: every site is in range of every other.");
+        }
+    } else {
+        println!("Repeaters:");
+        for &repeater in &plan.repeaters {
+            let existing = if sites[repeater].role == Role::Repeater {
+                " (existing)"
+            } else {
+                ""
+            };
+            let serves: Vec<&str> = links
+                .iter()
+                .filter(|l| l.margin_db >= margin && (l.a == repeater || l.b == repeater))
+                .map(|l| if l.a == repeater { l.b } else { l.a })
+                .filter(|s| !plan.repeaters.contains(s))
+                .map(name)
+                .collect();
+            if serves.is_empty() {
+                println!("  ✓ {}{existing}", name(repeater));
+            } else {
+                println!("  ✓ {}{existing}: {}", name(repeater), serves.join(", "));
+            }
+        }
+    }
+
+    // Every group but the main one, or all when no two sites link up
+    let main_group = usize::from(plan.groups[0].len() > 1);
+    for group in plan.groups.iter().skip(main_group) {
+        let members: Vec<&str> = group.iter().map(|&s| name(s)).collect();
+        let closest = links
+            .iter()
+            .filter(|l| group.contains(&l.a) != group.contains(&l.b))
+            .max_by(|x, y| x.margin_db.total_cmp(&y.margin_db));
+        let what = if group.len() == 1 {
+            format!("{} reaches no other site", members[0])
+        } else {
+            format!("{} can't reach the other sites", members.join(", "))
+        };
+        match closest {
+            Some(l) => println!(
+                "✗ {what} (closest: {} - {}, {}, {:.0} dB short)",
+                name(l.a),
+                name(l.b),
+                geo::format_distance(l.distance_m),
+                margin - l.margin_db
+            ),
+            None => println!("✗ {what}"),
+        }
+    }
+    if !plan.unreached.is_empty() {
+        let unreached: Vec<&str> = plan.unreached.iter().map(|&s| name(s)).collect();
+        println!(
+            "⚠ Only client sites reach {}: make one of them a candidate",
+            unreached.join(", ")
+        );
+    }
+
+    let connected = plan.groups[0]
+        .iter()
+        .filter(|s| !plan.unreached.contains(s))
+        .count();
+    println!(
+        "\n{} repeater(s); {connected} of {} sites connected",
+        plan.repeaters.len(),
+        sites.len()
+    );
+    Ok(())
+}
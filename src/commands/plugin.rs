@@ -0,0 +1,92 @@
+//! External plugin subcommands
+//!
+//! `meshgrid foo args...` runs a `meshgrid-foo` executable found on `PATH`
+//! (git-style). The plugin receives connection details in environment
+//! variables and, when a device is available, a proxy address it can pass as
+//! `--port` to reuse the connection opened by this process:
+//!
+//! - `MESHGRID_BIN`: path of the meshgrid executable
+//! - `MESHGRID_PORT`: port to use (the proxy address when one is running)
+//! - `MESHGRID_DEVICE_PORT`: the physical device port
+//! - `MESHGRID_PROXY`: proxy address (`unix:/...` or `tcp://...`)
+//! - `MESHGRID_PROXY_TOKEN`: token a `tcp://` proxy expects first (meshgrid
+//!   sends it itself when given the proxy address)
+//! - `MESHGRID_BAUD`, `MESHGRID_PIN`
+
+use crate::proxy::DeviceProxy;
+use anyhow::{bail, Context, Result};
+use std::path::PathBuf;
+
+/// Locate `meshgrid-<name>` on `PATH`.
+fn find_plugin(name: &str) -> Option<PathBuf> {
+    let exe_name = format!("meshgrid-{name}{}", std::env::consts::EXE_SUFFIX);
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(&exe_name))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Run an external plugin subcommand and return its exit code
+pub async fn cmd_plugin(
+    args: &[String],
+    port: Option<&String>,
+    baud: u32,
+    pin: Option<&str>,
+) -> Result<i32> {
+    let Some((name, plugin_args)) = args.split_first() else {
+        bail!("No subcommand given");
+    };
+
+    let Some(plugin) = find_plugin(name) else {
+        bail!(
+            "Unknown command '{name}'.\n\
+             No plugin named 'meshgrid-{name}' was found on PATH. Run 'meshgrid-cli --help' for built-in commands."
+        );
+    };
+
+    // Share the device with the plugin if one is available; plugins that
+    // don't talk to a device still work without one.
+    let device_port = match port {
+        Some(p) => Some(p.clone()),
+        None => crate::serial::detect_device()?,
+    };
+    let proxy = match &device_port {
+        Some(p) => match DeviceProxy::start(p, baud).await {
+            Ok(proxy) => Some(proxy),
+            Err(e) => {
+                tracing::warn!("Could not open {p} for plugin: {e}");
+                None
+            }
+        },
+        None => None,
+    };
+
+    let mut command = tokio::process::Command::new(&plugin);
+    command.args(plugin_args);
+    command.env("MESHGRID_BAUD", baud.to_string());
+    if let Ok(exe) = std::env::current_exe() {
+        command.env("MESHGRID_BIN", exe);
+    }
+    if let Some(p) = &device_port {
+        command.env("MESHGRID_DEVICE_PORT", p);
+        command.env("MESHGRID_PORT", p);
+    }
+    if let Some(proxy) = &proxy {
+        command.env("MESHGRID_PROXY", proxy.address());
+        command.env("MESHGRID_PORT", proxy.address());
+        if let Some(token) = proxy.token() {
+            command.env("MESHGRID_PROXY_TOKEN", token);
+        }
+    }
+    if let Some(pin) = pin {
+        command.env("MESHGRID_PIN", pin);
+    }
+
+    let status = command
+        .status()
+        .await
+        .with_context(|| format!("Failed to run plugin {}", plugin.display()))?;
+
+    drop(proxy);
+    Ok(status.code().unwrap_or(1))
+}
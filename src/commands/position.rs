@@ -0,0 +1,323 @@
+//! Node positions: fixed positions for GPS-less nodes, remote requests,
+//! distances between nodes
+
+use super::connect_with_auth;
+use super::contacts::resolve_node;
+use crate::cli::PositionAction;
+use crate::error::CliError;
+use crate::geo;
+use crate::output;
+use crate::protocol::{Protocol, RemotePosition, Response};
+use crate::timefmt;
+use anyhow::{bail, Result};
+use std::time::Duration;
+
+/// Set, advertise or show the device's position
+pub async fn cmd_position(
+    port: &str,
+    baud: u32,
+    pin: Option<&str>,
+    action: Option<PositionAction>,
+) -> Result<()> {
+    let action = action.unwrap_or(PositionAction::Show);
+    if let PositionAction::Set { lat, lon, .. } = action {
+        validate(lat, lon)?;
+    }
+    let mut dev = connect_with_auth(port, baud, pin).await?;
+
+    match action {
+        PositionAction::Show => {
+            let mut proto = dev.into_protocol();
+            match proto.command("POSITION").await? {
+                Response::Json(json) => {
+                    let Some((lat, lon, alt)) = position_fields(&json) else {
+                        bail!("Unexpected response to POSITION: {json}");
+                    };
+                    let source = json.get("source").and_then(|v| v.as_str());
+                    if output::is_plain() {
+                        output::kv("lat", lat);
+                        output::kv("lon", lon);
+                        if let Some(alt) = alt {
+                            output::kv("alt", alt);
+                        }
+                        if let Some(source) = source {
+                            output::kv("source", source);
+                        }
+                    } else {
+                        print!("Position: {lat:.6}, {lon:.6}");
+                        if let Some(alt) = alt {
+                            print!(" ({alt:.0} m)");
+                        }
+                        match source {
+                            Some(source) => println!(" [{source}]"),
+                            None => println!(),
+                        }
+                    }
+                }
+                Response::Ok(_) => println!("No position set"),
+                Response::Error(e) => {
+                    bail!(CliError::Device(format!("failed to get position: {e}")))
+                }
+            }
+        }
+        PositionAction::Set { lat, lon, alt } => {
+            let mut proto = dev.into_protocol();
+            set_position(&mut proto, lat, lon, alt).await?;
+            if !output::is_plain() {
+                println!("✓ Position set to {lat:.6}, {lon:.6}");
+                println!("  Included in adverts from now on; `position send` announces it now");
+            }
+        }
+        PositionAction::Request {
+            node,
+            timeout,
+            refresh,
+        } => {
+            let mut proto = dev.into_protocol();
+            let (target, label) = match resolve_node(&mut proto, port, &node, refresh).await {
+                Some(found) => (format!("0x{:02x}", found.node_hash), found.label()),
+                None => (node.clone(), node.clone()),
+            };
+            if !output::is_plain() {
+                println!("Requesting position from {label}...");
+            }
+            let fix = proto
+                .request_position(&target, Duration::from_secs(timeout))
+                .await?;
+            print_remote(&label, &fix);
+        }
+        PositionAction::Send { flood } => {
+            if flood {
+                dev.send_advert_flood().await?;
+                println!("Flood advertisement with position sent");
+            } else {
+                dev.send_advert_local().await?;
+                println!("Local advertisement with position sent");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Distance, bearing and elevation difference between two nodes
+pub async fn cmd_distance(
+    port: &str,
+    baud: u32,
+    pin: Option<&str>,
+    node_a: &str,
+    node_b: Option<&str>,
+    refresh: bool,
+) -> Result<()> {
+    let mut proto = connect_with_auth(port, baud, pin).await?.into_protocol();
+    let a = locate(&mut proto, port, node_a, refresh).await?;
+    let b = match node_b {
+        Some(node) => locate(&mut proto, port, node, refresh).await?,
+        None => {
+            let info = proto.get_info().await?;
+            let Some((lat, lon, alt)) = own_position(&mut proto).await? else {
+                bail!(
+                    "This device has no position; attach a GPS (`gps feed`) or set one with \
+                     `position set`"
+                );
+            };
+            Located {
+                label: info.name.unwrap_or_else(|| "this device".into()),
+                lat,
+                lon,
+                alt,
+            }
+        }
+    };
+
+    let distance = geo::distance_m((a.lat, a.lon), (b.lat, b.lon));
+    let bearing = geo::bearing_deg((a.lat, a.lon), (b.lat, b.lon));
+    let reverse = geo::bearing_deg((b.lat, b.lon), (a.lat, a.lon));
+    let climb = a.alt.zip(b.alt).map(|(a_alt, b_alt)| b_alt - a_alt);
+
+    if output::is_plain() {
+        output::kv("distance_m", format!("{distance:.0}"));
+        output::kv("bearing_deg", format!("{bearing:.1}"));
+        output::kv("reverse_bearing_deg", format!("{reverse:.1}"));
+        if let Some(climb) = climb {
+            output::kv("elevation_diff_m", format!("{climb:.0}"));
+        }
+        return Ok(());
+    }
+
+    println!("{} → {}", a.label, b.label);
+    println!("  Distance:  {}", geo::format_distance(distance));
+    println!(
+        "  Bearing:   {bearing:03.0}° ({}) from {}, {reverse:03.0}° ({}) from {}",
+        geo::compass(bearing),
+        a.label,
+        geo::compass(reverse),
+        b.label
+    );
+    match (a.alt, b.alt, climb) {
+        (Some(a_alt), Some(b_alt), Some(climb)) => {
+            let (higher, by) = if climb >= 0.0 {
+                (&b.label, climb)
+            } else {
+                (&a.label, -climb)
+            };
+            println!(
+                "  Elevation: {} {a_alt:.0} m, {} {b_alt:.0} m ({higher} {by:.0} m higher)",
+                a.label, b.label
+            );
+        }
+        _ => println!("  Elevation: unknown (no altitude for both nodes)"),
+    }
+    Ok(())
+}
+
+/// A node with a known position.
+struct Located {
+    label: String,
+    lat: f64,
+    lon: f64,
+    alt: Option<f64>,
+}
+
+/// Last advertised position of a node in the neighbor table or cache.
+async fn locate(proto: &mut Protocol, port: &str, node: &str, refresh: bool) -> Result<Located> {
+    let Some(found) = resolve_node(proto, port, node, refresh).await else {
+        bail!(CliError::InvalidArgs(format!(
+            "No node '{node}' in the neighbor table"
+        )));
+    };
+    let Some((lat, lon)) = found
+        .lat
+        .zip(found.lon)
+        .and_then(|(lat, lon)| geo::valid(lat, lon))
+    else {
+        bail!(
+            "{} hasn't advertised a position (try `position request {node}`)",
+            found.label()
+        );
+    };
+    Ok(Located {
+        label: found.label(),
+        lat,
+        lon,
+        alt: found.alt,
+    })
+}
+
+/// The device's GPS fix, or else the fixed position set on it.
+pub(super) async fn own_position(proto: &mut Protocol) -> Result<Option<(f64, f64, Option<f64>)>> {
+    match proto.get_telemetry().await {
+        Ok(telemetry) => {
+            let fix = telemetry.location.filter(|l| l.has_fix()).and_then(|l| {
+                geo::valid(l.latitude(), l.longitude())
+                    .map(|(lat, lon)| (lat, lon, Some(f64::from(l.altitude_meters()))))
+            });
+            if fix.is_some() {
+                return Ok(fix);
+            }
+        }
+        Err(e) => tracing::debug!("Failed to read telemetry: {e:#}"),
+    }
+    match proto.command("POSITION").await? {
+        Response::Json(json) => {
+            Ok(position_fields(&json).filter(|&(lat, lon, _)| geo::valid(lat, lon).is_some()))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// `lat`, `lon` and `alt` of a POSITION response.
+fn position_fields(json: &serde_json::Value) -> Option<(f64, f64, Option<f64>)> {
+    let field = |name| json.get(name).and_then(serde_json::Value::as_f64);
+    Some((field("lat")?, field("lon")?, field("alt")))
+}
+
+/// Store a position on the device, used in its adverts.
+pub(super) async fn set_position(
+    proto: &mut Protocol,
+    lat: f64,
+    lon: f64,
+    alt: Option<f64>,
+) -> Result<()> {
+    let mut command = format!("SET POSITION {lat:.6} {lon:.6}");
+    if let Some(alt) = alt {
+        command.push_str(&format!(" {alt:.1}"));
+    }
+    match proto.command(&command).await? {
+        Response::Ok(_) => Ok(()),
+        Response::Error(e) => bail!(CliError::Device(format!("failed to set position: {e}"))),
+        Response::Json(_) => bail!("Unexpected response to SET POSITION"),
+    }
+}
+
+fn print_remote(label: &str, fix: &RemotePosition) {
+    if output::is_plain() {
+        output::kv("lat", fix.lat);
+        output::kv("lon", fix.lon);
+        if let Some(alt) = fix.alt {
+            output::kv("alt", alt);
+        }
+        if let Some(age) = fix.age_secs {
+            output::kv("age_secs", age);
+        }
+        if let Some(accuracy) = fix.accuracy_m {
+            output::kv("accuracy_m", accuracy);
+        }
+        output::kv("map", map_link(fix.lat, fix.lon));
+        return;
+    }
+
+    println!("\n{label}: {:.6}, {:.6}", fix.lat, fix.lon);
+    if let Some(alt) = fix.alt {
+        println!("  Altitude: {alt:.0} m");
+    }
+    if let Some(age) = fix.age_secs {
+        println!("  Fix age:  {}", timefmt::duration(age));
+    }
+    match (fix.accuracy_m, fix.satellites) {
+        (Some(accuracy), Some(sats)) => {
+            println!("  Accuracy: ±{accuracy:.0} m ({sats} satellites)")
+        }
+        (Some(accuracy), None) => println!("  Accuracy: ±{accuracy:.0} m"),
+        (None, Some(sats)) => println!("  Accuracy: {sats} satellites"),
+        (None, None) => {}
+    }
+    println!("  Map:      {}", map_link(fix.lat, fix.lon));
+}
+
+/// OpenStreetMap link with a marker at the position.
+fn map_link(lat: f64, lon: f64) -> String {
+    format!("https://www.openstreetmap.org/?mlat={lat:.6}&mlon={lon:.6}#map=16/{lat:.6}/{lon:.6}")
+}
+
+pub(super) fn validate(lat: f64, lon: f64) -> Result<()> {
+    if !(-90.0..=90.0).contains(&lat) {
+        bail!(CliError::InvalidArgs(format!(
+            "Latitude {lat} out of range (-90 to 90)"
+        )));
+    }
+    if !(-180.0..=180.0).contains(&lon) {
+        bail!(CliError::InvalidArgs(format!(
+            "Longitude {lon} out of range (-180 to 180)"
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(51.5007, -0.1246).is_ok());
+        assert!(validate(-90.0, 180.0).is_ok());
+        assert!(validate(91.0, 0.0).is_err());
+        assert!(validate(0.0, -180.5).is_err());
+        assert!(validate(f64::NAN, 0.0).is_err());
+
+        assert_eq!(
+            map_link(51.5007, -0.1246),
+            "https://www.openstreetmap.org/?mlat=51.500700&mlon=-0.124600#map=16/51.500700/-0.124600"
+        );
+    }
+}
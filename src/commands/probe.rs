@@ -0,0 +1,228 @@
+//! Pre-flash hardware check
+//!
+//! `espflash board-info` reports the chip, crystal, flash size and embedded
+//! PSRAM of the connected ESP32. Before flashing, these are compared with the
+//! selected board, so picking `heltec-v3` (8 MB flash) for a LilyGo T3S3
+//! (4 MB flash, 2 MB PSRAM) stops with the likely board instead of flashing a
+//! firmware that won't boot.
+
+use super::system::board_chip;
+use crate::cli::BoardType;
+use crate::error::CliError;
+use crate::firmware::Chip;
+use crate::outln;
+use crate::output;
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use std::process::Command;
+
+/// What `espflash board-info` reports.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Hardware {
+    chip: Option<Chip>,
+    /// Chip line as printed, with the revision
+    chip_type: String,
+    crystal_mhz: Option<u32>,
+    flash_mb: Option<u32>,
+    psram_mb: Option<u32>,
+}
+
+impl Hardware {
+    fn summary(&self) -> String {
+        let mut parts = vec![self.chip.map_or(self.chip_type.clone(), |c| {
+            self.chip_type
+                .split_once(' ')
+                .map_or(c.name().to_string(), |(_, rest)| {
+                    format!("{} {rest}", c.name())
+                })
+        })];
+        if let Some(mhz) = self.crystal_mhz {
+            parts.push(format!("{mhz} MHz crystal"));
+        }
+        if let Some(mb) = self.flash_mb {
+            parts.push(format!("{mb} MB flash"));
+        }
+        if let Some(mb) = self.psram_mb {
+            parts.push(format!("{mb} MB PSRAM"));
+        }
+        parts.join(", ")
+    }
+}
+
+/// Megabytes in a size like `8MB` or `512KB`.
+fn parse_mb(text: &str) -> Option<u32> {
+    let text = text.trim().to_ascii_uppercase();
+    let digits: String = text.chars().take_while(char::is_ascii_digit).collect();
+    let n: u32 = digits.parse().ok()?;
+    match text[digits.len()..].trim() {
+        "MB" | "M" => Some(n),
+        "KB" | "K" => Some(n / 1024),
+        _ => None,
+    }
+}
+
+fn parse_board_info(text: &str) -> Hardware {
+    let mut hw = Hardware::default();
+    for line in text.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match key.trim() {
+            "Chip type" => {
+                hw.chip_type = value.to_string();
+                hw.chip = match value.split_whitespace().next().unwrap_or_default() {
+                    "esp32" => Some(Chip::Esp32),
+                    "esp32s2" => Some(Chip::Esp32S2),
+                    "esp32s3" => Some(Chip::Esp32S3),
+                    "esp32c3" => Some(Chip::Esp32C3),
+                    "esp32c6" => Some(Chip::Esp32C6),
+                    _ => None,
+                };
+            }
+            "Crystal frequency" => {
+                hw.crystal_mhz = value.trim_end_matches("MHz").trim().parse().ok();
+            }
+            "Flash size" => hw.flash_mb = parse_mb(value),
+            "Features" => {
+                hw.psram_mb = value
+                    .split(',')
+                    .find_map(|f| f.trim().strip_prefix("Embedded PSRAM"))
+                    .and_then(parse_mb);
+            }
+            _ => {}
+        }
+    }
+    hw
+}
+
+/// Flash size (MB) of boards whose module is known.
+fn board_flash_mb(board: BoardType) -> Option<u32> {
+    use BoardType::*;
+    match board {
+        HeltecV3
+        | HeltecWirelessStickLiteV3
+        | HeltecWirelessTracker
+        | HeltecWirelessPaper
+        | SeeedXiaoEsp32s3 => Some(8),
+        HeltecV4
+        | HeltecVisionMasterT190
+        | HeltecVisionMasterE213
+        | HeltecVisionMasterE290
+        | LilygoTdeck
+        | StationG2 => Some(16),
+        LilygoT3s3 | LilygoTbeam | HeltecHt62 => Some(4),
+        _ => None,
+    }
+}
+
+fn cli_name(board: BoardType) -> String {
+    board
+        .to_possible_value()
+        .map(|v| v.get_name().to_string())
+        .unwrap_or_default()
+}
+
+/// Boards with this chip and flash size.
+fn likely_boards(hw: &Hardware) -> Vec<String> {
+    BoardType::value_variants()
+        .iter()
+        .filter(|b| board_chip(**b) == hw.chip && board_flash_mb(**b) == hw.flash_mb)
+        .map(|b| cli_name(*b))
+        .collect()
+}
+
+/// Probe the ESP32 on `port` and refuse to flash `board` firmware if its
+/// chip or flash size don't match. Devices espflash can't talk to (nRF52,
+/// RP2040) are not checked.
+pub(super) fn check_hardware(
+    board: BoardType,
+    port: Option<&str>,
+    running: Option<&str>,
+) -> Result<()> {
+    let plain = output::is_plain();
+    let mut command = Command::new("espflash");
+    command.arg("board-info");
+    if let Some(p) = port {
+        command.args(["--port", p]);
+    }
+    let hw = match command.output() {
+        Ok(out) if out.status.success() => parse_board_info(&format!(
+            "{}\n{}",
+            String::from_utf8_lossy(&out.stdout),
+            String::from_utf8_lossy(&out.stderr)
+        )),
+        _ => Hardware::default(),
+    };
+    if hw.chip.is_none() {
+        if !plain {
+            outln!("⚠ Could not probe the hardware, skipping the board check");
+        }
+        return Ok(());
+    }
+
+    if plain {
+        output::kv("chip", hw.chip.map_or("", Chip::name));
+        if let Some(mb) = hw.flash_mb {
+            output::kv("flash_mb", mb);
+        }
+        if let Some(mb) = hw.psram_mb {
+            output::kv("psram_mb", mb);
+        }
+    } else {
+        outln!("Hardware: {}", hw.summary());
+        if let Some(running) = running {
+            outln!("Running firmware: {running}");
+        }
+    }
+
+    let name = cli_name(board);
+    let mismatch = if board_chip(board).is_some_and(|chip| Some(chip) != hw.chip) {
+        format!(
+            "The {name} is an {} but this device is an {}",
+            board_chip(board).map_or("", Chip::name),
+            hw.chip.map_or("", Chip::name)
+        )
+    } else if let (Some(expected), Some(found)) = (board_flash_mb(board), hw.flash_mb) {
+        if expected == found {
+            return Ok(());
+        }
+        format!("The {name} has {expected} MB of flash but this device has {found} MB")
+    } else {
+        return Ok(());
+    };
+    let hint = match likely_boards(&hw).as_slice() {
+        [] => String::new(),
+        [board] => format!("; it looks like a {board}"),
+        boards => format!("; it looks like one of {}", boards.join(", ")),
+    };
+    bail!(CliError::InvalidArgs(format!(
+        "{mismatch}{hint}. Pick the right board, or pass --no-hardware-check to flash anyway"
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_board_info() {
+        let t3s3 = "[INFO] Serial port: '/dev/ttyACM0'\n\
+                    [INFO] Connecting...\n\
+                    Chip type:         esp32s3 (revision v0.2)\n\
+                    Crystal frequency: 40 MHz\n\
+                    Flash size:        4MB\n\
+                    Features:          WiFi, BLE, Embedded PSRAM 2MB\n\
+                    MAC address:       48:27:e2:00:11:22\n";
+        let hw = parse_board_info(t3s3);
+        assert_eq!(hw.chip, Some(Chip::Esp32S3));
+        assert_eq!(hw.crystal_mhz, Some(40));
+        assert_eq!((hw.flash_mb, hw.psram_mb), (Some(4), Some(2)));
+        assert_eq!(
+            hw.summary(),
+            "ESP32-S3 (revision v0.2), 40 MHz crystal, 4 MB flash, 2 MB PSRAM"
+        );
+        assert_eq!(likely_boards(&hw), ["lilygo-t3s3"]);
+        assert_eq!(parse_board_info("nothing").chip, None);
+    }
+}
@@ -0,0 +1,133 @@
+//! Signed provisioning bundles
+
+use super::messaging::apply_channels;
+use super::{connect_with_auth, require_port};
+use crate::cli::ProvisionAction;
+use crate::error::CliError;
+use crate::provision::{self, Bundle};
+use crate::settings::Settings;
+use anyhow::{bail, Context, Result};
+
+/// Create, sign, verify and apply provisioning bundles
+pub async fn cmd_provision(
+    port: Option<&String>,
+    baud: u32,
+    pin: Option<&str>,
+    action: ProvisionAction,
+) -> Result<()> {
+    match action {
+        ProvisionAction::Keygen { key } => {
+            let signing_key = provision::generate_key(&key)?;
+            println!("✓ Wrote signing key to {key}");
+            println!("  Public key: {}", provision::public_key(&signing_key));
+            println!(
+                "Add the public key to [provision] trusted_keys on machines that apply bundles."
+            );
+        }
+        ProvisionAction::Sign { bundle, key } => {
+            let signing_key = provision::load_key(&key)?;
+            let text = read_bundle(&bundle)?;
+            let signed = provision::sign(&text, &signing_key)?;
+            std::fs::write(&bundle, signed).with_context(|| format!("Failed to write {bundle}"))?;
+            println!(
+                "✓ Signed {bundle} with {}",
+                provision::public_key(&signing_key)
+            );
+        }
+        ProvisionAction::Verify {
+            bundle,
+            trusted_key,
+        } => {
+            let text = read_bundle(&bundle)?;
+            let parsed = Bundle::parse(&text)?;
+            let signer = parsed
+                .verify(&trusted_keys(trusted_key)?)
+                .with_context(|| format!("{bundle} failed verification"))?;
+            parsed.manifest()?;
+            println!("✓ {bundle} is signed by trusted key {signer}");
+        }
+        ProvisionAction::Apply {
+            bundle,
+            trusted_key,
+            insecure,
+        } => {
+            let port = require_port(port)?;
+            apply_bundle(&port, baud, pin, &bundle, trusted_key, insecure).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Check a bundle's signature (unless `insecure`) and apply it to the device
+pub(super) async fn apply_bundle(
+    port: &str,
+    baud: u32,
+    pin: Option<&str>,
+    bundle: &str,
+    trusted_key: Vec<String>,
+    insecure: bool,
+) -> Result<()> {
+    let text = read_bundle(bundle)?;
+    let parsed = Bundle::parse(&text)?;
+    if insecure {
+        eprintln!("⚠ Skipping signature check (--insecure)");
+    } else {
+        let signer = parsed
+            .verify(&trusted_keys(trusted_key)?)
+            .with_context(|| format!("Refusing to apply {bundle} (use --insecure to override)"))?;
+        println!("✓ Signature OK (key {signer})");
+    }
+    let manifest = parsed.manifest()?;
+
+    let mut dev = connect_with_auth(port, baud, pin).await?;
+    let settings = &manifest.device;
+    if let Some(preset) = &settings.preset {
+        dev.set_preset(preset).await?;
+        println!("  Preset:    {preset}");
+    }
+    if let Some(name) = &settings.name {
+        dev.set_name(name).await?;
+        println!("  Name:      {name}");
+    }
+    if let Some(freq_mhz) = settings.frequency_mhz {
+        dev.set_frequency(freq_mhz).await?;
+        println!("  Frequency: {freq_mhz:.3} MHz");
+    }
+    if let Some(power_dbm) = settings.tx_power_dbm {
+        dev.set_power(power_dbm).await?;
+        println!("  TX Power:  {power_dbm} dBm");
+    }
+    if let Some(bandwidth_khz) = settings.bandwidth_khz {
+        dev.set_bandwidth(bandwidth_khz).await?;
+        println!("  Bandwidth: {bandwidth_khz} kHz");
+    }
+    if let Some(sf) = settings.spreading_factor {
+        dev.set_spreading_factor(sf).await?;
+        println!("  Spreading: SF{sf}");
+    }
+
+    if !manifest.channels.is_empty() {
+        let mut proto = dev.into_protocol();
+        apply_channels(&mut proto, manifest.channels, bundle, false, false).await?;
+    }
+    println!("✓ Applied {bundle}");
+    Ok(())
+}
+
+fn read_bundle(path: &str) -> Result<String> {
+    std::fs::read_to_string(path).with_context(|| format!("Failed to read {path}"))
+}
+
+/// Keys from `--trusted-key` plus `[provision] trusted_keys` in the config.
+fn trusted_keys(extra: Vec<String>) -> Result<Vec<String>> {
+    let mut keys = Settings::load()?.provision.trusted_keys;
+    keys.extend(extra);
+    if keys.is_empty() {
+        bail!(CliError::InvalidArgs(format!(
+            "No trusted provisioning keys. Add them to [provision] trusted_keys in {} or pass --trusted-key",
+            Settings::path()?.display()
+        )));
+    }
+    Ok(keys)
+}
@@ -0,0 +1,334 @@
+//! Crash-loop recovery
+//!
+//! Listens to the raw serial output of a device that keeps rebooting,
+//! works out why it resets from the boot banners, saves what it saw to a
+//! diagnostic bundle and walks through the fixes: erasing the settings
+//! partition and reflashing the firmware.
+
+use super::crashlog::backtrace_addresses;
+use super::nvs::erase_settings;
+use super::system::cmd_flash;
+use crate::confirm::{confirm, Operation, Target};
+use crate::error::CliError;
+use crate::output;
+use crate::protocol::BANNERS;
+use crate::serial::{cobs_decode_in_place, SerialPort};
+use anyhow::{bail, Context, Result};
+use std::fmt::Write as _;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Why the device resets, from its boot and panic output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ResetReason {
+    Brownout,
+    Watchdog,
+    Panic,
+    Unknown,
+}
+
+impl ResetReason {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Brownout => "brownout",
+            Self::Watchdog => "watchdog",
+            Self::Panic => "panic",
+            Self::Unknown => "unknown",
+        }
+    }
+
+    fn explanation(self) -> &'static str {
+        match self {
+            Self::Brownout => "the supply voltage drops below the brownout threshold",
+            Self::Watchdog => "a task or interrupt hangs until the watchdog resets the chip",
+            Self::Panic => "the firmware crashes on a panic, abort or failed assertion",
+            Self::Unknown => "no reset banner was seen",
+        }
+    }
+}
+
+/// Classify the resets in `text`. A brownout explains any panic or watchdog
+/// reset that follows it, and a watchdog panic is reported as a watchdog.
+fn reset_reason(text: &str) -> ResetReason {
+    let upper = text.to_ascii_uppercase();
+    if upper.contains("BROWNOUT") || upper.contains("BROWN_OUT") {
+        ResetReason::Brownout
+    } else if upper.contains("WDT") || upper.contains("WATCHDOG") {
+        ResetReason::Watchdog
+    } else if [
+        "GURU MEDITATION",
+        "ABORT() WAS CALLED",
+        "ASSERT FAILED",
+        "CORRUPT HEAP",
+        "HARDFAULT",
+        "PANIC",
+    ]
+    .iter()
+    .any(|marker| upper.contains(marker))
+    {
+        ResetReason::Panic
+    } else {
+        ResetReason::Unknown
+    }
+}
+
+/// Boots seen in `text` (one ROM `rst:` line each).
+fn boot_count(text: &str) -> usize {
+    text.lines().filter(|line| line.contains("rst:0x")).count()
+}
+
+/// Lines of `text` holding a boot or crash banner, deduplicated.
+fn banner_lines(text: &str) -> Vec<&str> {
+    let mut lines: Vec<&str> = Vec::new();
+    for line in text.lines().map(str::trim) {
+        if BANNERS.iter().any(|b| line.contains(b)) && !lines.contains(&line) {
+            lines.push(line);
+        }
+    }
+    lines
+}
+
+/// Capture everything the device prints for `duration`, decoding COBS
+/// frames and keeping unframed boot ROM output as is.
+async fn capture(port: &str, baud: u32, duration: Duration) -> Result<String> {
+    let mut serial = SerialPort::open(port, baud).await?;
+    let mut text = String::new();
+    let mut pending = Vec::new();
+    let mut buf = [0u8; 1024];
+    let deadline = Instant::now() + duration;
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let Some(n) = serial.read_timeout(&mut buf, remaining).await? else {
+            break;
+        };
+        if n == 0 {
+            break;
+        }
+        pending.extend_from_slice(&buf[..n]);
+        while let Some(pos) = pending.iter().position(|&b| b == 0) {
+            let raw: Vec<u8> = pending.drain(..=pos).collect();
+            let mut frame = raw[..pos].to_vec();
+            // Text frames never hold a zero: one that decodes to one was
+            // unframed output ending at the next frame
+            match cobs_decode_in_place(&mut frame) {
+                Some(len) if !frame[..len].contains(&0) => {
+                    text.push_str(&String::from_utf8_lossy(&frame[..len]));
+                }
+                _ => text.push_str(&String::from_utf8_lossy(&raw[..pos])),
+            }
+            if !text.ends_with('\n') {
+                text.push('\n');
+            }
+        }
+    }
+    text.push_str(&String::from_utf8_lossy(&pending));
+    Ok(text.replace("\r\n", "\n"))
+}
+
+/// Steps that may fix a reset loop of this kind.
+fn suggestions(reason: ResetReason) -> Vec<&'static str> {
+    match reason {
+        ResetReason::Brownout => vec![
+            "Use a shorter USB cable or a powered hub, or charge/replace the battery",
+            "Check the antenna is connected: transmitting without one can pull the supply down",
+            "Once stable, lower the transmit power (meshgrid-cli config power <dBm>)",
+        ],
+        ResetReason::Watchdog | ResetReason::Panic => vec![
+            "Erase the settings partition: corrupted settings are the usual cause of a crash at boot",
+            "Reflash the firmware if the loop continues",
+            "Report the bug with the diagnostic bundle attached",
+        ],
+        ResetReason::Unknown => vec![
+            "Check the port and baud rate: nothing recognizable was captured",
+            "Hold BOOT while pressing RESET to enter the bootloader, then reflash the firmware",
+        ],
+    }
+}
+
+/// Write the bundle: the raw capture and a report.
+fn write_bundle(dir: &Path, port: &str, text: &str, reason: ResetReason) -> Result<()> {
+    std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    std::fs::write(dir.join("boot.log"), text)?;
+
+    let mut report = String::new();
+    writeln!(report, "# Crash-loop diagnostics\n")?;
+    writeln!(report, "Captured: {}", chrono::Local::now().to_rfc3339())?;
+    writeln!(report, "Port:     {port}")?;
+    writeln!(
+        report,
+        "CLI:      meshgrid-cli {}",
+        env!("CARGO_PKG_VERSION")
+    )?;
+    writeln!(report, "Boots:    {}", boot_count(text))?;
+    writeln!(
+        report,
+        "Reason:   {} ({})",
+        reason.name(),
+        reason.explanation()
+    )?;
+    writeln!(report, "\n## Banners\n")?;
+    for line in banner_lines(text) {
+        writeln!(report, "    {line}")?;
+    }
+    let addresses = if text.contains("Backtrace:") {
+        backtrace_addresses(text)
+    } else {
+        Vec::new()
+    };
+    if !addresses.is_empty() {
+        writeln!(report, "\n## Backtrace\n")?;
+        for address in addresses {
+            writeln!(report, "0x{address:08x}")?;
+        }
+    }
+    writeln!(report, "\n## Suggested steps\n")?;
+    for (i, step) in suggestions(reason).iter().enumerate() {
+        writeln!(report, "{}. {step}", i + 1)?;
+    }
+    std::fs::write(dir.join("report.md"), report)?;
+    Ok(())
+}
+
+/// Diagnose a device stuck in a reset loop and suggest (or, with `fix`,
+/// run) the recovery steps.
+pub async fn cmd_recover(
+    port: &str,
+    baud: u32,
+    seconds: u64,
+    output_dir: Option<&str>,
+    fix: bool,
+    yes: bool,
+) -> Result<()> {
+    let plain = output::is_plain();
+    if fix && (port.starts_with("tcp://") || port.starts_with("unix:")) {
+        bail!(CliError::InvalidArgs(
+            "--fix needs the device's serial port, not a network connection".into()
+        ));
+    }
+    if fix && !yes && !std::io::IsTerminal::is_terminal(&std::io::stdin()) {
+        bail!(CliError::InvalidArgs(
+            "--fix asks before each step; pass --yes to run without a terminal".into()
+        ));
+    }
+
+    if !plain {
+        println!("Capturing boot output from {port} for {seconds}s...");
+    }
+    let text = capture(port, baud, Duration::from_secs(seconds)).await?;
+    let boots = boot_count(&text);
+    let reason = reset_reason(&text);
+    let dir = output_dir.map_or_else(
+        || format!("recovery-{}", chrono::Local::now().format("%Y%m%d-%H%M%S")),
+        String::from,
+    );
+    write_bundle(Path::new(&dir), port, &text, reason)?;
+
+    if plain {
+        output::kv("boots", boots);
+        output::kv("reason", reason.name());
+        output::kv("bundle", &dir);
+    } else {
+        if boots > 1 {
+            println!("⚠ {boots} boots in {seconds}s: the device is in a reset loop");
+        } else if boots == 1 {
+            println!("⚠ The device rebooted once while listening");
+        } else {
+            println!("✓ No reboot seen while listening");
+        }
+        println!("Reset reason: {} ({})", reason.name(), reason.explanation());
+        for line in banner_lines(&text).iter().take(5) {
+            println!("  {line}");
+        }
+        println!("✓ Diagnostic bundle saved to {dir}/");
+        println!("\nSuggested steps:");
+        for (i, step) in suggestions(reason).iter().enumerate() {
+            println!("  {}. {step}", i + 1);
+        }
+    }
+
+    if !fix {
+        if !plain && matches!(reason, ResetReason::Watchdog | ResetReason::Panic) {
+            println!("\nRun again with --fix to erase the settings and reflash, step by step.");
+        }
+        return Ok(());
+    }
+    if reason == ResetReason::Brownout {
+        if !plain {
+            println!("\n⚠ A brownout is a power problem; erasing or reflashing won't fix it.");
+        }
+        return Ok(());
+    }
+
+    // The device can't answer while it resets, so it's named by its port
+    let target = Target::new(Some(port), None, None);
+    if reason != ResetReason::Unknown && confirm(Operation::FactoryReset, &target)? {
+        erase_settings(Some(port))?;
+        if plain {
+            output::kv("erased", "settings");
+        } else {
+            println!("✓ Settings erased");
+        }
+
+        let text = capture(port, baud, Duration::from_secs(seconds)).await?;
+        if boot_count(&text) <= 1 {
+            if !plain {
+                println!("✓ The device booted without resetting again");
+            }
+            return Ok(());
+        }
+        if !plain {
+            println!("✗ Still resetting after the erase");
+        }
+    }
+
+    if !plain {
+        println!("\nNext step: reflash the firmware (erases the whole flash)");
+    }
+    cmd_flash(
+        None,
+        Some(port),
+        false,
+        None,
+        false,
+        None,
+        false,
+        false,
+        false,
+        true,
+        false,
+        true,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reset_reason() {
+        let panic = "ets Jun  8 2016 00:22:57\n\
+                     rst:0xc (SW_CPU_RESET),boot:0x13 (SPI_FAST_FLASH_BOOT)\n\
+                     Guru Meditation Error: Core  1 panic'ed (LoadProhibited)\n\
+                     Backtrace: 0x400d1a2b:0x3ffb1f50\n\
+                     rst:0xc (SW_CPU_RESET),boot:0x13 (SPI_FAST_FLASH_BOOT)\n";
+        assert_eq!(reset_reason(panic), ResetReason::Panic);
+        assert_eq!(boot_count(panic), 2);
+        assert_eq!(banner_lines(panic).len(), 4);
+
+        assert_eq!(
+            reset_reason("Brownout detector was triggered\nrst:0xc (SW_CPU_RESET)"),
+            ResetReason::Brownout
+        );
+        assert_eq!(
+            reset_reason("rst:0x8 (TG1WDT_SYS_RESET),boot:0x13"),
+            ResetReason::Watchdog
+        );
+        assert_eq!(
+            reset_reason("Guru Meditation Error: Core  0 panic'ed (Interrupt wdt timeout on CPU0)"),
+            ResetReason::Watchdog
+        );
+        assert_eq!(reset_reason("OK"), ResetReason::Unknown);
+    }
+}
@@ -0,0 +1,82 @@
+//! Capture replay
+
+use super::connect_with_auth;
+use crate::capture;
+use crate::error::CliError;
+use crate::output;
+use crate::packet::Packet;
+use anyhow::{bail, Result};
+use std::time::{Duration, Instant};
+
+/// Re-send the packets of a capture with their original spacing
+pub async fn cmd_replay(
+    port: &str,
+    baud: u32,
+    pin: Option<&str>,
+    file: &str,
+    speed: &str,
+) -> Result<()> {
+    let speed = parse_speed(speed)?;
+    let packets = capture::read(std::path::Path::new(file))?;
+    let mut proto = connect_with_auth(port, baud, pin).await?.into_protocol();
+
+    let plain = output::is_plain();
+    if !plain {
+        let span = packets.last().map_or(Duration::ZERO, |p| p.offset);
+        println!(
+            "Replaying {} packets ({:.1}s of capture) through {port}...\n",
+            packets.len(),
+            span.as_secs_f64()
+        );
+    }
+
+    let start = Instant::now();
+    for (i, packet) in packets.iter().enumerate() {
+        if speed > 0.0 {
+            let due = packet.offset.div_f64(speed);
+            tokio::time::sleep(due.saturating_sub(start.elapsed())).await;
+        }
+        proto.send_packet(&packet.data).await?;
+        if !plain {
+            let summary = Packet::parse(&packet.data)
+                .map_or_else(|_| format!("{} bytes", packet.data.len()), |p| p.summary());
+            println!(
+                "[{:>7.2}s] {}/{}: {summary}",
+                packet.offset.as_secs_f64(),
+                i + 1,
+                packets.len()
+            );
+        }
+    }
+
+    if plain {
+        output::kv("replayed", packets.len());
+    } else {
+        println!("\n✓ Replayed {} packets", packets.len());
+    }
+    Ok(())
+}
+
+/// Playback speed factor from `2x`, `0.5` and the like.
+fn parse_speed(speed: &str) -> Result<f64> {
+    match speed.trim().trim_end_matches(['x', 'X']).parse::<f64>() {
+        Ok(factor) if factor >= 0.0 && factor.is_finite() => Ok(factor),
+        _ => bail!(CliError::InvalidArgs(format!(
+            "Invalid --speed '{speed}' (expected e.g. 1x, 2x, 0.5x, or 0 for no delay)"
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_speed() {
+        assert_eq!(parse_speed("2x").unwrap(), 2.0);
+        assert_eq!(parse_speed("0.5").unwrap(), 0.5);
+        assert_eq!(parse_speed("0").unwrap(), 0.0);
+        assert!(parse_speed("-1x").is_err());
+        assert!(parse_speed("fast").is_err());
+    }
+}
@@ -0,0 +1,622 @@
+//! `rf noise`: the noise floor over hours, to find interference.
+//! `rf abtest`: which of two antennas links better to a node.
+
+use super::collector::CsvLog;
+use super::connect_with_auth;
+use super::contacts::resolve_node;
+use crate::error::CliError;
+use crate::output;
+use crate::protocol::{ProbeResult, Protocol};
+use crate::timefmt;
+use anyhow::{bail, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+const NOISE_COLUMNS: [&str; 3] = ["timestamp", "freq_mhz", "noise_floor_dbm"];
+
+/// Samples of one hour of the day.
+#[derive(Debug, PartialEq)]
+struct HourStats {
+    hour: u32,
+    samples: usize,
+    avg_dbm: f32,
+    max_dbm: f32,
+}
+
+/// Group `(hour, dBm)` samples by hour of the day.
+fn by_hour(samples: &[(u32, f32)]) -> Vec<HourStats> {
+    let mut hours: BTreeMap<u32, Vec<f32>> = BTreeMap::new();
+    for &(hour, dbm) in samples {
+        hours.entry(hour).or_default().push(dbm);
+    }
+    hours
+        .into_iter()
+        .map(|(hour, values)| HourStats {
+            hour,
+            samples: values.len(),
+            avg_dbm: values.iter().sum::<f32>() / values.len() as f32,
+            max_dbm: values.iter().copied().fold(f32::MIN, f32::max),
+        })
+        .collect()
+}
+
+/// The device connection, reopened after it fails.
+struct Sampler<'a> {
+    port: &'a str,
+    baud: u32,
+    pin: Option<&'a str>,
+    proto: Option<Protocol>,
+    freq_mhz: f32,
+}
+
+impl Sampler<'_> {
+    async fn connect(&mut self) -> Result<&mut Protocol> {
+        if self.proto.is_none() {
+            let mut proto = connect_with_auth(self.port, self.baud, self.pin)
+                .await?
+                .into_protocol();
+            self.freq_mhz = proto.get_config().await?.freq_mhz;
+            self.proto = Some(proto);
+        }
+        Ok(self.proto.as_mut().expect("connected above"))
+    }
+
+    async fn sample(&mut self) -> Result<f32> {
+        let result = self.connect().await?.get_noise_floor().await;
+        if result.is_err() {
+            self.proto = None;
+        }
+        result
+    }
+}
+
+/// Sample the noise floor every `interval` seconds for `hours`, printing
+/// each reading and appending it to the CSV file `output`
+pub async fn cmd_rf_noise(
+    port: &str,
+    baud: u32,
+    pin: Option<&str>,
+    hours: f64,
+    interval: u64,
+    output: Option<&str>,
+) -> Result<()> {
+    if !hours.is_finite() || hours < 0.0 {
+        bail!(CliError::InvalidArgs(format!("Invalid --hours {hours}")));
+    }
+    let mut csv = output
+        .map(|path| CsvLog::open(PathBuf::from(path), &NOISE_COLUMNS))
+        .transpose()?;
+    let mut sampler = Sampler {
+        port,
+        baud,
+        pin,
+        proto: None,
+        freq_mhz: 0.0,
+    };
+    sampler.connect().await?;
+
+    let duration = Duration::from_secs_f64(hours * 3600.0);
+    let until = if hours > 0.0 {
+        format!("for {}", timefmt::duration(duration.as_secs()))
+    } else {
+        "until Ctrl+C".to_string()
+    };
+    println!(
+        "Sampling the noise floor at {} MHz every {interval}s {until}{}\n",
+        sampler.freq_mhz,
+        output.map_or(String::new(), |path| format!(", logging to {path}"))
+    );
+
+    let started = Instant::now();
+    let mut ticker = tokio::time::interval(Duration::from_secs(interval));
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut samples: Vec<(DateTime<Utc>, f32)> = Vec::new();
+    let mut failing = false;
+    loop {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => break,
+            _ = ticker.tick() => {}
+        }
+        if hours > 0.0 && started.elapsed() >= duration {
+            break;
+        }
+        match sampler.sample().await {
+            Ok(dbm) => {
+                if failing {
+                    println!("[{}] ✓ Reconnected", timefmt::clock());
+                    failing = false;
+                }
+                let now = Utc::now();
+                println!("[{}] {dbm:.1} dBm", timefmt::clock());
+                if let Some(csv) = csv.as_mut() {
+                    csv.append(&[
+                        now.to_rfc3339_opts(SecondsFormat::Secs, true),
+                        sampler.freq_mhz.to_string(),
+                        format!("{dbm:.1}"),
+                    ]);
+                }
+                samples.push((now, dbm));
+            }
+            Err(e) if !failing => {
+                println!("[{}] ✗ {e:#}; retrying", timefmt::clock());
+                failing = true;
+            }
+            Err(e) => tracing::debug!("Sample failed: {e:#}"),
+        }
+    }
+
+    println!();
+    if samples.is_empty() {
+        println!("No samples taken");
+        return Ok(());
+    }
+    let values: Vec<f32> = samples.iter().map(|(_, dbm)| *dbm).collect();
+    let min = values.iter().copied().fold(f32::MAX, f32::min);
+    let max = values.iter().copied().fold(f32::MIN, f32::max);
+    let avg = values.iter().sum::<f32>() / values.len() as f32;
+    println!(
+        "Noise floor over {} ({} samples): min {min:.1}, avg {avg:.1}, max {max:.1} dBm",
+        timefmt::duration(started.elapsed().as_secs()),
+        samples.len()
+    );
+
+    let hourly: Vec<(u32, f32)> = samples
+        .iter()
+        .map(|(t, dbm)| (timefmt::hour(t), *dbm))
+        .collect();
+    let hourly = by_hour(&hourly);
+    if hourly.len() > 1 {
+        println!("\n  HOUR   SAMPLES  AVG (dBm)  MAX (dBm)");
+        for h in &hourly {
+            println!(
+                "  {:02}:00  {:<7}  {:<9.1}  {:.1}",
+                h.hour, h.samples, h.avg_dbm, h.max_dbm
+            );
+        }
+        let quietest = hourly.iter().map(|h| h.avg_dbm).fold(f32::MAX, f32::min);
+        if let Some(noisiest) = hourly.iter().max_by(|a, b| a.avg_dbm.total_cmp(&b.avg_dbm)) {
+            println!(
+                "\nNoisiest hour: {:02}:00, {:.1} dB above the quietest",
+                noisiest.hour,
+                noisiest.avg_dbm - quietest
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Two-sided 95% critical values of Student's t for 1 to 30 degrees of
+/// freedom; the normal 1.96 beyond.
+const T_95: [f64; 30] = [
+    12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160,
+    2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056,
+    2.052, 2.048, 2.045, 2.042,
+];
+
+/// Pause between probes, to go easy on airtime
+const PROBE_GAP: Duration = Duration::from_millis(500);
+
+/// A signal measure of a probe.
+struct Metric {
+    name: &'static str,
+    unit: &'static str,
+    get: fn(&ProbeResult) -> Option<f32>,
+}
+
+const METRICS: [Metric; 4] = [
+    Metric {
+        name: "RSSI here",
+        unit: "dBm",
+        get: |p| Some(p.rssi),
+    },
+    Metric {
+        name: "SNR here",
+        unit: "dB",
+        get: |p| Some(p.snr),
+    },
+    Metric {
+        name: "RSSI there",
+        unit: "dBm",
+        get: |p| p.remote_rssi,
+    },
+    Metric {
+        name: "SNR there",
+        unit: "dB",
+        get: |p| p.remote_snr,
+    },
+];
+
+/// Mean and sample standard deviation.
+fn mean_sd(values: &[f64]) -> Option<(f64, f64)> {
+    if values.is_empty() {
+        return None;
+    }
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let var = if values.len() > 1 {
+        values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0)
+    } else {
+        0.0
+    };
+    Some((mean, var.sqrt()))
+}
+
+/// How much B differs from A on one measure.
+#[derive(Debug, PartialEq, Serialize)]
+struct Difference {
+    metric: &'static str,
+    /// Mean of B - A over the rounds
+    mean: f64,
+    /// Half width of the 95% confidence interval, with two rounds or more
+    ci95: Option<f64>,
+}
+
+impl Difference {
+    /// `None` when there are too few rounds to tell.
+    fn significant(&self) -> Option<bool> {
+        self.ci95.map(|ci| self.mean.abs() > ci)
+    }
+}
+
+/// Paired comparison of per-round B - A differences (a paired t-test), so
+/// a drift in conditions over the test cancels out.
+fn paired(metric: &'static str, diffs: &[f64]) -> Option<Difference> {
+    let (mean, sd) = mean_sd(diffs)?;
+    let ci95 = (diffs.len() > 1).then(|| {
+        let t = T_95.get(diffs.len() - 2).copied().unwrap_or(1.96);
+        t * sd / (diffs.len() as f64).sqrt()
+    });
+    Some(Difference { metric, mean, ci95 })
+}
+
+/// Whether two loss rates differ significantly (two-proportion z-test);
+/// `None` when neither antenna lost anything.
+fn loss_differs(lost: [u32; 2], sent: [u32; 2]) -> Option<bool> {
+    let pooled = f64::from(lost[0] + lost[1]) / f64::from(sent[0] + sent[1]);
+    if pooled == 0.0 {
+        return None;
+    }
+    let rate = |i: usize| f64::from(lost[i]) / f64::from(sent[i]);
+    let se =
+        (pooled * (1.0 - pooled) * (1.0 / f64::from(sent[0]) + 1.0 / f64::from(sent[1]))).sqrt();
+    Some(se == 0.0 || ((rate(1) - rate(0)) / se).abs() > 1.96)
+}
+
+/// One burst of probes through one antenna.
+#[derive(Serialize)]
+struct Burst {
+    round: u32,
+    antenna: String,
+    sent: u32,
+    replies: Vec<ProbeResult>,
+    #[serde(skip)]
+    index: usize,
+}
+
+impl Burst {
+    fn mean(&self, metric: &Metric) -> Option<f64> {
+        let values: Vec<f64> = self
+            .replies
+            .iter()
+            .filter_map(|p| (metric.get)(p).map(f64::from))
+            .collect();
+        mean_sd(&values).map(|(mean, _)| mean)
+    }
+}
+
+/// Totals of one antenna.
+#[derive(Serialize)]
+struct AntennaStats {
+    antenna: String,
+    sent: u32,
+    received: u32,
+    /// Mean and standard deviation of each measure
+    signal: BTreeMap<&'static str, (f64, f64)>,
+}
+
+#[derive(Serialize)]
+struct AbReport<'a> {
+    to: &'a str,
+    bursts: &'a [Burst],
+    antennas: Vec<AntennaStats>,
+    /// B - A
+    differences: Vec<Difference>,
+    loss_significant: Option<bool>,
+}
+
+/// Wait for the operator to connect `antenna`; lines come from stdin, so
+/// a script or RF switch can drive the test too.
+async fn wait_for_antenna<R>(
+    lines: &mut tokio::io::Lines<R>,
+    antenna: &str,
+    first: bool,
+) -> Result<()>
+where
+    R: tokio::io::AsyncBufRead + Unpin,
+{
+    let prompt = if first {
+        format!("Connect antenna {antenna}, then press Enter")
+    } else {
+        format!("Swap to antenna {antenna}, then press Enter")
+    };
+    if output::is_structured() {
+        eprintln!("{prompt}");
+    } else {
+        println!("\n{prompt}");
+    }
+    if lines.next_line().await?.is_none() {
+        bail!(CliError::InvalidArgs(format!(
+            "stdin closed while waiting for antenna {antenna}"
+        )));
+    }
+    Ok(())
+}
+
+/// Compare two antennas by probing `to` in interleaved bursts, prompting
+/// for a swap between bursts
+#[allow(clippy::too_many_arguments)]
+pub async fn cmd_rf_abtest(
+    port: &str,
+    baud: u32,
+    pin: Option<&str>,
+    to: &str,
+    rounds: u32,
+    probes: u32,
+    timeout: u64,
+    labels: &[String],
+) -> Result<()> {
+    use tokio::io::{AsyncBufReadExt, BufReader};
+
+    let labels = match labels {
+        [a, b] if a != b => [a.as_str(), b.as_str()],
+        _ => bail!(CliError::InvalidArgs(format!(
+            "--labels needs two different names, not {}",
+            labels.join(",")
+        ))),
+    };
+    super::restore_blocking_stdin();
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+
+    let mut proto = connect_with_auth(port, baud, pin).await?.into_protocol();
+    let (target, label) = match resolve_node(&mut proto, port, to, false).await {
+        Some(found) => (format!("0x{:02x}", found.node_hash), found.label()),
+        None => (to.to_string(), to.to_string()),
+    };
+    let structured = output::is_structured();
+    if !structured {
+        println!(
+            "Comparing antennas {} and {} on {label}: {rounds} round(s) of {probes} probe(s) each",
+            labels[0], labels[1]
+        );
+    }
+
+    let timeout = Duration::from_secs(timeout);
+    let mut bursts: Vec<Burst> = Vec::new();
+    let mut connected = None;
+    for round in 1..=rounds {
+        // A-B, then B-A: one swap a round, and drift hits both alike
+        let order = if round % 2 == 1 { [0, 1] } else { [1, 0] };
+        for index in order {
+            if connected != Some(index) {
+                wait_for_antenna(&mut lines, labels[index], connected.is_none()).await?;
+                connected = Some(index);
+            }
+            let mut burst = Burst {
+                round,
+                antenna: labels[index].to_string(),
+                sent: probes,
+                replies: Vec::new(),
+                index,
+            };
+            for probe in 0..probes {
+                if probe > 0 {
+                    tokio::time::sleep(PROBE_GAP).await;
+                }
+                if let Some(reply) = proto.request_probe(&target, timeout).await? {
+                    burst.replies.push(reply);
+                }
+            }
+            if !structured {
+                let signal = match (burst.mean(&METRICS[0]), burst.mean(&METRICS[1])) {
+                    (Some(rssi), Some(snr)) => format!(", RSSI {rssi:.1} dBm, SNR {snr:.1} dB"),
+                    _ => String::new(),
+                };
+                println!(
+                    "[{}] Round {round}, {}: {}/{probes} replies{signal}",
+                    timefmt::clock(),
+                    labels[index],
+                    burst.replies.len()
+                );
+            }
+            bursts.push(burst);
+        }
+    }
+
+    let antennas: Vec<AntennaStats> = (0..2)
+        .map(|index| {
+            let mine: Vec<&Burst> = bursts.iter().filter(|b| b.index == index).collect();
+            let signal = METRICS
+                .iter()
+                .filter_map(|metric| {
+                    let values: Vec<f64> = mine
+                        .iter()
+                        .flat_map(|b| &b.replies)
+                        .filter_map(|p| (metric.get)(p).map(f64::from))
+                        .collect();
+                    Some((metric.name, mean_sd(&values)?))
+                })
+                .collect();
+            AntennaStats {
+                antenna: labels[index].to_string(),
+                sent: mine.iter().map(|b| b.sent).sum(),
+                received: mine.iter().map(|b| b.replies.len() as u32).sum(),
+                signal,
+            }
+        })
+        .collect();
+    let differences: Vec<Difference> = METRICS
+        .iter()
+        .filter_map(|metric| {
+            let diffs: Vec<f64> = (1..=rounds)
+                .filter_map(|round| {
+                    let mean = |index: usize| {
+                        bursts
+                            .iter()
+                            .find(|b| b.round == round && b.index == index)
+                            .and_then(|b| b.mean(metric))
+                    };
+                    Some(mean(1)? - mean(0)?)
+                })
+                .collect();
+            paired(metric.name, &diffs)
+        })
+        .collect();
+    let sent = [antennas[0].sent, antennas[1].sent];
+    let lost = [
+        sent[0] - antennas[0].received,
+        sent[1] - antennas[1].received,
+    ];
+    let loss_significant = loss_differs(lost, sent);
+
+    if structured {
+        return output::print(&AbReport {
+            to: &label,
+            bursts: &bursts,
+            antennas,
+            differences,
+            loss_significant,
+        });
+    }
+
+    let header = ["ANTENNA", "REPLIES", "LOSS"]
+        .into_iter()
+        .chain(METRICS.iter().map(|m| m.name))
+        .map(|h| h.to_uppercase())
+        .collect();
+    let mut rows: Vec<Vec<String>> = vec![header];
+    for stats in &antennas {
+        let mut row = vec![
+            stats.antenna.clone(),
+            format!("{}/{}", stats.received, stats.sent),
+            format!(
+                "{:.0}%",
+                loss_percent(stats.sent - stats.received, stats.sent)
+            ),
+        ];
+        row.extend(METRICS.iter().map(|m| {
+            stats
+                .signal
+                .get(m.name)
+                .map_or("-".to_string(), |(mean, sd)| format!("{mean:.1} ±{sd:.1}"))
+        }));
+        rows.push(row);
+    }
+    let widths: Vec<usize> = (0..rows[0].len())
+        .map(|col| {
+            rows.iter()
+                .map(|r| r[col].chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    println!();
+    for row in &rows {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, &width)| format!("{cell:<width$}"))
+            .collect();
+        println!("{}", cells.join("  ").trim_end());
+    }
+
+    println!("\n{} vs {}:", labels[1], labels[0]);
+    for diff in &differences {
+        let unit = METRICS
+            .iter()
+            .find(|m| m.name == diff.metric)
+            .map_or("", |m| if m.unit == "dBm" { "dB" } else { m.unit });
+        let change = format!("{:+.1} {unit}", diff.mean);
+        match (diff.significant(), diff.ci95) {
+            (Some(true), Some(ci)) => {
+                println!("  ✓ {:<10}  {change} (±{ci:.1}): significant", diff.metric)
+            }
+            (_, Some(ci)) => println!(
+                "  ⚠ {:<10}  {change} (±{ci:.1}): not significant",
+                diff.metric
+            ),
+            _ => println!("  - {:<10}  {change}", diff.metric),
+        }
+    }
+    let losses = format!(
+        "{:.0}% vs {:.0}%",
+        loss_percent(lost[1], sent[1]),
+        loss_percent(lost[0], sent[0])
+    );
+    match loss_significant {
+        Some(true) => println!("  ✓ {:<10}  {losses}: significant", "Loss"),
+        Some(false) => println!("  ⚠ {:<10}  {losses}: not significant", "Loss"),
+        None => println!("  - {:<10}  none lost", "Loss"),
+    }
+
+    println!();
+    if rounds < 2 {
+        println!("⚠ Run at least 2 rounds (--rounds) to tell chance from a real difference");
+    } else if differences.iter().all(|d| d.significant() != Some(true))
+        && loss_significant != Some(true)
+    {
+        println!("⚠ No significant difference: run more rounds (--rounds) to tell them apart");
+    }
+    Ok(())
+}
+
+fn loss_percent(lost: u32, sent: u32) -> f64 {
+    f64::from(lost) * 100.0 / f64::from(sent.max(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_noise_by_hour() {
+        let hourly = by_hour(&[(18, -104.0), (3, -118.0), (18, -110.0), (3, -116.0)]);
+        assert_eq!(
+            hourly,
+            [
+                HourStats {
+                    hour: 3,
+                    samples: 2,
+                    avg_dbm: -117.0,
+                    max_dbm: -116.0
+                },
+                HourStats {
+                    hour: 18,
+                    samples: 2,
+                    avg_dbm: -107.0,
+                    max_dbm: -104.0
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_abtest_significance() {
+        // B is 3 dB better in every round, give or take 0.5 dB
+        let clear = paired("SNR here", &[3.0, 2.5, 3.5, 3.0]).unwrap();
+        assert_eq!(clear.mean, 3.0);
+        assert_eq!(clear.significant(), Some(true));
+        // The same mean, but all over the place
+        let noisy = paired("SNR here", &[9.0, -4.0, 8.0, -1.0]).unwrap();
+        assert_eq!(noisy.significant(), Some(false));
+        // One round can't tell
+        assert_eq!(paired("SNR here", &[3.0]).unwrap().significant(), None);
+        assert_eq!(paired("SNR here", &[]), None);
+
+        assert_eq!(loss_differs([0, 0], [30, 30]), None);
+        assert_eq!(loss_differs([1, 2], [30, 30]), Some(false));
+        assert_eq!(loss_differs([0, 12], [30, 30]), Some(true));
+    }
+}
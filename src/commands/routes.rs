@@ -0,0 +1,231 @@
+//! `routes show`: the paths the device learned to other nodes.
+
+use super::connect_with_auth;
+use super::contacts::resolve_node;
+use crate::cli::{RoutesAction, RoutesFormat};
+use crate::error::CliError;
+use crate::nodes;
+use crate::output;
+use crate::protocol::Route;
+use crate::timefmt;
+use anyhow::{bail, Result};
+use std::collections::{BTreeSet, HashMap};
+
+/// Show the device's route cache as a table, DOT graph, or JSON/YAML
+pub async fn cmd_routes(
+    port: &str,
+    baud: u32,
+    pin: Option<&str>,
+    action: RoutesAction,
+) -> Result<()> {
+    let RoutesAction::Show { node, format, .. } = action;
+    let mut proto = connect_with_auth(port, baud, pin).await?.into_protocol();
+    let mut routes = proto.get_routes().await?;
+    let names = route_names(port, &routes);
+
+    if let Some(node) = node.as_deref() {
+        let hash = match find_route(&routes, node) {
+            Some(route) => route.node_hash,
+            None => match resolve_node(&mut proto, port, node, false).await {
+                Some(cached) => cached.node_hash,
+                None => bail!(CliError::InvalidArgs(format!("Unknown node '{node}'"))),
+            },
+        };
+        routes.retain(|r| r.node_hash == hash);
+        if routes.is_empty() && !output::is_structured() {
+            println!("No route to {node}: packets to it are flooded.");
+            return Ok(());
+        }
+    }
+    routes.sort_by_key(|r| {
+        (
+            r.hops().is_none(),
+            r.hops(),
+            r.name.as_ref().map(|n| n.to_lowercase()),
+        )
+    });
+
+    if output::is_structured() {
+        return output::print(&routes);
+    }
+    if format == RoutesFormat::Dot {
+        let info = proto.get_info().await?;
+        let own = info.name.unwrap_or_else(|| "device".into());
+        print!("{}", dot(&own, info.node_hash, &routes, &names));
+        return Ok(());
+    }
+    if routes.is_empty() {
+        println!("No routes learned yet.");
+        return Ok(());
+    }
+
+    let label = |hash: u8| {
+        names
+            .get(&hash)
+            .cloned()
+            .unwrap_or_else(|| format!("0x{hash:02x}"))
+    };
+    let rows: Vec<[String; 5]> = routes
+        .iter()
+        .map(|r| {
+            let via = match &r.path {
+                None => "flood".to_string(),
+                Some(path) if path.is_empty() => "direct".to_string(),
+                Some(path) => path
+                    .iter()
+                    .map(|&h| label(h))
+                    .collect::<Vec<_>>()
+                    .join(" -> "),
+            };
+            [
+                label(r.node_hash),
+                format!("0x{:02x}", r.node_hash),
+                r.hops().map_or("-".into(), |h| h.to_string()),
+                via,
+                r.age_secs.map_or("-".into(), timefmt::ago),
+            ]
+        })
+        .collect();
+    let name_width = rows.iter().map(|r| r[0].len()).max().unwrap_or(0).max(4);
+    let via_width = rows.iter().map(|r| r[3].len()).max().unwrap_or(0).max(3);
+
+    println!(
+        "{:<name_width$}  HASH  HOPS  {:<via_width$}  LEARNED",
+        "NODE", "VIA"
+    );
+    for [name, hash, hops, via, age] in &rows {
+        println!("{name:<name_width$}  {hash}  {hops:<4}  {via:<via_width$}  {age}");
+    }
+    println!("\n{} route(s)", rows.len());
+    Ok(())
+}
+
+/// The route to `node`, by name or hash.
+fn find_route<'a>(routes: &'a [Route], node: &str) -> Option<&'a Route> {
+    let hash = u8::from_str_radix(node.to_ascii_lowercase().trim_start_matches("0x"), 16).ok();
+    routes
+        .iter()
+        .find(|r| {
+            r.name
+                .as_deref()
+                .is_some_and(|n| n.eq_ignore_ascii_case(node))
+        })
+        .or_else(|| routes.iter().find(|r| Some(r.node_hash) == hash))
+}
+
+/// Names for the hashes in route paths: from the routes themselves, then
+/// from the node cache of the device on `port`.
+fn route_names(port: &str, routes: &[Route]) -> HashMap<u8, String> {
+    let mut names = HashMap::new();
+    match nodes::device_on_port(port)
+        .and_then(|key| key.map_or(Ok(Vec::new()), |k| nodes::nodes(&k)))
+    {
+        Ok(cached) => names.extend(
+            cached
+                .into_iter()
+                .filter_map(|n| Some((n.node_hash, n.name?))),
+        ),
+        Err(e) => tracing::debug!("Skipping node cache: {e:#}"),
+    }
+    names.extend(
+        routes
+            .iter()
+            .filter_map(|r| Some((r.node_hash, r.name.clone()?))),
+    );
+    names
+}
+
+/// Routes as a Graphviz digraph, one cluster for the device so graphs of
+/// several devices can be merged. Flooded routes are dashed.
+fn dot(own_name: &str, own_hash: u8, routes: &[Route], names: &HashMap<u8, String>) -> String {
+    let id = |hash: u8| format!("\"0x{hash:02x}\"");
+    let escape = |s: &str| s.replace('\\', "\\\\").replace('"', "\\\"");
+
+    let mut nodes = BTreeSet::new();
+    let mut edges = BTreeSet::new();
+    let mut flooded = BTreeSet::new();
+    for route in routes {
+        nodes.insert(route.node_hash);
+        match &route.path {
+            Some(path) => {
+                let mut from = own_hash;
+                for &hop in path.iter().chain([&route.node_hash]) {
+                    nodes.insert(hop);
+                    edges.insert((from, hop));
+                    from = hop;
+                }
+            }
+            None => {
+                flooded.insert(route.node_hash);
+            }
+        }
+    }
+    nodes.remove(&own_hash);
+
+    let mut out = String::from("digraph routes {\n  rankdir=LR;\n");
+    out += &format!("  subgraph \"cluster_0x{own_hash:02x}\" {{\n");
+    out += &format!("    label=\"{} (0x{own_hash:02x})\";\n", escape(own_name));
+    out += &format!(
+        "    {} [label=\"{}\", shape=box];\n",
+        id(own_hash),
+        escape(own_name)
+    );
+    for hash in nodes {
+        let label = names
+            .get(&hash)
+            .map_or(format!("0x{hash:02x}"), |name| escape(name));
+        out += &format!("    {} [label=\"{label}\"];\n", id(hash));
+    }
+    for (from, to) in edges {
+        out += &format!("    {} -> {};\n", id(from), id(to));
+    }
+    for to in flooded {
+        out += &format!(
+            "    {} -> {} [style=dashed, label=\"flood\"];\n",
+            id(own_hash),
+            id(to)
+        );
+    }
+    out += "  }\n}\n";
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(node_hash: u8, name: &str, path: Option<Vec<u8>>) -> Route {
+        Route {
+            node_hash,
+            name: Some(name.into()),
+            path,
+            age_secs: Some(30),
+        }
+    }
+
+    #[test]
+    fn test_routes_dot() {
+        let routes = [
+            route(0xb2, "Bob", Some(vec![])),
+            route(0xc3, "Carol", Some(vec![0xb2])),
+            route(0xd4, "Dave", None),
+        ];
+        assert_eq!(routes[1].hops(), Some(2));
+        assert_eq!(routes[2].hops(), None);
+        assert_eq!(find_route(&routes, "carol").unwrap().node_hash, 0xc3);
+        assert_eq!(find_route(&routes, "0xd4").unwrap().node_hash, 0xd4);
+
+        let names = HashMap::from([(0xb2, "Bob".to_string()), (0xc3, "Carol".to_string())]);
+        let graph = dot("Base \"1\"", 0xa1, &routes, &names);
+        assert!(
+            graph.contains("label=\"Base \\\"1\\\" (0xa1)\";"),
+            "{graph}"
+        );
+        assert!(graph.contains("\"0xd4\" [label=\"0xd4\"];"));
+        // Bob is a route of its own and Carol's repeater: one edge to it
+        assert_eq!(graph.matches("\"0xa1\" -> \"0xb2\";").count(), 1);
+        assert!(graph.contains("\"0xb2\" -> \"0xc3\";"));
+        assert!(graph.contains("\"0xa1\" -> \"0xd4\" [style=dashed"));
+        assert!(graph.ends_with("  }\n}\n"));
+    }
+}
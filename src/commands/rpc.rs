@@ -0,0 +1,461 @@
+//! JSON-RPC 2.0 over stdin/stdout
+//!
+//! One request per line on stdin, one response per line on stdout. After
+//! `subscribe`, mesh events arrive as `event` notifications:
+//!
+//! ```text
+//! → {"jsonrpc":"2.0","id":1,"method":"get_info"}
+//! ← {"jsonrpc":"2.0","id":1,"result":{"name":"base","node_hash":66,...}}
+//! → {"jsonrpc":"2.0","id":2,"method":"subscribe"}
+//! ← {"jsonrpc":"2.0","method":"event","params":{"type":"message","from":"alice",...}}
+//! ```
+//!
+//! Device failures are reported with code -32000 and the error `kind`/`code`
+//! from the CLI's exit code table in `data`.
+
+use super::connect_with_auth;
+use crate::audit;
+use crate::error::{CliError, ErrorReport};
+use crate::protocol::{Protocol, Response};
+use crate::text;
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::mpsc;
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const DEVICE_ERROR: i64 = -32000;
+
+/// Methods understood by `cmd_rpc`, listed by `list_methods`.
+const METHODS: &[&str] = &[
+    "authenticate",
+    "command",
+    "get_config",
+    "get_info",
+    "get_neighbors",
+    "get_telemetry",
+    "list_methods",
+    "reboot",
+    "send_advert",
+    "send_message",
+    "send_packet",
+    "set_bandwidth",
+    "set_frequency",
+    "set_name",
+    "set_power",
+    "set_preset",
+    "set_spreading_factor",
+    "subscribe",
+    "trace",
+    "unsubscribe",
+];
+
+/// JSON-RPC error object.
+struct RpcError {
+    code: i64,
+    message: String,
+    data: Option<Value>,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        let mut err = json!({ "code": self.code, "message": self.message });
+        if let Some(data) = &self.data {
+            err["data"] = data.clone();
+        }
+        err
+    }
+}
+
+impl From<anyhow::Error> for RpcError {
+    fn from(e: anyhow::Error) -> Self {
+        let report = ErrorReport::new(&e);
+        Self {
+            code: DEVICE_ERROR,
+            message: format!("{e:#}"),
+            data: Some(json!({ "kind": report.kind, "code": report.code })),
+        }
+    }
+}
+
+type RpcResult = std::result::Result<Value, RpcError>;
+
+fn params<T: DeserializeOwned>(params: Value) -> std::result::Result<T, RpcError> {
+    let params = if params.is_null() { json!({}) } else { params };
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+}
+
+#[derive(Deserialize)]
+struct NameParams {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct PinParams {
+    pin: String,
+}
+
+#[derive(Deserialize)]
+struct CommandParams {
+    command: String,
+}
+
+#[derive(Deserialize)]
+struct FrequencyParams {
+    freq_mhz: f32,
+}
+
+#[derive(Deserialize)]
+struct PowerParams {
+    dbm: i8,
+}
+
+#[derive(Deserialize)]
+struct PresetParams {
+    preset: String,
+}
+
+#[derive(Deserialize)]
+struct BandwidthParams {
+    bandwidth_khz: f32,
+}
+
+#[derive(Deserialize)]
+struct SpreadingFactorParams {
+    sf: u8,
+}
+
+#[derive(Deserialize)]
+struct TargetParams {
+    target: String,
+}
+
+#[derive(Deserialize)]
+struct AdvertParams {
+    #[serde(default)]
+    flood: bool,
+}
+
+#[derive(Deserialize)]
+struct PacketParams {
+    /// Hex-encoded packet bytes
+    hex: String,
+}
+
+#[derive(Deserialize)]
+struct MessageParams {
+    text: String,
+    to: Option<String>,
+    channel: Option<String>,
+}
+
+/// Map a plain OK/ERR command response to a result.
+fn expect_ok(response: Response, cmd: &str) -> RpcResult {
+    match response {
+        Response::Ok(msg) => Ok(json!({ "message": msg })),
+        Response::Json(json) => Ok(json),
+        Response::Error(e) => Err(anyhow::Error::new(CliError::Device(e))
+            .context(format!("{cmd} failed"))
+            .into()),
+    }
+}
+
+/// Methods that change the device, besides `command`.
+const AUDITED: &[&str] = &[
+    "set_name",
+    "set_frequency",
+    "set_power",
+    "set_preset",
+    "set_bandwidth",
+    "set_spreading_factor",
+    "send_packet",
+    "reboot",
+];
+
+/// Audit log entry for a call that changes the device.
+fn audited_call(method: &str, p: &Value) -> Option<String> {
+    if method == "command" {
+        let cmd = p.get("command")?.as_str()?;
+        return audit::describe_command(cmd).map(|op| format!("rpc command {op}"));
+    }
+    if !AUDITED.contains(&method) {
+        return None;
+    }
+    let args: Vec<String> = p
+        .as_object()
+        .into_iter()
+        .flatten()
+        .map(|(_, v)| v.as_str().map_or_else(|| v.to_string(), String::from))
+        .collect();
+    Some(
+        format!("rpc {method} {}", args.join(" "))
+            .trim_end()
+            .to_string(),
+    )
+}
+
+struct RpcSession {
+    proto: Protocol,
+    port: String,
+    subscribed: bool,
+}
+
+impl RpcSession {
+    /// Run a call, recording it in the audit log if it changes the device.
+    async fn call(&mut self, method: &str, p: Value) -> RpcResult {
+        let Some(operation) = audited_call(method, &p) else {
+            return self.dispatch(method, p).await;
+        };
+        let operation = audit::Operation::named(operation, Some(&self.port))?;
+        let result = self.dispatch(method, p).await;
+        let outcome = match &result {
+            // `command` answers device errors as a result
+            Ok(value) if value.get("ok") == Some(&json!(false)) => {
+                Err(value["error"].as_str().unwrap_or_default().to_string())
+            }
+            Ok(_) => Ok(()),
+            Err(e) => Err(e.message.clone()),
+        };
+        operation.finish(&outcome)?;
+        result
+    }
+
+    async fn dispatch(&mut self, method: &str, p: Value) -> RpcResult {
+        let result = match method {
+            "list_methods" => json!(METHODS),
+            "authenticate" => {
+                let PinParams { pin } = params(p)?;
+                match self.proto.command(&format!("AUTH {pin}")).await? {
+                    Response::Ok(_) => json!(true),
+                    Response::Error(e) => {
+                        return Err(anyhow::Error::new(CliError::AuthFailed(e)).into())
+                    }
+                    Response::Json(_) => {
+                        return Err(RpcError::new(DEVICE_ERROR, "Unexpected response to AUTH"))
+                    }
+                }
+            }
+            "get_info" => serde_json::to_value(self.proto.get_info().await?).unwrap_or_default(),
+            "get_config" => {
+                serde_json::to_value(self.proto.get_config().await?).unwrap_or_default()
+            }
+            "get_neighbors" => {
+                serde_json::to_value(self.proto.get_neighbors().await?).unwrap_or_default()
+            }
+            "get_telemetry" => {
+                serde_json::to_value(self.proto.get_telemetry().await?).unwrap_or_default()
+            }
+            "set_name" => {
+                let NameParams { name } = params(p)?;
+                self.proto.set_name(&name).await?;
+                json!(true)
+            }
+            "set_frequency" => {
+                let FrequencyParams { freq_mhz } = params(p)?;
+                self.proto.set_frequency(freq_mhz).await?;
+                json!(true)
+            }
+            "set_power" => {
+                let PowerParams { dbm } = params(p)?;
+                self.proto.set_power(dbm).await?;
+                json!(true)
+            }
+            "set_preset" => {
+                let PresetParams { preset } = params(p)?;
+                let cmd = format!("SET PRESET {}", preset.to_uppercase());
+                expect_ok(self.proto.command(&cmd).await?, "SET PRESET")?
+            }
+            "set_bandwidth" => {
+                let BandwidthParams { bandwidth_khz } = params(p)?;
+                let cmd = format!("SET BW {bandwidth_khz}");
+                expect_ok(self.proto.command(&cmd).await?, "SET BW")?
+            }
+            "set_spreading_factor" => {
+                let SpreadingFactorParams { sf } = params(p)?;
+                let cmd = format!("SET SF {sf}");
+                expect_ok(self.proto.command(&cmd).await?, "SET SF")?
+            }
+            "send_message" => {
+                let MessageParams { text, to, channel } = params(p)?;
+                let cmd = match (&to, &channel) {
+                    (Some(dest), _) => format!("SEND {dest}"),
+                    (None, Some(ch)) => format!("CHANNEL SEND {ch}"),
+                    (None, None) => "SEND".to_string(),
+                };
+                let text = text::clean(&text);
+                expect_ok(self.proto.send_text(&cmd, &text).await?, "SEND")?
+            }
+            "send_advert" => {
+                let AdvertParams { flood } = params(p)?;
+                let cmd = if flood {
+                    "ADVERT FLOOD"
+                } else {
+                    "ADVERT LOCAL"
+                };
+                expect_ok(self.proto.command(cmd).await?, "ADVERT")?
+            }
+            "send_packet" => {
+                let PacketParams { hex } = params(p)?;
+                let packet = hex::decode(hex.trim())
+                    .map_err(|e| RpcError::new(INVALID_PARAMS, format!("invalid hex: {e}")))?;
+                self.proto.send_packet(&packet).await?;
+                json!(true)
+            }
+            "trace" => {
+                let TargetParams { target } = params(p)?;
+                serde_json::to_value(self.proto.trace(&target).await?).unwrap_or_default()
+            }
+            "reboot" => {
+                self.proto.reboot().await?;
+                self.subscribed = false;
+                json!(true)
+            }
+            "command" => {
+                let CommandParams { command } = params(p)?;
+                match self.proto.command(&command).await? {
+                    Response::Ok(msg) => json!({ "ok": true, "message": msg }),
+                    Response::Error(e) => json!({ "ok": false, "error": e }),
+                    Response::Json(json) => json,
+                }
+            }
+            "subscribe" => {
+                self.proto.enter_monitor_mode().await?;
+                self.subscribed = true;
+                return Ok(json!(true));
+            }
+            "unsubscribe" => {
+                self.subscribed = false;
+                return Ok(json!(true));
+            }
+            _ => {
+                return Err(RpcError::new(
+                    METHOD_NOT_FOUND,
+                    format!("Method not found: {method}"),
+                ))
+            }
+        };
+
+        // Device commands leave monitor mode on some firmware
+        if self.subscribed {
+            self.proto.enter_monitor_mode().await?;
+        }
+        Ok(result)
+    }
+
+    /// Handle one request object; returns None for notifications.
+    async fn handle(&mut self, request: Value) -> Option<Value> {
+        let id = request.get("id").cloned();
+        let is_notification = id.is_none();
+        let id = id.unwrap_or(Value::Null);
+
+        let method = request.get("method").and_then(Value::as_str);
+        let result = match (request.get("jsonrpc").and_then(Value::as_str), method) {
+            (Some("2.0"), Some(method)) => {
+                let p = request.get("params").cloned().unwrap_or(Value::Null);
+                self.call(method, p).await
+            }
+            _ => Err(RpcError::new(INVALID_REQUEST, "Invalid request")),
+        };
+
+        if is_notification {
+            return None;
+        }
+        Some(match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(e) => json!({ "jsonrpc": "2.0", "id": id, "error": e.to_json() }),
+        })
+    }
+
+    /// Handle one input line (a request or a batch).
+    async fn handle_line(&mut self, line: &str) -> Option<Value> {
+        let parsed: Value = match serde_json::from_str(line) {
+            Ok(v) => v,
+            Err(e) => {
+                let err = RpcError::new(PARSE_ERROR, format!("Parse error: {e}"));
+                return Some(json!({ "jsonrpc": "2.0", "id": null, "error": err.to_json() }));
+            }
+        };
+
+        match parsed {
+            Value::Array(batch) if batch.is_empty() => {
+                let err = RpcError::new(INVALID_REQUEST, "Empty batch");
+                Some(json!({ "jsonrpc": "2.0", "id": null, "error": err.to_json() }))
+            }
+            Value::Array(batch) => {
+                let mut responses = Vec::new();
+                for request in batch {
+                    if let Some(response) = self.handle(request).await {
+                        responses.push(response);
+                    }
+                }
+                (!responses.is_empty()).then_some(Value::Array(responses))
+            }
+            request => self.handle(request).await,
+        }
+    }
+}
+
+/// Serve the device API as JSON-RPC 2.0 on stdin/stdout
+pub async fn cmd_rpc(port: &str, baud: u32, pin: Option<&str>) -> Result<()> {
+    super::restore_blocking_stdin();
+
+    let dev = connect_with_auth(port, baud, pin).await?;
+    let mut session = RpcSession {
+        proto: dev.into_protocol(),
+        port: port.to_string(),
+        subscribed: false,
+    };
+
+    // Read stdin on its own task so events can be delivered while idle
+    let (tx, mut rx) = mpsc::channel::<String>(64);
+    let reader = tokio::spawn(async move {
+        let mut lines = BufReader::new(tokio::io::stdin()).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if tx.send(line).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    loop {
+        let line = if session.subscribed {
+            if let Some(event) = session.proto.read_event().await? {
+                let note = json!({ "jsonrpc": "2.0", "method": "event", "params": event });
+                println!("{note}");
+            }
+            match rx.try_recv() {
+                Ok(line) => line,
+                Err(mpsc::error::TryRecvError::Empty) => continue,
+                Err(mpsc::error::TryRecvError::Disconnected) => break,
+            }
+        } else {
+            match rx.recv().await {
+                Some(line) => line,
+                None => break,
+            }
+        };
+
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = session.handle_line(&line).await {
+            println!("{response}");
+        }
+    }
+
+    reader.abort();
+    Ok(())
+}
@@ -0,0 +1,696 @@
+//! Network services exposing the attached device
+//!
+//! `serve grpc` implements the `meshgrid.v1.MeshGrid` service from
+//! `proto/meshgrid.proto`. One task owns the serial connection; calls are
+//! queued to it and mesh events are fanned out to every `StreamEvents` client.
+//!
+//! Clients are authorized by bearer token: `--token` grants everything, and
+//! `[[serve.tokens]]` in the config file grant a scope (read-only, send or
+//! admin) that each method checks.
+
+use super::connect_with_auth;
+use crate::audit;
+use crate::cli::ServeAction;
+use crate::error::{CliError, ErrorReport};
+use crate::grpc::{self, code, Call, Encoder, Message, Status};
+use crate::protocol::{DeviceInfo, MonitorEvent, Protocol, Response, Telemetry};
+use crate::settings::{Scope, ServeToken, Settings};
+use crate::text;
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use subtle::ConstantTimeEq;
+use tokio::sync::{broadcast, mpsc, oneshot};
+
+const SERVICE: &str = "/meshgrid.v1.MeshGrid/";
+
+/// Run a network service
+pub async fn cmd_serve(
+    port: &str,
+    baud: u32,
+    pin: Option<&str>,
+    action: ServeAction,
+) -> Result<()> {
+    match action {
+        ServeAction::Grpc { listen, token } => serve_grpc(port, baud, pin, &listen, token).await,
+    }
+}
+
+/// Work queued to the device task.
+enum DeviceRequest {
+    GetInfo(oneshot::Sender<Result<DeviceInfo>>),
+    GetTelemetry(oneshot::Sender<Result<Telemetry>>),
+    Command(String, oneshot::Sender<Result<Response>>),
+    /// A message, split into parts if it's too long
+    SendText(String, String, oneshot::Sender<Result<Response>>),
+}
+
+/// State shared by all calls.
+struct GrpcService {
+    /// Accepted tokens; empty means no authentication
+    tokens: Vec<ServeToken>,
+    /// Device port, for the audit log
+    port: String,
+    device: mpsc::Sender<DeviceRequest>,
+    events: broadcast::Sender<MonitorEvent>,
+}
+
+/// `:50051` means all interfaces.
+pub(super) fn listen_addr(listen: &str) -> String {
+    match listen.strip_prefix(':') {
+        Some(port) => format!("0.0.0.0:{port}"),
+        None => listen.to_string(),
+    }
+}
+
+async fn serve_grpc(
+    port: &str,
+    baud: u32,
+    pin: Option<&str>,
+    listen: &str,
+    token: Option<String>,
+) -> Result<()> {
+    let addr = listen_addr(listen);
+    let listener = tokio::net::TcpListener::bind(&addr)
+        .await
+        .with_context(|| format!("Failed to listen on {addr}"))?;
+    let local = listener.local_addr()?;
+    let mut tokens = Settings::load()?.serve.tokens;
+    tokens.extend(token.map(|token| ServeToken {
+        name: Some("--token".into()),
+        token,
+        scope: Scope::Admin,
+    }));
+    if tokens.is_empty() && !local.ip().is_loopback() {
+        tracing::warn!("gRPC server on {local} accepts calls without a token (set --token)");
+    }
+
+    let dev = connect_with_auth(port, baud, pin).await?;
+    let (device_tx, device_rx) = mpsc::channel(32);
+    let (events, _) = broadcast::channel(256);
+    let service = Arc::new(GrpcService {
+        tokens,
+        port: port.to_string(),
+        device: device_tx,
+        events: events.clone(),
+    });
+
+    println!("Serving meshgrid.v1.MeshGrid on {local} (Ctrl+C to stop)...");
+    let server = tokio::spawn(grpc::serve(listener, move |call| {
+        let service = Arc::clone(&service);
+        async move { service.handle(call).await }
+    }));
+
+    let result = run_device(dev.into_protocol(), device_rx, events).await;
+    server.abort();
+    result
+}
+
+/// Own the serial connection: publish mesh events and run queued calls.
+async fn run_device(
+    mut proto: Protocol,
+    mut requests: mpsc::Receiver<DeviceRequest>,
+    events: broadcast::Sender<MonitorEvent>,
+) -> Result<()> {
+    proto.enter_monitor_mode().await?;
+    loop {
+        if let Some(event) = proto.read_event().await? {
+            // No subscribers is fine
+            let _ = events.send(event);
+        }
+
+        while let Ok(request) = requests.try_recv() {
+            match request {
+                DeviceRequest::GetInfo(reply) => {
+                    let _ = reply.send(proto.get_info().await);
+                }
+                DeviceRequest::GetTelemetry(reply) => {
+                    let _ = reply.send(proto.get_telemetry().await);
+                }
+                DeviceRequest::Command(cmd, reply) => {
+                    let _ = reply.send(proto.command(&cmd).await);
+                }
+                DeviceRequest::SendText(cmd, text, reply) => {
+                    let _ = reply.send(proto.send_text(&cmd, &text).await);
+                }
+            }
+            // Commands leave monitor mode on some firmware
+            proto.enter_monitor_mode().await?;
+        }
+    }
+}
+
+/// Map a device failure to a gRPC status.
+fn device_status(err: &anyhow::Error) -> Status {
+    let status = match ErrorReport::new(err).kind {
+        "timeout" => code::DEADLINE_EXCEEDED,
+        "auth_failed" => code::UNAUTHENTICATED,
+        "device_error" => code::FAILED_PRECONDITION,
+        _ => code::INTERNAL,
+    };
+    Status::new(status, format!("{err:#}"))
+}
+
+fn invalid(e: impl std::fmt::Display) -> Status {
+    Status::new(code::INVALID_ARGUMENT, e.to_string())
+}
+
+impl GrpcService {
+    async fn handle(&self, mut call: Call) {
+        let method = call
+            .path
+            .strip_prefix(SERVICE)
+            .unwrap_or_default()
+            .to_string();
+        if let Err(status) = self.authorize(call.bearer_token(), &method) {
+            let _ = call.fail(&status);
+            return;
+        }
+
+        let request = match call.read_request().await {
+            Ok(request) => request,
+            Err(status) => {
+                let _ = call.fail(&status);
+                return;
+            }
+        };
+        tracing::debug!("gRPC call {}", call.path);
+
+        let result = match method.as_str() {
+            "GetInfo" => self.get_info().await,
+            "GetTelemetry" => self.get_telemetry().await,
+            "SendMessage" => self.send_message(&request).await,
+            "StreamEvents" => return self.stream_events(call).await,
+            "RunCommand" => self.run_command(&request).await,
+            _ => Err(Status::new(
+                code::UNIMPLEMENTED,
+                format!("Unknown method {}", call.path),
+            )),
+        };
+        let sent = match result {
+            Ok(msg) => call.reply(msg),
+            Err(status) => call.fail(&status),
+        };
+        if let Err(e) = sent {
+            tracing::debug!("Failed to answer {method}: {e}");
+        }
+    }
+
+    /// Check the caller's token allows `method`.
+    fn authorize(&self, token: Option<&str>, method: &str) -> std::result::Result<(), Status> {
+        if self.tokens.is_empty() {
+            return Ok(());
+        }
+        let client =
+            token.and_then(|token| self.tokens.iter().find(|t| token_matches(&t.token, token)));
+        let Some(client) = client else {
+            return Err(Status::new(code::UNAUTHENTICATED, "invalid token"));
+        };
+        let required = required_scope(method);
+        if client.scope < required {
+            tracing::warn!(
+                "Denied {method} to token {} ({} scope)",
+                client.name.as_deref().unwrap_or("?"),
+                client.scope
+            );
+            return Err(Status::new(
+                code::PERMISSION_DENIED,
+                format!("{method} requires the {required} scope"),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Queue a request for the device task and wait for the answer.
+    async fn device<T>(
+        &self,
+        request: impl FnOnce(oneshot::Sender<Result<T>>) -> DeviceRequest,
+    ) -> std::result::Result<T, Status> {
+        let unavailable = || Status::new(code::UNAVAILABLE, "device connection closed");
+        let (tx, rx) = oneshot::channel();
+        self.device
+            .send(request(tx))
+            .await
+            .map_err(|_| unavailable())?;
+        rx.await
+            .map_err(|_| unavailable())?
+            .map_err(|e| device_status(&e))
+    }
+
+    async fn get_info(&self) -> std::result::Result<Vec<u8>, Status> {
+        let info = self.device(DeviceRequest::GetInfo).await?;
+        Ok(encode_info(&info))
+    }
+
+    async fn get_telemetry(&self) -> std::result::Result<Vec<u8>, Status> {
+        let telemetry = self.device(DeviceRequest::GetTelemetry).await?;
+        Ok(encode_telemetry(&telemetry))
+    }
+
+    async fn send_message(&self, request: &[u8]) -> std::result::Result<Vec<u8>, Status> {
+        let request = Message::decode(request).map_err(invalid)?;
+        let text = request.string(1).map_err(invalid)?;
+        let to = request.string(2).map_err(invalid)?;
+        let channel = request.string(3).map_err(invalid)?;
+        let Some(text) = text else {
+            return Err(invalid("text is required"));
+        };
+        let text = text::clean(&text).into_owned();
+
+        let cmd = match (to, channel) {
+            (Some(dest), _) => format!("SEND {dest}"),
+            (None, Some(ch)) => format!("CHANNEL SEND {ch}"),
+            (None, None) => "SEND".to_string(),
+        };
+        match self
+            .device(|tx| DeviceRequest::SendText(cmd, text, tx))
+            .await?
+        {
+            Response::Ok(msg) => Ok(Encoder::new()
+                .string(1, msg.as_deref().unwrap_or_default())
+                .finish()),
+            Response::Error(e) => Err(device_status(&CliError::Device(e).into())),
+            Response::Json(json) => Ok(Encoder::new().string(1, &json.to_string()).finish()),
+        }
+    }
+
+    async fn run_command(&self, request: &[u8]) -> std::result::Result<Vec<u8>, Status> {
+        let request = Message::decode(request).map_err(invalid)?;
+        let Some(cmd) = request.string(1).map_err(invalid)? else {
+            return Err(invalid("command is required"));
+        };
+        let audit_failed =
+            |e: anyhow::Error| Status::new(code::FAILED_PRECONDITION, format!("{e:#}"));
+        let audited = audit::describe_command(&cmd)
+            .map(|op| audit::Operation::named(format!("serve RunCommand {op}"), Some(&self.port)))
+            .transpose()
+            .map_err(audit_failed)?;
+        let result = match self.device(|tx| DeviceRequest::Command(cmd, tx)).await {
+            Ok(Response::Ok(msg)) => Ok(msg.unwrap_or_default()),
+            Ok(Response::Error(e)) => Err(device_status(&CliError::Device(e).into())),
+            Ok(Response::Json(json)) => Ok(json.to_string()),
+            Err(status) => Err(status),
+        };
+        if let Some(operation) = audited {
+            operation
+                .finish(&result.as_ref().map_err(|status| &status.message))
+                .map_err(audit_failed)?;
+        }
+        Ok(Encoder::new().string(1, &result?).finish())
+    }
+
+    async fn stream_events(&self, call: Call) {
+        let mut events = self.events.subscribe();
+        let Ok(mut stream) = call.start() else {
+            return;
+        };
+
+        loop {
+            let event = tokio::select! {
+                event = events.recv() => event,
+                () = stream.cancelled() => return,
+            };
+            match event {
+                Ok(event) => {
+                    if stream.send(&encode_event(&event)).is_err() {
+                        return;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(n)) => {
+                    tracing::warn!("Event stream client lagging, dropped {n} events");
+                }
+                Err(broadcast::error::RecvError::Closed) => {
+                    let status = Status::new(code::UNAVAILABLE, "device connection closed");
+                    let _ = stream.finish(&status);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Compare tokens in constant time; hashing first gives both sides the same
+/// length so the comparison doesn't leak it either.
+pub fn token_matches(expected: &str, given: &str) -> bool {
+    Sha256::digest(expected.as_bytes())
+        .ct_eq(&Sha256::digest(given.as_bytes()))
+        .into()
+}
+
+/// Least scope allowed to call `method`; unknown methods need admin.
+fn required_scope(method: &str) -> Scope {
+    match method {
+        "GetInfo" | "GetTelemetry" | "StreamEvents" => Scope::ReadOnly,
+        "SendMessage" => Scope::Send,
+        _ => Scope::Admin,
+    }
+}
+
+fn encode_info(info: &DeviceInfo) -> Vec<u8> {
+    Encoder::new()
+        .string(1, info.name.as_deref().unwrap_or_default())
+        .bytes(2, &info.public_key)
+        .uint32(3, u32::from(info.node_hash))
+        .string(4, info.firmware_version.as_deref().unwrap_or_default())
+        .string(5, info.mode.as_deref().unwrap_or_default())
+        .float(6, info.freq_mhz)
+        .sint32(7, i32::from(info.tx_power_dbm))
+        .finish()
+}
+
+fn encode_telemetry(telemetry: &Telemetry) -> Vec<u8> {
+    let mut msg = Encoder::new();
+    if let Some(d) = &telemetry.device {
+        let device = Encoder::new()
+            .uint32(1, u32::from(d.battery_percent))
+            .float(2, d.voltage())
+            .bool(3, d.charging)
+            .bool(4, d.usb_power)
+            .uint32(5, d.uptime_secs)
+            .uint32(6, d.free_heap)
+            .float(7, d.cpu_temp_celsius());
+        msg = msg.message(1, device);
+    }
+    if let Some(e) = &telemetry.environment {
+        let environment = Encoder::new()
+            .float(1, e.temperature_celsius())
+            .float(2, e.humidity_percent())
+            .float(3, e.pressure_hpa())
+            .uint32(4, u32::from(e.air_quality));
+        msg = msg.message(2, environment);
+    }
+    if let Some(l) = &telemetry.location {
+        let location = Encoder::new()
+            .bool(1, l.has_fix())
+            .double(2, l.latitude())
+            .double(3, l.longitude())
+            .float(4, l.altitude_meters())
+            .float(5, l.speed_m_s())
+            .float(6, l.heading_degrees())
+            .uint32(7, u32::from(l.satellites));
+        msg = msg.message(3, location);
+    }
+    msg.finish()
+}
+
+fn encode_event(event: &MonitorEvent) -> Vec<u8> {
+    let (field, body) = match event {
+        MonitorEvent::Message {
+            from,
+            to,
+            channel,
+            rssi,
+            text,
+        } => (
+            1,
+            Encoder::new()
+                .string(1, from)
+                .string(2, to.as_deref().unwrap_or_default())
+                .sint32(3, i32::from(*rssi))
+                .string(4, text)
+                .string(5, channel.as_deref().unwrap_or_default()),
+        ),
+        MonitorEvent::Advertisement {
+            node_hash,
+            rssi,
+            name,
+        } => (
+            2,
+            Encoder::new()
+                .uint32(1, u32::from(*node_hash))
+                .sint32(2, i32::from(*rssi))
+                .string(3, name.as_deref().unwrap_or_default()),
+        ),
+        MonitorEvent::Ack { from } => (3, Encoder::new().string(1, from)),
+        MonitorEvent::Error { message } => (4, Encoder::new().string(1, message)),
+        MonitorEvent::Waypoint {
+            from,
+            rssi,
+            name,
+            lat,
+            lon,
+            icon,
+            expires,
+        } => (
+            5,
+            Encoder::new()
+                .string(1, from)
+                .sint32(2, i32::from(*rssi))
+                .string(3, name)
+                .double(4, *lat)
+                .double(5, *lon)
+                .string(6, icon.as_deref().unwrap_or_default())
+                .int64(7, expires.unwrap_or_default()),
+        ),
+    };
+    Encoder::new().message(field, body).finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{DeviceTelemetry, EnvironmentTelemetry, LocationTelemetry};
+
+    #[test]
+    fn test_authorize() {
+        let (device, _) = mpsc::channel(1);
+        let service = GrpcService {
+            tokens: vec![ServeToken {
+                name: None,
+                token: "dash".into(),
+                scope: Scope::ReadOnly,
+            }],
+            port: "/dev/ttyUSB0".into(),
+            device,
+            events: broadcast::channel(1).0,
+        };
+        assert!(service.authorize(Some("dash"), "GetTelemetry").is_ok());
+        assert_eq!(
+            service
+                .authorize(Some("dash"), "SendMessage")
+                .unwrap_err()
+                .code,
+            code::PERMISSION_DENIED
+        );
+        assert_eq!(
+            service.authorize(Some("nope"), "GetInfo").unwrap_err().code,
+            code::UNAUTHENTICATED
+        );
+        assert_eq!(
+            service.authorize(None, "GetInfo").unwrap_err().code,
+            code::UNAUTHENTICATED
+        );
+        assert_eq!(
+            service.authorize(Some("das"), "GetInfo").unwrap_err().code,
+            code::UNAUTHENTICATED
+        );
+    }
+
+    #[test]
+    fn test_required_scope() {
+        let (device, _) = mpsc::channel(1);
+        let token = |token: &str, scope| ServeToken {
+            name: None,
+            token: token.into(),
+            scope,
+        };
+        let service = GrpcService {
+            tokens: vec![
+                token("read", Scope::ReadOnly),
+                token("send", Scope::Send),
+                token("admin", Scope::Admin),
+            ],
+            port: "/dev/ttyUSB0".into(),
+            device,
+            events: broadcast::channel(1).0,
+        };
+        let denied = |token, method| {
+            service
+                .authorize(Some(token), method)
+                .is_err_and(|e| e.code == code::PERMISSION_DENIED)
+        };
+
+        for method in ["GetInfo", "GetTelemetry", "StreamEvents"] {
+            assert_eq!(required_scope(method), Scope::ReadOnly);
+            assert!(service.authorize(Some("read"), method).is_ok());
+        }
+
+        assert_eq!(required_scope("SendMessage"), Scope::Send);
+        assert!(denied("read", "SendMessage"));
+        assert!(service.authorize(Some("send"), "SendMessage").is_ok());
+
+        // Unknown methods need admin too
+        for method in ["RunCommand", "NotAMethod"] {
+            assert_eq!(required_scope(method), Scope::Admin);
+            assert!(denied("read", method));
+            assert!(denied("send", method));
+            assert!(service.authorize(Some("admin"), method).is_ok());
+        }
+        assert!(Scope::Admin > Scope::Send);
+    }
+
+    /// `message` from `proto/meshgrid.proto`.
+    fn schema(message: &str) -> prost_reflect::MessageDescriptor {
+        let files = protox::compile(
+            ["meshgrid.proto"],
+            [concat!(env!("CARGO_MANIFEST_DIR"), "/proto")],
+        )
+        .unwrap();
+        prost_reflect::DescriptorPool::from_file_descriptor_set(files)
+            .unwrap()
+            .get_message_by_name(&format!("meshgrid.v1.{message}"))
+            .unwrap()
+    }
+
+    /// Decode `bytes` as `message`, as JSON with the schema's field names.
+    fn decode_with_schema(message: &str, bytes: &[u8]) -> serde_json::Value {
+        let decoded = prost_reflect::DynamicMessage::decode(schema(message), bytes).unwrap();
+        let options = prost_reflect::SerializeOptions::new()
+            .use_proto_field_name(true)
+            .stringify_64_bit_integers(false);
+        decoded
+            .serialize_with_options(serde_json::value::Serializer, &options)
+            .unwrap()
+    }
+
+    /// Encode JSON as `message` following the schema.
+    fn encode_with_schema(message: &str, json: serde_json::Value) -> Vec<u8> {
+        use prost_reflect::prost::Message as _;
+
+        prost_reflect::DynamicMessage::deserialize(schema(message), json)
+            .unwrap()
+            .encode_to_vec()
+    }
+
+    #[test]
+    fn test_messages_match_proto() {
+        use serde_json::json;
+
+        let info = DeviceInfo {
+            name: Some("hilltop".into()),
+            public_key: [1; 32],
+            node_hash: 0xa5,
+            firmware_version: Some("0.0.5".into()),
+            mode: Some("repeater".into()),
+            freq_mhz: 869.5,
+            tx_power_dbm: -2,
+        };
+        let decoded = decode_with_schema("DeviceInfo", &encode_info(&info));
+        assert_eq!(decoded["name"], "hilltop");
+        assert_eq!(decoded["node_hash"], 0xa5);
+        assert_eq!(decoded["firmware_version"], "0.0.5");
+        assert_eq!(decoded["mode"], "repeater");
+        assert_eq!(decoded["freq_mhz"], 869.5);
+        assert_eq!(decoded["tx_power_dbm"], -2);
+        assert_eq!(decoded.as_object().unwrap().len(), 7);
+
+        let mut location = LocationTelemetry::new()
+            .with_latitude(52.5)
+            .with_longitude(-1.25)
+            .with_altitude(12.5)
+            .with_speed(1.5)
+            .with_heading(90.0);
+        location.satellites = 7;
+        location.fix_type = 1;
+        let telemetry = Telemetry::new()
+            .with_device(DeviceTelemetry {
+                battery_percent: 80,
+                voltage_mv: 4000,
+                charging: true,
+                usb_power: true,
+                uptime_secs: 60,
+                free_heap: 1024,
+                cpu_temp_deci_c: 255,
+            })
+            .with_environment(
+                EnvironmentTelemetry::new()
+                    .with_temperature(21.5)
+                    .with_humidity(40.0)
+                    .with_pressure_hpa(1000.0),
+            )
+            .with_location(location);
+        let decoded = decode_with_schema("Telemetry", &encode_telemetry(&telemetry));
+        assert_eq!(
+            decoded["device"],
+            json!({
+                "battery_percent": 80, "voltage": 4.0, "charging": true, "usb_power": true,
+                "uptime_secs": 60, "free_heap": 1024, "cpu_temp_celsius": 25.5,
+            })
+        );
+        assert_eq!(
+            decoded["environment"],
+            json!({ "temperature_celsius": 21.5, "humidity_percent": 40.0, "pressure_hpa": 1000.0 })
+        );
+        assert_eq!(
+            decoded["location"],
+            json!({
+                "has_fix": true, "latitude": 52.5, "longitude": -1.25, "altitude_m": 12.5,
+                "speed_m_s": 1.5, "heading_deg": 90.0, "satellites": 7,
+            })
+        );
+
+        let events = [
+            (
+                MonitorEvent::Message {
+                    from: "alice".into(),
+                    to: Some("bob".into()),
+                    channel: Some("ops".into()),
+                    rssi: -90,
+                    text: "hi".into(),
+                },
+                json!({ "message": {
+                    "from": "alice", "to": "bob", "rssi": -90, "text": "hi", "channel": "ops",
+                }}),
+            ),
+            (
+                MonitorEvent::Advertisement {
+                    node_hash: 7,
+                    rssi: -80,
+                    name: Some("rpt".into()),
+                },
+                json!({ "advert": { "node_hash": 7, "rssi": -80, "name": "rpt" } }),
+            ),
+            (
+                MonitorEvent::Ack { from: "bob".into() },
+                json!({ "ack": { "from": "bob" } }),
+            ),
+            (
+                MonitorEvent::Error {
+                    message: "oops".into(),
+                },
+                json!({ "error": { "message": "oops" } }),
+            ),
+            (
+                MonitorEvent::Waypoint {
+                    from: "carol".into(),
+                    rssi: -70,
+                    name: "camp".into(),
+                    lat: 52.5,
+                    lon: -1.25,
+                    icon: Some("tent".into()),
+                    expires: Some(1_700_000_000),
+                },
+                json!({ "waypoint": {
+                    "from": "carol", "rssi": -70, "name": "camp", "lat": 52.5, "lon": -1.25,
+                    "icon": "tent", "expires": 1_700_000_000,
+                }}),
+            ),
+        ];
+        for (event, expected) in events {
+            assert_eq!(decode_with_schema("Event", &encode_event(&event)), expected);
+        }
+
+        // Requests are read with the schema's field numbers
+        let bytes = encode_with_schema(
+            "SendMessageRequest",
+            json!({ "text": "hi", "to": "bob", "channel": "ops" }),
+        );
+        let request = Message::decode(&bytes).unwrap();
+        assert_eq!(request.string(1).unwrap().as_deref(), Some("hi"));
+        assert_eq!(request.string(2).unwrap().as_deref(), Some("bob"));
+        assert_eq!(request.string(3).unwrap().as_deref(), Some("ops"));
+        let bytes = encode_with_schema("RunCommandRequest", json!({ "command": "REBOOT" }));
+        let request = Message::decode(&bytes).unwrap();
+        assert_eq!(request.string(1).unwrap().as_deref(), Some("REBOOT"));
+    }
+}
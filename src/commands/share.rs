@@ -0,0 +1,431 @@
+//! Device sharing over TCP
+//!
+//! `share` relays the raw serial stream of the attached device to one TCP
+//! client at a time, like a ser2net raw port, so the CLI on another machine
+//! can use it as `--port tcp://host:4403`.
+//!
+//! With a `--token` (or `[[serve.tokens]]` in the config file) clients
+//! authenticate as they would to a PIN-protected device: the share answers
+//! `CHALLENGE` and `AUTH HMAC` itself. A token's scope, or `--read-only`,
+//! limits the commands a client may send; those frames are checked one by
+//! one, everything else is relayed verbatim.
+
+use super::connect_with_auth;
+use super::serve::listen_addr;
+use crate::audit;
+use crate::device::challenge_response;
+use crate::serial::{cobs_decode_in_place, cobs_encode_into, Transport};
+use crate::settings::{Scope, ServeToken, Settings};
+use crate::timefmt;
+use anyhow::{bail, Context, Result};
+use bytes::BytesMut;
+use std::net::SocketAddr;
+use std::time::Duration;
+use subtle::ConstantTimeEq;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Longest run of relayed bytes without a frame end kept for the audit scan.
+const MAX_UNSCANNED: usize = 4096;
+
+/// How long a refused client is answered before it is dropped.
+const REFUSED_LINGER: Duration = Duration::from_secs(10);
+
+/// The connected client.
+struct Session {
+    stream: TcpStream,
+    peer: SocketAddr,
+    /// What the client may do; `None` until it authenticates
+    scope: Option<Scope>,
+    nonce: Option<Vec<u8>>,
+    /// Client bytes short of a complete frame
+    pending: Vec<u8>,
+    /// Bytes relayed verbatim but not yet checked for commands to audit
+    unscanned: Vec<u8>,
+}
+
+/// What to do with bytes from the client.
+#[derive(Debug, Default, PartialEq)]
+struct Relay {
+    to_device: Vec<u8>,
+    to_client: Vec<u8>,
+    /// Commands among `to_device` that change the device, for the audit log
+    audited: Vec<String>,
+}
+
+impl Relay {
+    fn reply(&mut self, text: &str) {
+        let mut frame = BytesMut::new();
+        cobs_encode_into(text.as_bytes(), &mut frame);
+        self.to_client.extend_from_slice(&frame);
+    }
+}
+
+impl Session {
+    /// Sort client bytes into frames for the device and answers given
+    /// here. Unrestricted clients are relayed verbatim.
+    fn relay(&mut self, data: &[u8], tokens: &[ServeToken], cap: Scope) -> Result<Relay> {
+        let mut relay = Relay::default();
+        if self.scope == Some(Scope::Admin) {
+            relay.to_device = std::mem::take(&mut self.pending);
+            relay.to_device.extend_from_slice(data);
+            relay.audited = self.scan(&relay.to_device);
+            return Ok(relay);
+        }
+        self.pending.extend_from_slice(data);
+        while let Some(end) = self.pending.iter().position(|&b| b == 0) {
+            let raw: Vec<u8> = self.pending.drain(..=end).collect();
+            let mut frame = raw[..end].to_vec();
+            let Some(len) = cobs_decode_in_place(&mut frame) else {
+                continue;
+            };
+            let cmd = String::from_utf8_lossy(&frame[..len]).trim().to_string();
+            let upper = cmd.to_ascii_uppercase();
+
+            match self.scope {
+                None if upper == "CHALLENGE" => {
+                    let mut nonce = vec![0u8; 16];
+                    getrandom::getrandom(&mut nonce)?;
+                    relay.reply(&format!("OK {}", hex::encode(&nonce)));
+                    self.nonce = Some(nonce);
+                }
+                None if upper.starts_with("AUTH HMAC ") => {
+                    let answer = cmd["AUTH HMAC ".len()..].trim().to_ascii_lowercase();
+                    let token = match self.nonce.take() {
+                        Some(nonce) => tokens.iter().find(|t| {
+                            challenge_response(&t.token, &nonce).is_ok_and(|expected| {
+                                bool::from(expected.as_bytes().ct_eq(answer.as_bytes()))
+                            })
+                        }),
+                        None => None,
+                    };
+                    match token {
+                        Some(token) => {
+                            let scope = token.scope.min(cap);
+                            println!(
+                                "[{}] ✓ {} authenticated as {} ({scope})",
+                                timefmt::clock(),
+                                self.peer,
+                                token.name.as_deref().unwrap_or("token")
+                            );
+                            self.scope = Some(scope);
+                            relay.reply("OK Authenticated");
+                            // Anything sent along with AUTH goes by the new scope
+                            let rest = std::mem::take(&mut self.pending);
+                            let more = self.relay(&rest, tokens, cap)?;
+                            relay.to_device.extend(more.to_device);
+                            relay.to_client.extend(more.to_client);
+                            relay.audited.extend(more.audited);
+                            return Ok(relay);
+                        }
+                        None => {
+                            println!(
+                                "[{}] ✗ {} failed to authenticate",
+                                timefmt::clock(),
+                                self.peer
+                            );
+                            relay.reply("ERR Invalid PIN");
+                        }
+                    }
+                }
+                None if upper.starts_with("AUTH ") => {
+                    relay.reply("ERR Challenge-response authentication required");
+                }
+                // The CLI pings to sync before anything else
+                None if upper.split_whitespace().next() == Some("PING") => {
+                    relay.to_device.extend_from_slice(&raw);
+                }
+                None => relay.reply("ERR Authentication required (connect with --pin <token>)"),
+                Some(scope) => {
+                    let required = Scope::for_command(&cmd);
+                    if required <= scope {
+                        relay.to_device.extend_from_slice(&raw);
+                        relay.audited.extend(audit::describe_command(&cmd));
+                    } else {
+                        let verb = upper.split_whitespace().next().unwrap_or_default();
+                        println!(
+                            "[{}] ✗ Denied {verb} to {} ({scope})",
+                            timefmt::clock(),
+                            self.peer
+                        );
+                        relay.reply(&format!(
+                            "ERR {verb} needs the {required} scope on this shared device"
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(relay)
+    }
+
+    /// Commands that change the device among bytes relayed verbatim. Raw
+    /// packets and images aren't text frames and are passed over.
+    fn scan(&mut self, data: &[u8]) -> Vec<String> {
+        self.unscanned.extend_from_slice(data);
+        let mut audited = Vec::new();
+        while let Some(end) = self.unscanned.iter().position(|&b| b == 0) {
+            let mut frame: Vec<u8> = self.unscanned.drain(..=end).collect();
+            frame.pop();
+            let Some(len) = cobs_decode_in_place(&mut frame) else {
+                continue;
+            };
+            let Ok(cmd) = std::str::from_utf8(&frame[..len]) else {
+                continue;
+            };
+            if cmd.starts_with(|c: char| c.is_ascii_uppercase()) && !cmd.contains(char::is_control)
+            {
+                audited.extend(audit::describe_command(cmd.trim()));
+            }
+        }
+        if self.unscanned.len() > MAX_UNSCANNED {
+            self.unscanned.clear();
+        }
+        audited
+    }
+}
+
+/// Answer a client while another holds the device, so its commands fail
+/// with the reason rather than a closed connection.
+fn refuse(mut stream: TcpStream, reason: String) {
+    tokio::spawn(async move {
+        let mut reply = BytesMut::new();
+        cobs_encode_into(format!("ERR {reason}").as_bytes(), &mut reply);
+        let mut buf = [0u8; 256];
+        let linger = tokio::time::sleep(REFUSED_LINGER);
+        tokio::pin!(linger);
+        loop {
+            let n = tokio::select! {
+                n = stream.read(&mut buf) => n,
+                () = &mut linger => return,
+            };
+            let Ok(n @ 1..) = n else { return };
+            for _ in buf[..n].iter().filter(|&&b| b == 0) {
+                if stream.write_all(&reply).await.is_err() {
+                    return;
+                }
+            }
+        }
+    });
+}
+
+async fn read_client(session: &mut Option<Session>, buf: &mut [u8]) -> std::io::Result<usize> {
+    match session {
+        Some(session) => session.stream.read(buf).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Share the device on `port` over TCP
+pub async fn cmd_share(
+    port: &str,
+    baud: u32,
+    pin: Option<&str>,
+    listen: &str,
+    read_only: bool,
+    token: Option<String>,
+) -> Result<()> {
+    let addr = listen_addr(listen);
+    let listener = TcpListener::bind(&addr)
+        .await
+        .with_context(|| format!("Failed to listen on {addr}"))?;
+    let local = listener.local_addr()?;
+    let mut tokens = Settings::load()?.serve.tokens;
+    tokens.extend(token.map(|token| ServeToken {
+        name: Some("--token".into()),
+        token,
+        scope: Scope::Admin,
+    }));
+    if tokens.is_empty() && !local.ip().is_loopback() {
+        tracing::warn!("Sharing on {local} without authentication (set --token)");
+    }
+    let cap = if read_only {
+        Scope::ReadOnly
+    } else {
+        Scope::Admin
+    };
+
+    let mut device: Box<dyn Transport> = connect_with_auth(port, baud, pin)
+        .await?
+        .into_protocol()
+        .into_port()
+        .into_transport();
+
+    let mode = if read_only { ", read-only" } else { "" };
+    println!("Sharing {port} on {local}{mode} (Ctrl+C to stop)");
+    println!(
+        "Connect with: meshgrid-cli -p tcp://{local}{}",
+        if tokens.is_empty() {
+            ""
+        } else {
+            " --pin <token>"
+        }
+    );
+
+    let mut session: Option<Session> = None;
+    let mut client_buf = [0u8; 1024];
+    let mut device_buf = [0u8; 1024];
+    loop {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => break,
+            accepted = listener.accept() => {
+                let (stream, peer) = accepted?;
+                if let Some(current) = &session {
+                    println!("[{}] ✗ Refused {peer}: in use by {}", timefmt::clock(), current.peer);
+                    refuse(stream, format!("Shared device is in use by {}", current.peer.ip()));
+                    continue;
+                }
+                stream.set_nodelay(true)?;
+                let scope = tokens.is_empty().then_some(cap);
+                match scope {
+                    Some(scope) => println!("[{}] ✓ {peer} connected ({scope})", timefmt::clock()),
+                    None => println!("[{}] {peer} connected", timefmt::clock()),
+                }
+                session = Some(Session {
+                    stream,
+                    peer,
+                    scope,
+                    nonce: None,
+                    pending: Vec::new(),
+                    unscanned: Vec::new(),
+                });
+            }
+            n = read_client(&mut session, &mut client_buf) => {
+                let Some(current) = session.as_mut() else { continue };
+                let n = match n {
+                    Ok(n @ 1..) => n,
+                    _ => {
+                        println!("[{}] {} disconnected", timefmt::clock(), current.peer);
+                        session = None;
+                        continue;
+                    }
+                };
+                let mut relay = current.relay(&client_buf[..n], &tokens, cap)?;
+                // Nothing reaches the device that the audit log can't record
+                let peer = current.peer.ip();
+                if let Err(e) = relay
+                    .audited
+                    .iter()
+                    .try_for_each(|op| audit::relayed(format!("share {peer} {op}"), port))
+                {
+                    println!("[{}] ✗ Not relaying: {e:#}", timefmt::clock());
+                    relay.to_device.clear();
+                    relay.reply(&format!("ERR {e:#}"));
+                }
+                if !relay.to_device.is_empty() {
+                    device.write_all(&relay.to_device).await?;
+                    device.flush().await?;
+                }
+                if !relay.to_client.is_empty() && current.stream.write_all(&relay.to_client).await.is_err() {
+                    session = None;
+                }
+            }
+            n = device.read(&mut device_buf) => {
+                let n = n.context("Device connection failed")?;
+                if n == 0 {
+                    bail!(crate::error::CliError::ConnectionFailed(format!("{port} closed the connection")));
+                }
+                // Nobody listening: dropped, like a serial line without a terminal
+                if let Some(current) = session.as_mut() {
+                    if current.stream.write_all(&device_buf[..n]).await.is_err() {
+                        println!("[{}] {} disconnected", timefmt::clock(), current.peer);
+                        session = None;
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(text: &str) -> Vec<u8> {
+        let mut out = BytesMut::new();
+        cobs_encode_into(text.as_bytes(), &mut out);
+        out.to_vec()
+    }
+
+    #[tokio::test]
+    async fn test_share_session() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap());
+        let ((stream, peer), _client) =
+            tokio::join!(async { listener.accept().await.unwrap() }, async {
+                client.await.unwrap()
+            });
+        let tokens = [ServeToken {
+            name: Some("laptop".into()),
+            token: "s3cret".into(),
+            scope: Scope::Send,
+        }];
+        let mut session = Session {
+            stream,
+            peer,
+            scope: None,
+            nonce: None,
+            pending: Vec::new(),
+            unscanned: Vec::new(),
+        };
+
+        // PING goes through, other commands wait for authentication
+        let relay = session
+            .relay(
+                &[frame("PING 1f2e3d4c"), frame("INFO")].concat(),
+                &tokens,
+                Scope::Admin,
+            )
+            .unwrap();
+        assert_eq!(relay.to_device, frame("PING 1f2e3d4c"));
+        assert_eq!(
+            relay.to_client,
+            frame("ERR Authentication required (connect with --pin <token>)")
+        );
+
+        let relay = session
+            .relay(&frame("CHALLENGE"), &tokens, Scope::Admin)
+            .unwrap();
+        let mut reply = relay.to_client[..relay.to_client.len() - 1].to_vec();
+        let len = cobs_decode_in_place(&mut reply).unwrap();
+        let nonce = hex::decode(&String::from_utf8_lossy(&reply[..len])[3..]).unwrap();
+        let auth = format!(
+            "AUTH HMAC {}",
+            challenge_response("s3cret", &nonce).unwrap()
+        );
+        // Split mid-frame, with a command the scope allows behind it
+        let bytes = [frame(&auth), frame("SEND hi"), frame("REBOOT")].concat();
+        let first = session.relay(&bytes[..7], &tokens, Scope::Admin).unwrap();
+        assert_eq!(first, Relay::default());
+        let relay = session.relay(&bytes[7..], &tokens, Scope::Admin).unwrap();
+        assert_eq!(session.scope, Some(Scope::Send));
+        assert_eq!(relay.to_device, frame("SEND hi"));
+        assert_eq!(
+            relay.to_client,
+            [
+                frame("OK Authenticated"),
+                frame("ERR REBOOT needs the admin scope on this shared device")
+            ]
+            .concat()
+        );
+
+        // Admin clients are relayed verbatim, but what they change is audited
+        session.scope = Some(Scope::Admin);
+        let bytes = [
+            frame("INFO"),
+            frame("SET NAME hill"),
+            b"PKT 3\n\x01\x02\x03".to_vec(),
+        ]
+        .concat();
+        let first = session.relay(&bytes[..8], &tokens, Scope::Admin).unwrap();
+        let relay = session.relay(&bytes[8..], &tokens, Scope::Admin).unwrap();
+        assert_eq!([first.to_device, relay.to_device].concat(), bytes);
+        assert!(first.audited.is_empty());
+        assert_eq!(relay.audited, ["SET NAME hill"]);
+
+        assert_eq!(Scope::for_command("messages clear"), Scope::Admin);
+        assert_eq!(Scope::for_command("TIME REQUEST 0x3a"), Scope::Send);
+        assert_eq!(Scope::for_command("AUTH STATUS"), Scope::ReadOnly);
+        assert_eq!(Scope::for_command("PING 1f2e3d4c"), Scope::ReadOnly);
+    }
+}
@@ -0,0 +1,73 @@
+//! `sim`: a virtual mesh to develop against without hardware.
+
+use crate::cli::SimTopology;
+use crate::output;
+use crate::rng;
+use crate::sim::{self, Mesh};
+use crate::timefmt;
+use anyhow::Result;
+use clap::ValueEnum;
+use std::sync::{Arc, Mutex};
+
+/// Run simulated nodes until Ctrl+C, printing what they send
+pub async fn cmd_sim(
+    nodes: u16,
+    topology: SimTopology,
+    spacing: u32,
+    sf: u8,
+    advert_interval: u64,
+    seed: Option<u64>,
+) -> Result<()> {
+    let seed = seed.unwrap_or_else(rng::time_seed);
+    let mesh = Mesh::new(topology, usize::from(nodes), f64::from(spacing), sf, seed);
+    let summary = mesh.summary();
+    let mut log = mesh.log();
+    let mesh = Arc::new(Mutex::new(mesh));
+    let addresses = sim::listen(&mesh).await?;
+    let _registration = sim::register(&addresses)?;
+    sim::start_adverts(&mesh, advert_interval);
+
+    if output::is_structured() {
+        output::print(&summary)?;
+    } else {
+        let topology = topology
+            .to_possible_value()
+            .map_or("?".into(), |v| v.get_name().to_string());
+        println!("Simulating {nodes} nodes ({topology}, {spacing} m apart, SF{sf}, seed {seed})\n");
+        println!("  {:<8} {:<5} {:<22} IN RANGE", "NODE", "HASH", "POSITION");
+        for node in &summary {
+            let in_range = if node.in_range.is_empty() {
+                "none".to_string()
+            } else {
+                node.in_range.join(", ")
+            };
+            println!(
+                "  {:<8} 0x{:02x}  {:<22} {in_range}",
+                node.id,
+                node.node_hash,
+                format!("{:.5}, {:.5}", node.lat, node.lon)
+            );
+        }
+        println!(
+            "\nConnect with -p sim://node1 to sim://node{nodes} \
+             (e.g. `meshgrid-cli -p sim://node1 ui`). Ctrl+C stops.\n"
+        );
+    }
+
+    loop {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => break,
+            line = log.recv() => {
+                if let Ok(line) = line {
+                    if !output::is_structured() {
+                        println!("[{}] {line}", timefmt::clock());
+                    }
+                }
+            }
+        }
+    }
+    if !output::is_structured() {
+        println!("\nSimulator stopped");
+    }
+    Ok(())
+}
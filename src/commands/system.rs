@@ -1,15 +1,44 @@
 //! System commands
 
-use crate::cli::{AuthAction, BoardType, TimeAction};
-use crate::device::Device;
-use crate::protocol::Response;
-use anyhow::{bail, Result};
+use super::contacts::{contact_name, remember_neighbors};
+use super::probe::check_hardware;
+use crate::cli::{AuthAction, BoardType, DebugLevel, TimeAction};
+use crate::confirm::{confirm, Operation, Target};
+use crate::credentials::{self, Store};
+use crate::device::{Device, DeviceInfo};
+use crate::error::CliError;
+use crate::firmware::{image_chip, parse_version, settings_format_change, Chip};
+use crate::outln;
+use crate::output;
+use crate::portlock::PortLock;
+use crate::protocol::{Protocol, Response};
+use crate::timefmt;
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
 use clap::ValueEnum;
+use serde::Serialize;
+use std::time::Duration;
+
+/// meshgrid firmware's console baud rate, for reading the running version
+/// before flashing.
+const CONSOLE_BAUD: u32 = 115200;
+
+/// How long to wait for the running firmware to answer INFO before flashing.
+const VERSION_CHECK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
 
 pub async fn cmd_reboot(port: &str, baud: u32) -> Result<()> {
     let mut dev = Device::connect(port, baud).await?;
+    let info = dev.get_info().await.ok();
+    let target = Target::new(
+        Some(port),
+        info.as_ref().and_then(|i| i.name.as_deref()),
+        info.as_ref().map(|i| &i.public_key),
+    );
+    if !confirm(Operation::Reboot, &target)? {
+        return Ok(());
+    }
     dev.reboot().await?;
-    println!("Device rebooting...");
+    outln!("Device rebooting...");
     Ok(())
 }
 
@@ -25,20 +54,22 @@ pub async fn cmd_mode(port: &str, baud: u32, pin: Option<&str>, mode: &str) -> R
     let valid_modes = ["client", "repeater", "room"];
 
     if !valid_modes.contains(&mode_lower.as_str()) {
-        bail!("Invalid mode '{mode}'. Valid modes: client, repeater, room");
+        bail!(CliError::InvalidArgs(format!(
+            "Invalid mode '{mode}'. Valid modes: client, repeater, room"
+        )));
     }
 
     let command = format!("/mode {mode_lower}");
     match proto.command(&command).await? {
         Response::Ok(msg) => {
             if let Some(m) = msg {
-                println!("{m}");
+                outln!("{m}");
             } else {
-                println!("Mode set to: {}", mode_lower.to_uppercase());
+                outln!("Mode set to: {}", mode_lower.to_uppercase());
             }
             Ok(())
         }
-        Response::Error(e) => bail!("Failed to set mode: {e}"),
+        Response::Error(e) => bail!(CliError::Device(format!("failed to set mode: {e}"))),
         Response::Json(_) => bail!("Unexpected response to mode command"),
     }
 }
@@ -61,32 +92,47 @@ pub async fn cmd_time(
             // Query device time
             match proto.command("TIME").await? {
                 Response::Ok(msg) => {
-                    println!(
+                    outln!(
                         "{}",
                         msg.unwrap_or_else(|| "Device time not set".to_string())
                     );
                     Ok(())
                 }
-                Response::Error(e) => bail!("Failed to get time: {e}"),
+                Response::Error(e) => bail!(CliError::Device(format!("failed to get time: {e}"))),
                 Response::Json(_) => bail!("Unexpected response to TIME"),
             }
         }
-        TimeAction::Sync => {
+        TimeAction::Sync {
+            mesh,
+            timeout,
+            admin_password,
+        } => {
             // Sync with computer's current time
             let time_str = Local::now().format("%Y-%m-%d %H:%M:%S").to_string();
             let command = format!("/time {time_str}");
             match proto.command(&command).await? {
                 Response::Ok(msg) => {
-                    if let Some(m) = msg {
-                        println!("{m}");
-                    } else {
-                        println!("Time synced: {time_str}");
+                    // Keep stdout parseable for the --mesh report
+                    if !output::is_structured() {
+                        outln!(
+                            "{}",
+                            msg.unwrap_or_else(|| format!("Time synced: {time_str}"))
+                        );
                     }
-                    Ok(())
                 }
-                Response::Error(e) => bail!("Failed to sync time: {e}"),
+                Response::Error(e) => bail!(CliError::Device(format!("failed to sync time: {e}"))),
                 Response::Json(_) => bail!("Unexpected response to time sync"),
             }
+            if mesh {
+                sync_mesh_time(
+                    &mut proto,
+                    port,
+                    Duration::from_secs(timeout),
+                    admin_password.as_deref(),
+                )
+                .await?;
+            }
+            Ok(())
         }
         TimeAction::Set { time } => {
             // Set device time to specific value
@@ -94,41 +140,188 @@ pub async fn cmd_time(
             match proto.command(&command).await? {
                 Response::Ok(msg) => {
                     if let Some(m) = msg {
-                        println!("{m}");
+                        outln!("{m}");
                     } else {
-                        println!("Time set: {time}");
+                        outln!("Time set: {time}");
                     }
                     Ok(())
                 }
-                Response::Error(e) => bail!("Failed to set time: {e}"),
+                Response::Error(e) => bail!(CliError::Device(format!("failed to set time: {e}"))),
                 Response::Json(_) => bail!("Unexpected response to time set"),
             }
         }
     }
 }
 
+/// Clock of one node before and after `time sync --mesh`.
+#[derive(Debug, Serialize)]
+struct NodeClock {
+    node: String,
+    node_hash: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mode: Option<String>,
+    /// Node clock minus ours before the sync, in seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    offset_before: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    offset_after: Option<i64>,
+    /// `synced`, `skipped` (a companion, whose app sets its clock) or `failed`
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Push this computer's time to the repeaters and room servers in the
+/// neighbor table, one after the other.
+async fn sync_mesh_time(
+    proto: &mut Protocol,
+    port: &str,
+    timeout: Duration,
+    password: Option<&str>,
+) -> Result<()> {
+    let neighbors = proto.get_neighbors().await?;
+    remember_neighbors(proto, port, &neighbors).await;
+    if neighbors.is_empty() {
+        if !output::is_structured() {
+            outln!("No neighbors to sync");
+        }
+        return Ok(());
+    }
+    let live = !output::is_plain() && !output::is_structured();
+    if live {
+        outln!("Syncing {} neighbor(s) over the mesh...", neighbors.len());
+    }
+
+    let mut clocks = Vec::new();
+    for neighbor in &neighbors {
+        let mut clock = NodeClock {
+            node: contact_name(neighbor),
+            node_hash: format!("0x{:02x}", neighbor.node_hash),
+            mode: None,
+            offset_before: None,
+            offset_after: None,
+            status: "failed",
+            error: None,
+        };
+        if let Err(e) = sync_node_time(proto, &mut clock, timeout, password).await {
+            clock.error = Some(format!("{e:#}"));
+        }
+        if live {
+            let label = format!("{} ({})", clock.node, clock.node_hash);
+            let offset = |secs: Option<i64>| secs.map_or_else(|| "?".into(), clock_offset);
+            match clock.status {
+                "synced" => outln!(
+                    "  ✓ {label}: {} → {}",
+                    offset(clock.offset_before),
+                    offset(clock.offset_after)
+                ),
+                "skipped" => outln!("  - {label}: companion, skipped"),
+                _ => outln!(
+                    "  ✗ {label}: {}",
+                    clock.error.as_deref().unwrap_or("failed")
+                ),
+            }
+        }
+        clocks.push(clock);
+    }
+
+    if output::is_structured() {
+        output::print(&clocks)?;
+    } else if output::is_plain() {
+        for (key, value) in output::flatten_json(&serde_json::to_value(&clocks)?) {
+            output::kv(&key, value);
+        }
+    }
+    let failed = clocks.iter().filter(|c| c.status == "failed").count();
+    if failed > 0 {
+        bail!(CliError::Device(format!(
+            "{failed} of {} nodes not synced",
+            clocks.len()
+        )));
+    }
+    Ok(())
+}
+
+/// Read a node's clock, set it and read back the offset.
+async fn sync_node_time(
+    proto: &mut Protocol,
+    clock: &mut NodeClock,
+    timeout: Duration,
+    password: Option<&str>,
+) -> Result<()> {
+    let sent = Utc::now();
+    let before = proto.request_time(&clock.node_hash, timeout).await?;
+    let round_trip = Utc::now() - sent;
+    clock.offset_before = Some(offset_at(before.time, sent));
+    clock.mode.clone_from(&before.mode);
+    if before.mode.as_deref() == Some("client") {
+        clock.status = "skipped";
+        return Ok(());
+    }
+
+    // The reply left the node half a round trip before it arrived
+    let sent = Utc::now();
+    let time = (sent + round_trip / 2).timestamp();
+    let after = proto
+        .set_remote_time(&clock.node_hash, time, password, timeout)
+        .await?;
+    clock.offset_after = Some(offset_at(after.time, sent));
+    clock.status = "synced";
+    Ok(())
+}
+
+/// Offset of a clock reading received in reply to a request `sent`, against
+/// our clock halfway through the round trip.
+fn offset_at(remote: i64, sent: DateTime<Utc>) -> i64 {
+    let received = Utc::now();
+    remote - (sent + (received - sent) / 2).timestamp()
+}
+
+/// `+2m 5s` (node ahead), `-40s` (behind) or `0s`.
+fn clock_offset(secs: i64) -> String {
+    let sign = match secs.signum() {
+        1 => "+",
+        -1 => "-",
+        _ => "",
+    };
+    format!("{sign}{}", timefmt::duration(secs.unsigned_abs()))
+}
+
 pub async fn cmd_debug(
     port: &str,
     baud: u32,
     output_file: Option<String>,
     timeout_secs: u64,
+    level: DebugLevel,
+    modules: &[String],
+    json_output: bool,
 ) -> Result<()> {
     use crate::serial::SerialPort;
     use std::fs::OpenOptions;
-    use std::io::Write;
+    use std::io::{IsTerminal, Write};
 
     let infinite = timeout_secs == 0;
+    let filter = DebugFilter { level, modules };
+
+    // Keep stdout clean for the JSON lines
+    let status = |line: String| {
+        if json_output && output_file.is_none() {
+            eprintln!("{line}");
+        } else {
+            outln!("{line}");
+        }
+    };
 
     if let Some(ref file) = output_file {
-        println!("Capturing debug output to: {file}");
+        status(format!("Capturing debug output to: {file}"));
     } else {
-        println!("Streaming debug output to stdout");
+        status("Streaming debug output to stdout".to_string());
     }
 
     if infinite {
-        println!("Running indefinitely (Press Ctrl+C to stop)\n");
+        status("Running indefinitely (Press Ctrl+C to stop)\n".to_string());
     } else {
-        println!("Timeout: {timeout_secs} seconds\n");
+        status(format!("Timeout: {timeout_secs} seconds\n"));
     }
 
     // Open output file (unbuffered)
@@ -137,6 +330,10 @@ pub async fn cmd_debug(
     } else {
         None
     };
+    let color = file_handle.is_none()
+        && !json_output
+        && std::io::stdout().is_terminal()
+        && std::env::var_os("NO_COLOR").is_none();
 
     let mut serial = SerialPort::open(port, baud).await?;
     let start = std::time::Instant::now();
@@ -163,12 +360,23 @@ pub async fn cmd_debug(
                         // It's a debug frame - extract and output
                         let level = json.get("level").and_then(|v| v.as_str()).unwrap_or("INFO");
                         let msg = json.get("msg").and_then(|v| v.as_str()).unwrap_or("");
+                        let module = debug_module(&json, msg);
+                        if !filter.matches(level, module) {
+                            continue;
+                        }
 
-                        let output_line = format!("[{level}] {msg}\n");
+                        let output_line = if json_output {
+                            format!("{}\n", text.trim())
+                        } else {
+                            format!("[{level}] {msg}\n")
+                        };
 
                         if let Some(ref mut file) = file_handle {
                             file.write_all(output_line.as_bytes())?;
                             file.flush()?; // Force immediate write
+                        } else if color {
+                            let line = output_line.trim_end().to_string();
+                            outln!("{}", colorize(parse_debug_level(level), line));
                         } else {
                             print!("{output_line}");
                             std::io::stdout().flush()?;
@@ -187,13 +395,60 @@ pub async fn cmd_debug(
     }
 
     if output_file.is_some() {
-        println!("\nDebug capture stopped");
+        status("\nDebug capture stopped".to_string());
     } else {
-        println!("\n--- End of debug output ---");
+        status("\n--- End of debug output ---".to_string());
     }
     Ok(())
 }
 
+/// Which debug frames `cmd_debug` shows
+struct DebugFilter<'a> {
+    level: DebugLevel,
+    /// Module names to keep; empty keeps all
+    modules: &'a [String],
+}
+
+impl DebugFilter<'_> {
+    fn matches(&self, level: &str, module: Option<&str>) -> bool {
+        parse_debug_level(level) >= self.level
+            && (self.modules.is_empty()
+                || module.is_some_and(|module| {
+                    self.modules.iter().any(|m| m.eq_ignore_ascii_case(module))
+                }))
+    }
+}
+
+/// Severity of a firmware level name (`WARN`, `warning`, `E`, ...);
+/// unknown names count as info.
+fn parse_debug_level(level: &str) -> DebugLevel {
+    match level.to_ascii_lowercase().as_str() {
+        "trace" | "t" | "verbose" | "v" => DebugLevel::Trace,
+        "debug" | "d" => DebugLevel::Debug,
+        "warn" | "warning" | "w" => DebugLevel::Warn,
+        "error" | "err" | "e" | "fatal" | "crit" => DebugLevel::Error,
+        _ => DebugLevel::Info,
+    }
+}
+
+/// Module of a debug frame: its `module` field, or a `[module]` prefix on the message
+fn debug_module<'a>(json: &'a serde_json::Value, msg: &'a str) -> Option<&'a str> {
+    json.get("module")
+        .and_then(|v| v.as_str())
+        .or_else(|| msg.strip_prefix('[')?.split_once(']').map(|(m, _)| m))
+}
+
+fn colorize(level: DebugLevel, line: String) -> String {
+    use crossterm::style::Stylize;
+
+    match level {
+        DebugLevel::Error => line.red().to_string(),
+        DebugLevel::Warn => line.yellow().to_string(),
+        DebugLevel::Info => line,
+        DebugLevel::Debug | DebugLevel::Trace => line.dark_grey().to_string(),
+    }
+}
+
 /// USB VID/PID to board type mapping (prepared for future auto-detection)
 #[allow(dead_code)]
 struct UsbDeviceInfo {
@@ -389,21 +644,410 @@ fn detect_boards() -> Vec<(String, Option<BoardType>, String, &'static [BoardTyp
     detected
 }
 
+/// PlatformIO environment and display name of `board`.
+fn board_names(board: BoardType) -> (&'static str, &'static str) {
+    match board {
+        // Heltec ESP32-S3
+        BoardType::HeltecV3 => ("heltec_v3", "Heltec V3"),
+        BoardType::HeltecV4 => ("heltec_v4", "Heltec V4"),
+        BoardType::HeltecWirelessStickLiteV3 => (
+            "heltec_wireless_stick_lite_v3",
+            "Heltec Wireless Stick Lite V3",
+        ),
+        BoardType::HeltecWirelessTracker => ("heltec_wireless_tracker", "Heltec Wireless Tracker"),
+        BoardType::HeltecWirelessPaper => ("heltec_wireless_paper", "Heltec Wireless Paper"),
+        BoardType::HeltecVisionMasterT190 => {
+            ("heltec_vision_master_t190", "Heltec Vision Master T190")
+        }
+        BoardType::HeltecVisionMasterE213 => {
+            ("heltec_vision_master_e213", "Heltec Vision Master E213")
+        }
+        BoardType::HeltecVisionMasterE290 => {
+            ("heltec_vision_master_e290", "Heltec Vision Master E290")
+        }
+        BoardType::HeltecHt62 => ("heltec_ht62", "Heltec HT62"),
+        BoardType::HeltecMeshNodeT114 => ("heltec_mesh_node_t114", "Heltec Mesh Node T114"),
+        BoardType::HeltecMeshPocket => ("heltec_mesh_pocket", "Heltec MeshPocket"),
+
+        // LilyGo ESP32-S3
+        BoardType::LilygoT3s3 => ("lilygo_t3s3", "LilyGo T3S3"),
+        BoardType::LilygoT3s3Eink => ("lilygo_t3s3_eink", "LilyGo T3S3 E-Ink"),
+        BoardType::LilygoTbeamSupreme => ("lilygo_tbeam_supreme", "LilyGo T-Beam Supreme"),
+        BoardType::LilygoTdeck => ("lilygo_tdeck", "LilyGo T-Deck"),
+        BoardType::LilygoTdeckPro => ("lilygo_tdeck_pro", "LilyGo T-Deck Pro"),
+        BoardType::LilygoTloraPager => ("lilygo_tlora_pager", "LilyGo T-LoRa Pager"),
+        BoardType::LilygoTwatchS3 => ("lilygo_twatch_s3", "LilyGo T-Watch S3"),
+
+        // LilyGo ESP32
+        BoardType::LilygoTbeam => ("lilygo_tbeam", "LilyGo T-Beam"),
+        BoardType::LilygoTloraV2116 => ("lilygo_tlora_v21_16", "LilyGo T-LoRa V2.1-1.6"),
+        BoardType::LilygoTloraV2118 => ("lilygo_tlora_v21_18", "LilyGo T-LoRa V2.1-1.8"),
+
+        // LilyGo nRF52840
+        BoardType::LilygoTecho => ("lilygo_techo", "LilyGo T-Echo"),
+
+        // RAK nRF52840
+        BoardType::Rak4631 => ("rak4631", "RAK4631"),
+        BoardType::RakWismeshRepeater => ("rak_wismesh_repeater", "RAK WisMesh Repeater"),
+        BoardType::RakWismeshTap => ("rak_wismesh_tap", "RAK WisMesh Tap"),
+        BoardType::RakWismeshTag => ("rak_wismesh_tag", "RAK WisMesh Tag"),
+        BoardType::Rak34011w => ("rak3401_1w", "RAK3401 1W"),
+
+        // RAK ESP32/S3
+        BoardType::Rak11200 => ("rak11200", "RAK11200"),
+        BoardType::Rak3312 => ("rak3312", "RAK3312"),
+
+        // RAK RP2040
+        BoardType::Rak11310 => ("rak11310", "RAK11310"),
+
+        // Seeed nRF52840
+        BoardType::SeeedTrackerT1000e => ("seeed_tracker_t1000e", "Seeed Tracker T1000-E"),
+        BoardType::SeeedXiaoNrf52840 => ("seeed_xiao_nrf52840", "Seeed Xiao nRF52840"),
+        BoardType::SeeedSensecapSolar => ("seeed_sensecap_solar", "Seeed SenseCAP Solar"),
+        BoardType::SeeedWioTrackerL1 => ("seeed_wio_tracker_l1", "Seeed Wio Tracker L1"),
+        BoardType::SeeedWioTrackerL1Eink => {
+            ("seeed_wio_tracker_l1_eink", "Seeed Wio Tracker L1 E-Ink")
+        }
+        BoardType::SeeedWioWm1110 => ("seeed_wio_wm1110", "Seeed Wio WM1110"),
+
+        // Seeed ESP32-S3
+        BoardType::SeeedSensecapIndicator => {
+            ("seeed_sensecap_indicator", "Seeed SenseCAP Indicator")
+        }
+        BoardType::SeeedXiaoEsp32s3 => ("seeed_xiao_esp32s3", "Seeed Xiao ESP32-S3"),
+
+        // Elecrow
+        BoardType::ThinknodeM1 => ("thinknode_m1", "ThinkNode M1"),
+        BoardType::ThinknodeM2 => ("thinknode_m2", "ThinkNode M2"),
+        BoardType::ThinknodeM3 => ("thinknode_m3", "ThinkNode M3"),
+        BoardType::ThinknodeM5 => ("thinknode_m5", "ThinkNode M5"),
+        BoardType::Crowpanel24tft => ("crowpanel_24tft", "Crowpanel 2.4/2.8 TFT"),
+        BoardType::Crowpanel35tft => ("crowpanel_35tft", "Crowpanel 3.5 TFT"),
+        BoardType::Crowpanel43tft => ("crowpanel_43tft", "Crowpanel 4.3/5.0/7.0 TFT"),
+
+        // B&Q Consulting
+        BoardType::StationG2 => ("station_g2", "Station G2"),
+        BoardType::StationG1 => ("station_g1", "Station G1"),
+        BoardType::NanoG1 => ("nano_g1", "Nano G1"),
+        BoardType::NanoG1Explorer => ("nano_g1_explorer", "Nano G1 Explorer"),
+        BoardType::NanoG2Ultra => ("nano_g2_ultra", "Nano G2 Ultra"),
+
+        // M5Stack
+        BoardType::M5stack => ("m5stack", "M5 Stack"),
+        BoardType::M5stackUnitC6l => ("m5stack_unit_c6l", "M5Stack Unit C6L"),
+
+        // Other Vendors
+        BoardType::MuziBase => ("muzi_base", "muzi BASE"),
+        BoardType::MuziR1Neo => ("muzi_r1_neo", "muzi R1 Neo"),
+        BoardType::NomadstarMeteorPro => ("nomadstar_meteor_pro", "NomadStar Meteor Pro"),
+        BoardType::CanaryOne => ("canary_one", "Canary One"),
+        BoardType::Radiomaster900Bandit => ("radiomaster_900_bandit", "RadioMaster 900 Bandit"),
+        BoardType::EbyteEoraS3 => ("ebyte_eora_s3", "EByte EoRa-S3"),
+        BoardType::TracksengerSmall => ("tracksenger_small", "TrackSenger Small"),
+        BoardType::TracksengerBig => ("tracksenger_big", "TrackSenger Big"),
+        BoardType::PiComputerS3 => ("pi_computer_s3", "Pi Computer S3"),
+        BoardType::Unphone => ("unphone", "unPhone"),
+
+        // RP2040
+        BoardType::Rp2040Lora => ("rp2040_lora", "RP2040 LoRa"),
+        BoardType::RpiPico => ("rpi_pico", "Raspberry Pi Pico"),
+        BoardType::RpiPicoW => ("rpi_pico_w", "Raspberry Pi Pico W"),
+
+        // DIY
+        BoardType::DiyV1 => ("diy_v1", "DIY V1"),
+        BoardType::Hydra => ("hydra", "Hydra"),
+        BoardType::Nrf52PromicroDiy => ("nrf52_promicro_diy", "nRF52 Pro-micro DIY"),
+    }
+}
+
+/// Chip on `board`, where known.
+pub(super) fn board_chip(board: BoardType) -> Option<Chip> {
+    use BoardType::*;
+    Some(match board {
+        HeltecV3
+        | HeltecV4
+        | HeltecWirelessStickLiteV3
+        | HeltecWirelessTracker
+        | HeltecWirelessPaper
+        | HeltecVisionMasterT190
+        | HeltecVisionMasterE213
+        | HeltecVisionMasterE290
+        | LilygoT3s3
+        | LilygoT3s3Eink
+        | LilygoTbeamSupreme
+        | LilygoTdeck
+        | LilygoTdeckPro
+        | LilygoTloraPager
+        | LilygoTwatchS3
+        | Rak3312
+        | SeeedSensecapIndicator
+        | SeeedXiaoEsp32s3
+        | ThinknodeM2
+        | ThinknodeM5
+        | Crowpanel24tft
+        | Crowpanel35tft
+        | Crowpanel43tft
+        | StationG2
+        | EbyteEoraS3
+        | PiComputerS3
+        | Unphone => Chip::Esp32S3,
+        LilygoTbeam | LilygoTloraV2116 | LilygoTloraV2118 | Rak11200 | StationG1 | NanoG1
+        | NanoG1Explorer | M5stack | Radiomaster900Bandit | DiyV1 | Hydra => Chip::Esp32,
+        HeltecHt62 => Chip::Esp32C3,
+        M5stackUnitC6l => Chip::Esp32C6,
+        HeltecMeshNodeT114
+        | HeltecMeshPocket
+        | LilygoTecho
+        | Rak4631
+        | RakWismeshRepeater
+        | RakWismeshTap
+        | RakWismeshTag
+        | Rak34011w
+        | SeeedTrackerT1000e
+        | SeeedXiaoNrf52840
+        | SeeedSensecapSolar
+        | SeeedWioTrackerL1
+        | SeeedWioTrackerL1Eink
+        | SeeedWioWm1110
+        | ThinknodeM1
+        | ThinknodeM3
+        | NanoG2Ultra
+        | MuziR1Neo
+        | NomadstarMeteorPro
+        | CanaryOne
+        | Nrf52PromicroDiy => Chip::Nrf52840,
+        Rak11310 | Rp2040Lora | RpiPico | RpiPicoW => Chip::Rp2040,
+        MuziBase | TracksengerSmall | TracksengerBig => return None,
+    })
+}
+
+/// Refuse to flash `board` firmware to a port whose USB IDs belong to
+/// another chip family.
+fn check_port_chip(
+    board: BoardType,
+    board_name: &str,
+    port: Option<&str>,
+    detected: &[(String, Option<BoardType>, String, &'static [BoardType])],
+) -> Result<()> {
+    let Some(chip) = board_chip(board) else {
+        return Ok(());
+    };
+    let Some((port, _, chip_name, possible)) =
+        detected.iter().find(|(p, ..)| Some(p.as_str()) == port)
+    else {
+        return Ok(());
+    };
+    let families: Vec<&str> = possible
+        .iter()
+        .filter_map(|b| board_chip(*b))
+        .map(Chip::family)
+        .collect();
+    if families.is_empty() || families.contains(&chip.family()) {
+        return Ok(());
+    }
+    bail!(CliError::InvalidArgs(format!(
+        "The {board_name} is {} but the device on {port} is {chip_name} ({}); check the board type",
+        chip.name(),
+        families[0]
+    )))
+}
+
+/// Refuse a firmware image built for a different chip than `board`'s.
+fn check_image_chip(path: &std::path::Path, board: BoardType, board_name: &str) -> Result<()> {
+    let image =
+        std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    match (board_chip(board), image_chip(&image)) {
+        (Some(expected), Some(found)) if expected != found => {
+            bail!(CliError::InvalidArgs(format!(
+                "{} is built for {} but the {board_name} is {}",
+                path.display(),
+                found.name(),
+                expected.name()
+            )))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Info of the firmware running on `port`, if it answers INFO.
+pub(super) async fn running_info(port: Option<&str>) -> Option<DeviceInfo> {
+    tokio::time::timeout(VERSION_CHECK_TIMEOUT, async {
+        Device::connect(port?, CONSOLE_BAUD)
+            .await
+            .ok()?
+            .get_info()
+            .await
+            .ok()
+    })
+    .await
+    .ok()?
+}
+
+/// The device on `port`, as named by its running firmware, for confirming
+/// an operation that goes through espflash.
+pub(super) async fn flash_target(port: Option<&str>) -> (Target, Option<DeviceInfo>) {
+    let info = running_info(port).await;
+    let target = Target::new(
+        port,
+        info.as_ref().and_then(|i| i.name.as_deref()),
+        info.as_ref().map(|i| &i.public_key),
+    );
+    (target, info)
+}
+
+/// Warn when flashing `target` crosses a settings format change from the
+/// running firmware, and refuse a downgrade unless `allow_downgrade`.
+fn check_downgrade(running: Option<&str>, target: &str, allow_downgrade: bool) -> Result<()> {
+    let plain = output::is_plain();
+    let Some(running) = running else {
+        if !plain {
+            outln!("⚠ Could not read the running firmware version, skipping the downgrade check");
+        }
+        return Ok(());
+    };
+
+    if let Some(change) = settings_format_change(running, target) {
+        if !plain {
+            outln!(
+                "⚠ The settings format changed in {change}: settings saved by {running} can't be read by {target}"
+            );
+        }
+    }
+    let downgrade = matches!(
+        (parse_version(running), parse_version(target)),
+        (Some(running), Some(target)) if target < running
+    );
+    if !downgrade {
+        return Ok(());
+    }
+    if !allow_downgrade {
+        bail!(CliError::InvalidArgs(format!(
+            "{target} is older than the running firmware ({running}); \
+             pass --allow-downgrade to flash it anyway"
+        )));
+    }
+    if !plain {
+        outln!("⚠ Downgrading from {running} to {target}");
+    }
+    Ok(())
+}
+
+/// Where external flashing tools write their output (stderr in plain mode,
+/// keeping stdout to `key=value` lines)
+fn tool_stdout() -> std::process::Stdio {
+    if output::is_plain() {
+        std::io::stderr().into()
+    } else {
+        std::process::Stdio::inherit()
+    }
+}
+
+/// Update an ESP32 board on WiFi over the network with ArduinoOTA
+pub async fn cmd_flash_ota(
+    board: Option<BoardType>,
+    host: &str,
+    password: Option<&str>,
+    version: Option<&str>,
+    force_download: bool,
+    offline: bool,
+) -> Result<()> {
+    use crate::firmware::{app_image, FirmwareManager};
+
+    let plain = output::is_plain();
+    let Some(board) = board else {
+        bail!(CliError::InvalidArgs(
+            "--ota needs the board type: meshgrid-cli flash --ota <host> -B station-g2".into()
+        ));
+    };
+    let (env_name, board_name) = board_names(board);
+    if board_chip(board).is_some_and(|chip| chip.family() != "ESP32") {
+        bail!(CliError::InvalidArgs(format!(
+            "The {board_name} has no WiFi; --ota updates ESP32 boards only"
+        )));
+    }
+    if !confirm(Operation::Flash, &Target::new(Some(host), None, None))? {
+        return Ok(());
+    }
+
+    let firmware_manager = FirmwareManager::new()?;
+    let ver = firmware_manager
+        .resolve_version(version.unwrap_or("latest"), offline)
+        .await?;
+    let firmware_path = firmware_manager
+        .get_firmware(env_name, &ver, force_download, offline)
+        .await?;
+    check_image_chip(&firmware_path, board, board_name)?;
+    let merged = std::fs::read(&firmware_path)?;
+    let Some(app) = app_image(&merged) else {
+        bail!(
+            "No app image found in {} (expected a merged binary with a partition table)",
+            firmware_path.display()
+        );
+    };
+
+    if plain {
+        output::kv("board", env_name);
+        output::kv("host", host);
+        output::kv("firmware", firmware_path.display());
+    } else {
+        outln!(
+            "Updating {board_name} at {host} to {ver} over WiFi ({} KB)...",
+            app.len() / 1024
+        );
+    }
+    let pb = if plain {
+        indicatif::ProgressBar::hidden()
+    } else {
+        indicatif::ProgressBar::new(app.len() as u64)
+    };
+    pb.set_style(output::progress_style(
+        "  {spinner:.green} [{bar:40.cyan/blue}] {bytes}/{total_bytes} {percent}% - {eta}",
+    ));
+    let result = crate::ota::upload(host, app, password, |sent| pb.set_position(sent as u64)).await;
+    pb.finish_and_clear();
+    result.map_err(|e| CliError::Device(format!("OTA update failed: {e:#}")))?;
+
+    if plain {
+        output::kv("result", "ok");
+    } else {
+        outln!("✓ Firmware {ver} written; the device reboots into it");
+    }
+    Ok(())
+}
+
 /// Flash a precompiled firmware binary to an ESP32 device
 async fn flash_precompiled_binary(
     firmware_path: &std::path::Path,
     port: Option<&str>,
     monitor: bool,
+    incremental: bool,
+    verify: bool,
 ) -> Result<()> {
     use std::process::Command;
 
-    println!(
-        "Flashing merged firmware binary: {}",
-        firmware_path.display()
-    );
-
-    // Step 1: Erase entire flash
-    println!("Step 1/2: Erasing entire flash...");
+    let plain = output::is_plain();
+    if plain {
+        output::kv("firmware", firmware_path.display());
+    }
+    if incremental {
+        match flash_changed_regions(firmware_path, port, verify) {
+            Ok(()) => return monitor_after_flash(port, monitor),
+            Err(e) => {
+                if !plain {
+                    outln!("⚠ Incremental flash failed ({e:#}), flashing the full image\n");
+                }
+            }
+        }
+    }
+    if !plain {
+        outln!(
+            "Flashing merged firmware binary: {}",
+            firmware_path.display()
+        );
+
+        // Step 1: Erase entire flash
+        outln!("Step 1/2: Erasing entire flash...");
+    }
     let mut erase_args = vec!["erase-flash"];
 
     if let Some(p) = port {
@@ -413,6 +1057,7 @@ async fn flash_precompiled_binary(
 
     let status = Command::new("espflash")
         .args(&erase_args)
+        .stdout(tool_stdout())
         .status()
         .map_err(|e| {
             anyhow::anyhow!(
@@ -426,10 +1071,12 @@ async fn flash_precompiled_binary(
         bail!("Flash erase failed");
     }
 
-    println!("✓ Flash erased");
+    if !plain {
+        outln!("✓ Flash erased");
 
-    // Step 2: Write merged binary at 0x0
-    println!("\nStep 2/2: Writing merged binary (bootloader + partitions + app)...");
+        // Step 2: Write merged binary at 0x0
+        outln!("\nStep 2/2: Writing merged binary (bootloader + partitions + app)...");
+    }
     let mut write_args = vec!["write-bin"];
 
     if let Some(p) = port {
@@ -440,19 +1087,179 @@ async fn flash_precompiled_binary(
     write_args.push("0x0");
     write_args.push(firmware_path.to_str().unwrap());
 
-    let status = Command::new("espflash").args(&write_args).status()?;
+    let status = Command::new("espflash")
+        .args(&write_args)
+        .stdout(tool_stdout())
+        .status()?;
 
     if !status.success() {
         bail!("espflash write failed");
     }
+    if verify {
+        verify_flash(firmware_path, 0, port)?;
+    }
+
+    if plain {
+        output::kv("result", "ok");
+    } else {
+        outln!("\n✓ Flash complete!");
+    }
+
+    monitor_after_flash(port, monitor)
+}
+
+/// Read the flash back and write only the sectors that differ from the
+/// firmware image, without erasing the rest.
+fn flash_changed_regions(
+    firmware_path: &std::path::Path,
+    port: Option<&str>,
+    verify: bool,
+) -> Result<()> {
+    use crate::firmware::changed_regions;
+
+    let plain = output::is_plain();
+    let image = std::fs::read(firmware_path)?;
+    if !plain {
+        outln!(
+            "Flashing merged firmware binary: {}",
+            firmware_path.display()
+        );
+        outln!("Step 1/2: Reading back flash to find changed regions...");
+    }
+    let readback_path = firmware_path.with_extension("readback");
+    let result = espflash(
+        "read-flash",
+        port,
+        &[
+            "0x0".into(),
+            image.len().to_string(),
+            readback_path.display().to_string(),
+        ],
+    )
+    .and_then(|()| Ok(std::fs::read(&readback_path)?));
+    let _ = std::fs::remove_file(&readback_path);
+    let regions = changed_regions(&result?, &image);
+
+    let changed: usize = regions.iter().map(|(_, len)| len).sum();
+    if plain {
+        output::kv("changed_bytes", changed);
+    } else {
+        outln!(
+            "✓ {:.1} of {:.1} KB differ ({} regions)",
+            changed as f64 / 1024.0,
+            image.len() as f64 / 1024.0,
+            regions.len()
+        );
+        outln!("\nStep 2/2: Writing changed regions...");
+    }
+
+    let chunk_path = firmware_path.with_extension("chunk");
+    for (offset, len) in regions {
+        std::fs::write(&chunk_path, &image[offset..offset + len])?;
+        let result = espflash(
+            "write-bin",
+            port,
+            &[format!("0x{offset:x}"), chunk_path.display().to_string()],
+        );
+        let _ = std::fs::remove_file(&chunk_path);
+        result?;
+    }
+    if verify && changed > 0 {
+        verify_flash(firmware_path, 0, port)?;
+    }
+
+    if plain {
+        output::kv("result", "ok");
+    } else if changed == 0 {
+        outln!("\n✓ Flash already matches the firmware, nothing written");
+    } else {
+        outln!("\n✓ Flash complete!");
+    }
+    Ok(())
+}
+
+/// Read the image flashed at `offset` back and compare its SHA-256 with the
+/// file's, so a corrupted write is caught before the device is rebooted.
+pub(super) fn verify_flash(
+    firmware_path: &std::path::Path,
+    offset: u32,
+    port: Option<&str>,
+) -> Result<()> {
+    use crate::firmware::{changed_regions, sha256_hex};
+
+    let plain = output::is_plain();
+    let image = std::fs::read(firmware_path)?;
+    if !plain {
+        outln!(
+            "\nVerifying: reading back {:.1} KB...",
+            image.len() as f64 / 1024.0
+        );
+    }
+    let readback_path = firmware_path.with_extension("verify");
+    let result = espflash(
+        "read-flash",
+        port,
+        &[
+            format!("0x{offset:x}"),
+            image.len().to_string(),
+            readback_path.display().to_string(),
+        ],
+    )
+    .and_then(|()| Ok(std::fs::read(&readback_path)?));
+    let _ = std::fs::remove_file(&readback_path);
+    let flashed = result.context("Failed to read back the flash for verification")?;
+
+    let expected = sha256_hex(&image);
+    let actual = sha256_hex(&flashed);
+    if plain {
+        output::kv("sha256", &expected);
+        output::kv("verified", actual == expected);
+    }
+    if actual != expected {
+        let bad = changed_regions(&flashed, &image);
+        let first = bad.first().map_or(0, |(offset, _)| *offset);
+        bail!(CliError::Device(format!(
+            "Flash verification failed: read back SHA-256 {actual}, expected {expected} \
+             ({} region(s) differ, the first at 0x{:x}). Flash again before rebooting the device",
+            bad.len(),
+            offset as usize + first
+        )));
+    }
+    if !plain {
+        outln!("✓ Verified: SHA-256 {expected}");
+    }
+    Ok(())
+}
 
-    println!("\n✓ Flash complete!");
+/// Run an espflash subcommand on `port`.
+pub(super) fn espflash(subcommand: &str, port: Option<&str>, args: &[String]) -> Result<()> {
+    let mut command = std::process::Command::new("espflash");
+    command.arg(subcommand);
+    if let Some(p) = port {
+        command.args(["--port", p]);
+    }
+    let status = command
+        .args(args)
+        .stdout(tool_stdout())
+        .status()
+        .map_err(|e| {
+            anyhow::anyhow!(
+                "Failed to run espflash: {}\n\
+                 Make sure espflash is installed: cargo install espflash",
+                e
+            )
+        })?;
+    if !status.success() {
+        bail!("espflash {subcommand} failed");
+    }
+    Ok(())
+}
 
-    // Monitor if requested
+fn monitor_after_flash(port: Option<&str>, monitor: bool) -> Result<()> {
     if monitor {
-        println!("\nStarting serial monitor...");
+        outln!("\nStarting serial monitor...");
         let monitor_port = port.unwrap_or("/dev/ttyUSB0");
-        let status = Command::new("espflash")
+        let status = std::process::Command::new("espflash")
             .args(["monitor", "--port", monitor_port])
             .status()?;
 
@@ -475,6 +1282,10 @@ pub async fn cmd_flash(
     version: Option<&str>,
     force_download: bool,
     offline: bool,
+    incremental: bool,
+    verify: bool,
+    allow_downgrade: bool,
+    hardware_check: bool,
 ) -> Result<()> {
     use std::io::{self, Write};
     use std::process::Command;
@@ -483,24 +1294,39 @@ pub async fn cmd_flash(
     let detected = detect_boards();
 
     // If --detect flag, just list devices
+    if detect && output::is_plain() {
+        // One row per candidate: port, board, confirmed|possible
+        for (port, specific, _, possible) in &detected {
+            if let Some(board) = specific {
+                let board_name = board.to_possible_value().unwrap().get_name().to_string();
+                outln!("{port}\t{board_name}\tconfirmed");
+            } else {
+                for b in *possible {
+                    let board_name = b.to_possible_value().unwrap().get_name().to_string();
+                    outln!("{port}\t{board_name}\tpossible");
+                }
+            }
+        }
+        return Ok(());
+    }
     if detect {
-        println!("Detected devices:\n");
+        outln!("Detected devices:\n");
         if detected.is_empty() {
-            println!("  No compatible devices found.");
-            println!("\n  Make sure your device is connected via USB.");
+            outln!("  No compatible devices found.");
+            outln!("\n  Make sure your device is connected via USB.");
         } else {
             for (port, specific, chip_name, possible) in &detected {
                 if let Some(board) = specific {
                     let board_name = board.to_possible_value().unwrap().get_name().to_string();
-                    println!("  {port} - {board_name} (confirmed)");
+                    outln!("  {port} - {board_name} (confirmed)");
                 } else {
-                    println!("  {port} - {chip_name} (could be one of:)");
+                    outln!("  {port} - {chip_name} (could be one of:)");
                     for b in *possible {
                         let board_name = b.to_possible_value().unwrap().get_name().to_string();
-                        println!("       - {board_name}");
+                        outln!("       - {board_name}");
                     }
                 }
-                println!();
+                outln!();
             }
         }
         return Ok(());
@@ -522,7 +1348,9 @@ pub async fn cmd_flash(
 
             if let Some(board) = specific {
                 let board_name = board.to_possible_value().unwrap().get_name().to_string();
-                println!("Auto-detected: {board_name} on {detected_port}\n");
+                if !output::is_plain() {
+                    outln!("Auto-detected: {board_name} on {detected_port}\n");
+                }
                 *board
             } else if possible.is_empty() {
                 bail!(
@@ -531,13 +1359,13 @@ pub async fn cmd_flash(
                 );
             } else {
                 // Show menu for user to select
-                println!("Device detected on {detected_port}: {chip_name}\n");
-                println!("Which board is this?\n");
+                outln!("Device detected on {detected_port}: {chip_name}\n");
+                outln!("Which board is this?\n");
                 for (i, b) in possible.iter().enumerate() {
                     let board_name = b.to_possible_value().unwrap().get_name().to_string();
-                    println!("  [{}] {}", i + 1, board_name);
+                    outln!("  [{}] {}", i + 1, board_name);
                 }
-                println!();
+                outln!();
                 print!("Enter number (1-{}): ", possible.len());
                 io::stdout().flush()?;
 
@@ -551,13 +1379,13 @@ pub async fn cmd_flash(
                 possible[choice - 1]
             }
         } else {
-            println!("Multiple devices detected:\n");
+            outln!("Multiple devices detected:\n");
             for (i, (port, specific, chip_name, _)) in detected.iter().enumerate() {
                 if let Some(board) = specific {
                     let board_name = board.to_possible_value().unwrap().get_name().to_string();
-                    println!("  [{}] {} - {}", i + 1, port, board_name);
+                    outln!("  [{}] {} - {}", i + 1, port, board_name);
                 } else {
-                    println!("  [{}] {} - {}", i + 1, port, chip_name);
+                    outln!("  [{}] {} - {}", i + 1, port, chip_name);
                 }
             }
             bail!(
@@ -576,118 +1404,24 @@ pub async fn cmd_flash(
         None
     };
 
-    // Map board type to PlatformIO environment name
-    let (env_name, board_name) = match board {
-        // Heltec ESP32-S3
-        BoardType::HeltecV3 => ("heltec_v3", "Heltec V3"),
-        BoardType::HeltecV4 => ("heltec_v4", "Heltec V4"),
-        BoardType::HeltecWirelessStickLiteV3 => (
-            "heltec_wireless_stick_lite_v3",
-            "Heltec Wireless Stick Lite V3",
-        ),
-        BoardType::HeltecWirelessTracker => ("heltec_wireless_tracker", "Heltec Wireless Tracker"),
-        BoardType::HeltecWirelessPaper => ("heltec_wireless_paper", "Heltec Wireless Paper"),
-        BoardType::HeltecVisionMasterT190 => {
-            ("heltec_vision_master_t190", "Heltec Vision Master T190")
-        }
-        BoardType::HeltecVisionMasterE213 => {
-            ("heltec_vision_master_e213", "Heltec Vision Master E213")
-        }
-        BoardType::HeltecVisionMasterE290 => {
-            ("heltec_vision_master_e290", "Heltec Vision Master E290")
-        }
-        BoardType::HeltecHt62 => ("heltec_ht62", "Heltec HT62"),
-        BoardType::HeltecMeshNodeT114 => ("heltec_mesh_node_t114", "Heltec Mesh Node T114"),
-        BoardType::HeltecMeshPocket => ("heltec_mesh_pocket", "Heltec MeshPocket"),
-
-        // LilyGo ESP32-S3
-        BoardType::LilygoT3s3 => ("lilygo_t3s3", "LilyGo T3S3"),
-        BoardType::LilygoT3s3Eink => ("lilygo_t3s3_eink", "LilyGo T3S3 E-Ink"),
-        BoardType::LilygoTbeamSupreme => ("lilygo_tbeam_supreme", "LilyGo T-Beam Supreme"),
-        BoardType::LilygoTdeck => ("lilygo_tdeck", "LilyGo T-Deck"),
-        BoardType::LilygoTdeckPro => ("lilygo_tdeck_pro", "LilyGo T-Deck Pro"),
-        BoardType::LilygoTloraPager => ("lilygo_tlora_pager", "LilyGo T-LoRa Pager"),
-        BoardType::LilygoTwatchS3 => ("lilygo_twatch_s3", "LilyGo T-Watch S3"),
+    let (env_name, board_name) = board_names(board);
 
-        // LilyGo ESP32
-        BoardType::LilygoTbeam => ("lilygo_tbeam", "LilyGo T-Beam"),
-        BoardType::LilygoTloraV2116 => ("lilygo_tlora_v21_16", "LilyGo T-LoRa V2.1-1.6"),
-        BoardType::LilygoTloraV2118 => ("lilygo_tlora_v21_18", "LilyGo T-LoRa V2.1-1.8"),
-
-        // LilyGo nRF52840
-        BoardType::LilygoTecho => ("lilygo_techo", "LilyGo T-Echo"),
-
-        // RAK nRF52840
-        BoardType::Rak4631 => ("rak4631", "RAK4631"),
-        BoardType::RakWismeshRepeater => ("rak_wismesh_repeater", "RAK WisMesh Repeater"),
-        BoardType::RakWismeshTap => ("rak_wismesh_tap", "RAK WisMesh Tap"),
-        BoardType::RakWismeshTag => ("rak_wismesh_tag", "RAK WisMesh Tag"),
-        BoardType::Rak34011w => ("rak3401_1w", "RAK3401 1W"),
-
-        // RAK ESP32/S3
-        BoardType::Rak11200 => ("rak11200", "RAK11200"),
-        BoardType::Rak3312 => ("rak3312", "RAK3312"),
-
-        // RAK RP2040
-        BoardType::Rak11310 => ("rak11310", "RAK11310"),
-
-        // Seeed nRF52840
-        BoardType::SeeedTrackerT1000e => ("seeed_tracker_t1000e", "Seeed Tracker T1000-E"),
-        BoardType::SeeedXiaoNrf52840 => ("seeed_xiao_nrf52840", "Seeed Xiao nRF52840"),
-        BoardType::SeeedSensecapSolar => ("seeed_sensecap_solar", "Seeed SenseCAP Solar"),
-        BoardType::SeeedWioTrackerL1 => ("seeed_wio_tracker_l1", "Seeed Wio Tracker L1"),
-        BoardType::SeeedWioTrackerL1Eink => {
-            ("seeed_wio_tracker_l1_eink", "Seeed Wio Tracker L1 E-Ink")
-        }
-        BoardType::SeeedWioWm1110 => ("seeed_wio_wm1110", "Seeed Wio WM1110"),
-
-        // Seeed ESP32-S3
-        BoardType::SeeedSensecapIndicator => {
-            ("seeed_sensecap_indicator", "Seeed SenseCAP Indicator")
-        }
-        BoardType::SeeedXiaoEsp32s3 => ("seeed_xiao_esp32s3", "Seeed Xiao ESP32-S3"),
-
-        // Elecrow
-        BoardType::ThinknodeM1 => ("thinknode_m1", "ThinkNode M1"),
-        BoardType::ThinknodeM2 => ("thinknode_m2", "ThinkNode M2"),
-        BoardType::ThinknodeM3 => ("thinknode_m3", "ThinkNode M3"),
-        BoardType::ThinknodeM5 => ("thinknode_m5", "ThinkNode M5"),
-        BoardType::Crowpanel24tft => ("crowpanel_24tft", "Crowpanel 2.4/2.8 TFT"),
-        BoardType::Crowpanel35tft => ("crowpanel_35tft", "Crowpanel 3.5 TFT"),
-        BoardType::Crowpanel43tft => ("crowpanel_43tft", "Crowpanel 4.3/5.0/7.0 TFT"),
-
-        // B&Q Consulting
-        BoardType::StationG2 => ("station_g2", "Station G2"),
-        BoardType::StationG1 => ("station_g1", "Station G1"),
-        BoardType::NanoG1 => ("nano_g1", "Nano G1"),
-        BoardType::NanoG1Explorer => ("nano_g1_explorer", "Nano G1 Explorer"),
-        BoardType::NanoG2Ultra => ("nano_g2_ultra", "Nano G2 Ultra"),
-
-        // M5Stack
-        BoardType::M5stack => ("m5stack", "M5 Stack"),
-        BoardType::M5stackUnitC6l => ("m5stack_unit_c6l", "M5Stack Unit C6L"),
-
-        // Other Vendors
-        BoardType::MuziBase => ("muzi_base", "muzi BASE"),
-        BoardType::MuziR1Neo => ("muzi_r1_neo", "muzi R1 Neo"),
-        BoardType::NomadstarMeteorPro => ("nomadstar_meteor_pro", "NomadStar Meteor Pro"),
-        BoardType::CanaryOne => ("canary_one", "Canary One"),
-        BoardType::Radiomaster900Bandit => ("radiomaster_900_bandit", "RadioMaster 900 Bandit"),
-        BoardType::EbyteEoraS3 => ("ebyte_eora_s3", "EByte EoRa-S3"),
-        BoardType::TracksengerSmall => ("tracksenger_small", "TrackSenger Small"),
-        BoardType::TracksengerBig => ("tracksenger_big", "TrackSenger Big"),
-        BoardType::PiComputerS3 => ("pi_computer_s3", "Pi Computer S3"),
-        BoardType::Unphone => ("unphone", "unPhone"),
-
-        // RP2040
-        BoardType::Rp2040Lora => ("rp2040_lora", "RP2040 LoRa"),
-        BoardType::RpiPico => ("rpi_pico", "Raspberry Pi Pico"),
-        BoardType::RpiPicoW => ("rpi_pico_w", "Raspberry Pi Pico W"),
-
-        // DIY
-        BoardType::DiyV1 => ("diy_v1", "DIY V1"),
-        BoardType::Hydra => ("hydra", "Hydra"),
-        BoardType::Nrf52PromicroDiy => ("nrf52_promicro_diy", "nRF52 Pro-micro DIY"),
+    check_port_chip(board, board_name, flash_port.as_deref(), &detected)?;
+    let (target, info) = flash_target(flash_port.as_deref()).await;
+    let running = info.and_then(|i| i.firmware_version);
+    if let (true, Some(running)) = (output::is_plain(), &running) {
+        output::kv("running", running);
+    }
+    if hardware_check {
+        check_hardware(board, flash_port.as_deref(), running.as_deref())?;
+    }
+    if !confirm(Operation::Flash, &target)? {
+        return Ok(());
+    }
+    // Keep monitors and other meshgrid-cli commands off the port meanwhile
+    let _lock = match flash_port.as_deref() {
+        Some(port) => PortLock::acquire(port)?,
+        None => None,
     };
 
     // Determine firmware source
@@ -740,8 +1474,8 @@ pub async fn cmd_flash(
                 // Both available - prompt user
                 use dialoguer::Select;
 
-                println!("\nFound firmware from multiple sources:");
-                println!("Use arrow keys ↑/↓ to select, then press Enter\n");
+                outln!("\nFound firmware from multiple sources:");
+                outln!("Use arrow keys ↑/↓ to select, then press Enter\n");
 
                 let options = vec![
                     format!("Download from GitHub (version {})", gh_version),
@@ -762,12 +1496,16 @@ pub async fn cmd_flash(
             }
             (Some(gh_version), None) => {
                 // Only GitHub available
-                println!("Using firmware from GitHub (version {})", gh_version);
+                if !output::is_plain() {
+                    outln!("Using firmware from GitHub (version {})", gh_version);
+                }
                 FirmwareSource::GitHub(gh_version)
             }
             (None, Some(local_dir)) => {
                 // Only local available
-                println!("Using local firmware from {}", local_dir.display());
+                if !output::is_plain() {
+                    outln!("Using local firmware from {}", local_dir.display());
+                }
                 FirmwareSource::Local(local_dir)
             }
             (None, None) => {
@@ -784,6 +1522,13 @@ pub async fn cmd_flash(
         }
     };
 
+    if output::is_plain() {
+        output::kv("board", env_name);
+        if let Some(p) = &flash_port {
+            output::kv("port", p);
+        }
+    }
+
     // Flash based on firmware source
     match firmware_source {
         FirmwareSource::GitHub(ver) => {
@@ -791,11 +1536,21 @@ pub async fn cmd_flash(
             use crate::firmware::FirmwareManager;
 
             let firmware_manager = FirmwareManager::new()?;
+            let ver = firmware_manager.resolve_version(&ver, offline).await?;
+            check_downgrade(running.as_deref(), &ver, allow_downgrade)?;
             let firmware_path = firmware_manager
                 .get_firmware(env_name, &ver, force_download, offline)
                 .await?;
-
-            flash_precompiled_binary(&firmware_path, flash_port.as_deref(), monitor).await?;
+            check_image_chip(&firmware_path, board, board_name)?;
+
+            flash_precompiled_binary(
+                &firmware_path,
+                flash_port.as_deref(),
+                monitor,
+                incremental,
+                verify,
+            )
+            .await?;
         }
         FirmwareSource::Local(firmware_dir) => {
             // Build and flash with PlatformIO (existing behavior)
@@ -805,7 +1560,14 @@ pub async fn cmd_flash(
                 bail!("No platformio.ini found in {}", firmware_dir.display());
             }
 
-            println!("Flashing {board_name} firmware...\n");
+            if output::is_plain() {
+                output::kv("firmware", firmware_dir.display());
+            } else {
+                if incremental {
+                    outln!("⚠ --incremental only applies to downloaded firmware, PlatformIO writes the full image");
+                }
+                outln!("Flashing {board_name} firmware...\n");
+            }
 
             // Build PlatformIO command
             let mut pio_args = vec!["run", "-e", env_name, "-t", "upload"];
@@ -823,13 +1585,22 @@ pub async fn cmd_flash(
             let status = Command::new("pio")
                 .args(&pio_args)
                 .current_dir(&firmware_dir)
+                .stdout(if monitor {
+                    std::process::Stdio::inherit()
+                } else {
+                    tool_stdout()
+                })
                 .status()?;
 
             if !status.success() {
                 bail!("PlatformIO flash failed. Make sure PlatformIO is installed: pip install platformio");
             }
 
-            println!("\n✓ Flash complete!");
+            if output::is_plain() {
+                output::kv("result", "ok");
+            } else {
+                outln!("\n✓ Flash complete!");
+            }
         }
     }
 
@@ -846,41 +1617,82 @@ pub async fn cmd_auth(port: &str, baud: u32, action: AuthAction) -> Result<()> {
             let command = format!("AUTH {password}");
             match proto.command(&command).await? {
                 Response::Ok(_) => {
-                    println!("✓ Authenticated successfully");
+                    outln!("✓ Authenticated successfully");
                     Ok(())
                 }
-                Response::Error(e) => bail!("Authentication failed: {e}"),
+                Response::Error(e) => bail!(CliError::AuthFailed(e)),
                 Response::Json(_) => bail!("Unexpected response to AUTH"),
             }
         }
         AuthAction::Status => match proto.command("AUTH STATUS").await? {
             Response::Ok(msg) => {
-                println!("{}", msg.unwrap_or_else(|| "No response".to_string()));
+                outln!("{}", msg.unwrap_or_else(|| "No response".to_string()));
                 Ok(())
             }
-            Response::Error(e) => bail!("Failed to get status: {e}"),
+            Response::Error(e) => {
+                bail!(CliError::Device(format!("failed to get auth status: {e}")))
+            }
             Response::Json(_) => bail!("Unexpected response to AUTH STATUS"),
         },
         AuthAction::Enable => match proto.command("AUTH ENABLE").await? {
             Response::Ok(msg) => {
-                println!(
+                outln!(
                     "✓ {}",
                     msg.unwrap_or_else(|| "Serial auth enabled".to_string())
                 );
                 Ok(())
             }
-            Response::Error(e) => bail!("Failed to enable: {e}"),
+            Response::Error(e) => bail!(CliError::Device(format!("failed to enable auth: {e}"))),
             Response::Json(_) => bail!("Unexpected response to AUTH ENABLE"),
         },
+        AuthAction::Save { pin, plaintext } => {
+            let pin = match pin {
+                Some(pin) => pin,
+                None => dialoguer::Password::new()
+                    .with_prompt("Device PIN/password")
+                    .interact()?,
+            };
+            // Only save a PIN the device accepts
+            match proto.command(&format!("AUTH {pin}")).await? {
+                Response::Ok(_) => {}
+                Response::Error(e) => bail!(CliError::AuthFailed(e)),
+                Response::Json(_) => bail!("Unexpected response to AUTH"),
+            }
+            let info = proto.get_info().await?;
+            let public_key = hex::encode(info.public_key);
+            let store = credentials::save(&public_key, info.name.as_deref(), &pin, plaintext)?;
+            if store == Store::File {
+                eprintln!(
+                    "⚠ WARNING: no OS keyring available, the PIN is stored UNENCRYPTED in {store}"
+                );
+                eprintln!("  Anyone who can read that file as your user can unlock this device.");
+            }
+            outln!(
+                "✓ Saved PIN for {} ({}) in {store}",
+                info.name.as_deref().unwrap_or("device"),
+                public_key.get(..8).unwrap_or(&public_key)
+            );
+            Ok(())
+        }
+        AuthAction::Forget => {
+            let info = proto.get_info().await?;
+            let name = info.name.as_deref().unwrap_or("device");
+            if credentials::forget(&hex::encode(info.public_key))? {
+                outln!("✓ Removed saved PIN for {name}");
+            } else {
+                outln!("No saved PIN for {name}");
+            }
+            Ok(())
+        }
         AuthAction::Disable => match proto.command("AUTH DISABLE").await? {
             Response::Ok(msg) => {
-                println!(
+                outln!(
                     "✓ {}",
                     msg.unwrap_or_else(|| "Serial auth disabled".to_string())
                 );
                 Ok(())
             }
-            Response::Error(e) => bail!("Failed to disable: {e}"),
+            Response::Error(e) => bail!(CliError::Device(format!("failed to disable auth: {e}"))),
             Response::Json(_) => bail!("Unexpected response to AUTH DISABLE"),
         },
     }
@@ -894,10 +1706,10 @@ pub async fn cmd_setpass(port: &str, baud: u32, password: &str) -> Result<()> {
     let command = format!("SETPASS {password}");
     match proto.command(&command).await? {
         Response::Ok(msg) => {
-            println!("✓ {}", msg.unwrap_or_else(|| "Password set".to_string()));
+            outln!("✓ {}", msg.unwrap_or_else(|| "Password set".to_string()));
             Ok(())
         }
-        Response::Error(e) => bail!("Failed to set password: {e}"),
+        Response::Error(e) => bail!(CliError::Device(format!("failed to set password: {e}"))),
         Response::Json(_) => bail!("Unexpected response to SETPASS"),
     }
 }
@@ -910,10 +1722,44 @@ pub async fn cmd_setpin(port: &str, baud: u32, pin: &str) -> Result<()> {
     let command = format!("SETPIN {pin}");
     match proto.command(&command).await? {
         Response::Ok(msg) => {
-            println!("✓ {}", msg.unwrap_or_else(|| "BLE PIN set".to_string()));
+            outln!("✓ {}", msg.unwrap_or_else(|| "BLE PIN set".to_string()));
             Ok(())
         }
-        Response::Error(e) => bail!("Failed to set PIN: {e}"),
+        Response::Error(e) => bail!(CliError::Device(format!("failed to set PIN: {e}"))),
         Response::Json(_) => bail!("Unexpected response to SETPIN"),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_debug_filter() {
+        let modules = ["radio".to_string(), "mesh".to_string()];
+        let filter = DebugFilter {
+            level: DebugLevel::Warn,
+            modules: &modules,
+        };
+        assert!(filter.matches("ERROR", Some("Radio")));
+        assert!(filter.matches("warning", Some("mesh")));
+        assert!(!filter.matches("INFO", Some("radio")));
+        assert!(!filter.matches("WARN", Some("gps")));
+        assert!(!filter.matches("WARN", None));
+
+        let json = serde_json::json!({"type": "debug", "msg": "[mesh] dup dropped"});
+        assert_eq!(debug_module(&json, "[mesh] dup dropped"), Some("mesh"));
+        assert_eq!(debug_module(&json, "no module"), None);
+    }
+
+    #[test]
+    fn test_clock_offset() {
+        assert_eq!(clock_offset(125), "+2m 5s");
+        assert_eq!(clock_offset(-40), "-40s");
+        assert_eq!(clock_offset(0), "0s");
+
+        let sent = Utc::now();
+        let offset = offset_at(sent.timestamp() - 300, sent);
+        assert!((-301..=-299).contains(&offset), "{offset}");
+    }
+}
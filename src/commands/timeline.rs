@@ -0,0 +1,248 @@
+//! Device event timeline
+//!
+//! Merges the device's stored log, live debug frames and monitor events
+//! into one stream in host time. Entries stamped with device uptime are
+//! placed using the device's boot time, estimated from the uptime it
+//! reports against the host clock.
+
+use super::connect_with_auth;
+use crate::output;
+use crate::protocol::{uptime_ms, MonitorEvent, Protocol};
+use crate::timefmt;
+use anyhow::Result;
+use chrono::{DateTime, Duration as ChronoDuration, SecondsFormat, Utc};
+use std::time::{Duration, Instant};
+
+/// When an entry happened.
+#[derive(Debug, Clone, Copy)]
+enum Stamp {
+    /// Milliseconds since the device booted
+    Uptime(u64),
+    /// Host time the entry was received
+    Host(DateTime<Utc>),
+}
+
+#[derive(Debug)]
+struct Entry {
+    stamp: Stamp,
+    source: &'static str,
+    level: Option<String>,
+    text: String,
+}
+
+/// Entries collected from the device, placed in time once complete.
+#[derive(Default)]
+struct Timeline {
+    entries: Vec<Entry>,
+    boot: Option<DateTime<Utc>>,
+}
+
+impl Timeline {
+    /// Record that the device reported `uptime_ms` when the host clock read
+    /// `host`. The reply can only arrive after the device sent it, so the
+    /// earliest implied boot time is the closest.
+    fn observe_uptime(&mut self, host: DateTime<Utc>, uptime_ms: u64) {
+        let boot = host - ChronoDuration::milliseconds(i64::try_from(uptime_ms).unwrap_or(0));
+        self.boot = Some(self.boot.map_or(boot, |b| b.min(boot)));
+    }
+
+    fn push(&mut self, stamp: Stamp, source: &'static str, level: Option<&str>, text: String) {
+        self.entries.push(Entry {
+            stamp,
+            source,
+            level: level.map(str::to_string),
+            text,
+        });
+    }
+
+    /// Host time of an entry, unknown for uptime stamps without a boot estimate.
+    fn time(&self, stamp: Stamp) -> Option<DateTime<Utc>> {
+        match stamp {
+            Stamp::Host(time) => Some(time),
+            Stamp::Uptime(ms) => self
+                .boot
+                .map(|boot| boot + ChronoDuration::milliseconds(i64::try_from(ms).unwrap_or(0))),
+        }
+    }
+
+    /// Entries in chronological order, those of unknown time first.
+    fn sorted(self) -> Vec<(Option<DateTime<Utc>>, Entry)> {
+        let times: Vec<_> = self.entries.iter().map(|e| self.time(e.stamp)).collect();
+        let mut entries: Vec<_> = times.into_iter().zip(self.entries).collect();
+        entries.sort_by_key(|(time, _)| *time);
+        entries
+    }
+}
+
+fn describe(event: &MonitorEvent) -> (&'static str, String) {
+    match event {
+        MonitorEvent::Message {
+            from,
+            to,
+            channel,
+            rssi,
+            text,
+        } => (
+            "INFO",
+            format!(
+                "{from} -> {} ({rssi}dB): {text}",
+                to.as_deref().or(channel.as_deref()).unwrap_or("all")
+            ),
+        ),
+        MonitorEvent::Advertisement {
+            node_hash,
+            rssi,
+            name,
+        } => (
+            "INFO",
+            format!(
+                "ADV {} ({rssi}dB)",
+                name.clone().unwrap_or_else(|| format!("0x{node_hash:02x}"))
+            ),
+        ),
+        MonitorEvent::Ack { from } => ("INFO", format!("ACK from {from}")),
+        MonitorEvent::Waypoint {
+            from,
+            name,
+            lat,
+            lon,
+            ..
+        } => (
+            "INFO",
+            format!("WPT {name} at {lat:.5}, {lon:.5} from {from}"),
+        ),
+        MonitorEvent::Error { message } => ("ERROR", format!("ERR {message}")),
+    }
+}
+
+/// When the device booted, from the uptime it reports against the host clock.
+pub(super) async fn boot_time(proto: &mut Protocol) -> Result<Option<DateTime<Utc>>> {
+    let asked = Utc::now();
+    let Some(device) = proto.get_telemetry().await?.device else {
+        return Ok(None);
+    };
+    let answered = Utc::now();
+    let uptime = ChronoDuration::seconds(i64::from(device.uptime_secs));
+    Ok(Some(asked + (answered - asked) / 2 - uptime))
+}
+
+/// Merge the device log, debug frames and monitor events into one timeline
+pub async fn cmd_timeline(
+    port: &str,
+    baud: u32,
+    pin: Option<&str>,
+    duration_secs: u64,
+    json_output: bool,
+) -> Result<()> {
+    let mut proto = connect_with_auth(port, baud, pin).await?.into_protocol();
+    let mut timeline = Timeline {
+        boot: boot_time(&mut proto).await?,
+        ..Timeline::default()
+    };
+
+    // Stored log, if the firmware keeps one
+    match proto.get_log().await {
+        Ok(log) => {
+            for line in log.lines {
+                if let Some(ms) = line.uptime_ms {
+                    timeline.push(Stamp::Uptime(ms), "log", line.level.as_deref(), line.msg);
+                }
+            }
+        }
+        Err(e) => {
+            tracing::debug!("No stored log: {e:#}");
+            if !output::is_plain() {
+                eprintln!("⚠ Device has no stored log; showing live entries only");
+            }
+        }
+    }
+
+    proto.enter_monitor_mode().await?;
+    if !output::is_plain() && !json_output {
+        eprintln!("Collecting debug frames and events for {duration_secs}s...\n");
+    }
+    let duration = Duration::from_secs(duration_secs);
+    let start = Instant::now();
+    while start.elapsed() < duration {
+        let remaining = duration.saturating_sub(start.elapsed());
+        let Some(frame) = proto
+            .read_frame(remaining.min(Duration::from_millis(500)))
+            .await?
+        else {
+            continue;
+        };
+        let received = Utc::now();
+        if let Ok(json) = serde_json::from_str::<serde_json::Value>(&frame) {
+            if json.get("type").and_then(|v| v.as_str()) == Some("debug") {
+                let msg = json.get("msg").and_then(|v| v.as_str()).unwrap_or_default();
+                let level = json.get("level").and_then(|v| v.as_str());
+                let stamp = match uptime_ms(&json) {
+                    Some(ms) => {
+                        timeline.observe_uptime(received, ms);
+                        Stamp::Uptime(ms)
+                    }
+                    None => Stamp::Host(received),
+                };
+                timeline.push(stamp, "debug", level, msg.to_string());
+            }
+        } else if let Some(event) = MonitorEvent::parse(&frame) {
+            let (level, text) = describe(&event);
+            timeline.push(Stamp::Host(received), "event", Some(level), text);
+        }
+    }
+
+    for (time, entry) in timeline.sorted() {
+        if json_output {
+            let line = serde_json::json!({
+                "time": time.map(|t| t.to_rfc3339_opts(SecondsFormat::Millis, true)),
+                "source": entry.source,
+                "level": entry.level,
+                "msg": entry.text,
+            });
+            println!("{line}");
+        } else {
+            let time = time.map_or_else(
+                || "--:--:--.---".to_string(),
+                |t| timefmt::format(&t, "%H:%M:%S%.3f"),
+            );
+            let level = entry.level.as_deref().unwrap_or("-");
+            println!("{time}  {:<5}  {level:<5}  {}", entry.source, entry.text);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timeline_order() {
+        let boot = DateTime::parse_from_rfc3339("2026-01-12T15:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let mut timeline = Timeline::default();
+        // Telemetry said 60s up, answered 400ms late; a debug frame is tighter
+        timeline.observe_uptime(boot + ChronoDuration::milliseconds(60_400), 60_000);
+        timeline.observe_uptime(boot + ChronoDuration::milliseconds(61_010), 61_000);
+        assert_eq!(timeline.boot, Some(boot + ChronoDuration::milliseconds(10)));
+
+        let at = |ms| boot + ChronoDuration::milliseconds(ms);
+        timeline.push(Stamp::Host(at(62_000)), "event", None, "ADV bob".into());
+        timeline.push(Stamp::Uptime(61_500), "debug", None, "tx".into());
+        timeline.push(Stamp::Uptime(30_000), "log", None, "boot".into());
+        let order: Vec<_> = timeline
+            .sorted()
+            .into_iter()
+            .map(|(time, e)| (time, e.text))
+            .collect();
+        assert_eq!(
+            order,
+            [
+                (Some(at(30_010)), "boot".to_string()),
+                (Some(at(61_510)), "tx".to_string()),
+                (Some(at(62_000)), "ADV bob".to_string()),
+            ]
+        );
+    }
+}
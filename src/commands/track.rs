@@ -0,0 +1,272 @@
+//! Live tracking of remote trackers
+
+use super::connect_with_auth;
+use super::contacts::resolve_node;
+use super::position::{own_position, validate};
+use crate::cli::TrackAction;
+use crate::error::CliError;
+use crate::geo;
+use crate::gpx::{self, GpxWriter};
+use crate::output;
+use crate::protocol::RemotePosition;
+use crate::timefmt;
+use anyhow::{Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::io::{IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+/// Movement below this between fixes is GPS jitter, not a heading.
+const MIN_MOVE_M: f64 = 5.0;
+
+/// A tracker fix, placed in host time.
+#[derive(Debug, Clone, Copy)]
+struct TrackFix {
+    lat: f64,
+    lon: f64,
+    alt: Option<f64>,
+    time: DateTime<Utc>,
+    speed_m_s: Option<f64>,
+    heading_deg: Option<f64>,
+}
+
+impl TrackFix {
+    /// The fix in a reply received at `received`, with speed and heading
+    /// worked out from the previous fix when the tracker doesn't send them.
+    fn new(position: &RemotePosition, received: DateTime<Utc>, previous: Option<&Self>) -> Self {
+        let age = i64::try_from(position.age_secs.unwrap_or(0)).unwrap_or(0);
+        let mut fix = Self {
+            lat: position.lat,
+            lon: position.lon,
+            alt: position.alt,
+            time: received - chrono::Duration::seconds(age),
+            speed_m_s: position.speed_m_s,
+            heading_deg: position.heading_deg,
+        };
+        if let Some(previous) = previous {
+            let from = (previous.lat, previous.lon);
+            let moved = geo::distance_m(from, (fix.lat, fix.lon));
+            let secs = (fix.time - previous.time).num_milliseconds() as f64 / 1000.0;
+            if fix.speed_m_s.is_none() && secs >= 1.0 {
+                fix.speed_m_s = Some(moved / secs);
+            }
+            if fix.heading_deg.is_none() && moved >= MIN_MOVE_M {
+                fix.heading_deg = Some(geo::bearing_deg(from, (fix.lat, fix.lon)));
+            }
+        }
+        fix
+    }
+
+    /// Whether this is the fix already seen, reported again.
+    fn same_as(&self, other: &Self) -> bool {
+        (self.lat, self.lon) == (other.lat, other.lon)
+            && (self.time - other.time).num_seconds().abs() <= 2
+    }
+}
+
+/// Follow remote trackers
+pub async fn cmd_track(
+    port: &str,
+    baud: u32,
+    pin: Option<&str>,
+    action: TrackAction,
+) -> Result<()> {
+    match action {
+        TrackAction::Follow {
+            node,
+            interval,
+            from,
+            gpx: gpx_file,
+            refresh,
+        } => {
+            let reference = from.as_deref().map(parse_point).transpose()?;
+            let mut proto = connect_with_auth(port, baud, pin).await?.into_protocol();
+            let (target, label) = match resolve_node(&mut proto, port, &node, refresh).await {
+                Some(found) => (format!("0x{:02x}", found.node_hash), found.label()),
+                None => (node.clone(), node.clone()),
+            };
+            let reference = match reference {
+                Some(point) => Some((point, "reference".to_string())),
+                None => {
+                    let name = proto.get_info().await?.name;
+                    own_position(&mut proto).await?.map(|(lat, lon, _)| {
+                        ((lat, lon), name.unwrap_or_else(|| "this device".into()))
+                    })
+                }
+            };
+            let mut gpx = gpx_file
+                .as_deref()
+                .map(|path| GpxWriter::open(path, &label))
+                .transpose()?;
+
+            let live = std::io::stdout().is_terminal() && !output::is_plain();
+            if !output::is_plain() {
+                println!(
+                    "Following {label}, asking for its position every {interval}s (Ctrl+C to stop)..."
+                );
+                if let Some(path) = &gpx_file {
+                    println!("Recording the track to {path}");
+                }
+            }
+
+            let interval = Duration::from_secs(interval.max(1));
+            let mut next_request = Instant::now();
+            let mut last: Option<TrackFix> = None;
+            let mut problem: Option<String> = None;
+            loop {
+                if Instant::now() >= next_request {
+                    next_request = Instant::now() + interval;
+                    if let Err(e) = proto.send_position_request(&target).await {
+                        problem = Some(device_error(e)?);
+                    }
+                }
+
+                match proto.read_position_response(Duration::from_secs(1)).await {
+                    Ok(Some(position)) => {
+                        let fix = TrackFix::new(&position, Utc::now(), last.as_ref());
+                        problem = None;
+                        if last.as_ref().is_none_or(|l| !fix.same_as(l)) {
+                            if let Some(gpx) = &mut gpx {
+                                gpx.push(&gpx::Point {
+                                    lat: fix.lat,
+                                    lon: fix.lon,
+                                    alt: fix.alt,
+                                    time: fix.time,
+                                })?;
+                            }
+                            let distance = reference
+                                .as_ref()
+                                .map(|(point, _)| geo::distance_m(*point, (fix.lat, fix.lon)));
+                            if output::is_plain() {
+                                print_fix_kv(&fix, distance);
+                            } else if !live {
+                                println!("{}", status_line(&label, &fix, reference.as_ref()));
+                            }
+                            last = Some(fix);
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        let message = device_error(e)?;
+                        if !live {
+                            eprintln!("✗ {message}");
+                        }
+                        problem = Some(message);
+                    }
+                }
+
+                if live {
+                    let mut line = match &last {
+                        Some(fix) => status_line(&label, fix, reference.as_ref()),
+                        None => format!("{label}: waiting for a position..."),
+                    };
+                    if let Some(problem) = &problem {
+                        line.push_str(&format!("  ✗ {problem}"));
+                    }
+                    print!("\r\x1B[2K{line}");
+                    std::io::stdout().flush()?;
+                }
+            }
+        }
+    }
+}
+
+/// Message of a failed request; anything but a device error (e.g. a lost
+/// connection) ends tracking.
+fn device_error(e: anyhow::Error) -> Result<String> {
+    match e.downcast_ref::<CliError>() {
+        Some(CliError::Device(message)) => Ok(message.clone()),
+        _ => Err(e),
+    }
+}
+
+fn status_line(label: &str, fix: &TrackFix, reference: Option<&((f64, f64), String)>) -> String {
+    let mut parts = vec![format!("{:.5}, {:.5}", fix.lat, fix.lon)];
+    if let Some(speed) = fix.speed_m_s {
+        parts.push(format!("{:.1} km/h", speed * 3.6));
+    }
+    if let Some(heading) = fix.heading_deg {
+        parts.push(format!("{heading:03.0}° {}", geo::compass(heading)));
+    }
+    if let Some((point, name)) = reference {
+        let distance = geo::distance_m(*point, (fix.lat, fix.lon));
+        parts.push(format!("{} from {name}", geo::format_distance(distance)));
+    }
+    let age = u64::try_from((Utc::now() - fix.time).num_seconds()).unwrap_or(0);
+    parts.push(format!("updated {}", timefmt::ago(age)));
+    format!("{label}: {}", parts.join(" · "))
+}
+
+fn print_fix_kv(fix: &TrackFix, distance: Option<f64>) {
+    output::kv("time", fix.time.to_rfc3339_opts(SecondsFormat::Secs, true));
+    output::kv("lat", fix.lat);
+    output::kv("lon", fix.lon);
+    if let Some(alt) = fix.alt {
+        output::kv("alt", alt);
+    }
+    if let Some(speed) = fix.speed_m_s {
+        output::kv("speed_m_s", format!("{speed:.2}"));
+    }
+    if let Some(heading) = fix.heading_deg {
+        output::kv("heading_deg", format!("{heading:.0}"));
+    }
+    if let Some(distance) = distance {
+        output::kv("distance_m", format!("{distance:.0}"));
+    }
+    println!();
+}
+
+/// `LAT,LON` in decimal degrees.
+fn parse_point(s: &str) -> Result<(f64, f64)> {
+    let invalid = || CliError::InvalidArgs(format!("Invalid point '{s}' (use LAT,LON)"));
+    let (lat, lon) = s.split_once(',').ok_or_else(invalid)?;
+    let lat: f64 = lat.trim().parse().map_err(|_| invalid())?;
+    let lon: f64 = lon.trim().parse().map_err(|_| invalid())?;
+    validate(lat, lon).context("Invalid --from point")?;
+    Ok((lat, lon))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_track_fix_motion() {
+        let start = DateTime::parse_from_rfc3339("2026-01-12T15:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let position = |lat, age| RemotePosition {
+            lat,
+            lon: 8.5,
+            alt: None,
+            age_secs: Some(age),
+            accuracy_m: None,
+            satellites: None,
+            speed_m_s: None,
+            heading_deg: None,
+        };
+
+        let first = TrackFix::new(&position(47.0, 0), start, None);
+        assert_eq!((first.speed_m_s, first.heading_deg), (None, None));
+
+        // 1853 m north in 10 minutes, received 60s after the fix
+        let second = TrackFix::new(
+            &position(47.0 + 1.0 / 60.0, 60),
+            start + chrono::Duration::seconds(660),
+            Some(&first),
+        );
+        assert!((second.speed_m_s.unwrap() - 1853.0 / 600.0).abs() < 0.01);
+        assert!(second.heading_deg.unwrap().abs() < 0.01);
+
+        // The same fix again, a little older
+        let again = TrackFix::new(
+            &position(47.0 + 1.0 / 60.0, 75),
+            start + chrono::Duration::seconds(676),
+            Some(&second),
+        );
+        assert!(again.same_as(&second));
+
+        assert_eq!(parse_point("-33.86, 151.21").unwrap(), (-33.86, 151.21));
+        assert!(parse_point("47.1").is_err());
+        assert!(parse_point("95,1").is_err());
+    }
+}
@@ -0,0 +1,257 @@
+//! Update checks and CLI self-update
+//!
+//! `update` compares the CLI and the connected device's firmware with the
+//! latest GitHub releases. Release binaries are published as
+//! `meshgrid-cli-<arch>-<os>` with a `.sig` asset holding a signature line
+//! in the provisioning bundle format over the binary; `update --self` only
+//! installs a binary signed by a trusted release key.
+
+use super::connect_with_auth;
+use super::util::confirm;
+use crate::error::CliError;
+use crate::firmware::{parse_version, FirmwareManager, Release};
+use crate::output;
+use crate::provision::verify_detached;
+use crate::settings::Settings;
+use anyhow::{bail, Context, Result};
+use std::path::Path;
+
+/// Release signing key built into official binaries.
+const RELEASE_KEY: Option<&str> = option_env!("MESHGRID_RELEASE_KEY");
+
+const CURRENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Release asset holding the binary for this platform.
+fn asset_name() -> String {
+    format!(
+        "meshgrid-cli-{}-{}{}",
+        std::env::consts::ARCH,
+        std::env::consts::OS,
+        std::env::consts::EXE_SUFFIX
+    )
+}
+
+fn is_newer(latest: &str, current: &str) -> bool {
+    matches!(
+        (parse_version(latest), parse_version(current)),
+        (Some(latest), Some(current)) if latest > current
+    )
+}
+
+/// Check for newer CLI and firmware releases, or with `self_update`
+/// replace the CLI binary with the latest signed release
+pub async fn cmd_update(
+    port: Option<&str>,
+    baud: u32,
+    pin: Option<&str>,
+    check: bool,
+    self_update: bool,
+    trusted_key: Vec<String>,
+) -> Result<()> {
+    let plain = output::is_plain();
+    let manager = FirmwareManager::new()?;
+    if self_update {
+        return install_latest(&manager, trusted_key).await;
+    }
+
+    let cli = manager.fetch_cli_release().await?;
+    let cli_newer = is_newer(&cli.tag_name, CURRENT_VERSION);
+    let firmware = manager.fetch_release("latest").await.map(|r| r.tag_name);
+    let port = match port {
+        Some(p) => Some(p.to_string()),
+        None => crate::serial::detect_device()?,
+    };
+    let running = match &port {
+        Some(port) => match connect_with_auth(port, baud, pin).await {
+            Ok(mut dev) => dev.get_info().await.ok().and_then(|i| i.firmware_version),
+            Err(_) => None,
+        },
+        None => None,
+    };
+
+    if plain {
+        output::kv("cli_version", CURRENT_VERSION);
+        output::kv("cli_latest", &cli.tag_name);
+        if let Ok(latest) = &firmware {
+            output::kv("firmware_latest", latest);
+        }
+        if let (Some(port), Some(running)) = (&port, &running) {
+            output::kv("port", port);
+            output::kv("firmware_version", running);
+        }
+    } else {
+        if cli_newer {
+            println!(
+                "⚠ meshgrid-cli {} is available (running {CURRENT_VERSION}): meshgrid-cli update --self",
+                cli.tag_name
+            );
+        } else {
+            println!("✓ meshgrid-cli {CURRENT_VERSION} is up to date");
+        }
+        match (&firmware, &port, &running) {
+            (Err(e), ..) => println!("✗ Could not check firmware releases: {e:#}"),
+            (Ok(latest), Some(port), Some(running)) if is_newer(latest, running) => println!(
+                "⚠ Firmware {latest} is available (the device on {port} runs {running}): \
+                 meshgrid-cli flash --version {latest}"
+            ),
+            (Ok(_), Some(port), Some(running)) => {
+                println!("✓ Firmware {running} on {port} is up to date");
+            }
+            (Ok(latest), Some(port), None) => {
+                println!("Latest firmware: {latest} (could not read the version on {port})");
+            }
+            (Ok(latest), None, _) => println!("Latest firmware: {latest} (no device connected)"),
+        }
+    }
+
+    if cli_newer
+        && !check
+        && std::io::IsTerminal::is_terminal(&std::io::stdin())
+        && confirm(
+            &format!("Update meshgrid-cli to {} now?", cli.tag_name),
+            false,
+        )?
+    {
+        return install(&manager, &cli, trusted_key).await;
+    }
+    Ok(())
+}
+
+async fn install_latest(manager: &FirmwareManager, trusted_key: Vec<String>) -> Result<()> {
+    let release = manager.fetch_cli_release().await?;
+    if !is_newer(&release.tag_name, CURRENT_VERSION) {
+        if output::is_plain() {
+            output::kv("result", "up-to-date");
+        } else {
+            println!("✓ meshgrid-cli {CURRENT_VERSION} is the latest release");
+        }
+        return Ok(());
+    }
+    install(manager, &release, trusted_key).await
+}
+
+/// Download, verify and install `release` in place of the running binary.
+async fn install(
+    manager: &FirmwareManager,
+    release: &Release,
+    trusted_key: Vec<String>,
+) -> Result<()> {
+    let mut keys: Vec<String> = RELEASE_KEY.map(String::from).into_iter().collect();
+    keys.extend(Settings::load()?.update.trusted_keys);
+    keys.extend(trusted_key);
+    if keys.is_empty() {
+        bail!(CliError::InvalidArgs(format!(
+            "This build has no release key. Add it to [update] trusted_keys in {} or pass --trusted-key",
+            Settings::path()?.display()
+        )));
+    }
+
+    let name = asset_name();
+    let asset = |name: &str| {
+        release
+            .assets
+            .iter()
+            .find(|a| a.name == name)
+            .with_context(|| format!("Release {} has no {name}", release.tag_name))
+    };
+    let binary = asset(&name)?;
+    let signature = asset(&format!("{name}.sig"))?;
+
+    let exe = std::env::current_exe()?;
+    let dir = exe.parent().context("Cannot locate the running binary")?;
+    let new = dir.join(format!(".{name}.new"));
+    let sig = dir.join(format!(".{name}.sig"));
+    if !output::is_plain() {
+        println!("Downloading meshgrid-cli {}...", release.tag_name);
+    }
+    let result = async {
+        manager
+            .download_file(&binary.browser_download_url, &new)
+            .await?;
+        manager
+            .download_file(&signature.browser_download_url, &sig)
+            .await?;
+        let signer = verify_detached(
+            &std::fs::read(&new)?,
+            &std::fs::read_to_string(&sig)?,
+            &keys,
+        )
+        .with_context(|| format!("Refusing to install {name}"))?;
+        replace_exe(&exe, &new)?;
+        Ok::<_, anyhow::Error>(signer)
+    }
+    .await;
+    let _ = std::fs::remove_file(&sig);
+    if result.is_err() {
+        let _ = std::fs::remove_file(&new);
+    }
+    let signer = result?;
+
+    if output::is_plain() {
+        output::kv("version", &release.tag_name);
+        output::kv("signer", &signer);
+        output::kv("result", "updated");
+    } else {
+        println!("✓ Signature OK (key {signer})");
+        println!(
+            "✓ Updated meshgrid-cli {CURRENT_VERSION} → {}",
+            release.tag_name
+        );
+    }
+    Ok(())
+}
+
+/// Put `new` in place of `exe`, keeping `exe` if that fails.
+fn replace_exe(exe: &Path, new: &Path) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(new, std::fs::Permissions::from_mode(0o755))?;
+    }
+    // A running binary can be renamed but not overwritten on Windows
+    let old = exe.with_extension("old");
+    std::fs::rename(exe, &old).with_context(|| {
+        format!(
+            "Failed to replace {} (reinstall it with the tool it came from, or run with write access)",
+            exe.display()
+        )
+    })?;
+    if let Err(e) = std::fs::rename(new, exe) {
+        let _ = std::fs::rename(&old, exe);
+        return Err(e).with_context(|| format!("Failed to replace {}", exe.display()));
+    }
+    // Still in use on Windows; removed by the next update
+    let _ = std::fs::remove_file(&old);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::{engine::general_purpose, Engine as _};
+    use ed25519_dalek::{Signer, SigningKey};
+
+    #[test]
+    fn test_release_signature() {
+        assert!(is_newer("v0.0.2", "0.0.1"));
+        assert!(!is_newer("0.0.1", "0.0.1"));
+        assert!(!is_newer("nightly", "0.0.1"));
+        assert!(asset_name().starts_with("meshgrid-cli-"));
+
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let public_key = general_purpose::STANDARD.encode(key.verifying_key().to_bytes());
+        let binary = b"\x7fELF release binary";
+        let signature = key.sign(binary);
+        let line = format!(
+            "# meshgrid-signature ed25519 {public_key} {}\n",
+            general_purpose::STANDARD.encode(signature.to_bytes())
+        );
+        let trusted = [public_key];
+        assert_eq!(
+            verify_detached(binary, &line, &trusted).unwrap(),
+            trusted[0]
+        );
+        assert!(verify_detached(b"tampered", &line, &trusted).is_err());
+        assert!(verify_detached(binary, &line, &["other".into()]).is_err());
+    }
+}
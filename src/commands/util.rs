@@ -1,12 +1,74 @@
 //! Utility commands
 
+use crate::error::CliError;
+use crate::output;
+use crate::serial::UsbChip;
 use anyhow::Result;
+use serde::Serialize;
+
+/// A serial port in `ports --output json|yaml`.
+#[derive(Serialize)]
+struct PortEntry {
+    name: String,
+    /// usb, pci, bluetooth or unknown
+    kind: &'static str,
+    vid: Option<String>,
+    pid: Option<String>,
+    manufacturer: Option<String>,
+    product: Option<String>,
+    serial_number: Option<String>,
+    /// USB serial chip of a supported board
+    chip: Option<&'static str>,
+}
+
+impl From<serialport::SerialPortInfo> for PortEntry {
+    fn from(port: serialport::SerialPortInfo) -> Self {
+        use serialport::SerialPortType;
+        let mut entry = Self {
+            name: port.port_name,
+            kind: "unknown",
+            vid: None,
+            pid: None,
+            manufacturer: None,
+            product: None,
+            serial_number: None,
+            chip: None,
+        };
+        match port.port_type {
+            SerialPortType::UsbPort(info) => {
+                entry.kind = "usb";
+                entry.chip = UsbChip::from_ids(info.vid, info.pid).map(UsbChip::description);
+                entry.vid = Some(format!("{:04x}", info.vid));
+                entry.pid = Some(format!("{:04x}", info.pid));
+                entry.manufacturer = info.manufacturer;
+                entry.product = info.product.map(|p| friendly_product(&p, &entry.name));
+                entry.serial_number = info.serial_number;
+            }
+            SerialPortType::PciPort => entry.kind = "pci",
+            SerialPortType::BluetoothPort => entry.kind = "bluetooth",
+            SerialPortType::Unknown => {}
+        }
+        entry
+    }
+}
+
+/// Product name without the ` (COM12)` Windows appends to it.
+fn friendly_product(product: &str, port_name: &str) -> String {
+    product
+        .strip_suffix(&format!(" ({port_name})"))
+        .unwrap_or(product)
+        .to_string()
+}
 
 /// List available serial ports
 pub fn cmd_list_ports() -> Result<()> {
-    println!("Available serial ports:\n");
-
     let ports = serialport::available_ports()?;
+    if output::is_structured() {
+        let ports: Vec<PortEntry> = ports.into_iter().map(PortEntry::from).collect();
+        return output::print(&ports);
+    }
+
+    println!("Available serial ports:\n");
 
     if ports.is_empty() {
         println!("  No serial ports found");
@@ -22,9 +84,12 @@ pub fn cmd_list_ports() -> Result<()> {
                 print!(" - {manufacturer}");
             }
             if let Some(product) = info.product {
-                print!(" {product}");
+                print!(" {}", friendly_product(&product, &port.port_name));
             }
             print!(")");
+            if let Some(chip) = UsbChip::from_ids(info.vid, info.pid) {
+                print!(" [{}]", chip.description());
+            }
         }
 
         println!();
@@ -33,19 +98,51 @@ pub fn cmd_list_ports() -> Result<()> {
     Ok(())
 }
 
+/// Put stdin back into blocking mode for commands that read it line by line.
+///
+/// `main()` makes a non-terminal stdin non-blocking to keep it from stalling
+/// the runtime.
+pub fn restore_blocking_stdin() {
+    #[cfg(unix)]
+    unsafe {
+        use std::os::unix::io::AsRawFd;
+        let fd = std::io::stdin().as_raw_fd();
+        let flags = libc::fcntl(fd, libc::F_GETFL, 0);
+        if flags >= 0 && (flags & libc::O_NONBLOCK) != 0 {
+            let _ = libc::fcntl(fd, libc::F_SETFL, flags & !libc::O_NONBLOCK);
+        }
+    }
+}
+
+/// Ask before a destructive step, unless `yes` was given.
+pub fn confirm(prompt: &str, yes: bool) -> Result<bool> {
+    if yes {
+        return Ok(true);
+    }
+    if !std::io::IsTerminal::is_terminal(&std::io::stdin()) {
+        anyhow::bail!(CliError::InvalidArgs(
+            "Confirmation needs a terminal; pass --yes to go ahead".into()
+        ));
+    }
+    Ok(dialoguer::Confirm::new()
+        .with_prompt(prompt)
+        .default(false)
+        .interact()?)
+}
+
 /// Require port or auto-detect
 pub fn require_port(port: Option<&String>) -> Result<String> {
     if let Some(p) = port {
-        return Ok(p.clone());
+        return Ok(crate::serial::normalize_port(p));
     }
 
     // Try auto-detection
     if let Some(detected) = crate::serial::detect_device()? {
-        println!("Auto-detected device: {detected}");
+        eprintln!("Auto-detected device: {detected}");
         return Ok(detected);
     }
 
-    anyhow::bail!(
-        "No port specified and no device auto-detected.\nUse -p /dev/ttyUSB0 (Linux) or -p COM3 (Windows) or run 'meshgrid-cli ports' to list available ports"
-    )
+    anyhow::bail!(CliError::PortNotFound(
+        "No port specified and no device auto-detected.\nUse -p /dev/ttyUSB0 (Linux) or -p COM3 (Windows) or run 'meshgrid-cli ports' to list available ports".into()
+    ))
 }
@@ -0,0 +1,94 @@
+//! Encryption of local data at rest
+
+use crate::cli::VaultAction;
+use crate::error::CliError;
+use crate::vault;
+use anyhow::{bail, Context, Result};
+use std::path::PathBuf;
+
+/// Encrypt or decrypt local data files with a passphrase
+pub fn cmd_vault(action: VaultAction) -> Result<()> {
+    match action {
+        VaultAction::Lock { files } => {
+            let paths: Vec<PathBuf> = targets(files)?
+                .into_iter()
+                .filter(|path| !vault::is_locked(path))
+                .collect();
+            if paths.is_empty() {
+                println!("Nothing to lock.");
+                return Ok(());
+            }
+            let passphrase = passphrase(true)?;
+            for path in &paths {
+                let data = std::fs::read(path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+                vault::write_file(path, &vault::encrypt(&data, &passphrase)?)?;
+                println!("  ✓ Locked {}", path.display());
+            }
+            println!("✓ Locked {} file(s)", paths.len());
+        }
+        VaultAction::Unlock { files } => {
+            let paths: Vec<PathBuf> = targets(files)?
+                .into_iter()
+                .filter(|path| vault::is_locked(path))
+                .collect();
+            if paths.is_empty() {
+                println!("Nothing to unlock.");
+                return Ok(());
+            }
+            let passphrase = passphrase(false)?;
+            // Decrypt everything before writing, so a wrong passphrase changes nothing
+            let mut plain = Vec::new();
+            for path in &paths {
+                let data = std::fs::read(path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+                let data = vault::decrypt(&data, &passphrase)
+                    .with_context(|| format!("Failed to decrypt {}", path.display()))?;
+                plain.push(data);
+            }
+            for (path, data) in paths.iter().zip(plain) {
+                vault::write_file(path, &data)?;
+                println!("  ✓ Unlocked {}", path.display());
+            }
+            println!("✓ Unlocked {} file(s)", paths.len());
+        }
+    }
+
+    Ok(())
+}
+
+/// Files named on the command line, or the existing local data files.
+fn targets(files: Vec<String>) -> Result<Vec<PathBuf>> {
+    if files.is_empty() {
+        return Ok(vault::data_files()?
+            .into_iter()
+            .filter(|path| path.exists())
+            .collect());
+    }
+    files
+        .into_iter()
+        .map(|file| {
+            let path = PathBuf::from(&file);
+            if !path.is_file() {
+                bail!(CliError::InvalidArgs(format!("No such file: {file}")));
+            }
+            Ok(path)
+        })
+        .collect()
+}
+
+/// Passphrase from `MESHGRID_VAULT_PASSPHRASE` or a prompt.
+fn passphrase(confirm: bool) -> Result<String> {
+    if let Ok(passphrase) = std::env::var("MESHGRID_VAULT_PASSPHRASE") {
+        return Ok(passphrase);
+    }
+    let mut prompt = dialoguer::Password::new().with_prompt("Vault passphrase");
+    if confirm {
+        prompt = prompt.with_confirmation("Repeat passphrase", "Passphrases do not match");
+    }
+    let passphrase = prompt.interact()?;
+    if passphrase.is_empty() {
+        bail!(CliError::InvalidArgs("Passphrase must not be empty".into()));
+    }
+    Ok(passphrase)
+}
@@ -0,0 +1,328 @@
+//! Watchdog command: alert when a node stops being heard
+
+use super::bridge::detect_webhook_format;
+use super::connect_with_auth;
+use super::contacts::resolve_node;
+use super::messaging::notify_desktop;
+use crate::cli::WebhookFormat;
+use crate::error::CliError;
+use crate::hooks;
+use crate::protocol::{MonitorEvent, Protocol};
+use crate::timefmt;
+use anyhow::{bail, Result};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+/// How often the attached device is pinged, at most.
+const PING_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Longest wait before reconnecting to the attached device.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// A node the watchdog expects to hear from.
+struct Watched {
+    label: String,
+    /// `None` for the attached device, which is pinged instead
+    hash: Option<u8>,
+    last_heard: Instant,
+    silent: bool,
+}
+
+impl Watched {
+    /// Whether `event` came from this node.
+    fn heard_in(&self, event: &MonitorEvent) -> bool {
+        let Some(hash) = self.hash else {
+            return false;
+        };
+        let from = match event {
+            MonitorEvent::Advertisement { node_hash, .. } => return *node_hash == hash,
+            MonitorEvent::Message { from, .. }
+            | MonitorEvent::Ack { from }
+            | MonitorEvent::Waypoint { from, .. } => from,
+            MonitorEvent::Error { .. } => return false,
+        };
+        from.eq_ignore_ascii_case(&self.label)
+            || u8::from_str_radix(from.trim_start_matches("0x"), 16) == Ok(hash)
+    }
+}
+
+/// Where alerts go besides the terminal.
+struct Alerts {
+    exec: Option<String>,
+    webhook: Option<mpsc::Sender<serde_json::Value>>,
+    format: WebhookFormat,
+    notify: bool,
+}
+
+impl Alerts {
+    /// Report `node` going silent (`back` false) or being heard again.
+    fn raise(&self, node: &Watched, back: bool, expect: Duration) {
+        let silent_secs = node.last_heard.elapsed().as_secs();
+        let (event, text) = if back {
+            (
+                "node_back",
+                format!(
+                    "{} heard again after {}",
+                    node.label,
+                    timefmt::duration(silent_secs)
+                ),
+            )
+        } else {
+            (
+                "node_silent",
+                format!(
+                    "{} silent for {} (expected every {})",
+                    node.label,
+                    timefmt::duration(silent_secs),
+                    timefmt::duration(expect.as_secs())
+                ),
+            )
+        };
+        let mark = if back { "✓" } else { "✗" };
+        println!("[{}] {mark} {text}", timefmt::clock());
+
+        if let Some(cmd) = &self.exec {
+            let mut env = vec![
+                ("MESHGRID_EVENT", event.to_string()),
+                ("MESHGRID_TIMESTAMP", chrono::Local::now().to_rfc3339()),
+                ("MESHGRID_NODE_NAME", node.label.clone()),
+                ("MESHGRID_SILENT", silent_secs.to_string()),
+            ];
+            if let Some(hash) = node.hash {
+                env.push(("MESHGRID_NODE_HASH", format!("{hash:02x}")));
+            }
+            hooks::spawn(&format!("watchdog {event}"), cmd, env);
+        }
+        if let Some(tx) = &self.webhook {
+            let payload = webhook_payload(self.format, event, node, silent_secs, &text);
+            if tx.try_send(payload).is_err() {
+                tracing::warn!("Webhook queue full, dropping alert");
+            }
+        }
+        if self.notify {
+            notify_desktop("Meshgrid watchdog", &text, !back);
+        }
+    }
+}
+
+/// Webhook payload for an alert.
+fn webhook_payload(
+    format: WebhookFormat,
+    event: &str,
+    node: &Watched,
+    silent_secs: u64,
+    text: &str,
+) -> serde_json::Value {
+    match format {
+        WebhookFormat::Discord => serde_json::json!({
+            "username": "meshgrid watchdog",
+            "content": text,
+            "allowed_mentions": { "parse": [] },
+        }),
+        WebhookFormat::Slack => serde_json::json!({ "text": text }),
+        WebhookFormat::Json | WebhookFormat::Auto => serde_json::json!({
+            "event": event,
+            "node": node.label,
+            "node_hash": node.hash.map(|h| format!("{h:02x}")),
+            "silent_secs": silent_secs,
+            "text": text,
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+        }),
+    }
+}
+
+/// Post alerts to the webhook one at a time.
+async fn post_alerts(url: String, mut rx: mpsc::Receiver<serde_json::Value>) {
+    let client = reqwest::Client::new();
+    while let Some(payload) = rx.recv().await {
+        match client.post(&url).json(&payload).send().await {
+            Ok(resp) if !resp.status().is_success() => {
+                tracing::warn!("Webhook returned {}", resp.status());
+            }
+            Ok(_) => {}
+            Err(e) => tracing::warn!("Webhook post failed: {e}"),
+        }
+    }
+}
+
+/// Watch `nodes` (or the attached device, without any) and raise alerts when
+/// one isn't heard for `expect`, and again when it's back.
+#[allow(clippy::too_many_arguments)]
+pub async fn cmd_watchdog(
+    port: &str,
+    baud: u32,
+    pin: Option<&str>,
+    nodes: &[String],
+    expect: Duration,
+    exec: Option<String>,
+    webhook: Option<String>,
+    notify: bool,
+    refresh: bool,
+) -> Result<()> {
+    let dev = connect_with_auth(port, baud, pin).await?;
+    let mut p = dev.into_protocol();
+
+    let mut watched = Vec::new();
+    for node in nodes {
+        // A node that isn't in the neighbor table yet can be given by hash
+        let (label, hash) = match resolve_node(&mut p, port, node, refresh).await {
+            Some(found) => (found.label(), found.node_hash),
+            None => match u8::from_str_radix(node.trim_start_matches("0x"), 16) {
+                Ok(hash) => (format!("0x{hash:02x}"), hash),
+                Err(_) => bail!(CliError::InvalidArgs(format!(
+                    "Unknown node {node}: not in the neighbor table (give its hash instead)"
+                ))),
+            },
+        };
+        watched.push(Watched {
+            label,
+            hash: Some(hash),
+            last_heard: Instant::now(),
+            silent: false,
+        });
+    }
+    if watched.is_empty() {
+        watched.push(Watched {
+            label: port.to_string(),
+            hash: None,
+            last_heard: Instant::now(),
+            silent: false,
+        });
+    }
+
+    let format = webhook
+        .as_deref()
+        .map_or(WebhookFormat::Json, detect_webhook_format);
+    let webhook = webhook.map(|url| {
+        let (tx, rx) = mpsc::channel(32);
+        tokio::spawn(post_alerts(url, rx));
+        tx
+    });
+    let alerts = Alerts {
+        exec,
+        webhook,
+        format,
+        notify,
+    };
+
+    let labels: Vec<&str> = watched.iter().map(|w| w.label.as_str()).collect();
+    println!(
+        "Watching {} (expected every {}; Ctrl+C to stop)",
+        labels.join(", "),
+        timefmt::duration(expect.as_secs())
+    );
+
+    let local = watched.iter().any(|w| w.hash.is_none());
+    let ping_interval = (expect / 4).clamp(Duration::from_secs(1), PING_INTERVAL);
+    let mut last_ping = Instant::now();
+    let mut backoff = Duration::from_secs(1);
+    let mut retry_at = Instant::now();
+    p.enter_monitor_mode().await?;
+    let mut proto = Some(p);
+
+    loop {
+        if proto.is_none() && Instant::now() >= retry_at {
+            match connect_with_auth(port, baud, pin).await {
+                Ok(dev) => {
+                    let mut p = dev.into_protocol();
+                    if p.enter_monitor_mode().await.is_ok() {
+                        println!("[{}] Reconnected to {port}", timefmt::clock());
+                        proto = Some(p);
+                        backoff = Duration::from_secs(1);
+                    }
+                }
+                Err(e) => tracing::debug!("Reconnect failed: {e:#}"),
+            }
+            retry_at = Instant::now() + backoff;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+
+        let mut heard = Vec::new();
+        if let Some(p) = proto.as_mut() {
+            let result = poll(p, local && last_ping.elapsed() >= ping_interval).await;
+            match result {
+                Ok((pong, event)) => {
+                    if pong.is_some() {
+                        last_ping = Instant::now();
+                    }
+                    for (i, node) in watched.iter().enumerate() {
+                        let alive = match node.hash {
+                            None => pong == Some(true),
+                            Some(_) => event.as_ref().is_some_and(|e| node.heard_in(e)),
+                        };
+                        if alive {
+                            heard.push(i);
+                        }
+                    }
+                }
+                Err(e) => {
+                    println!("[{}] ⚠ Lost {port}: {e:#}", timefmt::clock());
+                    proto = None;
+                    retry_at = Instant::now() + backoff;
+                }
+            }
+        } else {
+            tokio::time::sleep(Duration::from_millis(250)).await;
+        }
+
+        for i in heard {
+            let node = &mut watched[i];
+            if node.silent {
+                alerts.raise(node, true, expect);
+                node.silent = false;
+            }
+            node.last_heard = Instant::now();
+        }
+        for node in &mut watched {
+            if !node.silent && node.last_heard.elapsed() >= expect {
+                alerts.raise(node, false, expect);
+                node.silent = true;
+            }
+        }
+    }
+}
+
+/// Read the next event, pinging the device first if `ping`: whether it
+/// answered (`None` when not pinged) and the event, if any.
+async fn poll(proto: &mut Protocol, ping: bool) -> Result<(Option<bool>, Option<MonitorEvent>)> {
+    let pong = if ping {
+        let alive = proto.command("PING").await.is_ok();
+        // A command leaves monitor mode on some firmware
+        proto.enter_monitor_mode().await?;
+        Some(alive)
+    } else {
+        None
+    };
+    let event = proto.read_event().await?;
+    Ok((pong, event))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watchdog_heard_in() {
+        let node = Watched {
+            label: "Ridge".into(),
+            hash: Some(0x3a),
+            last_heard: Instant::now(),
+            silent: false,
+        };
+        let advert = |node_hash| MonitorEvent::Advertisement {
+            node_hash,
+            rssi: -90,
+            name: None,
+        };
+        assert!(node.heard_in(&advert(0x3a)));
+        assert!(!node.heard_in(&advert(0x3b)));
+        let ack = |from: &str| MonitorEvent::Ack { from: from.into() };
+        assert!(node.heard_in(&ack("ridge")));
+        assert!(node.heard_in(&ack("3a")));
+        assert!(!node.heard_in(&ack("Valley")));
+
+        // The attached device is only heard through pings
+        let local = Watched { hash: None, ..node };
+        assert!(!local.heard_in(&advert(0x3a)));
+    }
+}
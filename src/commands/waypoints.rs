@@ -0,0 +1,212 @@
+//! Waypoints: saved locally, broadcast to devices with maps and screens
+
+use super::connect_with_auth;
+use super::messaging::parse_schedule;
+use super::position::validate;
+use super::util::require_port;
+use crate::cli::WaypointsAction;
+use crate::error::CliError;
+use crate::output;
+use crate::protocol::Response;
+use crate::timefmt;
+use crate::waypoints::{self, Waypoint};
+use anyhow::{bail, Result};
+use chrono::{SecondsFormat, Utc};
+
+/// Manage and send waypoints
+pub async fn cmd_waypoints(
+    port: Option<&String>,
+    baud: u32,
+    pin: Option<&str>,
+    action: WaypointsAction,
+) -> Result<()> {
+    match action {
+        WaypointsAction::Add {
+            name,
+            lat,
+            lon,
+            icon,
+            expires,
+        } => {
+            validate(lat, lon)?;
+            if name.trim().is_empty() {
+                bail!(CliError::InvalidArgs("Waypoint name is empty".into()));
+            }
+            if icon
+                .as_deref()
+                .is_some_and(|i| i.is_empty() || i == "-" || i.contains(char::is_whitespace))
+            {
+                bail!(CliError::InvalidArgs(
+                    "Icon must be a single word or emoji".into()
+                ));
+            }
+            let expires = expires
+                .as_deref()
+                .map(parse_schedule)
+                .transpose()?
+                .map(|t| {
+                    t.with_timezone(&Utc)
+                        .to_rfc3339_opts(SecondsFormat::Secs, true)
+                });
+
+            let replaced = waypoints::add(Waypoint {
+                name: name.trim().to_string(),
+                lat,
+                lon,
+                icon,
+                expires,
+            })?;
+            if !output::is_plain() {
+                let verb = if replaced { "Updated" } else { "Saved" };
+                println!("✓ {verb} waypoint {} ({lat:.6}, {lon:.6})", name.trim());
+                println!(
+                    "  `waypoints send {}` broadcasts it",
+                    shell_words::quote(name.trim())
+                );
+            }
+        }
+        WaypointsAction::List => {
+            let list = waypoints::list()?;
+            if output::is_plain() {
+                for (key, value) in output::flatten_json(&serde_json::to_value(&list)?) {
+                    output::kv(&key, value);
+                }
+                return Ok(());
+            }
+            if list.is_empty() {
+                println!("No waypoints saved (add one with `waypoints add`)");
+                return Ok(());
+            }
+            println!("Waypoints ({}):\n", list.len());
+            println!("  {:4} {:20} {:22} Expires", "Icon", "Name", "Position");
+            println!("  {:-<4} {:-<20} {:-<22} {:-<16}", "", "", "", "");
+            let now = Utc::now();
+            for waypoint in &list {
+                let position = format!("{:.5}, {:.5}", waypoint.lat, waypoint.lon);
+                let expires = match waypoint.expires_at() {
+                    Some(t) if t <= now => "expired".to_string(),
+                    Some(t) => timefmt::format(&t, "%Y-%m-%d %H:%M"),
+                    None => "never".to_string(),
+                };
+                println!(
+                    "  {:4} {:20} {position:22} {expires}",
+                    waypoint.icon.as_deref().unwrap_or(""),
+                    waypoint.name
+                );
+            }
+        }
+        WaypointsAction::Remove { name } => match waypoints::remove(&name)? {
+            Some(removed) => {
+                if !output::is_plain() {
+                    println!("✓ Removed waypoint {}", removed.name);
+                }
+            }
+            None => bail!(CliError::InvalidArgs(format!("No waypoint '{name}'"))),
+        },
+        WaypointsAction::Send { name } => {
+            let Some(waypoint) = waypoints::get(&name)? else {
+                bail!(CliError::InvalidArgs(format!(
+                    "No waypoint '{name}' (see `waypoints list`)"
+                )));
+            };
+            if waypoint.is_expired(Utc::now()) {
+                bail!(CliError::InvalidArgs(format!(
+                    "Waypoint {} has expired; re-add it with a new --expires",
+                    waypoint.name
+                )));
+            }
+            let port = require_port(port)?;
+            let mut proto = connect_with_auth(&port, baud, pin).await?.into_protocol();
+            match proto.command(&send_command(&waypoint)).await? {
+                Response::Ok(_) => {
+                    if !output::is_plain() {
+                        println!("✓ Waypoint {} sent", waypoint.name);
+                    }
+                }
+                Response::Error(e) => {
+                    bail!(CliError::Device(format!("failed to send waypoint: {e}")))
+                }
+                Response::Json(_) => bail!("Unexpected response to WAYPOINT SEND"),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `WAYPOINT SEND <lat> <lon> <expires|0> <icon|-> <name>`, the counterpart
+/// of the `WPT` monitor event.
+fn send_command(waypoint: &Waypoint) -> String {
+    format!(
+        "WAYPOINT SEND {:.6} {:.6} {} {} {}",
+        waypoint.lat,
+        waypoint.lon,
+        waypoint.expires_at().map_or(0, |t| t.timestamp()),
+        waypoint.icon.as_deref().unwrap_or("-"),
+        waypoint.name
+    )
+}
+
+/// One-line description of a received waypoint, for monitor output.
+pub(super) fn describe_received(
+    name: &str,
+    lat: f64,
+    lon: f64,
+    icon: Option<&str>,
+    expires: Option<i64>,
+) -> String {
+    let mut text = match icon {
+        Some(icon) => format!("{icon} {name}"),
+        None => name.to_string(),
+    };
+    text.push_str(&format!(" at {lat:.5}, {lon:.5}"));
+    if let Some(expires) = expires.and_then(|t| chrono::DateTime::from_timestamp(t, 0)) {
+        let expires = timefmt::format(&expires, "%Y-%m-%d %H:%M");
+        text.push_str(&format!(" until {expires}"));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::MonitorEvent;
+
+    #[test]
+    fn test_send_command_matches_event() {
+        let waypoint = Waypoint {
+            name: "Base camp".into(),
+            lat: 47.3769,
+            lon: 8.5417,
+            icon: Some("⛺".into()),
+            expires: Some("2026-01-12T18:00:00Z".into()),
+        };
+        let command = send_command(&waypoint);
+        assert_eq!(
+            command,
+            "WAYPOINT SEND 47.376900 8.541700 1768240800 ⛺ Base camp"
+        );
+
+        let line = command.replace("WAYPOINT SEND", "WPT alice -70");
+        let Some(MonitorEvent::Waypoint {
+            from,
+            name,
+            lat,
+            icon,
+            expires,
+            ..
+        }) = MonitorEvent::parse(&line)
+        else {
+            panic!("not a waypoint event: {line}");
+        };
+        assert_eq!(
+            (from.as_str(), name.as_str(), lat, icon.as_deref(), expires),
+            (
+                "alice",
+                "Base camp",
+                47.3769,
+                Some("⛺"),
+                Some(1_768_240_800)
+            )
+        );
+    }
+}
@@ -0,0 +1,227 @@
+//! Dictionary compression for short text messages.
+//!
+//! Common words and letter groups are replaced by control characters, which
+//! typed text doesn't contain, so a packed message is still valid UTF-8 and
+//! goes through the device's text commands unchanged. A leading [`MARKER`]
+//! flags it; [`unpack`] leaves any other text alone.
+
+use std::borrow::Cow;
+
+/// First character of a packed message (Shift Out).
+pub const MARKER: char = '\u{0e}';
+
+/// Lead byte of a two-byte code; the second byte picks from [`WORDS`].
+const LONG: u8 = 0x1f;
+
+/// Second bytes of two-byte codes, printable ASCII except space so a part
+/// boundary never falls between them (see [`crate::fragment`]).
+const LONG_FIRST: u8 = 0x21;
+
+/// One-byte codes: the control characters that aren't whitespace to the
+/// firmware (`\t`, `\n`, VT, FF, `\r`), [`MARKER`] or [`LONG`].
+const SHORT_CODES: [u8; 24] = [
+    0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x0f, 0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16,
+    0x17, 0x18, 0x19, 0x1a, 0x1b, 0x1c, 0x1d, 0x1e,
+];
+
+/// Letter groups worth a one-byte code.
+const SHORT: [&str; 24] = [
+    " the", "the ", " to ", "ing ", " and", " you", " is ", "ed ", "er", "in", "th", "re", "on",
+    "an", "at", "en", "es", "or", "st", "ou", "ea", "e ", "s ", "t ",
+];
+
+/// Words worth a two-byte code, mostly with their leading space.
+const WORDS: &[&str] = &[
+    " for",
+    " are",
+    " have",
+    " will",
+    " can",
+    " with",
+    " that",
+    " this",
+    " what",
+    " when",
+    " where",
+    " here",
+    " there",
+    " now",
+    " not",
+    " just",
+    " from",
+    " back",
+    " come",
+    " going",
+    " get",
+    " got",
+    " see",
+    " know",
+    " think",
+    " time",
+    " today",
+    " tonight",
+    " tomorrow",
+    " morning",
+    " meet",
+    " home",
+    " camp",
+    " trail",
+    " road",
+    " north",
+    " south",
+    " east",
+    " west",
+    " left",
+    " right",
+    " need",
+    " help",
+    " water",
+    " food",
+    " copy",
+    " roger",
+    " over",
+    " out",
+    " all",
+    " good",
+    " thanks",
+    " please",
+    " okay",
+    " yes",
+    " on my way",
+    " arrived",
+    " battery",
+    " signal",
+    " node",
+    " mesh",
+    " message",
+    " channel",
+    " repeater",
+    " antenna",
+    " test",
+    " check",
+    " about",
+    " after",
+    " before",
+    " again",
+    " still",
+    " soon",
+    " minutes",
+    " hours",
+    " wait",
+    " location",
+    " position",
+    " weather",
+    " rain",
+    " wind",
+    " was",
+    " our",
+    " your",
+    " they",
+    " we ",
+    " be ",
+    "ation",
+    "ment",
+    "ould",
+    "ight",
+    "ther",
+    "ough",
+];
+
+/// `text` packed, or `None` if that wouldn't make it shorter (or it holds
+/// control characters of its own).
+pub fn pack(text: &str) -> Option<String> {
+    if text
+        .chars()
+        .any(|c| c.is_control() && !matches!(c, '\t' | '\n' | '\r'))
+    {
+        return None;
+    }
+    let mut packed = vec![MARKER as u8];
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let short = SHORT
+            .iter()
+            .zip(SHORT_CODES)
+            .filter(|(entry, _)| rest.starts_with(**entry))
+            .map(|(entry, code)| (entry.len() - 1, entry.len(), vec![code]));
+        let long = WORDS
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| rest.starts_with(**entry))
+            .map(|(i, entry)| {
+                (
+                    entry.len() - 2,
+                    entry.len(),
+                    vec![LONG, LONG_FIRST + i as u8],
+                )
+            });
+        match short.chain(long).max_by_key(|(saved, _, _)| *saved) {
+            Some((saved, len, code)) if saved > 0 => {
+                packed.extend(code);
+                rest = &rest[len..];
+            }
+            _ => {
+                packed.extend(&rest.as_bytes()[..c.len_utf8()]);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    if packed.len() >= text.len() {
+        return None;
+    }
+    String::from_utf8(packed).ok()
+}
+
+/// `text` with a packed message expanded; anything else as it is.
+pub fn unpack(text: &str) -> Cow<'_, str> {
+    let Some(packed) = text.strip_prefix(MARKER) else {
+        return Cow::Borrowed(text);
+    };
+    let mut bytes = packed.bytes().peekable();
+    let mut text = Vec::with_capacity(packed.len() * 2);
+    while let Some(b) = bytes.next() {
+        if let Some(i) = SHORT_CODES.iter().position(|&code| code == b) {
+            text.extend(SHORT[i].bytes());
+            continue;
+        }
+        if b == LONG {
+            let word = bytes
+                .peek()
+                .and_then(|&next| WORDS.get(usize::from(next.wrapping_sub(LONG_FIRST))));
+            if let Some(word) = word {
+                text.extend(word.bytes());
+                bytes.next();
+                continue;
+            }
+        }
+        text.push(b);
+    }
+    Cow::Owned(String::from_utf8_lossy(&text).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pack_roundtrip() {
+        assert!(WORDS.len() <= usize::from(b'~' - LONG_FIRST + 1));
+
+        let text =
+            "Heading back to camp now, meet at the trail head in the morning. Battery is low!";
+        let packed = pack(text).unwrap();
+        assert!(packed.len() < text.len() * 3 / 4, "{}", packed.len());
+        assert!(packed.starts_with(MARKER));
+        assert!(!packed.contains(['\t', '\n', '\u{0b}', '\u{0c}', '\r', '\0']));
+        assert_eq!(unpack(&packed), text);
+
+        // Non-ASCII passes through
+        let text = "Café at the station 🛰 in ten minutes";
+        assert_eq!(unpack(&pack(text).unwrap()), text);
+
+        // Nothing to gain, or text that could be mistaken for packed
+        assert_eq!(pack("ok"), None);
+        assert_eq!(pack("\u{0e}the the the"), None);
+        assert_eq!(unpack("plain text"), "plain text");
+    }
+}
@@ -0,0 +1,253 @@
+//! Confirmation before destructive operations.
+//!
+//! Rebooting, rotating the identity, erasing or restoring the settings,
+//! clearing the inbox or the log, and flashing ask first. The question names
+//! the device they will hit (its name and public key, read from the device)
+//! so a wrong `-p` is caught before anything happens. `--yes` answers for the
+//! user; the `[confirm]` section of the config file sets the policy:
+//!
+//! ```toml
+//! [confirm]
+//! policy = "ask"      # ask (default), always (even with --yes) or never
+//! skip = ["reboot"]   # operations that never ask
+//!
+//! [profiles.bench.confirm]  # with --profile bench
+//! policy = "always"
+//! ```
+//!
+//! A profile's `policy` and `skip` replace the global ones when set.
+
+use crate::error::CliError;
+use crate::settings::Settings;
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+
+static ASSUME_YES: AtomicBool = AtomicBool::new(false);
+static PROFILE: OnceLock<String> = OnceLock::new();
+
+/// Answer yes to every confirmation (`--yes`).
+pub fn set_assume_yes(yes: bool) {
+    ASSUME_YES.store(yes, Ordering::Relaxed);
+}
+
+/// Whether `--yes` was given.
+pub fn assume_yes() -> bool {
+    ASSUME_YES.load(Ordering::Relaxed)
+}
+
+/// Use the `[profiles.<name>]` settings (`--profile`).
+pub fn set_profile(profile: Option<&str>) {
+    if let Some(profile) = profile {
+        let _ = PROFILE.set(profile.to_string());
+    }
+}
+
+/// When to ask before a destructive operation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Policy {
+    /// Ask at a terminal unless `--yes` is given
+    #[default]
+    Ask,
+    /// Always ask, ignoring `--yes`
+    Always,
+    /// Never ask
+    Never,
+}
+
+/// An operation that asks before it runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Operation {
+    Reboot,
+    RotateIdentity,
+    FactoryReset,
+    RestoreSettings,
+    MessagesClear,
+    LogClear,
+    Flash,
+}
+
+impl Operation {
+    fn question(self) -> &'static str {
+        match self {
+            Self::Reboot => "Reboot",
+            Self::RotateIdentity => "Generate a new identity for",
+            Self::FactoryReset => "Erase the settings (name, radio, channels, contacts, keys) of",
+            Self::RestoreSettings => "Replace the settings of",
+            Self::MessagesClear => "Delete every message in the inbox of",
+            Self::LogClear => "Delete the stored log of",
+            Self::Flash => "Flash new firmware to",
+        }
+    }
+}
+
+/// The device an operation will affect.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    /// Port or host
+    location: Option<String>,
+    name: Option<String>,
+    public_key: Option<[u8; 32]>,
+}
+
+impl Target {
+    /// The device at `location` (None: the one espflash picks), identified
+    /// by `name` and `public_key` when it answered.
+    pub fn new(location: Option<&str>, name: Option<&str>, public_key: Option<&[u8; 32]>) -> Self {
+        Self {
+            location: location.map(String::from),
+            name: name.map(String::from),
+            public_key: public_key.copied(),
+        }
+    }
+
+    /// Short form for the question.
+    fn label(&self) -> String {
+        let place = match &self.location {
+            Some(location) => format!("on {location}"),
+            None => "on the auto-detected port".into(),
+        };
+        match (&self.name, &self.public_key) {
+            (Some(name), _) => format!("{name} {place}"),
+            (None, Some(key)) => format!("{} {place}", hex::encode(&key[..4])),
+            (None, None) => format!("the device {place}"),
+        }
+    }
+
+    fn print(&self) {
+        match &self.public_key {
+            Some(key) => {
+                eprintln!(
+                    "  Device:     {}",
+                    self.name.as_deref().unwrap_or("<unnamed>")
+                );
+                eprintln!("  Public key: {}", hex::encode(key));
+            }
+            None => eprintln!("  Device:     not identified (it did not answer)"),
+        }
+        eprintln!(
+            "  Port:       {}",
+            self.location.as_deref().unwrap_or("auto-detected")
+        );
+    }
+}
+
+/// Settings for [`confirm`].
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfirmConfig {
+    pub policy: Policy,
+    /// Operations that run without asking
+    pub skip: Vec<Operation>,
+}
+
+/// A profile's `[confirm]` overrides; unset fields keep the global value.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfirmOverride {
+    pub policy: Option<Policy>,
+    pub skip: Option<Vec<Operation>>,
+}
+
+/// The `[confirm]` settings in effect for `profile`.
+fn effective_config(settings: Settings, profile: Option<&str>) -> Result<ConfirmConfig> {
+    let mut config = settings.confirm;
+    let Some(name) = profile else {
+        return Ok(config);
+    };
+    let Some(profile) = settings.profiles.get(name) else {
+        bail!(CliError::InvalidArgs(format!(
+            "No [profiles.{name}] in the config file"
+        )));
+    };
+    if let Some(policy) = profile.confirm.policy {
+        config.policy = policy;
+    }
+    if let Some(skip) = &profile.confirm.skip {
+        config.skip.clone_from(skip);
+    }
+    Ok(config)
+}
+
+/// Whether `operation` has to be confirmed at a terminal.
+fn must_ask(config: &ConfirmConfig, operation: Operation, yes: bool) -> bool {
+    match config.policy {
+        Policy::Never => false,
+        _ if config.skip.contains(&operation) => false,
+        Policy::Always => true,
+        Policy::Ask => !yes,
+    }
+}
+
+/// Ask before `operation` on `target`, following `--yes` and the
+/// `[confirm]` settings of the profile. False if the user declined.
+pub fn confirm(operation: Operation, target: &Target) -> Result<bool> {
+    let config = effective_config(Settings::load()?, PROFILE.get().map(String::as_str))?;
+    if !must_ask(&config, operation, assume_yes()) {
+        return Ok(true);
+    }
+    let action = format!("{} {}", operation.question(), target.label());
+    if !std::io::IsTerminal::is_terminal(&std::io::stdin()) {
+        let fix = if config.policy == Policy::Always {
+            "the [confirm] policy is \"always\", so run it from a terminal"
+        } else {
+            "pass --yes to go ahead"
+        };
+        bail!(CliError::InvalidArgs(format!(
+            "Confirmation needed to {}{}; {fix}",
+            action[..1].to_lowercase(),
+            &action[1..]
+        )));
+    }
+    target.print();
+    Ok(dialoguer::Confirm::new()
+        .with_prompt(format!("{action}?"))
+        .default(false)
+        .interact()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_must_ask() {
+        let config: ConfirmConfig = toml::from_str("skip = [\"reboot\"]").unwrap();
+        assert!(must_ask(&config, Operation::Flash, false));
+        assert!(!must_ask(&config, Operation::Flash, true));
+        assert!(!must_ask(&config, Operation::Reboot, false));
+
+        let always: ConfirmConfig = toml::from_str("policy = \"always\"").unwrap();
+        assert!(must_ask(&always, Operation::MessagesClear, true));
+        let never: ConfirmConfig = toml::from_str("policy = \"never\"").unwrap();
+        assert!(!must_ask(&never, Operation::FactoryReset, false));
+        assert!(toml::from_str::<ConfirmConfig>("skip = [\"reboto\"]").is_err());
+
+        // A profile's policy and skip list win over the global ones
+        let settings: Settings = toml::from_str(
+            "[confirm]\npolicy = \"never\"\nskip = [\"reboot\"]\n\n\
+             [profiles.bench.confirm]\npolicy = \"always\"\n\n\
+             [profiles.field.confirm]\nskip = [\"flash\"]\n",
+        )
+        .unwrap();
+        let global = effective_config(settings.clone(), None).unwrap();
+        assert!(!must_ask(&global, Operation::Flash, false));
+        let bench = effective_config(settings.clone(), Some("bench")).unwrap();
+        assert!(must_ask(&bench, Operation::Flash, true));
+        assert!(!must_ask(&bench, Operation::Reboot, true));
+        let field = effective_config(settings.clone(), Some("field")).unwrap();
+        assert_eq!(field.skip, [Operation::Flash]);
+        assert!(effective_config(settings, Some("lab")).is_err());
+
+        let key = [0xab; 32];
+        let target = Target::new(Some("/dev/ttyUSB0"), Some("base"), Some(&key));
+        assert_eq!(target.label(), "base on /dev/ttyUSB0");
+        assert_eq!(
+            Target::new(None, None, None).label(),
+            "the device on the auto-detected port"
+        );
+    }
+}
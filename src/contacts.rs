@@ -0,0 +1,256 @@
+//! Known contact public keys.
+//!
+//! `contacts.toml` next to the config file records each contact key the first
+//! time a message is sent to it (trust on first use), and which keys were
+//! checked in person with `contacts verify` and marked with `contacts trust`.
+//! A contact whose name turns up with a different key is reported as changed
+//! until the new key is trusted, unless the contact pre-announced it with
+//! `rotate-identity --announce` while still using its trusted key.
+
+use crate::settings::Settings;
+use crate::vault;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Words used to spell out key fingerprints, one per byte.
+const WORDS: [&str; 256] = [
+    "acid", "acorn", "actor", "adobe", "agent", "alarm", "album", "alloy", "amber", "angle",
+    "ankle", "apple", "apron", "arena", "argon", "arrow", "aspen", "atlas", "attic", "audio",
+    "autumn", "badge", "bagel", "baker", "bamboo", "banjo", "barley", "basil", "basket", "beach",
+    "beacon", "bean", "beaver", "bench", "berry", "bison", "blade", "blanket", "blossom", "bonus",
+    "border", "bottle", "boxer", "brave", "bread", "brick", "bridge", "bronze", "brook", "brush",
+    "bucket", "bugle", "bundle", "butter", "cabin", "cactus", "camel", "camera", "canal", "candle",
+    "canoe", "canyon", "carbon", "cargo", "carpet", "carrot", "castle", "cedar", "cello", "chalk",
+    "cherry", "chess", "cider", "cinema", "circle", "citrus", "clay", "cliff", "clock", "cloud",
+    "clover", "cobalt", "cocoa", "comet", "copper", "coral", "cotton", "cougar", "crane", "crater",
+    "crayon", "cricket", "crystal", "cube", "dagger", "daisy", "delta", "denim", "desert",
+    "diesel", "dingo", "dolphin", "domino", "donkey", "dragon", "drum", "eagle", "easel", "echo",
+    "elbow", "ember", "engine", "falcon", "feather", "fennel", "ferry", "fiddle", "figure",
+    "flame", "flute", "forest", "fossil", "fox", "galaxy", "garden", "garlic", "gecko", "ginger",
+    "globe", "goblet", "grape", "gravel", "guitar", "hammer", "harbor", "hazel", "helmet", "heron",
+    "honey", "hornet", "igloo", "indigo", "island", "ivory", "jacket", "jaguar", "jelly", "jigsaw",
+    "jungle", "kayak", "kettle", "kiwi", "koala", "ladder", "lagoon", "lantern", "laser", "lemon",
+    "lentil", "lily", "linen", "lizard", "magnet", "mango", "maple", "marble", "meadow", "melon",
+    "meteor", "mint", "mirror", "mosaic", "muffin", "nectar", "needle", "nickel", "noodle",
+    "nutmeg", "oasis", "ocean", "olive", "onion", "orbit", "orchid", "otter", "oyster", "paddle",
+    "palace", "panda", "paper", "parrot", "pasta", "peach", "pebble", "pepper", "piano", "pickle",
+    "pillow", "pilot", "planet", "plum", "pocket", "pony", "poppy", "potato", "prism", "pumpkin",
+    "puzzle", "quartz", "quill", "rabbit", "radar", "radish", "raven", "ribbon", "rocket",
+    "saddle", "salmon", "sandal", "satin", "scarf", "shadow", "silver", "sketch", "sparrow",
+    "spider", "sponge", "spruce", "squid", "summit", "sunset", "swan", "tablet", "tango", "temple",
+    "tiger", "timber", "tomato", "topaz", "tulip", "tunnel", "turtle", "valley", "velvet",
+    "violin", "wagon", "walnut", "walrus", "willow", "window", "wizard", "yacht", "yarrow",
+    "zebra", "zephyr", "zinnia",
+];
+
+/// Trust state of a contact key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyStatus {
+    /// Verified and marked with `contacts trust`
+    Trusted,
+    /// Seen before but never verified
+    Unverified,
+    /// Never seen before
+    New,
+    /// The contact used a different key before
+    Changed { previous: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct KeyRecord {
+    name: String,
+    #[serde(default)]
+    trusted: bool,
+    first_seen: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Index {
+    #[serde(default)]
+    keys: BTreeMap<String, KeyRecord>,
+}
+
+impl Index {
+    fn status(&self, name: &str, public_key: &str) -> KeyStatus {
+        if let Some(record) = self.keys.get(public_key) {
+            return if record.trusted {
+                KeyStatus::Trusted
+            } else {
+                KeyStatus::Unverified
+            };
+        }
+        // Prefer reporting the trusted key if the contact had several
+        let previous = self
+            .keys
+            .iter()
+            .filter(|(_, r)| r.name.eq_ignore_ascii_case(name))
+            .max_by_key(|(_, r)| r.trusted);
+        match previous {
+            Some((key, _)) => KeyStatus::Changed {
+                previous: key.clone(),
+            },
+            None => KeyStatus::New,
+        }
+    }
+
+    fn insert(&mut self, name: &str, public_key: &str, trusted: bool) {
+        let first_seen = chrono::Utc::now().format("%Y-%m-%d").to_string();
+        let record = self
+            .keys
+            .entry(public_key.to_string())
+            .or_insert_with(|| KeyRecord {
+                name: name.to_string(),
+                trusted,
+                first_seen,
+            });
+        record.name = name.to_string();
+        record.trusted |= trusted;
+    }
+}
+
+fn index_path() -> Result<PathBuf> {
+    Ok(Settings::path()?.with_file_name("contacts.toml"))
+}
+
+fn read_index() -> Result<Index> {
+    let path = index_path()?;
+    vault::ensure_unlocked(&path)?;
+    match std::fs::read_to_string(&path) {
+        Ok(content) => toml::from_str(&content)
+            .with_context(|| format!("Invalid contacts file {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Index::default()),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+fn write_index(index: &Index) -> Result<()> {
+    let path = index_path()?;
+    vault::ensure_unlocked(&path)?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&path, toml::to_string(index)?)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Trust state of `public_key` (hex) for contact `name`.
+pub fn status(name: &str, public_key: &str) -> Result<KeyStatus> {
+    Ok(read_index()?.status(name, public_key))
+}
+
+/// Like [`status`], but records a never-seen contact's key (trust on first
+/// use). A changed key is not recorded, so it keeps being reported.
+pub fn remember(name: &str, public_key: &str) -> Result<KeyStatus> {
+    let mut index = read_index()?;
+    let status = index.status(name, public_key);
+    if status == KeyStatus::New {
+        index.insert(name, public_key, false);
+        write_index(&index)?;
+    }
+    Ok(status)
+}
+
+/// Mark `public_key` as verified for `name`, replacing any other key recorded
+/// for that contact. Returns the replaced keys.
+pub fn trust(name: &str, public_key: &str) -> Result<Vec<String>> {
+    let mut index = read_index()?;
+    let replaced: Vec<String> = index
+        .keys
+        .iter()
+        .filter(|(key, r)| *key != public_key && r.name.eq_ignore_ascii_case(name))
+        .map(|(key, _)| key.clone())
+        .collect();
+    for key in &replaced {
+        index.keys.remove(key);
+    }
+    index.insert(name, public_key, true);
+    write_index(&index)?;
+    Ok(replaced)
+}
+
+/// Trusted contacts as (name, public key hex).
+pub fn trusted() -> Result<Vec<(String, String)>> {
+    Ok(read_index()?
+        .keys
+        .into_iter()
+        .filter(|(_, r)| r.trusted)
+        .map(|(key, r)| (r.name, key))
+        .collect())
+}
+
+/// Prefix of the direct message announcing a key rotation.
+const ROTATION_PREFIX: &str = "meshgrid-key-rotation ";
+
+/// Message announcing that this node switches to `new_key` (hex) at
+/// `effective` (unix time).
+pub fn rotation_announcement(new_key: &str, effective: i64) -> String {
+    format!("{ROTATION_PREFIX}{new_key} {effective}")
+}
+
+/// (new key hex, effective unix time) of a rotation announcement.
+pub fn parse_rotation_announcement(text: &str) -> Option<(String, i64)> {
+    let mut fields = text.strip_prefix(ROTATION_PREFIX)?.split_whitespace();
+    let key = fields.next()?.to_ascii_lowercase();
+    let effective = fields.next()?.parse().ok()?;
+    (key.len() == 64 && hex::decode(&key).is_ok()).then_some((key, effective))
+}
+
+/// Trust `new_key` for `name` if the announcement came from its trusted key
+/// `sender_key`. Returns whether it was accepted.
+pub fn accept_rotation(name: &str, sender_key: &str, new_key: &str) -> Result<bool> {
+    let mut index = read_index()?;
+    if index.status(name, sender_key) != KeyStatus::Trusted {
+        return Ok(false);
+    }
+    if index.status(name, new_key) != KeyStatus::Trusted {
+        index.insert(name, new_key, true);
+        write_index(&index)?;
+    }
+    Ok(true)
+}
+
+/// Fingerprint of a public key as eight words, for reading aloud.
+pub fn fingerprint_words(public_key: &[u8]) -> Vec<&'static str> {
+    Sha256::digest(public_key)[..8]
+        .iter()
+        .map(|&b| WORDS[usize::from(b)])
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_status() {
+        let mut index = Index::default();
+        assert_eq!(index.status("alice", "aa"), KeyStatus::New);
+
+        index.insert("alice", "aa", false);
+        assert_eq!(index.status("Alice", "aa"), KeyStatus::Unverified);
+        assert_eq!(
+            index.status("alice", "bb"),
+            KeyStatus::Changed {
+                previous: "aa".into()
+            }
+        );
+
+        index.insert("alice", "aa", true);
+        assert_eq!(index.status("alice", "aa"), KeyStatus::Trusted);
+        assert_eq!(fingerprint_words(&[0u8; 32]).len(), 8);
+
+        let key = "ab".repeat(32);
+        let text = rotation_announcement(&key, 1_700_000_000);
+        assert_eq!(
+            parse_rotation_announcement(&text),
+            Some((key, 1_700_000_000))
+        );
+        assert_eq!(
+            parse_rotation_announcement("meshgrid-key-rotation zz 1"),
+            None
+        );
+    }
+}
@@ -0,0 +1,220 @@
+//! Saved device PINs/passwords.
+//!
+//! Secrets are kept in the OS keyring, keyed by the device's public key: the
+//! Secret Service on Linux/BSD, the login keychain on macOS and the Credential
+//! Manager on Windows. Where none is available they can be written in
+//! plaintext to `credentials.toml` next to the config file, readable only by
+//! the user, but only when asked to explicitly.
+//! That file also records which devices have a saved secret, so connecting to
+//! a device without one costs no extra round trip.
+
+use crate::error::CliError;
+use crate::settings::Settings;
+use crate::vault;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Write;
+use std::path::PathBuf;
+
+const SERVICE: &str = "meshgrid-cli";
+
+/// Where a secret is stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Store {
+    SecretService,
+    Keychain,
+    CredentialManager,
+    File,
+}
+
+impl fmt::Display for Store {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Store::SecretService => write!(f, "the Secret Service keyring"),
+            Store::Keychain => write!(f, "the macOS keychain"),
+            Store::CredentialManager => write!(f, "the Windows Credential Manager"),
+            Store::File => write!(f, "{}", index_path().unwrap_or_default().display()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    store: Store,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    /// Only set for `Store::File`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    secret: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Index {
+    #[serde(default)]
+    devices: BTreeMap<String, Entry>,
+}
+
+fn index_path() -> Result<PathBuf> {
+    Ok(Settings::path()?.with_file_name("credentials.toml"))
+}
+
+fn read_index() -> Result<Index> {
+    let path = index_path()?;
+    vault::ensure_unlocked(&path)?;
+    match std::fs::read_to_string(&path) {
+        Ok(content) => toml::from_str(&content)
+            .with_context(|| format!("Invalid credentials file {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Index::default()),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+fn write_index(index: &Index) -> Result<()> {
+    let path = index_path()?;
+    vault::ensure_unlocked(&path)?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options
+        .open(&path)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    file.write_all(toml::to_string(index)?.as_bytes())?;
+    Ok(())
+}
+
+/// The OS keyring on this platform, if the keyring crate has one.
+fn native_store() -> Option<Store> {
+    if cfg!(target_os = "macos") {
+        Some(Store::Keychain)
+    } else if cfg!(windows) {
+        Some(Store::CredentialManager)
+    } else if cfg!(any(
+        target_os = "linux",
+        target_os = "freebsd",
+        target_os = "openbsd"
+    )) {
+        Some(Store::SecretService)
+    } else {
+        None
+    }
+}
+
+fn keyring_entry(public_key: &str) -> keyring::Result<keyring::Entry> {
+    keyring::Entry::new(SERVICE, public_key)
+}
+
+/// Store a secret in the OS keyring.
+fn keyring_store(public_key: &str, secret: &str) -> Result<Store> {
+    let Some(store) = native_store() else {
+        bail!("no OS keyring on this platform");
+    };
+    keyring_entry(public_key)
+        .and_then(|entry| entry.set_password(secret))
+        .with_context(|| format!("Failed to store the PIN in {store}"))?;
+    Ok(store)
+}
+
+/// Save the secret for a device; returns where it was stored. Without a
+/// usable OS keyring the secret is only written to `credentials.toml` in
+/// plaintext when `allow_file` is set.
+pub fn save(public_key: &str, name: Option<&str>, secret: &str, allow_file: bool) -> Result<Store> {
+    let mut index = read_index()?;
+    let entry = match keyring_store(public_key, secret) {
+        Ok(store) => Entry {
+            store,
+            name: name.map(String::from),
+            secret: None,
+        },
+        Err(e) if allow_file => {
+            tracing::debug!("{e:#}");
+            Entry {
+                store: Store::File,
+                name: name.map(String::from),
+                secret: Some(secret.to_string()),
+            }
+        }
+        Err(e) => bail!(CliError::InvalidArgs(format!(
+            "{e:#}; pass --plaintext to store the PIN unencrypted in {}",
+            Store::File
+        ))),
+    };
+    let store = entry.store;
+    index.devices.insert(public_key.to_string(), entry);
+    write_index(&index)?;
+    Ok(store)
+}
+
+/// Saved secret for a device.
+pub fn load(public_key: &str) -> Result<Option<String>> {
+    let index = read_index()?;
+    let Some(entry) = index.devices.get(public_key) else {
+        return Ok(None);
+    };
+    let secret = match entry.store {
+        Store::File => entry.secret.clone(),
+        _ => keyring_entry(public_key)
+            .and_then(|e| e.get_password())
+            .map_err(|e| tracing::debug!("{e}"))
+            .ok(),
+    };
+    match secret {
+        Some(secret) => Ok(Some(secret)),
+        None => bail!("Saved PIN for this device is missing from {}", entry.store),
+    }
+}
+
+/// Remove the saved secret for a device; returns false if there was none.
+pub fn forget(public_key: &str) -> Result<bool> {
+    let mut index = read_index()?;
+    let Some(entry) = index.devices.remove(public_key) else {
+        return Ok(false);
+    };
+    if entry.store != Store::File {
+        if let Err(e) = keyring_entry(public_key).and_then(|e| e.delete_credential()) {
+            tracing::debug!("{e}");
+        }
+    }
+    write_index(&index)?;
+    Ok(true)
+}
+
+/// Whether any device has a saved secret.
+pub fn any_saved() -> bool {
+    read_index().is_ok_and(|index| !index.devices.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_index_stores() {
+        let index: Index = toml::from_str(
+            "[devices.ab12]\nstore = \"secret-service\"\n\n[devices.cd34]\nstore = \"file\"\nsecret = \"123456\"\n",
+        )
+        .unwrap();
+        assert_eq!(index.devices["ab12"].store, Store::SecretService);
+        assert_eq!(index.devices["ab12"].secret, None);
+        assert_eq!(index.devices["cd34"].secret.as_deref(), Some("123456"));
+        let toml = toml::to_string(&Index {
+            devices: [(
+                "ef56".to_string(),
+                Entry {
+                    store: Store::CredentialManager,
+                    name: None,
+                    secret: None,
+                },
+            )]
+            .into(),
+        })
+        .unwrap();
+        assert!(toml.contains("store = \"credential-manager\""));
+    }
+}
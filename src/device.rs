@@ -3,34 +3,80 @@
 //! Wraps the protocol layer with a user-friendly API.
 
 use anyhow::Result;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
 
-use crate::protocol::Protocol;
+use crate::audit;
+use crate::error::CliError;
+use crate::protocol::{Protocol, Response};
 use crate::serial::SerialPort;
+use crate::settings::Settings;
 
 /// High-level device interface.
 pub struct Device {
     protocol: Protocol,
+    /// Connected over the network or a proxy rather than a local serial port
+    remote: bool,
 }
 
 impl Device {
     /// Connect to a device.
     pub async fn connect(port: &str, baud: u32) -> Result<Self> {
         let serial = SerialPort::open(port, baud).await?;
-        let protocol = Protocol::new(serial);
+        let mut protocol = Protocol::new(serial);
+
+        // Identify the device for the audit log of a mutating command
+        if audit::wants_device() {
+            match protocol.get_info().await {
+                Ok(info) => audit::note_device(port, info.name.as_deref(), Some(&info.public_key)),
+                Err(e) => {
+                    tracing::debug!("Could not identify device for audit log: {e:#}");
+                    audit::note_device(port, None, None);
+                }
+            }
+        }
 
-        Ok(Self { protocol })
+        Ok(Self {
+            protocol,
+            remote: port.starts_with("tcp://") || port.starts_with("unix:"),
+        })
     }
 
     /// Authenticate with PIN.
+    ///
+    /// Firmware that answers `CHALLENGE` with a nonce gets
+    /// `AUTH HMAC <HMAC-SHA256(pin, nonce)>`, so the PIN itself is never sent.
+    /// Older firmware falls back to plaintext `AUTH <pin>`, unless
+    /// `[auth] require_challenge` is set.
     pub async fn authenticate(&mut self, pin: &str) -> Result<()> {
-        use crate::protocol::Response;
-
-        let cmd = format!("AUTH {pin}");
+        let cmd = match self.protocol.command("CHALLENGE").await? {
+            Response::Ok(Some(nonce)) => {
+                let nonce = hex::decode(nonce.trim())
+                    .map_err(|_| anyhow::anyhow!("Malformed CHALLENGE nonce from device"))?;
+                format!("AUTH HMAC {}", challenge_response(pin, &nonce)?)
+            }
+            _ => {
+                if Settings::load()?.auth.require_challenge {
+                    anyhow::bail!(CliError::AuthFailed(
+                        "Firmware does not support challenge-response authentication \
+                         and [auth] require_challenge is set"
+                            .into()
+                    ));
+                }
+                if self.remote {
+                    tracing::warn!(
+                        "Firmware does not support challenge-response authentication; \
+                         sending the PIN in the clear"
+                    );
+                }
+                format!("AUTH {pin}")
+            }
+        };
         let response = self.protocol.command(&cmd).await?;
 
         match response {
             Response::Ok(_) => Ok(()),
-            Response::Error(msg) => anyhow::bail!("Authentication failed: {msg}"),
+            Response::Error(msg) => anyhow::bail!(CliError::AuthFailed(msg)),
             Response::Json(_) => anyhow::bail!("Unexpected response to AUTH command"),
         }
     }
@@ -102,30 +148,7 @@ impl Device {
     /// Get neighbor table.
     pub async fn get_neighbors(&mut self) -> Result<Vec<NeighborInfo>> {
         let neighbors = self.protocol.get_neighbors().await?;
-
-        Ok(neighbors
-            .into_iter()
-            .map(|n| NeighborInfo {
-                node_hash: n.node_hash,
-                name: n.name,
-                rssi: n.rssi,
-                snr: n.snr,
-                last_seen_secs: n.last_seen_secs,
-                firmware: n.firmware,
-                protocol_version: n.protocol_version.unwrap_or(0),
-            })
-            .collect())
-    }
-
-    /// Trace route to a target.
-    pub async fn trace(&mut self, target: &str) -> Result<TraceResult> {
-        let result = self.protocol.trace(target).await?;
-
-        Ok(TraceResult {
-            path: result.path,
-            hop_count: result.hop_count,
-            rtt_ms: result.rtt_ms,
-        })
+        Ok(neighbors.into_iter().map(NeighborInfo::from).collect())
     }
 
     /// Reboot the device.
@@ -156,6 +179,27 @@ impl Device {
     }
 }
 
+/// HMAC-SHA256 of the device's nonce keyed with the PIN, hex.
+pub(crate) fn challenge_response(pin: &str, nonce: &[u8]) -> Result<String> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(pin.as_bytes())?;
+    mac.update(nonce);
+    Ok(hex::encode(mac.finalize().into_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_challenge_response() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            challenge_response("Jefe", b"what do ya want for nothing?").unwrap(),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}
+
 /// Device information.
 #[derive(Debug, Clone)]
 pub struct DeviceInfo {
@@ -188,14 +232,25 @@ pub struct NeighborInfo {
     pub last_seen_secs: u32,
     pub firmware: Option<String>,
     pub protocol_version: u8,
+    /// Advertised position, in decimal degrees
+    pub lat: Option<f64>,
+    pub lon: Option<f64>,
 }
 
-/// Trace result.
-#[derive(Debug, Clone)]
-pub struct TraceResult {
-    pub path: Vec<String>,
-    pub hop_count: u8,
-    pub rtt_ms: u32,
+impl From<crate::protocol::NeighborInfo> for NeighborInfo {
+    fn from(n: crate::protocol::NeighborInfo) -> Self {
+        Self {
+            node_hash: n.node_hash,
+            name: n.name,
+            rssi: n.rssi,
+            snr: n.snr,
+            last_seen_secs: n.last_seen_secs,
+            firmware: n.firmware,
+            protocol_version: n.protocol_version.unwrap_or(0),
+            lat: n.lat,
+            lon: n.lon,
+        }
+    }
 }
 
 /// Mesh event for monitoring.
@@ -204,6 +259,7 @@ pub enum MeshEvent {
     Message {
         from: String,
         to: Option<String>,
+        channel: Option<String>,
         text: String,
         rssi: i16,
     },
@@ -215,6 +271,13 @@ pub enum MeshEvent {
     Ack {
         from: String,
     },
+    Waypoint {
+        from: String,
+        name: String,
+        lat: f64,
+        lon: f64,
+        icon: Option<String>,
+    },
     Error {
         message: String,
     },
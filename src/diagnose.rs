@@ -0,0 +1,178 @@
+//! Hints for common failures.
+//!
+//! Errors keep what caused them (the I/O error behind a serial port that
+//! won't open, a classified timeout, a crash loop), and [`hints`] turns the
+//! ones users keep running into into next steps: joining the group that owns
+//! the port, closing the program that holds it (or connecting through its
+//! proxy), or checking the baud rate and firmware. Hints are printed after the error and included in
+//! `--error-format json` output.
+
+use crate::error::CliError;
+use crate::portlock::PortInUse;
+use crate::protocol::CrashLoop;
+use crate::serial::OpenError;
+use std::io::ErrorKind;
+
+/// Suggestions for `err`, most specific first.
+pub fn hints(err: &anyhow::Error) -> Vec<String> {
+    let mut hints = Vec::new();
+    for cause in err.chain() {
+        if let Some(open) = cause.downcast_ref::<OpenError>() {
+            match open.error.kind() {
+                ErrorKind::PermissionDenied => hints.push(permission_hint(&open.port)),
+                ErrorKind::ResourceBusy => hints.push(busy_hint(&open.port)),
+                _ => {}
+            }
+        }
+        if let Some(busy) = cause.downcast_ref::<PortInUse>() {
+            hints.push(match &busy.holder.proxy {
+                Some(proxy) => {
+                    format!("Connect through its proxy instead: meshgrid-cli -p {proxy} <command>")
+                }
+                None => format!(
+                    "Stop the {} (pid {}) and try again, or wait for it to finish.",
+                    busy.holder.command, busy.holder.pid
+                ),
+            });
+        }
+        if cause.is::<CrashLoop>() {
+            hints.push("Run `meshgrid-cli recover` to find out why it keeps resetting.".into());
+        }
+    }
+    if matches!(err.downcast_ref::<CliError>(), Some(CliError::Timeout(_))) {
+        hints.push(
+            "Check that the device runs meshgrid firmware (`meshgrid-cli flash`) and that the \
+             baud rate matches (-b, meshgrid uses 115200). A device waiting in the bootloader \
+             doesn't answer: press RESET."
+                .into(),
+        );
+    }
+    hints
+}
+
+fn permission_hint(port: &str) -> String {
+    match port_group(port) {
+        Some(group) if cfg!(target_os = "linux") => format!(
+            "{port} belongs to the '{group}' group. Add yourself with \
+             `sudo usermod -aG {group} $USER` and log in again, or run \
+             `meshgrid-cli setup-permissions` to install a udev rule for the device."
+        ),
+        _ => format!("Your user has no access to {port}; check its permissions."),
+    }
+}
+
+/// Group owning the device node.
+#[cfg(unix)]
+pub fn port_group(port: &str) -> Option<String> {
+    use std::os::unix::fs::MetadataExt;
+    let gid = std::fs::metadata(port).ok()?.gid();
+    // SAFETY: getgrgid returns null or a pointer to a static entry, read
+    // before any other call can overwrite it
+    unsafe {
+        let group = libc::getgrgid(gid);
+        if group.is_null() {
+            return None;
+        }
+        Some(
+            std::ffi::CStr::from_ptr((*group).gr_name)
+                .to_string_lossy()
+                .into_owned(),
+        )
+    }
+}
+
+#[cfg(not(unix))]
+pub fn port_group(_port: &str) -> Option<String> {
+    None
+}
+
+fn busy_hint(port: &str) -> String {
+    let holders: Vec<String> = port_holders(port)
+        .into_iter()
+        .map(|(pid, name)| format!("{name} (pid {pid})"))
+        .collect();
+    if holders.is_empty() {
+        format!(
+            "Another program has {port} open: a serial monitor, PlatformIO, ModemManager or \
+             another meshgrid-cli. Close it and try again{}.",
+            if cfg!(target_os = "macos") {
+                format!(" (`lsof {port}` shows which)")
+            } else {
+                String::new()
+            }
+        )
+    } else {
+        format!(
+            "{port} is open in {}. Close it and try again.",
+            holders.join(", ")
+        )
+    }
+}
+
+/// Processes with `port` open, as (pid, name). Only processes this user
+/// may inspect are found.
+#[cfg(target_os = "linux")]
+fn port_holders(port: &str) -> Vec<(u32, String)> {
+    let Ok(port) = std::fs::canonicalize(port) else {
+        return Vec::new();
+    };
+    let Ok(procs) = std::fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    let mut holders = Vec::new();
+    for entry in procs.flatten() {
+        let Some(pid) = entry.file_name().to_str().and_then(|p| p.parse().ok()) else {
+            continue;
+        };
+        let Ok(fds) = std::fs::read_dir(entry.path().join("fd")) else {
+            continue;
+        };
+        if fds
+            .flatten()
+            .any(|fd| std::fs::read_link(fd.path()).is_ok_and(|target| target == port))
+        {
+            let name = std::fs::read_to_string(entry.path().join("comm"))
+                .map_or_else(|_| "?".into(), |n| n.trim().to_string());
+            holders.push((pid, name));
+        }
+    }
+    holders
+}
+
+#[cfg(not(target_os = "linux"))]
+fn port_holders(_port: &str) -> Vec<(u32, String)> {
+    Vec::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hints() {
+        let timeout = anyhow::Error::new(CliError::Timeout("Command timeout".into()))
+            .context("Failed to read config");
+        assert!(hints(&timeout)[0].contains("baud rate"));
+        assert!(hints(&anyhow::anyhow!("boom")).is_empty());
+
+        let path = std::env::temp_dir().join(format!("meshgrid-busy-{}", std::process::id()));
+        let held = std::fs::File::create(&path).unwrap();
+        let port = path.display().to_string();
+        let busy = anyhow::Error::new(OpenError {
+            port: port.clone(),
+            error: std::io::Error::new(ErrorKind::ResourceBusy, "locked"),
+        })
+        .context(CliError::ConnectionFailed(format!(
+            "Failed to open serial port {port}"
+        )));
+        let hint = &hints(&busy)[0];
+        if cfg!(target_os = "linux") {
+            assert!(
+                hint.contains(&format!("(pid {})", std::process::id())),
+                "{hint}"
+            );
+        }
+        drop(held);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,157 @@
+//! Error classification and exit codes.
+//!
+//! Failures that scripts need to tell apart are raised as [`CliError`]
+//! variants; everything else is a generic error. The exit codes are stable:
+//!
+//! | Code | Kind               | Meaning                                   |
+//! |------|--------------------|-------------------------------------------|
+//! | 0    |                    | Success                                   |
+//! | 1    | `error`            | Unclassified failure                      |
+//! | 2    | `invalid_args`     | Invalid command-line arguments            |
+//! | 3    | `port_not_found`   | No port given/detected, or port missing   |
+//! | 4    | `auth_failed`      | Device rejected the PIN/password          |
+//! | 5    | `timeout`          | Device did not answer in time             |
+//! | 6    | `device_error`     | Device answered with an error             |
+//! | 7    | `connection_failed`| Port exists but could not be opened       |
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+/// Classified CLI failure.
+#[derive(Debug, thiserror::Error)]
+pub enum CliError {
+    #[error("{0}")]
+    InvalidArgs(String),
+    #[error("{0}")]
+    PortNotFound(String),
+    #[error("Authentication failed: {0}")]
+    AuthFailed(String),
+    #[error("{0}")]
+    Timeout(String),
+    #[error("Device error: {0}")]
+    Device(String),
+    #[error("{0}")]
+    ConnectionFailed(String),
+}
+
+impl CliError {
+    /// Stable exit code for this error.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            CliError::InvalidArgs(_) => 2,
+            CliError::PortNotFound(_) => 3,
+            CliError::AuthFailed(_) => 4,
+            CliError::Timeout(_) => 5,
+            CliError::Device(_) => 6,
+            CliError::ConnectionFailed(_) => 7,
+        }
+    }
+
+    /// Stable machine-readable kind.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            CliError::InvalidArgs(_) => "invalid_args",
+            CliError::PortNotFound(_) => "port_not_found",
+            CliError::AuthFailed(_) => "auth_failed",
+            CliError::Timeout(_) => "timeout",
+            CliError::Device(_) => "device_error",
+            CliError::ConnectionFailed(_) => "connection_failed",
+        }
+    }
+}
+
+/// How errors are reported on stderr.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ErrorFormat {
+    /// Human-readable message
+    #[default]
+    Text,
+    /// One JSON object per error
+    Json,
+}
+
+/// Structured error report printed with `--error-format json`.
+#[derive(Debug, Serialize)]
+pub struct ErrorReport {
+    pub kind: &'static str,
+    pub code: u8,
+    pub message: String,
+    pub causes: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub hints: Vec<String>,
+}
+
+impl ErrorReport {
+    pub fn new(err: &anyhow::Error) -> Self {
+        // Also finds a CliError attached with `.context()`
+        let classified = err
+            .downcast_ref::<CliError>()
+            .or_else(|| err.chain().find_map(|e| e.downcast_ref::<CliError>()));
+        Self {
+            kind: classified.map_or("error", CliError::kind),
+            code: classified.map_or(1, CliError::exit_code),
+            message: err.to_string(),
+            causes: err.chain().skip(1).map(ToString::to_string).collect(),
+            hints: crate::diagnose::hints(err),
+        }
+    }
+
+    /// Report for a command-line parsing error.
+    pub fn invalid_args(message: String) -> Self {
+        Self {
+            kind: "invalid_args",
+            code: 2,
+            message,
+            causes: Vec::new(),
+            hints: Vec::new(),
+        }
+    }
+
+    /// Print the report to stderr in the requested format.
+    pub fn print(&self, err: Option<&anyhow::Error>, format: ErrorFormat) {
+        match format {
+            ErrorFormat::Json => {
+                let json = serde_json::json!({ "error": self });
+                eprintln!("{json}");
+            }
+            ErrorFormat::Text => match err {
+                Some(e) => eprintln!(
+                    "{} {}",
+                    console::style("Error:").red().bold().for_stderr(),
+                    crate::output::render(&format!("{e:?}"))
+                ),
+                None => eprintln!("{}", crate::output::render(&self.message)),
+            },
+        }
+        if format == ErrorFormat::Text {
+            for hint in &self.hints {
+                eprintln!(
+                    "\n{} {hint}",
+                    console::style("Hint:").yellow().bold().for_stderr()
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classified_error_through_context() {
+        let err = anyhow::Error::new(CliError::Timeout("Command timeout".into()))
+            .context("Failed to read config");
+        let report = ErrorReport::new(&err);
+        assert_eq!(report.kind, "timeout");
+        assert_eq!(report.code, 5);
+        assert_eq!(report.causes, vec!["Command timeout".to_string()]);
+    }
+
+    #[test]
+    fn test_unclassified_error() {
+        let report = ErrorReport::new(&anyhow::anyhow!("boom"));
+        assert_eq!(report.kind, "error");
+        assert_eq!(report.code, 1);
+    }
+}
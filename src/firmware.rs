@@ -1,5 +1,6 @@
-use anyhow::{anyhow, Context, Result};
-use indicatif::{ProgressBar, ProgressStyle};
+use crate::output;
+use anyhow::{anyhow, bail, Context, Result};
+use indicatif::ProgressBar;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
@@ -8,8 +9,80 @@ use std::io::Write;
 use std::path::{Path, PathBuf};
 
 const GITHUB_REPO: &str = "MeshGridStack/meshgrid-firmware";
+const CLI_REPO: &str = "MeshGridStack/meshgrid-cli";
 const GITHUB_API_BASE: &str = "https://api.github.com";
 
+/// Header of a delta patch between two firmware binaries.
+const DELTA_MAGIC: &[u8; 8] = b"MGDELTA1";
+
+/// Flash sector size: the unit read-back comparison works in.
+pub const FLASH_SECTOR: usize = 4096;
+
+/// Settings (NVS) partition in the firmware's partition table.
+pub const NVS_OFFSET: u32 = 0x9000;
+pub const NVS_SIZE: usize = 0x5000;
+
+/// ESP-IDF partition table location and maximum size.
+pub const PARTITION_TABLE_OFFSET: u32 = 0x8000;
+pub const PARTITION_TABLE_SIZE: usize = 0xc00;
+
+/// Entry of an ESP-IDF partition table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Partition {
+    pub label: String,
+    /// 0x00 app, 0x01 data
+    pub kind: u8,
+    pub subtype: u8,
+    pub offset: u32,
+    pub size: u32,
+}
+
+impl Partition {
+    /// A SPIFFS, LittleFS or FAT data partition.
+    pub fn is_filesystem(&self) -> bool {
+        self.kind == 0x01 && matches!(self.subtype, 0x81..=0x83)
+    }
+}
+
+/// Releases that changed the settings layout: firmware on either side of
+/// one can't read the other's saved settings.
+pub const SETTINGS_FORMAT_CHANGES: &[&str] = &["0.0.4"];
+
+/// Chip a firmware image is built for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chip {
+    Esp32,
+    Esp32S2,
+    Esp32S3,
+    Esp32C3,
+    Esp32C6,
+    Nrf52840,
+    Rp2040,
+}
+
+impl Chip {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Esp32 => "ESP32",
+            Self::Esp32S2 => "ESP32-S2",
+            Self::Esp32S3 => "ESP32-S3",
+            Self::Esp32C3 => "ESP32-C3",
+            Self::Esp32C6 => "ESP32-C6",
+            Self::Nrf52840 => "nRF52840",
+            Self::Rp2040 => "RP2040",
+        }
+    }
+
+    /// Chips that share a USB bootloader and flashing tool.
+    pub fn family(self) -> &'static str {
+        match self {
+            Self::Nrf52840 => "nRF52",
+            Self::Rp2040 => "RP2040",
+            _ => "ESP32",
+        }
+    }
+}
+
 /// GitHub release information
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Release {
@@ -61,17 +134,7 @@ impl FirmwareManager {
         force_download: bool,
         offline: bool,
     ) -> Result<PathBuf> {
-        let version = if version == "latest" {
-            if offline {
-                return Err(anyhow!(
-                    "Cannot use 'latest' version in offline mode\n\
-                     Please specify a specific version or remove --offline"
-                ));
-            }
-            self.get_latest_version().await?
-        } else {
-            version.to_string()
-        };
+        let version = self.resolve_version(version, offline).await?;
 
         let firmware_filename = format!("meshgrid-{}-{}.bin", env_name, version);
         let version_dir = self.cache_dir.join(&version);
@@ -79,7 +142,9 @@ impl FirmwareManager {
 
         // Check cache first
         if firmware_path.exists() && !force_download {
-            println!("✓ Using cached firmware: {}", firmware_filename);
+            if !output::is_plain() {
+                println!("✓ Using cached firmware: {}", firmware_filename);
+            }
             return Ok(firmware_path);
         }
 
@@ -97,13 +162,29 @@ impl FirmwareManager {
         }
 
         // Download and verify
-        println!("Downloading firmware version {}...", version);
+        if !output::is_plain() {
+            println!("Downloading firmware version {}...", version);
+        }
         self.download_and_verify(&version, env_name, &version_dir, &firmware_filename)
             .await?;
 
         Ok(firmware_path)
     }
 
+    /// `version`, or the latest release's for "latest"
+    pub async fn resolve_version(&self, version: &str, offline: bool) -> Result<String> {
+        if version != "latest" {
+            return Ok(version.to_string());
+        }
+        if offline {
+            return Err(anyhow!(
+                "Cannot use 'latest' version in offline mode\n\
+                 Please specify a specific version or remove --offline"
+            ));
+        }
+        self.get_latest_version().await
+    }
+
     /// Get the latest release version from GitHub
     async fn get_latest_version(&self) -> Result<String> {
         let release = self.fetch_release("latest").await?;
@@ -112,12 +193,21 @@ impl FirmwareManager {
 
     /// Fetch release information from GitHub API
     pub async fn fetch_release(&self, version: &str) -> Result<Release> {
+        self.fetch_repo_release(GITHUB_REPO, version).await
+    }
+
+    /// Latest release of the CLI itself
+    pub async fn fetch_cli_release(&self) -> Result<Release> {
+        self.fetch_repo_release(CLI_REPO, "latest").await
+    }
+
+    async fn fetch_repo_release(&self, repo: &str, version: &str) -> Result<Release> {
         let url = if version == "latest" {
-            format!("{}/repos/{}/releases/latest", GITHUB_API_BASE, GITHUB_REPO)
+            format!("{}/repos/{}/releases/latest", GITHUB_API_BASE, repo)
         } else {
             format!(
                 "{}/repos/{}/releases/tags/{}",
-                GITHUB_API_BASE, GITHUB_REPO, version
+                GITHUB_API_BASE, repo, version
             )
         };
 
@@ -138,7 +228,7 @@ impl FirmwareManager {
                 "Release version '{}' not found\n\
                  Check available versions at: https://github.com/{}/releases",
                 version,
-                GITHUB_REPO
+                repo
             ));
         }
 
@@ -176,6 +266,7 @@ impl FirmwareManager {
 
         // Fetch release info to get download URLs
         let release = self.fetch_release(version).await?;
+        let plain = output::is_plain();
 
         // Find firmware and checksum assets
         let firmware_asset = release
@@ -212,27 +303,116 @@ impl FirmwareManager {
                 )
             })?;
 
+        // Patch a cached older version when the release publishes a delta
+        let mut patched = false;
+        if let Some((from, base, delta_asset)) = self.delta_base(&release, env_name, version) {
+            match self
+                .download_delta(&base, delta_asset, &firmware_path)
+                .await
+            {
+                Ok(delta_len) => {
+                    patched = true;
+                    if !plain {
+                        println!(
+                            "✓ Patched cached {from} with a {:.1} KB delta",
+                            delta_len as f64 / 1024.0
+                        );
+                    }
+                }
+                Err(e) => {
+                    if !plain {
+                        println!("⚠ Delta update from {from} failed ({e:#}), downloading the full binary");
+                    }
+                }
+            }
+        }
+
         // Download firmware binary with progress bar
-        println!("\nDownloading {}...", firmware_filename);
-        self.download_file(&firmware_asset.browser_download_url, &firmware_path)
-            .await?;
+        if !patched {
+            if !plain {
+                println!("\nDownloading {}...", firmware_filename);
+            }
+            self.download_file(&firmware_asset.browser_download_url, &firmware_path)
+                .await?;
+        }
 
         // Download checksum file
         self.download_file(&checksum_asset.browser_download_url, &checksum_path)
             .await?;
 
         // Verify checksum
-        print!("Verifying integrity... ");
-        self.verify_checksum(&firmware_path, &checksum_path).await?;
-        println!("✓");
-
-        println!("\n✓ Firmware ready to flash");
+        if !plain {
+            print!("Verifying integrity... ");
+        }
+        if patched
+            && self
+                .verify_checksum(&firmware_path, &checksum_path)
+                .await
+                .is_err()
+        {
+            if !plain {
+                println!("✗\n⚠ Patched binary does not match, downloading the full binary");
+            }
+            self.download_file(&firmware_asset.browser_download_url, &firmware_path)
+                .await?;
+            self.download_file(&checksum_asset.browser_download_url, &checksum_path)
+                .await?;
+            if !plain {
+                print!("Verifying integrity... ");
+            }
+            patched = false;
+        }
+        if !patched {
+            self.verify_checksum(&firmware_path, &checksum_path).await?;
+        }
+        if !plain {
+            println!("✓");
+            println!("\n✓ Firmware ready to flash");
+        }
 
         Ok(())
     }
 
+    /// A cached binary of another version for `env_name` that `release`
+    /// publishes a delta from, as (version, path, delta asset).
+    fn delta_base<'a>(
+        &self,
+        release: &'a Release,
+        env_name: &str,
+        version: &str,
+    ) -> Option<(String, PathBuf, &'a Asset)> {
+        let cached = self.list_cached_versions().ok()?;
+        // Newest cached version first
+        cached.into_iter().rev().find_map(|from| {
+            let base = self
+                .cache_dir
+                .join(&from)
+                .join(format!("meshgrid-{env_name}-{from}.bin"));
+            let delta_name = format!("meshgrid-{env_name}-{from}-to-{version}.delta");
+            let asset = release.assets.iter().find(|a| a.name == delta_name)?;
+            (from != version && base.exists()).then_some((from, base, asset))
+        })
+    }
+
+    /// Download the delta `asset` and apply it to `base`, writing the result to
+    /// `dest_path`. Returns the delta size.
+    async fn download_delta(&self, base: &Path, asset: &Asset, dest_path: &Path) -> Result<usize> {
+        let delta_path = dest_path.with_extension("delta");
+        if !output::is_plain() {
+            println!("\nDownloading {}...", asset.name);
+        }
+        self.download_file(&asset.browser_download_url, &delta_path)
+            .await?;
+        let delta = fs::read(&delta_path).context("Failed to read delta")?;
+        let _ = fs::remove_file(&delta_path);
+        let old = fs::read(base).context("Failed to read cached firmware")?;
+        fs::write(dest_path, apply_delta(&old, &delta)?)
+            .context("Failed to write firmware file")?;
+        Ok(delta.len())
+    }
+
     /// Download a file from URL with progress bar
-    async fn download_file(&self, url: &str, dest_path: &Path) -> Result<()> {
+    pub async fn download_file(&self, url: &str, dest_path: &Path) -> Result<()> {
         let response = self
             .client
             .get(url)
@@ -244,13 +424,14 @@ impl FirmwareManager {
 
         let total_size = response.content_length().unwrap_or(0);
 
-        let pb = ProgressBar::new(total_size);
-        pb.set_style(
-            ProgressStyle::default_bar()
-                .template("  {spinner:.green} [{bar:40.cyan/blue}] {bytes}/{total_bytes} {percent}% - {eta}")
-                .unwrap()
-                .progress_chars("█▓░"),
-        );
+        let pb = if output::is_plain() {
+            ProgressBar::hidden()
+        } else {
+            ProgressBar::new(total_size)
+        };
+        pb.set_style(output::progress_style(
+            "  {spinner:.green} [{bar:40.cyan/blue}] {bytes}/{total_bytes} {percent}% - {eta}",
+        ));
 
         let mut file = fs::File::create(dest_path).context("Failed to create destination file")?;
         let mut downloaded: u64 = 0;
@@ -282,9 +463,7 @@ impl FirmwareManager {
 
         // Compute actual checksum
         let firmware_data = fs::read(firmware_path).context("Failed to read firmware file")?;
-        let mut hasher = Sha256::new();
-        hasher.update(&firmware_data);
-        let actual_hash = format!("{:x}", hasher.finalize());
+        let actual_hash = sha256_hex(&firmware_data);
 
         // Compare checksums
         if actual_hash != expected_hash {
@@ -327,3 +506,314 @@ impl FirmwareManager {
         Ok(versions)
     }
 }
+
+/// Rebuild a firmware binary from the previous version `old` and a delta:
+///
+/// ```text
+/// MGDELTA1 | new length (u32 LE) | ops
+/// COPY: 0x00 | offset (u32 LE) | length (u32 LE)   bytes from `old`
+/// DATA: 0x01 | length (u32 LE) | bytes             new bytes
+/// ```
+pub fn apply_delta(old: &[u8], delta: &[u8]) -> Result<Vec<u8>> {
+    let Some(mut ops) = delta.strip_prefix(DELTA_MAGIC) else {
+        bail!("Not a firmware delta");
+    };
+    fn read_u32(ops: &mut &[u8]) -> Result<usize> {
+        let (n, rest) = ops
+            .split_first_chunk::<4>()
+            .ok_or_else(|| anyhow!("Delta is truncated"))?;
+        *ops = rest;
+        Ok(u32::from_le_bytes(*n) as usize)
+    }
+    let new_len = read_u32(&mut ops)?;
+    let mut new = Vec::with_capacity(new_len);
+    while let Some((&op, rest)) = ops.split_first() {
+        ops = rest;
+        match op {
+            0x00 => {
+                let offset = read_u32(&mut ops)?;
+                let len = read_u32(&mut ops)?;
+                let bytes = offset
+                    .checked_add(len)
+                    .and_then(|end| old.get(offset..end))
+                    .ok_or_else(|| anyhow!("Delta copies past the end of the cached firmware"))?;
+                new.extend_from_slice(bytes);
+            }
+            0x01 => {
+                let len = read_u32(&mut ops)?;
+                if ops.len() < len {
+                    bail!("Delta is truncated");
+                }
+                let (bytes, rest) = ops.split_at(len);
+                new.extend_from_slice(bytes);
+                ops = rest;
+            }
+            op => bail!("Unknown delta operation 0x{op:02x}"),
+        }
+        if new.len() > new_len {
+            bail!("Delta produces more than {new_len} bytes");
+        }
+    }
+    if new.len() != new_len {
+        bail!("Delta produced {} of {new_len} bytes", new.len());
+    }
+    Ok(new)
+}
+
+/// Chip `image` is built for: from the ESP image header of a merged binary
+/// (bootloader at 0x0, or 0x1000 on the original ESP32), or the family ID
+/// of a UF2 file.
+pub fn image_chip(image: &[u8]) -> Option<Chip> {
+    const UF2_MAGIC: &[u8] = b"UF2\n";
+    const UF2_FAMILY_ID_PRESENT: u32 = 0x2000;
+    if image.starts_with(UF2_MAGIC) && image.len() >= 32 {
+        let u32_at = |i: usize| u32::from_le_bytes(image[i..i + 4].try_into().unwrap());
+        if u32_at(8) & UF2_FAMILY_ID_PRESENT == 0 {
+            return None;
+        }
+        return match u32_at(28) {
+            0xada5_2840 => Some(Chip::Nrf52840),
+            0xe48b_ff56 => Some(Chip::Rp2040),
+            _ => None,
+        };
+    }
+    [0, 0x1000].into_iter().find_map(|offset| {
+        let header = image.get(offset..offset + 16)?;
+        if header[0] != 0xe9 {
+            return None;
+        }
+        match u16::from_le_bytes([header[12], header[13]]) {
+            0 => Some(Chip::Esp32),
+            2 => Some(Chip::Esp32S2),
+            5 => Some(Chip::Esp32C3),
+            9 => Some(Chip::Esp32S3),
+            13 => Some(Chip::Esp32C6),
+            _ => None,
+        }
+    })
+}
+
+/// Length of the ESP app image at the start of `data`: header, segments,
+/// checksum padded to 16 bytes and the optional SHA-256.
+fn esp_image_len(data: &[u8]) -> Option<usize> {
+    let header = data.get(..24)?;
+    if header[0] != 0xe9 {
+        return None;
+    }
+    let mut pos = 24;
+    for _ in 0..header[1] {
+        let len = u32::from_le_bytes(data.get(pos + 4..pos + 8)?.try_into().ok()?) as usize;
+        pos += 8 + len;
+    }
+    pos += 16 - pos % 16;
+    if header[23] == 1 {
+        pos += 32;
+    }
+    (pos <= data.len()).then_some(pos)
+}
+
+/// The app in a merged binary (bootloader, partition table and app flashed
+/// at 0x0), as OTA updates take it: the first app partition's image.
+pub fn app_image(merged: &[u8]) -> Option<&[u8]> {
+    let table = merged.get(PARTITION_TABLE_OFFSET as usize..)?;
+    let app = parse_partition_table(table)
+        .into_iter()
+        .find(|p| p.kind == 0x00)?;
+    let data = merged.get(app.offset as usize..)?;
+    Some(&data[..esp_image_len(data)?])
+}
+
+/// (major, minor, patch) of a version like `v0.0.5` or `0.0.5-beta`.
+pub fn parse_version(version: &str) -> Option<(u32, u32, u32)> {
+    let version = version.trim().trim_start_matches('v');
+    let core = version.split(['-', '+', ' ']).next()?;
+    let mut parts = core.split('.').map(|p| p.parse::<u32>().ok());
+    let version = (
+        parts.next()??,
+        parts.next()??,
+        parts.next().unwrap_or(Some(0))?,
+    );
+    parts.next().is_none().then_some(version)
+}
+
+/// The settings format change crossed going from `running` to `target`.
+pub fn settings_format_change(running: &str, target: &str) -> Option<&'static str> {
+    let (running, target) = (parse_version(running)?, parse_version(target)?);
+    let (low, high) = (running.min(target), running.max(target));
+    SETTINGS_FORMAT_CHANGES
+        .iter()
+        .copied()
+        .find(|change| parse_version(change).is_some_and(|v| low < v && v <= high))
+}
+
+/// Lowercase hex SHA-256 of `data`.
+pub fn sha256_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// (offset, length) of the runs of [`FLASH_SECTOR`]-sized sectors where
+/// `image` differs from `current` flash contents.
+pub fn changed_regions(current: &[u8], image: &[u8]) -> Vec<(usize, usize)> {
+    let mut regions: Vec<(usize, usize)> = Vec::new();
+    for (i, sector) in image.chunks(FLASH_SECTOR).enumerate() {
+        let offset = i * FLASH_SECTOR;
+        let end = (offset + sector.len()).min(current.len());
+        if current.get(offset..end) == Some(sector) {
+            continue;
+        }
+        match regions.last_mut() {
+            Some((start, len)) if *start + *len == offset => *len += sector.len(),
+            _ => regions.push((offset, sector.len())),
+        }
+    }
+    regions
+}
+
+/// Entries of a binary ESP-IDF partition table, up to its end marker.
+pub fn parse_partition_table(data: &[u8]) -> Vec<Partition> {
+    data.chunks_exact(32)
+        .take_while(|entry| entry[..2] == [0xaa, 0x50])
+        .map(|entry| {
+            let u32_at = |i: usize| u32::from_le_bytes(entry[i..i + 4].try_into().unwrap());
+            let label = &entry[12..28];
+            let len = label.iter().position(|&b| b == 0).unwrap_or(label.len());
+            Partition {
+                label: String::from_utf8_lossy(&label[..len]).into_owned(),
+                kind: entry[2],
+                subtype: entry[3],
+                offset: u32_at(4),
+                size: u32_at(8),
+            }
+        })
+        .collect()
+}
+
+/// Whether `data` is a whole NVS partition: every page starts with a valid
+/// page state (empty, active, full or freeing).
+pub fn looks_like_nvs(data: &[u8]) -> bool {
+    const PAGE_STATES: [u32; 4] = [0xffff_ffff, 0xffff_fffe, 0xffff_fffc, 0xffff_fff8];
+    data.len() == NVS_SIZE
+        && data.chunks(FLASH_SECTOR).all(|page| {
+            PAGE_STATES.contains(&u32::from_le_bytes([page[0], page[1], page[2], page[3]]))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_partition_table() {
+        let entry = |kind: u8, subtype: u8, offset: u32, size: u32, label: &str| {
+            let mut e = vec![0xaa, 0x50, kind, subtype];
+            e.extend(offset.to_le_bytes());
+            e.extend(size.to_le_bytes());
+            let mut name = [0u8; 16];
+            name[..label.len()].copy_from_slice(label.as_bytes());
+            e.extend(name);
+            e.extend(0u32.to_le_bytes());
+            e
+        };
+        let mut table = entry(0x01, 0x02, 0x9000, 0x5000, "nvs");
+        table.extend(entry(0x00, 0x10, 0x10000, 0x640000, "app0"));
+        table.extend(entry(0x01, 0x82, 0x670000, 0x180000, "spiffs"));
+        table.extend([0xeb, 0xeb]);
+        table.resize(PARTITION_TABLE_SIZE, 0xff);
+
+        let partitions = parse_partition_table(&table);
+        assert_eq!(partitions.len(), 3);
+        assert_eq!(partitions[0].label, "nvs");
+        let fs: Vec<_> = partitions.iter().filter(|p| p.is_filesystem()).collect();
+        assert_eq!(fs.len(), 1);
+        assert_eq!((fs[0].offset, fs[0].size), (0x670000, 0x180000));
+    }
+
+    #[test]
+    fn test_looks_like_nvs() {
+        let mut nvs = vec![0xff; NVS_SIZE];
+        nvs[..4].copy_from_slice(&0xffff_fffe_u32.to_le_bytes());
+        assert!(looks_like_nvs(&nvs));
+        nvs[FLASH_SECTOR] = 0x00;
+        assert!(!looks_like_nvs(&nvs));
+        assert!(!looks_like_nvs(&[0xff; 4096]));
+    }
+
+    #[test]
+    fn test_image_chip_and_versions() {
+        let mut esp32s3 = vec![0xffu8; 64];
+        esp32s3[0] = 0xe9;
+        esp32s3[12..14].copy_from_slice(&[9, 0]);
+        assert_eq!(image_chip(&esp32s3), Some(Chip::Esp32S3));
+        let mut esp32 = vec![0xffu8; 0x1010];
+        esp32[0x1000] = 0xe9;
+        esp32[0x100c..0x100e].copy_from_slice(&[0, 0]);
+        assert_eq!(image_chip(&esp32), Some(Chip::Esp32));
+        let mut uf2 = b"UF2\n".to_vec();
+        uf2.resize(32, 0);
+        uf2[8..12].copy_from_slice(&0x2000u32.to_le_bytes());
+        uf2[28..32].copy_from_slice(&0xada5_2840u32.to_le_bytes());
+        assert_eq!(image_chip(&uf2), Some(Chip::Nrf52840));
+        assert_eq!(image_chip(b"not firmware"), None);
+
+        assert_eq!(parse_version("v0.0.5"), Some((0, 0, 5)));
+        assert_eq!(parse_version("1.2-beta"), Some((1, 2, 0)));
+        assert_eq!(parse_version("latest"), None);
+        assert_eq!(settings_format_change("0.0.5", "0.0.3"), Some("0.0.4"));
+        assert_eq!(settings_format_change("0.0.3", "0.0.4"), Some("0.0.4"));
+        assert_eq!(settings_format_change("0.0.5", "0.0.4"), None);
+    }
+
+    #[test]
+    fn test_app_image() {
+        let mut merged = vec![0xffu8; 0x10000];
+        let mut entry = vec![0xaa, 0x50, 0x00, 0x10];
+        entry.extend_from_slice(&0x10000u32.to_le_bytes());
+        entry.extend_from_slice(&0x100000u32.to_le_bytes());
+        entry.extend_from_slice(b"app0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0");
+        merged[0x8000..0x8020].copy_from_slice(&entry);
+        // One 5-byte segment: 24 + 8 + 5 = 37, padded to 48, plus a hash
+        let mut app = vec![0xe9, 1];
+        app.resize(23, 0);
+        app.push(1);
+        app.extend_from_slice(&[0, 0, 0, 0, 5, 0, 0, 0]);
+        app.extend_from_slice(b"hello");
+        app.resize(48 + 32, 0x5a);
+        merged.extend_from_slice(&app);
+        merged.extend_from_slice(&[0xff; 100]);
+        assert_eq!(app_image(&merged), Some(&app[..]));
+        assert_eq!(app_image(&merged[..0x9000]), None);
+    }
+
+    #[test]
+    fn test_sha256_hex() {
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_delta_and_regions() {
+        let old = vec![0xaa; 3 * FLASH_SECTOR];
+        let mut delta = DELTA_MAGIC.to_vec();
+        delta.extend((4 * FLASH_SECTOR as u32).to_le_bytes());
+        // Old image, one new sector, then a new last sector
+        delta.push(0x00);
+        delta.extend(0u32.to_le_bytes());
+        delta.extend((3 * FLASH_SECTOR as u32).to_le_bytes());
+        delta.push(0x01);
+        delta.extend((FLASH_SECTOR as u32).to_le_bytes());
+        delta.extend(vec![0x55; FLASH_SECTOR]);
+        let mut new = apply_delta(&old, &delta).unwrap();
+        assert_eq!(new.len(), 4 * FLASH_SECTOR);
+        assert!(apply_delta(&old[..FLASH_SECTOR], &delta).is_err());
+        assert!(apply_delta(&old, &delta[..delta.len() - 1]).is_err());
+
+        new[10] = 0;
+        assert_eq!(
+            changed_regions(&old, &new),
+            vec![(0, FLASH_SECTOR), (3 * FLASH_SECTOR, FLASH_SECTOR)]
+        );
+        assert!(changed_regions(&new, &new).is_empty());
+    }
+}
@@ -0,0 +1,247 @@
+//! Long messages sent as numbered parts.
+//!
+//! Text over the payload limit goes out in parts prefixed `[id n/total] `,
+//! where `id` is two hex digits shared by the parts of one message, so they
+//! still read sensibly on clients that don't join them. `monitor` and
+//! `messages` put the parts back together.
+
+use crate::error::CliError;
+use crate::rng::{self, Rng};
+use anyhow::{bail, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+/// Text bytes per packet, leaving room for the sender name firmware puts
+/// in front of channel messages
+pub const MAX_TEXT_BYTES: usize = 140;
+
+/// Most parts one message is split into.
+pub const MAX_PARTS: usize = 16;
+
+/// Stands in for parts that never arrived.
+const MISSING: &str = " […] ";
+
+/// One part of a split message.
+#[derive(Debug, PartialEq)]
+pub struct Fragment<'a> {
+    pub id: u8,
+    /// 1-based
+    pub seq: usize,
+    pub total: usize,
+    pub text: &'a str,
+}
+
+/// Parse the `[id n/total] ` prefix of a part.
+pub fn parse(text: &str) -> Option<Fragment<'_>> {
+    let (header, rest) = text.strip_prefix('[')?.split_once("] ")?;
+    let (id, counts) = header.split_once(' ')?;
+    let (seq, total) = counts.split_once('/')?;
+    if id.len() != 2 {
+        return None;
+    }
+    let id = u8::from_str_radix(id, 16).ok()?;
+    let seq: usize = seq.parse().ok()?;
+    let total: usize = total.parse().ok()?;
+    if seq == 0 || seq > total || !(2..=MAX_PARTS).contains(&total) {
+        return None;
+    }
+    Some(Fragment {
+        id,
+        seq,
+        total,
+        text: rest,
+    })
+}
+
+/// `text` as it goes on air: unchanged if it fits one packet, otherwise in
+/// numbered parts split at spaces where possible.
+pub fn split(text: &str) -> Result<Vec<String>> {
+    if text.len() <= MAX_TEXT_BYTES {
+        return Ok(vec![text.to_string()]);
+    }
+    let id = Rng::new(rng::time_seed()).next_u64() as u8;
+    // The header grows by a digit per part once there are ten of them
+    for digits in 1..=2u32 {
+        let header = format!("[{id:02x} {0}/{0}] ", "9".repeat(digits as usize)).len();
+        let chunks = chunks(text, MAX_TEXT_BYTES - header);
+        if chunks.len() > MAX_PARTS {
+            bail!(CliError::InvalidArgs(format!(
+                "Message too long: {} bytes, more than {MAX_PARTS} parts of {} bytes",
+                text.len(),
+                MAX_TEXT_BYTES - header
+            )));
+        }
+        if chunks.len() < 10usize.pow(digits) {
+            let total = chunks.len();
+            return Ok(chunks
+                .iter()
+                .enumerate()
+                .map(|(i, chunk)| format!("[{id:02x} {}/{total}] {chunk}", i + 1))
+                .collect());
+        }
+    }
+    unreachable!("MAX_PARTS has two digits")
+}
+
+/// Split `text` into pieces of at most `limit` bytes that concatenate back
+/// to it, breaking before a space in the second half of a piece if there is
+/// one. The space starts the next piece, since the device's command line
+/// drops trailing whitespace.
+fn chunks(text: &str, limit: usize) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut rest = text;
+    while rest.len() > limit {
+        let mut end = limit;
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        if let Some(space) = rest[..end].rfind(' ') {
+            if space > end / 2 {
+                end = space;
+            }
+        }
+        pieces.push(&rest[..end]);
+        rest = &rest[end..];
+    }
+    pieces.push(rest);
+    pieces
+}
+
+/// Parts of a message still coming in.
+struct Partial<M> {
+    meta: M,
+    total: usize,
+    parts: BTreeMap<usize, String>,
+    started: Instant,
+}
+
+impl<M> Partial<M> {
+    /// The text so far, with a marker for each run of missing parts.
+    fn text(&self) -> String {
+        let mut text = String::new();
+        let mut expected = 1;
+        for (&seq, part) in &self.parts {
+            if seq > expected {
+                text.push_str(MISSING);
+            }
+            text.push_str(part);
+            expected = seq + 1;
+        }
+        if expected <= self.total {
+            text.push_str(MISSING);
+        }
+        if self.parts.len() < self.total {
+            return text.trim().to_string();
+        }
+        text
+    }
+}
+
+/// Joins the parts of messages from each sender (`K`); `M` is whatever the
+/// caller keeps about a message, taken from its first part to arrive.
+pub struct Reassembler<K, M> {
+    pending: HashMap<(K, u8), Partial<M>>,
+}
+
+impl<K: Eq + Hash, M> Default for Reassembler<K, M> {
+    fn default() -> Self {
+        Self {
+            pending: HashMap::new(),
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone, M> Reassembler<K, M> {
+    /// Take in a received text: the whole message once it's complete
+    /// (right away for an unsplit one), `None` while parts are missing.
+    pub fn push(&mut self, key: K, meta: M, text: &str) -> Option<(M, String)> {
+        let Some(fragment) = parse(text) else {
+            return Some((meta, text.to_string()));
+        };
+        let slot = (key, fragment.id);
+        let partial = self.pending.entry(slot.clone()).or_insert_with(|| Partial {
+            meta,
+            total: fragment.total,
+            parts: BTreeMap::new(),
+            started: Instant::now(),
+        });
+        partial
+            .parts
+            .insert(fragment.seq, fragment.text.to_string());
+        if partial.parts.len() < partial.total {
+            return None;
+        }
+        let partial = self.pending.remove(&slot)?;
+        let text = partial.text();
+        Some((partial.meta, text))
+    }
+
+    /// Give up on messages still missing parts after `max_age`: what
+    /// arrived of them, with the gaps marked.
+    pub fn expire(&mut self, max_age: Duration) -> Vec<(M, String)> {
+        let expired: Vec<(K, u8)> = self
+            .pending
+            .iter()
+            .filter(|(_, p)| p.started.elapsed() >= max_age)
+            .map(|(slot, _)| slot.clone())
+            .collect();
+        let mut incomplete: Vec<Partial<M>> = expired
+            .into_iter()
+            .filter_map(|slot| self.pending.remove(&slot))
+            .collect();
+        incomplete.sort_by_key(|p| p.started);
+        incomplete
+            .into_iter()
+            .map(|p| {
+                let text = p.text();
+                (p.meta, text)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fragment_roundtrip() {
+        assert_eq!(split("short").unwrap(), ["short"]);
+
+        let text = "word ".repeat(100) + "end";
+        let parts = split(&text).unwrap();
+        assert_eq!(parts.len(), 4);
+        assert!(parts.iter().all(|p| p.len() <= MAX_TEXT_BYTES));
+        let first = parse(&parts[0]).unwrap();
+        assert_eq!((first.seq, first.total), (1, 4));
+        // Split at spaces, so no word is cut in two
+        assert!(first.text.ends_with("word"));
+        assert!(parse(&parts[1]).unwrap().text.starts_with(" word"));
+
+        // Parts arrive out of order and mixed with other messages
+        let mut joiner = Reassembler::default();
+        assert_eq!(joiner.push("bob", 1, &parts[2]), None);
+        assert_eq!(
+            joiner.push("bob", 2, "hello"),
+            Some((2, "hello".to_string()))
+        );
+        assert_eq!(joiner.push("bob", 3, &parts[0]), None);
+        assert_eq!(joiner.push("bob", 4, &parts[3]), None);
+        assert_eq!(joiner.push("bob", 5, &parts[1]), Some((1, text.clone())));
+
+        // Multi-byte characters aren't cut either
+        let emoji = "🛰".repeat(100);
+        let parts = split(&emoji).unwrap();
+        assert!(parts.iter().all(|p| parse(p).is_some()));
+        for part in &parts[1..] {
+            joiner.push("ann", 0, part);
+        }
+        let expired = joiner.expire(Duration::ZERO);
+        assert_eq!(expired.len(), 1);
+        assert!(expired[0].1.starts_with("[…] 🛰"));
+
+        assert!(split(&"x".repeat(MAX_TEXT_BYTES * MAX_PARTS)).is_err());
+        assert_eq!(parse("[notes 1/2] plain text"), None);
+    }
+}
@@ -0,0 +1,121 @@
+//! Frame codec of the serial protocol: COBS framing and the continuation
+//! frames large responses are split into.
+//!
+//! Every byte to and from the device passes through here, so it works in
+//! place on reused buffers. It depends on nothing else in the crate, which
+//! lets `benches/framing.rs` build it on its own.
+
+use anyhow::{bail, Result};
+use bytes::{BufMut, BytesMut};
+
+/// COBS-encode `data` onto the end of `out`, followed by the zero frame
+/// delimiter.
+pub fn cobs_encode_into(data: &[u8], out: &mut BytesMut) {
+    out.reserve(data.len() + data.len() / 254 + 2);
+    let mut code_ptr = out.len();
+    out.put_u8(0); // Placeholder for code byte
+    let mut code = 1u8;
+
+    for &byte in data {
+        if byte == 0 {
+            // Found zero - write code byte
+            out[code_ptr] = code;
+            code_ptr = out.len();
+            out.put_u8(0); // Placeholder for next code byte
+            code = 1;
+        } else {
+            out.put_u8(byte);
+            code = code.wrapping_add(1);
+            if code == 0xFF {
+                // Code byte full - write it
+                out[code_ptr] = code;
+                code_ptr = out.len();
+                out.put_u8(0); // Placeholder for next code byte
+                code = 1;
+            }
+        }
+    }
+
+    // Write final code byte
+    out[code_ptr] = code;
+    out.put_u8(0); // COBS frame delimiter
+}
+
+/// COBS-decode a frame (without its delimiter) in place.
+/// Returns the decoded length, or None if invalid
+pub fn cobs_decode_in_place(buf: &mut [u8]) -> Option<usize> {
+    let (mut read, mut write) = (0, 0);
+
+    while read < buf.len() {
+        let code = usize::from(buf[read]);
+        if code == 0 {
+            return None; // Invalid
+        }
+        read += 1;
+
+        // Move data bytes down over the code bytes already consumed
+        let end = (read + code - 1).min(buf.len());
+        buf.copy_within(read..end, write);
+        write += end - read;
+        read = end;
+
+        // Insert zero if not at end
+        if code < 0xFF && read < buf.len() {
+            buf[write] = 0;
+            write += 1;
+        }
+    }
+
+    Some(write)
+}
+
+/// Split the next complete frame off `buf` and decode it in place: `None`
+/// until its delimiter has arrived, `Some(None)` if it isn't valid COBS.
+pub fn split_frame(buf: &mut BytesMut) -> Option<Option<BytesMut>> {
+    let pos = buf.iter().position(|&b| b == 0)?;
+    let mut frame = buf.split_to(pos + 1);
+    frame.truncate(pos);
+    Some(cobs_decode_in_place(&mut frame).map(|len| {
+        frame.truncate(len);
+        frame
+    }))
+}
+
+/// Largest response reassembled from continuation frames.
+pub const MAX_RESPONSE_LEN: usize = 4 * 1024 * 1024;
+
+/// (sequence number, whether it's the last, data) of a continuation frame.
+pub fn continuation_frame(frame: &[u8]) -> Option<(u32, bool, &[u8])> {
+    let (last, rest) = if let Some(rest) = frame.strip_prefix(b"MORE ") {
+        (false, rest)
+    } else {
+        (true, frame.strip_prefix(b"END ")?)
+    };
+    let end = rest.iter().position(|&b| b == b' ').unwrap_or(rest.len());
+    let seq = std::str::from_utf8(&rest[..end]).ok()?.parse().ok()?;
+    Some((seq, last, rest.get(end + 1..).unwrap_or_default()))
+}
+
+/// A response being reassembled from continuation frames.
+#[derive(Debug, Default)]
+pub struct Continuation {
+    pub data: BytesMut,
+    next_seq: u32,
+}
+
+impl Continuation {
+    pub fn push(&mut self, seq: u32, chunk: &[u8]) -> Result<()> {
+        if seq != self.next_seq {
+            let expected = self.next_seq;
+            *self = Self::default();
+            bail!("Lost part of a large response (got frame {seq}, expected {expected})");
+        }
+        if self.data.len() + chunk.len() > MAX_RESPONSE_LEN {
+            *self = Self::default();
+            bail!("Response exceeds {} MiB", MAX_RESPONSE_LEN / (1024 * 1024));
+        }
+        self.data.extend_from_slice(chunk);
+        self.next_seq += 1;
+        Ok(())
+    }
+}
@@ -0,0 +1,70 @@
+//! Great-circle distance and bearing between positions
+
+const EARTH_RADIUS_M: f64 = 6_371_000.0;
+
+/// Positions of (0, 0) mean "no fix" on most firmware.
+pub fn valid(lat: f64, lon: f64) -> Option<(f64, f64)> {
+    let in_range = (-90.0..=90.0).contains(&lat) && (-180.0..=180.0).contains(&lon);
+    (in_range && (lat, lon) != (0.0, 0.0)).then_some((lat, lon))
+}
+
+/// Distance in meters between two `(lat, lon)` positions.
+pub fn distance_m(from: (f64, f64), to: (f64, f64)) -> f64 {
+    let (lat1, lat2) = (from.0.to_radians(), to.0.to_radians());
+    let dlat = lat2 - lat1;
+    let dlon = (to.1 - from.1).to_radians();
+    let h = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_M * h.sqrt().asin()
+}
+
+/// Initial bearing from `from` to `to`, in degrees clockwise from north.
+pub fn bearing_deg(from: (f64, f64), to: (f64, f64)) -> f64 {
+    let (lat1, lat2) = (from.0.to_radians(), to.0.to_radians());
+    let dlon = (to.1 - from.1).to_radians();
+    let y = dlon.sin() * lat2.cos();
+    let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * dlon.cos();
+    y.atan2(x).to_degrees().rem_euclid(360.0)
+}
+
+/// Nearest of the 16 compass points (`N`, `NNE`, ...).
+pub fn compass(bearing: f64) -> &'static str {
+    const POINTS: [&str; 16] = [
+        "N", "NNE", "NE", "ENE", "E", "ESE", "SE", "SSE", "S", "SSW", "SW", "WSW", "W", "WNW",
+        "NW", "NNW",
+    ];
+    POINTS[((bearing.rem_euclid(360.0) / 22.5).round() as usize) % 16]
+}
+
+/// `850 m`, `12.3 km` or `145 km`.
+pub fn format_distance(meters: f64) -> String {
+    match meters {
+        m if m < 1000.0 => format!("{m:.0} m"),
+        m if m < 100_000.0 => format!("{:.1} km", m / 1000.0),
+        m => format!("{:.0} km", m / 1000.0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_distance_and_bearing() {
+        // One arc minute of latitude is a nautical mile
+        let d = distance_m((48.0, 11.0), (48.0 + 1.0 / 60.0, 11.0));
+        assert!((d - 1853.0).abs() < 2.0, "{d}");
+
+        assert!((bearing_deg((0.0, 0.0), (0.0, 1.0)) - 90.0).abs() < 1e-9);
+        assert!((bearing_deg((48.0, 11.0), (47.0, 11.0)) - 180.0).abs() < 1e-9);
+        assert_eq!(compass(350.0), "N");
+        assert_eq!(compass(47.0), "NE");
+        assert_eq!(compass(200.0), "SSW");
+
+        assert_eq!(format_distance(849.6), "850 m");
+        assert_eq!(format_distance(12_345.0), "12.3 km");
+        assert_eq!(format_distance(145_200.0), "145 km");
+
+        assert_eq!(valid(0.0, 0.0), None);
+        assert_eq!(valid(91.0, 8.0), None);
+    }
+}
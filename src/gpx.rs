@@ -0,0 +1,133 @@
+//! GPX track files
+//!
+//! Points are appended as they arrive and the closing tags rewritten after
+//! each one, so the file is valid GPX even if the CLI is killed mid-track.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+const FOOTER: &str = "    </trkseg>\n  </trk>\n</gpx>\n";
+
+/// A track point.
+#[derive(Debug, Clone, Copy)]
+pub struct Point {
+    pub lat: f64,
+    pub lon: f64,
+    pub alt: Option<f64>,
+    pub time: DateTime<Utc>,
+}
+
+/// An open GPX file with a single track.
+pub struct GpxWriter {
+    file: File,
+}
+
+impl GpxWriter {
+    /// Open `path`, continuing its track if it was written by us before,
+    /// otherwise starting a new file named `name`.
+    pub fn open(path: &str, name: &str) -> Result<Self> {
+        let mut file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .with_context(|| format!("Failed to open {path}"))?;
+        let mut existing = String::new();
+        // Non-UTF-8 content can't be our track; it gets replaced below
+        let _ = file.read_to_string(&mut existing);
+
+        if existing.ends_with(FOOTER) {
+            file.seek(SeekFrom::Start((existing.len() - FOOTER.len()) as u64))?;
+        } else {
+            file.set_len(0)?;
+            file.seek(SeekFrom::Start(0))?;
+            write!(
+                file,
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+                 <gpx version=\"1.1\" creator=\"meshgrid-cli\" \
+                 xmlns=\"http://www.topografix.com/GPX/1/1\">\n  <trk>\n    \
+                 <name>{}</name>\n    <trkseg>\n",
+                escape(name)
+            )?;
+        }
+        let mut writer = Self { file };
+        writer.finish_and_rewind()?;
+        Ok(writer)
+    }
+
+    /// Append a point, leaving the file complete.
+    pub fn push(&mut self, point: &Point) -> Result<()> {
+        write!(
+            self.file,
+            "      <trkpt lat=\"{:.7}\" lon=\"{:.7}\">",
+            point.lat, point.lon
+        )?;
+        if let Some(alt) = point.alt {
+            write!(self.file, "<ele>{alt:.1}</ele>")?;
+        }
+        writeln!(
+            self.file,
+            "<time>{}</time></trkpt>",
+            point.time.to_rfc3339_opts(SecondsFormat::Secs, true)
+        )?;
+        self.finish_and_rewind()
+    }
+
+    /// Write the closing tags, then step back so the next point replaces them.
+    fn finish_and_rewind(&mut self) -> Result<()> {
+        self.file.write_all(FOOTER.as_bytes())?;
+        let end = self.file.stream_position()?;
+        self.file.set_len(end)?;
+        self.file.flush()?;
+        self.file.seek(SeekFrom::Start(end - FOOTER.len() as u64))?;
+        Ok(())
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append_keeps_file_valid() {
+        let path = std::env::temp_dir().join(format!("meshgrid-test-{}.gpx", std::process::id()));
+        let path = path.to_str().unwrap();
+        let time = DateTime::parse_from_rfc3339("2026-01-12T15:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let point = |lat| Point {
+            lat,
+            lon: 8.5,
+            alt: None,
+            time,
+        };
+
+        GpxWriter::open(path, "T1000-E <1>")
+            .unwrap()
+            .push(&point(47.1))
+            .unwrap();
+        let first = std::fs::read_to_string(path).unwrap();
+        assert!(first.contains("<name>T1000-E &lt;1&gt;</name>"));
+        assert!(first.ends_with(FOOTER));
+
+        // Reopening continues the same track
+        GpxWriter::open(path, "ignored")
+            .unwrap()
+            .push(&point(47.2))
+            .unwrap();
+        let second = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(second.matches("<trkpt").count(), 2);
+        assert_eq!(second.matches("</gpx>").count(), 1);
+        assert!(second.contains("lat=\"47.2000000\""));
+    }
+}
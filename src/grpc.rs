@@ -0,0 +1,462 @@
+//! Minimal gRPC server: protobuf wire format and HTTP/2 framing.
+//!
+//! Only what `serve grpc` needs — unary and server-streaming calls, no
+//! compression. Messages are built with [`Encoder`] and read with
+//! [`Message::decode`] following `proto/meshgrid.proto`; the `serve`
+//! tests decode them with that schema to keep the field numbers in step.
+
+use anyhow::{bail, Result};
+use bytes::{BufMut, Bytes, BytesMut};
+use h2::server::SendResponse;
+use h2::{RecvStream, SendStream};
+use http::{HeaderMap, HeaderValue, StatusCode};
+use std::future::Future;
+use tokio::net::TcpListener;
+
+/// Largest accepted request message.
+const MAX_MESSAGE: usize = 4 * 1024 * 1024;
+
+/// gRPC status codes used by the server.
+pub mod code {
+    pub const OK: u32 = 0;
+    pub const INVALID_ARGUMENT: u32 = 3;
+    pub const DEADLINE_EXCEEDED: u32 = 4;
+    pub const PERMISSION_DENIED: u32 = 7;
+    pub const FAILED_PRECONDITION: u32 = 9;
+    pub const UNIMPLEMENTED: u32 = 12;
+    pub const INTERNAL: u32 = 13;
+    pub const UNAVAILABLE: u32 = 14;
+    pub const UNAUTHENTICATED: u32 = 16;
+}
+
+/// Call outcome sent in the `grpc-status`/`grpc-message` trailers.
+#[derive(Debug, Clone)]
+pub struct Status {
+    pub code: u32,
+    pub message: String,
+}
+
+impl Status {
+    pub fn new(code: u32, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn ok() -> Self {
+        Self::new(code::OK, "")
+    }
+
+    fn trailers(&self) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        map.insert("grpc-status", HeaderValue::from(self.code));
+        if !self.message.is_empty() {
+            if let Ok(value) = HeaderValue::from_str(&percent_encode(&self.message)) {
+                map.insert("grpc-message", value);
+            }
+        }
+        map
+    }
+}
+
+/// Percent-encode a `grpc-message` value.
+fn percent_encode(message: &str) -> String {
+    let mut out = String::with_capacity(message.len());
+    for b in message.bytes() {
+        if (0x20..=0x7e).contains(&b) && b != b'%' {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{b:02X}"));
+        }
+    }
+    out
+}
+
+// ---------------------------------------------------------------------------
+// Protobuf wire format
+// ---------------------------------------------------------------------------
+
+const WIRE_VARINT: u8 = 0;
+const WIRE_FIXED64: u8 = 1;
+const WIRE_LEN: u8 = 2;
+const WIRE_FIXED32: u8 = 5;
+
+/// Protobuf message builder. Scalar fields equal to their default are
+/// skipped, as proto3 requires.
+#[derive(Debug, Default)]
+pub struct Encoder {
+    buf: Vec<u8>,
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn varint(&mut self, mut v: u64) {
+        while v >= 0x80 {
+            self.buf.push((v as u8) | 0x80);
+            v >>= 7;
+        }
+        self.buf.push(v as u8);
+    }
+
+    fn key(&mut self, field: u32, wire: u8) {
+        self.varint(u64::from(field << 3 | u32::from(wire)));
+    }
+
+    pub fn uint32(mut self, field: u32, v: u32) -> Self {
+        if v != 0 {
+            self.key(field, WIRE_VARINT);
+            self.varint(u64::from(v));
+        }
+        self
+    }
+
+    pub fn sint32(mut self, field: u32, v: i32) -> Self {
+        if v != 0 {
+            self.key(field, WIRE_VARINT);
+            self.varint(u64::from(((v << 1) ^ (v >> 31)) as u32));
+        }
+        self
+    }
+
+    pub fn int64(mut self, field: u32, v: i64) -> Self {
+        if v != 0 {
+            self.key(field, WIRE_VARINT);
+            self.varint(v as u64);
+        }
+        self
+    }
+
+    pub fn bool(mut self, field: u32, v: bool) -> Self {
+        if v {
+            self.key(field, WIRE_VARINT);
+            self.varint(1);
+        }
+        self
+    }
+
+    pub fn float(mut self, field: u32, v: f32) -> Self {
+        if v != 0.0 {
+            self.key(field, WIRE_FIXED32);
+            self.buf.extend_from_slice(&v.to_le_bytes());
+        }
+        self
+    }
+
+    pub fn double(mut self, field: u32, v: f64) -> Self {
+        if v != 0.0 {
+            self.key(field, WIRE_FIXED64);
+            self.buf.extend_from_slice(&v.to_le_bytes());
+        }
+        self
+    }
+
+    pub fn bytes(mut self, field: u32, v: &[u8]) -> Self {
+        if !v.is_empty() {
+            self.key(field, WIRE_LEN);
+            self.varint(v.len() as u64);
+            self.buf.extend_from_slice(v);
+        }
+        self
+    }
+
+    pub fn string(self, field: u32, v: &str) -> Self {
+        self.bytes(field, v.as_bytes())
+    }
+
+    /// Embedded message; always written so the field reads as present.
+    pub fn message(mut self, field: u32, msg: Encoder) -> Self {
+        self.key(field, WIRE_LEN);
+        self.varint(msg.buf.len() as u64);
+        self.buf.extend_from_slice(&msg.buf);
+        self
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+/// Raw field value as read from the wire.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    Bytes(&'a [u8]),
+    Fixed32(u32),
+}
+
+/// Decoded protobuf message (fields in wire order).
+#[derive(Debug, Default)]
+pub struct Message<'a> {
+    fields: Vec<(u32, Value<'a>)>,
+}
+
+fn read_varint(buf: &[u8], pos: &mut usize) -> Result<u64> {
+    let mut v = 0u64;
+    for shift in (0..64).step_by(7) {
+        let Some(&b) = buf.get(*pos) else {
+            bail!("truncated varint");
+        };
+        *pos += 1;
+        v |= u64::from(b & 0x7f) << shift;
+        if b & 0x80 == 0 {
+            return Ok(v);
+        }
+    }
+    bail!("varint too long")
+}
+
+fn take<'a>(buf: &'a [u8], pos: &mut usize, n: usize) -> Result<&'a [u8]> {
+    let end = pos.checked_add(n).filter(|&end| end <= buf.len());
+    let Some(end) = end else {
+        bail!("truncated field");
+    };
+    let slice = &buf[*pos..end];
+    *pos = end;
+    Ok(slice)
+}
+
+impl<'a> Message<'a> {
+    pub fn decode(buf: &'a [u8]) -> Result<Self> {
+        let mut fields = Vec::new();
+        let mut pos = 0;
+        while pos < buf.len() {
+            let key = read_varint(buf, &mut pos)?;
+            let field = u32::try_from(key >> 3)?;
+            let value = match (key & 7) as u8 {
+                WIRE_VARINT => Value::Varint(read_varint(buf, &mut pos)?),
+                WIRE_FIXED64 => {
+                    let b = take(buf, &mut pos, 8)?;
+                    Value::Fixed64(u64::from_le_bytes(b.try_into()?))
+                }
+                WIRE_LEN => {
+                    let len = usize::try_from(read_varint(buf, &mut pos)?)?;
+                    Value::Bytes(take(buf, &mut pos, len)?)
+                }
+                WIRE_FIXED32 => {
+                    let b = take(buf, &mut pos, 4)?;
+                    Value::Fixed32(u32::from_le_bytes(b.try_into()?))
+                }
+                wire => bail!("unsupported wire type {wire}"),
+            };
+            fields.push((field, value));
+        }
+        Ok(Self { fields })
+    }
+
+    /// Last value of a field (proto3 "last one wins").
+    fn get(&self, field: u32) -> Option<Value<'a>> {
+        self.fields
+            .iter()
+            .rev()
+            .find(|(f, _)| *f == field)
+            .map(|(_, v)| *v)
+    }
+
+    /// String field; `None` when absent or empty.
+    pub fn string(&self, field: u32) -> Result<Option<String>> {
+        match self.get(field) {
+            Some(Value::Bytes(b)) if !b.is_empty() => Ok(Some(std::str::from_utf8(b)?.into())),
+            Some(Value::Bytes(_)) | None => Ok(None),
+            Some(_) => bail!("field {field} is not a string"),
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// HTTP/2 transport
+// ---------------------------------------------------------------------------
+
+/// Prefix a message with the gRPC length-delimited frame header.
+fn frame(msg: &[u8]) -> Bytes {
+    let mut buf = BytesMut::with_capacity(5 + msg.len());
+    buf.put_u8(0);
+    buf.put_u32(msg.len() as u32);
+    buf.put_slice(msg);
+    buf.freeze()
+}
+
+fn response_head() -> http::Response<()> {
+    let mut response = http::Response::new(());
+    response
+        .headers_mut()
+        .insert("content-type", HeaderValue::from_static("application/grpc"));
+    response
+}
+
+/// One incoming RPC.
+pub struct Call {
+    /// Full method path, e.g. `/meshgrid.v1.MeshGrid/GetInfo`
+    pub path: String,
+    pub headers: HeaderMap,
+    body: RecvStream,
+    respond: SendResponse<Bytes>,
+}
+
+impl Call {
+    /// Bearer token from the `authorization` metadata, if any.
+    pub fn bearer_token(&self) -> Option<&str> {
+        self.headers
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+    }
+
+    /// Read the (single) request message.
+    pub async fn read_request(&mut self) -> std::result::Result<Vec<u8>, Status> {
+        let mut buf = Vec::new();
+        while let Some(chunk) = self.body.data().await {
+            let chunk = chunk.map_err(|e| Status::new(code::UNAVAILABLE, e.to_string()))?;
+            let _ = self.body.flow_control().release_capacity(chunk.len());
+            buf.extend_from_slice(&chunk);
+            if buf.len() > MAX_MESSAGE + 5 {
+                return Err(Status::new(code::INVALID_ARGUMENT, "request too large"));
+            }
+        }
+
+        if buf.is_empty() {
+            return Ok(Vec::new());
+        }
+        if buf.len() < 5 {
+            return Err(Status::new(code::INVALID_ARGUMENT, "truncated message"));
+        }
+        if buf[0] != 0 {
+            return Err(Status::new(
+                code::UNIMPLEMENTED,
+                "compressed messages are not supported",
+            ));
+        }
+        let len = u32::from_be_bytes([buf[1], buf[2], buf[3], buf[4]]) as usize;
+        if buf.len() != 5 + len {
+            return Err(Status::new(
+                code::INVALID_ARGUMENT,
+                "malformed message frame",
+            ));
+        }
+        Ok(buf.split_off(5))
+    }
+
+    /// Finish a unary call with one response message.
+    pub fn reply(mut self, msg: Vec<u8>) -> Result<()> {
+        let mut stream = self.respond.send_response(response_head(), false)?;
+        stream.send_data(frame(&msg), false)?;
+        stream.send_trailers(Status::ok().trailers())?;
+        Ok(())
+    }
+
+    /// Finish the call with an error status and no messages.
+    pub fn fail(mut self, status: &Status) -> Result<()> {
+        let mut response = response_head();
+        response.headers_mut().extend(status.trailers());
+        self.respond.send_response(response, true)?;
+        Ok(())
+    }
+
+    /// Start a server-streaming response.
+    pub fn start(mut self) -> Result<ServerStream> {
+        let send = self.respond.send_response(response_head(), false)?;
+        Ok(ServerStream { send })
+    }
+}
+
+/// Response stream of a server-streaming call.
+pub struct ServerStream {
+    send: SendStream<Bytes>,
+}
+
+impl ServerStream {
+    /// Queue one message on the stream.
+    pub fn send(&mut self, msg: &[u8]) -> Result<()> {
+        self.send.send_data(frame(msg), false)?;
+        Ok(())
+    }
+
+    /// Resolves when the client cancels the call.
+    pub async fn cancelled(&mut self) {
+        let _ = std::future::poll_fn(|cx| self.send.poll_reset(cx)).await;
+    }
+
+    pub fn finish(mut self, status: &Status) -> Result<()> {
+        self.send.send_trailers(status.trailers())?;
+        Ok(())
+    }
+}
+
+/// Serve gRPC calls from `listener` with `handler` until the task is dropped.
+pub async fn serve<F, Fut>(listener: TcpListener, handler: F) -> Result<()>
+where
+    F: Fn(Call) -> Fut + Clone + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let handler = handler.clone();
+        tokio::spawn(async move {
+            let mut conn = match h2::server::handshake(stream).await {
+                Ok(conn) => conn,
+                Err(e) => {
+                    tracing::debug!("HTTP/2 handshake with {peer} failed: {e}");
+                    return;
+                }
+            };
+            while let Some(request) = conn.accept().await {
+                let (request, mut respond) = match request {
+                    Ok(r) => r,
+                    Err(e) => {
+                        tracing::debug!("Connection from {peer} failed: {e}");
+                        break;
+                    }
+                };
+                let is_grpc = request
+                    .headers()
+                    .get("content-type")
+                    .and_then(|v| v.to_str().ok())
+                    .is_some_and(|ct| ct.starts_with("application/grpc"));
+                if request.method() != http::Method::POST || !is_grpc {
+                    let mut response = http::Response::new(());
+                    *response.status_mut() = StatusCode::UNSUPPORTED_MEDIA_TYPE;
+                    let _ = respond.send_response(response, true);
+                    continue;
+                }
+
+                let (parts, body) = request.into_parts();
+                let call = Call {
+                    path: parts.uri.path().to_string(),
+                    headers: parts.headers,
+                    body,
+                    respond,
+                };
+                tokio::spawn(handler(call));
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_protobuf_roundtrip() {
+        let msg = Encoder::new()
+            .string(1, "hello")
+            .sint32(2, -3)
+            .uint32(3, 0)
+            .message(4, Encoder::new().bool(1, true))
+            .finish();
+        assert_eq!(
+            msg,
+            [0x0a, 5, b'h', b'e', b'l', b'l', b'o', 0x10, 5, 0x22, 2, 0x08, 1]
+        );
+
+        let decoded = Message::decode(&msg).unwrap();
+        assert_eq!(decoded.string(1).unwrap().as_deref(), Some("hello"));
+        assert_eq!(decoded.string(3).unwrap(), None);
+        assert_eq!(decoded.get(2), Some(Value::Varint(5)));
+        assert!(Message::decode(&[0x0a, 5, b'h']).is_err());
+    }
+}
@@ -0,0 +1,102 @@
+//! xxd-style hex dumps with highlighted fields.
+//!
+//! ```text
+//! 00000000: 1100 0474 6573 7420 6d65 7373 6167 6520  ...test message
+//! ```
+
+use crossterm::style::{Color, Stylize};
+use std::fmt::Write as _;
+use std::ops::Range;
+
+/// Colors of highlighted fields, in order.
+const PALETTE: [Color; 5] = [
+    Color::Magenta,
+    Color::Cyan,
+    Color::Yellow,
+    Color::Green,
+    Color::Blue,
+];
+
+/// Dump `data` with `width` bytes per line. With `color`, bytes inside
+/// `fields` are colored by field.
+pub fn hexdump(data: &[u8], width: usize, fields: &[(&str, Range<usize>)], color: bool) -> String {
+    let width = width.max(1);
+    let paint = |at: usize, text: String| match fields.iter().position(|(_, r)| r.contains(&at)) {
+        Some(i) if color => text.with(PALETTE[i % PALETTE.len()]).to_string(),
+        _ => text,
+    };
+
+    let mut out = String::new();
+    for (line, chunk) in data.chunks(width).enumerate() {
+        let offset = line * width;
+        let _ = write!(out, "{offset:08x}: ");
+        for column in 0..width {
+            match chunk.get(column) {
+                Some(byte) => out.push_str(&paint(offset + column, format!("{byte:02x}"))),
+                None => out.push_str("  "),
+            }
+            if column % 2 == 1 {
+                out.push(' ');
+            }
+        }
+        if width % 2 == 1 {
+            out.push(' ');
+        }
+        out.push(' ');
+        for (column, &byte) in chunk.iter().enumerate() {
+            let c = if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                '.'
+            };
+            out.push_str(&paint(offset + column, c.to_string()));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Offsets of `fields`, e.g. `header 0, path length 1, payload 2-33`,
+/// colored like [`hexdump`] colors them.
+pub fn legend(fields: &[(&str, Range<usize>)], color: bool) -> String {
+    fields
+        .iter()
+        .enumerate()
+        .map(|(i, (name, range))| {
+            let text = if range.len() == 1 {
+                format!("{name} {}", range.start)
+            } else {
+                format!("{name} {}-{}", range.start, range.end - 1)
+            };
+            if color {
+                text.with(PALETTE[i % PALETTE.len()]).to_string()
+            } else {
+                text
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hexdump() {
+        let data = b"\x11\x00hello, mesh!\x00\xff";
+        assert_eq!(
+            hexdump(data, 8, &[], false),
+            "00000000: 1100 6865 6c6c 6f2c  ..hello,\n\
+             00000008: 206d 6573 6821 00ff   mesh!..\n"
+        );
+        assert_eq!(
+            hexdump(&data[..3], 5, &[], false),
+            "00000000: 1100 68       ..h\n"
+        );
+        assert_eq!(
+            legend(&[("header", 0..1), ("payload", 2..16)], false),
+            "header 0, payload 2-15"
+        );
+    }
+}
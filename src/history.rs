@@ -0,0 +1,380 @@
+//! Local message and event history.
+//!
+//! `monitor` and the terminal UI append every message they receive as one
+//! JSON line to `messages.log` next to the config file, for `channels stats`,
+//! and the other monitor events (adverts, acks, waypoints) to `events.log`.
+//! `telemetry` appends each sample to `telemetry.log`. `events export` merges
+//! these with the audit log into one journal. Set `record = false` under
+//! `[history]` in the config to turn recording off.
+
+use crate::audit;
+use crate::channels;
+use crate::error::CliError;
+use crate::protocol::{MonitorEvent, Telemetry};
+use crate::settings::Settings;
+use crate::vault;
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Local, NaiveDate, TimeDelta, TimeZone, Timelike};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+/// One received message.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub timestamp: String,
+    pub from: String,
+    /// Destination of a direct message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to: Option<String>,
+    /// Channel of a broadcast
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>,
+    pub rssi: i16,
+    pub text: String,
+}
+
+/// A monitor event or telemetry sample with the time it was recorded.
+#[derive(Serialize)]
+struct Stamped<'a, T: Serialize> {
+    timestamp: String,
+    #[serde(flatten)]
+    data: &'a T,
+}
+
+pub fn path() -> Result<PathBuf> {
+    Ok(Settings::path()?.with_file_name("messages.log"))
+}
+
+pub fn events_path() -> Result<PathBuf> {
+    Ok(Settings::path()?.with_file_name("events.log"))
+}
+
+pub fn telemetry_path() -> Result<PathBuf> {
+    Ok(Settings::path()?.with_file_name("telemetry.log"))
+}
+
+fn now() -> String {
+    Local::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, false)
+}
+
+/// Appends received messages, monitor events and telemetry samples to the
+/// history, unless disabled in the config.
+pub struct Recorder {
+    enabled: bool,
+}
+
+impl Recorder {
+    pub fn new(settings: &Settings) -> Self {
+        Self {
+            enabled: settings.history.record.unwrap_or(true),
+        }
+    }
+
+    /// Record `event`: messages in the message history, everything else in
+    /// the event history. After a failure (e.g. the vault is locked)
+    /// recording stops with a warning.
+    pub fn record(&mut self, event: &MonitorEvent) {
+        if !self.enabled {
+            return;
+        }
+        let result = match event {
+            MonitorEvent::Message {
+                from,
+                to,
+                channel,
+                rssi,
+                text,
+            } => path().and_then(|path| {
+                let entry = Entry {
+                    timestamp: now(),
+                    from: from.clone(),
+                    to: to.clone(),
+                    channel: channels::of_message(to.as_deref(), channel.as_deref())
+                        .map(String::from),
+                    rssi: *rssi,
+                    text: text.clone(),
+                };
+                append(&path, &entry)
+            }),
+            _ => events_path().and_then(|path| {
+                append(
+                    &path,
+                    &Stamped {
+                        timestamp: now(),
+                        data: event,
+                    },
+                )
+            }),
+        };
+        self.check(result);
+    }
+
+    /// Record a telemetry sample.
+    pub fn record_telemetry(&mut self, telemetry: &Telemetry) {
+        if !self.enabled {
+            return;
+        }
+        let result = telemetry_path().and_then(|path| {
+            append(
+                &path,
+                &Stamped {
+                    timestamp: now(),
+                    data: telemetry,
+                },
+            )
+        });
+        self.check(result);
+    }
+
+    fn check(&mut self, result: Result<()>) {
+        if let Err(e) = result {
+            tracing::warn!("Not recording history: {e:#}");
+            self.enabled = false;
+        }
+    }
+}
+
+fn append(path: &Path, entry: &impl Serialize) -> Result<()> {
+    vault::ensure_unlocked(path)?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    writeln!(file, "{}", serde_json::to_string(entry)?)?;
+    Ok(())
+}
+
+/// All entries, oldest first. Unreadable lines are skipped.
+pub fn read() -> Result<Vec<Entry>> {
+    Ok(read_lines(&path()?)?
+        .into_iter()
+        .filter_map(|line| match serde_json::from_str(&line) {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                tracing::debug!("Skipping history line: {e}");
+                None
+            }
+        })
+        .collect())
+}
+
+/// Non-empty lines of a history file (none when it doesn't exist yet).
+fn read_lines(path: &Path) -> Result<Vec<String>> {
+    vault::ensure_unlocked(path)?;
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    let mut lines = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            lines.push(line);
+        }
+    }
+    Ok(lines)
+}
+
+/// The message, event, telemetry and audit histories merged into one
+/// journal, oldest first, from `since` on. Each record is tagged with its
+/// `source` (`monitor`, `telemetry` or `audit`); monitor records carry the
+/// event `type`.
+pub fn journal(since: Option<DateTime<Local>>) -> Result<Vec<serde_json::Value>> {
+    let sources = [
+        (path()?, "monitor", Some("message")),
+        (events_path()?, "monitor", None),
+        (telemetry_path()?, "telemetry", None),
+        (audit::path()?, "audit", None),
+    ];
+    let mut records = Vec::new();
+    for (path, source, kind) in sources {
+        for line in read_lines(&path)? {
+            let Ok(serde_json::Value::Object(fields)) = serde_json::from_str(&line) else {
+                tracing::debug!("Skipping {} line", path.display());
+                continue;
+            };
+            let Some(time) = fields
+                .get("timestamp")
+                .and_then(|t| t.as_str())
+                .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+            else {
+                continue;
+            };
+            if since.is_some_and(|since| time < since) {
+                continue;
+            }
+            records.push((time, tag(fields, source, kind)));
+        }
+    }
+    records.sort_by_key(|(time, _)| *time);
+    Ok(records.into_iter().map(|(_, record)| record).collect())
+}
+
+fn tag(
+    mut fields: serde_json::Map<String, serde_json::Value>,
+    source: &str,
+    kind: Option<&str>,
+) -> serde_json::Value {
+    fields.insert("source".into(), source.into());
+    if let Some(kind) = kind {
+        fields.insert("type".into(), kind.into());
+    }
+    serde_json::Value::Object(fields)
+}
+
+/// Start of a `--since` period: `30m`, `24h`, `7d`, `YYYY-MM-DD` or RFC 3339.
+pub fn parse_since(s: &str) -> Result<DateTime<Local>> {
+    let invalid = || {
+        CliError::InvalidArgs(format!(
+            "Invalid time '{s}' (use 24h, 7d, YYYY-MM-DD or RFC 3339)"
+        ))
+    };
+    if let Ok(t) = DateTime::parse_from_rfc3339(s) {
+        return Ok(t.with_timezone(&Local));
+    }
+    if let Ok(day) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return Local
+            .from_local_datetime(&day.and_time(chrono::NaiveTime::MIN))
+            .earliest()
+            .ok_or_else(|| invalid().into());
+    }
+    if !s.is_ascii() || s.len() < 2 {
+        bail!(invalid());
+    }
+    let (count, unit) = s.split_at(s.len() - 1);
+    let count: i32 = count.parse().map_err(|_| invalid())?;
+    let unit = match unit {
+        "m" => TimeDelta::minutes(1),
+        "h" => TimeDelta::hours(1),
+        "d" => TimeDelta::days(1),
+        _ => bail!(invalid()),
+    };
+    Ok(Local::now() - unit * count)
+}
+
+/// Activity of one channel ("DM" for direct messages).
+#[derive(Debug, Default, Serialize)]
+pub struct ChannelStats {
+    pub messages: u32,
+    pub senders: usize,
+    /// Mean RSSI (dBm)
+    pub avg_rssi: f64,
+    /// Messages by local hour of day
+    pub hourly: [u32; 24],
+}
+
+impl ChannelStats {
+    /// The `n` hours with the most messages, busiest first.
+    pub fn busiest_hours(&self, n: usize) -> Vec<(u32, u32)> {
+        let mut hours: Vec<(u32, u32)> = (0..24)
+            .map(|h| (h, self.hourly[h as usize]))
+            .filter(|(_, count)| *count > 0)
+            .collect();
+        hours.sort_by_key(|(hour, count)| (std::cmp::Reverse(*count), *hour));
+        hours.truncate(n);
+        hours
+    }
+}
+
+/// Per-channel statistics of the entries received at or after `since`.
+pub fn stats(entries: &[Entry], since: Option<DateTime<Local>>) -> BTreeMap<String, ChannelStats> {
+    let mut stats: BTreeMap<String, ChannelStats> = BTreeMap::new();
+    let mut senders: BTreeMap<String, HashSet<&str>> = BTreeMap::new();
+    let mut rssi_sums: BTreeMap<String, i64> = BTreeMap::new();
+    for entry in entries {
+        let Ok(time) = DateTime::parse_from_rfc3339(&entry.timestamp) else {
+            continue;
+        };
+        let time = time.with_timezone(&Local);
+        if since.is_some_and(|since| time < since) {
+            continue;
+        }
+        let channel = entry.channel.clone().unwrap_or_else(|| "DM".into());
+        let channel_stats = stats.entry(channel.clone()).or_default();
+        channel_stats.messages += 1;
+        channel_stats.hourly[time.hour() as usize] += 1;
+        senders
+            .entry(channel.clone())
+            .or_default()
+            .insert(&entry.from);
+        *rssi_sums.entry(channel).or_default() += i64::from(entry.rssi);
+    }
+    for (channel, channel_stats) in &mut stats {
+        channel_stats.senders = senders[channel].len();
+        channel_stats.avg_rssi = rssi_sums[channel] as f64 / f64::from(channel_stats.messages);
+    }
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats() {
+        let at = |hour: u32, minute: u32| {
+            Local
+                .with_ymd_and_hms(2026, 1, 12, hour, minute, 0)
+                .unwrap()
+                .to_rfc3339()
+        };
+        let entry = |timestamp: String, from: &str, channel: Option<&str>, rssi| Entry {
+            timestamp,
+            from: from.into(),
+            to: channel.is_none().then(|| "0x11".into()),
+            channel: channel.map(String::from),
+            rssi,
+            text: "hi".into(),
+        };
+        let entries = [
+            entry(at(7, 0), "alice", Some("Public"), -100),
+            entry(at(18, 5), "alice", Some("Public"), -90),
+            entry(at(18, 40), "bob", Some("Public"), -80),
+            entry(at(9, 15), "carol", Some("Public"), -70),
+            entry(at(18, 50), "carol", None, -60),
+        ];
+        let since = Local.with_ymd_and_hms(2026, 1, 12, 8, 0, 0).unwrap();
+        let stats = stats(&entries, Some(since));
+
+        let public = &stats["Public"];
+        assert_eq!((public.messages, public.senders), (3, 3));
+        assert!((public.avg_rssi + 80.0).abs() < 1e-9);
+        assert_eq!(public.busiest_hours(2), [(18, 2), (9, 1)]);
+        assert_eq!(stats["DM"].messages, 1);
+
+        assert!(parse_since("24h").unwrap() < Local::now());
+        assert!(parse_since("2026-01-12").is_ok());
+        assert!(parse_since("soon").is_err());
+    }
+
+    #[test]
+    fn test_journal_record() {
+        let event = MonitorEvent::Ack {
+            from: "0x42".into(),
+        };
+        let line = serde_json::to_string(&Stamped {
+            timestamp: "2026-01-12T07:00:00+01:00".into(),
+            data: &event,
+        })
+        .unwrap();
+        let serde_json::Value::Object(fields) = serde_json::from_str(&line).unwrap() else {
+            panic!("not an object: {line}");
+        };
+        assert_eq!(
+            tag(fields, "monitor", None),
+            serde_json::json!({
+                "timestamp": "2026-01-12T07:00:00+01:00",
+                "source": "monitor",
+                "type": "ack",
+                "from": "0x42",
+            })
+        );
+    }
+}
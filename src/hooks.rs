@@ -0,0 +1,235 @@
+//! Event hooks.
+//!
+//! Runs user-configured shell commands (see `[hooks]` in the CLI config) when
+//! mesh events occur. Event details are passed as `MESHGRID_*` environment
+//! variables rather than substituted into the command line, so message text
+//! from the mesh can never be interpreted by the shell.
+
+use crate::settings::HooksConfig;
+
+/// Events that can trigger a hook.
+#[derive(Debug, Clone)]
+pub enum HookEvent {
+    MessageReceived {
+        from: String,
+        to: Option<String>,
+        /// Channel of a broadcast (`None` for direct messages)
+        channel: Option<String>,
+        /// Whether the channel has priority (see `channels config`)
+        priority: bool,
+        text: String,
+        rssi: i16,
+    },
+    NodeAppeared {
+        node_hash: u8,
+        name: Option<String>,
+        rssi: i16,
+    },
+    BatteryLow {
+        percent: u8,
+        voltage: f32,
+    },
+    AckTimeout {
+        to: String,
+        text: String,
+        timeout_secs: u64,
+    },
+    /// A message from the outbox was sent when its destination reappeared
+    QueuedDelivered {
+        to: String,
+        text: String,
+        queued_secs: u64,
+    },
+}
+
+impl HookEvent {
+    /// Event name as used in the config file and `MESHGRID_EVENT`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            HookEvent::MessageReceived { .. } => "message",
+            HookEvent::NodeAppeared { .. } => "node_appeared",
+            HookEvent::BatteryLow { .. } => "battery_low",
+            HookEvent::AckTimeout { .. } => "ack_timeout",
+            HookEvent::QueuedDelivered { .. } => "queued_delivered",
+        }
+    }
+
+    /// Environment variables describing this event.
+    pub fn env(&self) -> Vec<(&'static str, String)> {
+        let mut vars = vec![
+            ("MESHGRID_EVENT", self.name().to_string()),
+            ("MESHGRID_TIMESTAMP", chrono::Local::now().to_rfc3339()),
+        ];
+        match self {
+            HookEvent::MessageReceived {
+                from,
+                to,
+                channel,
+                priority,
+                text,
+                rssi,
+            } => {
+                vars.push(("MESHGRID_FROM", from.clone()));
+                vars.push(("MESHGRID_TO", to.clone().unwrap_or_else(|| "*".into())));
+                if let Some(channel) = channel {
+                    vars.push(("MESHGRID_CHANNEL", channel.clone()));
+                }
+                vars.push(("MESHGRID_PRIORITY", u8::from(*priority).to_string()));
+                vars.push(("MESHGRID_TEXT", text.clone()));
+                vars.push(("MESHGRID_RSSI", rssi.to_string()));
+            }
+            HookEvent::NodeAppeared {
+                node_hash,
+                name,
+                rssi,
+            } => {
+                vars.push(("MESHGRID_NODE_HASH", format!("{node_hash:02x}")));
+                vars.push((
+                    "MESHGRID_NODE_NAME",
+                    name.clone().unwrap_or_else(|| format!("0x{node_hash:02x}")),
+                ));
+                vars.push(("MESHGRID_RSSI", rssi.to_string()));
+            }
+            HookEvent::BatteryLow { percent, voltage } => {
+                vars.push(("MESHGRID_BATTERY", percent.to_string()));
+                vars.push(("MESHGRID_VOLTAGE", format!("{voltage:.2}")));
+            }
+            HookEvent::AckTimeout {
+                to,
+                text,
+                timeout_secs,
+            } => {
+                vars.push(("MESHGRID_TO", to.clone()));
+                vars.push(("MESHGRID_TEXT", text.clone()));
+                vars.push(("MESHGRID_TIMEOUT", timeout_secs.to_string()));
+            }
+            HookEvent::QueuedDelivered {
+                to,
+                text,
+                queued_secs,
+            } => {
+                vars.push(("MESHGRID_TO", to.clone()));
+                vars.push(("MESHGRID_TEXT", text.clone()));
+                vars.push(("MESHGRID_QUEUED", queued_secs.to_string()));
+            }
+        }
+        vars
+    }
+}
+
+/// Runs hook commands for events.
+pub struct HookRunner {
+    config: HooksConfig,
+}
+
+impl HookRunner {
+    pub fn new(config: HooksConfig) -> Self {
+        Self { config }
+    }
+
+    /// Battery threshold for the `battery_low` hook.
+    pub fn battery_low_percent(&self) -> u8 {
+        self.config.battery_low_percent.unwrap_or(20)
+    }
+
+    fn command_for(&self, event: &HookEvent) -> Option<&str> {
+        match event {
+            HookEvent::MessageReceived { .. } => self.config.message.as_deref(),
+            HookEvent::NodeAppeared { .. } => self.config.node_appeared.as_deref(),
+            HookEvent::BatteryLow { .. } => self.config.battery_low.as_deref(),
+            HookEvent::AckTimeout { .. } => self.config.ack_timeout.as_deref(),
+            HookEvent::QueuedDelivered { .. } => self.config.queued_delivered.as_deref(),
+        }
+    }
+
+    /// Run the hook for an event, if one is configured.
+    ///
+    /// The command runs in the background; its exit status is logged.
+    pub fn fire(&self, event: &HookEvent) {
+        if let Some(cmd) = self.command_for(event) {
+            spawn(event.name(), cmd, event.env());
+        }
+    }
+}
+
+/// Run shell command `cmd` in the background with `env`, logging its exit
+/// status under `name`.
+pub fn spawn(name: &str, cmd: &str, env: Vec<(&'static str, String)>) {
+    let name = name.to_string();
+    let cmd = cmd.to_string();
+    tokio::spawn(async move {
+        match run(&cmd, env).await {
+            Ok(status) if !status.success() => {
+                tracing::warn!("Hook '{name}' exited with {status}");
+            }
+            Err(e) => tracing::warn!("Failed to run hook '{name}': {e}"),
+            Ok(_) => {}
+        }
+    });
+}
+
+/// Run shell command `cmd` with `env` and wait for it to exit.
+async fn run(
+    cmd: &str,
+    env: Vec<(&'static str, String)>,
+) -> std::io::Result<std::process::ExitStatus> {
+    shell_command(cmd).envs(env).status().await
+}
+
+/// Build a platform shell invocation for a hook command line.
+fn shell_command(cmd: &str) -> tokio::process::Command {
+    #[cfg(windows)]
+    {
+        let mut c = tokio::process::Command::new("cmd");
+        c.args(["/C", cmd]);
+        c
+    }
+    #[cfg(not(windows))]
+    {
+        let mut c = tokio::process::Command::new("sh");
+        c.args(["-c", cmd]);
+        c
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_hook_script_env_and_status() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("env.txt");
+        let script = dir.path().join("hook.sh");
+        std::fs::write(
+            &script,
+            "#!/bin/sh\n\
+             printf '%s|%s|%s|%s\\n' \"$MESHGRID_EVENT\" \"$MESHGRID_FROM\" \
+                 \"$MESHGRID_CHANNEL\" \"$MESHGRID_TEXT\" > \"$OUT\"\n\
+             exit 3\n",
+        )
+        .unwrap();
+
+        let event = HookEvent::MessageReceived {
+            from: "alice".into(),
+            to: None,
+            channel: Some("ops".into()),
+            priority: false,
+            text: "hi $(touch pwned) `touch pwned`".into(),
+            rssi: -90,
+        };
+        let mut env = event.env();
+        env.push(("OUT", out.display().to_string()));
+        let cmd = format!("cd '{}' && sh hook.sh", dir.path().display());
+        let status = run(&cmd, env).await.unwrap();
+
+        assert_eq!(status.code(), Some(3));
+        assert_eq!(
+            std::fs::read_to_string(&out).unwrap(),
+            "message|alice|ops|hi $(touch pwned) `touch pwned`\n"
+        );
+        // Message text reaches the hook as data, never as shell code
+        assert!(!dir.path().join("pwned").exists());
+    }
+}
@@ -0,0 +1,182 @@
+//! Minimal HTTP/1.1 server for local endpoints.
+//!
+//! Serves one request per connection (`Connection: close`), which is all the
+//! small local APIs in this tool need.
+
+use anyhow::{bail, Result};
+use std::future::Future;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Largest accepted request (headers + body).
+const MAX_REQUEST: usize = 1024 * 1024;
+
+/// Parsed HTTP request.
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+    /// Path without the query string
+    pub path: String,
+    /// Header names are lowercased
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// HTTP response.
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16, content_type: &'static str, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status,
+            content_type,
+            headers: Vec::new(),
+            body: body.into(),
+        }
+    }
+
+    pub fn text(status: u16, body: impl Into<String>) -> Self {
+        Self::new(status, "text/plain; charset=utf-8", body.into())
+    }
+
+    pub fn json(status: u16, value: &serde_json::Value) -> Self {
+        Self::new(status, "application/json", value.to_string())
+    }
+
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        202 => "Accepted",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        500 => "Internal Server Error",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+/// Read and parse one request from a connection.
+pub async fn read_request(stream: &mut TcpStream) -> Result<Request> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 4096];
+
+    loop {
+        let n = stream.read(&mut chunk).await?;
+        if n == 0 {
+            bail!("Connection closed before request was complete");
+        }
+        buf.extend_from_slice(&chunk[..n]);
+        if buf.len() > MAX_REQUEST {
+            bail!("Request too large");
+        }
+
+        let mut headers = [httparse::EMPTY_HEADER; 64];
+        let mut parsed = httparse::Request::new(&mut headers);
+        let httparse::Status::Complete(header_len) = parsed.parse(&buf)? else {
+            continue;
+        };
+
+        let headers: Vec<(String, String)> = parsed
+            .headers
+            .iter()
+            .map(|h| {
+                (
+                    h.name.to_ascii_lowercase(),
+                    String::from_utf8_lossy(h.value).into_owned(),
+                )
+            })
+            .collect();
+        let content_length = headers
+            .iter()
+            .find(|(n, _)| n == "content-length")
+            .and_then(|(_, v)| v.trim().parse::<usize>().ok())
+            .unwrap_or(0);
+        if header_len + content_length > MAX_REQUEST {
+            bail!("Request too large");
+        }
+        if buf.len() < header_len + content_length {
+            continue;
+        }
+
+        let target = parsed.path.unwrap_or("/");
+        let path = target.split_once('?').map_or(target, |(path, _)| path);
+        return Ok(Request {
+            method: parsed.method.unwrap_or("GET").to_string(),
+            path: path.to_string(),
+            headers,
+            body: buf[header_len..header_len + content_length].to_vec(),
+        });
+    }
+}
+
+/// Write `response` and close out the exchange (`Connection: close`).
+pub async fn write_response(stream: &mut TcpStream, response: &Response) -> Result<()> {
+    let mut head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status,
+        reason(response.status),
+        response.content_type,
+        response.body.len()
+    );
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    head.push_str("\r\n");
+
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(&response.body).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+/// Serve requests from `listener` with `handler` until the task is dropped.
+pub async fn serve<F, Fut>(listener: TcpListener, handler: F) -> Result<()>
+where
+    F: Fn(Request) -> Fut + Clone + Send + Sync + 'static,
+    Fut: Future<Output = Response> + Send,
+{
+    loop {
+        let (mut stream, peer) = listener.accept().await?;
+        let handler = handler.clone();
+        tokio::spawn(async move {
+            let response = match read_request(&mut stream).await {
+                Ok(request) => handler(request).await,
+                Err(e) => {
+                    tracing::debug!("Bad request from {peer}: {e}");
+                    Response::text(400, "Bad request\n")
+                }
+            };
+            if let Err(e) = write_response(&mut stream, &response).await {
+                tracing::debug!("Failed to write response to {peer}: {e}");
+            }
+        });
+    }
+}
@@ -0,0 +1,246 @@
+//! Forwarding device log lines to syslog or the systemd journal.
+//!
+//! Syslog messages are RFC 5424, sent over UDP (one message per datagram) or
+//! TCP (octet-counted, RFC 6587), with the device name as the host name and
+//! node, level and subsystem as structured data. Journal entries go through
+//! journald's native socket with `MESHGRID_*` fields.
+
+use crate::error::CliError;
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, SecondsFormat, Utc};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpStream, UdpSocket};
+
+/// Syslog facility of forwarded messages (local0).
+const FACILITY: u8 = 16;
+
+/// SD-ID of the structured data element (enterprise number reserved for
+/// documentation, as RFC 5424 suggests for private use).
+const SD_ID: &str = "meshgrid@32473";
+
+/// One device log line with its origin.
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub time: DateTime<Utc>,
+    pub node: String,
+    pub node_hash: u8,
+    pub level: Option<String>,
+    pub subsystem: Option<String>,
+    pub msg: String,
+}
+
+impl Record {
+    /// Syslog severity of the line's level (informational when unknown).
+    fn severity(&self) -> u8 {
+        match self
+            .level
+            .as_deref()
+            .unwrap_or_default()
+            .to_ascii_uppercase()
+            .as_str()
+        {
+            "FATAL" | "CRIT" | "CRITICAL" => 2,
+            "ERROR" | "ERR" | "E" => 3,
+            "WARN" | "WARNING" | "W" => 4,
+            "DEBUG" | "TRACE" | "VERBOSE" | "D" | "V" => 7,
+            _ => 6,
+        }
+    }
+}
+
+/// Where records are forwarded to.
+pub enum Sink {
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+    #[cfg(target_os = "linux")]
+    Journald(tokio::net::UnixDatagram),
+}
+
+impl Sink {
+    /// Connect to a syslog server: `udp://host[:514]` or `tcp://host[:601]`.
+    pub async fn syslog(spec: &str) -> Result<Self> {
+        let invalid = || {
+            CliError::InvalidArgs(format!(
+                "Invalid syslog address '{spec}' (use udp://host:514 or tcp://host:601)"
+            ))
+        };
+        let (scheme, rest) = spec.split_once("://").ok_or_else(invalid)?;
+        let default_port = match scheme {
+            "udp" => 514,
+            "tcp" => 601,
+            _ => bail!(invalid()),
+        };
+        let addr = match rest.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {
+                rest.to_string()
+            }
+            None if !rest.is_empty() => format!("{rest}:{default_port}"),
+            _ => bail!(invalid()),
+        };
+        if scheme == "udp" {
+            let socket = UdpSocket::bind("0.0.0.0:0").await?;
+            socket
+                .connect(&addr)
+                .await
+                .with_context(|| format!("Failed to resolve {addr}"))?;
+            Ok(Self::Udp(socket))
+        } else {
+            let stream = TcpStream::connect(&addr)
+                .await
+                .with_context(|| format!("Failed to connect to {addr}"))?;
+            Ok(Self::Tcp(stream))
+        }
+    }
+
+    /// Connect to the systemd journal.
+    #[cfg(target_os = "linux")]
+    pub fn journald() -> Result<Self> {
+        let socket = tokio::net::UnixDatagram::unbound()?;
+        socket
+            .connect("/run/systemd/journal/socket")
+            .context("Failed to connect to journald (is systemd-journald running?)")?;
+        Ok(Self::Journald(socket))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn journald() -> Result<Self> {
+        bail!(CliError::InvalidArgs(
+            "journald is only available on Linux".into()
+        ))
+    }
+
+    pub async fn send(&mut self, record: &Record) -> Result<()> {
+        match self {
+            Self::Udp(socket) => {
+                socket.send(syslog_message(record).as_bytes()).await?;
+            }
+            Self::Tcp(stream) => {
+                let message = syslog_message(record);
+                stream
+                    .write_all(format!("{} {message}", message.len()).as_bytes())
+                    .await?;
+            }
+            #[cfg(target_os = "linux")]
+            Self::Journald(socket) => {
+                socket.send(&journal_entry(record)).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// RFC 5424 message:
+/// `<PRI>1 TIMESTAMP HOSTNAME meshgrid - MSGID [SD] MSG`.
+pub fn syslog_message(record: &Record) -> String {
+    let pri = u16::from(FACILITY) * 8 + u16::from(record.severity());
+    let mut sd = format!(
+        "[{SD_ID} node=\"{}\" hash=\"0x{:02x}\"",
+        sd_escape(&record.node),
+        record.node_hash
+    );
+    for (name, value) in [("level", &record.level), ("subsystem", &record.subsystem)] {
+        if let Some(value) = value {
+            sd.push_str(&format!(" {name}=\"{}\"", sd_escape(value)));
+        }
+    }
+    sd.push(']');
+    format!(
+        "<{pri}>1 {} {} meshgrid - {} {sd} {}",
+        record.time.to_rfc3339_opts(SecondsFormat::Millis, true),
+        header_field(&record.node, 255),
+        record
+            .subsystem
+            .as_deref()
+            .map_or_else(|| "-".to_string(), |s| header_field(s, 32)),
+        record.msg
+    )
+}
+
+/// A header field: printable ASCII without spaces, `-` when empty.
+fn header_field(value: &str, max: usize) -> String {
+    let field: String = value
+        .chars()
+        .filter(|c| c.is_ascii_graphic())
+        .take(max)
+        .collect();
+    if field.is_empty() {
+        "-".into()
+    } else {
+        field
+    }
+}
+
+fn sd_escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace(']', "\\]")
+}
+
+/// journald native protocol datagram: `FIELD=value` lines, with values
+/// containing newlines length-prefixed.
+#[cfg(target_os = "linux")]
+fn journal_entry(record: &Record) -> Vec<u8> {
+    let mut entry = Vec::new();
+    let mut field = |name: &str, value: &str| {
+        if value.contains('\n') {
+            entry.extend_from_slice(name.as_bytes());
+            entry.push(b'\n');
+            entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+            entry.extend_from_slice(value.as_bytes());
+        } else {
+            entry.extend_from_slice(format!("{name}={value}").as_bytes());
+        }
+        entry.push(b'\n');
+    };
+    field("MESSAGE", &record.msg);
+    field("PRIORITY", &record.severity().to_string());
+    field("SYSLOG_FACILITY", &FACILITY.to_string());
+    field("SYSLOG_IDENTIFIER", "meshgrid");
+    field(
+        "MESHGRID_TIMESTAMP",
+        &record.time.to_rfc3339_opts(SecondsFormat::Millis, true),
+    );
+    field("MESHGRID_NODE", &record.node);
+    field("MESHGRID_NODE_HASH", &format!("0x{:02x}", record.node_hash));
+    if let Some(level) = &record.level {
+        field("MESHGRID_LEVEL", level);
+    }
+    if let Some(subsystem) = &record.subsystem {
+        field("MESHGRID_SUBSYSTEM", subsystem);
+    }
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_syslog_message() {
+        let record = Record {
+            time: DateTime::parse_from_rfc3339("2026-01-12T15:00:00.250Z")
+                .unwrap()
+                .with_timezone(&Utc),
+            node: "Hill Top \"1\"".into(),
+            node_hash: 0x42,
+            level: Some("WARN".into()),
+            subsystem: Some("radio".into()),
+            msg: "tx queue full".into(),
+        };
+        assert_eq!(
+            syslog_message(&record),
+            "<132>1 2026-01-12T15:00:00.250Z HillTop\"1\" meshgrid - radio \
+             [meshgrid@32473 node=\"Hill Top \\\"1\\\"\" hash=\"0x42\" level=\"WARN\" \
+             subsystem=\"radio\"] tx queue full"
+        );
+
+        let record = Record {
+            level: None,
+            subsystem: None,
+            ..record
+        };
+        assert!(syslog_message(&record).starts_with("<134>1 "));
+        assert!(syslog_message(&record).contains(" meshgrid - - [meshgrid@32473 "));
+    }
+}
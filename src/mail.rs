@@ -0,0 +1,596 @@
+//! Minimal SMTP and IMAP clients for the email bridge.
+//!
+//! Only what the bridge needs: sending a plain-text message, and fetching and
+//! marking unseen messages in the inbox. Servers are given as `host[:port]` or
+//! with a scheme: `smtps://`/`imaps://` for implicit TLS, `smtp://`/`imap://`
+//! for plaintext upgraded with STARTTLS when the server offers it. Without a
+//! scheme, ports 465 and 993 use implicit TLS. Credentials are never sent
+//! over an unencrypted connection to a non-loopback host.
+
+use crate::serial::Transport;
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose, Engine as _};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls;
+
+/// Mail server address.
+#[derive(Debug, Clone)]
+pub struct Server {
+    pub host: String,
+    pub port: u16,
+    /// TLS from the first byte (as opposed to STARTTLS)
+    pub implicit_tls: bool,
+}
+
+impl Server {
+    /// Parse `[scheme://]host[:port]` with the given default ports.
+    fn parse(spec: &str, scheme: &str, plain_port: u16, tls_port: u16) -> Result<Self> {
+        let (implicit_tls, rest) = if let Some(rest) = spec.strip_prefix(&format!("{scheme}s://")) {
+            (Some(true), rest)
+        } else if let Some(rest) = spec.strip_prefix(&format!("{scheme}://")) {
+            (Some(false), rest)
+        } else {
+            (None, spec)
+        };
+
+        let (host, port) = match rest.rsplit_once(':') {
+            Some((host, port)) => (
+                host,
+                Some(
+                    port.parse::<u16>()
+                        .with_context(|| format!("Invalid port in '{spec}'"))?,
+                ),
+            ),
+            None => (rest, None),
+        };
+        if host.is_empty() {
+            bail!("Missing host in '{spec}'");
+        }
+
+        let implicit_tls = implicit_tls.unwrap_or(port == Some(tls_port));
+        let port = port.unwrap_or(if implicit_tls { tls_port } else { plain_port });
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            implicit_tls,
+        })
+    }
+
+    /// Parse an SMTP server (default port 587, implicit TLS on 465).
+    pub fn smtp(spec: &str) -> Result<Self> {
+        Self::parse(spec, "smtp", 587, 465)
+    }
+
+    /// Parse an IMAP server (default port 143, implicit TLS on 993).
+    pub fn imap(spec: &str) -> Result<Self> {
+        Self::parse(spec, "imap", 143, 993)
+    }
+
+    fn is_loopback(&self) -> bool {
+        self.host == "localhost"
+            || self
+                .host
+                .parse::<std::net::IpAddr>()
+                .is_ok_and(|ip| ip.is_loopback())
+    }
+}
+
+/// Login credentials.
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    pub user: String,
+    pub password: String,
+}
+
+/// Start a TLS session (verified against the webpki roots) over `stream`.
+pub async fn tls_connect(host: &str, stream: Box<dyn Transport>) -> Result<Box<dyn Transport>> {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    let config = rustls::ClientConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()?
+    .with_root_certificates(roots)
+    .with_no_client_auth();
+
+    let name = rustls::pki_types::ServerName::try_from(host.to_string())
+        .map_err(|_| anyhow!("Invalid server name '{host}'"))?;
+    let stream = tokio_rustls::TlsConnector::from(Arc::new(config))
+        .connect(name, stream)
+        .await
+        .with_context(|| format!("TLS handshake with {host} failed"))?;
+    Ok(Box::new(stream))
+}
+
+/// Line-oriented connection that can be upgraded to TLS.
+struct Connection {
+    stream: BufReader<Box<dyn Transport>>,
+    tls: bool,
+}
+
+impl Connection {
+    async fn open(server: &Server) -> Result<Self> {
+        let tcp = TcpStream::connect((server.host.as_str(), server.port))
+            .await
+            .with_context(|| format!("Failed to connect to {}:{}", server.host, server.port))?;
+        let mut conn = Self {
+            stream: BufReader::new(Box::new(tcp)),
+            tls: false,
+        };
+        if server.implicit_tls {
+            conn = conn.upgrade(&server.host).await?;
+        }
+        Ok(conn)
+    }
+
+    /// Wrap the connection in TLS.
+    async fn upgrade(self, host: &str) -> Result<Self> {
+        let stream = tls_connect(host, self.stream.into_inner()).await?;
+        Ok(Self {
+            stream: BufReader::new(stream),
+            tls: true,
+        })
+    }
+
+    async fn read_line(&mut self) -> Result<String> {
+        let mut line = String::new();
+        if self.stream.read_line(&mut line).await? == 0 {
+            bail!("Connection closed by server");
+        }
+        Ok(line.trim_end_matches(['\r', '\n']).to_string())
+    }
+
+    async fn read_exact(&mut self, len: usize) -> Result<Vec<u8>> {
+        let mut buf = vec![0u8; len];
+        self.stream.read_exact(&mut buf).await?;
+        Ok(buf)
+    }
+
+    async fn write_line(&mut self, line: &str) -> Result<()> {
+        let stream = self.stream.get_mut();
+        stream.write_all(line.as_bytes()).await?;
+        stream.write_all(b"\r\n").await?;
+        stream.flush().await?;
+        Ok(())
+    }
+}
+
+/// Outgoing plain-text email.
+#[derive(Debug, Clone)]
+pub struct OutgoingMail {
+    pub from: String,
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+impl OutgoingMail {
+    /// RFC 5322 message with CRLF line endings and dot-stuffing applied.
+    fn to_data(&self) -> String {
+        let mut data = format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMIME-Version: 1.0\r\n\
+             Content-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n",
+            self.from,
+            self.to,
+            self.subject,
+            chrono::Local::now().to_rfc2822(),
+        );
+        for line in self.body.lines() {
+            if line.starts_with('.') {
+                data.push('.');
+            }
+            data.push_str(line);
+            data.push_str("\r\n");
+        }
+        data
+    }
+}
+
+/// Read an SMTP reply, returning its code and the text of all lines.
+async fn smtp_reply(conn: &mut Connection) -> Result<(u16, String)> {
+    let mut text = String::new();
+    loop {
+        let line = conn.read_line().await?;
+        let code = line
+            .get(..3)
+            .and_then(|c| c.parse::<u16>().ok())
+            .ok_or_else(|| anyhow!("Malformed SMTP reply: {line}"))?;
+        text.push_str(line.get(4..).unwrap_or(""));
+        text.push('\n');
+        if line.as_bytes().get(3) != Some(&b'-') {
+            return Ok((code, text));
+        }
+    }
+}
+
+/// Send a command and require a reply code in `expected`.
+async fn smtp_expect(conn: &mut Connection, cmd: &str, expected: &[u16]) -> Result<String> {
+    conn.write_line(cmd).await?;
+    let (code, text) = smtp_reply(conn).await?;
+    if !expected.contains(&code) {
+        let verb = cmd.split_whitespace().next().unwrap_or(cmd);
+        bail!("SMTP {verb} failed: {code} {}", text.trim());
+    }
+    Ok(text)
+}
+
+/// Send one email.
+pub async fn send_mail(
+    server: &Server,
+    credentials: Option<&Credentials>,
+    mail: &OutgoingMail,
+) -> Result<()> {
+    let mut conn = Connection::open(server).await?;
+    let (code, text) = smtp_reply(&mut conn).await?;
+    if code != 220 {
+        bail!("SMTP server not ready: {code} {}", text.trim());
+    }
+
+    let extensions = smtp_expect(&mut conn, "EHLO meshgrid", &[250]).await?;
+    if !conn.tls
+        && extensions
+            .lines()
+            .any(|l| l.eq_ignore_ascii_case("STARTTLS"))
+    {
+        smtp_expect(&mut conn, "STARTTLS", &[220]).await?;
+        conn = conn.upgrade(&server.host).await?;
+        smtp_expect(&mut conn, "EHLO meshgrid", &[250]).await?;
+    }
+
+    if let Some(creds) = credentials {
+        if !conn.tls && !server.is_loopback() {
+            bail!(
+                "Refusing to send credentials to {} without TLS",
+                server.host
+            );
+        }
+        let token =
+            general_purpose::STANDARD.encode(format!("\0{}\0{}", creds.user, creds.password));
+        smtp_expect(&mut conn, &format!("AUTH PLAIN {token}"), &[235]).await?;
+    }
+
+    smtp_expect(&mut conn, &format!("MAIL FROM:<{}>", mail.from), &[250]).await?;
+    smtp_expect(&mut conn, &format!("RCPT TO:<{}>", mail.to), &[250, 251]).await?;
+    smtp_expect(&mut conn, "DATA", &[354]).await?;
+    let data = mail.to_data();
+    conn.stream.get_mut().write_all(data.as_bytes()).await?;
+    smtp_expect(&mut conn, ".", &[250]).await?;
+    let _ = smtp_expect(&mut conn, "QUIT", &[221]).await;
+
+    Ok(())
+}
+
+/// IMAP session with tagged commands.
+struct ImapSession {
+    conn: Connection,
+    tag: u32,
+}
+
+/// Untagged response lines and literals from one IMAP command.
+struct ImapResponse {
+    lines: Vec<String>,
+    literals: Vec<Vec<u8>>,
+}
+
+impl ImapSession {
+    async fn login(server: &Server, credentials: &Credentials) -> Result<Self> {
+        let conn = Connection::open(server).await?;
+        let mut session = Self { conn, tag: 0 };
+        let greeting = session.conn.read_line().await?;
+        if !greeting.starts_with("* OK") {
+            bail!("IMAP server not ready: {greeting}");
+        }
+
+        if !session.conn.tls {
+            let caps = session.command("CAPABILITY").await?;
+            if caps.lines.iter().any(|l| l.contains("STARTTLS")) {
+                session.command("STARTTLS").await?;
+                session.conn = session.conn.upgrade(&server.host).await?;
+            } else if !server.is_loopback() {
+                bail!(
+                    "Refusing to send credentials to {} without TLS",
+                    server.host
+                );
+            }
+        }
+
+        session
+            .command(&format!(
+                "LOGIN {} {}",
+                imap_quote(&credentials.user),
+                imap_quote(&credentials.password)
+            ))
+            .await
+            .context("IMAP login failed")?;
+        Ok(session)
+    }
+
+    async fn command(&mut self, cmd: &str) -> Result<ImapResponse> {
+        self.tag += 1;
+        let tag = format!("A{}", self.tag);
+        self.conn.write_line(&format!("{tag} {cmd}")).await?;
+
+        let mut response = ImapResponse {
+            lines: Vec::new(),
+            literals: Vec::new(),
+        };
+        loop {
+            let line = self.conn.read_line().await?;
+            if let Some(status) = line.strip_prefix(&format!("{tag} ")) {
+                if status.starts_with("OK") {
+                    return Ok(response);
+                }
+                let verb = cmd.split_whitespace().next().unwrap_or(cmd);
+                bail!("IMAP {verb} failed: {status}");
+            }
+            // A line ending in {N} is followed by an N-byte literal
+            if let Some(len) = line
+                .strip_suffix('}')
+                .and_then(|l| l.rsplit_once('{'))
+                .and_then(|(_, n)| n.parse::<usize>().ok())
+            {
+                response.literals.push(self.conn.read_exact(len).await?);
+            }
+            response.lines.push(line);
+        }
+    }
+}
+
+/// Quote a string for an IMAP command.
+fn imap_quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// An email fetched from the inbox.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IncomingMail {
+    /// Bare sender address, lowercased
+    pub from: String,
+    pub subject: String,
+    /// Plain-text body
+    pub body: String,
+}
+
+/// Log in and out again to verify the server and credentials.
+pub async fn check_login(server: &Server, credentials: &Credentials) -> Result<()> {
+    let mut session = ImapSession::login(server, credentials).await?;
+    let _ = session.command("LOGOUT").await;
+    Ok(())
+}
+
+/// Fetch unseen messages from INBOX and mark them as seen.
+pub async fn fetch_unseen(server: &Server, credentials: &Credentials) -> Result<Vec<IncomingMail>> {
+    let mut session = ImapSession::login(server, credentials).await?;
+    session.command("SELECT INBOX").await?;
+
+    let search = session.command("UID SEARCH UNSEEN").await?;
+    let uids: Vec<u32> = search
+        .lines
+        .iter()
+        .filter_map(|l| l.strip_prefix("* SEARCH"))
+        .flat_map(|l| l.split_whitespace().filter_map(|n| n.parse().ok()))
+        .collect();
+
+    let mut mails = Vec::new();
+    for uid in uids {
+        let fetched = session
+            .command(&format!("UID FETCH {uid} BODY.PEEK[]"))
+            .await?;
+        if let Some(raw) = fetched.literals.first() {
+            match parse_message(&String::from_utf8_lossy(raw)) {
+                Some(mail) => mails.push(mail),
+                None => tracing::warn!("Skipping unparseable email (UID {uid})"),
+            }
+        }
+        session
+            .command(&format!("UID STORE {uid} +FLAGS (\\Seen)"))
+            .await?;
+    }
+
+    let _ = session.command("LOGOUT").await;
+    Ok(mails)
+}
+
+/// Split a message (or MIME part) into lowercased headers and body.
+fn split_headers(raw: &str) -> (Vec<(String, String)>, &str) {
+    let (head, body) = raw
+        .split_once("\r\n\r\n")
+        .or_else(|| raw.split_once("\n\n"))
+        .unwrap_or((raw, ""));
+
+    let mut headers: Vec<(String, String)> = Vec::new();
+    for line in head.lines() {
+        if line.starts_with([' ', '\t']) {
+            // Folded continuation of the previous header
+            if let Some((_, value)) = headers.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+        }
+    }
+    (headers, body)
+}
+
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(n, _)| n == name)
+        .map(|(_, v)| v.as_str())
+}
+
+/// Extract the bare address from `Name <addr>` or `addr`.
+pub fn parse_address(value: &str) -> String {
+    let addr = match (value.rfind('<'), value.rfind('>')) {
+        (Some(start), Some(end)) if start < end => &value[start + 1..end],
+        _ => value,
+    };
+    addr.trim().to_ascii_lowercase()
+}
+
+/// Decode a body according to its Content-Transfer-Encoding.
+fn decode_body(body: &str, encoding: Option<&str>) -> String {
+    match encoding.map(str::to_ascii_lowercase).as_deref() {
+        Some("base64") => {
+            let compact: String = body.split_whitespace().collect();
+            general_purpose::STANDARD
+                .decode(compact)
+                .map(|b| String::from_utf8_lossy(&b).into_owned())
+                .unwrap_or_default()
+        }
+        Some("quoted-printable") => decode_quoted_printable(body),
+        _ => body.to_string(),
+    }
+}
+
+fn decode_quoted_printable(body: &str) -> String {
+    let mut out = Vec::with_capacity(body.len());
+    for line in body.lines() {
+        let (line, soft_break) = match line.strip_suffix('=') {
+            Some(l) => (l, true),
+            None => (line, false),
+        };
+        let bytes = line.as_bytes();
+        let mut i = 0;
+        while i < bytes.len() {
+            if bytes[i] == b'=' {
+                if let Some(byte) = line
+                    .get(i + 1..i + 3)
+                    .and_then(|h| u8::from_str_radix(h, 16).ok())
+                {
+                    out.push(byte);
+                    i += 3;
+                    continue;
+                }
+            }
+            out.push(bytes[i]);
+            i += 1;
+        }
+        if !soft_break {
+            out.push(b'\n');
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Find the text/plain content of a message or MIME part.
+fn plain_text(headers: &[(String, String)], body: &str) -> Option<String> {
+    let content_type = header(headers, "content-type").unwrap_or("text/plain");
+    let lower = content_type.to_ascii_lowercase();
+
+    if lower.starts_with("multipart/") {
+        let boundary = content_type
+            .split(';')
+            .filter_map(|p| p.trim().split_once('='))
+            .find(|(k, _)| k.eq_ignore_ascii_case("boundary"))
+            .map(|(_, v)| v.trim_matches('"'))?;
+        let delimiter = format!("--{boundary}");
+        return body
+            .split(delimiter.as_str())
+            .skip(1)
+            .take_while(|part| !part.starts_with("--"))
+            .find_map(|part| {
+                let (part_headers, part_body) =
+                    split_headers(part.trim_start_matches(['\r', '\n']));
+                plain_text(&part_headers, part_body)
+            });
+    }
+
+    if lower.starts_with("text/plain") {
+        Some(decode_body(
+            body,
+            header(headers, "content-transfer-encoding"),
+        ))
+    } else {
+        None
+    }
+}
+
+/// Parse a raw RFC 5322 message.
+pub fn parse_message(raw: &str) -> Option<IncomingMail> {
+    let (headers, body) = split_headers(raw);
+    let from = parse_address(header(&headers, "from")?);
+    let subject = header(&headers, "subject").unwrap_or("").to_string();
+    let body = plain_text(&headers, body).unwrap_or_default();
+    Some(IncomingMail {
+        from,
+        subject,
+        body,
+    })
+}
+
+/// Reduce an email to the text worth sending over the mesh: quoted replies
+/// and signatures are dropped, whitespace is collapsed, and the subject is
+/// used when the body is empty.
+pub fn condense(mail: &IncomingMail) -> String {
+    let mut kept = Vec::new();
+    for line in mail.body.lines() {
+        let line = line.trim_end();
+        if line == "--" || line == "-- " {
+            break;
+        }
+        // "On <date>, <someone> wrote:" introduces the quoted original
+        if line.starts_with('>') || (line.starts_with("On ") && line.ends_with("wrote:")) {
+            continue;
+        }
+        kept.push(line);
+    }
+    let text = kept
+        .join(" ")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    if text.is_empty() {
+        mail.subject.trim().to_string()
+    } else {
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_multipart_quoted_printable() {
+        let raw = "From: Alice <Alice@Example.com>\r\n\
+                   Subject: Re: Mesh message from bob\r\n\
+                   Content-Type: multipart/alternative; boundary=\"xyz\"\r\n\r\n\
+                   --xyz\r\n\
+                   Content-Type: text/plain; charset=utf-8\r\n\
+                   Content-Transfer-Encoding: quoted-printable\r\n\r\n\
+                   See you at the hut =E2=9B=BA at 6=\r\n\
+                   pm\r\n\
+                   \r\n\
+                   On Mon, Bob wrote:\r\n\
+                   > are you coming?\r\n\
+                   --xyz\r\n\
+                   Content-Type: text/html\r\n\r\n\
+                   <p>ignored</p>\r\n\
+                   --xyz--\r\n";
+        let mail = parse_message(raw).unwrap();
+        assert_eq!(mail.from, "alice@example.com");
+        assert_eq!(condense(&mail), "See you at the hut ⛺ at 6pm");
+    }
+
+    #[test]
+    fn test_server_spec() {
+        let s = Server::smtp("smtp.example.com").unwrap();
+        assert_eq!((s.port, s.implicit_tls), (587, false));
+        let s = Server::smtp("smtp.example.com:465").unwrap();
+        assert!(s.implicit_tls);
+        let s = Server::imap("imap://127.0.0.1:1143").unwrap();
+        assert_eq!(
+            (s.host.as_str(), s.port, s.implicit_tls),
+            ("127.0.0.1", 1143, false)
+        );
+        assert!(
+            Server::imap("imaps://mail.example.com")
+                .unwrap()
+                .implicit_tls
+        );
+    }
+}
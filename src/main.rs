@@ -3,56 +3,150 @@
 //! Connects to meshgrid/MeshCore devices over USB serial and provides
 //! tools for sending messages, monitoring the mesh, and device management.
 
+mod airtime;
+mod audit;
+mod capture;
+mod channels;
 mod cli;
 mod commands;
+mod compress;
+mod confirm;
+mod contacts;
+mod credentials;
 mod device;
+mod diagnose;
+mod error;
 mod firmware;
+mod fragment;
+mod framing;
+mod geo;
+mod gpx;
+mod grpc;
+mod hexdump;
+mod history;
+mod hooks;
+mod http;
+mod logfwd;
+mod mail;
+mod nmea;
+mod nodes;
+mod nostr;
+mod ota;
+mod outbox;
+mod output;
+mod packet;
+mod plan;
+mod portlock;
 mod protocol;
+mod provision;
+mod proxy;
+mod psk;
+mod qr;
+mod rng;
 mod serial;
+mod session;
+mod settings;
+mod sim;
+mod text;
+mod timefmt;
 mod ui;
+mod vault;
+mod waypoints;
+mod websocket;
 
-use anyhow::Result;
-use clap::Parser;
+use anyhow::{bail, Result};
+use clap::{CommandFactory, FromArgMatches, Parser};
+use std::process::ExitCode;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 // Import CLI definitions and command functions
-use cli::{Cli, Commands};
+use cli::{ChannelsAction, Cli, Commands, ContactsAction, FlashAction, OutboxAction, RfAction};
 use commands::{
     cmd_advert,
+    cmd_audit,
     cmd_auth,
+    cmd_batch,
+    cmd_bench,
+    cmd_bridge,
     cmd_channels,
+    cmd_channels_audit,
+    cmd_channels_config,
+    cmd_channels_stats,
+    cmd_collector,
     // Config commands
     cmd_config,
+    cmd_contacts,
+    cmd_contacts_sync,
+    cmd_crashlog,
     cmd_debug,
+    cmd_distance,
+    cmd_events,
+    cmd_export,
     cmd_flash,
+    cmd_flash_fs,
+    cmd_flash_ota,
+    cmd_fleet,
+    cmd_fleet_apply,
+    cmd_fuzz,
+    cmd_gps,
     // Info commands
     cmd_info,
     // Utility commands
     cmd_list_ports,
+    cmd_log,
+    cmd_map,
     cmd_messages,
     cmd_mode,
+    cmd_monitor,
     cmd_neighbors,
+    cmd_nodeinfo,
+    cmd_nodes,
+    cmd_nvs,
+    cmd_outbox,
+    cmd_plan,
+    cmd_plugin,
+    cmd_position,
+    cmd_provision,
     cmd_raw,
+    cmd_raw_build,
     // System commands
     cmd_reboot,
+    cmd_recover,
     cmd_recv,
+    cmd_replay,
+    cmd_rf_abtest,
+    cmd_rf_noise,
     cmd_rotate_identity,
+    cmd_routes,
+    cmd_rpc,
+    cmd_run,
     // Messaging commands
     cmd_send,
+    cmd_serve,
     cmd_setpass,
     cmd_setpin,
+    cmd_setup_permissions,
+    cmd_share,
+    cmd_sim,
     cmd_stats,
+    cmd_stdin,
     cmd_telemetry,
     cmd_time,
+    cmd_timeline,
     // Network commands
     cmd_trace,
+    cmd_track,
     cmd_ui,
+    cmd_update,
+    cmd_vault,
+    cmd_watchdog,
+    cmd_waypoints,
     require_port,
 };
+use error::CliError;
 
 #[tokio::main]
-#[allow(clippy::too_many_lines)]
-async fn main() -> Result<()> {
+async fn main() -> ExitCode {
     // When running without a TTY (e.g., subprocess, cron, systemd),
     // stdin might block tokio's reactor. Set it to non-blocking mode.
     #[cfg(unix)]
@@ -71,20 +165,146 @@ async fn main() -> Result<()> {
         }
     }
 
-    let cli = Cli::parse();
+    let matches = match Cli::command().try_get_matches() {
+        Ok(matches) => matches,
+        Err(e) => return report_parse_error(&e),
+    };
+    let cli = match Cli::from_arg_matches(&matches) {
+        Ok(cli) => cli,
+        Err(e) => return report_parse_error(&e),
+    };
+
+    output::set_plain(cli.quiet);
+    output::set_style(cli.color);
+    output::set_format(cli.command.output_format());
+    confirm::set_assume_yes(cli.yes);
+    confirm::set_profile(cli.profile.as_deref());
+    timefmt::set_utc(cli.utc);
+    portlock::set_command(&command_name(&matches));
+    if let Some(path) = &cli.record {
+        session::record_to(path, session::redacted_args(&matches));
+    }
 
     // Initialize logging
-    let filter = if cli.verbose { "debug" } else { "info" };
+    let filter = if cli.verbose {
+        "debug"
+    } else if cli.quiet {
+        "warn"
+    } else {
+        "info"
+    };
     tracing_subscriber::registry()
-        .with(tracing_subscriber::fmt::layer())
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_writer(std::io::stderr)
+                .with_ansi(console::colors_enabled_stderr()),
+        )
         .with(tracing_subscriber::EnvFilter::new(filter))
         .init();
 
+    let error_format = cli.error_format;
+    let result = match audit::Operation::start(&cli) {
+        Ok(Some(operation)) => operation.close(run(cli).await),
+        Ok(None) => run(cli).await,
+        Err(e) => Err(e),
+    };
+    if let Err(e) = session::finish_recording(&result) {
+        eprintln!("⚠ {e:#}");
+    }
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            let report = error::ErrorReport::new(&e);
+            report.print(Some(&e), error_format);
+            ExitCode::from(report.code)
+        }
+    }
+}
+
+/// Run the command of a recorded session against the recording.
+async fn replay_session(file: &str) -> Result<()> {
+    let session = session::Session::load(file)?;
+    let recorded = Cli::try_parse_from(session.replay_args())
+        .map_err(|e| CliError::InvalidArgs(format!("Recorded command line: {e}")))?;
+    if matches!(recorded.command, Commands::ReplaySession { .. }) {
+        bail!(CliError::InvalidArgs("The session replays a replay".into()));
+    }
+    println!(
+        "Replaying `meshgrid-cli {}` (recorded {} with meshgrid-cli {})\n",
+        session.args.join(" "),
+        session.started,
+        session.cli_version
+    );
+    output::set_plain(recorded.quiet);
+    output::set_format(recorded.command.output_format());
+    let error_format = recorded.error_format;
+    session::start_replay(&session);
+    let result = Box::pin(run(recorded)).await;
+    if let Err(e) = &result {
+        error::ErrorReport::new(e).print(Some(e), error_format);
+    }
+    session::finish_replay(&session, &result)
+}
+
+/// Subcommand path, e.g. `log follow`.
+fn command_name(matches: &clap::ArgMatches) -> String {
+    let mut names = Vec::new();
+    let mut matches = matches;
+    while let Some((name, sub)) = matches.subcommand() {
+        names.push(name);
+        matches = sub;
+    }
+    names.join(" ")
+}
+
+/// Report a command-line parsing error, honoring `--error-format json`.
+fn report_parse_error(e: &clap::Error) -> ExitCode {
+    use clap::error::ErrorKind;
+
+    let args: Vec<String> = std::env::args().collect();
+    let json = args
+        .windows(2)
+        .any(|w| w[0] == "--error-format" && w[1] == "json")
+        || args.iter().any(|a| a == "--error-format=json");
+    let is_error = !matches!(
+        e.kind(),
+        ErrorKind::DisplayHelp
+            | ErrorKind::DisplayVersion
+            | ErrorKind::DisplayHelpOnMissingArgumentOrSubcommand
+    );
+
+    if json && is_error {
+        let message = e.render().to_string();
+        let message = message.lines().next().unwrap_or_default();
+        let message = message.strip_prefix("error: ").unwrap_or(message);
+        error::ErrorReport::invalid_args(message.to_string()).print(None, cli::ErrorFormat::Json);
+        ExitCode::from(2)
+    } else {
+        let _ = e.print();
+        ExitCode::from(u8::try_from(e.exit_code()).unwrap_or(2))
+    }
+}
+
+#[allow(clippy::too_many_lines)]
+async fn run(cli: Cli) -> Result<()> {
+    if let Some(path) = &cli.trace_serial {
+        serial::trace_to(path)?;
+    }
+    if !cli.ports.is_empty() || cli.all_devices {
+        return cmd_fleet(&cli).await;
+    }
+
     match cli.command {
-        Commands::Ports => {
+        Commands::Ports { .. } => {
             cmd_list_ports()?;
         }
-        Commands::Info => {
+        Commands::ReplaySession { file } => {
+            replay_session(&file).await?;
+        }
+        Commands::SetupPermissions { install } => {
+            cmd_setup_permissions(install)?;
+        }
+        Commands::Info { .. } => {
             let port = require_port(cli.port.as_ref())?;
             cmd_info(&port, cli.baud, cli.pin.as_deref()).await?;
         }
@@ -92,18 +312,111 @@ async fn main() -> Result<()> {
             to,
             channel,
             message,
+            wait_ack,
+            refresh,
+            spacing,
+            queue,
+            no_fragment,
+            compress,
+            transliterate,
         } => {
             let port = require_port(cli.port.as_ref())?;
             cmd_send(
                 &port,
                 cli.baud,
                 cli.pin.as_deref(),
-                to.as_deref(),
-                channel.as_deref(),
+                &to,
+                &channel,
                 &message,
+                wait_ack,
+                refresh,
+                spacing,
+                queue,
+                !no_fragment,
+                compress,
+                transliterate,
+            )
+            .await?;
+        }
+        Commands::Collector { config } => {
+            cmd_collector(cli.port.as_ref(), cli.baud, cli.pin.as_deref(), &config).await?;
+        }
+        Commands::Monitor {
+            battery_interval,
+            notify,
+        } => {
+            let port = require_port(cli.port.as_ref())?;
+            cmd_monitor(
+                &port,
+                cli.baud,
+                cli.pin.as_deref(),
+                battery_interval,
+                notify,
+            )
+            .await?;
+        }
+        Commands::Rpc => {
+            let port = require_port(cli.port.as_ref())?;
+            cmd_rpc(&port, cli.baud, cli.pin.as_deref()).await?;
+        }
+        Commands::Bridge { action } => {
+            let port = require_port(cli.port.as_ref())?;
+            cmd_bridge(&port, cli.baud, cli.pin.as_deref(), action).await?;
+        }
+        Commands::Export { action } => {
+            let port = require_port(cli.port.as_ref())?;
+            cmd_export(&port, cli.baud, cli.pin.as_deref(), action).await?;
+        }
+        Commands::Gps { action } => {
+            let port = require_port(cli.port.as_ref())?;
+            cmd_gps(&port, cli.baud, cli.pin.as_deref(), action).await?;
+        }
+        Commands::Serve { action } => {
+            let port = require_port(cli.port.as_ref())?;
+            cmd_serve(&port, cli.baud, cli.pin.as_deref(), action).await?;
+        }
+        Commands::Watchdog {
+            node,
+            expect_interval,
+            exec,
+            webhook,
+            notify,
+            refresh,
+        } => {
+            let port = require_port(cli.port.as_ref())?;
+            cmd_watchdog(
+                &port,
+                cli.baud,
+                cli.pin.as_deref(),
+                &node,
+                expect_interval,
+                exec,
+                webhook,
+                notify,
+                refresh,
             )
             .await?;
         }
+        Commands::Share {
+            listen,
+            read_only,
+            token,
+        } => {
+            let port = require_port(cli.port.as_ref())?;
+            cmd_share(
+                &port,
+                cli.baud,
+                cli.pin.as_deref(),
+                &listen,
+                read_only,
+                token,
+            )
+            .await?;
+        }
+        Commands::Map { listen, tiles } => {
+            let port = require_port(cli.port.as_ref())?;
+            cmd_map(&port, cli.baud, cli.pin.as_deref(), &listen, &tiles).await?;
+        }
         Commands::Ui => {
             let port = require_port(cli.port.as_ref())?;
             cmd_ui(&port, cli.baud).await?;
@@ -112,33 +425,238 @@ async fn main() -> Result<()> {
             let port = require_port(cli.port.as_ref())?;
             cmd_config(&port, cli.baud, action).await?;
         }
-        Commands::Neighbors => {
+        Commands::Provision { action } => {
+            cmd_provision(cli.port.as_ref(), cli.baud, cli.pin.as_deref(), action).await?;
+        }
+        Commands::Audit { action } => {
+            cmd_audit(action)?;
+        }
+        Commands::Events { action } => {
+            cmd_events(action)?;
+        }
+        Commands::Vault { action } => {
+            cmd_vault(action)?;
+        }
+        Commands::Neighbors {
+            format,
+            sort,
+            columns,
+            ..
+        } => {
             let port = require_port(cli.port.as_ref())?;
-            cmd_neighbors(&port, cli.baud, cli.pin.as_deref()).await?;
+            cmd_neighbors(&port, cli.baud, cli.pin.as_deref(), format, sort, &columns).await?;
         }
-        Commands::Trace { target } => {
+        Commands::Nodeinfo {
+            node,
+            timeout,
+            refresh,
+        } => {
             let port = require_port(cli.port.as_ref())?;
-            cmd_trace(&port, cli.baud, cli.pin.as_deref(), &target).await?;
+            cmd_nodeinfo(&port, cli.baud, cli.pin.as_deref(), &node, timeout, refresh).await?;
+        }
+        Commands::Contacts {
+            action: ContactsAction::Sync { from, to, dry_run },
+        } => {
+            cmd_contacts_sync(&from, &to, cli.baud, cli.pin.as_deref(), dry_run).await?;
+        }
+        Commands::Contacts { action } => {
+            let port = require_port(cli.port.as_ref())?;
+            cmd_contacts(&port, cli.baud, cli.pin.as_deref(), action).await?;
+        }
+        Commands::Trace {
+            target,
+            refresh,
+            count,
+            ..
+        } => {
+            let port = require_port(cli.port.as_ref())?;
+            cmd_trace(
+                &port,
+                cli.baud,
+                cli.pin.as_deref(),
+                target.as_deref(),
+                refresh,
+                count,
+            )
+            .await?;
+        }
+        Commands::Routes { action } => {
+            let port = require_port(cli.port.as_ref())?;
+            cmd_routes(&port, cli.baud, cli.pin.as_deref(), action).await?;
         }
         Commands::Reboot => {
             let port = require_port(cli.port.as_ref())?;
             cmd_reboot(&port, cli.baud).await?;
         }
-        Commands::Raw { hex } => {
+        Commands::Raw {
+            action: Some(action),
+            ..
+        } => {
+            cmd_raw_build(cli.port.as_ref(), cli.baud, action).await?;
+        }
+        Commands::Raw { hex, action: None } => {
+            // Required by clap without a subcommand
+            let hex = hex.unwrap_or_default();
             let port = require_port(cli.port.as_ref())?;
             cmd_raw(&port, cli.baud, &hex).await?;
         }
-        Commands::Recv { timeout } => {
+        Commands::Recv {
+            timeout,
+            capture,
+            width,
+        } => {
+            let port = require_port(cli.port.as_ref())?;
+            cmd_recv(&port, cli.baud, timeout, capture.as_deref(), width.into()).await?;
+        }
+        Commands::Timeline { duration, json } => {
+            let port = require_port(cli.port.as_ref())?;
+            cmd_timeline(&port, cli.baud, cli.pin.as_deref(), duration, json).await?;
+        }
+        Commands::Log { action } => {
+            let port = require_port(cli.port.as_ref())?;
+            cmd_log(&port, cli.baud, cli.pin.as_deref(), action).await?;
+        }
+        Commands::Crashlog { elf, output, clear } => {
+            let port = require_port(cli.port.as_ref())?;
+            cmd_crashlog(
+                &port,
+                cli.baud,
+                cli.pin.as_deref(),
+                elf.as_deref(),
+                output.as_deref(),
+                clear,
+            )
+            .await?;
+        }
+        Commands::Recover {
+            timeout,
+            output,
+            fix,
+        } => {
             let port = require_port(cli.port.as_ref())?;
-            cmd_recv(&port, cli.baud, timeout).await?;
+            cmd_recover(&port, cli.baud, timeout, output.as_deref(), fix, cli.yes).await?;
+        }
+        Commands::Fuzz {
+            iterations,
+            seed,
+            log,
+        } => {
+            let port = require_port(cli.port.as_ref())?;
+            cmd_fuzz(
+                &port,
+                cli.baud,
+                cli.pin.as_deref(),
+                iterations,
+                seed,
+                log.as_deref(),
+            )
+            .await?;
+        }
+        Commands::Sim {
+            nodes,
+            topology,
+            spacing,
+            sf,
+            advert_interval,
+            seed,
+        } => {
+            cmd_sim(nodes, topology, spacing, sf, advert_interval, seed).await?;
+        }
+        Commands::Plan {
+            nodes,
+            preset,
+            elevation,
+            gain,
+            margin,
+            ..
+        } => {
+            cmd_plan(
+                cli.port.as_ref(),
+                cli.baud,
+                cli.pin.as_deref(),
+                &nodes,
+                preset,
+                elevation.as_deref(),
+                gain,
+                margin,
+            )
+            .await?;
+        }
+        Commands::Rf {
+            action:
+                RfAction::Noise {
+                    hours,
+                    interval,
+                    output,
+                },
+        } => {
+            let port = require_port(cli.port.as_ref())?;
+            cmd_rf_noise(
+                &port,
+                cli.baud,
+                cli.pin.as_deref(),
+                hours,
+                interval,
+                output.as_deref(),
+            )
+            .await?;
+        }
+        Commands::Rf {
+            action:
+                RfAction::Abtest {
+                    to,
+                    rounds,
+                    probes,
+                    timeout,
+                    labels,
+                    ..
+                },
+        } => {
+            let port = require_port(cli.port.as_ref())?;
+            cmd_rf_abtest(
+                &port,
+                cli.baud,
+                cli.pin.as_deref(),
+                &to,
+                rounds,
+                probes,
+                timeout,
+                &labels,
+            )
+            .await?;
+        }
+        Commands::Replay { file, speed } => {
+            let port = require_port(cli.port.as_ref())?;
+            cmd_replay(&port, cli.baud, cli.pin.as_deref(), &file, &speed).await?;
         }
         Commands::Telemetry { watch } => {
             let port = require_port(cli.port.as_ref())?;
             cmd_telemetry(&port, cli.baud, watch).await?;
         }
-        Commands::Stats => {
+        Commands::Stats {
+            json,
+            watch,
+            interval,
+            delta,
+            log,
+            log_max_mb,
+            log_keep,
+            ..
+        } => {
             let port = require_port(cli.port.as_ref())?;
-            cmd_stats(&port, cli.baud, cli.pin.as_deref()).await?;
+            cmd_stats(
+                &port,
+                cli.baud,
+                cli.pin.as_deref(),
+                json,
+                watch,
+                interval,
+                delta,
+                log.as_deref(),
+                log_max_mb,
+                log_keep,
+            )
+            .await?;
         }
         Commands::Mode { mode } => {
             let port = require_port(cli.port.as_ref())?;
@@ -149,19 +667,120 @@ async fn main() -> Result<()> {
             };
             cmd_mode(&port, cli.baud, cli.pin.as_deref(), mode_str).await?;
         }
+        Commands::Position { action } => {
+            let port = require_port(cli.port.as_ref())?;
+            cmd_position(&port, cli.baud, cli.pin.as_deref(), action).await?;
+        }
+        Commands::Distance {
+            node_a,
+            node_b,
+            refresh,
+        } => {
+            let port = require_port(cli.port.as_ref())?;
+            cmd_distance(
+                &port,
+                cli.baud,
+                cli.pin.as_deref(),
+                &node_a,
+                node_b.as_deref(),
+                refresh,
+            )
+            .await?;
+        }
+        Commands::Track { action } => {
+            let port = require_port(cli.port.as_ref())?;
+            cmd_track(&port, cli.baud, cli.pin.as_deref(), action).await?;
+        }
+        Commands::Nodes { action } => {
+            cmd_nodes(cli.port.as_ref(), action)?;
+        }
+        Commands::Waypoints { action } => {
+            cmd_waypoints(cli.port.as_ref(), cli.baud, cli.pin.as_deref(), action).await?;
+        }
         Commands::Time { action } => {
             let port = require_port(cli.port.as_ref())?;
             cmd_time(&port, cli.baud, cli.pin.as_deref(), action).await?;
         }
-        Commands::Messages { action } => {
+        Commands::Messages { action, .. } => {
             let port = require_port(cli.port.as_ref())?;
             cmd_messages(&port, cli.baud, cli.pin.as_deref(), action).await?;
         }
-        Commands::Channels { action } => {
+        Commands::Outbox { action } => {
+            cmd_outbox(action.unwrap_or(OutboxAction::List))?;
+        }
+        Commands::Channels {
+            action: Some(ChannelsAction::Audit { files }),
+            ..
+        } if !files.is_empty() => {
+            cmd_channels_audit(&files)?;
+        }
+        Commands::Channels {
+            action:
+                Some(ChannelsAction::Config {
+                    name,
+                    mute,
+                    notify,
+                    priority,
+                }),
+            ..
+        } => {
+            cmd_channels_config(&name, mute, notify, priority)?;
+        }
+        Commands::Channels {
+            action: Some(ChannelsAction::Stats { since, json }),
+            ..
+        } => {
+            cmd_channels_stats(since.as_deref(), json)?;
+        }
+        Commands::Channels { action, .. } => {
             let port = require_port(cli.port.as_ref())?;
             cmd_channels(&port, cli.baud, cli.pin.as_deref(), action).await?;
         }
+        Commands::Fleet { action } => {
+            cmd_fleet_apply(cli.baud, cli.pin.as_deref(), action).await?;
+        }
+        Commands::Nvs { action } => {
+            cmd_nvs(cli.port.as_deref(), action).await?;
+        }
+        Commands::Flash {
+            action:
+                Some(FlashAction::Fs {
+                    source,
+                    fs,
+                    label,
+                    no_verify,
+                }),
+            ..
+        } => {
+            cmd_flash_fs(
+                cli.port.as_deref(),
+                &source,
+                fs,
+                label.as_deref(),
+                !no_verify,
+            )?;
+        }
+        Commands::Flash {
+            board,
+            version,
+            force_download,
+            offline,
+            ota: Some(host),
+            ota_password,
+            ..
+        } => {
+            cmd_flash_ota(
+                board,
+                &host,
+                ota_password.as_deref(),
+                version.as_deref(),
+                force_download,
+                offline,
+            )
+            .await?;
+        }
         Commands::Flash {
+            action: None,
             board,
             monitor,
             local,
@@ -169,6 +788,12 @@ async fn main() -> Result<()> {
             version,
             force_download,
             offline,
+            incremental,
+            no_verify,
+            allow_downgrade,
+            no_hardware_check,
+            ota: None,
+            ..
         } => {
             let port = cli.port.clone();
             cmd_flash(
@@ -180,6 +805,25 @@ async fn main() -> Result<()> {
                 version.as_deref(),
                 force_download,
                 offline,
+                incremental,
+                !no_verify,
+                allow_downgrade,
+                !no_hardware_check,
+            )
+            .await?;
+        }
+        Commands::Update {
+            check,
+            self_update,
+            trusted_key,
+        } => {
+            cmd_update(
+                cli.port.as_deref(),
+                cli.baud,
+                cli.pin.as_deref(),
+                check,
+                self_update,
+                trusted_key,
             )
             .await?;
         }
@@ -187,9 +831,16 @@ async fn main() -> Result<()> {
             let port = require_port(cli.port.as_ref())?;
             cmd_advert(&port, cli.baud, cli.pin.as_deref(), local, flood).await?;
         }
-        Commands::RotateIdentity => {
+        Commands::RotateIdentity { schedule, announce } => {
             let port = require_port(cli.port.as_ref())?;
-            cmd_rotate_identity(&port, cli.baud, cli.pin.as_deref()).await?;
+            cmd_rotate_identity(
+                &port,
+                cli.baud,
+                cli.pin.as_deref(),
+                schedule.as_deref(),
+                announce,
+            )
+            .await?;
         }
         Commands::Auth { action } => {
             let port = require_port(cli.port.as_ref())?;
@@ -203,13 +854,61 @@ async fn main() -> Result<()> {
             let port = require_port(cli.port.as_ref())?;
             cmd_setpin(&port, cli.baud, &pin).await?;
         }
-        Commands::Debug { output, timeout } => {
+        Commands::Debug {
+            output,
+            timeout,
+            level,
+            module,
+            json,
+        } => {
+            let port = require_port(cli.port.as_ref())?;
+            cmd_debug(&port, cli.baud, output, timeout, level, &module, json).await?;
+        }
+        Commands::Run {
+            file,
+            vars,
+            stop_on_error,
+        } => {
+            let port = require_port(cli.port.as_ref())?;
+            cmd_run(
+                &port,
+                cli.baud,
+                cli.pin.as_deref(),
+                &file,
+                &vars,
+                stop_on_error,
+                &|cli| Box::pin(run(cli)),
+            )
+            .await?;
+        }
+        Commands::Batch {
+            commands,
+            stop_on_error,
+        } => {
             let port = require_port(cli.port.as_ref())?;
-            cmd_debug(&port, cli.baud, output, timeout).await?;
+            cmd_batch(
+                &port,
+                cli.baud,
+                cli.pin.as_deref(),
+                &commands,
+                stop_on_error,
+                &|cli| Box::pin(run(cli)),
+            )
+            .await?;
+        }
+        Commands::Bench { count, size } => {
+            let port = require_port(cli.port.as_ref())?;
+            cmd_bench(&port, cli.baud, cli.pin.as_deref(), count, size).await?;
         }
-        Commands::Stdin => {
-            // TODO: Implement stdin command processing
-            eprintln!("Stdin command not yet implemented");
+        Commands::Stdin { expect_ok } => {
+            let port = require_port(cli.port.as_ref())?;
+            cmd_stdin(&port, cli.baud, cli.pin.as_deref(), expect_ok).await?;
+        }
+        Commands::External(args) => {
+            let code = cmd_plugin(&args, cli.port.as_ref(), cli.baud, cli.pin.as_deref()).await?;
+            if code != 0 {
+                std::process::exit(code);
+            }
         }
     }
 
@@ -0,0 +1,99 @@
+//! NMEA 0183 position sentences (GGA and RMC) from GPS receivers
+
+/// A position fix reported by a receiver.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fix {
+    pub lat: f64,
+    pub lon: f64,
+    /// Meters above mean sea level (GGA only)
+    pub alt: Option<f64>,
+    /// Satellites in use (GGA only)
+    pub satellites: Option<u8>,
+}
+
+/// Parse one sentence. Sentences of other types, without a fix or with a
+/// bad checksum give `None`. Any talker is accepted (`$GP`, `$GN`, `$GL`...).
+pub fn parse(line: &str) -> Option<Fix> {
+    let body = line.trim().strip_prefix('$')?;
+    let body = match body.split_once('*') {
+        Some((body, sum)) => {
+            let expected = u8::from_str_radix(sum.get(..2)?, 16).ok()?;
+            (body.bytes().fold(0, |acc, b| acc ^ b) == expected).then_some(body)?
+        }
+        None => body,
+    };
+
+    let fields: Vec<&str> = body.split(',').collect();
+    let kind = fields[0].get(fields[0].len().checked_sub(3)?..)?;
+    match kind {
+        // $xxGGA,time,lat,N,lon,E,quality,sats,hdop,alt,M,...
+        "GGA" => {
+            if fields.get(6).is_none_or(|q| q.is_empty() || *q == "0") {
+                return None;
+            }
+            Some(Fix {
+                lat: coordinate(fields.get(2)?, fields.get(3)?)?,
+                lon: coordinate(fields.get(4)?, fields.get(5)?)?,
+                alt: fields.get(9).and_then(|a| a.parse().ok()),
+                satellites: fields.get(7).and_then(|s| s.parse().ok()),
+            })
+        }
+        // $xxRMC,time,status,lat,N,lon,E,...
+        "RMC" => {
+            if fields.get(2) != Some(&"A") {
+                return None;
+            }
+            Some(Fix {
+                lat: coordinate(fields.get(3)?, fields.get(4)?)?,
+                lon: coordinate(fields.get(5)?, fields.get(6)?)?,
+                alt: None,
+                satellites: None,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// `ddmm.mmmm` / `dddmm.mmmm` with a hemisphere to decimal degrees.
+fn coordinate(value: &str, hemisphere: &str) -> Option<f64> {
+    let dot = value.find('.').unwrap_or(value.len());
+    let split = dot.checked_sub(2)?;
+    let degrees: f64 = value[..split].parse().ok()?;
+    let minutes: f64 = value[split..].parse().ok()?;
+    let decimal = degrees + minutes / 60.0;
+    match hemisphere {
+        "N" | "E" => Some(decimal),
+        "S" | "W" => Some(-decimal),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let fix =
+            parse("$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*47").unwrap();
+        assert!((fix.lat - 48.1173).abs() < 1e-6);
+        assert!((fix.lon - 11.516_666).abs() < 1e-6);
+        assert_eq!(fix.alt, Some(545.4));
+        assert_eq!(fix.satellites, Some(8));
+
+        let fix =
+            parse("$GNRMC,083559.00,A,3345.6000,S,15112.3000,W,0.004,77.52,091202,,,A").unwrap();
+        assert!((fix.lat + 33.76).abs() < 1e-6);
+        assert!((fix.lon + 151.205).abs() < 1e-6);
+
+        // No fix yet, wrong checksum, other sentences
+        assert_eq!(parse("$GPGGA,123519,,,,,0,00,,,M,,M,,"), None);
+        assert_eq!(parse("$GPRMC,083559.00,V,,,,,,,091202,,,N"), None);
+        assert_eq!(
+            parse("$GPGGA,123519,4807.038,N,01131.000,E,1,08,0.9,545.4,M,46.9,M,,*48"),
+            None
+        );
+        assert_eq!(parse("$GPGSV,3,1,11,03,03,111,00"), None);
+        assert_eq!(parse("garbage"), None);
+    }
+}
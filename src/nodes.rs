@@ -0,0 +1,364 @@
+//! Local node database.
+//!
+//! `neighbors.toml` next to the config file keeps every node each device has
+//! reported, keyed by the device's public key (one database per mesh), so
+//! `send --to <name>` and `trace <name>` resolve names without a NEIGHBORS
+//! round trip, including nodes that have since gone out of range. The device
+//! last seen on each port is recorded too, so a cached lookup doesn't query
+//! the device at all.
+//!
+//! Each sighting (`neighbors`, name lookups, adverts heard by `monitor`) adds
+//! to the node's record: first and last seen, best RSSI, firmware versions
+//! and positions. Operators can attach a note with `nodes annotate`.
+
+use crate::protocol::NeighborInfo;
+use crate::settings::Settings;
+use crate::vault;
+use anyhow::{Context, Result};
+use chrono::{SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// A node from a device's neighbor table.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CachedNode {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub node_hash: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<String>,
+    /// When the node was first seen (RFC 3339)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_seen: Option<String>,
+    /// When the node was last seen (RFC 3339; a date in older files)
+    pub last_seen: String,
+    /// Strongest signal heard from the node (dBm)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub best_rssi: Option<i16>,
+    /// Firmware versions the node has reported, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub firmware: Vec<String>,
+    /// Last advertised position, in decimal degrees
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lat: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lon: Option<f64>,
+    /// Altitude in meters
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alt: Option<f64>,
+    /// Positions the node has advertised, oldest first
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub positions: Vec<SeenPosition>,
+    /// Operator note
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+/// A position a node advertised, and when it was first seen there.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SeenPosition {
+    pub lat: f64,
+    pub lon: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alt: Option<f64>,
+    pub seen: String,
+}
+
+/// Positions kept per node.
+const MAX_POSITIONS: usize = 20;
+
+fn now() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+impl From<&NeighborInfo> for CachedNode {
+    fn from(neighbor: &NeighborInfo) -> Self {
+        let now = now();
+        let position = neighbor
+            .lat
+            .zip(neighbor.lon)
+            .map(|(lat, lon)| SeenPosition {
+                lat,
+                lon,
+                alt: neighbor.alt,
+                seen: now.clone(),
+            });
+        Self {
+            name: neighbor.name.clone(),
+            node_hash: neighbor.node_hash,
+            public_key: neighbor.public_key.map(hex::encode),
+            first_seen: Some(now.clone()),
+            last_seen: now,
+            best_rssi: Some(neighbor.rssi),
+            firmware: neighbor.firmware.clone().into_iter().collect(),
+            lat: neighbor.lat,
+            lon: neighbor.lon,
+            alt: neighbor.alt,
+            positions: position.into_iter().collect(),
+            note: None,
+        }
+    }
+}
+
+impl CachedNode {
+    /// Display name, or the node hash for unnamed nodes.
+    pub fn label(&self) -> String {
+        self.name
+            .clone()
+            .unwrap_or_else(|| format!("0x{:02x}", self.node_hash))
+    }
+
+    /// Add a newer sighting of the same node to its record.
+    fn absorb(&mut self, newer: CachedNode) {
+        if newer.name.is_some() {
+            self.name = newer.name;
+        }
+        self.public_key = self.public_key.take().or(newer.public_key);
+        if self.first_seen.is_none() {
+            self.first_seen = Some(self.last_seen.clone());
+        }
+        self.last_seen = newer.last_seen;
+        self.best_rssi = self.best_rssi.max(newer.best_rssi);
+        for version in newer.firmware {
+            if !self.firmware.contains(&version) {
+                self.firmware.push(version);
+            }
+        }
+        // Keep the last known position of nodes that stopped sending one
+        if newer.lat.is_some() {
+            self.lat = newer.lat;
+            self.lon = newer.lon;
+            self.alt = newer.alt;
+        }
+        for position in newer.positions {
+            let moved = self
+                .positions
+                .last()
+                .is_none_or(|last| (last.lat, last.lon) != (position.lat, position.lon));
+            if moved {
+                self.positions.push(position);
+            }
+        }
+        let excess = self.positions.len().saturating_sub(MAX_POSITIONS);
+        self.positions.drain(..excess);
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Cache {
+    /// Device public key last seen on each port
+    #[serde(default)]
+    ports: BTreeMap<String, String>,
+    /// Nodes by device public key
+    #[serde(default)]
+    devices: BTreeMap<String, Vec<CachedNode>>,
+}
+
+impl Cache {
+    fn merge(&mut self, device_key: &str, neighbors: &[NeighborInfo]) {
+        let nodes = self.devices.entry(device_key.to_string()).or_default();
+        for node in neighbors.iter().map(CachedNode::from) {
+            // Same key, or same hash for nodes that don't report one
+            let existing = nodes.iter_mut().find(|n| match &node.public_key {
+                Some(key) => n.public_key.as_ref() == Some(key),
+                None => n.public_key.is_none() && n.node_hash == node.node_hash,
+            });
+            match existing {
+                Some(existing) => existing.absorb(node),
+                None => nodes.push(node),
+            }
+        }
+    }
+
+    /// Record an advert. Adverts carry no public key, so it counts for the
+    /// most recently seen node with the same hash, preferring the same name.
+    fn heard(&mut self, device_key: &str, node_hash: u8, name: Option<&str>, rssi: i16) {
+        let now = now();
+        let node = CachedNode {
+            name: name.map(String::from),
+            node_hash,
+            public_key: None,
+            first_seen: Some(now.clone()),
+            last_seen: now,
+            best_rssi: Some(rssi),
+            firmware: Vec::new(),
+            lat: None,
+            lon: None,
+            alt: None,
+            positions: Vec::new(),
+            note: None,
+        };
+        let nodes = self.devices.entry(device_key.to_string()).or_default();
+        let latest = |named: bool| {
+            nodes
+                .iter()
+                .enumerate()
+                .filter(|(_, n)| n.node_hash == node_hash)
+                .filter(|(_, n)| !named || (name.is_some() && n.name.as_deref() == name))
+                .max_by(|(_, a), (_, b)| a.last_seen.cmp(&b.last_seen))
+                .map(|(i, _)| i)
+        };
+        match latest(true).or_else(|| latest(false)) {
+            Some(i) => nodes[i].absorb(node),
+            None => nodes.push(node),
+        }
+    }
+
+    fn lookup(&self, device_key: &str, node: &str) -> Option<&CachedNode> {
+        let nodes = self.devices.get(device_key)?;
+        let lower = node.to_ascii_lowercase();
+        let hash = u8::from_str_radix(lower.trim_start_matches("0x"), 16).ok();
+        // Most recently seen first, for names reused by a new key
+        let mut by_recency: Vec<&CachedNode> = nodes.iter().collect();
+        by_recency.sort_by(|a, b| b.last_seen.cmp(&a.last_seen));
+        by_recency
+            .iter()
+            .find(|n| {
+                n.name
+                    .as_deref()
+                    .is_some_and(|name| name.eq_ignore_ascii_case(node))
+            })
+            .or_else(|| by_recency.iter().find(|n| Some(n.node_hash) == hash))
+            .or_else(|| {
+                (lower.len() >= 8).then_some(())?;
+                by_recency
+                    .iter()
+                    .find(|n| n.public_key.as_ref().is_some_and(|k| k.starts_with(&lower)))
+            })
+            .copied()
+    }
+}
+
+fn cache_path() -> Result<PathBuf> {
+    Ok(Settings::path()?.with_file_name("neighbors.toml"))
+}
+
+fn read_cache() -> Result<Cache> {
+    let path = cache_path()?;
+    vault::ensure_unlocked(&path)?;
+    match std::fs::read_to_string(&path) {
+        Ok(content) => toml::from_str(&content)
+            .with_context(|| format!("Invalid neighbor cache {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Cache::default()),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+fn write_cache(cache: &Cache) -> Result<()> {
+    let path = cache_path()?;
+    vault::ensure_unlocked(&path)?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&path, toml::to_string(cache)?)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Public key (hex) of the device last seen on `port`.
+pub fn device_on_port(port: &str) -> Result<Option<String>> {
+    Ok(read_cache()?.ports.remove(port))
+}
+
+/// Cached node `node` (name, node hash or public key prefix) from the
+/// neighbor table of device `device_key`.
+pub fn lookup(device_key: &str, node: &str) -> Result<Option<CachedNode>> {
+    Ok(read_cache()?.lookup(device_key, node).cloned())
+}
+
+/// All cached nodes from the neighbor table of device `device_key`.
+pub fn nodes(device_key: &str) -> Result<Vec<CachedNode>> {
+    Ok(read_cache()?.devices.remove(device_key).unwrap_or_default())
+}
+
+/// Nodes of every device, by device public key.
+pub fn devices() -> Result<BTreeMap<String, Vec<CachedNode>>> {
+    Ok(read_cache()?.devices)
+}
+
+/// Record an advert from `node_hash` heard by device `device_key`.
+pub fn heard(device_key: &str, node_hash: u8, name: Option<&str>, rssi: i16) -> Result<()> {
+    let mut cache = read_cache()?;
+    cache.heard(device_key, node_hash, name, rssi);
+    write_cache(&cache)
+}
+
+/// Set the note on node `node` of device `device_key`, or clear it with
+/// `None`; returns the updated node, if found.
+pub fn annotate(device_key: &str, node: &str, note: Option<String>) -> Result<Option<CachedNode>> {
+    let mut cache = read_cache()?;
+    let Some(found) = cache.lookup(device_key, node).cloned() else {
+        return Ok(None);
+    };
+    let nodes = cache.devices.get_mut(device_key).expect("node was found");
+    let Some(target) = nodes.iter_mut().find(|n| **n == found) else {
+        return Ok(None);
+    };
+    target.note = note;
+    let updated = target.clone();
+    write_cache(&cache)?;
+    Ok(Some(updated))
+}
+
+/// Record the neighbor table of device `device_key`, seen on `port`.
+pub fn store(port: &str, device_key: &str, neighbors: &[NeighborInfo]) -> Result<()> {
+    let mut cache = read_cache()?;
+    cache.ports.insert(port.to_string(), device_key.to_string());
+    cache.merge(device_key, neighbors);
+    write_cache(&cache)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn neighbor(name: &str, node_hash: u8, key: Option<u8>) -> NeighborInfo {
+        NeighborInfo {
+            node_hash,
+            protocol_version: None,
+            name: Some(name.into()),
+            public_key: key.map(|b| [b; 32]),
+            rssi: -80,
+            snr: 5,
+            last_seen_secs: 10,
+            firmware: None,
+            lat: None,
+            lon: None,
+            alt: None,
+        }
+    }
+
+    #[test]
+    fn test_merge_and_lookup() {
+        let mut cache = Cache::default();
+        cache.merge(
+            "dev",
+            &[
+                neighbor("alice", 0x11, Some(2)),
+                neighbor("bob", 0x22, None),
+            ],
+        );
+        // Later table without alice, and bob renamed with a new firmware
+        let mut robert = neighbor("robert", 0x22, None);
+        robert.rssi = -60;
+        robert.firmware = Some("0.0.5".into());
+        cache.merge("dev", &[robert]);
+        // An advert from him, weaker than before
+        cache.heard("dev", 0x22, Some("robert"), -90);
+
+        assert_eq!(cache.lookup("dev", "ALICE").unwrap().node_hash, 0x11);
+        assert_eq!(cache.lookup("dev", "0x22").unwrap().label(), "robert");
+        assert_eq!(cache.lookup("dev", "02020202").unwrap().label(), "alice");
+        assert!(cache.lookup("dev", "bob").is_none());
+        assert!(cache.lookup("other", "alice").is_none());
+
+        let robert = cache.lookup("dev", "robert").unwrap();
+        assert_eq!(robert.best_rssi, Some(-60));
+        assert_eq!(robert.firmware, ["0.0.5"]);
+        assert!(robert.first_seen.as_ref().unwrap() <= &robert.last_seen);
+
+        let text = toml::to_string(&cache).unwrap();
+        let parsed: Cache = toml::from_str(&text).unwrap();
+        assert_eq!(parsed.devices["dev"].len(), 2);
+    }
+}
@@ -0,0 +1,376 @@
+//! Nostr events, keys and signatures for the Nostr bridge.
+//!
+//! Implements the pieces of NIP-01 (events, BIP-340 Schnorr signatures over
+//! secp256k1), NIP-04 (decrypting direct messages) and NIP-19 (`npub`/`nsec`
+//! bech32 encoding) the bridge needs. Curve arithmetic is done with `k256`.
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::{engine::general_purpose, Engine as _};
+use cbc::cipher::{block_padding::Pkcs7, BlockDecryptMut, KeyIvInit};
+use k256::ecdh::diffie_hellman;
+use k256::schnorr::{Signature, SigningKey, VerifyingKey};
+use k256::PublicKey;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Text note.
+pub const KIND_TEXT_NOTE: u32 = 1;
+/// NIP-04 encrypted direct message.
+pub const KIND_ENCRYPTED_DM: u32 = 4;
+
+fn sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+/// BIP-340 tagged hash.
+fn tagged_hash(tag: &str, parts: &[&[u8]]) -> [u8; 32] {
+    let tag = sha256(tag.as_bytes());
+    let mut hasher = Sha256::new();
+    hasher.update(tag);
+    hasher.update(tag);
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+/// Bridge identity.
+#[derive(Clone)]
+pub struct Keys {
+    signing: SigningKey,
+    /// x-only public key
+    pub public: [u8; 32],
+}
+
+impl Keys {
+    pub fn from_secret(secret: [u8; 32]) -> Result<Self> {
+        let signing =
+            SigningKey::from_bytes(&secret).map_err(|_| anyhow!("Invalid Nostr secret key"))?;
+        let public = signing.verifying_key().to_bytes().into();
+        Ok(Self { signing, public })
+    }
+
+    /// Parse a secret key given as `nsec1...` or 64 hex characters.
+    pub fn parse(secret: &str) -> Result<Self> {
+        let secret = secret.trim();
+        let bytes = if secret.starts_with("nsec1") {
+            let (hrp, data) = bech32_decode(secret)?;
+            if hrp != "nsec" {
+                bail!("Expected an nsec key");
+            }
+            data
+        } else {
+            hex::decode(secret).context("Secret key must be nsec1... or hex")?
+        };
+        let secret: [u8; 32] = bytes
+            .try_into()
+            .map_err(|_| anyhow!("Secret key must be 32 bytes"))?;
+        Self::from_secret(secret)
+    }
+
+    /// Derive a key from a local seed and a node's public key, so every
+    /// bridged node gets its own stable identity.
+    pub fn derive(seed: &[u8], node_public_key: &[u8]) -> Result<Self> {
+        let mut material = tagged_hash("meshgrid/nostr", &[seed, node_public_key]);
+        // A hash >= n is astronomically unlikely; rehash rather than reduce
+        while Self::from_secret(material).is_err() {
+            material = sha256(&material);
+        }
+        Self::from_secret(material)
+    }
+
+    pub fn public_hex(&self) -> String {
+        hex::encode(self.public)
+    }
+
+    pub fn npub(&self) -> String {
+        bech32_encode("npub", &self.public)
+    }
+
+    /// BIP-340 signature of a 32-byte message.
+    pub fn sign(&self, msg: &[u8; 32], aux: &[u8; 32]) -> Result<[u8; 64]> {
+        let sig = self
+            .signing
+            .sign_raw(msg, aux)
+            .map_err(|_| anyhow!("Failed to sign event"))?;
+        Ok(sig.to_bytes())
+    }
+
+    /// NIP-04 shared secret with another public key.
+    fn shared_secret(&self, other: &[u8; 32]) -> Result<[u8; 32]> {
+        let mut compressed = [0u8; 33];
+        compressed[0] = 0x02;
+        compressed[1..].copy_from_slice(other);
+        let point =
+            PublicKey::from_sec1_bytes(&compressed).map_err(|_| anyhow!("Invalid public key"))?;
+        let shared = diffie_hellman(self.signing.as_nonzero_scalar(), point.as_affine());
+        Ok((*shared.raw_secret_bytes()).into())
+    }
+
+    /// Decrypt NIP-04 DM content (`base64?iv=base64`) from `sender`.
+    pub fn decrypt_dm(&self, sender: &[u8; 32], content: &str) -> Result<String> {
+        let (data, iv) = content
+            .split_once("?iv=")
+            .ok_or_else(|| anyhow!("Malformed encrypted message"))?;
+        let data = general_purpose::STANDARD.decode(data)?;
+        let iv = general_purpose::STANDARD.decode(iv)?;
+        let key = self.shared_secret(sender)?;
+        let plain = cbc::Decryptor::<aes::Aes256>::new_from_slices(&key, &iv)
+            .map_err(|_| anyhow!("Malformed encrypted message"))?
+            .decrypt_padded_vec_mut::<Pkcs7>(&data)
+            .map_err(|_| anyhow!("Failed to decrypt message"))?;
+        Ok(String::from_utf8(plain)?)
+    }
+}
+
+/// Verify a BIP-340 signature.
+pub fn verify(public: &[u8; 32], msg: &[u8; 32], sig: &[u8; 64]) -> bool {
+    let (Ok(key), Ok(sig)) = (
+        VerifyingKey::from_bytes(public),
+        Signature::try_from(&sig[..]),
+    ) else {
+        return false;
+    };
+    key.verify_raw(msg, &sig).is_ok()
+}
+
+/// Signed Nostr event (NIP-01).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Event {
+    pub id: String,
+    pub pubkey: String,
+    pub created_at: u64,
+    pub kind: u32,
+    pub tags: Vec<Vec<String>>,
+    pub content: String,
+    pub sig: String,
+}
+
+fn event_id(
+    pubkey: &str,
+    created_at: u64,
+    kind: u32,
+    tags: &[Vec<String>],
+    content: &str,
+) -> [u8; 32] {
+    let serialized = serde_json::json!([0, pubkey, created_at, kind, tags, content]).to_string();
+    sha256(serialized.as_bytes())
+}
+
+impl Event {
+    /// Create and sign an event.
+    pub fn sign(keys: &Keys, kind: u32, tags: Vec<Vec<String>>, content: &str) -> Result<Self> {
+        let pubkey = keys.public_hex();
+        let created_at = chrono::Utc::now().timestamp().max(0) as u64;
+        let id = event_id(&pubkey, created_at, kind, &tags, content);
+        let mut aux = [0u8; 32];
+        getrandom::getrandom(&mut aux)?;
+        let sig = keys.sign(&id, &aux)?;
+        Ok(Self {
+            id: hex::encode(id),
+            pubkey,
+            created_at,
+            kind,
+            tags,
+            content: content.to_string(),
+            sig: hex::encode(sig),
+        })
+    }
+
+    /// Check the id and signature.
+    pub fn verify(&self) -> bool {
+        let id = event_id(
+            &self.pubkey,
+            self.created_at,
+            self.kind,
+            &self.tags,
+            &self.content,
+        );
+        let (Ok(pubkey), Ok(sig)) = (hex::decode(&self.pubkey), hex::decode(&self.sig)) else {
+            return false;
+        };
+        let (Ok(pubkey), Ok(sig)) = (<[u8; 32]>::try_from(pubkey), <[u8; 64]>::try_from(sig))
+        else {
+            return false;
+        };
+        hex::encode(id) == self.id && verify(&pubkey, &id, &sig)
+    }
+
+    /// Author public key bytes.
+    pub fn author(&self) -> Option<[u8; 32]> {
+        hex::decode(&self.pubkey).ok()?.try_into().ok()
+    }
+
+    /// Whether the event tags `pubkey` with a `p` tag.
+    pub fn mentions(&self, pubkey: &str) -> bool {
+        self.tags.iter().any(|t| {
+            t.first().map(String::as_str) == Some("p")
+                && t.get(1).map(String::as_str) == Some(pubkey)
+        })
+    }
+}
+
+const BECH32_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+fn bech32_polymod(values: &[u8]) -> u32 {
+    const GEN: [u32; 5] = [
+        0x3b6a_57b2,
+        0x2650_8e6d,
+        0x1ea1_19fa,
+        0x3d42_33dd,
+        0x2a14_62b3,
+    ];
+    let mut chk = 1u32;
+    for &v in values {
+        let top = chk >> 25;
+        chk = ((chk & 0x01ff_ffff) << 5) ^ u32::from(v);
+        for (i, g) in GEN.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                chk ^= g;
+            }
+        }
+    }
+    chk
+}
+
+fn bech32_hrp_expand(hrp: &str) -> Vec<u8> {
+    let mut out: Vec<u8> = hrp.bytes().map(|b| b >> 5).collect();
+    out.push(0);
+    out.extend(hrp.bytes().map(|b| b & 31));
+    out
+}
+
+/// Regroup bits (8 -> 5 when encoding, 5 -> 8 when decoding).
+fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Result<Vec<u8>> {
+    let mut acc = 0u32;
+    let mut bits = 0u32;
+    let mut out = Vec::new();
+    let max = (1u32 << to) - 1;
+    for &v in data {
+        acc = (acc << from) | u32::from(v);
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            out.push(((acc >> bits) & max) as u8);
+        }
+    }
+    if pad {
+        if bits > 0 {
+            out.push(((acc << (to - bits)) & max) as u8);
+        }
+    } else if bits >= from || (acc << (to - bits)) & max != 0 {
+        bail!("Invalid bech32 padding");
+    }
+    Ok(out)
+}
+
+/// Encode bytes as bech32 (NIP-19 `npub`/`nsec`).
+pub fn bech32_encode(hrp: &str, data: &[u8]) -> String {
+    let data = convert_bits(data, 8, 5, true).expect("padding allowed");
+    let mut values = bech32_hrp_expand(hrp);
+    values.extend(&data);
+    values.extend([0u8; 6]);
+    let checksum = bech32_polymod(&values) ^ 1;
+
+    let mut out = format!("{hrp}1");
+    for v in data {
+        out.push(BECH32_CHARSET[usize::from(v)] as char);
+    }
+    for i in 0..6 {
+        out.push(BECH32_CHARSET[((checksum >> (5 * (5 - i))) & 31) as usize] as char);
+    }
+    out
+}
+
+/// Decode a bech32 string into (hrp, bytes).
+pub fn bech32_decode(s: &str) -> Result<(String, Vec<u8>)> {
+    let s = s.to_ascii_lowercase();
+    let (hrp, data) = s
+        .rsplit_once('1')
+        .ok_or_else(|| anyhow!("Invalid bech32 string"))?;
+    if hrp.is_empty() || data.len() < 6 {
+        bail!("Invalid bech32 string");
+    }
+    let values = data
+        .bytes()
+        .map(|c| {
+            BECH32_CHARSET
+                .iter()
+                .position(|&x| x == c)
+                .map(|p| p as u8)
+                .ok_or_else(|| anyhow!("Invalid bech32 character '{}'", c as char))
+        })
+        .collect::<Result<Vec<u8>>>()?;
+
+    let mut check = bech32_hrp_expand(hrp);
+    check.extend(&values);
+    if bech32_polymod(&check) != 1 {
+        bail!("Invalid bech32 checksum");
+    }
+    let bytes = convert_bits(&values[..values.len() - 6], 5, 8, false)?;
+    Ok((hrp.to_string(), bytes))
+}
+
+/// Parse a public key given as `npub1...` or 64 hex characters.
+pub fn parse_public_key(key: &str) -> Result<String> {
+    let key = key.trim();
+    let bytes = if key.starts_with("npub1") {
+        let (hrp, data) = bech32_decode(key)?;
+        if hrp != "npub" {
+            bail!("Expected an npub key");
+        }
+        data
+    } else {
+        hex::decode(key).with_context(|| format!("Invalid public key '{key}'"))?
+    };
+    if bytes.len() != 32 {
+        bail!("Public key must be 32 bytes");
+    }
+    Ok(hex::encode(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bip340_vector() {
+        // BIP-340 test vector 0
+        let mut secret = [0u8; 32];
+        secret[31] = 3;
+        let keys = Keys::from_secret(secret).unwrap();
+        assert_eq!(
+            keys.public_hex(),
+            "f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9"
+        );
+        let sig = keys.sign(&[0u8; 32], &[0u8; 32]).unwrap();
+        assert_eq!(
+            hex::encode_upper(sig),
+            "E907831F80848D1069A5371B402410364BDF1C5F8307B0084C55F1CE2DCA8215\
+             25F66A4A85EA8B71E482A74F382D2CE5EBEEE8FDB2172F477DF4900D310536C0"
+        );
+        assert!(verify(&keys.public, &[0u8; 32], &sig));
+        assert!(!verify(&keys.public, &[1u8; 32], &sig));
+
+        let event = Event::sign(&keys, KIND_TEXT_NOTE, vec![], "hello").unwrap();
+        assert!(event.verify());
+
+        let other = Keys::derive(b"seed", b"node").unwrap();
+        assert_eq!(
+            keys.shared_secret(&other.public).unwrap(),
+            other.shared_secret(&keys.public).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_nip19_keys() {
+        let npub = "npub10elfcs4fr0l0r8af98jlmgdh9c8tcxjvz9qkw038js35mp4dma8qzvjptg";
+        let hex = "7e7e9c42a91bfef19fa929e5fda1b72e0ebc1a4c1141673e2794234d86addf4e";
+        assert_eq!(parse_public_key(npub).unwrap(), hex);
+        assert_eq!(bech32_encode("npub", &hex::decode(hex).unwrap()), npub);
+        assert!(parse_public_key(
+            "npub10elfcs4fr0l0r8af98jlmgdh9c8tcxjvz9qkw038js35mp4dma8qzvjptx"
+        )
+        .is_err());
+    }
+}
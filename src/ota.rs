@@ -0,0 +1,153 @@
+//! ArduinoOTA (espota) network updates.
+//!
+//! The host invites the device over UDP with `<command> <port> <size> <md5>`,
+//! answers a password challenge if the device sends `AUTH <nonce>`, then
+//! waits for the device to connect back over TCP and streams the app image
+//! in 1 KB chunks. The device acknowledges each chunk and sends `OK` once
+//! the image is written and its MD5 checked.
+
+use anyhow::{bail, Context, Result};
+use md5::{Digest, Md5};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::time::timeout;
+
+/// Port ArduinoOTA listens on unless configured otherwise.
+pub const DEFAULT_PORT: u16 = 3232;
+
+const COMMAND_FLASH: u32 = 0;
+const COMMAND_AUTH: u32 = 200;
+const CHUNK: usize = 1024;
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+/// The device checks the image and switches partitions after the last chunk.
+const FINISH_TIMEOUT: Duration = Duration::from_secs(60);
+
+fn md5_hex(data: &[u8]) -> Result<String> {
+    Ok(hex::encode(Md5::digest(data)))
+}
+
+/// `host[:port]` to a socket address.
+async fn resolve(target: &str) -> Result<SocketAddr> {
+    let target = match target.parse::<std::net::IpAddr>() {
+        Ok(ip) => return Ok(SocketAddr::new(ip, DEFAULT_PORT)),
+        Err(_) if target.contains(':') => target.to_string(),
+        Err(_) => format!("{target}:{DEFAULT_PORT}"),
+    };
+    let mut addrs = tokio::net::lookup_host(target.as_str())
+        .await
+        .with_context(|| format!("Cannot resolve {target}"))?;
+    addrs
+        .next()
+        .with_context(|| format!("Cannot resolve {target}"))
+}
+
+/// Answer to an `AUTH <nonce>` challenge: (cnonce, response).
+fn auth_response(password: &str, nonce: &str, image_md5: &str) -> Result<(String, String)> {
+    let cnonce = md5_hex(format!("{image_md5}{nonce}{}", std::process::id()).as_bytes())?;
+    let response =
+        md5_hex(format!("{}:{nonce}:{cnonce}", md5_hex(password.as_bytes())?).as_bytes())?;
+    Ok((cnonce, response))
+}
+
+/// Send the app `image` to the device at `target` (`host[:port]`), calling
+/// `progress` with the bytes sent so far.
+pub async fn upload(
+    target: &str,
+    image: &[u8],
+    password: Option<&str>,
+    mut progress: impl FnMut(usize),
+) -> Result<()> {
+    let device = resolve(target).await?;
+    let image_md5 = md5_hex(image)?;
+    let listener = TcpListener::bind(if device.is_ipv6() {
+        "[::]:0"
+    } else {
+        "0.0.0.0:0"
+    })
+    .await?;
+    let local_port = listener.local_addr()?.port();
+
+    let socket = UdpSocket::bind(if device.is_ipv6() {
+        "[::]:0"
+    } else {
+        "0.0.0.0:0"
+    })
+    .await?;
+    socket.connect(device).await?;
+    let invitation = format!("{COMMAND_FLASH} {local_port} {} {image_md5}\n", image.len());
+    let reply = exchange(&socket, &invitation).await?;
+    if let Some(nonce) = reply.strip_prefix("AUTH ") {
+        let Some(password) = password else {
+            bail!("{target} requires an OTA password (--ota-password)");
+        };
+        let (cnonce, response) = auth_response(password, nonce.trim(), &image_md5)?;
+        let reply = exchange(&socket, &format!("{COMMAND_AUTH} {cnonce} {response}\n")).await?;
+        if reply != "OK" {
+            bail!("{target} rejected the OTA password");
+        }
+    } else if reply != "OK" {
+        bail!("{target} refused the update: {reply}");
+    }
+
+    let (mut stream, _) = timeout(REPLY_TIMEOUT, listener.accept()).await.context(
+        "The device did not connect back (is a firewall blocking incoming connections?)",
+    )??;
+    let mut buf = [0u8; 32];
+    let mut sent = 0;
+    let mut last_reply = String::new();
+    for chunk in image.chunks(CHUNK) {
+        stream.write_all(chunk).await?;
+        sent += chunk.len();
+        progress(sent);
+        let n = timeout(REPLY_TIMEOUT, stream.read(&mut buf))
+            .await
+            .context("The device stopped acknowledging the upload")??;
+        if n == 0 {
+            bail!("The device closed the connection after {sent} bytes");
+        }
+        last_reply = String::from_utf8_lossy(&buf[..n]).into_owned();
+    }
+
+    let mut reply = last_reply;
+    while !reply.contains("OK") {
+        let n = timeout(FINISH_TIMEOUT, stream.read(&mut buf))
+            .await
+            .context("No result from the device after the upload")??;
+        if n == 0 {
+            bail!("The device closed the connection without confirming the update");
+        }
+        reply = String::from_utf8_lossy(&buf[..n]).into_owned();
+        if reply.contains('E') && !reply.contains("OK") {
+            bail!("The device rejected the image: {}", reply.trim());
+        }
+    }
+    Ok(())
+}
+
+/// Send a UDP message and wait for the reply.
+async fn exchange(socket: &UdpSocket, message: &str) -> Result<String> {
+    socket.send(message.as_bytes()).await?;
+    let mut buf = [0u8; 64];
+    let n = timeout(REPLY_TIMEOUT, socket.recv(&mut buf))
+        .await
+        .context("No answer from the device (is OTA enabled and the address right?)")??;
+    Ok(String::from_utf8_lossy(&buf[..n]).trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_auth_response() {
+        let (cnonce, response) = auth_response("secret", "0123abcd", "d41d8cd9").unwrap();
+        assert_eq!(cnonce.len(), 32);
+        let expected =
+            md5_hex(format!("{}:0123abcd:{cnonce}", md5_hex(b"secret").unwrap()).as_bytes())
+                .unwrap();
+        assert_eq!(response, expected);
+        assert_eq!(md5_hex(b"").unwrap(), "d41d8cd98f00b204e9800998ecf8427e");
+    }
+}
@@ -0,0 +1,155 @@
+//! Store-and-forward outbox.
+//!
+//! `outbox.toml` next to the config file keeps the direct messages that
+//! `send --queue` couldn't deliver because the destination was out of
+//! range. `monitor` sends them when the node advertises again.
+
+use crate::settings::Settings;
+use crate::vault;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// A message waiting for its destination to reappear.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Queued {
+    pub id: u32,
+    /// Destination as given to `send --to`
+    pub to: String,
+    /// Node hash of the destination, when it was known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node_hash: Option<u8>,
+    pub text: String,
+    /// When the message was queued (RFC 3339)
+    pub queued: String,
+}
+
+impl Queued {
+    /// Whether an advert from `node_hash` (named `name`) is this message's
+    /// destination.
+    pub fn is_for(&self, node_hash: u8, name: Option<&str>) -> bool {
+        match self.node_hash {
+            Some(hash) => hash == node_hash,
+            None => name.is_some_and(|name| name.eq_ignore_ascii_case(&self.to)),
+        }
+    }
+
+    /// Seconds since the message was queued.
+    pub fn waited_secs(&self) -> u64 {
+        DateTime::parse_from_rfc3339(&self.queued).map_or(0, |t| {
+            u64::try_from((Utc::now() - t.with_timezone(&Utc)).num_seconds()).unwrap_or(0)
+        })
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Store {
+    #[serde(default)]
+    messages: Vec<Queued>,
+}
+
+impl Store {
+    fn push(&mut self, to: &str, node_hash: Option<u8>, text: &str) -> Queued {
+        let queued = Queued {
+            id: self.messages.iter().map(|m| m.id).max().unwrap_or(0) + 1,
+            to: to.to_string(),
+            node_hash,
+            text: text.to_string(),
+            queued: Utc::now().to_rfc3339(),
+        };
+        self.messages.push(queued.clone());
+        queued
+    }
+}
+
+fn store_path() -> Result<PathBuf> {
+    Ok(Settings::path()?.with_file_name("outbox.toml"))
+}
+
+fn read_store() -> Result<Store> {
+    let path = store_path()?;
+    vault::ensure_unlocked(&path)?;
+    match std::fs::read_to_string(&path) {
+        Ok(content) => toml::from_str(&content)
+            .with_context(|| format!("Invalid outbox file {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Store::default()),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+fn write_store(store: &Store) -> Result<()> {
+    let path = store_path()?;
+    vault::ensure_unlocked(&path)?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&path, toml::to_string(store)?)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Queue `text` for `to`.
+pub fn add(to: &str, node_hash: Option<u8>, text: &str) -> Result<Queued> {
+    let mut store = read_store()?;
+    let queued = store.push(to, node_hash, text);
+    write_store(&store)?;
+    Ok(queued)
+}
+
+/// Queued messages, oldest first.
+pub fn list() -> Result<Vec<Queued>> {
+    Ok(read_store()?.messages)
+}
+
+/// Queued messages for the node behind an advert, oldest first.
+pub fn waiting_for(node_hash: u8, name: Option<&str>) -> Result<Vec<Queued>> {
+    Ok(list()?
+        .into_iter()
+        .filter(|m| m.is_for(node_hash, name))
+        .collect())
+}
+
+/// Drop message `id` (sent or cancelled), returning it if it was queued.
+pub fn remove(id: u32) -> Result<Option<Queued>> {
+    let mut store = read_store()?;
+    let Some(index) = store.messages.iter().position(|m| m.id == id) else {
+        return Ok(None);
+    };
+    let removed = store.messages.remove(index);
+    write_store(&store)?;
+    Ok(Some(removed))
+}
+
+/// Drop every queued message, returning how many there were.
+pub fn clear() -> Result<usize> {
+    let mut store = read_store()?;
+    let count = store.messages.len();
+    if count > 0 {
+        store.messages.clear();
+        write_store(&store)?;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_outbox_store() {
+        let mut store = Store::default();
+        let alice = store.push("Alice", Some(0x3f), "Meet at 5");
+        let bob = store.push("bob", None, "Hi");
+        assert_eq!((alice.id, bob.id), (1, 2));
+
+        assert!(alice.is_for(0x3f, None));
+        assert!(!alice.is_for(0x40, Some("Alice")));
+        // Without a hash, the advertised name decides
+        assert!(bob.is_for(0x12, Some("Bob")));
+        assert!(!bob.is_for(0x12, None));
+
+        let parsed: Store = toml::from_str(&toml::to_string(&store).unwrap()).unwrap();
+        assert_eq!(parsed.messages, store.messages);
+        assert!(alice.waited_secs() < 5);
+    }
+}
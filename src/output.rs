@@ -0,0 +1,377 @@
+//! Process-wide output mode.
+//!
+//! `--quiet`/`--plain` switches commands from decorated, human-oriented output
+//! (banners, emoji, box drawing, progress bars) to plain `key=value` lines that
+//! are easy to `grep` and `awk`.
+//!
+//! Decorated output goes through [`render`] (or the [`outln!`] macro), which
+//! colors the ✓/✗/⚠ markers and degrades emoji and box drawing to ASCII on
+//! terminals that can't show them: `TERM=dumb`, the legacy Windows console,
+//! or when `MESHGRID_NO_EMOJI` is set. `--color` and `NO_COLOR` control the
+//! colors.
+//!
+//! `--output json|yaml` replaces the pretty view of commands that support it
+//! with the same data in machine-readable form, via [`print`].
+
+use clap::ValueEnum;
+use console::style;
+use indicatif::ProgressStyle;
+use serde::Serialize;
+use serde_json::Value;
+use std::borrow::Cow;
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+static PLAIN: AtomicBool = AtomicBool::new(false);
+static ASCII: AtomicBool = AtomicBool::new(false);
+static FORMAT: AtomicU8 = AtomicU8::new(OutputFormat::Table as u8);
+
+/// How commands print their results.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Human-readable tables and text
+    #[default]
+    Table,
+    /// Pretty-printed JSON
+    Json,
+    /// YAML
+    Yaml,
+}
+
+/// When to use colors.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ColorMode {
+    /// Color when writing to a terminal and NO_COLOR is not set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// Enable or disable plain output.
+pub fn set_plain(plain: bool) {
+    PLAIN.store(plain, Ordering::Relaxed);
+}
+
+/// Whether plain output is enabled.
+pub fn is_plain() -> bool {
+    PLAIN.load(Ordering::Relaxed)
+}
+
+/// Select the output format.
+pub fn set_format(format: OutputFormat) {
+    FORMAT.store(format as u8, Ordering::Relaxed);
+}
+
+/// The selected output format.
+pub fn format() -> OutputFormat {
+    match FORMAT.load(Ordering::Relaxed) {
+        1 => OutputFormat::Json,
+        2 => OutputFormat::Yaml,
+        _ => OutputFormat::Table,
+    }
+}
+
+/// Whether `--output json` or `--output yaml` was given.
+pub fn is_structured() -> bool {
+    format() != OutputFormat::Table
+}
+
+/// Print `value` in the selected format (JSON for `table`).
+pub fn print(value: &impl Serialize) -> anyhow::Result<()> {
+    match format() {
+        OutputFormat::Yaml => print!("{}", to_yaml(&serde_json::to_value(value)?)),
+        _ => println!("{}", serde_json::to_string_pretty(value)?),
+    }
+    Ok(())
+}
+
+/// Block-style YAML for a JSON value.
+pub fn to_yaml(value: &Value) -> String {
+    fn scalar(value: &Value) -> String {
+        match value {
+            Value::Null => "null".into(),
+            Value::String(s) => string(s),
+            Value::Object(_) => "{}".into(),
+            Value::Array(_) => "[]".into(),
+            other => other.to_string(),
+        }
+    }
+    // Plain when it can't be read as anything but a string, else quoted
+    // (a JSON string is a valid YAML double-quoted scalar)
+    fn string(s: &str) -> String {
+        let reserved = [
+            "true", "false", "null", "yes", "no", "on", "off", "y", "n", "~",
+        ];
+        let plain = s.starts_with(|c: char| c.is_ascii_alphabetic())
+            && s.chars()
+                .all(|c| c.is_ascii_alphanumeric() || " _-./()+".contains(c))
+            && !s.ends_with(' ')
+            && !reserved.contains(&s.to_ascii_lowercase().as_str());
+        if plain {
+            s.to_string()
+        } else {
+            Value::String(s.to_string()).to_string()
+        }
+    }
+    fn nested(value: &Value) -> bool {
+        match value {
+            Value::Object(map) => !map.is_empty(),
+            Value::Array(items) => !items.is_empty(),
+            _ => false,
+        }
+    }
+    fn block(value: &Value, indent: usize, out: &mut String) {
+        let pad = " ".repeat(indent);
+        match value {
+            Value::Object(map) if nested(value) => {
+                for (key, v) in map {
+                    out.push_str(&format!("{pad}{}:", string(key)));
+                    if nested(v) {
+                        out.push('\n');
+                        block(v, indent + 2, out);
+                    } else {
+                        out.push_str(&format!(" {}\n", scalar(v)));
+                    }
+                }
+            }
+            Value::Array(items) if nested(value) => {
+                for v in items {
+                    // The item's first line goes after the dash
+                    let mut item = String::new();
+                    block(v, indent + 2, &mut item);
+                    out.push_str(&format!("{pad}- {}", &item[indent + 2..]));
+                }
+            }
+            other => out.push_str(&format!("{pad}{}\n", scalar(other))),
+        }
+    }
+
+    let mut out = String::new();
+    block(value, 0, &mut out);
+    out
+}
+
+/// Set up colors and emoji for this terminal.
+pub fn set_style(color: ColorMode) {
+    let env_set = |name: &str| std::env::var_os(name).is_some_and(|v| !v.is_empty());
+    let colors = match color {
+        ColorMode::Always => Some(true),
+        ColorMode::Never => Some(false),
+        ColorMode::Auto if env_set("NO_COLOR") => Some(false),
+        ColorMode::Auto => None,
+    };
+    if let Some(colors) = colors {
+        console::set_colors_enabled(colors);
+        console::set_colors_enabled_stderr(colors);
+    }
+
+    let dumb = std::env::var("TERM").is_ok_and(|t| t == "dumb");
+    // Windows Terminal sets WT_SESSION; conhost can't show emoji
+    let legacy_console = cfg!(windows) && !env_set("WT_SESSION");
+    ASCII.store(
+        env_set("MESHGRID_NO_EMOJI") || dumb || legacy_console,
+        Ordering::Relaxed,
+    );
+}
+
+/// Whether emoji and box drawing are replaced with ASCII.
+pub fn is_ascii() -> bool {
+    ASCII.load(Ordering::Relaxed)
+}
+
+fn is_emoji(c: char) -> bool {
+    matches!(c, '\u{1F000}'..='\u{1FAFF}' | '\u{2600}'..='\u{26FF}' | '\u{FE0F}')
+}
+
+/// ASCII for a box drawing or marker character.
+fn ascii_for(c: char) -> Option<&'static str> {
+    Some(match c {
+        '✓' => "[OK]",
+        '✗' => "[FAIL]",
+        '⚠' => "[WARN]",
+        '→' => "->",
+        '•' => "*",
+        '═' => "=",
+        '─' => "-",
+        '║' | '│' => "|",
+        '╔' | '╗' | '╚' | '╝' | '┌' | '┐' | '└' | '┘' | '├' | '┤' | '╠' | '╣' => {
+            "+"
+        }
+        '█' | '▓' => "#",
+        '░' => ".",
+        _ => return None,
+    })
+}
+
+fn render_with(text: &str, ascii: bool, colors: bool) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let replacement = ascii_for(c);
+        if ascii {
+            // Drop the variation selector that asks for the emoji form
+            while chars.next_if_eq(&'\u{FE0F}').is_some() {}
+            if replacement.is_none() && is_emoji(c) {
+                chars.next_if_eq(&' ');
+                continue;
+            }
+        }
+        let shown: Cow<str> = match replacement {
+            Some(a) if ascii => a.into(),
+            _ => c.to_string().into(),
+        };
+        match c {
+            '✓' if colors => out.push_str(&style(shown).force_styling(true).green().to_string()),
+            '✗' if colors => out.push_str(&style(shown).force_styling(true).red().to_string()),
+            '⚠' if colors => out.push_str(&style(shown).force_styling(true).yellow().to_string()),
+            _ => out.push_str(&shown),
+        }
+    }
+    out
+}
+
+/// `text` as it should appear on this terminal: markers colored, emoji and
+/// box drawing replaced when the terminal can't show them.
+pub fn render(text: &str) -> Cow<'_, str> {
+    let ascii = is_ascii();
+    let colors = console::colors_enabled();
+    if !ascii && (!colors || !text.contains(['✓', '✗', '⚠'])) {
+        return text.into();
+    }
+    render_with(text, ascii, colors).into()
+}
+
+/// `println!` through [`render`].
+#[macro_export]
+macro_rules! outln {
+    () => {
+        println!()
+    };
+    ($($arg:tt)*) => {
+        println!("{}", $crate::output::render(&format!($($arg)*)))
+    };
+}
+
+/// Progress bar style shared by the flash, download and fuzz bars.
+pub fn progress_style(template: &str) -> ProgressStyle {
+    let style = ProgressStyle::default_bar()
+        .template(template)
+        .unwrap_or_else(|_| ProgressStyle::default_bar());
+    if is_ascii() {
+        style.progress_chars("#>-").tick_chars("|/-\\ ")
+    } else {
+        style.progress_chars("█▓░")
+    }
+}
+
+/// Print one `key=value` line.
+pub fn kv(key: &str, value: impl Display) {
+    println!("{key}={value}");
+}
+
+/// Flatten JSON into `key=value` pairs with dotted keys (`memory.ram_used_kb`).
+///
+/// Array elements are keyed by index; strings are printed without quotes.
+pub fn flatten_json(json: &serde_json::Value) -> Vec<(String, String)> {
+    fn walk(prefix: &str, value: &serde_json::Value, out: &mut Vec<(String, String)>) {
+        let key = |k: &str| {
+            if prefix.is_empty() {
+                k.to_string()
+            } else {
+                format!("{prefix}.{k}")
+            }
+        };
+        match value {
+            serde_json::Value::Object(map) => {
+                for (k, v) in map {
+                    walk(&key(k), v, out);
+                }
+            }
+            serde_json::Value::Array(items) => {
+                for (i, v) in items.iter().enumerate() {
+                    walk(&key(&i.to_string()), v, out);
+                }
+            }
+            serde_json::Value::String(s) => out.push((prefix.to_string(), s.clone())),
+            serde_json::Value::Null => out.push((prefix.to_string(), String::new())),
+            other => out.push((prefix.to_string(), other.to_string())),
+        }
+    }
+
+    let mut out = Vec::new();
+    walk("", json, &mut out);
+    out
+}
+
+/// Print JSON as flattened `key=value` lines.
+pub fn print_json_kv(json: &serde_json::Value) {
+    for (key, value) in flatten_json(json) {
+        kv(&key, value);
+    }
+}
+
+/// Quote a CSV field if it needs it.
+pub fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flatten_json() {
+        let json = serde_json::json!({
+            "hardware": { "board": "heltec_v3", "cores": 2 },
+            "features": { "hw_aes": true },
+            "list": [1, 2],
+        });
+        let flat = flatten_json(&json);
+        assert!(flat.contains(&("hardware.board".into(), "heltec_v3".into())));
+        assert!(flat.contains(&("hardware.cores".into(), "2".into())));
+        assert!(flat.contains(&("features.hw_aes".into(), "true".into())));
+        assert!(flat.contains(&("list.1".into(), "2".into())));
+    }
+
+    #[test]
+    fn test_to_yaml() {
+        let json = serde_json::json!({
+            "name": "Base camp",
+            "hash": "0x1a",
+            "radio": { "freq_mhz": 869.525, "sf": 11 },
+            "channels": [{ "name": "#test", "builtin": false }, { "name": "Public" }],
+            "tags": ["yes", "solar"],
+            "firmware": null,
+            "empty": [],
+        });
+        assert_eq!(
+            to_yaml(&json),
+            "name: Base camp\n\
+             hash: \"0x1a\"\n\
+             radio:\n  freq_mhz: 869.525\n  sf: 11\n\
+             channels:\n  - name: \"#test\"\n    builtin: false\n  - name: Public\n\
+             tags:\n  - \"yes\"\n  - solar\n\
+             firmware: null\n\
+             empty: []\n"
+        );
+        assert_eq!(to_yaml(&serde_json::json!([])), "[]\n");
+    }
+
+    #[test]
+    fn test_render() {
+        let stats = "╔══╗\n║ 📟 Device ║\n✓ Flashed → 0.0.5\n⚠️ Low battery";
+        assert_eq!(
+            render_with(stats, true, false),
+            "+==+\n| Device |\n[OK] Flashed -> 0.0.5\n[WARN] Low battery"
+        );
+        assert_eq!(render_with(stats, false, false), stats);
+        let colored = render_with("✗ Failed", false, true);
+        assert!(colored.starts_with("\u{1b}[31m✗"));
+        assert!(colored.ends_with(" Failed"));
+    }
+}
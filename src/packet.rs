@@ -0,0 +1,429 @@
+//! MeshCore packet building and dissection.
+//!
+//! A packet on air is:
+//!
+//! ```text
+//! header (1) | [transport codes (4)] | path length (1) | path (n) | payload
+//! ```
+//!
+//! The header holds the route type (bits 0-1), payload type (bits 2-5) and
+//! payload version (bits 6-7). Transport codes are only present on the
+//! transport route types, and the path lists the 1-byte hashes of the nodes
+//! a direct packet travels through (or a flood packet has travelled).
+//!
+//! `raw build` assembles packets from named fields with [`build`] and
+//! `recv` describes received packets with [`Packet::parse`].
+
+use aes::cipher::{generic_array::GenericArray, BlockDecrypt, BlockEncrypt, KeyInit};
+use aes::Aes128;
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use ed25519_dalek::{Signer, SigningKey};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::ops::Range;
+
+use crate::error::CliError;
+
+/// Longest payload the firmware accepts.
+pub const MAX_PAYLOAD: usize = 184;
+
+/// Longest path (in node hashes).
+pub const MAX_PATH: usize = 64;
+
+/// PSK of the MeshCore public channel.
+pub const PUBLIC_CHANNEL_PSK: &str = "izOH6cXN6mrJ5e26oRXNcg==";
+
+/// How a packet is routed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum RouteType {
+    TransportFlood,
+    Flood,
+    Direct,
+    TransportDirect,
+}
+
+impl RouteType {
+    fn has_transport_codes(self) -> bool {
+        matches!(self, RouteType::TransportFlood | RouteType::TransportDirect)
+    }
+}
+
+/// What a packet's payload carries.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum PayloadType {
+    Req,
+    Response,
+    Txt,
+    Ack,
+    Advert,
+    GrpTxt,
+    GrpData,
+    AnonReq,
+    Path,
+    Trace,
+    Multipart,
+    RawCustom,
+}
+
+impl PayloadType {
+    const ALL: [PayloadType; 12] = [
+        PayloadType::Req,
+        PayloadType::Response,
+        PayloadType::Txt,
+        PayloadType::Ack,
+        PayloadType::Advert,
+        PayloadType::GrpTxt,
+        PayloadType::GrpData,
+        PayloadType::AnonReq,
+        PayloadType::Path,
+        PayloadType::Trace,
+        PayloadType::Multipart,
+        PayloadType::RawCustom,
+    ];
+
+    fn code(self) -> u8 {
+        match self {
+            PayloadType::Req => 0x00,
+            PayloadType::Response => 0x01,
+            PayloadType::Txt => 0x02,
+            PayloadType::Ack => 0x03,
+            PayloadType::Advert => 0x04,
+            PayloadType::GrpTxt => 0x05,
+            PayloadType::GrpData => 0x06,
+            PayloadType::AnonReq => 0x07,
+            PayloadType::Path => 0x08,
+            PayloadType::Trace => 0x09,
+            PayloadType::Multipart => 0x0a,
+            PayloadType::RawCustom => 0x0f,
+        }
+    }
+
+    fn from_code(code: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|t| t.code() == code)
+    }
+}
+
+/// A decoded packet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    pub route: RouteType,
+    pub payload_type: PayloadType,
+    pub version: u8,
+    pub transport_codes: Option<[u16; 2]>,
+    pub path: Vec<u8>,
+    pub payload: Vec<u8>,
+}
+
+impl Packet {
+    /// Wire encoding of the packet.
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        if self.path.len() > MAX_PATH {
+            bail!(CliError::InvalidArgs(format!(
+                "Path has {} hops, at most {MAX_PATH} fit",
+                self.path.len()
+            )));
+        }
+        if self.payload.len() > MAX_PAYLOAD {
+            bail!(CliError::InvalidArgs(format!(
+                "Payload is {} bytes, at most {MAX_PAYLOAD} fit",
+                self.payload.len()
+            )));
+        }
+        let route = self.route as u8;
+        let mut out = vec![route | (self.payload_type.code() << 2) | (self.version << 6)];
+        if self.route.has_transport_codes() {
+            for code in self.transport_codes.unwrap_or_default() {
+                out.extend(code.to_le_bytes());
+            }
+        }
+        out.push(self.path.len() as u8);
+        out.extend(&self.path);
+        out.extend(&self.payload);
+        Ok(out)
+    }
+
+    /// Decode a packet received off air.
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let (&header, mut rest) = data.split_first().context("Empty packet")?;
+        let route = RouteType::value_variants()[usize::from(header & 0x03)];
+        let payload_type = PayloadType::from_code((header >> 2) & 0x0f)
+            .with_context(|| format!("Unknown payload type 0x{:x}", (header >> 2) & 0x0f))?;
+        let transport_codes = if route.has_transport_codes() {
+            let (codes, tail) = rest
+                .split_first_chunk::<4>()
+                .context("Packet is truncated")?;
+            rest = tail;
+            Some([
+                u16::from_le_bytes([codes[0], codes[1]]),
+                u16::from_le_bytes([codes[2], codes[3]]),
+            ])
+        } else {
+            None
+        };
+        let (&path_len, rest) = rest.split_first().context("Packet is truncated")?;
+        if rest.len() < usize::from(path_len) {
+            bail!("Packet is truncated");
+        }
+        let (path, payload) = rest.split_at(usize::from(path_len));
+        Ok(Self {
+            route,
+            payload_type,
+            version: header >> 6,
+            transport_codes,
+            path: path.to_vec(),
+            payload: payload.to_vec(),
+        })
+    }
+
+    /// Byte ranges of the header fields in the encoded packet; the payload
+    /// follows the last.
+    pub fn fields(&self) -> Vec<(&'static str, Range<usize>)> {
+        let mut fields = vec![("header", 0..1)];
+        let mut at = 1;
+        if self.transport_codes.is_some() {
+            fields.push(("transport codes", 1..5));
+            at = 5;
+        }
+        fields.push(("path length", at..at + 1));
+        at += 1;
+        if !self.path.is_empty() {
+            fields.push(("path", at..at + self.path.len()));
+        }
+        fields
+    }
+
+    /// One-line description, e.g. `flood advert, 2 hops (a1,b2), 101-byte payload`.
+    pub fn summary(&self) -> String {
+        let name = |value: Option<clap::builder::PossibleValue>| {
+            value.map_or_else(String::new, |v| v.get_name().to_string())
+        };
+        let mut summary = format!(
+            "{} {}",
+            name(self.route.to_possible_value()),
+            name(self.payload_type.to_possible_value())
+        );
+        if !self.path.is_empty() {
+            let hops: Vec<String> = self.path.iter().map(|h| format!("{h:02x}")).collect();
+            summary.push_str(&format!(", {} hops ({})", hops.len(), hops.join(",")));
+        }
+        if let Some([a, b]) = self.transport_codes {
+            summary.push_str(&format!(", transport codes {a:04x},{b:04x}"));
+        }
+        summary.push_str(&format!(", {}-byte payload", self.payload.len()));
+        summary
+    }
+}
+
+/// Named fields for [`build`].
+#[derive(Debug, Default)]
+pub struct Fields<'a> {
+    /// Text payload (advert name, channel message)
+    pub text: Option<&'a str>,
+    /// Payload bytes, used as-is for any type
+    pub raw: Option<Vec<u8>>,
+    /// Ed25519 seed (hex) to sign adverts with
+    pub key: Option<&'a str>,
+    /// Base64 channel PSK for group messages
+    pub channel_psk: Option<&'a str>,
+}
+
+/// Build a packet, computing signatures, hashes and MACs from `fields`.
+pub fn build(
+    route: RouteType,
+    payload_type: PayloadType,
+    path: Vec<u8>,
+    fields: &Fields,
+) -> Result<Packet> {
+    let timestamp = u32::try_from(chrono::Utc::now().timestamp()).unwrap_or_default();
+    let payload = match (&fields.raw, payload_type) {
+        (Some(raw), _) => raw.clone(),
+        (None, PayloadType::Advert) => advert_payload(fields.text, fields.key, timestamp)?,
+        (None, PayloadType::GrpTxt) => {
+            let text = fields.text.context(CliError::InvalidArgs(
+                "grp-txt needs --payload with the message".into(),
+            ))?;
+            group_text_payload(
+                fields.channel_psk.unwrap_or(PUBLIC_CHANNEL_PSK),
+                text,
+                timestamp,
+            )?
+        }
+        (None, PayloadType::Trace) => {
+            // Random tag, no auth code, 1-byte path hashes
+            let mut payload = vec![0u8; 9];
+            getrandom::getrandom(&mut payload[..4])?;
+            payload
+        }
+        (None, PayloadType::RawCustom) => fields.text.unwrap_or_default().as_bytes().to_vec(),
+        (None, other) => bail!(CliError::InvalidArgs(format!(
+            "{} payloads are encrypted per peer; give the payload bytes with --hex",
+            other
+                .to_possible_value()
+                .map_or_else(String::new, |v| v.get_name().to_string())
+        ))),
+    };
+    Ok(Packet {
+        route,
+        payload_type,
+        version: 0,
+        transport_codes: route.has_transport_codes().then_some([0, 0]),
+        path,
+        payload,
+    })
+}
+
+/// Signed advert: public key (32) | timestamp (4) | signature (64) | app data,
+/// where app data is a flags byte (chat node, has name) and the name.
+fn advert_payload(name: Option<&str>, seed: Option<&str>, timestamp: u32) -> Result<Vec<u8>> {
+    const ADV_TYPE_CHAT: u8 = 0x01;
+    const ADV_HAS_NAME: u8 = 0x80;
+
+    let seed: [u8; 32] = match seed {
+        Some(seed) => hex::decode(seed.trim())
+            .ok()
+            .and_then(|s| s.try_into().ok())
+            .context(CliError::InvalidArgs(
+                "--key must be a 32-byte Ed25519 seed in hex".into(),
+            ))?,
+        None => {
+            let mut seed = [0u8; 32];
+            getrandom::getrandom(&mut seed)?;
+            seed
+        }
+    };
+    let key = SigningKey::from_bytes(&seed);
+    let public_key = key.verifying_key().to_bytes();
+
+    let mut app_data = Vec::new();
+    match name {
+        Some(name) => {
+            app_data.push(ADV_TYPE_CHAT | ADV_HAS_NAME);
+            app_data.extend(name.as_bytes());
+        }
+        None => app_data.push(ADV_TYPE_CHAT),
+    }
+
+    let signed = [&public_key[..], &timestamp.to_le_bytes(), &app_data].concat();
+    let signature = key.sign(&signed).to_bytes();
+    Ok([
+        &public_key[..],
+        &timestamp.to_le_bytes(),
+        &signature,
+        &app_data,
+    ]
+    .concat())
+}
+
+/// Hash byte that group packets carry to name the channel with PSK `key`.
+pub fn channel_hash(key: &[u8]) -> u8 {
+    Sha256::digest(key)[0]
+}
+
+/// Shared secret of a channel: its 16 or 32-byte PSK, zero-padded.
+fn channel_secret(key: &[u8]) -> [u8; 32] {
+    let mut secret = [0u8; 32];
+    secret[..key.len()].copy_from_slice(key);
+    secret
+}
+
+fn channel_mac(secret: &[u8; 32], ciphertext: &[u8]) -> [u8; 32] {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(secret).expect("HMAC takes any key length");
+    mac.update(ciphertext);
+    mac.finalize().into_bytes().into()
+}
+
+/// Channel message: channel hash (1) | MAC (2) | ciphertext, where the
+/// plaintext is timestamp (4) | flags (1) | text, AES-128-ECB encrypted and
+/// zero-padded, and the MAC is the first bytes of HMAC-SHA256 over it.
+fn group_text_payload(psk: &str, text: &str, timestamp: u32) -> Result<Vec<u8>> {
+    let key = crate::psk::decode(psk)?;
+    let secret = channel_secret(&key);
+
+    let mut plaintext = [&timestamp.to_le_bytes()[..], &[0], text.as_bytes()].concat();
+    plaintext.resize(plaintext.len().div_ceil(16) * 16, 0);
+    let cipher = Aes128::new(GenericArray::from_slice(&secret[..16]));
+    let mut ciphertext = plaintext;
+    for block in ciphertext.chunks_exact_mut(16) {
+        cipher.encrypt_block(GenericArray::from_mut_slice(block));
+    }
+
+    let mac = channel_mac(&secret, &ciphertext);
+    Ok([&[channel_hash(&key)], &mac[..2], &ciphertext].concat())
+}
+
+/// Timestamp and text of a channel message payload, if it was encrypted
+/// with PSK `key` (the MAC matches).
+pub fn decrypt_group_text(key: &[u8], payload: &[u8]) -> Option<(u32, String)> {
+    let secret = channel_secret(key);
+    let (mac, ciphertext) = payload.get(1..)?.split_at_checked(2)?;
+    if ciphertext.is_empty() || ciphertext.len() % 16 != 0 {
+        return None;
+    }
+    if channel_mac(&secret, ciphertext)[..2] != *mac {
+        return None;
+    }
+
+    let cipher = Aes128::new(GenericArray::from_slice(&secret[..16]));
+    let mut plaintext = ciphertext.to_vec();
+    for block in plaintext.chunks_exact_mut(16) {
+        cipher.decrypt_block(GenericArray::from_mut_slice(block));
+    }
+
+    let (timestamp, rest) = plaintext.split_first_chunk::<4>()?;
+    let text = rest.get(1..)?.split(|&b| b == 0).next().unwrap_or_default();
+    Some((
+        u32::from_le_bytes(*timestamp),
+        String::from_utf8_lossy(text).into_owned(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_and_parse() {
+        let fields = Fields {
+            text: Some("alice"),
+            key: Some(&"01".repeat(32)),
+            ..Fields::default()
+        };
+        let advert = build(RouteType::Flood, PayloadType::Advert, vec![], &fields).unwrap();
+        let bytes = advert.encode().unwrap();
+        // Flood (1) | advert (4 << 2), no path, key + timestamp + signature + flags + name
+        assert_eq!(&bytes[..2], &[0x11, 0x00]);
+        assert_eq!(bytes.len(), 2 + 32 + 4 + 64 + 1 + 5);
+        assert_eq!(Packet::parse(&bytes).unwrap(), advert);
+
+        let fields = Fields {
+            text: Some("hi"),
+            ..Fields::default()
+        };
+        let msg = build(
+            RouteType::TransportDirect,
+            PayloadType::GrpTxt,
+            vec![0xa1, 0xb2],
+            &fields,
+        )
+        .unwrap();
+        let bytes = msg.encode().unwrap();
+        assert_eq!(bytes[5..8], [2, 0xa1, 0xb2]);
+        // Public channel hash, MAC, one AES block
+        assert_eq!(msg.payload[0], 0x11);
+        assert_eq!(msg.payload.len(), 1 + 2 + 16);
+        let public = crate::psk::decode(PUBLIC_CHANNEL_PSK).unwrap();
+        assert_eq!(
+            decrypt_group_text(&public, &msg.payload).map(|(_, text)| text),
+            Some("hi".to_string())
+        );
+        assert_eq!(decrypt_group_text(&[7; 16], &msg.payload), None);
+        assert_eq!(
+            Packet::parse(&bytes).unwrap().summary(),
+            "transport-direct grp-txt, 2 hops (a1,b2), transport codes 0000,0000, 19-byte payload"
+        );
+
+        assert!(build(RouteType::Flood, PayloadType::Txt, vec![], &fields).is_err());
+        assert!(Packet::parse(&[0x11, 3, 0xa1]).is_err());
+    }
+}
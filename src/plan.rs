@@ -0,0 +1,598 @@
+//! Repeater placement for `meshgrid-cli plan`.
+//!
+//! The path loss between two sites is free-space loss plus knife-edge
+//! diffraction over the worst obstruction of the first Fresnel zone, on an
+//! earth with 4/3 of its radius (standard refraction), or plane-earth loss
+//! where that is higher, as it is between low antennas. Terrain comes from
+//! SRTM `.hgt` tiles when given; otherwise the ground between two sites is
+//! taken as level. Repeaters are then picked greedily: a connected set of
+//! sites that every other site reaches directly.
+
+use crate::cli::RadioPreset;
+use crate::geo;
+use crate::protocol::DeviceConfig;
+use crate::sim::{demodulation_limit_db, noise_floor_dbm};
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+
+/// Earth radius with standard atmospheric refraction (k = 4/3).
+const EFFECTIVE_EARTH_RADIUS_M: f64 = 6_371_000.0 * 4.0 / 3.0;
+
+/// Antenna height above ground when the sites file has none.
+const DEFAULT_HEIGHT_M: f64 = 2.0;
+
+/// Radio settings assumed for a preset.
+pub fn preset(preset: RadioPreset) -> DeviceConfig {
+    let (freq_mhz, tx_power_dbm) = match preset {
+        RadioPreset::Eu => (869.525, 20),
+        RadioPreset::Us => (910.525, 22),
+    };
+    DeviceConfig {
+        name: None,
+        freq_mhz,
+        tx_power_dbm,
+        bandwidth_khz: 250,
+        spreading_factor: 11,
+        coding_rate: 5,
+        preamble_len: 8,
+    }
+}
+
+/// What a site may be in the plan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Already a repeater, kept as one
+    Repeater,
+    /// Could host a repeater
+    Candidate,
+    /// Can't host one (a handheld, a node without power)
+    Client,
+}
+
+/// A site from the sites file.
+#[derive(Debug, Clone, Serialize)]
+pub struct Site {
+    pub name: String,
+    pub lat: f64,
+    pub lon: f64,
+    /// Antenna height above ground, in meters
+    pub height: f64,
+    /// Ground elevation in meters, from the file or the terrain
+    pub elevation: Option<f64>,
+    pub role: Role,
+}
+
+/// Read sites from CSV with a header row naming the columns `name`, `lat`,
+/// `lon` and optionally `height`, `elevation` and `role`.
+pub fn parse_sites(csv: &str) -> Result<Vec<Site>> {
+    let mut lines = csv
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'));
+    let Some((_, header)) = lines.next() else {
+        bail!("No sites");
+    };
+    let header: Vec<String> = fields(header)
+        .into_iter()
+        .map(|f| f.to_lowercase())
+        .collect();
+    let column = |names: &[&str]| header.iter().position(|h| names.contains(&h.as_str()));
+    let (Some(name_col), Some(lat_col), Some(lon_col)) = (
+        column(&["name"]),
+        column(&["lat", "latitude"]),
+        column(&["lon", "lng", "longitude"]),
+    ) else {
+        bail!("The header row needs name, lat and lon columns");
+    };
+    let height_col = column(&["height", "antenna_height"]);
+    let elevation_col = column(&["elevation", "ground"]);
+    let role_col = column(&["role"]);
+
+    let mut sites: Vec<Site> = Vec::new();
+    for (index, line) in lines {
+        let row = fields(line);
+        let line_no = index + 1;
+        let get = |col: Option<usize>| {
+            col.and_then(|c| row.get(c))
+                .map(String::as_str)
+                .filter(|v| !v.is_empty())
+        };
+        let number = |col: Option<usize>, what: &str| -> Result<Option<f64>> {
+            get(col)
+                .map(|v| {
+                    v.parse::<f64>()
+                        .with_context(|| format!("Line {line_no}: invalid {what} '{v}'"))
+                })
+                .transpose()
+        };
+
+        let Some(name) = get(Some(name_col)) else {
+            bail!("Line {line_no}: missing name");
+        };
+        if sites.iter().any(|s| s.name.eq_ignore_ascii_case(name)) {
+            bail!("Line {line_no}: duplicate site '{name}'");
+        }
+        let lat = number(Some(lat_col), "latitude")?;
+        let lon = number(Some(lon_col), "longitude")?;
+        let Some((lat, lon)) = lat.zip(lon).and_then(|(lat, lon)| geo::valid(lat, lon)) else {
+            bail!("Line {line_no}: '{name}' needs a valid lat and lon");
+        };
+        let role = match get(role_col).map(str::to_lowercase).as_deref() {
+            None | Some("candidate") => Role::Candidate,
+            Some("repeater") => Role::Repeater,
+            Some("client") => Role::Client,
+            Some(other) => {
+                bail!("Line {line_no}: unknown role '{other}' (use repeater, candidate or client)")
+            }
+        };
+        sites.push(Site {
+            name: name.to_string(),
+            lat,
+            lon,
+            height: number(height_col, "height")?.unwrap_or(DEFAULT_HEIGHT_M),
+            elevation: number(elevation_col, "elevation")?,
+            role,
+        });
+    }
+    Ok(sites)
+}
+
+/// Split a CSV line, honoring double quotes.
+fn fields(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        let field = fields.last_mut().expect("never empty");
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            c => field.push(c),
+        }
+    }
+    fields.into_iter().map(|f| f.trim().to_string()).collect()
+}
+
+/// One SRTM tile: a square grid of heights, north row first.
+struct Tile {
+    size: usize,
+    heights: Vec<i16>,
+}
+
+/// Elevations from a directory of SRTM `.hgt` tiles (`N52E005.hgt`, 1 or 3
+/// arc-second), loaded as they're needed.
+pub struct Terrain {
+    dir: PathBuf,
+    tiles: HashMap<(i32, i32), Option<Tile>>,
+}
+
+impl Terrain {
+    pub fn open(dir: &Path) -> Result<Self> {
+        if !dir.is_dir() {
+            bail!("Elevation directory {} not found", dir.display());
+        }
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            tiles: HashMap::new(),
+        })
+    }
+
+    /// Tiles found so far.
+    pub fn loaded(&self) -> usize {
+        self.tiles.values().filter(|t| t.is_some()).count()
+    }
+
+    /// Ground elevation in meters, None without a tile or in a void.
+    pub fn elevation(&mut self, lat: f64, lon: f64) -> Option<f64> {
+        let key = (lat.floor() as i32, lon.floor() as i32);
+        let dir = &self.dir;
+        let tile = self
+            .tiles
+            .entry(key)
+            .or_insert_with(|| load_tile(dir, key))
+            .as_ref()?;
+        let last = (tile.size - 1) as f64;
+        let row = ((f64::from(key.0) + 1.0 - lat) * last).round() as usize;
+        let col = ((lon - f64::from(key.1)) * last).round() as usize;
+        let height = *tile.heights.get(row.min(tile.size - 1) * tile.size + col)?;
+        (height != i16::MIN).then_some(f64::from(height))
+    }
+}
+
+fn load_tile(dir: &Path, (lat, lon): (i32, i32)) -> Option<Tile> {
+    let name = format!(
+        "{}{:02}{}{:03}.hgt",
+        if lat < 0 { 'S' } else { 'N' },
+        lat.unsigned_abs(),
+        if lon < 0 { 'W' } else { 'E' },
+        lon.unsigned_abs()
+    );
+    let path = dir.join(&name);
+    let bytes = match std::fs::read(&path) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            tracing::debug!("No elevation tile {}: {e}", path.display());
+            return None;
+        }
+    };
+    let size = ((bytes.len() / 2) as f64).sqrt() as usize;
+    if size < 2 || size * size * 2 != bytes.len() {
+        tracing::warn!("Skipping {}: not an SRTM tile", path.display());
+        return None;
+    }
+    let heights = bytes
+        .chunks_exact(2)
+        .map(|b| i16::from_be_bytes([b[0], b[1]]))
+        .collect();
+    Some(Tile { size, heights })
+}
+
+/// Fill in the ground elevation of sites the file has none for.
+pub fn fill_elevations(sites: &mut [Site], terrain: &mut Terrain) {
+    for site in sites.iter_mut().filter(|s| s.elevation.is_none()) {
+        site.elevation = terrain.elevation(site.lat, site.lon);
+    }
+}
+
+/// Radio settings shared by every site.
+#[derive(Debug, Clone, Serialize)]
+pub struct Radio {
+    pub config: DeviceConfig,
+    /// Antenna gain at each end, in dBi
+    pub gain_dbi: f64,
+}
+
+impl Radio {
+    /// Most path loss a link can have and still be received.
+    fn max_loss_db(&self) -> f64 {
+        let sensitivity = noise_floor_dbm(self.config.bandwidth_khz)
+            + demodulation_limit_db(self.config.spreading_factor);
+        f64::from(self.config.tx_power_dbm) + 2.0 * self.gain_dbi - sensitivity
+    }
+}
+
+/// Link budget between two sites.
+#[derive(Debug, Clone, Serialize)]
+pub struct Link {
+    #[serde(skip)]
+    pub a: usize,
+    #[serde(skip)]
+    pub b: usize,
+    pub distance_m: f64,
+    /// Path loss
+    pub loss_db: f64,
+    /// Signal above the receiver's limit; negative when out of range
+    pub margin_db: f64,
+    /// Clearance of the first Fresnel zone at the worst point (1 = clear,
+    /// 0 = grazing, negative = line of sight blocked); None when the link
+    /// is out of range even in free space
+    pub clearance: Option<f64>,
+}
+
+/// The link budget between sites `a` and `b`.
+pub fn link(
+    sites: &[Site],
+    a: usize,
+    b: usize,
+    radio: &Radio,
+    terrain: Option<&mut Terrain>,
+) -> Link {
+    let (from, to) = (&sites[a], &sites[b]);
+    let distance = geo::distance_m((from.lat, from.lon), (to.lat, to.lon)).max(1.0);
+    let wavelength = 299_792_458.0 / (f64::from(radio.config.freq_mhz) * 1e6);
+    let free_space = 20.0 * (4.0 * std::f64::consts::PI * distance / wavelength).log10();
+    let mut link = Link {
+        a,
+        b,
+        distance_m: distance,
+        loss_db: free_space,
+        margin_db: radio.max_loss_db() - free_space,
+        clearance: None,
+    };
+    // Free space is the least it can lose
+    if link.margin_db < 0.0 {
+        return link;
+    }
+
+    let ground_a = from.elevation.unwrap_or(0.0);
+    let ground_b = to.elevation.unwrap_or(0.0);
+    let (antenna_a, antenna_b) = (ground_a + from.height, ground_b + to.height);
+    let samples = (distance / 90.0).clamp(16.0, 1000.0) as usize;
+    let mut terrain = terrain;
+    let mut clearance = f64::INFINITY;
+    for i in 1..samples {
+        let t = i as f64 / samples as f64;
+        let (d1, d2) = (t * distance, (1.0 - t) * distance);
+        let ground = terrain
+            .as_deref_mut()
+            .and_then(|terrain| {
+                terrain.elevation(
+                    from.lat + t * (to.lat - from.lat),
+                    from.lon + t * (to.lon - from.lon),
+                )
+            })
+            .unwrap_or(ground_a + t * (ground_b - ground_a));
+        let bulge = d1 * d2 / (2.0 * EFFECTIVE_EARTH_RADIUS_M);
+        let sight_line = antenna_a + t * (antenna_b - antenna_a);
+        let fresnel = (wavelength * d1 * d2 / distance).sqrt();
+        clearance = clearance.min((sight_line - ground - bulge) / fresnel);
+    }
+    // Ground reflection cancels the direct ray beyond a few hundred meters
+    let plane_earth =
+        40.0 * distance.log10() - 20.0 * (from.height.max(1.0) * to.height.max(1.0)).log10();
+    link.loss_db =
+        (free_space + knife_edge_loss_db(-std::f64::consts::SQRT_2 * clearance)).max(plane_earth);
+    link.margin_db = radio.max_loss_db() - link.loss_db;
+    link.clearance = Some(clearance);
+    link
+}
+
+/// Diffraction loss over a knife edge with Fresnel-Kirchhoff parameter `v`
+/// (ITU-R P.526 approximation).
+fn knife_edge_loss_db(v: f64) -> f64 {
+    if v <= -0.78 {
+        return 0.0;
+    }
+    6.9 + 20.0 * (((v - 0.1).powi(2) + 1.0).sqrt() + v - 0.1).log10()
+}
+
+/// Where repeaters are needed.
+#[derive(Debug, Default)]
+pub struct Plan {
+    /// Sites to run as repeaters, existing ones included
+    pub repeaters: Vec<usize>,
+    /// Sites in a group that no repeater reaches, because every path to
+    /// them goes through client sites
+    pub unreached: Vec<usize>,
+    /// Sites with usable links between them, largest group first; more than
+    /// one means the network is split
+    pub groups: Vec<Vec<usize>>,
+}
+
+/// Pick repeaters among `sites`, using links with at least `margin_db`.
+pub fn plan(sites: &[Site], links: &[Link], margin_db: f64) -> Plan {
+    let mut adjacent = vec![BTreeSet::new(); sites.len()];
+    for link in links.iter().filter(|l| l.margin_db >= margin_db) {
+        adjacent[link.a].insert(link.b);
+        adjacent[link.b].insert(link.a);
+    }
+
+    let mut plan = Plan::default();
+    let mut grouped = vec![false; sites.len()];
+    for start in 0..sites.len() {
+        if grouped[start] {
+            continue;
+        }
+        let group = reachable(&adjacent, start, |_| true);
+        for &site in &group {
+            grouped[site] = true;
+        }
+        let (repeaters, unreached) = choose(sites, &adjacent, &group);
+        plan.repeaters.extend(repeaters);
+        plan.unreached.extend(unreached);
+        plan.groups.push(group);
+    }
+    plan.groups.sort_by_key(|g| std::cmp::Reverse(g.len()));
+    plan.repeaters.sort_unstable();
+    plan.unreached.sort_unstable();
+    plan
+}
+
+/// Sites reachable from `start` through sites `allowed` admits, sorted.
+fn reachable(
+    adjacent: &[BTreeSet<usize>],
+    start: usize,
+    allowed: impl Fn(usize) -> bool,
+) -> Vec<usize> {
+    let mut seen = BTreeSet::from([start]);
+    let mut queue = VecDeque::from([start]);
+    while let Some(site) = queue.pop_front() {
+        for &next in &adjacent[site] {
+            if allowed(next) && seen.insert(next) {
+                queue.push_back(next);
+            }
+        }
+    }
+    seen.into_iter().collect()
+}
+
+/// Repeaters for one group, and the sites they leave unreached.
+fn choose(
+    sites: &[Site],
+    adjacent: &[BTreeSet<usize>],
+    group: &[usize],
+) -> (BTreeSet<usize>, Vec<usize>) {
+    let eligible = |site: usize| sites[site].role != Role::Client;
+    let mut chosen: BTreeSet<usize> = group
+        .iter()
+        .copied()
+        .filter(|&s| sites[s].role == Role::Repeater)
+        .collect();
+    // Every site hears every other: no repeater needed
+    if group.iter().all(|&s| adjacent[s].len() + 1 == group.len()) {
+        return (chosen, Vec::new());
+    }
+    if chosen.is_empty() {
+        match group
+            .iter()
+            .copied()
+            .filter(|&s| eligible(s))
+            .max_by_key(|&s| (adjacent[s].len(), std::cmp::Reverse(s)))
+        {
+            Some(site) => chosen.insert(site),
+            None => return (chosen, group.to_vec()),
+        };
+    }
+
+    loop {
+        let covered: BTreeSet<usize> = chosen
+            .iter()
+            .flat_map(|&s| adjacent[s].iter().copied().chain([s]))
+            .collect();
+        let piece = pieces(adjacent, &chosen);
+        let piece_count = piece.values().copied().max().map_or(0, |p| p + 1);
+        if covered.len() == group.len() && piece_count <= 1 {
+            break;
+        }
+
+        // The covered site that reaches most new sites and joins most pieces
+        let best = covered
+            .iter()
+            .copied()
+            .filter(|&s| eligible(s) && !chosen.contains(&s))
+            .map(|s| {
+                let new = adjacent[s].difference(&covered).count();
+                let joined: BTreeSet<usize> = adjacent[s]
+                    .iter()
+                    .filter_map(|n| piece.get(n).copied())
+                    .collect();
+                (new + joined.len().saturating_sub(1), adjacent[s].len(), s)
+            })
+            .max_by_key(|&(gain, links, s)| (gain, links, std::cmp::Reverse(s)));
+        match best {
+            Some((gain, _, site)) if gain > 0 => {
+                chosen.insert(site);
+            }
+            _ => match bridge(adjacent, &chosen, &piece, eligible) {
+                Some(path) => chosen.extend(path),
+                None => break,
+            },
+        }
+    }
+
+    let covered: BTreeSet<usize> = chosen
+        .iter()
+        .flat_map(|&s| adjacent[s].iter().copied().chain([s]))
+        .collect();
+    let unreached = group
+        .iter()
+        .copied()
+        .filter(|s| !covered.contains(s))
+        .collect();
+    (chosen, unreached)
+}
+
+/// Connected pieces of the chosen repeaters, as a piece number per site.
+fn pieces(adjacent: &[BTreeSet<usize>], chosen: &BTreeSet<usize>) -> HashMap<usize, usize> {
+    let mut piece = HashMap::new();
+    let mut count = 0;
+    for &site in chosen {
+        if piece.contains_key(&site) {
+            continue;
+        }
+        for member in reachable(adjacent, site, |s| chosen.contains(&s)) {
+            piece.insert(member, count);
+        }
+        count += 1;
+    }
+    piece
+}
+
+/// Sites that would join the first piece of repeaters to another: the
+/// shortest path between them through eligible sites, ends excluded.
+fn bridge(
+    adjacent: &[BTreeSet<usize>],
+    chosen: &BTreeSet<usize>,
+    piece: &HashMap<usize, usize>,
+    eligible: impl Fn(usize) -> bool,
+) -> Option<Vec<usize>> {
+    let mut previous: HashMap<usize, usize> = HashMap::new();
+    let mut queue: VecDeque<usize> = chosen.iter().copied().filter(|s| piece[s] == 0).collect();
+    let mut seen: BTreeSet<usize> = queue.iter().copied().collect();
+    while let Some(site) = queue.pop_front() {
+        for &next in &adjacent[site] {
+            if piece.get(&next).is_some_and(|&p| p != 0) {
+                let mut path = Vec::new();
+                let mut at = site;
+                while !chosen.contains(&at) {
+                    path.push(at);
+                    at = previous[&at];
+                }
+                return Some(path);
+            }
+            if eligible(next) && !chosen.contains(&next) && seen.insert(next) {
+                previous.insert(next, site);
+                queue.push_back(next);
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sites() {
+        let csv = "Name,Lat,Lon,Height,Role\n\
+                   # hills first\n\
+                   \"Hill, north\",52.10,5.12,10,repeater\n\
+                   Farm,52.05,5.20,,client\n";
+        let sites = parse_sites(csv).unwrap();
+        assert_eq!(sites[0].name, "Hill, north");
+        assert_eq!((sites[0].height, sites[0].role), (10.0, Role::Repeater));
+        assert_eq!(
+            (sites[1].height, sites[1].role),
+            (DEFAULT_HEIGHT_M, Role::Client)
+        );
+
+        let err = parse_sites("name,lat,lon\nFarm,52.0,abc\n").unwrap_err();
+        assert_eq!(err.to_string(), "Line 2: invalid longitude 'abc'");
+        assert!(parse_sites("name,lat\nFarm,52.0\n").is_err());
+    }
+
+    #[test]
+    fn test_plan_line() {
+        // Five sites 4 km apart on level ground, antennas at 2 m: each
+        // reaches only its neighbors
+        let sites: Vec<Site> = (0..5)
+            .map(|i| Site {
+                name: format!("site{i}"),
+                lat: 52.0,
+                lon: 5.0 + f64::from(i) * 4000.0 / 68_600.0,
+                height: 2.0,
+                elevation: None,
+                role: if i == 4 {
+                    Role::Client
+                } else {
+                    Role::Candidate
+                },
+            })
+            .collect();
+        let radio = Radio {
+            config: preset(RadioPreset::Eu),
+            gain_dbi: 0.0,
+        };
+        let mut links = Vec::new();
+        for a in 0..sites.len() {
+            for b in a + 1..sites.len() {
+                links.push(link(&sites, a, b, &radio, None));
+            }
+        }
+        // Clear of the earth's bulge, but near the ground
+        let next = &links[0];
+        assert!(next.clearance.unwrap() > 0.0, "{next:?}");
+        assert!(next.loss_db > 120.0, "{next:?}");
+        assert!(links
+            .iter()
+            .all(|l| (l.b - l.a == 1) == (l.margin_db >= 10.0)));
+
+        let plan = plan(&sites, &links, 10.0);
+        assert_eq!(plan.repeaters, [1, 2, 3]);
+        assert!(plan.unreached.is_empty());
+        assert_eq!(plan.groups.len(), 1);
+
+        // A stricter margin splits every site off
+        let plan = super::plan(&sites, &links, 30.0);
+        assert_eq!(plan.groups.len(), 5);
+        assert!(plan.repeaters.is_empty());
+    }
+}
@@ -0,0 +1,194 @@
+//! Port locks shared by meshgrid-cli processes.
+//!
+//! Opening a serial port takes an advisory lock on a file named after the
+//! port, which records the process holding it and its command. A second
+//! invocation then reports "in use by PID 4242 (monitor)" instead of a
+//! serial open error, along with the proxy address to connect through when
+//! the holder shares the device (`run`, plugins).
+
+use crate::error::CliError;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, Write};
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+
+static COMMAND: OnceLock<String> = OnceLock::new();
+
+/// Lock files held by this process, for [`set_proxy`].
+static HELD: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Proxy this process serves its device on.
+static PROXY: Mutex<Option<String>> = Mutex::new(None);
+
+/// Name this process's command in its locks (`monitor`, `log follow`).
+pub fn set_command(command: &str) {
+    let _ = COMMAND.set(command.to_string());
+}
+
+/// The process holding a port.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Holder {
+    pub pid: u32,
+    pub command: String,
+    /// Address of the holder's device proxy
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+}
+
+/// A port locked by another meshgrid-cli process.
+#[derive(Debug, thiserror::Error)]
+#[error("{port} is in use by PID {} ({})", holder.pid, holder.command)]
+pub struct PortInUse {
+    pub port: String,
+    pub holder: Holder,
+}
+
+/// Lock on a port, released when dropped.
+#[derive(Debug)]
+pub struct PortLock {
+    file: File,
+    path: PathBuf,
+}
+
+impl PortLock {
+    /// Lock `port` for this process. None when locks can't be taken here
+    /// (no writable runtime directory, no lock support): opening the port
+    /// goes ahead unlocked.
+    pub fn acquire(port: &str) -> Result<Option<Self>> {
+        let path = lock_path(port);
+        let opened = path
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|()| {
+                OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .truncate(false)
+                    .open(&path)
+            });
+        let mut file = match opened {
+            Ok(file) => file,
+            Err(e) => {
+                tracing::debug!("No port lock at {}: {e}", path.display());
+                return Ok(None);
+            }
+        };
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let mut content = String::new();
+                let _ = file.read_to_string(&mut content);
+                let holder = serde_json::from_str(&content).unwrap_or(Holder {
+                    pid: 0,
+                    command: "unknown command".into(),
+                    proxy: None,
+                });
+                return Err(anyhow::Error::new(PortInUse {
+                    port: port.to_string(),
+                    holder,
+                })
+                .context(CliError::ConnectionFailed(format!(
+                    "Failed to open serial port {port}"
+                ))));
+            }
+            Err(TryLockError::Error(e)) => {
+                tracing::debug!("No port lock at {}: {e}", path.display());
+                return Ok(None);
+            }
+        }
+        let proxy = PROXY.lock().unwrap_or_else(|e| e.into_inner()).clone();
+        write_holder(&mut file, proxy.as_deref());
+        HELD.lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(path.clone());
+        Ok(Some(Self { file, path }))
+    }
+}
+
+impl Drop for PortLock {
+    fn drop(&mut self) {
+        HELD.lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|p| *p != self.path);
+        // The file stays: removing it would let the next process lock a
+        // file that a waiting one can no longer see
+        let _ = self.file.set_len(0);
+    }
+}
+
+/// Advertise (or withdraw) the proxy sharing this process's device in the
+/// locks it holds.
+pub fn set_proxy(address: Option<&str>) {
+    *PROXY.lock().unwrap_or_else(|e| e.into_inner()) = address.map(String::from);
+    for path in HELD.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+        if let Ok(mut file) = OpenOptions::new().write(true).open(path) {
+            write_holder(&mut file, address);
+        }
+    }
+}
+
+fn write_holder(file: &mut File, proxy: Option<&str>) {
+    let holder = Holder {
+        pid: std::process::id(),
+        command: COMMAND
+            .get()
+            .cloned()
+            .unwrap_or_else(|| "meshgrid-cli".into()),
+        proxy: proxy.map(String::from),
+    };
+    let written = file
+        .set_len(0)
+        .and_then(|()| file.rewind())
+        .and_then(|()| file.write_all(&serde_json::to_vec(&holder).unwrap_or_default()));
+    if let Err(e) = written {
+        tracing::debug!("Failed to record port lock holder: {e}");
+    }
+}
+
+/// Lock file for `port`, the same for every name of the device
+/// (`/dev/serial/by-id/...` and `/dev/ttyUSB0`).
+fn lock_path(port: &str) -> PathBuf {
+    let device = std::fs::canonicalize(port)
+        .map_or_else(|_| port.to_string(), |path| path.display().to_string());
+    let name: String = device
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    dirs::runtime_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("meshgrid-cli")
+        .join(format!("{}.lock", name.trim_start_matches('_')))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_port_lock() {
+        let port = format!("test-port-{}", std::process::id());
+        let Some(lock) = PortLock::acquire(&port).unwrap() else {
+            return; // No lock support here
+        };
+        set_proxy(Some("unix:/tmp/test.sock"));
+
+        // A second lock on the port fails, naming this process
+        let err = PortLock::acquire(&port).unwrap_err();
+        let in_use = err.downcast_ref::<PortInUse>().unwrap();
+        assert_eq!(in_use.holder.pid, std::process::id());
+        assert_eq!(in_use.holder.proxy.as_deref(), Some("unix:/tmp/test.sock"));
+        assert!(matches!(
+            err.downcast_ref::<CliError>(),
+            Some(CliError::ConnectionFailed(_))
+        ));
+
+        set_proxy(None);
+        let path = lock.path.clone();
+        drop(lock);
+        assert!(PortLock::acquire(&port).unwrap().is_some());
+        let _ = std::fs::remove_file(path);
+    }
+}
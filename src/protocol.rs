@@ -17,6 +17,14 @@
 //! {"json": "response"}\n
 //! ```
 //!
+//! Responses too large for one frame are split into continuation frames,
+//! numbered from 0, and reassembled before parsing:
+//! ```text
+//! MORE 0 [{"node_hash":1,...
+//! MORE 1 ...},{"node_hash":2,...
+//! END 2 ...}]
+//! ```
+//!
 //! ## Binary Packet Format
 //!
 //! For raw packet send/receive, binary format is used:
@@ -25,14 +33,19 @@
 //! <binary data>
 //! ```
 
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
 
+use crate::airtime::{self, Budget};
+use crate::error::CliError;
+use crate::fragment;
+use crate::framing::{continuation_frame, Continuation};
 use crate::serial::SerialPort;
+use crate::settings::Settings;
 
 /// Device telemetry data.
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct DeviceTelemetry {
     pub battery_percent: u8,
     pub voltage_mv: u16,
@@ -56,7 +69,7 @@ impl DeviceTelemetry {
 }
 
 /// Environment telemetry data.
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct EnvironmentTelemetry {
     temp_deci_c: i16,
     humidity_deci_pct: u16,
@@ -96,7 +109,7 @@ impl EnvironmentTelemetry {
 }
 
 /// Location telemetry data.
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct LocationTelemetry {
     lat_micro: i32,
     lon_micro: i32,
@@ -158,7 +171,7 @@ impl LocationTelemetry {
 }
 
 /// Combined telemetry.
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct Telemetry {
     pub device: Option<DeviceTelemetry>,
     pub environment: Option<EnvironmentTelemetry>,
@@ -183,9 +196,29 @@ impl Telemetry {
     }
 }
 
-/// Command timeout.
+/// Command timeout: the longest the device may stay silent while answering.
 const CMD_TIMEOUT: Duration = Duration::from_secs(5);
 
+/// Serial output that only appears when the device (re)boots or crashes.
+pub const BANNERS: [&str; 10] = [
+    "rst:0x",
+    "boot:0x",
+    "ets Jun",
+    "Guru Meditation",
+    "Backtrace:",
+    "abort() was called",
+    "Brownout detector",
+    "CORRUPT HEAP",
+    "assert failed",
+    "HardFault",
+];
+
+/// The device kept printing boot and crash banners instead of answering,
+/// because it reboots over and over (see `meshgrid-cli recover`).
+#[derive(Debug, thiserror::Error)]
+#[error("Device keeps rebooting - it may be in a crash loop")]
+pub struct CrashLoop;
+
 /// Response from device.
 #[derive(Debug, Clone)]
 pub enum Response {
@@ -221,6 +254,17 @@ pub struct DeviceConfig {
     pub preamble_len: u16,
 }
 
+/// Channel entry from `CHANNELS`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChannelInfo {
+    pub name: String,
+    #[serde(default)]
+    pub builtin: bool,
+    /// Where `send` goes without a destination (see `channels default`)
+    #[serde(default)]
+    pub default: bool,
+}
+
 /// Neighbor entry.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NeighborInfo {
@@ -232,6 +276,14 @@ pub struct NeighborInfo {
     pub snr: i8,
     pub last_seen_secs: u32,
     pub firmware: Option<String>,
+    /// Position from the node's advert, in decimal degrees
+    #[serde(default)]
+    pub lat: Option<f64>,
+    #[serde(default)]
+    pub lon: Option<f64>,
+    /// Altitude in meters, when the advert carries one
+    #[serde(default)]
+    pub alt: Option<f64>,
 }
 
 /// Trace result.
@@ -242,19 +294,305 @@ pub struct TraceResult {
     pub rtt_ms: u32,
 }
 
+/// Learned path to a node, from the device's route cache.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Route {
+    pub node_hash: u8,
+    pub name: Option<String>,
+    /// Hashes of the repeaters to go through, nearest first; empty when the
+    /// node is reached directly, `None` when packets to it are flooded
+    #[serde(default)]
+    pub path: Option<Vec<u8>>,
+    /// Seconds since the path was learned
+    #[serde(default)]
+    pub age_secs: Option<u64>,
+}
+
+impl Route {
+    /// Hops to the node, counting the last one; `None` when flooded.
+    pub fn hops(&self) -> Option<usize> {
+        self.path.as_ref().map(|path| path.len() + 1)
+    }
+}
+
+/// Reading from `NOISE`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NoiseFloor {
+    /// RSSI of the idle channel on the configured frequency, in dBm
+    #[serde(alias = "noise_floor")]
+    pub noise_floor_dbm: f32,
+}
+
+/// State of the device's GNSS receiver.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GpsStatus {
+    /// 0 = no fix, 2 = 2D, 3 = 3D
+    #[serde(default)]
+    pub fix_type: u8,
+    #[serde(default)]
+    pub satellites_in_view: u8,
+    #[serde(default)]
+    pub satellites_used: u8,
+    pub hdop: Option<f32>,
+    /// Seconds from power-on to the first fix, unset while searching
+    pub ttff_secs: Option<u32>,
+    /// Seconds since the receiver was powered on
+    pub on_secs: Option<u32>,
+    #[serde(default)]
+    pub constellations: Vec<ConstellationStatus>,
+}
+
+/// Satellites of one constellation (GPS, Galileo, GLONASS, BeiDou...).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConstellationStatus {
+    pub name: String,
+    #[serde(default)]
+    pub in_view: u8,
+    #[serde(default)]
+    pub used: u8,
+}
+
+/// Position reported by a remote node.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemotePosition {
+    pub lat: f64,
+    pub lon: f64,
+    pub alt: Option<f64>,
+    /// Seconds since the node's last fix
+    pub age_secs: Option<u64>,
+    /// Horizontal accuracy in meters
+    pub accuracy_m: Option<f64>,
+    pub satellites: Option<u8>,
+    /// Ground speed, for moving trackers
+    pub speed_m_s: Option<f64>,
+    /// Course over ground in degrees from north
+    pub heading_deg: Option<f64>,
+}
+
+/// Identity and status reported by a remote node.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteNodeInfo {
+    pub name: Option<String>,
+    pub mode: Option<String>,
+    pub firmware: Option<String>,
+    pub battery_percent: Option<u8>,
+    pub battery_mv: Option<u16>,
+    pub uptime_secs: Option<u64>,
+}
+
+/// Clock of a remote node.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemoteTime {
+    /// Node time, unix seconds
+    pub time: i64,
+    /// Node role (`client`, `repeater`, `room`), when reported
+    #[serde(default)]
+    pub mode: Option<String>,
+}
+
+/// Signal of a probe to a remote node and of its reply.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProbeResult {
+    /// How the reply was heard here
+    pub rssi: f32,
+    pub snr: f32,
+    /// How the node heard the probe, when it reports it
+    #[serde(default)]
+    pub remote_rssi: Option<f32>,
+    #[serde(default)]
+    pub remote_snr: Option<f32>,
+    #[serde(default)]
+    pub rtt_ms: Option<u64>,
+}
+
+/// One line of the device's stored log.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogLine {
+    /// Position in the device's log, counting every line since boot
+    pub seq: Option<u64>,
+    pub uptime_ms: Option<u64>,
+    /// Device clock (RTC) time in unix milliseconds, for firmware that
+    /// stamps lines with it
+    pub rtc_ms: Option<i64>,
+    pub level: Option<String>,
+    pub msg: String,
+}
+
+impl LogLine {
+    /// A stored log line or `debug` frame.
+    pub fn from_json(json: &serde_json::Value) -> Self {
+        let field = |name| json.get(name).and_then(serde_json::Value::as_i64);
+        Self {
+            seq: json.get("seq").and_then(serde_json::Value::as_u64),
+            uptime_ms: uptime_ms(json),
+            rtc_ms: field("time_ms").or_else(|| field("time").map(|s| s * 1000)),
+            level: json.get("level").and_then(|v| v.as_str()).map(String::from),
+            msg: json
+                .get("msg")
+                .and_then(|v| v.as_str())
+                .unwrap_or_default()
+                .to_string(),
+        }
+    }
+}
+
+/// Firmware log verbosity.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogLevels {
+    /// Level of subsystems without their own
+    pub level: String,
+    /// Subsystems set to a different level
+    #[serde(default)]
+    pub modules: std::collections::BTreeMap<String, String>,
+}
+
+/// The device's stored log, read page by page.
+#[derive(Debug, Default)]
+pub struct DeviceLog {
+    pub lines: Vec<LogLine>,
+    /// Lines overwritten in the device's ring buffer while it was being read
+    pub dropped: u64,
+    /// Device uptime when it sent the last page, if it said
+    pub uptime_ms: Option<u64>,
+    /// Sequence number to ask for next
+    next: u64,
+}
+
+impl DeviceLog {
+    /// Add a page of the log: `{"first": <oldest seq kept>, "more": bool,
+    /// "lines": [...]}`, optionally with the sender's `uptime_ms`. Returns
+    /// whether to ask for another page.
+    fn absorb(&mut self, page: &serde_json::Value) -> bool {
+        let first = page
+            .get("first")
+            .and_then(serde_json::Value::as_u64)
+            .unwrap_or(self.next);
+        if !self.lines.is_empty() && first > self.next {
+            self.dropped += first - self.next;
+        }
+        self.uptime_ms = uptime_ms(page).or(self.uptime_ms);
+        let before = self.next;
+        for json in page
+            .get("lines")
+            .and_then(|v| v.as_array())
+            .into_iter()
+            .flatten()
+        {
+            let line = LogLine::from_json(json);
+            match line.seq {
+                Some(seq) if seq < self.next => continue,
+                Some(seq) => self.next = seq + 1,
+                None => {}
+            }
+            self.lines.push(line);
+        }
+        let more = page.get("more").and_then(serde_json::Value::as_bool) == Some(true);
+        more && self.next > before
+    }
+}
+
+/// Device uptime in a JSON frame (`uptime_ms`, or `uptime`/`uptime_secs` in seconds).
+pub fn uptime_ms(json: &serde_json::Value) -> Option<u64> {
+    let field = |name| json.get(name).and_then(serde_json::Value::as_u64);
+    field("uptime_ms").or_else(|| {
+        field("uptime")
+            .or_else(|| field("uptime_secs"))
+            .map(|s| s * 1000)
+    })
+}
+
 /// `MeshCore` protocol handler.
 pub struct Protocol {
     port: SerialPort,
 }
 
+/// Estimated over-the-air size of the packet a command transmits, if any:
+/// the command's text plus routing header, hashes and MAC.
+fn transmission_len(cmd: &str) -> Option<usize> {
+    const PACKET_OVERHEAD: usize = 16;
+    /// Advert: public key, timestamp, signature and app data
+    const ADVERT_LEN: usize = 110;
+
+    let upper = cmd.to_ascii_uppercase();
+    if upper.starts_with("ADVERT") {
+        return Some(ADVERT_LEN);
+    }
+    let payload = [
+        "CHANNEL SEND ",
+        "SEND ",
+        "TRACE ",
+        "TELEMETRY ",
+        "POSITION REQUEST ",
+        "NODEINFO REQUEST ",
+        "LOG REQUEST ",
+        "TIME REQUEST ",
+        "TIME SET ",
+        "WAYPOINT SEND ",
+    ]
+    .iter()
+    .find_map(|verb| upper.starts_with(verb).then(|| cmd.len() - verb.len()))?;
+    Some(payload + PACKET_OVERHEAD)
+}
+
 impl Protocol {
     /// Create a new protocol handler.
     pub fn new(port: SerialPort) -> Self {
         Self { port }
     }
 
+    /// Take the serial port back (e.g., to speak raw frames after logging in).
+    pub fn into_port(self) -> SerialPort {
+        self.port
+    }
+
     /// Send a command and wait for response.
+    ///
+    /// Commands that transmit (SEND, ADVERT, ...) are held back while the
+    /// duty-cycle budget is exhausted.
     pub async fn command(&mut self, cmd: &str) -> Result<Response> {
+        if let Some(len) = transmission_len(cmd) {
+            self.wait_for_airtime(len).await?;
+        }
+        self.exchange(cmd).await
+    }
+
+    /// Wait until a `payload_len` byte packet fits the duty-cycle budget.
+    async fn wait_for_airtime(&mut self, payload_len: usize) -> Result<()> {
+        if !airtime::started() {
+            let budget = self.load_budget().await?;
+            airtime::start(budget);
+        }
+        let (wait, limit_percent) = airtime::reserve(payload_len)?;
+        if !wait.is_zero() {
+            eprintln!(
+                "Waiting {}s for airtime ({limit_percent}% duty cycle)...",
+                wait.as_secs()
+            );
+            tokio::time::sleep(wait).await;
+        }
+        Ok(())
+    }
+
+    async fn load_budget(&mut self) -> Result<Budget> {
+        let settings = Settings::load()?.airtime;
+        if settings.duty_cycle_percent == Some(0.0) {
+            return Ok(Budget::unlimited());
+        }
+        let config = match self.exchange("CONFIG").await? {
+            Response::Json(json) => serde_json::from_value::<DeviceConfig>(json).ok(),
+            _ => None,
+        };
+        Ok(match config {
+            Some(config) => Budget::new(config, &settings),
+            None => {
+                tracing::debug!("Radio settings unknown; not tracking airtime");
+                Budget::unlimited()
+            }
+        })
+    }
+
+    /// Send a command and wait for response, without airtime checks.
+    async fn exchange(&mut self, cmd: &str) -> Result<Response> {
         // Clear any pending data/responses
         self.port.clear().await?;
 
@@ -262,26 +600,46 @@ impl Protocol {
         self.port.write_cobs_frame(cmd.as_bytes()).await?;
 
         // Wait for response
-        self.read_response().await
+        let ping = cmd.split_whitespace().next() == Some("PING");
+        self.read_response(ping).await
     }
 
-    /// Read a response from the device.
-    async fn read_response(&mut self) -> Result<Response> {
+    /// Read a response from the device; a PONG only counts as one for `ping`,
+    /// otherwise it's a late answer to an earlier PING.
+    async fn read_response(&mut self, ping: bool) -> Result<Response> {
         // Loop to skip debug frames and wait for command response
         // Limit iterations to prevent infinite loops on stuck devices
         const MAX_SKIP_FRAMES: usize = 50;
+        /// About two crash-and-reboot cycles
+        const MAX_BANNERS: usize = 8;
         let mut skip_count = 0;
+        let mut banners = 0;
+        let mut partial = Continuation::default();
 
         loop {
+            if banners >= MAX_BANNERS {
+                bail!(CrashLoop);
+            }
             if skip_count >= MAX_SKIP_FRAMES {
-                bail!("Too many unrecognized frames - device may be in a crash loop");
+                bail!(CliError::Device(
+                    "Too many unrecognized frames while waiting for a response".into()
+                ));
             }
 
             // Read COBS frame
-            let Some(frame) = self.port.read_cobs_frame_timeout(CMD_TIMEOUT).await? else {
-                bail!("Command timeout");
+            let Some(mut frame) = self.port.read_cobs_frame_idle(CMD_TIMEOUT).await? else {
+                bail!(CliError::Timeout("Command timeout".into()));
             };
 
+            // Reassemble a response split over several frames
+            if let Some((seq, last, chunk)) = continuation_frame(&frame) {
+                partial.push(seq, chunk)?;
+                if !last {
+                    continue;
+                }
+                frame = std::mem::take(&mut partial).data;
+            }
+
             // Convert to string
             let line = String::from_utf8_lossy(&frame).to_string();
             tracing::debug!("Raw response: {:?}", line);
@@ -316,12 +674,20 @@ impl Protocol {
                 // Binary packet - treat as OK (actual packet reading done via recv_packet)
                 return Ok(Response::Ok(Some(line)));
             } else if line.starts_with("PONG") {
-                // PING response
-                return Ok(Response::Ok(Some(line)));
+                if ping {
+                    return Ok(Response::Ok(Some(line)));
+                }
+                tracing::debug!("Skipping stale PONG: {:?}", line);
+                skip_count += 1;
+                continue;
             }
             // Skip unrecognized frames
             tracing::debug!("Skipping unrecognized frame: {:?}", line);
-            skip_count += 1;
+            if BANNERS.iter().any(|b| line.contains(b)) {
+                banners += 1;
+            } else {
+                skip_count += 1;
+            }
         }
     }
 
@@ -332,11 +698,20 @@ impl Protocol {
                 let info: DeviceInfo = serde_json::from_value(json)?;
                 Ok(info)
             }
-            Response::Error(e) => bail!("Device error: {e}"),
+            Response::Error(e) => bail!(CliError::Device(e)),
             Response::Ok(_) => bail!("Unexpected OK response to INFO"),
         }
     }
 
+    /// Get the GNSS receiver's state.
+    pub async fn get_gps_status(&mut self) -> Result<GpsStatus> {
+        match self.command("GPS").await? {
+            Response::Json(json) => Ok(serde_json::from_value(json)?),
+            Response::Error(e) => bail!(CliError::Device(e)),
+            Response::Ok(_) => bail!("Unexpected OK response to GPS"),
+        }
+    }
+
     /// Get device configuration.
     pub async fn get_config(&mut self) -> Result<DeviceConfig> {
         match self.command("CONFIG").await? {
@@ -344,7 +719,7 @@ impl Protocol {
                 let config: DeviceConfig = serde_json::from_value(json)?;
                 Ok(config)
             }
-            Response::Error(e) => bail!("Device error: {e}"),
+            Response::Error(e) => bail!(CliError::Device(e)),
             Response::Ok(_) => bail!("Unexpected OK response to CONFIG"),
         }
     }
@@ -354,7 +729,7 @@ impl Protocol {
         let cmd = format!("SET NAME {name}");
         match self.command(&cmd).await? {
             Response::Ok(_) => Ok(()),
-            Response::Error(e) => bail!("Device error: {e}"),
+            Response::Error(e) => bail!(CliError::Device(e)),
             Response::Json(_) => bail!("Unexpected response to SET NAME"),
         }
     }
@@ -364,7 +739,7 @@ impl Protocol {
         let cmd = format!("SET FREQ {freq_mhz:.2}");
         match self.command(&cmd).await? {
             Response::Ok(_) => Ok(()),
-            Response::Error(e) => bail!("Device error: {e}"),
+            Response::Error(e) => bail!(CliError::Device(e)),
             Response::Json(_) => bail!("Unexpected response to SET FREQ"),
         }
     }
@@ -374,7 +749,7 @@ impl Protocol {
         let cmd = format!("SET POWER {dbm}");
         match self.command(&cmd).await? {
             Response::Ok(_) => Ok(()),
-            Response::Error(e) => bail!("Device error: {e}"),
+            Response::Error(e) => bail!(CliError::Device(e)),
             Response::Json(_) => bail!("Unexpected response to SET POWER"),
         }
     }
@@ -386,17 +761,162 @@ impl Protocol {
                 let neighbors: Vec<NeighborInfo> = serde_json::from_value(json)?;
                 Ok(neighbors)
             }
-            Response::Error(e) => bail!("Device error: {e}"),
+            Response::Error(e) => bail!(CliError::Device(e)),
             Response::Ok(_) => bail!("Unexpected OK response to NEIGHBORS"),
         }
     }
 
+    /// Get the device's route cache: the paths it learned to other nodes.
+    pub async fn get_routes(&mut self) -> Result<Vec<Route>> {
+        match self.command("ROUTES").await? {
+            Response::Json(json) => {
+                // `{"routes": [...]}`, or a bare array
+                let routes = json.get("routes").cloned().unwrap_or(json);
+                Ok(serde_json::from_value(routes)?)
+            }
+            Response::Error(e) => bail!(CliError::Device(e)),
+            Response::Ok(_) => bail!("Unexpected OK response to ROUTES"),
+        }
+    }
+
+    /// Sample the noise floor on the configured frequency.
+    pub async fn get_noise_floor(&mut self) -> Result<f32> {
+        match self.command("NOISE").await? {
+            Response::Json(json) => {
+                let noise: NoiseFloor = serde_json::from_value(json)?;
+                Ok(noise.noise_floor_dbm)
+            }
+            Response::Error(e) => bail!(CliError::Device(e)),
+            Response::Ok(_) => bail!("Unexpected OK response to NOISE"),
+        }
+    }
+
+    /// Add a contact to the device, so it can message the node before
+    /// hearing its advert.
+    pub async fn add_contact(&mut self, public_key: &[u8; 32], name: &str) -> Result<()> {
+        let cmd = format!("CONTACT ADD {} {name}", hex::encode(public_key));
+        match self.command(&cmd).await? {
+            Response::Ok(_) => Ok(()),
+            Response::Error(e) => bail!(CliError::Device(e)),
+            Response::Json(_) => bail!("Unexpected response to CONTACT ADD"),
+        }
+    }
+
+    /// Read the device's stored log. Logs larger than one response are read
+    /// in pages (`LOG <seq>`); firmware without paging answers `LOG` with
+    /// the whole buffer as an array.
+    pub async fn get_log(&mut self) -> Result<DeviceLog> {
+        let mut log = DeviceLog::default();
+        loop {
+            let cmd = if log.next == 0 {
+                "LOG".to_string()
+            } else {
+                format!("LOG {}", log.next)
+            };
+            match self.command(&cmd).await? {
+                Response::Json(serde_json::Value::Array(lines)) => {
+                    log.lines.extend(lines.iter().map(LogLine::from_json));
+                    return Ok(log);
+                }
+                Response::Json(page) => {
+                    if !log.absorb(&page) {
+                        return Ok(log);
+                    }
+                }
+                Response::Error(e) => bail!(CliError::Device(e)),
+                Response::Ok(_) => bail!("Unexpected OK response to LOG"),
+            }
+        }
+    }
+
+    /// Delete the device's stored log.
+    pub async fn clear_log(&mut self) -> Result<()> {
+        match self.command("LOG CLEAR").await? {
+            Response::Ok(_) => Ok(()),
+            Response::Error(e) => bail!(CliError::Device(e)),
+            Response::Json(_) => bail!("Unexpected response to LOG CLEAR"),
+        }
+    }
+
+    /// Get the firmware's log levels.
+    pub async fn get_log_levels(&mut self) -> Result<LogLevels> {
+        match self.command("LOG LEVEL").await? {
+            Response::Json(json) => {
+                serde_json::from_value(json).context("Invalid LOG LEVEL response")
+            }
+            Response::Error(e) => bail!(CliError::Device(e)),
+            Response::Ok(_) => bail!("Unexpected OK response to LOG LEVEL"),
+        }
+    }
+
+    /// Set the firmware's log level, for one subsystem or all of them.
+    pub async fn set_log_level(&mut self, level: &str, module: Option<&str>) -> Result<()> {
+        let cmd = match module {
+            Some(module) => format!("LOG LEVEL {level} {module}"),
+            None => format!("LOG LEVEL {level}"),
+        };
+        match self.command(&cmd).await? {
+            Response::Ok(_) => Ok(()),
+            Response::Error(e) => bail!(CliError::Device(e)),
+            Response::Json(_) => bail!("Unexpected response to LOG LEVEL"),
+        }
+    }
+
+    /// Get the device's channels, in order.
+    pub async fn get_channels(&mut self) -> Result<Vec<ChannelInfo>> {
+        match self.command("CHANNELS").await? {
+            Response::Json(json) => {
+                let channels = json.get("channels").cloned().unwrap_or_default();
+                Ok(serde_json::from_value(channels)?)
+            }
+            Response::Error(e) => bail!(CliError::Device(e)),
+            Response::Ok(_) => bail!("Unexpected OK response to CHANNELS"),
+        }
+    }
+
+    /// The custom channel messages without a destination go to, if one is
+    /// set; otherwise they are broadcast on the public channel.
+    pub async fn default_channel(&mut self) -> Result<Option<String>> {
+        Ok(self
+            .get_channels()
+            .await?
+            .into_iter()
+            .find(|c| c.default && !c.builtin)
+            .map(|c| c.name))
+    }
+
+    /// Send `text` with `cmd` (`SEND <node>`, `CHANNEL SEND <channel>`, ...),
+    /// in numbered parts when it doesn't fit one packet (see
+    /// [`fragment`]). Stops at the first part the device refuses and
+    /// returns its response, otherwise that of the last part.
+    pub async fn send_text(&mut self, cmd: &str, text: &str) -> Result<Response> {
+        let mut response = Response::Ok(None);
+        for part in fragment::split(text)? {
+            response = self.command(&format!("{cmd} {part}")).await?;
+            if !matches!(response, Response::Ok(_)) {
+                break;
+            }
+        }
+        Ok(response)
+    }
+
+    /// Send a message to a channel.
+    pub async fn send_channel(&mut self, channel: &str, message: &str) -> Result<()> {
+        match self
+            .send_text(&format!("CHANNEL SEND {channel}"), message)
+            .await?
+        {
+            Response::Ok(_) => Ok(()),
+            Response::Error(e) => bail!(CliError::Device(e)),
+            Response::Json(_) => bail!("Unexpected response to CHANNEL SEND"),
+        }
+    }
+
     /// Send a broadcast message.
     pub async fn send_broadcast(&mut self, message: &str) -> Result<()> {
-        let cmd = format!("SEND {message}");
-        match self.command(&cmd).await? {
+        match self.send_text("SEND", message).await? {
             Response::Ok(_) => Ok(()),
-            Response::Error(e) => bail!("Device error: {e}"),
+            Response::Error(e) => bail!(CliError::Device(e)),
             Response::Json(_) => bail!("Unexpected response to SEND"),
         }
     }
@@ -410,7 +930,7 @@ impl Protocol {
             Response::Json(_) => {
                 // Initial "sent" response - now wait for trace_response
             }
-            Response::Error(e) => bail!("Device error: {e}"),
+            Response::Error(e) => bail!(CliError::Device(e)),
             Response::Ok(_) => bail!("Unexpected OK response to TRACE"),
         }
 
@@ -420,7 +940,9 @@ impl Protocol {
 
         loop {
             if start.elapsed() > timeout {
-                bail!("Trace timeout - no response from target");
+                bail!(CliError::Timeout(
+                    "Trace timeout - no response from target".into()
+                ));
             }
 
             // Read a line
@@ -479,11 +1001,222 @@ impl Protocol {
         }
     }
 
+    /// Ask a node over the mesh for its position, waiting up to `timeout`
+    /// for the reply.
+    pub async fn request_position(
+        &mut self,
+        target: &str,
+        timeout: Duration,
+    ) -> Result<RemotePosition> {
+        self.send_position_request(target).await?;
+
+        let start = std::time::Instant::now();
+        while start.elapsed() < timeout {
+            if let Some(position) = self
+                .read_position_response(Duration::from_millis(500))
+                .await?
+            {
+                return Ok(position);
+            }
+        }
+        bail!(CliError::Timeout(format!(
+            "No position from {target} within {}s",
+            timeout.as_secs()
+        )))
+    }
+
+    /// Ask a node over the mesh for its position without waiting; the reply
+    /// comes in through [`Self::read_position_response`].
+    pub async fn send_position_request(&mut self, target: &str) -> Result<()> {
+        match self.command(&format!("POSITION REQUEST {target}")).await? {
+            Response::Json(_) | Response::Ok(_) => Ok(()),
+            Response::Error(e) => bail!(CliError::Device(e)),
+        }
+    }
+
+    /// Wait up to `timeout` for a line; `None` unless it is a position
+    /// reply. A failed request is a [`CliError::Device`].
+    pub async fn read_position_response(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<Option<RemotePosition>> {
+        let Some(line) = self.port.read_line_timeout(timeout).await? else {
+            return Ok(None);
+        };
+        let Ok(json) = serde_json::from_str::<serde_json::Value>(&line) else {
+            return Ok(None);
+        };
+        if json.get("type").and_then(|v| v.as_str()) != Some("position_response") {
+            return Ok(None);
+        }
+        if let Some(error) = json.get("error").and_then(|v| v.as_str()) {
+            bail!(CliError::Device(error.to_string()));
+        }
+        serde_json::from_value(json)
+            .map(Some)
+            .context("Invalid position response from the device")
+    }
+
+    /// Ask a node over the mesh for its name, firmware and status, waiting
+    /// up to `timeout` for the reply.
+    pub async fn request_node_info(
+        &mut self,
+        target: &str,
+        timeout: Duration,
+    ) -> Result<RemoteNodeInfo> {
+        match self.command(&format!("NODEINFO REQUEST {target}")).await? {
+            Response::Json(_) | Response::Ok(_) => {}
+            Response::Error(e) => bail!(CliError::Device(e)),
+        }
+
+        match self.read_mesh_reply("nodeinfo_response", timeout).await? {
+            Some(json) => serde_json::from_value(json).context("Invalid node info from the device"),
+            None => bail!(CliError::Timeout(format!(
+                "No node info from {target} within {}s",
+                timeout.as_secs()
+            ))),
+        }
+    }
+
+    /// Probe a node over the mesh (`PROBE <target>`), waiting up to
+    /// `timeout` for the reply; `None` if the probe or its reply was lost.
+    pub async fn request_probe(
+        &mut self,
+        target: &str,
+        timeout: Duration,
+    ) -> Result<Option<ProbeResult>> {
+        match self.command(&format!("PROBE {target}")).await? {
+            Response::Json(_) | Response::Ok(_) => {}
+            Response::Error(e) => bail!(CliError::Device(e)),
+        }
+        self.read_mesh_reply("probe_response", timeout)
+            .await?
+            .map(|json| serde_json::from_value(json).context("Invalid probe reply from the device"))
+            .transpose()
+    }
+
+    /// Ask a node over the mesh for its clock, waiting up to `timeout` for
+    /// the reply.
+    pub async fn request_time(&mut self, target: &str, timeout: Duration) -> Result<RemoteTime> {
+        self.remote_time(&format!("TIME REQUEST {target}"), "time_response", timeout)
+            .await
+    }
+
+    /// Set a node's clock over the mesh (remote admin, with the node's admin
+    /// `password` if it needs one); returns the clock it reports afterwards.
+    pub async fn set_remote_time(
+        &mut self,
+        target: &str,
+        time: i64,
+        password: Option<&str>,
+        timeout: Duration,
+    ) -> Result<RemoteTime> {
+        let mut cmd = format!("TIME SET {target} {time}");
+        if let Some(password) = password {
+            cmd.push(' ');
+            cmd.push_str(password);
+        }
+        self.remote_time(&cmd, "time_set_response", timeout).await
+    }
+
+    async fn remote_time(
+        &mut self,
+        cmd: &str,
+        kind: &str,
+        timeout: Duration,
+    ) -> Result<RemoteTime> {
+        match self.command(cmd).await? {
+            Response::Json(_) | Response::Ok(_) => {}
+            Response::Error(e) => bail!(CliError::Device(e)),
+        }
+        match self.read_mesh_reply(kind, timeout).await? {
+            Some(json) => serde_json::from_value(json).context("Invalid time from the device"),
+            None => bail!(CliError::Timeout(format!(
+                "No reply within {}s",
+                timeout.as_secs()
+            ))),
+        }
+    }
+
+    /// Read a remote node's stored log over the mesh, a page at a time
+    /// (`LOG REQUEST <target> <seq>`), asking up to `attempts` times for
+    /// each page before giving up.
+    pub async fn get_remote_log(
+        &mut self,
+        target: &str,
+        timeout: Duration,
+        attempts: u32,
+    ) -> Result<DeviceLog> {
+        let mut log = DeviceLog::default();
+        loop {
+            let page = self
+                .request_log_page(target, log.next, timeout, attempts)
+                .await?;
+            if !log.absorb(&page) {
+                return Ok(log);
+            }
+        }
+    }
+
+    async fn request_log_page(
+        &mut self,
+        target: &str,
+        seq: u64,
+        timeout: Duration,
+        attempts: u32,
+    ) -> Result<serde_json::Value> {
+        for attempt in 1..=attempts.max(1) {
+            match self.command(&format!("LOG REQUEST {target} {seq}")).await? {
+                Response::Json(_) | Response::Ok(_) => {}
+                Response::Error(e) => bail!(CliError::Device(e)),
+            }
+            if let Some(page) = self.read_mesh_reply("log_response", timeout).await? {
+                return Ok(page);
+            }
+            tracing::debug!("No log page {seq} from {target} (attempt {attempt}/{attempts})");
+        }
+        bail!(CliError::Timeout(format!(
+            "No log page from {target} after {} attempts of {}s",
+            attempts.max(1),
+            timeout.as_secs()
+        )))
+    }
+
+    /// Wait up to `timeout` for the JSON reply of type `kind` to a request
+    /// sent over the mesh. A failed request is a [`CliError::Device`].
+    async fn read_mesh_reply(
+        &mut self,
+        kind: &str,
+        timeout: Duration,
+    ) -> Result<Option<serde_json::Value>> {
+        let start = std::time::Instant::now();
+        while start.elapsed() < timeout {
+            let Some(line) = self
+                .port
+                .read_line_timeout(Duration::from_millis(500))
+                .await?
+            else {
+                continue;
+            };
+            let Ok(json) = serde_json::from_str::<serde_json::Value>(&line) else {
+                continue;
+            };
+            if json.get("type").and_then(|v| v.as_str()) != Some(kind) {
+                continue;
+            }
+            if let Some(error) = json.get("error").and_then(|v| v.as_str()) {
+                bail!(CliError::Device(error.to_string()));
+            }
+            return Ok(Some(json));
+        }
+        Ok(None)
+    }
+
     /// Reboot the device.
     pub async fn reboot(&mut self) -> Result<()> {
         match self.command("REBOOT").await? {
             Response::Ok(_) => Ok(()),
-            Response::Error(e) => bail!("Device error: {e}"),
+            Response::Error(e) => bail!(CliError::Device(e)),
             Response::Json(_) => bail!("Unexpected response to REBOOT"),
         }
     }
@@ -492,7 +1225,7 @@ impl Protocol {
     pub async fn enter_monitor_mode(&mut self) -> Result<()> {
         match self.command("MONITOR").await? {
             Response::Ok(_) => Ok(()),
-            Response::Error(e) => bail!("Device error: {e}"),
+            Response::Error(e) => bail!(CliError::Device(e)),
             Response::Json(_) => bail!("Unexpected response to MONITOR"),
         }
     }
@@ -506,68 +1239,49 @@ impl Protocol {
         else {
             return Ok(None);
         };
+        Ok(MonitorEvent::parse(&line))
+    }
 
-        // Parse event
-        if line.starts_with("MSG ") {
-            // Format: MSG <from> <to> <rssi> <snr> <text>
-            let parts: Vec<&str> = line.splitn(6, ' ').collect();
-            if parts.len() >= 6 {
-                return Ok(Some(MonitorEvent::Message {
-                    from: parts[1].to_string(),
-                    to: if parts[2] == "*" {
-                        None
-                    } else {
-                        Some(parts[2].to_string())
-                    },
-                    rssi: parts[3].parse().unwrap_or(0),
-                    // snr: parts[4] - ignored
-                    text: parts[5].to_string(),
-                }));
-            }
-        } else if line.starts_with("ADV ") {
-            // Format: ADV <hash> <rssi> <name>
-            let parts: Vec<&str> = line.splitn(4, ' ').collect();
-            if parts.len() >= 3 {
-                let hash = u8::from_str_radix(parts[1].trim_start_matches("0x"), 16).unwrap_or(0);
-                return Ok(Some(MonitorEvent::Advertisement {
-                    node_hash: hash,
-                    rssi: parts[2].parse().unwrap_or(0),
-                    name: parts.get(3).map(std::string::ToString::to_string),
-                }));
-            }
-        } else if line.starts_with("ACK ") {
-            // Format: ACK <from>
-            let from = line.strip_prefix("ACK ").unwrap_or("?").to_string();
-            return Ok(Some(MonitorEvent::Ack { from }));
-        } else if line.starts_with("ERR ") {
-            let msg = line.strip_prefix("ERR ").unwrap_or(&line).to_string();
-            return Ok(Some(MonitorEvent::Error { message: msg }));
-        }
-
-        Ok(None)
+    /// Read the next frame as text, e.g. a debug frame or a monitor event.
+    pub async fn read_frame(&mut self, timeout: Duration) -> Result<Option<String>> {
+        Ok(self
+            .port
+            .read_cobs_frame_timeout(timeout)
+            .await?
+            .map(|frame| String::from_utf8_lossy(&frame).trim_end().to_string()))
     }
 
     /// Send a raw packet.
     pub async fn send_packet(&mut self, packet: &[u8]) -> Result<()> {
+        self.wait_for_airtime(packet.len()).await?;
         let header = format!("PKT {}\n", packet.len());
         self.port.write(header.as_bytes()).await?;
         self.port.write(packet).await?;
 
-        match self.read_response().await? {
+        match self.read_response(false).await? {
             Response::Ok(msg) => {
                 if let Some(m) = msg {
                     tracing::debug!("PKT response: {}", m);
                 }
                 Ok(())
             }
-            Response::Error(e) => bail!("Device error: {e}"),
+            Response::Error(e) => bail!(CliError::Device(e)),
             Response::Json(_) => bail!("Unexpected response to PKT"),
         }
     }
 
     /// Get device telemetry.
     pub async fn get_telemetry(&mut self) -> Result<Telemetry> {
-        match self.command("TELEMETRY").await? {
+        self.request_telemetry("TELEMETRY").await
+    }
+
+    /// Request telemetry from another node over the mesh.
+    pub async fn get_node_telemetry(&mut self, node: &str) -> Result<Telemetry> {
+        self.request_telemetry(&format!("TELEMETRY {node}")).await
+    }
+
+    async fn request_telemetry(&mut self, cmd: &str) -> Result<Telemetry> {
+        match self.command(cmd).await? {
             Response::Json(json) => {
                 // Parse JSON telemetry response
                 let mut telem = Telemetry::new();
@@ -662,8 +1376,8 @@ impl Protocol {
 
                 Ok(telem)
             }
-            Response::Error(e) => bail!("Device error: {e}"),
-            Response::Ok(_) => bail!("Unexpected OK response to TELEMETRY"),
+            Response::Error(e) => bail!(CliError::Device(e)),
+            Response::Ok(_) => bail!("Unexpected OK response to {cmd}"),
         }
     }
 
@@ -701,11 +1415,15 @@ impl Protocol {
 }
 
 /// Monitor event types.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum MonitorEvent {
     Message {
         from: String,
         to: Option<String>,
+        /// Channel of a broadcast, when it isn't the public channel
+        #[serde(skip_serializing_if = "Option::is_none")]
+        channel: Option<String>,
         rssi: i16,
         text: String,
     },
@@ -717,7 +1435,163 @@ pub enum MonitorEvent {
     Ack {
         from: String,
     },
+    Waypoint {
+        from: String,
+        rssi: i16,
+        name: String,
+        lat: f64,
+        lon: f64,
+        icon: Option<String>,
+        /// Unix time after which the waypoint should be hidden
+        expires: Option<i64>,
+    },
     Error {
         message: String,
     },
 }
+
+impl MonitorEvent {
+    /// Parse a monitor mode event line.
+    pub fn parse(line: &str) -> Option<Self> {
+        if line.starts_with("MSG ") {
+            // Format: MSG <from> <to> <rssi> <snr> <text>, where <to> is
+            // `*` for the public channel and `*<channel>` for other channels
+            let parts: Vec<&str> = line.splitn(6, ' ').collect();
+            if parts.len() >= 6 {
+                let (to, channel) = match parts[2].strip_prefix('*') {
+                    Some("") => (None, None),
+                    Some(channel) => (None, Some(channel.to_string())),
+                    None => (Some(parts[2].to_string()), None),
+                };
+                return Some(MonitorEvent::Message {
+                    from: parts[1].to_string(),
+                    to,
+                    channel,
+                    rssi: parts[3].parse().unwrap_or(0),
+                    // snr: parts[4] - ignored
+                    text: parts[5].to_string(),
+                });
+            }
+        } else if line.starts_with("ADV ") {
+            // Format: ADV <hash> <rssi> <name>
+            let parts: Vec<&str> = line.splitn(4, ' ').collect();
+            if parts.len() >= 3 {
+                let hash = u8::from_str_radix(parts[1].trim_start_matches("0x"), 16).unwrap_or(0);
+                return Some(MonitorEvent::Advertisement {
+                    node_hash: hash,
+                    rssi: parts[2].parse().unwrap_or(0),
+                    name: parts.get(3).map(std::string::ToString::to_string),
+                });
+            }
+        } else if line.starts_with("ACK ") {
+            // Format: ACK <from>
+            let from = line.strip_prefix("ACK ").unwrap_or("?").to_string();
+            return Some(MonitorEvent::Ack { from });
+        } else if let Some(rest) = line.strip_prefix("WPT ") {
+            // Format: WPT <from> <rssi> <lat> <lon> <expires|0> <icon|-> <name>
+            let parts: Vec<&str> = rest.splitn(7, ' ').collect();
+            if let [from, rssi, lat, lon, expires, icon, name] = parts[..] {
+                return Some(MonitorEvent::Waypoint {
+                    from: from.to_string(),
+                    rssi: rssi.parse().unwrap_or(0),
+                    name: name.to_string(),
+                    lat: lat.parse().ok()?,
+                    lon: lon.parse().ok()?,
+                    icon: (icon != "-").then(|| icon.to_string()),
+                    expires: expires.parse().ok().filter(|&t: &i64| t > 0),
+                });
+            }
+        } else if line.starts_with("ERR ") {
+            let msg = line.strip_prefix("ERR ").unwrap_or(line).to_string();
+            return Some(MonitorEvent::Error { message: msg });
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_crash_loop_only_on_banners() {
+        use crate::framing::cobs_encode_into;
+        use tokio::io::AsyncWriteExt;
+
+        async fn answer(frames: &[&str]) -> Result<Response> {
+            let (host, mut device) = tokio::io::duplex(64 * 1024);
+            let mut data = bytes::BytesMut::new();
+            for frame in frames {
+                cobs_encode_into(frame.as_bytes(), &mut data);
+            }
+            device.write_all(&data).await.unwrap();
+            let mut proto = Protocol::new(SerialPort::from_transport(Box::new(host)));
+            proto.read_response(false).await
+        }
+
+        // A chatty device is not a crashing one
+        let mut frames = vec!["PONG 1234"; 30];
+        frames.extend(["log: tx done"; 30]);
+        let err = answer(&frames).await.unwrap_err();
+        assert!(!err.is::<CrashLoop>());
+        assert!(err.to_string().contains("Too many unrecognized frames"));
+
+        // A reboot while waiting is fine
+        let reboot = [
+            "rst:0x1 (POWERON_RESET),boot:0x13",
+            "ets Jun  8 2016 00:22:57",
+        ];
+        let mut frames = reboot.to_vec();
+        frames.push("OK");
+        assert!(matches!(answer(&frames).await, Ok(Response::Ok(None))));
+
+        let frames: Vec<&str> = ["Guru Meditation Error", "Backtrace: 0x4008"]
+            .iter()
+            .chain(&reboot)
+            .copied()
+            .cycle()
+            .take(12)
+            .collect();
+        assert!(answer(&frames).await.unwrap_err().is::<CrashLoop>());
+    }
+
+    #[test]
+    fn test_continuation_frames() {
+        let mut partial = Continuation::default();
+        for frame in [&b"MORE 0 [{\"a\":"[..], b"MORE 1 1},", b"END 2 {\"a\":2}]"] {
+            let (seq, last, chunk) = continuation_frame(frame).unwrap();
+            partial.push(seq, chunk).unwrap();
+            assert_eq!(last, frame.starts_with(b"END"));
+        }
+        assert_eq!(&partial.data[..], b"[{\"a\":1},{\"a\":2}]");
+
+        assert!(continuation_frame(b"OK").is_none());
+        assert!(continuation_frame(b"MORE x data").is_none());
+        let mut partial = Continuation::default();
+        partial.push(0, b"[").unwrap();
+        assert!(partial.push(2, b"]").is_err());
+    }
+
+    #[test]
+    fn test_log_pages() {
+        let line = |seq: u64| serde_json::json!({"seq": seq, "uptime": seq, "level": "INFO", "msg": format!("line {seq}")});
+        let mut log = DeviceLog::default();
+        assert!(
+            log.absorb(&serde_json::json!({"first": 5, "more": true, "lines": [line(5), line(6)]}))
+        );
+        // Lines 7 and 8 were overwritten before the next page; 9 is repeated
+        assert!(log
+            .absorb(&serde_json::json!({"first": 9, "more": true, "lines": [line(9), line(10)]})));
+        assert!(!log.absorb(
+            &serde_json::json!({"first": 9, "more": false, "uptime_ms": 12_000, "lines": [line(10), line(11)]})
+        ));
+
+        let seqs: Vec<_> = log.lines.iter().filter_map(|l| l.seq).collect();
+        assert_eq!(seqs, [5, 6, 9, 10, 11]);
+        assert_eq!(log.dropped, 2);
+        assert_eq!(log.uptime_ms, Some(12_000));
+        assert_eq!(log.lines[0].uptime_ms, Some(5000));
+        assert_eq!(log.lines[4].msg, "line 11");
+    }
+}
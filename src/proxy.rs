@@ -0,0 +1,245 @@
+//! Local device proxy.
+//!
+//! Shares one open device connection with child processes (e.g., plugins)
+//! through a local socket. Clients are served one at a time and bytes are
+//! relayed verbatim, so any meshgrid command can use the proxy address as
+//! its `--port`.
+//!
+//! On Unix the socket lives in a fresh directory only the owner can enter,
+//! and its address is recorded in the port lock, so another invocation told
+//! the port is busy learns where to connect instead. Elsewhere the proxy
+//! listens on loopback TCP, which any local user can reach, so clients must
+//! first send a random token: this process knows it, and plugins get it in
+//! `MESHGRID_PROXY_TOKEN`.
+
+use anyhow::Result;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::task::JoinHandle;
+
+use crate::portlock;
+use crate::serial::{SerialPort, Transport};
+
+/// Tokens of the proxies this process runs, by address.
+static TOKENS: Mutex<Option<HashMap<String, String>>> = Mutex::new(None);
+
+/// A running proxy for an open device.
+pub struct DeviceProxy {
+    address: String,
+    token: Option<String>,
+    task: JoinHandle<()>,
+    #[cfg(unix)]
+    _socket_dir: tempfile::TempDir,
+}
+
+impl DeviceProxy {
+    /// Open the device and start serving it on a local socket.
+    pub async fn start(port: &str, baud: u32) -> Result<Self> {
+        let device = SerialPort::open(port, baud).await?.into_transport();
+        Self::serve(device)
+    }
+
+    #[cfg(unix)]
+    fn serve(mut device: Box<dyn Transport>) -> Result<Self> {
+        let socket_dir = socket_dir()?;
+        let socket_path = socket_dir.path().join("device.sock");
+        let listener = tokio::net::UnixListener::bind(&socket_path)?;
+
+        let task = tokio::spawn(async move {
+            while let Ok((client, _)) = listener.accept().await {
+                if let Err(e) = relay(client, &mut device).await {
+                    tracing::warn!("Proxy stopped: {e}");
+                    break;
+                }
+            }
+        });
+
+        let address = format!("unix:{}", socket_path.display());
+        portlock::set_proxy(Some(&address));
+        Ok(Self {
+            address,
+            token: None,
+            task,
+            _socket_dir: socket_dir,
+        })
+    }
+
+    #[cfg(not(unix))]
+    fn serve(mut device: Box<dyn Transport>) -> Result<Self> {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        listener.set_nonblocking(true)?;
+        let addr = listener.local_addr()?;
+        let listener = tokio::net::TcpListener::from_std(listener)?;
+        let mut token = [0u8; 16];
+        getrandom::getrandom(&mut token)?;
+        let token = hex::encode(token);
+
+        let expected = token.clone();
+        let task = tokio::spawn(async move {
+            while let Ok((mut client, peer)) = listener.accept().await {
+                if !check_token(&mut client, &expected).await {
+                    tracing::warn!("Proxy client {peer} sent a wrong token");
+                    continue;
+                }
+                if let Err(e) = relay(client, &mut device).await {
+                    tracing::warn!("Proxy stopped: {e}");
+                    break;
+                }
+            }
+        });
+
+        // Not advertised in the port lock: other processes lack the token
+        let address = format!("tcp://{addr}");
+        tokens(|t| t.insert(address.clone(), token.clone()));
+        Ok(Self {
+            address,
+            token: Some(token),
+            task,
+        })
+    }
+
+    /// Address clients pass as `--port` to reach the device.
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// Token clients must send first, when the proxy listens on TCP.
+    pub fn token(&self) -> Option<&str> {
+        self.token.as_deref()
+    }
+}
+
+impl Drop for DeviceProxy {
+    fn drop(&mut self) {
+        self.task.abort();
+        if self.token.is_some() {
+            tokens(|t| t.remove(&self.address));
+        } else {
+            portlock::set_proxy(None);
+        }
+    }
+}
+
+/// New directory for the proxy socket that only the owner can enter, so the
+/// socket itself needs no authentication.
+#[cfg(unix)]
+fn socket_dir() -> Result<tempfile::TempDir> {
+    use std::os::unix::fs::PermissionsExt;
+
+    Ok(tempfile::Builder::new()
+        .prefix("meshgrid-")
+        .permissions(std::fs::Permissions::from_mode(0o700))
+        .tempdir()?)
+}
+
+fn tokens<T>(f: impl FnOnce(&mut HashMap<String, String>) -> T) -> T {
+    let mut tokens = TOKENS.lock().unwrap_or_else(|e| e.into_inner());
+    f(tokens.get_or_insert_with(HashMap::new))
+}
+
+/// Token to send when connecting to `port`, if it is a proxy of this
+/// process or the one a parent process passed in the environment.
+pub fn token_for(port: &str) -> Option<String> {
+    tokens(|t| t.get(port).cloned()).or_else(|| {
+        let proxy = std::env::var("MESHGRID_PROXY").ok()?;
+        (proxy == port)
+            .then(|| std::env::var("MESHGRID_PROXY_TOKEN").ok())
+            .flatten()
+    })
+}
+
+/// Send `token` as the first line of a proxy connection.
+pub async fn send_token<S: AsyncWrite + Unpin>(stream: &mut S, token: &str) -> Result<()> {
+    stream.write_all(format!("{token}\n").as_bytes()).await?;
+    Ok(())
+}
+
+/// Whether the client's first line is `expected`; clients get a few seconds
+/// to send it.
+#[cfg(any(not(unix), test))]
+async fn check_token<C: AsyncRead + Unpin>(client: &mut C, expected: &str) -> bool {
+    let mut line = vec![0u8; expected.len() + 1];
+    let read = tokio::time::timeout(
+        std::time::Duration::from_secs(5),
+        client.read_exact(&mut line),
+    )
+    .await;
+    if !matches!(read, Ok(Ok(_))) || line.pop() != Some(b'\n') {
+        return false;
+    }
+    crate::commands::serve::token_matches(expected, &String::from_utf8_lossy(&line))
+}
+
+/// Relay bytes between a client and the device until the client disconnects.
+///
+/// Client-side errors end the session; device-side errors are returned.
+async fn relay<C>(mut client: C, device: &mut Box<dyn Transport>) -> Result<()>
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut client_buf = [0u8; 1024];
+    let mut device_buf = [0u8; 1024];
+
+    loop {
+        tokio::select! {
+            n = client.read(&mut client_buf) => {
+                let Ok(n) = n else { return Ok(()) };
+                if n == 0 {
+                    return Ok(());
+                }
+                device.write_all(&client_buf[..n]).await?;
+                device.flush().await?;
+            }
+            n = device.read(&mut device_buf) => {
+                let n = n?;
+                if n == 0 {
+                    anyhow::bail!("EOF on device");
+                }
+                if client.write_all(&device_buf[..n]).await.is_err() {
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_socket_dir_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = socket_dir().unwrap();
+        let mode = dir.path().metadata().unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+    }
+
+    #[tokio::test]
+    async fn test_proxy_token() {
+        let token = "0123456789abcdef0123456789abcdef";
+        for (sent, accepted) in [
+            (format!("{token}\n"), true),
+            (format!("{}\n", token.replace('0', "f")), false),
+            (format!("{token}x"), false),
+            ("short".to_string(), false),
+        ] {
+            let (mut client, mut server) = tokio::io::duplex(64);
+            client.write_all(sent.as_bytes()).await.unwrap();
+            drop(client);
+            assert_eq!(check_token(&mut server, token).await, accepted, "{sent:?}");
+        }
+
+        let mut sent = Vec::new();
+        send_token(&mut sent, token).await.unwrap();
+        assert!(check_token(&mut sent.as_slice(), token).await);
+
+        tokens(|t| t.insert("tcp://127.0.0.1:9".into(), token.into()));
+        assert_eq!(token_for("tcp://127.0.0.1:9").as_deref(), Some(token));
+        assert_eq!(token_for("tcp://127.0.0.1:10"), None);
+        tokens(|t| t.remove("tcp://127.0.0.1:9"));
+    }
+}
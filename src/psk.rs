@@ -0,0 +1,108 @@
+//! Channel PSK checks.
+//!
+//! A private channel is only as private as its key. These checks catch keys
+//! that decode fine but protect nothing: published defaults, keys derived from
+//! the channel name (which makes it a public hashtag channel), low-entropy and
+//! typed-in keys.
+
+use anyhow::{bail, Result};
+use base64::{engine::general_purpose, Engine as _};
+use sha2::{Digest, Sha256};
+
+use crate::error::CliError;
+
+/// Published keys that must not be used for private channels.
+const WELL_KNOWN: [(&str, &str); 2] = [
+    ("1PG7OiApB1nwvP+rz05pAQ==", "the Meshtastic default key"),
+    (
+        "izOH6cXN6mrJ5e26oRXNcg==",
+        "the MeshCore public channel key",
+    ),
+];
+
+/// PSK of a public hashtag channel: SHA256(name).
+pub fn hashtag_psk(name: &str) -> String {
+    general_purpose::STANDARD.encode(Sha256::digest(name.as_bytes()))
+}
+
+/// Decode a base64 PSK, which must be 16 or 32 bytes.
+pub fn decode(psk: &str) -> Result<Vec<u8>> {
+    match general_purpose::STANDARD.decode(psk.trim()) {
+        Ok(key) if key.len() == 16 || key.len() == 32 => Ok(key),
+        Ok(key) => bail!(CliError::InvalidArgs(format!(
+            "PSK must be 16 or 32 bytes, got {} (base64-encoded)",
+            key.len()
+        ))),
+        Err(_) => bail!(CliError::InvalidArgs(
+            "PSK must be 16 or 32 bytes, base64-encoded".into()
+        )),
+    }
+}
+
+/// Problems with the key of private channel `name`; empty if none found.
+pub fn weaknesses(name: &str, key: &[u8]) -> Vec<String> {
+    let mut found = Vec::new();
+    let encoded = general_purpose::STANDARD.encode(key);
+
+    if let Some((_, label)) = WELL_KNOWN.iter().find(|(known, _)| *known == encoded) {
+        found.push(format!("is {label}"));
+    }
+    let bare = name.trim_start_matches('#');
+    if encoded == hashtag_psk(bare) || encoded == hashtag_psk(&format!("#{bare}")) {
+        found.push("is derived from the channel name, like a public hashtag channel".into());
+    }
+
+    let mut distinct = key.to_vec();
+    distinct.sort_unstable();
+    distinct.dedup();
+    if distinct.len() < key.len() / 2 {
+        found.push(format!(
+            "has only {} distinct byte values (low entropy)",
+            distinct.len()
+        ));
+    } else if key.windows(2).all(|w| w[1] == w[0].wrapping_add(1)) {
+        found.push("is a run of sequential bytes".into());
+    } else if key.iter().all(|b| (0x20..0x7f).contains(b)) {
+        found.push("is printable text rather than random bytes".into());
+    }
+    found
+}
+
+/// Print a warning for a weak key. Returns whether any problem was found.
+pub fn warn_if_weak(name: &str, key: &[u8]) -> bool {
+    let found = weaknesses(name, key);
+    if found.is_empty() {
+        return false;
+    }
+    eprintln!("⚠ WARNING: the PSK for channel '{name}' is weak. It:");
+    for problem in &found {
+        eprintln!("    - {problem}");
+    }
+    eprintln!("  Anyone may be able to read this channel. Generate a strong key with");
+    eprintln!("  `openssl rand -base64 32`.");
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_weaknesses() {
+        let strong = general_purpose::STANDARD
+            .decode("nNj88ipHMztZHZaiuEi3P0V7G7Gj6iRTqIX55Xh3ZbE=")
+            .unwrap();
+        assert!(weaknesses("ops", &strong).is_empty());
+        // The same key is name-derived for "#test"
+        assert_eq!(weaknesses("test", &strong).len(), 1);
+
+        let default = decode("1PG7OiApB1nwvP+rz05pAQ==").unwrap();
+        assert_eq!(
+            weaknesses("ops", &default),
+            ["is the Meshtastic default key"]
+        );
+        assert_eq!(weaknesses("ops", &[0u8; 16]).len(), 1);
+        assert_eq!(weaknesses("ops", b"correcthorsebatt").len(), 1);
+        assert!(decode("c2hvcnQ=").is_err());
+    }
+}
@@ -0,0 +1,487 @@
+//! Minimal QR code encoder for terminal display.
+//!
+//! Byte mode at error correction level M, versions 1-10 (up to 213 bytes),
+//! which is plenty for keys and share links.
+
+use anyhow::{bail, Result};
+
+/// Level M block layout per version: (EC codewords per block, [(block count,
+/// data codewords per block)]).
+const BLOCKS: [(usize, &[(usize, usize)]); 10] = [
+    (10, &[(1, 16)]),
+    (16, &[(1, 28)]),
+    (26, &[(1, 44)]),
+    (18, &[(2, 32)]),
+    (24, &[(2, 43)]),
+    (16, &[(4, 27)]),
+    (18, &[(4, 31)]),
+    (22, &[(2, 38), (2, 39)]),
+    (22, &[(3, 36), (2, 37)]),
+    (26, &[(4, 43), (1, 44)]),
+];
+
+/// Alignment pattern centres per version.
+const ALIGNMENT: [&[usize]; 10] = [
+    &[],
+    &[6, 18],
+    &[6, 22],
+    &[6, 26],
+    &[6, 30],
+    &[6, 34],
+    &[6, 22, 38],
+    &[6, 24, 42],
+    &[6, 26, 46],
+    &[6, 28, 50],
+];
+
+/// Encoded QR symbol.
+pub struct QrCode {
+    size: usize,
+    /// Dark modules, row-major
+    modules: Vec<bool>,
+}
+
+impl QrCode {
+    /// Encode `data` in the smallest version that fits.
+    pub fn encode(data: &[u8]) -> Result<Self> {
+        let Some(version) = (1..=BLOCKS.len()).find(|&v| data.len() <= capacity(v)) else {
+            bail!(
+                "Too much data for a QR code ({} bytes, max {})",
+                data.len(),
+                capacity(BLOCKS.len())
+            );
+        };
+        let codewords = add_error_correction(version, &data_codewords(version, data));
+
+        let mut symbol = Symbol::new(version);
+        symbol.draw_function_patterns();
+        symbol.draw_codewords(&codewords);
+
+        let best = (0..8)
+            .map(|mask| {
+                let mut candidate = symbol.clone();
+                candidate.apply_mask(mask);
+                candidate.draw_format(mask);
+                candidate
+            })
+            .min_by_key(Symbol::penalty)
+            .expect("eight masks");
+
+        Ok(QrCode {
+            size: best.size,
+            modules: best.modules,
+        })
+    }
+
+    fn is_dark(&self, x: isize, y: isize) -> bool {
+        let size = self.size as isize;
+        (0..size).contains(&x) && (0..size).contains(&y) && self.modules[(y * size + x) as usize]
+    }
+
+    /// Render with half-block characters, two module rows per line. Light
+    /// modules are drawn, so the code reads on dark terminal backgrounds.
+    pub fn render(&self) -> String {
+        const QUIET: isize = 2;
+        let size = self.size as isize;
+        let mut out = String::new();
+        for y in (-QUIET..size + QUIET).step_by(2) {
+            for x in -QUIET..size + QUIET {
+                let top = !self.is_dark(x, y);
+                let bottom = y + 1 >= size + QUIET || !self.is_dark(x, y + 1);
+                out.push(match (top, bottom) {
+                    (true, true) => '█',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (false, false) => ' ',
+                });
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+fn data_capacity(version: usize) -> usize {
+    BLOCKS[version - 1]
+        .1
+        .iter()
+        .map(|(count, len)| count * len)
+        .sum()
+}
+
+fn count_bits(version: usize) -> usize {
+    if version < 10 {
+        8
+    } else {
+        16
+    }
+}
+
+/// Payload bytes that fit in a version.
+fn capacity(version: usize) -> usize {
+    (data_capacity(version) * 8 - 4 - count_bits(version)) / 8
+}
+
+/// Mode indicator, length, data, terminator and padding.
+fn data_codewords(version: usize, data: &[u8]) -> Vec<u8> {
+    let mut bits = BitWriter::default();
+    bits.push(0b0100, 4);
+    bits.push(data.len() as u32, count_bits(version));
+    for &b in data {
+        bits.push(u32::from(b), 8);
+    }
+    let capacity = data_capacity(version);
+    bits.push(0, (capacity * 8 - bits.len).min(4));
+    let mut codewords = bits.into_bytes();
+    for pad in [0xec, 0x11].into_iter().cycle() {
+        if codewords.len() >= capacity {
+            break;
+        }
+        codewords.push(pad);
+    }
+    codewords
+}
+
+/// Split into blocks, append Reed-Solomon codewords and interleave.
+fn add_error_correction(version: usize, data: &[u8]) -> Vec<u8> {
+    let (ec_len, groups) = BLOCKS[version - 1];
+    let divisor = rs_divisor(ec_len);
+
+    let mut blocks = Vec::new();
+    let mut offset = 0;
+    for &(count, len) in groups {
+        for _ in 0..count {
+            let block = &data[offset..offset + len];
+            blocks.push((block, rs_remainder(block, &divisor)));
+            offset += len;
+        }
+    }
+
+    let longest = blocks.iter().map(|(d, _)| d.len()).max().unwrap_or(0);
+    let mut out = Vec::new();
+    for i in 0..longest {
+        out.extend(blocks.iter().filter_map(|(d, _)| d.get(i)));
+    }
+    for i in 0..ec_len {
+        out.extend(blocks.iter().map(|(_, ec)| ec[i]));
+    }
+    out
+}
+
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    len: usize,
+}
+
+impl BitWriter {
+    fn push(&mut self, value: u32, bits: usize) {
+        for i in (0..bits).rev() {
+            if self.len.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            if (value >> i) & 1 == 1 {
+                *self.bytes.last_mut().expect("pushed") |= 0x80 >> (self.len % 8);
+            }
+            self.len += 1;
+        }
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// Multiply in GF(2^8) modulo x^8 + x^4 + x^3 + x^2 + 1.
+fn gf_mul(x: u8, y: u8) -> u8 {
+    let mut z: u8 = 0;
+    for i in (0..8).rev() {
+        z = (z << 1) ^ ((z >> 7) * 0x1d);
+        z ^= ((y >> i) & 1) * x;
+    }
+    z
+}
+
+/// Generator polynomial coefficients (highest first, leading 1 omitted).
+fn rs_divisor(degree: usize) -> Vec<u8> {
+    let mut result = vec![0u8; degree];
+    result[degree - 1] = 1;
+    let mut root = 1u8;
+    for _ in 0..degree {
+        for j in 0..degree {
+            result[j] = gf_mul(result[j], root);
+            if j + 1 < degree {
+                result[j] ^= result[j + 1];
+            }
+        }
+        root = gf_mul(root, 0x02);
+    }
+    result
+}
+
+fn rs_remainder(data: &[u8], divisor: &[u8]) -> Vec<u8> {
+    let mut result = vec![0u8; divisor.len()];
+    for &b in data {
+        let factor = b ^ result.remove(0);
+        result.push(0);
+        for (r, &d) in result.iter_mut().zip(divisor) {
+            *r ^= gf_mul(d, factor);
+        }
+    }
+    result
+}
+
+/// Symbol under construction; coordinates are (x = column, y = row).
+#[derive(Clone)]
+struct Symbol {
+    version: usize,
+    size: usize,
+    modules: Vec<bool>,
+    /// Finder, timing, alignment, format and version modules
+    function: Vec<bool>,
+}
+
+impl Symbol {
+    fn new(version: usize) -> Self {
+        let size = 17 + 4 * version;
+        Symbol {
+            version,
+            size,
+            modules: vec![false; size * size],
+            function: vec![false; size * size],
+        }
+    }
+
+    fn get(&self, x: usize, y: usize) -> bool {
+        self.modules[y * self.size + x]
+    }
+
+    fn set_function(&mut self, x: usize, y: usize, dark: bool) {
+        self.modules[y * self.size + x] = dark;
+        self.function[y * self.size + x] = true;
+    }
+
+    fn draw_function_patterns(&mut self) {
+        let size = self.size;
+        for i in 0..size {
+            self.set_function(6, i, i % 2 == 0);
+            self.set_function(i, 6, i % 2 == 0);
+        }
+
+        self.draw_finder(3, 3);
+        self.draw_finder(size - 4, 3);
+        self.draw_finder(3, size - 4);
+
+        let centres = ALIGNMENT[self.version - 1];
+        let last = centres.len().saturating_sub(1);
+        for (i, &x) in centres.iter().enumerate() {
+            for (j, &y) in centres.iter().enumerate() {
+                let corner = (i == 0 && (j == 0 || j == last)) || (i == last && j == 0);
+                if !corner {
+                    self.draw_alignment(x, y);
+                }
+            }
+        }
+
+        // Reserve the format areas; real bits are drawn after masking
+        self.draw_format(0);
+        self.draw_version();
+    }
+
+    /// Finder pattern plus separator centred on (x, y).
+    fn draw_finder(&mut self, x: usize, y: usize) {
+        for dy in -4isize..=4 {
+            for dx in -4isize..=4 {
+                let (px, py) = (x as isize + dx, y as isize + dy);
+                if (0..self.size as isize).contains(&px) && (0..self.size as isize).contains(&py) {
+                    let dist = dx.abs().max(dy.abs());
+                    self.set_function(px as usize, py as usize, dist != 2 && dist != 4);
+                }
+            }
+        }
+    }
+
+    fn draw_alignment(&mut self, x: usize, y: usize) {
+        for dy in -2isize..=2 {
+            for dx in -2isize..=2 {
+                let dark = dx.abs().max(dy.abs()) != 1;
+                self.set_function((x as isize + dx) as usize, (y as isize + dy) as usize, dark);
+            }
+        }
+    }
+
+    /// Format information (level M) for `mask`, both copies.
+    fn draw_format(&mut self, mask: u32) {
+        let data = mask; // level M is 0b00
+        let mut rem = data;
+        for _ in 0..10 {
+            rem = (rem << 1) ^ ((rem >> 9) * 0x537);
+        }
+        let bits = ((data << 10) | rem) ^ 0x5412;
+        let bit = |i: usize| (bits >> i) & 1 == 1;
+        let size = self.size;
+
+        for i in 0..=5 {
+            self.set_function(8, i, bit(i));
+        }
+        self.set_function(8, 7, bit(6));
+        self.set_function(8, 8, bit(7));
+        self.set_function(7, 8, bit(8));
+        for i in 9..15 {
+            self.set_function(14 - i, 8, bit(i));
+        }
+
+        for i in 0..8 {
+            self.set_function(size - 1 - i, 8, bit(i));
+        }
+        for i in 8..15 {
+            self.set_function(8, size - 15 + i, bit(i));
+        }
+        self.set_function(8, size - 8, true);
+    }
+
+    /// Version information, versions 7 and up.
+    fn draw_version(&mut self) {
+        if self.version < 7 {
+            return;
+        }
+        let version = self.version as u32;
+        let mut rem = version;
+        for _ in 0..12 {
+            rem = (rem << 1) ^ ((rem >> 11) * 0x1f25);
+        }
+        let bits = (version << 12) | rem;
+        for i in 0..18 {
+            let dark = (bits >> i) & 1 == 1;
+            let a = self.size - 11 + i % 3;
+            let b = i / 3;
+            self.set_function(a, b, dark);
+            self.set_function(b, a, dark);
+        }
+    }
+
+    /// Place codewords in the zigzag pattern, skipping function modules.
+    fn draw_codewords(&mut self, codewords: &[u8]) {
+        let size = self.size;
+        let total = codewords.len() * 8;
+        let mut i = 0;
+        let mut right = size as isize - 1;
+        while right >= 1 {
+            if right == 6 {
+                right = 5;
+            }
+            let upward = (right + 1) & 2 == 0;
+            for vert in 0..size {
+                let y = if upward { size - 1 - vert } else { vert };
+                for j in 0..2 {
+                    let x = right as usize - j;
+                    if !self.function[y * size + x] && i < total {
+                        self.modules[y * size + x] = (codewords[i / 8] >> (7 - i % 8)) & 1 == 1;
+                        i += 1;
+                    }
+                }
+            }
+            right -= 2;
+        }
+    }
+
+    fn apply_mask(&mut self, mask: u32) {
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let flip = match mask {
+                    0 => (x + y) % 2 == 0,
+                    1 => y % 2 == 0,
+                    2 => x % 3 == 0,
+                    3 => (x + y) % 3 == 0,
+                    4 => (x / 3 + y / 2) % 2 == 0,
+                    5 => x * y % 2 + x * y % 3 == 0,
+                    6 => (x * y % 2 + x * y % 3) % 2 == 0,
+                    _ => ((x + y) % 2 + x * y % 3) % 2 == 0,
+                };
+                let idx = y * self.size + x;
+                if flip && !self.function[idx] {
+                    self.modules[idx] = !self.modules[idx];
+                }
+            }
+        }
+    }
+
+    /// Mask penalty score (lower is better).
+    fn penalty(&self) -> usize {
+        let size = self.size;
+        let mut score = 0;
+
+        // Runs of five or more, and finder-like patterns, in rows and columns
+        for horizontal in [true, false] {
+            for a in 0..size {
+                let line: Vec<bool> = (0..size)
+                    .map(|b| {
+                        if horizontal {
+                            self.get(b, a)
+                        } else {
+                            self.get(a, b)
+                        }
+                    })
+                    .collect();
+                let mut run = 1;
+                for b in 1..=size {
+                    if b < size && line[b] == line[b - 1] {
+                        run += 1;
+                    } else {
+                        if run >= 5 {
+                            score += run - 2;
+                        }
+                        run = 1;
+                    }
+                }
+                const FINDER: [bool; 7] = [true, false, true, true, true, false, true];
+                for start in 0..size.saturating_sub(6) {
+                    if line[start..start + 7] == FINDER {
+                        let light = |from: isize| {
+                            (from..from + 4)
+                                .all(|i| i < 0 || i >= size as isize || !line[i as usize])
+                        };
+                        if light(start as isize - 4) || light(start as isize + 7) {
+                            score += 40;
+                        }
+                    }
+                }
+            }
+        }
+
+        // 2x2 blocks of one colour
+        for y in 0..size - 1 {
+            for x in 0..size - 1 {
+                let c = self.get(x, y);
+                if c == self.get(x + 1, y) && c == self.get(x, y + 1) && c == self.get(x + 1, y + 1)
+                {
+                    score += 3;
+                }
+            }
+        }
+
+        // Dark/light balance
+        let dark = self.modules.iter().filter(|&&m| m).count();
+        let percent = dark * 100 / self.modules.len();
+        score + percent.abs_diff(50) / 5 * 10
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reed_solomon() {
+        // "HELLO WORLD" at 1-M
+        let data = [
+            32, 91, 11, 120, 209, 114, 220, 77, 67, 64, 236, 17, 236, 17, 236, 17,
+        ];
+        assert_eq!(
+            rs_remainder(&data, &rs_divisor(10)),
+            [196, 35, 39, 119, 235, 215, 231, 226, 93, 23]
+        );
+        assert_eq!(capacity(1), 14);
+        assert_eq!(capacity(10), 213);
+    }
+}
@@ -3,89 +3,332 @@
 //! Handles USB serial communication with meshgrid/MeshCore devices.
 //! Supports COBS (Consistent Overhead Byte Stuffing) framing.
 
-use anyhow::{Context, Result};
+use anyhow::{Context as _, Result};
+use bytes::BytesMut;
+use std::collections::VecDeque;
+use std::io::Write as _;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
 use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 use tokio_serial::SerialPortBuilderExt;
 
-/// COBS encode a buffer
-/// Returns the encoded data (without the zero terminator)
-fn cobs_encode(data: &[u8]) -> Vec<u8> {
-    let mut encoded = Vec::with_capacity(data.len() + (data.len() / 254) + 1);
-    let mut code_ptr = 0;
-    encoded.push(0); // Placeholder for code byte
-    let mut code = 1u8;
-
-    for &byte in data {
-        if byte == 0 {
-            // Found zero - write code byte
-            encoded[code_ptr] = code;
-            code_ptr = encoded.len();
-            encoded.push(0); // Placeholder for next code byte
-            code = 1;
-        } else {
-            encoded.push(byte);
-            code = code.wrapping_add(1);
-            if code == 0xFF {
-                // Code byte full - write it
-                encoded[code_ptr] = code;
-                code_ptr = encoded.len();
-                encoded.push(0); // Placeholder for next code byte
-                code = 1;
-            }
-        }
-    }
+use crate::error::CliError;
+use crate::framing;
+pub(crate) use crate::framing::{cobs_decode_in_place, cobs_encode_into};
+use crate::portlock::PortLock;
+use crate::rng::{time_seed, Rng};
+use crate::session;
+
+/// Byte stream a device can be reached over (serial port, TCP, Unix socket).
+pub trait Transport: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Transport for T {}
+
+/// Wire trace file set by `--trace-serial`.
+static TRACE: Mutex<Option<std::fs::File>> = Mutex::new(None);
+
+/// Log every byte read from or written to a device to `path`, one line per
+/// transfer: time, port, direction (TX/RX), length, hex and printable text.
+pub fn trace_to(path: &str) -> Result<()> {
+    let file = std::fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {path}"))?;
+    *TRACE.lock().unwrap_or_else(|e| e.into_inner()) = Some(file);
+    Ok(())
+}
 
-    // Write final code byte
-    encoded[code_ptr] = code;
-    encoded
+fn trace_line(port: &str, direction: &str, data: &[u8]) -> String {
+    let text: String = data
+        .iter()
+        .map(|&b| {
+            if b.is_ascii_graphic() || b == b' ' {
+                b as char
+            } else {
+                '.'
+            }
+        })
+        .collect();
+    format!(
+        "{} {port} {direction} {:>5}  {}  |{text}|",
+        chrono::Utc::now().format("%Y-%m-%dT%H:%M:%S%.6fZ"),
+        data.len(),
+        hex::encode(data)
+    )
 }
 
-/// COBS decode a buffer
-/// Returns the decoded data, or None if invalid
-fn cobs_decode(data: &[u8]) -> Option<Vec<u8>> {
+fn trace(port: &str, direction: &str, data: &[u8]) {
     if data.is_empty() {
-        return Some(Vec::new());
+        return;
+    }
+    let mut trace = TRACE.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(file) = trace.as_mut() {
+        // Tracing is best effort; a full disk shouldn't break the command
+        let _ = writeln!(file, "{}", trace_line(port, direction, data));
     }
+}
 
-    let mut decoded = Vec::with_capacity(data.len());
-    let mut i = 0;
+/// Transport that copies everything passing through it to the wire trace.
+struct Traced {
+    inner: Box<dyn Transport>,
+    port: String,
+}
 
-    while i < data.len() {
-        let code = data[i];
-        if code == 0 {
-            return None; // Invalid
+impl AsyncRead for Traced {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = result {
+            trace(&self.port, "RX", &buf.filled()[before..]);
         }
-        i += 1;
+        result
+    }
+}
 
-        // Copy data bytes
-        for _ in 1..code {
-            if i >= data.len() {
-                break;
-            }
-            decoded.push(data[i]);
-            i += 1;
+impl AsyncWrite for Traced {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let result = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = result {
+            trace(&self.port, "TX", &buf[..n]);
         }
+        result
+    }
 
-        // Insert zero if not at end
-        if code < 0xFF && i < data.len() {
-            decoded.push(0);
-        }
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Serial port that keeps its port lock for as long as it is open.
+struct Locked {
+    inner: tokio_serial::SerialStream,
+    _lock: Option<PortLock>,
+}
+
+impl AsyncRead for Locked {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Locked {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
     }
 
-    Some(decoded)
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Bytes requested from the transport per read.
+const READ_CHUNK: usize = 4096;
+
+/// Data between frame delimiters that isn't valid COBS, such as the tail of a
+/// frame sent before the port was opened.
+#[derive(Debug, thiserror::Error)]
+#[error("Invalid COBS frame")]
+pub struct InvalidFrame;
+
+/// Monitor events the firmware prints as text lines, between frames.
+const EVENT_PREFIXES: [&[u8]; 4] = [b"MSG ", b"ADV ", b"ACK ", b"WPT "];
+
+/// Event lines kept aside while commands run; older ones are dropped.
+const MAX_HELD_EVENTS: usize = 256;
+
+/// How long the handshake waits for each PING to be answered.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_millis(300);
+
+/// Longest `clear` keeps draining a talkative device.
+const MAX_DRAIN: Duration = Duration::from_millis(500);
+
+/// Outcome of the PING handshake.
+#[derive(Debug, PartialEq)]
+enum Handshake {
+    /// The answer carried this attempt's nonce (or came on the first try)
+    Matched,
+    /// An untagged answer after a retry, which may be to an earlier attempt
+    Unmatched,
+    /// No answer: firmware without PING, or still booting
+    None,
+}
+
+/// Whether `frame` answers `PING <nonce>`: `Some(true)` when it echoes the
+/// nonce, `Some(false)` for an untagged PONG or the `ERR unknown command`
+/// of firmware without PING, `None` for anything else.
+fn ping_answer(frame: &[u8], nonce: &str) -> Option<bool> {
+    let text = String::from_utf8_lossy(frame);
+    if let Some(rest) = text.strip_prefix("PONG") {
+        let echoed = rest.trim();
+        return match echoed {
+            "" => Some(false),
+            _ if echoed == nonce => Some(true),
+            _ => None, // Answer to another attempt
+        };
+    }
+    let err = text.strip_prefix("ERR")?.trim().to_ascii_lowercase();
+    err.starts_with("unknown command").then_some(false)
 }
 
 /// Serial port connection.
+///
+/// Frames are split off the read buffer and decoded in place, and outgoing
+/// frames are encoded into a reused write buffer, so steady traffic doesn't
+/// allocate per frame.
 pub struct SerialPort {
-    port: tokio_serial::SerialStream,
-    read_buf: Vec<u8>,
+    port: Box<dyn Transport>,
+    read_buf: BytesMut,
+    write_buf: BytesMut,
+    /// Whether the device has answered since the last timeout, so only
+    /// already-buffered data needs draining before the next command.
+    synced: bool,
+    /// Monitor event lines that arrived around command responses, for
+    /// `read_line`.
+    events: VecDeque<String>,
+}
+
+/// Why a serial port could not be opened, for [`crate::diagnose`].
+#[derive(Debug, thiserror::Error)]
+#[error("{error}")]
+pub struct OpenError {
+    pub port: String,
+    pub error: std::io::Error,
+}
+
+/// Classify a failure to open `port_name`, keeping the cause.
+fn open_error(port_name: &str, e: tokio_serial::Error) -> anyhow::Error {
+    use std::io::ErrorKind;
+    let kind = match e.kind {
+        // serialport reports a port locked by another program as NoDevice
+        tokio_serial::ErrorKind::NoDevice
+            if cfg!(unix) && std::path::Path::new(port_name).exists() =>
+        {
+            ErrorKind::ResourceBusy
+        }
+        tokio_serial::ErrorKind::NoDevice => ErrorKind::NotFound,
+        // Windows denies access to a COM port another program has open
+        tokio_serial::ErrorKind::Io(ErrorKind::PermissionDenied) if cfg!(windows) => {
+            ErrorKind::ResourceBusy
+        }
+        tokio_serial::ErrorKind::Io(kind) => kind,
+        _ => ErrorKind::Other,
+    };
+    let classified = if kind == ErrorKind::NotFound {
+        CliError::PortNotFound(format!("Serial port {port_name} not found"))
+    } else {
+        CliError::ConnectionFailed(format!("Failed to open serial port {port_name}"))
+    };
+    anyhow::Error::new(OpenError {
+        port: port_name.to_string(),
+        error: std::io::Error::new(kind, e.description),
+    })
+    .context(classified)
 }
 
 impl SerialPort {
-    /// Open a serial port connection.
+    /// Open a device connection.
+    ///
+    /// Besides serial device names, `tcp://host:port` and (on Unix)
+    /// `unix:/path/to/socket` connect to a device shared over the network
+    /// or by another meshgrid process, and `sim://node3` to a node of
+    /// `meshgrid-cli sim`.
     pub async fn open(port_name: &str, baud_rate: u32) -> Result<Self> {
+        let port: Box<dyn Transport> = if let Some(replayed) = session::replayed(port_name)? {
+            replayed
+        } else if let Some(addr) = port_name.strip_prefix("tcp://") {
+            let mut stream = tokio::net::TcpStream::connect(addr).await.map_err(|e| {
+                CliError::ConnectionFailed(format!("Failed to connect to {addr}: {e}"))
+            })?;
+            stream.set_nodelay(true)?;
+            if let Some(token) = crate::proxy::token_for(port_name) {
+                crate::proxy::send_token(&mut stream, &token).await?;
+            }
+            Box::new(stream)
+        } else if let Some(path) = port_name.strip_prefix("unix:") {
+            Box::new(Self::connect_unix(path).await?)
+        } else if let Some(node) = port_name.strip_prefix("sim://") {
+            let addr = crate::sim::address(node)?;
+            let stream = tokio::net::TcpStream::connect(&addr).await.map_err(|e| {
+                CliError::ConnectionFailed(format!(
+                    "Failed to connect to simulated node {node} at {addr}: {e} \
+                     (is `meshgrid-cli sim` still running?)"
+                ))
+            })?;
+            stream.set_nodelay(true)?;
+            Box::new(stream)
+        } else {
+            // Locked first, so a port held by another meshgrid-cli is
+            // reported as such rather than as an open error
+            let lock = PortLock::acquire(&normalize_port(port_name))?;
+            Box::new(Locked {
+                inner: Self::open_serial(port_name, baud_rate).await?,
+                _lock: lock,
+            })
+        };
+        let port = session::recorded(port_name, port);
+        let tracing = TRACE.lock().unwrap_or_else(|e| e.into_inner()).is_some();
+        let port: Box<dyn Transport> = if tracing {
+            Box::new(Traced {
+                inner: port,
+                port: port_name.to_string(),
+            })
+        } else {
+            port
+        };
+
+        Ok(Self {
+            port,
+            read_buf: BytesMut::with_capacity(READ_CHUNK),
+            write_buf: BytesMut::with_capacity(256),
+            synced: false,
+            events: VecDeque::new(),
+        })
+    }
+
+    #[cfg(unix)]
+    async fn connect_unix(path: &str) -> Result<tokio::net::UnixStream> {
+        tokio::net::UnixStream::connect(path).await.map_err(|e| {
+            CliError::ConnectionFailed(format!("Failed to connect to socket: {path}: {e}")).into()
+        })
+    }
+
+    #[cfg(not(unix))]
+    async fn connect_unix(path: &str) -> Result<tokio::net::TcpStream> {
+        anyhow::bail!("Unix sockets are not supported on this platform: {path}")
+    }
+
+    /// Open a physical serial port.
+    async fn open_serial(port_name: &str, baud_rate: u32) -> Result<tokio_serial::SerialStream> {
         use tokio_serial::SerialPort as _;
 
+        let port_name = &normalize_port(port_name);
         let mut port = tokio_serial::new(port_name, baud_rate)
             .data_bits(tokio_serial::DataBits::Eight)
             .stop_bits(tokio_serial::StopBits::One)
@@ -93,29 +336,53 @@ impl SerialPort {
             .flow_control(tokio_serial::FlowControl::None)
             .timeout(Duration::from_millis(100))
             .open_native_async()
-            .with_context(|| format!("Failed to open serial port: {port_name}"))?;
+            .map_err(|e| open_error(port_name, e))?;
 
-        // ESP32-S3 native USB (ttyACM) - DON'T toggle DTR/RTS as it triggers reset!
         // The auto-reset circuit uses DTR+RTS to enter bootloader or reset.
-        // Set both HIGH to avoid triggering reset.
-        let is_native_usb = port_name.contains("ttyACM") || port_name.contains("cu.usb");
-
-        if is_native_usb {
-            // Set DTR and RTS high to avoid reset (low triggers reset on ESP32)
-            let _ = port.write_data_terminal_ready(true);
-            let _ = port.write_request_to_send(true);
-            // ESP32-S3 native USB needs extra time after boot
-            // The firmware has a 2s delay + boot messages before it's ready
-            tokio::time::sleep(Duration::from_millis(200)).await;
-        } else {
-            // Small delay for USB CDC to stabilize
-            tokio::time::sleep(Duration::from_millis(50)).await;
+        let chip = port_chip(port_name);
+        match chip {
+            // ESP32-S3 native USB - DON'T toggle DTR/RTS as it triggers reset!
+            // Set both HIGH to avoid triggering reset.
+            Some(UsbChip::Esp32Usb) => {
+                let _ = port.write_data_terminal_ready(true);
+                let _ = port.write_request_to_send(true);
+            }
+            // The Windows CH340 driver applies RTS only when DTR is written,
+            // so release RTS first and then write DTR; the other order leaves
+            // RTS asserted alone, which holds EN low and resets the board.
+            Some(UsbChip::Ch340) if cfg!(windows) => {
+                let _ = port.write_request_to_send(false);
+                let _ = port.write_data_terminal_ready(false);
+            }
+            _ => {}
         }
+        // Let USB CDC settle; ESP32-S3 native USB needs longer, as its
+        // firmware drops input for a moment after the port opens
+        let settle = if matches!(chip, Some(UsbChip::Esp32Usb)) {
+            200
+        } else {
+            50
+        };
+        tokio::time::sleep(Duration::from_millis(settle)).await;
 
-        Ok(Self {
+        Ok(port)
+    }
+
+    /// Port over an in-memory stream to a device that has already answered.
+    #[cfg(test)]
+    pub(crate) fn from_transport(port: Box<dyn Transport>) -> Self {
+        Self {
             port,
-            read_buf: Vec::with_capacity(4096),
-        })
+            read_buf: BytesMut::new(),
+            write_buf: BytesMut::new(),
+            synced: true,
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Take the underlying byte stream (e.g., to relay it through a proxy).
+    pub fn into_transport(self) -> Box<dyn Transport> {
+        self.port
     }
 
     /// Write raw bytes to the serial port.
@@ -126,28 +393,35 @@ impl SerialPort {
         Ok(())
     }
 
-    /// Read a line from the serial port.
+    /// Read a line from the serial port, starting with event lines kept
+    /// aside while a command ran.
     pub async fn read_line(&mut self) -> Result<String> {
-        use tokio::io::AsyncReadExt;
-
+        if let Some(line) = self.events.pop_front() {
+            return Ok(line);
+        }
         loop {
             // Check if we have a complete line in buffer
             if let Some(pos) = self.read_buf.iter().position(|&b| b == b'\n') {
-                let line: Vec<u8> = self.read_buf.drain(..=pos).collect();
-                let s = String::from_utf8_lossy(&line[..line.len() - 1])
-                    .trim_end()
-                    .to_string();
+                let line = self.read_buf.split_to(pos + 1);
+                let s = String::from_utf8_lossy(&line[..pos]).trim_end().to_string();
                 return Ok(s);
             }
 
-            // Read more data
-            let mut tmp = [0u8; 256];
-            let n = self.port.read(&mut tmp).await?;
-            if n == 0 {
-                anyhow::bail!("EOF on serial port");
-            }
-            self.read_buf.extend_from_slice(&tmp[..n]);
+            self.fill().await?;
+        }
+    }
+
+    /// Read more data into the read buffer.
+    async fn fill(&mut self) -> Result<()> {
+        use tokio::io::AsyncReadExt;
+
+        // Reclaims the space of frames already split off and dropped
+        self.read_buf.reserve(READ_CHUNK);
+        let n = self.port.read_buf(&mut self.read_buf).await?;
+        if n == 0 {
+            anyhow::bail!("EOF on serial port");
         }
+        Ok(())
     }
 
     /// Read a line with timeout.
@@ -159,9 +433,14 @@ impl SerialPort {
         }
     }
 
-    /// Read raw bytes (up to buf size).
+    /// Read raw bytes (up to buf size), starting with any already buffered.
     pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
         use tokio::io::AsyncReadExt;
+        if !self.read_buf.is_empty() {
+            let n = buf.len().min(self.read_buf.len());
+            buf[..n].copy_from_slice(&self.read_buf.split_to(n));
+            return Ok(n);
+        }
         let n = self.port.read(buf).await?;
         Ok(n)
     }
@@ -180,105 +459,336 @@ impl SerialPort {
     }
 
     /// Clear input/output buffers and wait for device to be ready.
+    ///
+    /// Once the device has answered, this only drops data that is already
+    /// waiting, keeping monitor event lines for `read_line`. Otherwise
+    /// (first command, or after a timeout) it pings the device until it
+    /// answers, then drains until the line goes quiet so a late answer to an
+    /// earlier attempt isn't left for the next command.
     pub async fn clear(&mut self) -> Result<()> {
-        // Clear read buffer
-        self.read_buf.clear();
+        if self.synced {
+            // Keep the monitor events in what's waiting
+            while let Ok(Ok(())) = tokio::time::timeout(Duration::ZERO, self.fill()).await {}
+            self.hold_all_event_lines();
+            self.read_buf.clear();
+            return Ok(());
+        }
 
-        // Drain any pending data (boot messages, etc.)
-        // Use longer timeout to catch all buffered output
+        self.read_buf.clear();
         let mut buf = [0u8; 1024];
-        let start = std::time::Instant::now();
-        let max_drain_time = Duration::from_millis(500);
 
-        while start.elapsed() < max_drain_time {
-            match self
-                .read_timeout(&mut buf, Duration::from_millis(100))
-                .await
-            {
+        // Firmware that doesn't answer PING gets the old drain; an answer that
+        // may belong to an earlier attempt waits out that attempt's reply
+        let quiet = match self.handshake().await? {
+            Handshake::Matched => Duration::from_millis(20),
+            Handshake::Unmatched => HANDSHAKE_TIMEOUT,
+            Handshake::None => Duration::from_millis(100),
+        };
+        let start = std::time::Instant::now();
+        while start.elapsed() < MAX_DRAIN {
+            match self.read_timeout(&mut buf, quiet).await {
                 Ok(Some(n)) if n > 0 => {} // More data, keep draining
                 _ => break,                // Timeout or error, buffer is empty
             }
         }
-
+        self.read_buf.clear();
+        self.synced = true;
         Ok(())
     }
 
+    /// Send PING, tagged with a nonce the firmware echoes, and skip frames
+    /// (boot messages, stale responses, answers to earlier attempts) until
+    /// the device answers this one.
+    async fn handshake(&mut self) -> Result<Handshake> {
+        const ATTEMPTS: usize = 3;
+
+        let mut rng = Rng::new(time_seed());
+        for attempt in 0..ATTEMPTS {
+            let nonce = format!("{:08x}", rng.next_u64() as u32);
+            self.write_cobs_frame(format!("PING {nonce}").as_bytes())
+                .await?;
+            let deadline = tokio::time::Instant::now() + HANDSHAKE_TIMEOUT;
+            loop {
+                let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+                match tokio::time::timeout(remaining, self.read_cobs_frame()).await {
+                    Ok(Ok(frame)) => match ping_answer(&frame, &nonce) {
+                        Some(true) => return Ok(Handshake::Matched),
+                        // Firmware that doesn't echo: after a retry this may
+                        // be the answer to an earlier attempt
+                        Some(false) if attempt == 0 => return Ok(Handshake::Matched),
+                        Some(false) => return Ok(Handshake::Unmatched),
+                        None => {} // Boot message or stale response
+                    },
+                    // Partial frame from before we connected
+                    Ok(Err(e)) if e.is::<InvalidFrame>() => {}
+                    Ok(Err(e)) => return Err(e),
+                    Err(_) => break, // Try again
+                }
+            }
+        }
+        Ok(Handshake::None)
+    }
+
     /// Write a COBS-encoded frame (with zero terminator)
     pub async fn write_cobs_frame(&mut self, data: &[u8]) -> Result<()> {
         use tokio::io::AsyncWriteExt;
-        let encoded = cobs_encode(data);
-        self.port.write_all(&encoded).await?;
-        self.port.write_all(&[0]).await?; // COBS frame delimiter
+        self.write_buf.clear();
+        cobs_encode_into(data, &mut self.write_buf);
+        self.port.write_all(&self.write_buf).await?;
         self.port.flush().await?;
         Ok(())
     }
 
-    /// Read a COBS-encoded frame (blocking until zero byte)
-    pub async fn read_cobs_frame(&mut self) -> Result<Vec<u8>> {
-        use tokio::io::AsyncReadExt;
+    /// Move complete event lines at the front of the read buffer aside, so
+    /// they aren't taken for the start of the next frame.
+    fn hold_event_lines(&mut self) {
+        while EVENT_PREFIXES.iter().any(|p| self.read_buf.starts_with(p)) {
+            let Some(end) = self.read_buf.iter().position(|&b| b == b'\n') else {
+                break;
+            };
+            let line = self.read_buf.split_to(end + 1);
+            let line = String::from_utf8_lossy(&line[..end]).trim_end().to_string();
+            if self.events.len() == MAX_HELD_EVENTS {
+                self.events.pop_front();
+            }
+            self.events.push_back(line);
+        }
+    }
 
-        let mut encoded = Vec::new();
+    /// Hold every event line in the read buffer, skipping the frames and
+    /// partial data between them.
+    fn hold_all_event_lines(&mut self) {
         loop {
-            // Check if we have a zero byte in buffer
-            if let Some(pos) = self.read_buf.iter().position(|&b| b == 0) {
-                encoded.extend_from_slice(&self.read_buf[..pos]);
-                self.read_buf.drain(..=pos);
-                break;
+            self.hold_event_lines();
+            let next = self.read_buf.iter().position(|&b| b == 0 || b == b'\n');
+            match next {
+                Some(pos) => drop(self.read_buf.split_to(pos + 1)),
+                None => break,
             }
+        }
+    }
 
-            // Read more data
-            let mut tmp = [0u8; 256];
-            let n = self.port.read(&mut tmp).await?;
-            if n == 0 {
-                anyhow::bail!("EOF on serial port");
+    /// Split the next complete frame off the read buffer and decode it.
+    fn next_frame(&mut self) -> Option<Result<BytesMut>> {
+        self.hold_event_lines();
+        let frame = framing::split_frame(&mut self.read_buf)?;
+        Some(frame.ok_or_else(|| InvalidFrame.into()))
+    }
+
+    /// Read a COBS-encoded frame (blocking until zero byte)
+    pub async fn read_cobs_frame(&mut self) -> Result<BytesMut> {
+        loop {
+            if let Some(frame) = self.next_frame() {
+                return frame;
             }
-            self.read_buf.extend_from_slice(&tmp[..n]);
+            self.fill().await?;
         }
+    }
 
-        // Decode COBS
-        cobs_decode(&encoded).ok_or_else(|| anyhow::anyhow!("Invalid COBS frame"))
+    /// Read a COBS frame, giving up only if no data arrives for `idle`, so a
+    /// large frame arriving slowly isn't cut off.
+    pub async fn read_cobs_frame_idle(&mut self, idle: Duration) -> Result<Option<BytesMut>> {
+        loop {
+            if let Some(frame) = self.next_frame() {
+                return frame.map(Some);
+            }
+            match tokio::time::timeout(idle, self.fill()).await {
+                Ok(filled) => filled?,
+                Err(_) => {
+                    // The next command re-checks that the device is listening
+                    self.synced = false;
+                    return Ok(None);
+                }
+            }
+        }
     }
 
     /// Read a COBS frame with timeout
-    pub async fn read_cobs_frame_timeout(&mut self, timeout: Duration) -> Result<Option<Vec<u8>>> {
+    pub async fn read_cobs_frame_timeout(&mut self, timeout: Duration) -> Result<Option<BytesMut>> {
         match tokio::time::timeout(timeout, self.read_cobs_frame()).await {
             Ok(Ok(frame)) => Ok(Some(frame)),
             Ok(Err(e)) => Err(e),
-            Err(_) => Ok(None), // Timeout
+            Err(_) => {
+                // The next command re-checks that the device is listening
+                self.synced = false;
+                Ok(None)
+            }
         }
     }
 }
 
+/// `COM12` for `com12`, `\\.\COM12` and `//./COM12`; other names unchanged.
+///
+/// serialport adds the `\\.\` device prefix itself, so a name that already
+/// has it (needed elsewhere for COM10 and up) would not open.
+pub fn normalize_port(name: &str) -> String {
+    let bare = name
+        .strip_prefix(r"\\.\")
+        .or_else(|| name.strip_prefix("//./"))
+        .unwrap_or(name);
+    match bare.get(..3) {
+        Some(com)
+            if com.eq_ignore_ascii_case("com")
+                && bare.len() > 3
+                && bare[3..].bytes().all(|b| b.is_ascii_digit()) =>
+        {
+            format!("COM{}", &bare[3..])
+        }
+        _ => name.to_string(),
+    }
+}
+
+/// USB serial chips on supported boards.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsbChip {
+    /// ESP32-S3 native USB (T3S3, Heltec V3/V4, Station G2)
+    Esp32Usb,
+    /// Silicon Labs CP210x (common on ESP32 dev boards)
+    Cp210x,
+    /// CH340 (Heltec, some clones)
+    Ch340,
+    /// Seeed devices
+    Seeed,
+    /// Nordic Semiconductor (RAK4631 has nRF52840)
+    Nrf52,
+}
+
+impl UsbChip {
+    pub const ALL: [Self; 5] = [
+        Self::Esp32Usb,
+        Self::Cp210x,
+        Self::Ch340,
+        Self::Seeed,
+        Self::Nrf52,
+    ];
+
+    /// USB vendor ID, and product ID unless any product of the vendor matches.
+    pub fn ids(self) -> (u16, Option<u16>) {
+        match self {
+            Self::Esp32Usb => (0x303a, None),
+            Self::Cp210x => (0x10c4, Some(0xea60)),
+            Self::Ch340 => (0x1a86, Some(0x7523)),
+            Self::Seeed => (0x239a, None),
+            Self::Nrf52 => (0x1915, None),
+        }
+    }
+
+    pub fn from_ids(vid: u16, pid: u16) -> Option<Self> {
+        Self::ALL.into_iter().find(|chip| match chip.ids() {
+            (v, Some(p)) => (v, p) == (vid, pid),
+            (v, None) => v == vid,
+        })
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::Esp32Usb => "ESP32-S3 native USB",
+            Self::Cp210x => "CP210x USB-UART",
+            Self::Ch340 => "CH340 USB-UART",
+            Self::Seeed => "Seeed",
+            Self::Nrf52 => "nRF52840 native USB",
+        }
+    }
+}
+
+/// Chip behind a serial port, from its USB IDs or, when the port isn't
+/// listed with them, its name.
+fn port_chip(port_name: &str) -> Option<UsbChip> {
+    let listed = serialport::available_ports()
+        .unwrap_or_default()
+        .into_iter()
+        .find(|p| p.port_name.eq_ignore_ascii_case(port_name));
+    match listed.map(|p| p.port_type) {
+        Some(serialport::SerialPortType::UsbPort(info)) => UsbChip::from_ids(info.vid, info.pid),
+        _ if port_name.contains("ttyACM") || port_name.contains("cu.usb") => {
+            Some(UsbChip::Esp32Usb)
+        }
+        _ => None,
+    }
+}
+
 /// Auto-detect a connected meshgrid/MeshCore device.
 pub fn detect_device() -> Result<Option<String>> {
+    Ok(detect_devices()?.into_iter().next())
+}
+
+/// All connected ports that look like meshgrid/MeshCore devices.
+///
+/// Some Windows drivers list ports without USB IDs; when none match, those
+/// ports are asked for a PING instead.
+pub fn detect_devices() -> Result<Vec<String>> {
+    let found: Vec<String> = detect_usb_devices()?
+        .into_iter()
+        .map(|(port, _)| port)
+        .collect();
+    if !found.is_empty() || !cfg!(windows) {
+        return Ok(found);
+    }
+    Ok(serialport::available_ports()?
+        .into_iter()
+        .filter(|p| {
+            matches!(
+                p.port_type,
+                serialport::SerialPortType::Unknown | serialport::SerialPortType::PciPort
+            )
+        })
+        .map(|p| p.port_name)
+        .filter(|name| probe(name))
+        .collect())
+}
+
+/// Whether a meshgrid device answers PING on `port_name`.
+fn probe(port_name: &str) -> bool {
+    use std::io::{Read, Write};
+    const PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+
+    tracing::debug!("Probing {port_name}");
+    let Ok(mut port) = serialport::new(port_name, 115_200)
+        .timeout(Duration::from_millis(100))
+        .dtr_on_open(false)
+        .open()
+    else {
+        return false;
+    };
+    let mut ping = BytesMut::new();
+    cobs_encode_into(b"PING", &mut ping);
+    if port.write_all(&ping).is_err() {
+        return false;
+    }
+    let start = std::time::Instant::now();
+    let mut received = Vec::new();
+    let mut buf = [0u8; 256];
+    while start.elapsed() < PROBE_TIMEOUT {
+        match port.read(&mut buf) {
+            Ok(n) => received.extend_from_slice(&buf[..n]),
+            Err(e) if e.kind() == std::io::ErrorKind::TimedOut => {}
+            Err(_) => return false,
+        }
+        for frame in received.split_mut(|&b| b == 0) {
+            if let Some(len) = cobs_decode_in_place(frame) {
+                if frame[..len].starts_with(b"PONG") {
+                    return true;
+                }
+            }
+        }
+    }
+    false
+}
+
+/// Known USB devices with their USB serial numbers, when reported.
+pub fn detect_usb_devices() -> Result<Vec<(String, Option<String>)>> {
     let ports = serialport::available_ports()?;
+    let mut found = Vec::new();
 
     for port in ports {
-        if let serialport::SerialPortType::UsbPort(info) = port.port_type {
-            // ESP32-S3 native USB (T3S3, Heltec V3/V4, Station G2)
-            if info.vid == 0x303a {
-                return Ok(Some(port.port_name));
-            }
-            // Silicon Labs CP210x (common on ESP32 dev boards)
-            if info.vid == 0x10c4 && info.pid == 0xea60 {
-                return Ok(Some(port.port_name));
-            }
-            // CH340 (Heltec, some clones)
-            if info.vid == 0x1a86 && info.pid == 0x7523 {
-                return Ok(Some(port.port_name));
-            }
-            // Seeed devices
-            if info.vid == 0x239a {
-                return Ok(Some(port.port_name));
-            }
-            // Nordic Semiconductor (RAK4631 has nRF52840)
-            if info.vid == 0x1915 {
-                return Ok(Some(port.port_name));
+        if let serialport::SerialPortType::UsbPort(info) = &port.port_type {
+            if UsbChip::from_ids(info.vid, info.pid).is_some() {
+                found.push((port.port_name.clone(), info.serial_number.clone()));
             }
         }
     }
 
-    Ok(None)
+    Ok(found)
 }
 
 #[cfg(test)]
@@ -290,4 +800,115 @@ mod tests {
         // Should not panic even if no devices connected
         let _ = detect_device();
     }
+
+    #[test]
+    fn test_normalize_port() {
+        assert_eq!(normalize_port("com12"), "COM12");
+        assert_eq!(normalize_port(r"\\.\COM12"), "COM12");
+        assert_eq!(normalize_port("//./com3"), "COM3");
+        assert_eq!(normalize_port("/dev/ttyUSB0"), "/dev/ttyUSB0");
+        assert_eq!(normalize_port("COMX"), "COMX");
+        assert_eq!(UsbChip::from_ids(0x1a86, 0x7523), Some(UsbChip::Ch340));
+    }
+
+    #[test]
+    fn test_cobs_round_trip() {
+        let long: Vec<u8> = (0..600).map(|i| (i % 7) as u8).collect();
+        for data in [&b""[..], b"\0", b"PING", b"a\0\0b", &[0xaa; 300], &long] {
+            let mut encoded = BytesMut::new();
+            cobs_encode_into(data, &mut encoded);
+            assert_eq!(
+                encoded.iter().position(|&b| b == 0),
+                Some(encoded.len() - 1)
+            );
+            let end = encoded.len() - 1;
+            let len = cobs_decode_in_place(&mut encoded[..end]).unwrap();
+            assert_eq!(&encoded[..len], data);
+        }
+    }
+
+    #[test]
+    fn test_trace_line() {
+        let line = trace_line("/dev/ttyUSB0", "TX", b"\x05PING\x00");
+        assert!(line.ends_with(" /dev/ttyUSB0 TX     6  0550494e4700  |.PING.|"));
+    }
+
+    #[tokio::test]
+    async fn test_handshake_skips_stale_frames() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let (host, mut device) = tokio::io::duplex(1024);
+        let mut port = SerialPort {
+            port: Box::new(host),
+            read_buf: BytesMut::new(),
+            write_buf: BytesMut::new(),
+            synced: false,
+            events: VecDeque::new(),
+        };
+        // The tail of a frame, boot log, stale responses and the answer to
+        // another PING are waiting before the real PONG
+        let mut frames = BytesMut::from(&b"\x09ab\x00"[..]);
+        for frame in [&b"boot"[..], b"OK old", b"ERR Bad args", b"PONG 0badcafe"] {
+            cobs_encode_into(frame, &mut frames);
+        }
+        device.write_all(&frames).await.unwrap();
+        let handshake = tokio::spawn(async move {
+            port.clear().await.unwrap();
+            port
+        });
+
+        let mut sent = [0u8; 32];
+        let n = device.read(&mut sent).await.unwrap();
+        let len = cobs_decode_in_place(&mut sent[..n - 1]).unwrap();
+        let ping = std::str::from_utf8(&sent[..len]).unwrap();
+        let nonce = ping.strip_prefix("PING ").unwrap();
+        let mut frames = BytesMut::new();
+        cobs_encode_into(format!("PONG {nonce}").as_bytes(), &mut frames);
+        device.write_all(&frames).await.unwrap();
+        let mut port = handshake.await.unwrap();
+        assert!(port.synced);
+
+        // Once synced, clear() doesn't ping again
+        port.clear().await.unwrap();
+        port.write_cobs_frame(b"INFO").await.unwrap();
+        let n = device.read(&mut sent).await.unwrap();
+        let len = cobs_decode_in_place(&mut sent[..n - 1]).unwrap();
+        assert_eq!(&sent[..len], b"INFO");
+
+        assert_eq!(ping_answer(b"PONG 1234", "1234"), Some(true));
+        assert_eq!(ping_answer(b"PONG", "1234"), Some(false));
+        assert_eq!(ping_answer(b"ERR Unknown command", "1234"), Some(false));
+        assert_eq!(ping_answer(b"PONG 5678", "1234"), None);
+        assert_eq!(ping_answer(b"ERR Unknown node x", "1234"), None);
+    }
+
+    #[tokio::test]
+    async fn test_event_lines_around_responses() {
+        use tokio::io::AsyncWriteExt;
+
+        let (host, mut device) = tokio::io::duplex(1024);
+        let mut port = SerialPort {
+            port: Box::new(host),
+            read_buf: BytesMut::new(),
+            write_buf: BytesMut::new(),
+            synced: true,
+            events: VecDeque::new(),
+        };
+        // Monitor events printed before and after the answer to a command
+        let mut data = BytesMut::from(&b"ADV 0x11 -70 hilltop\n"[..]);
+        cobs_encode_into(b"[]", &mut data);
+        data.extend_from_slice(b"MSG a1b2 * -60 5 hi\n");
+        cobs_encode_into(b"OK stale", &mut data);
+        device.write_all(&data).await.unwrap();
+
+        let frame = port
+            .read_cobs_frame_idle(Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(frame.as_deref(), Some(&b"[]"[..]));
+        port.clear().await.unwrap();
+        assert!(port.read_buf.is_empty());
+        assert_eq!(port.read_line().await.unwrap(), "ADV 0x11 -70 hilltop");
+        assert_eq!(port.read_line().await.unwrap(), "MSG a1b2 * -60 5 hi");
+    }
 }
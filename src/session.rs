@@ -0,0 +1,602 @@
+//! Session recording and replay.
+//!
+//! `--record session.json` saves the command line, every byte exchanged
+//! with the device and the command's result. `replay-session` runs the same
+//! command again with each connection served from the recording: a frame
+//! the CLI writes releases what the device sent after the recorded one, so
+//! a bug seen on someone else's board can be reproduced without it.
+//!
+//! PINs, passwords and channel keys are replaced with `<redacted>` on the
+//! command line and in frames sent to the device. What the device sent is
+//! kept as is.
+
+use crate::error::{CliError, ErrorReport};
+use crate::serial::{cobs_decode_in_place, cobs_encode_into, Transport};
+use anyhow::{bail, Context as _, Result};
+use bytes::BytesMut;
+use clap::parser::ValueSource;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Instant;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+const VERSION: u32 = 1;
+const REDACTED: &str = "<redacted>";
+
+/// A recorded session file.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Session {
+    pub version: u32,
+    pub cli_version: String,
+    pub started: String,
+    /// Command line without the program name
+    pub args: Vec<String>,
+    pub events: Vec<Event>,
+    pub result: Option<Outcome>,
+}
+
+/// Something that happened on a connection, `ms` after the start.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Event {
+    pub ms: u64,
+    /// Connection number, in the order they were opened
+    pub conn: usize,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventKind {
+    /// Connection opened to a port
+    Open(String),
+    /// COBS frame sent to the device (hex)
+    Tx(String),
+    /// Bytes received from the device (hex)
+    Rx(String),
+}
+
+/// How the command ended.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Outcome {
+    pub code: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<serde_json::Value>,
+}
+
+impl Outcome {
+    fn of(result: &Result<()>) -> Self {
+        match result {
+            Ok(()) => Self {
+                code: 0,
+                error: None,
+            },
+            Err(e) => {
+                let report = ErrorReport::new(e);
+                Self {
+                    code: report.code,
+                    error: serde_json::to_value(&report).ok(),
+                }
+            }
+        }
+    }
+}
+
+impl Session {
+    pub fn load(path: &str) -> Result<Self> {
+        let content =
+            std::fs::read_to_string(path).with_context(|| format!("Failed to read {path}"))?;
+        let session: Self = serde_json::from_str(&content)
+            .with_context(|| format!("{path} is not a session recording"))?;
+        if session.version != VERSION {
+            bail!(CliError::InvalidArgs(format!(
+                "{path} is a version {} session; this meshgrid-cli reads version {VERSION}",
+                session.version
+            )));
+        }
+        Ok(session)
+    }
+
+    /// Command line to replay: the recorded one, pointed at the recorded port
+    /// when that was auto-detected.
+    pub fn replay_args(&self) -> Vec<String> {
+        let has_port = self
+            .args
+            .iter()
+            .any(|a| a == "-p" || a == "--port" || a.starts_with("--port="));
+        let first_port = self.events.iter().find_map(|e| match &e.kind {
+            EventKind::Open(port) => Some(port.clone()),
+            _ => None,
+        });
+        let mut args = vec!["meshgrid-cli".to_string()];
+        if let (false, Some(port)) = (has_port, first_port) {
+            args.extend(["-p".to_string(), port]);
+        }
+        args.extend(self.args.iter().cloned());
+        args
+    }
+}
+
+/// Command line with secret values replaced and without `--record`, from
+/// the parsed arguments.
+pub fn redacted_args(matches: &clap::ArgMatches) -> Vec<String> {
+    const SECRETS: [&str; 7] = [
+        "pin",
+        "password",
+        "pass",
+        "passphrase",
+        "psk",
+        "token",
+        "secret",
+    ];
+    // Indices of subcommand arguments count from the subcommand, so the
+    // values are matched instead
+    let mut secrets: Vec<String> = Vec::new();
+    let mut matches = Some(matches);
+    while let Some(m) = matches {
+        for id in m.ids() {
+            if !id.as_str().split('_').any(|w| SECRETS.contains(&w))
+                || m.value_source(id.as_str()) != Some(ValueSource::CommandLine)
+            {
+                continue;
+            }
+            if let Ok(Some(values)) = m.try_get_raw(id.as_str()) {
+                secrets.extend(values.map(|v| v.to_string_lossy().into_owned()));
+            }
+        }
+        matches = m.subcommand().map(|(_, sub)| sub);
+    }
+    let mut args: Vec<String> = std::env::args().collect();
+    for arg in args.iter_mut().skip(1) {
+        if secrets.contains(arg) {
+            *arg = REDACTED.to_string();
+        } else if let Some((flag, value)) = arg.split_once('=') {
+            if flag.starts_with("--") && secrets.iter().any(|s| s == value) {
+                *arg = format!("{flag}={REDACTED}");
+            }
+        }
+    }
+    // A replay doesn't record itself
+    if let Some(index) = args
+        .iter()
+        .position(|a| a == "--record" || a.starts_with("--record="))
+    {
+        let len = if args[index] == "--record" { 2 } else { 1 };
+        args.drain(index..(index + len).min(args.len()));
+    }
+    args.remove(0);
+    args
+}
+
+/// Secrets in a command sent to the device replaced with `<redacted>`.
+fn redact_command(frame: &[u8]) -> Option<Vec<u8>> {
+    let text = std::str::from_utf8(frame).ok()?;
+    let (keep, _) = if let Some(rest) = text.strip_prefix("AUTH ") {
+        let keyword = ["STATUS", "ENABLE", "DISABLE", "HMAC "]
+            .iter()
+            .any(|k| rest.starts_with(k));
+        if keyword {
+            return None;
+        }
+        text.split_at(5)
+    } else if text.starts_with("SETPASS ") || text.starts_with("SETPIN ") {
+        text.split_at(text.find(' ')? + 1)
+    } else if text.starts_with("CHANNEL JOIN ") {
+        text.split_at(text.rfind(' ')? + 1)
+    } else if text.starts_with("TIME SET ") && text.split(' ').count() > 4 {
+        // TIME SET <target> <time> <admin password>
+        text.split_at(text.rfind(' ')? + 1)
+    } else {
+        return None;
+    };
+    Some(format!("{keep}{REDACTED}").into_bytes())
+}
+
+/// A COBS frame (with delimiter) with secrets redacted.
+fn redact_frame(frame: &[u8]) -> Vec<u8> {
+    let mut decoded = frame.strip_suffix(&[0]).unwrap_or(frame).to_vec();
+    let Some(len) = cobs_decode_in_place(&mut decoded) else {
+        return frame.to_vec();
+    };
+    match redact_command(&decoded[..len]) {
+        Some(redacted) => {
+            let mut out = BytesMut::new();
+            cobs_encode_into(&redacted, &mut out);
+            out.to_vec()
+        }
+        None => frame.to_vec(),
+    }
+}
+
+struct Recording {
+    path: String,
+    start: Instant,
+    session: Session,
+    connections: usize,
+}
+
+static RECORDING: Mutex<Option<Recording>> = Mutex::new(None);
+
+/// Record the session to `path` (`--record`).
+pub fn record_to(path: &str, args: Vec<String>) {
+    *RECORDING.lock().unwrap_or_else(|e| e.into_inner()) = Some(Recording {
+        path: path.to_string(),
+        start: Instant::now(),
+        session: Session {
+            version: VERSION,
+            cli_version: env!("CARGO_PKG_VERSION").to_string(),
+            started: chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, false),
+            args,
+            events: Vec::new(),
+            result: None,
+        },
+        connections: 0,
+    });
+}
+
+fn record(conn: usize, kind: EventKind) {
+    if let Some(recording) = RECORDING.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+        let ms = u64::try_from(recording.start.elapsed().as_millis()).unwrap_or(u64::MAX);
+        recording.session.events.push(Event { ms, conn, kind });
+    }
+}
+
+/// Save the recording with the command's result.
+pub fn finish_recording(result: &Result<()>) -> Result<()> {
+    let Some(mut recording) = RECORDING.lock().unwrap_or_else(|e| e.into_inner()).take() else {
+        return Ok(());
+    };
+    recording.session.result = Some(Outcome::of(result));
+    let json = serde_json::to_string_pretty(&recording.session)?;
+    std::fs::write(&recording.path, json + "\n")
+        .with_context(|| format!("Failed to write {}", recording.path))?;
+    eprintln!(
+        "✓ Session recorded to {} ({} connection(s), {} events)",
+        recording.path,
+        recording.connections,
+        recording.session.events.len()
+    );
+    Ok(())
+}
+
+/// The recorded connection to serve in place of `port` during
+/// `replay-session`.
+pub fn replayed(port: &str) -> Result<Option<Box<dyn Transport>>> {
+    match REPLAY.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+        Some(replay) => replay.next_connection(port).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// A newly opened connection, recorded with `--record`.
+pub fn recorded(port: &str, inner: Box<dyn Transport>) -> Box<dyn Transport> {
+    let mut guard = RECORDING.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(recording) = guard.as_mut() else {
+        return inner;
+    };
+    let conn = recording.connections;
+    recording.connections += 1;
+    drop(guard);
+    record(conn, EventKind::Open(port.to_string()));
+    Box::new(Recorded {
+        inner,
+        conn,
+        pending: Vec::new(),
+    })
+}
+
+/// Transport that records what passes through it.
+struct Recorded {
+    inner: Box<dyn Transport>,
+    conn: usize,
+    /// Sent bytes not yet ending in a frame delimiter
+    pending: Vec<u8>,
+}
+
+impl AsyncRead for Recorded {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = result {
+            let data = &buf.filled()[before..];
+            if !data.is_empty() {
+                record(self.conn, EventKind::Rx(hex::encode(data)));
+            }
+        }
+        result
+    }
+}
+
+impl AsyncWrite for Recorded {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let result = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = result {
+            self.pending.extend_from_slice(&buf[..n]);
+            while let Some(end) = self.pending.iter().position(|&b| b == 0) {
+                let frame: Vec<u8> = self.pending.drain(..=end).collect();
+                record(self.conn, EventKind::Tx(hex::encode(redact_frame(&frame))));
+            }
+        }
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// What the replay saw.
+#[derive(Debug, Default)]
+struct ReplayStats {
+    frames: usize,
+    /// Frames that differ from the recording: (recorded, sent)
+    differing: Vec<(String, String)>,
+    /// Frames sent after the recording of their connection ran out
+    extra: usize,
+}
+
+struct Replay {
+    connections: VecDeque<(String, VecDeque<EventKind>)>,
+    stats: Arc<Mutex<ReplayStats>>,
+}
+
+impl Replay {
+    fn next_connection(&mut self, port: &str) -> Result<Box<dyn Transport>> {
+        let Some((recorded, events)) = self.connections.pop_front() else {
+            bail!(CliError::ConnectionFailed(format!(
+                "The session has no more recorded connections (opening {port})"
+            )));
+        };
+        if recorded != port {
+            tracing::debug!("Replaying the connection to {recorded} for {port}");
+        }
+        Ok(Box::new(Replayed {
+            events,
+            pending: Vec::new(),
+            unread: Vec::new(),
+            waker: None,
+            stats: Arc::clone(&self.stats),
+        }))
+    }
+}
+
+static REPLAY: Mutex<Option<Replay>> = Mutex::new(None);
+
+/// Serve connections from `session` until [`finish_replay`].
+pub fn start_replay(session: &Session) {
+    let mut connections: Vec<(String, VecDeque<EventKind>)> = Vec::new();
+    for event in &session.events {
+        match &event.kind {
+            EventKind::Open(port) => connections.push((port.clone(), VecDeque::new())),
+            kind => {
+                if let Some((_, events)) = connections.get_mut(event.conn) {
+                    events.push_back(kind.clone());
+                }
+            }
+        }
+    }
+    *REPLAY.lock().unwrap_or_else(|e| e.into_inner()) = Some(Replay {
+        connections: connections.into(),
+        stats: Arc::default(),
+    });
+}
+
+/// Compare the replay with the recording and print the verdict. Fails if
+/// the command ended differently or sent different frames.
+pub fn finish_replay(session: &Session, result: &Result<()>) -> Result<()> {
+    let Some(replay) = REPLAY.lock().unwrap_or_else(|e| e.into_inner()).take() else {
+        return Ok(());
+    };
+    let stats = replay.stats.lock().unwrap_or_else(|e| e.into_inner());
+    let outcome = Outcome::of(result);
+    println!();
+    println!(
+        "Replayed {} frame(s) over {} connection(s)",
+        stats.frames,
+        session
+            .events
+            .iter()
+            .filter(|e| matches!(e.kind, EventKind::Open(_)))
+            .count()
+    );
+    let mut diverged = false;
+    for (recorded, sent) in &stats.differing {
+        println!(
+            "✗ Sent {} where the recording has {}",
+            frame_text(sent),
+            frame_text(recorded)
+        );
+        diverged = true;
+    }
+    if stats.extra > 0 {
+        println!("✗ {} frame(s) sent beyond the recording", stats.extra);
+        diverged = true;
+    }
+    match &session.result {
+        Some(recorded) if recorded.code == outcome.code => {
+            println!("✓ Exit code {} as recorded", outcome.code);
+        }
+        Some(recorded) => {
+            println!("✗ Exit code {} (recorded: {})", outcome.code, recorded.code);
+            diverged = true;
+        }
+        None => println!("⚠ The recording has no result to compare"),
+    }
+    if diverged {
+        bail!("Replay diverged from the recording");
+    }
+    Ok(())
+}
+
+/// A hex frame as its command text, for messages.
+fn frame_text(hex_frame: &str) -> String {
+    let Ok(mut frame) = hex::decode(hex_frame) else {
+        return hex_frame.to_string();
+    };
+    frame.pop();
+    match cobs_decode_in_place(&mut frame) {
+        Some(len) => format!("{:?}", String::from_utf8_lossy(&frame[..len])),
+        None => hex_frame.to_string(),
+    }
+}
+
+/// Transport that plays a recorded connection.
+struct Replayed {
+    events: VecDeque<EventKind>,
+    /// Written bytes not yet ending in a frame delimiter
+    pending: Vec<u8>,
+    /// Rest of a received chunk larger than the read buffer
+    unread: Vec<u8>,
+    waker: Option<Waker>,
+    stats: Arc<Mutex<ReplayStats>>,
+}
+
+impl Replayed {
+    /// Match a frame the CLI sent against the next recorded one.
+    fn sent(&mut self, frame: &[u8]) {
+        let mut stats = self.stats.lock().unwrap_or_else(|e| e.into_inner());
+        stats.frames += 1;
+        let sent = hex::encode(redact_frame(frame));
+        // Received chunks ahead of it stay readable
+        let next = self
+            .events
+            .iter()
+            .position(|e| matches!(e, EventKind::Tx(_)));
+        match next.and_then(|i| self.events.remove(i)) {
+            Some(EventKind::Tx(recorded)) if recorded != sent => {
+                stats.differing.push((recorded, sent));
+            }
+            Some(_) => {}
+            None => stats.extra += 1,
+        }
+    }
+}
+
+impl AsyncRead for Replayed {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        if self.unread.is_empty() {
+            match self.events.front() {
+                Some(EventKind::Rx(hex_data)) => {
+                    self.unread = hex::decode(hex_data).unwrap_or_default();
+                    self.events.pop_front();
+                }
+                // Wait for the frame that the next recorded answer follows
+                Some(_) => {
+                    self.waker = Some(cx.waker().clone());
+                    return Poll::Pending;
+                }
+                // End of the recording
+                None => return Poll::Ready(Ok(())),
+            }
+        }
+        let n = self.unread.len().min(buf.remaining());
+        buf.put_slice(&self.unread[..n]);
+        self.unread.drain(..n);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for Replayed {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        self.pending.extend_from_slice(buf);
+        while let Some(end) = self.pending.iter().position(|&b| b == 0) {
+            let frame: Vec<u8> = self.pending.drain(..=end).collect();
+            self.sent(&frame);
+        }
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn frame(text: &str) -> Vec<u8> {
+        let mut out = BytesMut::new();
+        cobs_encode_into(text.as_bytes(), &mut out);
+        out.to_vec()
+    }
+
+    #[tokio::test]
+    async fn test_replay() {
+        assert_eq!(redact_frame(&frame("AUTH 1234")), frame("AUTH <redacted>"));
+        assert_eq!(
+            redact_frame(&frame("CHANNEL JOIN ops c2VjcmV0")),
+            frame("CHANNEL JOIN ops <redacted>")
+        );
+        assert_eq!(redact_frame(&frame("AUTH STATUS")), frame("AUTH STATUS"));
+        assert_eq!(
+            redact_frame(&frame("TIME SET 0x1f 1760000000 hunter2")),
+            frame("TIME SET 0x1f 1760000000 <redacted>")
+        );
+        assert_eq!(
+            redact_frame(&frame("TIME SET 0x1f 1760000000")),
+            frame("TIME SET 0x1f 1760000000")
+        );
+
+        let stats = Arc::new(Mutex::new(ReplayStats::default()));
+        let mut device = Replayed {
+            events: [
+                EventKind::Rx(hex::encode(b"boot\n")),
+                EventKind::Tx(hex::encode(frame("PING"))),
+                EventKind::Rx(hex::encode(frame("PONG"))),
+                EventKind::Tx(hex::encode(frame("AUTH <redacted>"))),
+            ]
+            .into(),
+            pending: Vec::new(),
+            unread: Vec::new(),
+            waker: None,
+            stats: Arc::clone(&stats),
+        };
+        // The answer is only released once its request was sent
+        device.write_all(&frame("PING")).await.unwrap();
+        let mut buf = [0u8; 64];
+        let n = device.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"boot\n");
+        let n = device.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], frame("PONG").as_slice());
+
+        device.write_all(&frame("AUTH 9999")).await.unwrap();
+        device.write_all(&frame("INFO")).await.unwrap();
+        assert_eq!(device.read(&mut buf).await.unwrap(), 0);
+        let stats = stats.lock().unwrap();
+        assert_eq!(stats.frames, 3);
+        assert!(stats.differing.is_empty());
+        assert_eq!(stats.extra, 1);
+    }
+}
@@ -0,0 +1,241 @@
+//! CLI configuration file.
+//!
+//! User preferences for the CLI itself (as opposed to device configuration)
+//! are read from a TOML file, by default `~/.config/meshgrid-cli/config.toml`.
+//! The location can be overridden with the `MESHGRID_CONFIG` environment variable.
+//!
+//! ```toml
+//! [hooks]
+//! message = "notify-send \"$MESHGRID_FROM\" \"$MESHGRID_TEXT\""
+//! node_appeared = "logger -t mesh \"new node $MESHGRID_NODE_NAME\""
+//! battery_low = "./alert.sh"
+//! battery_low_percent = 15
+//!
+//! [bridge.email.contacts]
+//! "alice@example.com" = "alice"
+//! "bob@example.org" = "0x3a"
+//!
+//! [provision]
+//! trusted_keys = ["base64 ed25519 public key"]
+//!
+//! [update]
+//! trusted_keys = ["base64 ed25519 public key"]
+//!
+//! [auth]
+//! require_challenge = true
+//!
+//! [[serve.tokens]]
+//! name = "dashboard"
+//! token = "..."
+//! scope = "read-only"
+//!
+//! [airtime]
+//! duty_cycle_percent = 1.0
+//! max_wait_secs = 120
+//!
+//! [history]
+//! record = false
+//!
+//! [confirm]
+//! policy = "ask"
+//! skip = ["reboot"]
+//!
+//! [profiles.bench.confirm]
+//! policy = "always"
+//! ```
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+/// Shell commands to run when mesh events occur.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HooksConfig {
+    /// Run when a message is received
+    pub message: Option<String>,
+    /// Run when a node is heard for the first time in a session
+    pub node_appeared: Option<String>,
+    /// Run when the device battery drops below `battery_low_percent`
+    pub battery_low: Option<String>,
+    /// Run when a direct message is not acknowledged in time
+    pub ack_timeout: Option<String>,
+    /// Run when `monitor` sends a queued message (`send --queue`)
+    pub queued_delivered: Option<String>,
+    /// Battery threshold for the `battery_low` hook (percent)
+    pub battery_low_percent: Option<u8>,
+}
+
+/// Email bridge settings.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EmailBridgeConfig {
+    /// Email address -> mesh node (name or hash). Only these addresses may
+    /// send messages into the mesh.
+    pub contacts: BTreeMap<String, String>,
+}
+
+/// Bridge settings.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BridgeConfig {
+    pub email: EmailBridgeConfig,
+}
+
+/// Provisioning bundle settings.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProvisionConfig {
+    /// Public keys (base64 ed25519) allowed to sign bundles for `provision apply`
+    pub trusted_keys: Vec<String>,
+}
+
+/// CLI self-update settings.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UpdateConfig {
+    /// Public keys (base64 ed25519) allowed to sign release binaries for
+    /// `update --self`, besides the built-in release key
+    pub trusted_keys: Vec<String>,
+}
+
+/// Device authentication settings.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    /// Never send the PIN in the clear, even to firmware without
+    /// challenge-response support
+    pub require_challenge: bool,
+}
+
+/// Duty-cycle limits on transmissions (see `airtime.rs`).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AirtimeConfig {
+    /// Share of each hour the device may transmit (percent). Defaults to the
+    /// limit of the EU868 sub-band in use; 0 disables the check.
+    pub duty_cycle_percent: Option<f64>,
+    /// Longest a send may be held back for airtime before it is refused
+    /// (seconds, default 60)
+    pub max_wait_secs: Option<u64>,
+}
+
+/// Local message and event history (see `history.rs`).
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HistoryConfig {
+    /// Record received messages, monitor events and telemetry samples
+    /// (default true)
+    pub record: Option<bool>,
+}
+
+/// What a `serve` token may do. Each scope includes the ones before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Scope {
+    /// Device info, telemetry and events
+    ReadOnly,
+    /// Also send messages
+    Send,
+    /// Also run arbitrary device commands (reboot, config, ...)
+    Admin,
+}
+
+impl Scope {
+    /// Least scope a device command needs; unknown commands need admin.
+    pub fn for_command(cmd: &str) -> Self {
+        let mut words = cmd.split_whitespace().map(str::to_ascii_uppercase);
+        let verb = words.next().unwrap_or_default();
+        let arg = words.next();
+        match (verb.as_str(), arg.as_deref()) {
+            // PING carries the handshake's nonce
+            ("PING", _) => Self::ReadOnly,
+            (
+                "INFO" | "CONFIG" | "STATS" | "NEIGHBORS" | "ROUTES" | "NOISE" | "CHANNELS"
+                | "MESSAGES" | "TIME" | "POSITION" | "GPS" | "CRASHLOG" | "LOG" | "TELEMETRY"
+                | "MONITOR" | "CHALLENGE",
+                None,
+            ) => Self::ReadOnly,
+            ("AUTH", Some(arg)) if arg != "ENABLE" && arg != "DISABLE" => Self::ReadOnly,
+            ("SEND" | "ADVERT" | "TRACE" | "PROBE" | "TELEMETRY", _)
+            | ("CHANNEL", Some("SEND"))
+            | ("TIME" | "NODEINFO" | "POSITION" | "LOG", Some("REQUEST")) => Self::Send,
+            _ => Self::Admin,
+        }
+    }
+}
+
+impl std::fmt::Display for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Scope::ReadOnly => "read-only",
+            Scope::Send => "send",
+            Scope::Admin => "admin",
+        })
+    }
+}
+
+/// Client token accepted by `serve`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServeToken {
+    /// Label for logs
+    #[serde(default)]
+    pub name: Option<String>,
+    pub token: String,
+    pub scope: Scope,
+}
+
+/// Network service settings.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServeConfig {
+    pub tokens: Vec<ServeToken>,
+}
+
+/// Settings selected with `--profile <name>`, overriding the global ones.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    pub confirm: crate::confirm::ConfirmOverride,
+}
+
+/// Top-level CLI settings.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub hooks: HooksConfig,
+    pub bridge: BridgeConfig,
+    pub provision: ProvisionConfig,
+    pub update: UpdateConfig,
+    pub auth: AuthConfig,
+    pub serve: ServeConfig,
+    pub airtime: AirtimeConfig,
+    pub history: HistoryConfig,
+    pub confirm: crate::confirm::ConfirmConfig,
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+impl Settings {
+    /// Path of the configuration file.
+    pub fn path() -> Result<PathBuf> {
+        if let Ok(path) = std::env::var("MESHGRID_CONFIG") {
+            return Ok(PathBuf::from(path));
+        }
+        let config_base =
+            dirs::config_dir().ok_or_else(|| anyhow!("Could not determine config directory"))?;
+        Ok(config_base.join("meshgrid-cli").join("config.toml"))
+    }
+
+    /// Load settings, falling back to defaults when no file exists.
+    pub fn load() -> Result<Self> {
+        let path = Self::path()?;
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        toml::from_str(&content).with_context(|| format!("Invalid config file {}", path.display()))
+    }
+}
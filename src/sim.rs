@@ -0,0 +1,1259 @@
+//! Virtual mesh for `meshgrid-cli sim`.
+//!
+//! Simulated nodes answer the device protocol on local TCP sockets, listed in
+//! `sim.json` in the runtime directory, so every command and the UI can use
+//! `-p sim://node3` in place of a serial port. Packets are flooded as on a
+//! real mesh: a transmission reaches each node with a chance that falls with
+//! distance (log-distance path loss against the demodulation limit of the
+//! spreading factor), and repeaters pass on what they hear once. Collisions
+//! aren't modelled.
+
+use crate::airtime::time_on_air;
+use crate::cli::SimTopology;
+use crate::error::CliError;
+use crate::geo;
+use crate::protocol::DeviceConfig;
+use crate::rng::Rng;
+use crate::serial::{cobs_decode_in_place, cobs_encode_into};
+use crate::timefmt;
+use anyhow::{bail, Context, Result};
+use bytes::BytesMut;
+use chrono::{Local, NaiveDateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, oneshot};
+
+/// Most repeaters a packet passes through.
+const MAX_HOPS: usize = 8;
+
+/// Path loss exponent, between open country (2) and built-up areas (4).
+const PATH_LOSS_EXPONENT: f64 = 3.5;
+
+/// Receiver noise figure.
+const NOISE_FIGURE_DB: f64 = 6.0;
+
+/// Where the first node is placed on the map.
+const ORIGIN: (f64, f64) = (52.0907, 5.1214);
+
+/// How long a node waits for the reply to `TELEMETRY <node>`, within the
+/// CLI's command timeout.
+const REPLY_TIMEOUT: Duration = Duration::from_millis(4500);
+
+/// Firmware version simulated nodes report.
+const FIRMWARE: &str = "0.0.0-sim";
+
+/// The simulated mesh, shared by the nodes' connections and transmissions.
+pub type Shared = Arc<Mutex<Mesh>>;
+
+/// Lock the mesh. No lock is held across an await.
+fn lock(mesh: &Shared) -> MutexGuard<'_, Mesh> {
+    mesh.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Signal of one node at another.
+#[derive(Debug, Clone, Copy)]
+struct Link {
+    rssi: f64,
+    snr: f64,
+    /// Chance a packet is received
+    chance: f64,
+}
+
+/// The link from a transmitter with radio settings `tx` to a receiver with
+/// `rx` `distance_m` away; None when their settings don't match.
+fn link(tx: &DeviceConfig, rx: &DeviceConfig, distance_m: f64) -> Option<Link> {
+    if (tx.freq_mhz - rx.freq_mhz).abs() > 0.01
+        || tx.spreading_factor != rx.spreading_factor
+        || tx.bandwidth_khz != rx.bandwidth_khz
+    {
+        return None;
+    }
+    let wavelength = 299_792_458.0 / (f64::from(tx.freq_mhz) * 1e6);
+    let path_loss = 20.0 * (4.0 * std::f64::consts::PI / wavelength).log10()
+        + 10.0 * PATH_LOSS_EXPONENT * distance_m.max(1.0).log10();
+    let rssi = f64::from(tx.tx_power_dbm) - path_loss;
+    let snr = rssi - noise_floor_dbm(rx.bandwidth_khz);
+    let limit = demodulation_limit_db(rx.spreading_factor);
+    // Fading: an even chance at the limit, near certain a few dB above it
+    let chance = 1.0 / (1.0 + (-(snr - limit) / 1.2).exp());
+    Some(Link { rssi, snr, chance })
+}
+
+/// Thermal noise over `bandwidth_khz` plus the receiver's noise figure.
+pub fn noise_floor_dbm(bandwidth_khz: u32) -> f64 {
+    -174.0 + 10.0 * (f64::from(bandwidth_khz) * 1000.0).log10() + NOISE_FIGURE_DB
+}
+
+/// Lowest SNR a spreading factor demodulates: -7.5 dB at SF7 down to -20 dB
+/// at SF12.
+pub fn demodulation_limit_db(spreading_factor: u8) -> f64 {
+    -2.5 * (f64::from(spreading_factor) - 4.0)
+}
+
+/// Node positions in meters east and north of the first one.
+fn layout(topology: SimTopology, count: usize, spacing: f64, rng: &mut Rng) -> Vec<(f64, f64)> {
+    let columns = (count as f64).sqrt().ceil() as usize;
+    (0..count)
+        .map(|i| match topology {
+            SimTopology::Line => (i as f64 * spacing, 0.0),
+            SimTopology::Grid => (
+                (i % columns) as f64 * spacing,
+                (i / columns) as f64 * spacing,
+            ),
+            // Spread so the average distance between neighbors is `spacing`
+            SimTopology::Random => {
+                let side = spacing * (count as f64).sqrt();
+                (rng.unit() * side, rng.unit() * side)
+            }
+        })
+        .collect()
+}
+
+/// Latitude and longitude of a point `east`, `north` meters from [`ORIGIN`].
+fn to_lat_lon((east, north): (f64, f64)) -> (f64, f64) {
+    const METERS_PER_DEGREE: f64 = 111_320.0;
+    (
+        ORIGIN.0 + north / METERS_PER_DEGREE,
+        ORIGIN.1 + east / (METERS_PER_DEGREE * ORIGIN.0.to_radians().cos()),
+    )
+}
+
+/// A line for a node's connections: a monitor event or a mesh reply.
+#[derive(Debug, Clone)]
+struct Event {
+    line: String,
+    /// Only sent in monitor mode (MSG, ADV, ACK)
+    monitor_only: bool,
+}
+
+/// A node heard directly.
+#[derive(Debug, Clone, Copy)]
+struct Heard {
+    rssi: f64,
+    snr: f64,
+    at: Instant,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Stats {
+    rx: u64,
+    tx: u64,
+    fwd: u64,
+    /// Packets lost to fading from nodes in range
+    dropped: u64,
+    duplicates: u64,
+}
+
+struct Node {
+    /// Name in `sim://` addresses, unchanged by `SET NAME`
+    id: String,
+    config: DeviceConfig,
+    public_key: [u8; 32],
+    mode: String,
+    lat: f64,
+    lon: f64,
+    booted: Instant,
+    /// Seconds the node's clock is ahead of the host's
+    clock_offset: i64,
+    battery_percent: u8,
+    neighbors: BTreeMap<usize, Heard>,
+    /// Nodes heard advertising, addressable by name or hash
+    known: HashSet<usize>,
+    channels: Vec<String>,
+    default_channel: Option<String>,
+    inbox: Vec<serde_json::Value>,
+    seen: HashSet<u64>,
+    /// Path back to each node that flooded a packet here, nearest first
+    routes: BTreeMap<usize, (Vec<usize>, Instant)>,
+    stats: Stats,
+    events: broadcast::Sender<Event>,
+}
+
+impl Node {
+    fn name(&self) -> &str {
+        self.config.name.as_deref().unwrap_or(&self.id)
+    }
+
+    fn hash(&self) -> u8 {
+        self.public_key[0]
+    }
+
+    fn clock(&self) -> i64 {
+        Utc::now().timestamp() + self.clock_offset
+    }
+
+    fn battery_mv(&self) -> u16 {
+        3300 + 9 * u16::from(self.battery_percent)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Dest {
+    Public,
+    Channel(String),
+    Node(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Request {
+    Trace,
+    Telemetry,
+    /// Read the clock, or set it to a unix time
+    Time(Option<i64>),
+    NodeInfo,
+    Position,
+    /// Report the signal the request arrived with
+    Probe,
+}
+
+#[derive(Debug, Clone)]
+enum Body {
+    Advert,
+    Text {
+        to: Dest,
+        text: String,
+    },
+    Ack {
+        to: usize,
+    },
+    Request {
+        to: usize,
+        request: Request,
+    },
+    Reply {
+        to: usize,
+        request: u64,
+        json: serde_json::Value,
+    },
+}
+
+impl Body {
+    /// Node a packet is addressed to, unless it's for everyone.
+    fn to(&self) -> Option<usize> {
+        match self {
+            Body::Text {
+                to: Dest::Node(to), ..
+            }
+            | Body::Ack { to }
+            | Body::Request { to, .. }
+            | Body::Reply { to, .. } => Some(*to),
+            _ => None,
+        }
+    }
+
+    /// Approximate length on air.
+    fn len(&self) -> usize {
+        match self {
+            Body::Advert => 110,
+            Body::Text { text, .. } => 16 + text.len(),
+            Body::Reply { json, .. } => 16 + json.to_string().len(),
+            Body::Ack { .. } | Body::Request { .. } => 24,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Packet {
+    id: u64,
+    origin: usize,
+    /// Repeaters it passed through
+    path: Vec<usize>,
+    body: Body,
+}
+
+/// A request waiting for its reply.
+struct Waiting {
+    sent: Instant,
+    /// Where `TELEMETRY <node>` waits; other replies go out as lines
+    reply: Option<oneshot::Sender<serde_json::Value>>,
+}
+
+/// What a node does with a command.
+enum Handled {
+    Reply(String),
+    /// Reply, then transmit
+    Send(String, Packet),
+    /// Transmit, and answer with the reply that comes back
+    Ask(Packet, oneshot::Receiver<serde_json::Value>),
+}
+
+/// A node as listed when the simulator starts.
+#[derive(Debug, Serialize)]
+pub struct NodeSummary {
+    pub id: String,
+    pub node_hash: u8,
+    pub lat: f64,
+    pub lon: f64,
+    /// Nodes it reaches more often than not
+    pub in_range: Vec<String>,
+}
+
+pub struct Mesh {
+    nodes: Vec<Node>,
+    rng: Rng,
+    next_id: u64,
+    requests: HashMap<u64, Waiting>,
+    /// Packets sent on command and direct messages delivered, for the
+    /// simulator's console
+    log: broadcast::Sender<String>,
+}
+
+impl Mesh {
+    /// `count` repeaters laid out by `topology`, sharing radio settings.
+    pub fn new(topology: SimTopology, count: usize, spacing: f64, sf: u8, seed: u64) -> Self {
+        let mut rng = Rng::new(seed);
+        let nodes = layout(topology, count, spacing, &mut rng)
+            .into_iter()
+            .enumerate()
+            .map(|(i, position)| {
+                let id = format!("node{}", i + 1);
+                let (lat, lon) = to_lat_lon(position);
+                let mut public_key = [0u8; 32];
+                public_key.copy_from_slice(&rng.bytes(32));
+                // Distinct hashes: 37 is coprime with 256
+                public_key[0] = (0x11 + 37 * i) as u8;
+                Node {
+                    config: DeviceConfig {
+                        name: Some(id.clone()),
+                        freq_mhz: 869.525,
+                        tx_power_dbm: 20,
+                        bandwidth_khz: 250,
+                        spreading_factor: sf,
+                        coding_rate: 5,
+                        preamble_len: 8,
+                    },
+                    id,
+                    public_key,
+                    mode: "repeater".into(),
+                    lat,
+                    lon,
+                    booted: Instant::now(),
+                    clock_offset: 0,
+                    battery_percent: 60 + rng.below(41) as u8,
+                    neighbors: BTreeMap::new(),
+                    known: HashSet::new(),
+                    channels: Vec::new(),
+                    default_channel: None,
+                    inbox: Vec::new(),
+                    seen: HashSet::new(),
+                    routes: BTreeMap::new(),
+                    stats: Stats::default(),
+                    events: broadcast::channel(256).0,
+                }
+            })
+            .collect();
+        Self {
+            nodes,
+            rng,
+            next_id: 1,
+            requests: HashMap::new(),
+            log: broadcast::channel(256).0,
+        }
+    }
+
+    pub fn summary(&self) -> Vec<NodeSummary> {
+        (0..self.nodes.len())
+            .map(|i| {
+                let node = &self.nodes[i];
+                NodeSummary {
+                    id: node.id.clone(),
+                    node_hash: node.hash(),
+                    lat: node.lat,
+                    lon: node.lon,
+                    in_range: (0..self.nodes.len())
+                        .filter(|&j| j != i && self.link(i, j).is_some_and(|l| l.chance >= 0.5))
+                        .map(|j| self.nodes[j].id.clone())
+                        .collect(),
+                }
+            })
+            .collect()
+    }
+
+    /// Follow packets sent on command and direct messages delivered.
+    pub fn log(&self) -> broadcast::Receiver<String> {
+        self.log.subscribe()
+    }
+
+    fn link(&self, from: usize, to: usize) -> Option<Link> {
+        let (tx, rx) = (&self.nodes[from], &self.nodes[to]);
+        link(
+            &tx.config,
+            &rx.config,
+            geo::distance_m((tx.lat, tx.lon), (rx.lat, rx.lon)),
+        )
+    }
+
+    fn jitter(&mut self) -> Duration {
+        Duration::from_millis(50 + self.rng.below(250) as u64)
+    }
+
+    fn event(&self, node: usize, line: String, monitor_only: bool) {
+        // Nobody listening is fine
+        let _ = self.nodes[node].events.send(Event { line, monitor_only });
+    }
+
+    /// A new packet from `origin`, which won't repeat it.
+    fn packet(&mut self, origin: usize, body: Body) -> Packet {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.nodes[origin].seen.insert(id);
+        Packet {
+            id,
+            origin,
+            path: Vec::new(),
+            body,
+        }
+    }
+
+    /// A request from `origin`, waiting for its reply.
+    fn request(
+        &mut self,
+        origin: usize,
+        to: usize,
+        request: Request,
+        reply: Option<oneshot::Sender<serde_json::Value>>,
+    ) -> Packet {
+        let packet = self.packet(origin, Body::Request { to, request });
+        self.requests.insert(
+            packet.id,
+            Waiting {
+                sent: Instant::now(),
+                reply,
+            },
+        );
+        packet
+    }
+
+    /// A node `node` has heard advertising, by name or `0x` hash.
+    fn resolve(&self, node: usize, target: &str) -> Option<usize> {
+        let known = &self.nodes[node].known;
+        if let Some(hex) = target.strip_prefix("0x") {
+            let hash = u8::from_str_radix(hex, 16).ok()?;
+            return known
+                .iter()
+                .copied()
+                .find(|&n| self.nodes[n].hash() == hash);
+        }
+        known
+            .iter()
+            .copied()
+            .find(|&n| self.nodes[n].name().eq_ignore_ascii_case(target))
+    }
+
+    /// Deliver a transmission from `from` to the nodes that hear it; returns
+    /// the transmissions that follow (repeats and replies), with their
+    /// delays.
+    fn receive(&mut self, from: usize, packet: &Packet) -> Vec<(usize, Packet, Duration)> {
+        let mut next = Vec::new();
+        for node in 0..self.nodes.len() {
+            if node == from {
+                continue;
+            }
+            let Some(link) = self.link(from, node) else {
+                continue;
+            };
+            let heard = self.rng.unit() < link.chance;
+            let stats = &mut self.nodes[node].stats;
+            if !heard {
+                if link.chance >= 0.01 {
+                    stats.dropped += 1;
+                }
+                continue;
+            }
+            stats.rx += 1;
+            if !self.nodes[node].seen.insert(packet.id) {
+                self.nodes[node].stats.duplicates += 1;
+                continue;
+            }
+            if packet.origin != node {
+                let path = packet.path.iter().rev().copied().collect();
+                self.nodes[node]
+                    .routes
+                    .insert(packet.origin, (path, Instant::now()));
+            }
+            next.extend(self.deliver(node, from, link, packet));
+            if packet.body.to() != Some(node)
+                && self.nodes[node].mode == "repeater"
+                && packet.path.len() < MAX_HOPS
+            {
+                self.nodes[node].stats.fwd += 1;
+                let mut repeat = packet.clone();
+                repeat.path.push(node);
+                let delay = self.jitter();
+                next.push((node, repeat, delay));
+            }
+        }
+        next
+    }
+
+    /// Handle a packet `node` received from `from`.
+    fn deliver(
+        &mut self,
+        node: usize,
+        from: usize,
+        link: Link,
+        packet: &Packet,
+    ) -> Vec<(usize, Packet, Duration)> {
+        let origin = packet.origin;
+        let sender = self.nodes[origin].name().to_string();
+        let rssi = link.rssi.round();
+        match &packet.body {
+            Body::Advert => {
+                let receiver = &mut self.nodes[node];
+                receiver.known.insert(origin);
+                if from == origin {
+                    receiver.neighbors.insert(
+                        origin,
+                        Heard {
+                            rssi: link.rssi,
+                            snr: link.snr,
+                            at: Instant::now(),
+                        },
+                    );
+                }
+                let hash = self.nodes[origin].hash();
+                self.event(node, format!("ADV 0x{hash:02x} {rssi} {sender}"), true);
+            }
+            Body::Text { to, text } => {
+                let receiver = &self.nodes[node];
+                let (to_field, channel) = match to {
+                    Dest::Public => ("*".to_string(), "public".to_string()),
+                    Dest::Channel(channel)
+                        if receiver
+                            .channels
+                            .iter()
+                            .any(|c| c.eq_ignore_ascii_case(channel)) =>
+                    {
+                        (format!("*{channel}"), channel.clone())
+                    }
+                    Dest::Node(to) if *to == node => {
+                        (receiver.name().to_string(), "direct".to_string())
+                    }
+                    _ => return Vec::new(),
+                };
+                let entry = json!({
+                    "timestamp": Utc::now().timestamp(),
+                    "from_name": sender,
+                    "from_hash": format!("{:02x}", self.nodes[origin].hash()),
+                    "channel": channel,
+                    "protocol": "v1",
+                    "decrypted": true,
+                    "text": text,
+                });
+                self.nodes[node].inbox.push(entry);
+                let snr = link.snr.round();
+                self.event(
+                    node,
+                    format!("MSG {sender} {to_field} {rssi} {snr} {text}"),
+                    true,
+                );
+                if *to == Dest::Node(node) {
+                    let _ = self.log.send(format!(
+                        "{} ← {sender}: {} ({} hops)",
+                        self.nodes[node].name(),
+                        text,
+                        packet.path.len() + 1
+                    ));
+                    let ack = self.packet(node, Body::Ack { to: origin });
+                    return vec![(node, ack, self.jitter())];
+                }
+            }
+            Body::Ack { to } if *to == node => self.event(node, format!("ACK {sender}"), true),
+            Body::Request { to, request } if *to == node => {
+                let mut json = self.answer(node, *request, packet);
+                if json["type"] == "probe_response" {
+                    json["remote_rssi"] = json!(rssi);
+                    json["remote_snr"] = json!(link.snr.round());
+                }
+                let reply = self.packet(
+                    node,
+                    Body::Reply {
+                        to: origin,
+                        request: packet.id,
+                        json,
+                    },
+                );
+                return vec![(node, reply, self.jitter())];
+            }
+            Body::Reply { to, request, json } if *to == node => {
+                let Some(waiting) = self.requests.remove(request) else {
+                    return Vec::new();
+                };
+                let mut json = json.clone();
+                if json["type"] == "trace_response" || json["type"] == "probe_response" {
+                    json["rtt_ms"] = json!(waiting.sent.elapsed().as_millis() as u64);
+                }
+                if json["type"] == "probe_response" {
+                    json["rssi"] = json!(rssi);
+                    json["snr"] = json!(link.snr.round());
+                }
+                match waiting.reply {
+                    Some(reply) => {
+                        let _ = reply.send(json);
+                    }
+                    None => self.event(node, json.to_string(), false),
+                }
+            }
+            _ => {}
+        }
+        Vec::new()
+    }
+
+    /// `node`'s reply to a request.
+    fn answer(&mut self, node: usize, request: Request, packet: &Packet) -> serde_json::Value {
+        match request {
+            Request::Trace => {
+                let path: Vec<&str> = packet
+                    .path
+                    .iter()
+                    .chain([&node])
+                    .map(|&n| self.nodes[n].name())
+                    .collect();
+                json!({"type": "trace_response", "path": path, "hops": path.len()})
+            }
+            Request::Telemetry => self.telemetry(node),
+            Request::Time(time) => {
+                let target = &mut self.nodes[node];
+                let kind = match time {
+                    Some(time) => {
+                        target.clock_offset = time - Utc::now().timestamp();
+                        "time_set_response"
+                    }
+                    None => "time_response",
+                };
+                json!({"type": kind, "time": target.clock(), "mode": target.mode})
+            }
+            Request::NodeInfo => {
+                let target = &self.nodes[node];
+                json!({
+                    "type": "nodeinfo_response",
+                    "name": target.name(),
+                    "mode": target.mode,
+                    "firmware": FIRMWARE,
+                    "battery_percent": target.battery_percent,
+                    "battery_mv": target.battery_mv(),
+                    "uptime_secs": target.booted.elapsed().as_secs(),
+                })
+            }
+            Request::Position => {
+                let target = &self.nodes[node];
+                json!({"type": "position_response", "lat": target.lat, "lon": target.lon})
+            }
+            Request::Probe => json!({"type": "probe_response"}),
+        }
+    }
+
+    fn telemetry(&self, node: usize) -> serde_json::Value {
+        let node = &self.nodes[node];
+        json!({
+            "device": {
+                "battery": node.battery_percent,
+                "voltage": f64::from(node.battery_mv()) / 1000.0,
+                "uptime": node.booted.elapsed().as_secs(),
+                "heap": 180_000,
+            },
+            "location": {
+                "latitude": node.lat,
+                "longitude": node.lon,
+                "satellites": 8,
+                "fix": 3,
+            },
+        })
+    }
+
+    /// Answer a command sent to `node`.
+    fn command(&mut self, node: usize, command: &str, monitoring: &mut bool) -> Handled {
+        let (verb, rest) = split(command);
+        let verb = verb.to_ascii_uppercase();
+        *monitoring = verb == "MONITOR";
+        let reply = |text: &str| Handled::Reply(text.to_string());
+        let unknown = |target: &str| Handled::Reply(format!("ERR Unknown node {target}"));
+        match verb.as_str() {
+            // Echo the nonce the CLI tags its handshake with
+            "PING" => reply(format!("PONG {rest}").trim_end()),
+            "MONITOR" => reply("OK"),
+            "INFO" => {
+                let n = &self.nodes[node];
+                Handled::Reply(
+                    json!({
+                        "name": n.name(),
+                        "public_key": n.public_key,
+                        "node_hash": n.hash(),
+                        "firmware_version": FIRMWARE,
+                        "mode": n.mode,
+                        "freq_mhz": n.config.freq_mhz,
+                        "tx_power_dbm": n.config.tx_power_dbm,
+                    })
+                    .to_string(),
+                )
+            }
+            "CONFIG" => Handled::Reply(json!(self.nodes[node].config).to_string()),
+            "STATS" => Handled::Reply(self.stats(node).to_string()),
+            "NEIGHBORS" => Handled::Reply(self.neighbors(node).to_string()),
+            "ROUTES" => Handled::Reply(self.routes(node).to_string()),
+            "NOISE" => {
+                let floor = noise_floor_dbm(self.nodes[node].config.bandwidth_khz);
+                let jitter = self.rng.unit() * 3.0 - 1.5;
+                Handled::Reply(json!({ "noise_floor_dbm": (floor + jitter) as f32 }).to_string())
+            }
+            "TELEMETRY" if rest.is_empty() => Handled::Reply(self.telemetry(node).to_string()),
+            "TELEMETRY" => {
+                let Some(target) = self.resolve(node, rest) else {
+                    return unknown(rest);
+                };
+                let (tx, rx) = oneshot::channel();
+                let packet = self.request(node, target, Request::Telemetry, Some(tx));
+                Handled::Ask(packet, rx)
+            }
+            "CHANNELS" => Handled::Reply(self.channels(node).to_string()),
+            "CHANNEL" => self.channel_command(node, rest),
+            "MESSAGES" if rest.eq_ignore_ascii_case("CLEAR") => {
+                self.nodes[node].inbox.clear();
+                reply("OK Messages cleared")
+            }
+            "MESSAGES" => {
+                let inbox = &self.nodes[node].inbox;
+                Handled::Reply(json!({"total": inbox.len(), "messages": inbox}).to_string())
+            }
+            "SEND" => {
+                let (first, text) = split(rest);
+                let to = match self.resolve(node, first) {
+                    Some(target) if !text.is_empty() => Dest::Node(target),
+                    _ if rest.is_empty() => return reply("ERR Usage: SEND [node] <text>"),
+                    _ => Dest::Public,
+                };
+                let text = if to == Dest::Public { rest } else { text };
+                self.send_text(node, to, text)
+            }
+            "ADVERT" => {
+                let _ = self.log.send(format!("{} advert", self.nodes[node].name()));
+                Handled::Send("OK".into(), self.packet(node, Body::Advert))
+            }
+            "TRACE" => {
+                let Some(target) = self.resolve(node, rest) else {
+                    return unknown(rest);
+                };
+                let _ = self.log.send(format!(
+                    "{} trace {}",
+                    self.nodes[node].name(),
+                    self.nodes[target].name()
+                ));
+                let packet = self.request(node, target, Request::Trace, None);
+                Handled::Send(json!({"status": "sent"}).to_string(), packet)
+            }
+            "PROBE" => {
+                let Some(target) = self.resolve(node, rest) else {
+                    return unknown(rest);
+                };
+                let packet = self.request(node, target, Request::Probe, None);
+                Handled::Send(json!({"status": "sent"}).to_string(), packet)
+            }
+            "TIME" | "NODEINFO" | "POSITION" if !rest.is_empty() => {
+                let (action, rest) = split(rest);
+                let (target, rest) = split(rest);
+                let request = match (verb.as_str(), action.to_ascii_uppercase().as_str()) {
+                    ("TIME", "REQUEST") => Request::Time(None),
+                    ("TIME", "SET") => match split(rest).0.parse() {
+                        Ok(time) => Request::Time(Some(time)),
+                        Err(_) => return reply("ERR Usage: TIME SET <node> <unix time>"),
+                    },
+                    ("NODEINFO", "REQUEST") => Request::NodeInfo,
+                    ("POSITION", "REQUEST") => Request::Position,
+                    _ => return reply("ERR Unknown command"),
+                };
+                let Some(target) = self.resolve(node, target) else {
+                    return unknown(target);
+                };
+                Handled::Send("OK".into(), self.request(node, target, request, None))
+            }
+            "TIME" => {
+                let time = Utc.timestamp_opt(self.nodes[node].clock(), 0);
+                Handled::Reply(format!(
+                    "OK {}",
+                    time.single()
+                        .map_or_else(String::new, |t| timefmt::datetime(&t.with_timezone(&Local)))
+                ))
+            }
+            "/TIME" => match NaiveDateTime::parse_from_str(rest, "%Y-%m-%d %H:%M:%S")
+                .ok()
+                .and_then(|t| Local.from_local_datetime(&t).single())
+            {
+                Some(time) => {
+                    self.nodes[node].clock_offset = time.timestamp() - Utc::now().timestamp();
+                    reply("OK Time set")
+                }
+                None => reply("ERR Usage: /time YYYY-MM-DD HH:MM:SS"),
+            },
+            "/MODE" => match rest.to_ascii_lowercase().as_str() {
+                mode @ ("client" | "repeater" | "room") => {
+                    self.nodes[node].mode = mode.to_string();
+                    Handled::Reply(format!("OK Mode set to: {}", mode.to_uppercase()))
+                }
+                _ => reply("ERR Unknown mode"),
+            },
+            "POSITION" => {
+                let n = &self.nodes[node];
+                Handled::Reply(json!({"lat": n.lat, "lon": n.lon, "source": "sim"}).to_string())
+            }
+            "SET" => self.set(node, rest),
+            "REBOOT" => {
+                let n = &mut self.nodes[node];
+                n.booted = Instant::now();
+                n.neighbors.clear();
+                n.known.clear();
+                reply("OK Rebooting")
+            }
+            _ => reply("ERR Unknown command"),
+        }
+    }
+
+    fn send_text(&mut self, node: usize, to: Dest, text: &str) -> Handled {
+        let dest = match &to {
+            Dest::Public => "all".to_string(),
+            Dest::Channel(channel) => format!("#{channel}"),
+            Dest::Node(to) => self.nodes[*to].name().to_string(),
+        };
+        let _ = self
+            .log
+            .send(format!("{} → {dest}: {text}", self.nodes[node].name()));
+        let text = text.to_string();
+        Handled::Send("OK".into(), self.packet(node, Body::Text { to, text }))
+    }
+
+    fn channel_command(&mut self, node: usize, command: &str) -> Handled {
+        let (action, rest) = split(command);
+        let (name, text) = split(rest);
+        let n = &mut self.nodes[node];
+        let joined = n.channels.iter().position(|c| c.eq_ignore_ascii_case(name));
+        match (action.to_ascii_uppercase().as_str(), joined) {
+            ("SEND", _) if name.eq_ignore_ascii_case("public") => {
+                self.send_text(node, Dest::Public, text)
+            }
+            ("SEND", Some(i)) => {
+                let channel = n.channels[i].clone();
+                self.send_text(node, Dest::Channel(channel), text)
+            }
+            ("JOIN", None) if !name.is_empty() => {
+                n.channels.push(name.to_string());
+                Handled::Reply(format!("OK Joined {name}"))
+            }
+            ("JOIN", Some(_)) => Handled::Reply(format!("OK Already in {name}")),
+            ("LEAVE", Some(i)) => {
+                n.channels.remove(i);
+                if n.default_channel.as_deref() == Some(name) {
+                    n.default_channel = None;
+                }
+                Handled::Reply(format!("OK Left {name}"))
+            }
+            ("DEFAULT", Some(i)) => {
+                n.default_channel = Some(n.channels[i].clone());
+                Handled::Reply("OK".into())
+            }
+            ("DEFAULT", None) if name.is_empty() || name.eq_ignore_ascii_case("public") => {
+                n.default_channel = None;
+                Handled::Reply("OK".into())
+            }
+            ("SEND" | "LEAVE" | "DEFAULT", None) => {
+                Handled::Reply(format!("ERR Unknown channel {name}"))
+            }
+            _ => Handled::Reply("ERR Unknown command".into()),
+        }
+    }
+
+    fn set(&mut self, node: usize, command: &str) -> Handled {
+        let (key, value) = split(command);
+        let n = &mut self.nodes[node];
+        let config = &mut n.config;
+        let ok = match key.to_ascii_uppercase().as_str() {
+            "NAME" if !value.is_empty() => {
+                config.name = Some(value.to_string());
+                true
+            }
+            "FREQ" => value.parse().map(|v| config.freq_mhz = v).is_ok(),
+            "POWER" => value.parse().map(|v| config.tx_power_dbm = v).is_ok(),
+            "BW" => value.parse().map(|v| config.bandwidth_khz = v).is_ok(),
+            "SF" => value
+                .parse()
+                .ok()
+                .filter(|sf| (7..=12).contains(sf))
+                .map(|sf| config.spreading_factor = sf)
+                .is_some(),
+            "POSITION" => {
+                let mut parts = value.split(' ').map(str::parse::<f64>);
+                match (parts.next(), parts.next()) {
+                    (Some(Ok(lat)), Some(Ok(lon))) if geo::valid(lat, lon).is_some() => {
+                        (n.lat, n.lon) = (lat, lon);
+                        true
+                    }
+                    _ => false,
+                }
+            }
+            _ => return Handled::Reply("ERR Unknown setting".into()),
+        };
+        Handled::Reply(if ok { "OK" } else { "ERR Invalid value" }.into())
+    }
+
+    fn stats(&self, node: usize) -> serde_json::Value {
+        let n = &self.nodes[node];
+        let modes: Vec<&str> = n
+            .neighbors
+            .keys()
+            .map(|&i| self.nodes[i].mode.as_str())
+            .collect();
+        let count = |mode| modes.iter().filter(|&&m| m == mode).count();
+        json!({
+            "packets": n.stats,
+            "firmware": {"version": FIRMWARE, "uptime_secs": n.booted.elapsed().as_secs()},
+            "power": {"battery_pct": n.battery_percent, "battery_mv": n.battery_mv()},
+            "radio": {
+                "freq_mhz": n.config.freq_mhz,
+                "bandwidth_khz": n.config.bandwidth_khz,
+                "spreading_factor": n.config.spreading_factor,
+            },
+            "neighbors": {
+                "total": modes.len(),
+                "clients": count("client"),
+                "repeaters": count("repeater"),
+                "rooms": count("room"),
+            },
+        })
+    }
+
+    fn neighbors(&self, node: usize) -> serde_json::Value {
+        self.nodes[node]
+            .neighbors
+            .iter()
+            .map(|(&i, heard)| {
+                let n = &self.nodes[i];
+                json!({
+                    "node_hash": n.hash(),
+                    "protocol_version": 1,
+                    "name": n.name(),
+                    "public_key": n.public_key,
+                    "rssi": heard.rssi.round() as i16,
+                    "snr": heard.snr.round().clamp(-128.0, 127.0) as i8,
+                    "last_seen_secs": heard.at.elapsed().as_secs(),
+                    "firmware": FIRMWARE,
+                    "lat": n.lat,
+                    "lon": n.lon,
+                })
+            })
+            .collect()
+    }
+
+    fn routes(&self, node: usize) -> serde_json::Value {
+        let routes: Vec<_> = self.nodes[node]
+            .routes
+            .iter()
+            .map(|(&i, (path, at))| {
+                json!({
+                    "node_hash": self.nodes[i].hash(),
+                    "name": self.nodes[i].name(),
+                    "path": path.iter().map(|&r| self.nodes[r].hash()).collect::<Vec<_>>(),
+                    "age_secs": at.elapsed().as_secs(),
+                })
+            })
+            .collect();
+        json!({ "routes": routes })
+    }
+
+    fn channels(&self, node: usize) -> serde_json::Value {
+        let n = &self.nodes[node];
+        let mut channels = vec![
+            json!({"name": "Public", "builtin": true, "default": n.default_channel.is_none()}),
+        ];
+        channels.extend(n.channels.iter().map(|c| {
+            json!({"name": c, "builtin": false, "default": n.default_channel.as_ref() == Some(c)})
+        }));
+        json!({"total": channels.len(), "channels": channels})
+    }
+}
+
+/// First word and the rest.
+fn split(s: &str) -> (&str, &str) {
+    let s = s.trim();
+    s.split_once(' ')
+        .map_or((s, ""), |(first, rest)| (first, rest.trim_start()))
+}
+
+/// Put `packet` on the air from `from` after `delay`, then deliver it and
+/// whatever follows.
+fn transmit(mesh: &Shared, from: usize, packet: Packet, delay: Duration) {
+    let mesh = Arc::clone(mesh);
+    tokio::spawn(async move {
+        tokio::time::sleep(delay).await;
+        let airtime = {
+            let mut m = lock(&mesh);
+            m.nodes[from].stats.tx += 1;
+            time_on_air(&m.nodes[from].config, packet.body.len())
+        };
+        tokio::time::sleep(airtime).await;
+        let next = lock(&mesh).receive(from, &packet);
+        for (node, packet, delay) in next {
+            transmit(&mesh, node, packet, delay);
+        }
+    });
+}
+
+/// Have every node advertise at startup, spread over a few seconds, and
+/// then every `interval` seconds (0 = never again).
+pub fn start_adverts(mesh: &Shared, interval: u64) {
+    let mesh = Arc::clone(mesh);
+    tokio::spawn(async move {
+        loop {
+            let count = lock(&mesh).nodes.len();
+            for node in 0..count {
+                let (packet, delay) = {
+                    let mut m = lock(&mesh);
+                    let delay = m.jitter();
+                    (m.packet(node, Body::Advert), delay)
+                };
+                transmit(&mesh, node, packet, Duration::ZERO);
+                tokio::time::sleep(delay).await;
+            }
+            if interval == 0 {
+                return;
+            }
+            tokio::time::sleep(Duration::from_secs(interval)).await;
+        }
+    });
+}
+
+/// Serve every node on a local TCP port; returns (node id, address) pairs.
+pub async fn listen(mesh: &Shared) -> Result<Vec<(String, String)>> {
+    let ids: Vec<String> = lock(mesh).nodes.iter().map(|n| n.id.clone()).collect();
+    let mut addresses = Vec::new();
+    for (node, id) in ids.into_iter().enumerate() {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        addresses.push((id, listener.local_addr()?.to_string()));
+        let mesh = Arc::clone(mesh);
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let mesh = Arc::clone(&mesh);
+                tokio::spawn(async move {
+                    if let Err(e) = serve_client(&mesh, node, stream).await {
+                        tracing::debug!("Simulated node connection closed: {e}");
+                    }
+                });
+            }
+        });
+    }
+    Ok(addresses)
+}
+
+/// Speak the device protocol for `node` until the client disconnects.
+async fn serve_client(mesh: &Shared, node: usize, stream: TcpStream) -> Result<()> {
+    stream.set_nodelay(true)?;
+    let (mut reader, mut writer) = stream.into_split();
+    let mut events = lock(mesh).nodes[node].events.subscribe();
+    let mut input = BytesMut::with_capacity(1024);
+    let mut output = BytesMut::new();
+    let mut monitoring = false;
+    loop {
+        tokio::select! {
+            read = reader.read_buf(&mut input) => {
+                if read? == 0 {
+                    return Ok(());
+                }
+                while let Some(end) = input.iter().position(|&b| b == 0) {
+                    let mut frame = input.split_to(end + 1);
+                    let Some(len) = cobs_decode_in_place(&mut frame[..end]) else {
+                        continue;
+                    };
+                    let command = String::from_utf8_lossy(&frame[..len]).to_string();
+                    let response = respond(mesh, node, &command, &mut monitoring).await;
+                    output.clear();
+                    cobs_encode_into(response.as_bytes(), &mut output);
+                    writer.write_all(&output).await?;
+                }
+            }
+            event = events.recv() => match event {
+                Ok(event) if monitoring || !event.monitor_only => {
+                    writer.write_all(format!("{}\n", event.line).as_bytes()).await?;
+                }
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(broadcast::error::RecvError::Closed) => return Ok(()),
+            },
+        }
+    }
+}
+
+async fn respond(mesh: &Shared, node: usize, command: &str, monitoring: &mut bool) -> String {
+    let handled = lock(mesh).command(node, command, monitoring);
+    match handled {
+        Handled::Reply(reply) => reply,
+        Handled::Send(reply, packet) => {
+            transmit(mesh, node, packet, Duration::ZERO);
+            reply
+        }
+        Handled::Ask(packet, reply) => {
+            let id = packet.id;
+            transmit(mesh, node, packet, Duration::ZERO);
+            match tokio::time::timeout(REPLY_TIMEOUT, reply).await {
+                Ok(Ok(json)) => json.to_string(),
+                _ => {
+                    lock(mesh).requests.remove(&id);
+                    "ERR No reply".into()
+                }
+            }
+        }
+    }
+}
+
+/// `sim.json`: the nodes of the running simulator.
+#[derive(Debug, Serialize, Deserialize)]
+struct Registry {
+    pid: u32,
+    /// Address by node id
+    nodes: BTreeMap<String, String>,
+}
+
+fn registry_path() -> PathBuf {
+    dirs::runtime_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("meshgrid-cli")
+        .join("sim.json")
+}
+
+/// The running simulator's registry, removed when dropped.
+pub struct Registration(PathBuf);
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// List the nodes' addresses for `sim://` ports. Fails if another
+/// simulator is running.
+pub fn register(addresses: &[(String, String)]) -> Result<Registration> {
+    let path = registry_path();
+    if let Some(running) = std::fs::read_to_string(&path)
+        .ok()
+        .and_then(|text| serde_json::from_str::<Registry>(&text).ok())
+    {
+        let alive = running
+            .nodes
+            .values()
+            .next()
+            .is_some_and(|address| std::net::TcpStream::connect(address).is_ok());
+        if alive {
+            bail!(CliError::ConnectionFailed(format!(
+                "A simulator is already running (PID {})",
+                running.pid
+            )));
+        }
+    }
+    let registry = Registry {
+        pid: std::process::id(),
+        nodes: addresses.iter().cloned().collect(),
+    };
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&path, serde_json::to_string_pretty(&registry)?)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(Registration(path))
+}
+
+/// Address of simulated node `id` (`sim://node3`).
+pub fn address(id: &str) -> Result<String> {
+    let path = registry_path();
+    let registry: Registry = match std::fs::read_to_string(&path) {
+        Ok(text) => serde_json::from_str(&text)
+            .with_context(|| format!("Invalid simulator registry {}", path.display()))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => bail!(CliError::PortNotFound(
+            format!("No simulator is running for sim://{id}; start one with `meshgrid-cli sim`")
+        )),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    registry
+        .nodes
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(id))
+        .map(|(_, address)| address.clone())
+        .ok_or_else(|| {
+            CliError::PortNotFound(format!(
+                "No simulated node {id} (the simulator runs node1 to node{})",
+                registry.nodes.len()
+            ))
+            .into()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deliver a packet and everything it causes, ignoring timing.
+    fn flood(mesh: &mut Mesh, from: usize, packet: Packet) {
+        let mut queue = vec![(from, packet)];
+        while let Some((from, packet)) = queue.pop() {
+            queue.extend(
+                mesh.receive(from, &packet)
+                    .into_iter()
+                    .map(|(node, packet, _)| (node, packet)),
+            );
+        }
+    }
+
+    #[test]
+    fn test_link_range() {
+        let config = |sf| DeviceConfig {
+            name: None,
+            freq_mhz: 869.525,
+            tx_power_dbm: 20,
+            bandwidth_khz: 250,
+            spreading_factor: sf,
+            coding_rate: 5,
+            preamble_len: 8,
+        };
+        let chance = |sf, distance| link(&config(sf), &config(sf), distance).unwrap().chance;
+        assert!(chance(7, 1000.0) > 0.9);
+        assert!(chance(7, 2000.0) < 0.05);
+        assert!(chance(11, 2000.0) > 0.9);
+        assert!(link(&config(7), &config(11), 10.0).is_none());
+    }
+
+    #[test]
+    fn test_flooding() {
+        let mut mesh = Mesh::new(SimTopology::Line, 3, 1000.0, 7, 1);
+        assert!(mesh.link(0, 2).unwrap().chance < 0.05);
+        for node in 0..3 {
+            let advert = mesh.packet(node, Body::Advert);
+            flood(&mut mesh, node, advert);
+        }
+        // node3 is out of node1's range, but known from the repeated advert
+        assert!(!mesh.nodes[0].neighbors.contains_key(&2));
+        assert_eq!(mesh.resolve(0, "NODE3"), Some(2));
+        assert_eq!(
+            mesh.resolve(0, &format!("0x{:02x}", mesh.nodes[2].hash())),
+            Some(2)
+        );
+        assert_eq!(mesh.nodes[0].routes[&2].0, [1]);
+        assert!(mesh.nodes[0].routes[&1].0.is_empty());
+
+        let mut received = mesh.nodes[2].events.subscribe();
+        let mut acked = mesh.nodes[0].events.subscribe();
+        let mut monitoring = false;
+        let Handled::Send(reply, packet) = mesh.command(0, "SEND node3 hi there", &mut monitoring)
+        else {
+            panic!("SEND didn't transmit");
+        };
+        assert_eq!(reply, "OK");
+        flood(&mut mesh, 0, packet);
+        let msg = received.try_recv().unwrap().line;
+        assert!(
+            msg.starts_with("MSG node1 node3 ") && msg.ends_with(" hi there"),
+            "{msg}"
+        );
+        assert_eq!(acked.try_recv().unwrap().line, "ACK node3");
+        assert_eq!(mesh.nodes[2].inbox[0]["channel"], "direct");
+    }
+}
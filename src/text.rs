@@ -0,0 +1,129 @@
+//! Message text checks before it goes on air.
+//!
+//! Payload limits are in bytes of UTF-8, where accents take two bytes and
+//! emoji four, so lengths here are counted in bytes, never characters.
+//! [`transliterate`] brings text down to ASCII for clients whose fonts have
+//! nothing else (e-ink displays in particular).
+
+use std::borrow::Cow;
+
+/// ASCII for U+00C0 to U+017F, `?` where it takes more than one letter.
+const LATIN: &[u8; 192] = b"AAAAAA?CEEEEIIIIDNOOOOOxOUUUUY??aaaaaa?ceeeeiiiidnooooo/ouuuuy?y\
+AaAaAaCcCcCcCcDdDdEeEeEeEeEeGgGgGgGgHhHhIiIiIiIiIi??JjKkkLlLlLlL\
+lLlNnNnNnnNnOoOoOo??RrRrRrSsSsSsSsTtTtTtUuUuUuUuUuUuWwYyYZzZzZzs";
+
+/// `text` without control characters, which the device sends as they are
+/// and other clients show as garbage: tabs become spaces, CR LF becomes LF
+/// and the rest are dropped.
+pub fn clean(text: &str) -> Cow<'_, str> {
+    if !text.chars().any(|c| c.is_control() && c != '\n') {
+        return Cow::Borrowed(text);
+    }
+    let text = text.replace("\r\n", "\n");
+    Cow::Owned(
+        text.chars()
+            .filter_map(|c| match c {
+                '\t' => Some(' '),
+                '\n' => Some('\n'),
+                c if c.is_control() => None,
+                c => Some(c),
+            })
+            .collect(),
+    )
+}
+
+/// `text` cut to at most `max` bytes at a character boundary, the cut
+/// marked with `…`.
+pub fn truncate(text: &str, max: usize) -> String {
+    if text.len() <= max {
+        return text.to_string();
+    }
+    let mut end = max.saturating_sub('…'.len_utf8());
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}…", &text[..end])
+}
+
+/// `text` in plain ASCII: accents dropped, typographic punctuation and
+/// common emoji replaced, anything else shown as `?`.
+pub fn transliterate(text: &str) -> String {
+    let mut ascii = String::with_capacity(text.len());
+    for c in text.chars() {
+        let replacement = match c {
+            c if c.is_ascii() => {
+                ascii.push(c);
+                continue;
+            }
+            // Accents, emoji variation selectors, zero-width joiners and
+            // skin tones modify the character before them
+            '\u{300}'..='\u{36f}' | '\u{fe00}'..='\u{fe0f}' | '\u{200b}'..='\u{200d}' => "",
+            '\u{1f3fb}'..='\u{1f3ff}' => "",
+            'Æ' => "AE",
+            'æ' => "ae",
+            'Œ' => "OE",
+            'œ' => "oe",
+            'Þ' => "TH",
+            'þ' => "th",
+            'ß' => "ss",
+            'Ĳ' => "IJ",
+            'ĳ' => "ij",
+            '\u{c0}'..='\u{17f}' => {
+                ascii.push(LATIN[c as usize - 0xc0] as char);
+                continue;
+            }
+            '‘' | '’' | '‚' | '′' => "'",
+            '“' | '”' | '„' | '″' | '«' | '»' => "\"",
+            '‐' | '‑' | '–' | '—' | '−' => "-",
+            '…' => "...",
+            '•' | '·' => "*",
+            '°' => " deg",
+            '€' => "EUR",
+            '£' => "GBP",
+            '©' => "(c)",
+            '¿' => "?",
+            '¡' => "!",
+            '🙂' | '😊' | '☺' | '😀' | '😃' | '😄' | '😁' => ":)",
+            '😉' => ";)",
+            '😂' | '🤣' | '😆' => ":D",
+            '🙁' | '☹' | '😞' | '😢' | '😭' => ":(",
+            '😮' => ":O",
+            '😛' | '😜' => ":P",
+            '❤' | '♥' | '💕' => "<3",
+            '👍' => "+1",
+            '👎' => "-1",
+            '👋' => "o/",
+            '✓' | '✔' | '✅' => "OK",
+            '✗' | '❌' => "X",
+            '⚠' => "!",
+            c if c.is_whitespace() => " ",
+            _ => "?",
+        };
+        ascii.push_str(replacement);
+    }
+    ascii
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_safety() {
+        assert_eq!(clean("plain"), "plain");
+        assert_eq!(clean("a\tb\r\nc\u{7}"), "a b\nc");
+
+        // Counted in bytes: the cut never splits a character
+        assert_eq!(truncate("short", 140), "short");
+        assert_eq!(truncate("ééééé", 8), "éé…");
+        assert!(truncate(&"🛰".repeat(50), 140).len() <= 140);
+
+        assert_eq!(
+            transliterate("Crème brûlée “à la” Łódź — ok 👍🏽 ❤️ 漢"),
+            "Creme brulee \"a la\" Lodz - ok +1 <3 ?"
+        );
+        assert_eq!(transliterate("Straße Æsir"), "Strasse AEsir");
+        // Combining accents as well as precomposed ones
+        assert_eq!(transliterate("Jose\u{301}"), "Jose");
+    }
+}
@@ -0,0 +1,152 @@
+//! Durations and times for display.
+//!
+//! Uptimes render as `12m 5s` or `2d 4h`, how long ago something was heard
+//! as `3m ago`, and clock times in the local time zone, or in UTC with
+//! `--utc`. JSON output and exports keep raw seconds and RFC 3339.
+//! Duration arguments are read as `90s`, `10m`, `2h` or `1d`.
+
+use chrono::{DateTime, Local, SecondsFormat, TimeZone, Timelike, Utc};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+static UTC: AtomicBool = AtomicBool::new(false);
+
+/// Show clock times in UTC instead of local time (`--utc`).
+pub fn set_utc(utc: bool) {
+    UTC.store(utc, Ordering::Relaxed);
+}
+
+fn utc() -> bool {
+    UTC.load(Ordering::Relaxed)
+}
+
+/// `45s`, `12m 5s`, `3h 20m` or `2d 4h`.
+pub fn duration(secs: u64) -> String {
+    match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{}m {}s", secs / 60, secs % 60),
+        3600..86400 => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+        _ => format!("{}d {}h", secs / 86400, secs % 86400 / 3600),
+    }
+}
+
+/// A duration argument: `90` or `90s`, `10m`, `2h` or `1d`.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let invalid = || format!("invalid duration '{s}' (use e.g. 90s, 10m, 2h or 1d)");
+    let (count, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, "s"),
+    };
+    let count: u64 = count.parse().map_err(|_| invalid())?;
+    let unit = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => return Err(invalid()),
+    };
+    match count.checked_mul(unit) {
+        Some(0) => Err(format!("duration '{s}' must be more than zero")),
+        Some(secs) => Ok(Duration::from_secs(secs)),
+        None => Err(invalid()),
+    }
+}
+
+/// `just now`, `45s ago`, `3m ago`, `2h ago` or `5d ago`.
+pub fn ago(secs: u64) -> String {
+    match secs {
+        0 => "just now".into(),
+        1..60 => format!("{secs}s ago"),
+        60..3600 => format!("{}m ago", secs / 60),
+        3600..86400 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
+
+/// `t` with a chrono format string, in local time or in UTC (marked `Z`).
+pub fn format<Tz: TimeZone>(t: &DateTime<Tz>, fmt: &str) -> String {
+    if utc() {
+        format!("{}Z", t.with_timezone(&Utc).format(fmt))
+    } else {
+        t.with_timezone(&Local).format(fmt).to_string()
+    }
+}
+
+/// `2026-01-12 15:04:05`.
+pub fn datetime<Tz: TimeZone>(t: &DateTime<Tz>) -> String {
+    format(t, "%Y-%m-%d %H:%M:%S")
+}
+
+/// Time of day for live output: `15:04:05`.
+pub fn clock() -> String {
+    format(&Utc::now(), "%H:%M:%S")
+}
+
+/// Hour of the day (0-23) of `t` on the clock times are shown in.
+pub fn hour(t: &DateTime<Utc>) -> u32 {
+    if utc() {
+        t.hour()
+    } else {
+        t.with_timezone(&Local).hour()
+    }
+}
+
+/// Log timestamp: RFC 3339 with milliseconds.
+pub fn precise<Tz: TimeZone>(t: &DateTime<Tz>) -> String {
+    if utc() {
+        t.with_timezone(&Utc)
+            .to_rfc3339_opts(SecondsFormat::Millis, true)
+    } else {
+        t.with_timezone(&Local)
+            .to_rfc3339_opts(SecondsFormat::Millis, false)
+    }
+}
+
+/// `3m ago` within the last day, the date and time before that.
+pub fn relative<Tz: TimeZone>(t: &DateTime<Tz>) -> String {
+    relative_to(&t.with_timezone(&Utc), Utc::now())
+}
+
+/// Unix timestamp as [`relative`].
+pub fn relative_unix(secs: i64) -> String {
+    DateTime::from_timestamp(secs, 0).map_or_else(|| format!("invalid-ts:{secs}"), |t| relative(&t))
+}
+
+fn relative_to(t: &DateTime<Utc>, now: DateTime<Utc>) -> String {
+    match u64::try_from((now - *t).num_seconds()) {
+        Ok(secs) if secs < 86400 => ago(secs),
+        _ => format(t, "%Y-%m-%d %H:%M"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        assert_eq!(duration(45), "45s");
+        assert_eq!(duration(725), "12m 5s");
+        assert_eq!(duration(2 * 86400 + 4 * 3600 + 59), "2d 4h");
+        assert_eq!(ago(0), "just now");
+        assert_eq!(ago(200), "3m ago");
+        assert_eq!(ago(3 * 86400), "3d ago");
+        assert_eq!(parse_duration("10m"), Ok(Duration::from_secs(600)));
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert!(parse_duration("0h").is_err());
+        assert!(parse_duration("5 min").is_err());
+
+        let now = DateTime::parse_from_rfc3339("2026-01-12T15:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(
+            relative_to(&(now - chrono::TimeDelta::minutes(3)), now),
+            "3m ago"
+        );
+        let old = now - chrono::TimeDelta::days(2);
+        assert_eq!(relative_to(&old, now), format(&old, "%Y-%m-%d %H:%M"));
+        // Clock skew: a timestamp from the future is shown as is
+        let ahead = now + chrono::TimeDelta::minutes(5);
+        assert_eq!(relative_to(&ahead, now), format(&ahead, "%Y-%m-%d %H:%M"));
+    }
+}
@@ -1,6 +1,8 @@
 //! Terminal UI for meshgrid.
 //!
 //! Interactive terminal interface for monitoring and sending messages.
+//! The UI draws immediately; connecting, loading device details and
+//! refreshing the neighbor table happen in a background task.
 
 use anyhow::Result;
 use crossterm::{
@@ -21,9 +23,15 @@ use std::io;
 use std::sync::{Arc, Mutex};
 use tokio::sync::mpsc;
 
+use crate::channels::{self, Level, Prefs};
 use crate::device::MeshEvent;
-use crate::protocol::{MonitorEvent, Protocol};
+use crate::history::Recorder;
+use crate::protocol::{ChannelInfo, MonitorEvent, NeighborInfo, Protocol};
 use crate::serial::SerialPort;
+use crate::settings::Settings;
+
+/// How often the neighbor table is re-read from the device.
+const NEIGHBOR_REFRESH: std::time::Duration = std::time::Duration::from_secs(30);
 
 /// Message log entry.
 #[derive(Debug, Clone)]
@@ -51,26 +59,46 @@ struct App {
     cursor: usize,
     /// Neighbors map (`node_hash` -> display info)
     neighbors: HashMap<u8, NeighborDisplay>,
-    /// Device name
+    /// Device name ("connecting…" until known)
     device_name: String,
+    /// Radio settings summary, once loaded
+    radio: Option<String>,
+    /// Channel notification preferences
+    prefs: Prefs,
+    /// Messages received since the last Esc, by channel ("DM" for direct
+    /// messages); muted channels aren't counted
+    unread: HashMap<String, u32>,
+    /// Device channels, in order
+    channels: Vec<ChannelInfo>,
+    /// Index in `channels` that typed messages go to (Tab switches)
+    focus: usize,
     /// Should quit
     should_quit: bool,
 }
 
 impl App {
-    fn new(device_name: String) -> Self {
+    fn new(prefs: Prefs) -> Self {
         Self {
             messages: Vec::new(),
             input: String::new(),
             cursor: 0,
             neighbors: HashMap::new(),
-            device_name,
+            device_name: "connecting…".into(),
+            radio: None,
+            prefs,
+            unread: HashMap::new(),
+            channels: vec![ChannelInfo {
+                name: channels::PUBLIC.into(),
+                builtin: true,
+                default: false,
+            }],
+            focus: 0,
             should_quit: false,
         }
     }
 
     fn add_message(&mut self, content: String, style: Style) {
-        let timestamp = chrono::Local::now().format("%H:%M:%S").to_string();
+        let timestamp = crate::timefmt::clock();
         self.messages.push(LogEntry {
             timestamp,
             content,
@@ -87,9 +115,67 @@ impl App {
         self.add_message(content, Style::default().fg(Color::Cyan));
     }
 
-    fn add_received(&mut self, from: &str, text: &str, rssi: i16) {
+    fn add_waypoint(&mut self, content: String) {
+        self.add_message(content, Style::default().fg(Color::Magenta));
+    }
+
+    fn add_received(&mut self, from: &str, text: &str, rssi: i16, channel: Option<&str>) {
         let content = format!("{from} ({rssi}dB): {text}");
-        self.add_message(content, Style::default().fg(Color::Green));
+        let level = self.prefs.get(channel);
+        let style = match level {
+            Level::Mute => Style::default().fg(Color::DarkGray),
+            Level::Notify => Style::default().fg(Color::Green),
+            Level::Priority => Style::default()
+                .fg(Color::LightRed)
+                .add_modifier(Modifier::BOLD),
+        };
+        if level != Level::Mute {
+            *self
+                .unread
+                .entry(channel.unwrap_or("DM").to_string())
+                .or_default() += 1;
+        }
+        self.add_message(content, style);
+    }
+
+    /// Unread counts for the header, priority channels first.
+    fn unread_summary(&self) -> Option<String> {
+        let mut unread: Vec<(&String, &u32)> = self.unread.iter().collect();
+        if unread.is_empty() {
+            return None;
+        }
+        let priority =
+            |channel: &str| channel != "DM" && self.prefs.get(Some(channel)) == Level::Priority;
+        unread.sort_by_key(|(channel, count)| {
+            (!priority(channel), std::cmp::Reverse(**count), *channel)
+        });
+        let counts: Vec<String> = unread
+            .iter()
+            .map(|(channel, count)| {
+                let mark = if priority(channel) { "★" } else { "" };
+                format!("{mark}{channel} {count}")
+            })
+            .collect();
+        Some(format!("unread: {}", counts.join(", ")))
+    }
+
+    /// Take the device's channel list, focusing its default channel.
+    fn set_channels(&mut self, list: Vec<ChannelInfo>) {
+        if list.is_empty() {
+            return;
+        }
+        self.focus = list
+            .iter()
+            .position(|c| c.default && !c.builtin)
+            .or_else(|| list.iter().position(|c| c.builtin))
+            .unwrap_or(0);
+        self.channels = list;
+    }
+
+    /// Channel typed messages go to; `None` broadcasts on the public channel.
+    fn send_channel(&self) -> Option<String> {
+        let channel = &self.channels[self.focus];
+        (!channel.builtin).then(|| channel.name.clone())
     }
 
     fn add_sent(&mut self, text: &str) {
@@ -102,13 +188,34 @@ impl App {
     }
 
     fn update_neighbor(&mut self, node_hash: u8, name: Option<String>, rssi: i16) {
+        self.insert_neighbor(node_hash, name, rssi, std::time::Instant::now());
+    }
+
+    /// Merge the device's neighbor table.
+    fn set_neighbors(&mut self, neighbors: Vec<NeighborInfo>) {
+        let now = std::time::Instant::now();
+        for n in neighbors {
+            let last_seen = now
+                .checked_sub(std::time::Duration::from_secs(n.last_seen_secs.into()))
+                .unwrap_or(now);
+            self.insert_neighbor(n.node_hash, n.name, n.rssi, last_seen);
+        }
+    }
+
+    fn insert_neighbor(
+        &mut self,
+        node_hash: u8,
+        name: Option<String>,
+        rssi: i16,
+        last_seen: std::time::Instant,
+    ) {
         let display_name = name.unwrap_or_else(|| format!("0x{node_hash:02x}"));
         self.neighbors.insert(
             node_hash,
             NeighborDisplay {
                 name: display_name,
                 rssi,
-                last_seen: std::time::Instant::now(),
+                last_seen,
             },
         );
 
@@ -122,16 +229,7 @@ impl App {
 
 /// Run the terminal UI.
 pub async fn run(port: &str, baud: u32) -> Result<()> {
-    // Connect to device - get info first
-    let serial = SerialPort::open(port, baud).await?;
-    let mut protocol = Protocol::new(serial);
-
-    // Get device info
-    let info = protocol.get_info().await?;
-    let device_name = info
-        .name
-        .clone()
-        .unwrap_or_else(|| format!("0x{:02x}", info.node_hash));
+    let prefs = channels::load()?;
 
     // Set up terminal
     enable_raw_mode()?;
@@ -141,76 +239,24 @@ pub async fn run(port: &str, baud: u32) -> Result<()> {
     let mut terminal = Terminal::new(backend)?;
 
     // Create app state
-    let app = Arc::new(Mutex::new(App::new(device_name)));
-    app.lock().unwrap().add_info(format!(
-        "Connected to {} on {}",
-        info.name.as_deref().unwrap_or("device"),
-        port
-    ));
-    app.lock()
-        .unwrap()
-        .add_info("Type a message and press Enter to send. Ctrl+Q to quit.".into());
+    let app = Arc::new(Mutex::new(App::new(prefs)));
+    app.lock().unwrap().add_info(
+        "Type a message and press Enter to send. Tab switches channel, Esc marks all read, Ctrl+Q quits."
+            .into(),
+    );
 
     // Create channels for communication
     let (tx_event, mut rx_event) = mpsc::channel::<MeshEvent>(100);
-    let (tx_cmd, mut rx_cmd) = mpsc::channel::<String>(10);
+    let (tx_cmd, rx_cmd) = mpsc::channel::<(Option<String>, String)>(10);
 
     // Spawn device handler task
-    let app_clone = app.clone();
-    let device_task = tokio::spawn(async move {
-        // Enter monitor mode and handle events
-        if let Err(e) = protocol.enter_monitor_mode().await {
-            app_clone
-                .lock()
-                .unwrap()
-                .add_error(format!("Monitor error: {e}"));
-            return;
-        }
-
-        loop {
-            tokio::select! {
-                // Check for mesh events
-                result = protocol.read_event() => {
-                    match result {
-                        Ok(Some(event)) => {
-                            let _ = tx_event.send(match event {
-                                MonitorEvent::Message { from, to, rssi, text } => {
-                                    MeshEvent::Message { from, to, text, rssi }
-                                }
-                                MonitorEvent::Advertisement { node_hash, rssi, name } => {
-                                    MeshEvent::Advertisement { node_hash, rssi, name }
-                                }
-                                MonitorEvent::Ack { from } => {
-                                    MeshEvent::Ack { from }
-                                }
-                                MonitorEvent::Error { message } => {
-                                    MeshEvent::Error { message }
-                                }
-                            }).await;
-                        }
-                        Ok(None) => {}
-                        Err(e) => {
-                            app_clone.lock().unwrap().add_error(format!("Read error: {e}"));
-                            break;
-                        }
-                    }
-                }
-                // Check for commands to send
-                cmd = rx_cmd.recv() => {
-                    match cmd {
-                        Some(msg) => {
-                            if let Err(e) = protocol.send_broadcast(&msg).await {
-                                app_clone.lock().unwrap().add_error(format!("Send error: {e}"));
-                            }
-                        }
-                        None => break,
-                    }
-                }
-            }
-
-            tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
-        }
-    });
+    let device_task = tokio::spawn(run_device(
+        port.to_string(),
+        baud,
+        app.clone(),
+        tx_event,
+        rx_cmd,
+    ));
 
     // Main UI loop
     let result = run_ui_loop(&mut terminal, app.clone(), &mut rx_event, &tx_cmd).await;
@@ -230,12 +276,135 @@ pub async fn run(port: &str, baud: u32) -> Result<()> {
     result
 }
 
+/// Connect, load device details, then relay mesh events and send messages.
+async fn run_device(
+    port: String,
+    baud: u32,
+    app: Arc<Mutex<App>>,
+    tx_event: mpsc::Sender<MeshEvent>,
+    mut rx_cmd: mpsc::Receiver<(Option<String>, String)>,
+) {
+    let mut protocol = match SerialPort::open(&port, baud).await {
+        Ok(serial) => Protocol::new(serial),
+        Err(e) => {
+            let mut app = app.lock().unwrap();
+            app.device_name = "not connected".into();
+            app.add_error(format!("Failed to connect to {port}: {e}"));
+            return;
+        }
+    };
+
+    match protocol.get_info().await {
+        Ok(info) => {
+            let name = info
+                .name
+                .clone()
+                .unwrap_or_else(|| format!("0x{:02x}", info.node_hash));
+            let mut app = app.lock().unwrap();
+            app.add_info(format!("Connected to {name} on {port}"));
+            app.device_name = name;
+        }
+        Err(e) => app
+            .lock()
+            .unwrap()
+            .add_error(format!("Failed to read device info: {e}")),
+    }
+    match protocol.get_config().await {
+        Ok(config) => {
+            app.lock().unwrap().radio = Some(format!(
+                "{:.3} MHz SF{} BW{}",
+                config.freq_mhz, config.spreading_factor, config.bandwidth_khz
+            ));
+        }
+        Err(e) => tracing::debug!("Failed to read radio config: {e:#}"),
+    }
+    match protocol.get_channels().await {
+        Ok(list) => app.lock().unwrap().set_channels(list),
+        Err(e) => tracing::debug!("Failed to read channels: {e:#}"),
+    }
+
+    // Enter monitor mode and handle events
+    if let Err(e) = protocol.enter_monitor_mode().await {
+        app.lock().unwrap().add_error(format!("Monitor error: {e}"));
+        return;
+    }
+
+    let mut history = Recorder::new(&Settings::load().unwrap_or_default());
+
+    // The first tick fires immediately and loads the neighbor table
+    let mut refresh = tokio::time::interval(NEIGHBOR_REFRESH);
+    loop {
+        tokio::select! {
+            // Check for mesh events
+            result = protocol.read_event() => {
+                match result {
+                    Ok(Some(event)) => {
+                        history.record(&event);
+                        let _ = tx_event.send(match event {
+                            MonitorEvent::Message { from, to, channel, rssi, text } => {
+                                MeshEvent::Message { from, to, channel, text, rssi }
+                            }
+                            MonitorEvent::Advertisement { node_hash, rssi, name } => {
+                                MeshEvent::Advertisement { node_hash, rssi, name }
+                            }
+                            MonitorEvent::Ack { from } => {
+                                MeshEvent::Ack { from }
+                            }
+                            MonitorEvent::Waypoint { from, name, lat, lon, icon, .. } => {
+                                MeshEvent::Waypoint { from, name, lat, lon, icon }
+                            }
+                            MonitorEvent::Error { message } => {
+                                MeshEvent::Error { message }
+                            }
+                        }).await;
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        app.lock().unwrap().add_error(format!("Read error: {e}"));
+                        break;
+                    }
+                }
+            }
+            // Check for commands to send
+            cmd = rx_cmd.recv() => {
+                match cmd {
+                    Some((channel, msg)) => {
+                        let sent = match &channel {
+                            Some(channel) => protocol.send_channel(channel, &msg).await,
+                            None => protocol.send_broadcast(&msg).await,
+                        };
+                        if let Err(e) = sent {
+                            app.lock().unwrap().add_error(format!("Send error: {e}"));
+                        }
+                    }
+                    None => break,
+                }
+            }
+            // Periodically re-read the neighbor table; events that arrive
+            // meanwhile are held by the port for the next read_event()
+            _ = refresh.tick() => {
+                match protocol.get_neighbors().await {
+                    Ok(neighbors) => app.lock().unwrap().set_neighbors(neighbors),
+                    Err(e) => tracing::debug!("Failed to refresh neighbors: {e:#}"),
+                }
+                // Sending a command leaves monitor mode on some firmware
+                if let Err(e) = protocol.enter_monitor_mode().await {
+                    app.lock().unwrap().add_error(format!("Monitor error: {e}"));
+                    break;
+                }
+            }
+        }
+
+        tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
+    }
+}
+
 #[allow(clippy::too_many_lines)]
 async fn run_ui_loop(
     terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
     app: Arc<Mutex<App>>,
     rx_event: &mut mpsc::Receiver<MeshEvent>,
-    tx_cmd: &mpsc::Sender<String>,
+    tx_cmd: &mpsc::Sender<(Option<String>, String)>,
 ) -> Result<()> {
     loop {
         // Draw UI
@@ -251,11 +420,13 @@ async fn run_ui_loop(
                 MeshEvent::Message {
                     from,
                     to,
+                    channel,
                     text,
                     rssi,
                 } => {
-                    let dest = to.as_deref().unwrap_or("all");
-                    app.add_received(&from, &format!("[->{dest}] {text}"), rssi);
+                    let dest = to.as_deref().or(channel.as_deref()).unwrap_or("all");
+                    let channel = channels::of_message(to.as_deref(), channel.as_deref());
+                    app.add_received(&from, &format!("[->{dest}] {text}"), rssi, channel);
                 }
                 MeshEvent::Advertisement {
                     node_hash,
@@ -269,6 +440,18 @@ async fn run_ui_loop(
                 MeshEvent::Ack { from } => {
                     app.add_info(format!("ACK from {from}"));
                 }
+                MeshEvent::Waypoint {
+                    from,
+                    name,
+                    lat,
+                    lon,
+                    icon,
+                } => {
+                    let icon = icon.map(|i| format!("{i} ")).unwrap_or_default();
+                    app.add_waypoint(format!(
+                        "WPT from {from}: {icon}{name} ({lat:.5}, {lon:.5})"
+                    ));
+                }
                 MeshEvent::Error { message } => {
                     app.add_error(message);
                 }
@@ -294,7 +477,7 @@ async fn run_ui_loop(
                             app.add_sent(&msg);
                             app.input.clear();
                             app.cursor = 0;
-                            Some(msg)
+                            Some((app.send_channel(), msg))
                         }
                     }; // Lock dropped here
 
@@ -311,17 +494,20 @@ async fn run_ui_loop(
                         {
                             app.should_quit = true;
                         }
+                        KeyCode::Esc => app.unread.clear(),
+                        KeyCode::Tab => app.focus = (app.focus + 1) % app.channels.len(),
+                        KeyCode::BackTab => {
+                            app.focus = (app.focus + app.channels.len() - 1) % app.channels.len();
+                        }
                         KeyCode::Char(c) => {
                             let cursor = app.cursor;
                             app.input.insert(cursor, c);
                             app.cursor += 1;
                         }
-                        KeyCode::Backspace => {
-                            if app.cursor > 0 {
-                                app.cursor -= 1;
-                                let cursor = app.cursor;
-                                app.input.remove(cursor);
-                            }
+                        KeyCode::Backspace if app.cursor > 0 => {
+                            app.cursor -= 1;
+                            let cursor = app.cursor;
+                            app.input.remove(cursor);
                         }
                         KeyCode::Delete => {
                             let cursor = app.cursor;
@@ -329,15 +515,11 @@ async fn run_ui_loop(
                                 app.input.remove(cursor);
                             }
                         }
-                        KeyCode::Left => {
-                            if app.cursor > 0 {
-                                app.cursor -= 1;
-                            }
+                        KeyCode::Left if app.cursor > 0 => {
+                            app.cursor -= 1;
                         }
-                        KeyCode::Right => {
-                            if app.cursor < app.input.len() {
-                                app.cursor += 1;
-                            }
+                        KeyCode::Right if app.cursor < app.input.len() => {
+                            app.cursor += 1;
                         }
                         KeyCode::Home => {
                             app.cursor = 0;
@@ -366,10 +548,19 @@ fn draw_ui(f: &mut Frame, app: &App) {
 
     // Header
     let neighbor_count = app.neighbors.len();
-    let header_text = format!(
-        " meshgrid - {} | {} neighbors ",
-        app.device_name, neighbor_count
-    );
+    let mut header_text = match &app.radio {
+        Some(radio) => format!(
+            " meshgrid - {} | {radio} | {neighbor_count} neighbors ",
+            app.device_name
+        ),
+        None => format!(
+            " meshgrid - {} | {neighbor_count} neighbors ",
+            app.device_name
+        ),
+    };
+    if let Some(unread) = app.unread_summary() {
+        header_text.push_str(&format!("| {unread} "));
+    }
     let header = Paragraph::new(header_text)
         .style(
             Style::default()
@@ -413,18 +604,13 @@ fn draw_ui(f: &mut Frame, app: &App) {
 
     // Neighbors panel
     let mut neighbors: Vec<_> = app.neighbors.iter().collect();
-    neighbors.sort_by(|a, b| b.1.rssi.cmp(&a.1.rssi)); // Sort by signal strength
+    neighbors.sort_by_key(|n| std::cmp::Reverse(n.1.rssi)); // Sort by signal strength
 
     let neighbor_items: Vec<ListItem> = neighbors
         .iter()
         .take(content_chunks[1].height as usize - 2)
         .map(|(_, info)| {
-            let age_secs = info.last_seen.elapsed().as_secs();
-            let age_str = if age_secs < 60 {
-                format!("{age_secs}s")
-            } else {
-                format!("{}m", age_secs / 60)
-            };
+            let age_str = crate::timefmt::ago(info.last_seen.elapsed().as_secs());
 
             let rssi_color = if info.rssi > -70 {
                 Color::Green
@@ -458,7 +644,10 @@ fn draw_ui(f: &mut Frame, app: &App) {
         .style(Style::default())
         .block(
             Block::default()
-                .title(" Send (Enter) | Ctrl+Q quit ")
+                .title(format!(
+                    " Send to {} (Enter) | Tab channel | Esc mark read | Ctrl+Q quit ",
+                    app.channels[app.focus].name
+                ))
                 .borders(Borders::ALL),
         );
     f.render_widget(input, main_chunks[2]);
@@ -0,0 +1,168 @@
+//! Saved waypoints.
+//!
+//! `waypoints.toml` next to the config file keeps the named waypoints created
+//! with `waypoints add`, ready to be broadcast with `waypoints send`.
+
+use crate::settings::Settings;
+use crate::vault;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// A named point of interest.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Waypoint {
+    pub name: String,
+    pub lat: f64,
+    pub lon: f64,
+    /// Symbol shown by devices with maps, e.g. an emoji
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+    /// RFC 3339 time after which devices stop showing the waypoint
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<String>,
+}
+
+impl Waypoint {
+    pub fn expires_at(&self) -> Option<DateTime<Utc>> {
+        let expires = self.expires.as_deref()?;
+        DateTime::parse_from_rfc3339(expires)
+            .ok()
+            .map(|t| t.with_timezone(&Utc))
+    }
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at().is_some_and(|t| t <= now)
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Store {
+    #[serde(default)]
+    waypoints: Vec<Waypoint>,
+}
+
+impl Store {
+    /// Add a waypoint, replacing one of the same name. Returns whether it
+    /// replaced one.
+    fn insert(&mut self, waypoint: Waypoint) -> bool {
+        match self
+            .waypoints
+            .iter_mut()
+            .find(|w| w.name.eq_ignore_ascii_case(&waypoint.name))
+        {
+            Some(existing) => {
+                *existing = waypoint;
+                true
+            }
+            None => {
+                self.waypoints.push(waypoint);
+                false
+            }
+        }
+    }
+
+    fn remove(&mut self, name: &str) -> Option<Waypoint> {
+        let index = self
+            .waypoints
+            .iter()
+            .position(|w| w.name.eq_ignore_ascii_case(name))?;
+        Some(self.waypoints.remove(index))
+    }
+}
+
+fn store_path() -> Result<PathBuf> {
+    Ok(Settings::path()?.with_file_name("waypoints.toml"))
+}
+
+fn read_store() -> Result<Store> {
+    let path = store_path()?;
+    vault::ensure_unlocked(&path)?;
+    match std::fs::read_to_string(&path) {
+        Ok(content) => toml::from_str(&content)
+            .with_context(|| format!("Invalid waypoints file {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Store::default()),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+fn write_store(store: &Store) -> Result<()> {
+    let path = store_path()?;
+    vault::ensure_unlocked(&path)?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&path, toml::to_string(store)?)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// All saved waypoints, in the order they were added.
+pub fn list() -> Result<Vec<Waypoint>> {
+    Ok(read_store()?.waypoints)
+}
+
+/// Saved waypoint `name` (case-insensitive).
+pub fn get(name: &str) -> Result<Option<Waypoint>> {
+    Ok(read_store()?
+        .waypoints
+        .into_iter()
+        .find(|w| w.name.eq_ignore_ascii_case(name)))
+}
+
+/// Save a waypoint, replacing one of the same name. Returns whether it
+/// replaced one.
+pub fn add(waypoint: Waypoint) -> Result<bool> {
+    let mut store = read_store()?;
+    let replaced = store.insert(waypoint);
+    write_store(&store)?;
+    Ok(replaced)
+}
+
+/// Delete waypoint `name`, returning it if it existed.
+pub fn remove(name: &str) -> Result<Option<Waypoint>> {
+    let mut store = read_store()?;
+    let removed = store.remove(name);
+    if removed.is_some() {
+        write_store(&store)?;
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_store() {
+        let camp = Waypoint {
+            name: "Camp".into(),
+            lat: 47.3769,
+            lon: 8.5417,
+            icon: Some("⛺".into()),
+            expires: Some("2026-01-12T18:00:00Z".into()),
+        };
+        let mut store = Store::default();
+        assert!(!store.insert(camp.clone()));
+        assert!(store.insert(Waypoint {
+            name: "camp".into(),
+            lat: 47.4,
+            ..camp.clone()
+        }));
+        assert_eq!(store.waypoints.len(), 1);
+        assert_eq!(store.waypoints[0].lat, 47.4);
+
+        let text = toml::to_string(&store).unwrap();
+        let parsed: Store = toml::from_str(&text).unwrap();
+        assert_eq!(parsed.waypoints, store.waypoints);
+
+        let noon = DateTime::parse_from_rfc3339("2026-01-12T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert!(!camp.is_expired(noon));
+        assert!(camp.is_expired(noon + chrono::Duration::hours(6)));
+
+        assert!(store.remove("CAMP").is_some());
+        assert!(store.remove("camp").is_none());
+    }
+}
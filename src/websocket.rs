@@ -0,0 +1,255 @@
+//! Minimal WebSocket (RFC 6455) for Nostr relay connections and the live map.
+//!
+//! Supports `ws://` and `wss://` URLs, text/binary messages (including
+//! fragmented ones), ping/pong and close. No extensions or subprotocols.
+//! [`accept`] completes the server side of a handshake read by [`crate::http`].
+
+use crate::mail::tls_connect;
+use crate::serial::Transport;
+use anyhow::{bail, Context, Result};
+use base64::{engine::general_purpose, Engine as _};
+use sha1::{Digest, Sha1};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, ReadHalf, WriteHalf};
+use tokio::net::TcpStream;
+
+/// Largest accepted message.
+const MAX_MESSAGE: usize = 1024 * 1024;
+
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xa;
+
+/// Received message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong,
+    Close,
+}
+
+/// Receiving half of a connection.
+pub struct Reader {
+    stream: BufReader<ReadHalf<Box<dyn Transport>>>,
+}
+
+/// Sending half of a connection.
+pub struct Writer {
+    stream: WriteHalf<Box<dyn Transport>>,
+    /// Clients mask their frames, servers don't
+    masked: bool,
+}
+
+/// Open a WebSocket connection.
+pub async fn connect(url: &str) -> Result<(Reader, Writer)> {
+    let parsed = url::Url::parse(url).with_context(|| format!("Invalid URL '{url}'"))?;
+    let tls = match parsed.scheme() {
+        "wss" => true,
+        "ws" => false,
+        other => bail!("Unsupported scheme '{other}' (use ws:// or wss://)"),
+    };
+    let host = parsed
+        .host_str()
+        .with_context(|| format!("Missing host in '{url}'"))?
+        .to_string();
+    let port = parsed.port().unwrap_or(if tls { 443 } else { 80 });
+
+    let tcp = TcpStream::connect((host.as_str(), port))
+        .await
+        .with_context(|| format!("Failed to connect to {host}:{port}"))?;
+    let mut stream: Box<dyn Transport> = Box::new(tcp);
+    if tls {
+        stream = tls_connect(&host, stream).await?;
+    }
+
+    let mut nonce = [0u8; 16];
+    getrandom::getrandom(&mut nonce)?;
+    let key = general_purpose::STANDARD.encode(nonce);
+    let mut target = parsed.path().to_string();
+    if let Some(query) = parsed.query() {
+        target = format!("{target}?{query}");
+    }
+    let host_header = match parsed.port() {
+        Some(port) => format!("{host}:{port}"),
+        None => host.clone(),
+    };
+    let request = format!(
+        "GET {target} HTTP/1.1\r\nHost: {host_header}\r\nUpgrade: websocket\r\n\
+         Connection: Upgrade\r\nSec-WebSocket-Key: {key}\r\nSec-WebSocket-Version: 13\r\n\r\n"
+    );
+    stream.write_all(request.as_bytes()).await?;
+    stream.flush().await?;
+
+    let (read, write) = tokio::io::split(stream);
+    let mut reader = BufReader::new(read);
+
+    // Status line and headers
+    let mut status = String::new();
+    reader.read_line(&mut status).await?;
+    if status.split_whitespace().nth(1) != Some("101") {
+        bail!("WebSocket handshake with {host} failed: {}", status.trim());
+    }
+    let expected =
+        general_purpose::STANDARD.encode(Sha1::digest(format!("{key}{GUID}").as_bytes()));
+    let mut accepted = false;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            bail!("Connection closed during WebSocket handshake");
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("sec-websocket-accept") && value.trim() == expected {
+                accepted = true;
+            }
+        }
+    }
+    if !accepted {
+        bail!("WebSocket handshake with {host} failed: bad Sec-WebSocket-Accept");
+    }
+
+    Ok((
+        Reader { stream: reader },
+        Writer {
+            stream: write,
+            masked: true,
+        },
+    ))
+}
+
+/// Accept a WebSocket upgrade whose request carried `Sec-WebSocket-Key: key`.
+pub async fn accept(mut stream: Box<dyn Transport>, key: &str) -> Result<(Reader, Writer)> {
+    let accept = general_purpose::STANDARD.encode(Sha1::digest(format!("{key}{GUID}").as_bytes()));
+    let response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {accept}\r\n\r\n"
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.flush().await?;
+
+    let (read, write) = tokio::io::split(stream);
+    Ok((
+        Reader {
+            stream: BufReader::new(read),
+        },
+        Writer {
+            stream: write,
+            masked: false,
+        },
+    ))
+}
+
+impl Reader {
+    /// Read one frame: (fin, opcode, payload).
+    async fn frame(&mut self) -> Result<(bool, u8, Vec<u8>)> {
+        let mut head = [0u8; 2];
+        self.stream.read_exact(&mut head).await?;
+        let fin = head[0] & 0x80 != 0;
+        let opcode = head[0] & 0x0f;
+        let masked = head[1] & 0x80 != 0;
+        let len = match head[1] & 0x7f {
+            126 => u64::from(self.stream.read_u16().await?),
+            127 => self.stream.read_u64().await?,
+            n => u64::from(n),
+        };
+        if len > MAX_MESSAGE as u64 {
+            bail!("WebSocket frame too large ({len} bytes)");
+        }
+        let mut mask = [0u8; 4];
+        if masked {
+            self.stream.read_exact(&mut mask).await?;
+        }
+        let mut payload = vec![0u8; len as usize];
+        self.stream.read_exact(&mut payload).await?;
+        if masked {
+            for (i, b) in payload.iter_mut().enumerate() {
+                *b ^= mask[i % 4];
+            }
+        }
+        Ok((fin, opcode, payload))
+    }
+
+    /// Receive the next message; control frames are returned as they arrive.
+    pub async fn recv(&mut self) -> Result<Message> {
+        let mut message: Option<(u8, Vec<u8>)> = None;
+        loop {
+            let (fin, opcode, payload) = self.frame().await?;
+            match opcode {
+                OP_PING => return Ok(Message::Ping(payload)),
+                OP_PONG => return Ok(Message::Pong),
+                OP_CLOSE => return Ok(Message::Close),
+                OP_TEXT | OP_BINARY if message.is_none() => message = Some((opcode, payload)),
+                OP_CONTINUATION if message.is_some() => {
+                    let (_, data) = message.as_mut().expect("checked");
+                    data.extend_from_slice(&payload);
+                    if data.len() > MAX_MESSAGE {
+                        bail!("WebSocket message too large");
+                    }
+                }
+                _ => bail!("Unexpected WebSocket opcode {opcode:#x}"),
+            }
+
+            if fin {
+                let (opcode, data) = message.take().expect("set above");
+                return Ok(if opcode == OP_TEXT {
+                    Message::Text(String::from_utf8(data).context("Invalid UTF-8 in text message")?)
+                } else {
+                    Message::Binary(data)
+                });
+            }
+        }
+    }
+}
+
+impl Writer {
+    /// Send one unfragmented frame.
+    async fn frame(&mut self, opcode: u8, payload: &[u8]) -> Result<()> {
+        let mut frame = Vec::with_capacity(payload.len() + 14);
+        frame.push(0x80 | opcode);
+        let mask_bit = if self.masked { 0x80 } else { 0 };
+        match payload.len() {
+            n @ 0..=125 => frame.push(mask_bit | n as u8),
+            n @ 126..=0xffff => {
+                frame.push(mask_bit | 126);
+                frame.extend_from_slice(&(n as u16).to_be_bytes());
+            }
+            n => {
+                frame.push(mask_bit | 127);
+                frame.extend_from_slice(&(n as u64).to_be_bytes());
+            }
+        }
+        if self.masked {
+            let mut mask = [0u8; 4];
+            getrandom::getrandom(&mut mask)?;
+            frame.extend_from_slice(&mask);
+            frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        } else {
+            frame.extend_from_slice(payload);
+        }
+
+        self.stream.write_all(&frame).await?;
+        self.stream.flush().await?;
+        Ok(())
+    }
+
+    pub async fn send_text(&mut self, text: &str) -> Result<()> {
+        self.frame(OP_TEXT, text.as_bytes()).await
+    }
+
+    pub async fn pong(&mut self, payload: &[u8]) -> Result<()> {
+        self.frame(OP_PONG, payload).await
+    }
+
+    pub async fn close(&mut self) -> Result<()> {
+        self.frame(OP_CLOSE, &[]).await
+    }
+}